@@ -0,0 +1,340 @@
+use std::{
+    error::Error,
+    ffi::OsString,
+    io::{self, Read, Write},
+    iter,
+    process::ExitCode,
+};
+
+use base64::{engine::general_purpose, Engine as _};
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    conf::DBConfig, FormatMutation, RecordCipher, UploadSessionMutation, UserMutation, UserQuery,
+};
+use clap::{Args, Parser, Subcommand};
+use entity::{format, user};
+use migration::{Migrator, MigratorTrait};
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use sea_orm::EntityTrait;
+use tracing_subscriber::EnvFilter;
+
+use crate::auth::hashing::UserPassword;
+
+/// Management tool for central-repository. It talks to the database
+/// directly, so the HTTP API doesn't have to be up.
+#[derive(Parser)]
+#[command(name = "repository-admin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage users.
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Run or revert database migrations.
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Prune upload sessions older than their format's retention period.
+    Prune(PruneArgs),
+    /// Export or import format definitions.
+    #[command(subcommand)]
+    Format(FormatCommand),
+    /// Generate a new base64-encoded Ed25519 key for ED25519_SIGNING_KEY.
+    Keygen(KeygenArgs),
+    /// Print a new ED25519_SIGNING_KEY, and ED25519_PREVIOUS_PUBLIC_KEYS
+    /// extended with the public part of the current one, so the tokens it
+    /// signed stay valid.
+    RotateSigningKey,
+}
+
+#[derive(Args)]
+struct KeygenArgs {
+    /// Generate a key for TOTP_ENCRYPTION_KEY instead.
+    #[arg(long)]
+    totp: bool,
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    /// Create a new user.
+    Create {
+        username: String,
+        /// Create the user as a superuser.
+        #[arg(long)]
+        superuser: bool,
+        /// Role of the user (User, Auditor, FormatManager or Admin).
+        #[arg(long, default_value = "User", value_parser = parse_role)]
+        role: user::Role,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Set the password of an existing user.
+    SetPassword {
+        username: String,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Disable 2FA for a user, e.g. if they lost their authenticator and
+    /// recovery codes.
+    #[command(name = "reset-2fa")]
+    Reset2fa { username: String },
+}
+
+#[derive(Args)]
+struct PasswordArgs {
+    /// Read the password from this file instead of stdin.
+    #[arg(long)]
+    password_file: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum MigrateCommand {
+    /// Apply pending migrations.
+    Up {
+        /// Number of migrations to apply (default: all pending).
+        #[arg(short = 'n', long)]
+        steps: Option<u32>,
+    },
+    /// Revert applied migrations.
+    Down {
+        /// Number of migrations to revert.
+        #[arg(short = 'n', long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// Show the status of every migration.
+    Status,
+}
+
+#[derive(Args)]
+struct PruneArgs {
+    /// Only prune this format (default: all formats with a retention period).
+    #[arg(long)]
+    format: Option<i32>,
+    /// Only count the upload sessions that would be pruned.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Subcommand)]
+enum FormatCommand {
+    /// Print a format definition as JSON.
+    Export { id: i32 },
+    /// Create a format from a JSON definition ("-" reads from stdin).
+    Import { file: String },
+}
+
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    // stdout is reserved for command output
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .init();
+
+    let mut out = io::stdout().lock();
+    let result = match cli.command {
+        Command::Keygen(args) => keygen(args, &mut out),
+        Command::RotateSigningKey => {
+            Config::init_and_check().and_then(|_| rotate_signing_key(&mut out))
+        }
+        command => actix_web::rt::System::new().block_on(async {
+            Config::init_and_check()?;
+            RecordCipher::init()?;
+            DBConfig::init_db_connection().await?;
+            run(command, &mut out).await
+        }),
+    };
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run `repository-admin` with `args` (without the binary name) and write
+/// its output to `out`. Unlike [`main`], this expects the config and the
+/// database connection to be set up already, e.g. by the app.
+pub async fn run_with_args<I, T>(args: I, out: &mut dyn Write) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let args =
+        iter::once(OsString::from("repository-admin")).chain(args.into_iter().map(Into::into));
+    match Cli::try_parse_from(args)?.command {
+        Command::Keygen(args) => keygen(args, out),
+        Command::RotateSigningKey => rotate_signing_key(out),
+        command => run(command, out).await,
+    }
+}
+
+async fn run(command: Command, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let db = DBConfig::get_connection();
+
+    match command {
+        Command::User(UserCommand::Create {
+            username,
+            superuser,
+            role,
+            password,
+        }) => {
+            if UserQuery::find_by_username(&username).await?.is_some() {
+                return Err(format!("user {username:?} already exists").into());
+            }
+            let user = user::Model {
+                username,
+                password: read_password(&password)?.to_hash()?,
+                is_superuser: superuser,
+                role,
+                active: true,
+                created_at: chrono::offset::Utc::now(),
+                ..Default::default()
+            };
+            let user = UserMutation::create(db, user).await?;
+            writeln!(out, "created user {:?} (id={})", user.username, user.id)?;
+        }
+        Command::User(UserCommand::SetPassword { username, password }) => {
+            let user = UserQuery::find_by_username(&username)
+                .await?
+                .ok_or_else(|| format!("user {username:?} does not exist"))?;
+            let new_user = user::UpdatableModel {
+                password: Some(read_password(&password)?.to_hash()?),
+                ..Default::default()
+            };
+            UserMutation::update(db, user, new_user).await?;
+            writeln!(out, "updated password for user {username:?}")?;
+        }
+        Command::User(UserCommand::Reset2fa { username }) => {
+            let user = UserQuery::find_by_username(&username)
+                .await?
+                .ok_or_else(|| format!("user {username:?} does not exist"))?;
+            UserMutation::reset_totp(db, user).await?;
+            writeln!(out, "disabled 2FA for user {username:?}")?;
+        }
+        Command::Migrate(MigrateCommand::Up { steps }) => Migrator::up(db, steps).await?,
+        Command::Migrate(MigrateCommand::Down { steps }) => Migrator::down(db, Some(steps)).await?,
+        Command::Migrate(MigrateCommand::Status) => {
+            for migration in Migrator::get_migration_with_status(db).await? {
+                writeln!(out, "{}\t{}", migration.status(), migration.name())?;
+            }
+        }
+        Command::Prune(PruneArgs { format, dry_run }) => {
+            let formats = match format {
+                Some(id) => vec![format::Entity::find_by_id(id)
+                    .one(db)
+                    .await?
+                    .ok_or_else(|| format!("format {id} does not exist"))?],
+                _ => FormatMutation::get_prunable_formats(db).await?,
+            };
+            let now = chrono::offset::Utc::now();
+            for format in formats {
+                if format.retention_period_minutes <= 0 {
+                    return Err(format!(
+                        "format {} has no retention period, refusing to prune",
+                        format.id
+                    )
+                    .into());
+                }
+                let result = UploadSessionMutation::prune_format(db, format, now, dry_run).await?;
+                writeln!(out, "{}", serde_json::to_string(&result)?)?;
+            }
+        }
+        Command::Format(FormatCommand::Export { id }) => {
+            let format = format::Entity::find_by_id(id)
+                .one(db)
+                .await?
+                .ok_or_else(|| format!("format {id} does not exist"))?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&format)?)?;
+        }
+        Command::Format(FormatCommand::Import { file }) => {
+            let contents = match file.as_str() {
+                "-" => {
+                    let mut contents = String::new();
+                    io::stdin().read_to_string(&mut contents)?;
+                    contents
+                }
+                path => std::fs::read_to_string(path)?,
+            };
+            let format: format::Model = serde_json::from_str(&contents)?;
+            let format = FormatMutation::create(db, format, None).await?;
+            writeln!(
+                out,
+                "created format {:?} (id={})",
+                format.name.as_ref(),
+                format.id.as_ref()
+            )?;
+        }
+        Command::Keygen(_) | Command::RotateSigningKey => {
+            unreachable!("doesn't need the database")
+        }
+    }
+    Ok(())
+}
+
+/// Read a password from `--password-file` or, if not set, the first line of stdin.
+/// The password must satisfy the complexity policy.
+fn read_password(args: &PasswordArgs) -> Result<UserPassword, Box<dyn Error>> {
+    let password = match args.password_file.as_ref() {
+        Some(path) => std::fs::read_to_string(path)?,
+        _ => {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            line
+        }
+    };
+    let password = UserPassword::from(password.trim_end_matches(['\r', '\n']).to_string());
+    password.verify_complexity()?;
+    Ok(password)
+}
+
+fn parse_role(role: &str) -> Result<user::Role, String> {
+    serde_json::from_value(serde_json::Value::String(role.into()))
+        .map_err(|_| format!("unknown role {role:?}"))
+}
+
+fn keygen(args: KeygenArgs, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let rng = SystemRandom::new();
+    if args.totp {
+        let mut key = [0u8; 32];
+        rng.fill(&mut key).map_err(|_| "cannot generate TOTP key")?;
+        writeln!(out, "{}", general_purpose::STANDARD.encode(key))?;
+        return Ok(());
+    }
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(|_| "cannot generate Ed25519 key")?;
+    writeln!(out, "{}", general_purpose::STANDARD.encode(pkcs8.as_ref()))?;
+    Ok(())
+}
+
+/// Print the variables to set for a rotated signing key, as `KEY=value` lines.
+fn rotate_signing_key(out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let config = Config::get();
+    let current = general_purpose::STANDARD.decode(&config.ed25519_signing_key)?;
+    let current = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&current)
+        .map_err(|_| "ED25519_SIGNING_KEY isn't a valid Ed25519 key")?;
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| "cannot generate Ed25519 key")?;
+    let previous = config
+        .ed25519_previous_public_keys
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(String::from);
+    let previous = iter::once(general_purpose::STANDARD.encode(current.public_key()))
+        .chain(previous)
+        .collect::<Vec<_>>();
+    writeln!(
+        out,
+        "ED25519_SIGNING_KEY={}",
+        general_purpose::STANDARD.encode(pkcs8.as_ref())
+    )?;
+    writeln!(out, "ED25519_PREVIOUS_PUBLIC_KEYS={}", previous.join(","))?;
+    Ok(())
+}
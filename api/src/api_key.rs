@@ -1,31 +1,62 @@
 use crate::{
     auth::jwt::Token,
+    core_middleware::scope::RequireWriteScope,
     error::{APIError, APIResponse, AsAPIResult},
-    pagination::{PaginatedResponse, Validate},
-    util::verify_admin,
+    pagination::{validated_pager, PaginatedResponse},
+    util::{verify_can_manage, verify_can_see_user, verify_role},
 };
 use actix_web::{
-    delete, get, patch, post,
+    delete, patch, post, route,
     web::{Json, Path, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use central_repository_config::inner::Config;
 use central_repository_dao::{
-    user::Model as UserModel, ApiKeyMutation, ApiKeyQuery, GetAllPaginated, PaginationOptions,
+    conf::DBConfig,
+    user::{Model as UserModel, Role},
+    ApiKeyMutation, ApiKeyQuery, GetAllPaginated, PaginationOptions,
 };
 use entity::api_key::{ModelAsQuery, UpdatableModel as ApiKeyUpdatableModel};
 use itertools::Itertools;
 use log::info;
 use uuid::Uuid;
 
-#[post("{user}/api-key")]
+/// Only admins can see the keys of other users (see `get_all_api_keys`), for
+/// everyone else they don't exist.
+fn verify_can_see_keys(auth: &UserModel, user_id: Uuid, key_id: Uuid) -> Result<(), APIError> {
+    if user_id != auth.id && !auth.has_role(Role::Admin) {
+        info!(
+            "Hid the api keys of user {user_id} from user id: {}",
+            auth.id
+        );
+        return Err(key_not_found(user_id, key_id));
+    }
+    Ok(())
+}
+
+fn key_not_found(user_id: Uuid, key_id: Uuid) -> APIError {
+    APIError::NotFound(format!(
+        "key with id '{}' for user id '{}'",
+        key_id, user_id
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/{user}/api-key",
+    tag = "api_key",
+    params(("user" = Uuid, Path, description = "User ID")),
+    responses((status = 201, description = "The new API key and its token", body = TokenResponse))
+)]
+#[post("{user}/api-key", wrap = "RequireWriteScope")]
 pub async fn create_api_key(user: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
     let user_id = user.into_inner();
     info!("api key: user: {:?}, target ID: {:?}", auth.id, user_id);
+    verify_can_see_user(&auth, user_id)?;
     if user_id != auth.id {
         // if this user is trying to create api key for someone else,
         // check if it has admin permissions.
-        verify_admin(&auth)?;
+        verify_role(&auth, Role::Admin)?;
     }
     if auth.is_superuser && user_id == auth.id {
         return Err(APIError::InvalidOperation(
@@ -33,22 +64,30 @@ pub async fn create_api_key(user: Path<Uuid>, auth: ReqData<UserModel>) -> APIRe
         ));
     }
 
-    let user = match ApiKeyQuery::get_user_and_keys(user_id).await? {
-        Some((user, keys)) => {
-            if keys.len() >= Config::get().max_api_keys_per_user as usize {
-                return Err(APIError::InvalidOperation(format!(
-                    "Cannot have more than {} keys. Plase delete one of the following {} keys: {}",
-                    Config::get().max_api_keys_per_user,
-                    keys.len(),
-                    keys.iter().map(|it| it.id).join(",")
-                )));
-            }
-            user
-        }
-        _ => return Err(APIError::NotFound(format!("user ID '{}'", user_id))),
-    };
-
-    let api_key = ApiKeyMutation::create_for_user(&user).await?;
+    let auth = auth.into_inner();
+    let max_keys = Config::get().max_api_keys_per_user;
+    let (user, api_key) = DBConfig::transaction(|txn| {
+        Box::pin(async move {
+            let user = match ApiKeyQuery::get_user_and_keys(txn, user_id).await? {
+                Some((user, keys)) => {
+                    if keys.len() >= max_keys as usize {
+                        return Err(APIError::InvalidOperation(format!(
+                            "Cannot have more than {} keys. Plase delete one of the following {} keys: {}",
+                            max_keys,
+                            keys.len(),
+                            keys.iter().map(|it| it.id).join(",")
+                        )));
+                    }
+                    user
+                }
+                _ => return Err(APIError::NotFound(format!("user ID '{}'", user_id))),
+            };
+            verify_can_manage(&auth, &user)?;
+            let api_key = ApiKeyMutation::create_for_user(txn, &user).await?;
+            Ok((user, api_key))
+        })
+    })
+    .await?;
     let json = Token::create_api_key(user, api_key).await?;
     HttpResponse::Created().json(json).to_ok()
 }
@@ -56,7 +95,22 @@ pub async fn create_api_key(user: Path<Uuid>, auth: ReqData<UserModel>) -> APIRe
 /// Update this api key.
 /// {user} <- 1st item of user_and_key_id
 /// {key_id} <- 2nd item of user_and_key_id
-#[patch("{user}/api-key/{key_id}")]
+#[utoipa::path(
+    patch,
+    path = "/user/{user}/api-key/{key_id}",
+    tag = "api_key",
+    params(
+        ("user" = Uuid, Path, description = "User ID"),
+        ("key_id" = Uuid, Path, description = "API key ID")
+    ),
+    request_body = ApiKeyUpdate,
+    responses((
+        status = 200,
+        description = "The updated API key (as a TokenResponse with the new token if it was rotated)",
+        body = ApiKey
+    ))
+)]
+#[patch("{user}/api-key/{key_id}", wrap = "RequireWriteScope")]
 pub async fn update_api_key(
     user_and_key_id: Path<(Uuid, Uuid)>,
     auth: ReqData<UserModel>,
@@ -64,11 +118,7 @@ pub async fn update_api_key(
 ) -> APIResponse {
     let (user_id, key_id) = user_and_key_id.into_inner();
     info!("api key: user: {:?}, target ID: {:?}", auth.id, user_id);
-    if user_id != auth.id {
-        // if this user is trying to create api key for someone else,
-        // check if it has admin permissions.
-        verify_admin(&auth)?;
-    }
+    verify_can_see_keys(&auth, user_id, key_id)?;
     if auth.is_superuser && user_id == auth.id {
         return Err(APIError::InvalidOperation(
             "cannot modify api keys for an admin".into(),
@@ -77,16 +127,12 @@ pub async fn update_api_key(
 
     let (user, key) = match ApiKeyQuery::get_user_and_single_key(user_id, key_id).await? {
         Some((user, key)) => (user, key),
-        _ => {
-            return Err(APIError::NotFound(format!(
-                "key with id '{}' for user id '{}'",
-                key_id, user_id
-            )))
-        }
+        _ => return Err(key_not_found(user_id, key_id)),
     };
+    verify_can_manage(&auth, &user)?;
 
     let rotate_requested = new.rotate.unwrap_or_default();
-    let api_key = ApiKeyMutation::update(key, new.into_inner()).await?;
+    let api_key = ApiKeyMutation::update(DBConfig::get_connection(), key, new.into_inner()).await?;
     if !rotate_requested {
         // no need to forge token again since it wasn't rotated.
         return HttpResponse::Ok().json(api_key).to_ok();
@@ -99,18 +145,24 @@ pub async fn update_api_key(
 /// Update this api key.
 /// {user} <- 1st item of user_and_key_id
 /// {key_id} <- 2nd item of user_and_key_id
-#[delete("{user}/api-key/{key_id}")]
+#[utoipa::path(
+    delete,
+    path = "/user/{user}/api-key/{key_id}",
+    tag = "api_key",
+    params(
+        ("user" = Uuid, Path, description = "User ID"),
+        ("key_id" = Uuid, Path, description = "API key ID")
+    ),
+    responses((status = 204, description = "The API key was deleted"))
+)]
+#[delete("{user}/api-key/{key_id}", wrap = "RequireWriteScope")]
 pub async fn delete_api_key(
     user_and_key_id: Path<(Uuid, Uuid)>,
     auth: ReqData<UserModel>,
 ) -> APIResponse {
     let (user_id, key_id) = user_and_key_id.into_inner();
     info!("api key: user: {:?}, target ID: {:?}", auth.id, user_id);
-    if user_id != auth.id {
-        // if this user is trying to create api key for someone else,
-        // check if it has admin permissions.
-        verify_admin(&auth)?;
-    }
+    verify_can_see_keys(&auth, user_id, key_id)?;
     if auth.is_superuser && user_id == auth.id {
         return Err(APIError::InvalidOperation(
             "cannot modify api keys for an admin".into(),
@@ -118,29 +170,43 @@ pub async fn delete_api_key(
     }
 
     let key = match ApiKeyQuery::get_user_and_single_key(user_id, key_id).await? {
-        Some((_user, key)) => key,
-        _ => {
-            return Err(APIError::NotFound(format!(
-                "key with id '{}' for user id '{}'",
-                key_id, user_id
-            )))
+        Some((user, key)) => {
+            verify_can_manage(&auth, &user)?;
+            key
         }
+        _ => return Err(key_not_found(user_id, key_id)),
     };
-    ApiKeyMutation::delete(key).await?;
+    ApiKeyMutation::delete(DBConfig::get_connection(), key).await?;
     HttpResponse::NoContent().finish().to_ok()
 }
 
-#[get("api-key")]
+#[utoipa::path(
+    get,
+    path = "/user/api-key",
+    tag = "api_key",
+    params(PaginationOptions, ModelAsQuery),
+    responses((status = 200, description = "API keys visible to this user", body = Vec<ApiKeyWithStatus>))
+)]
+#[route("api-key", method = "GET", method = "HEAD")]
 async fn get_all_api_keys(
+    req: HttpRequest,
     pager: Query<PaginationOptions>,
     filter: Query<ModelAsQuery>,
     auth: ReqData<UserModel>,
 ) -> APIResponse {
-    pager.validate()?;
+    let pager = validated_pager(&req, pager)?;
     let filter = filter.into_inner();
-    // todo!()
-    let pager = pager.into_inner();
     let user = auth.into_inner();
-    let entries = ApiKeyQuery::get_all_filtered_for_user(&filter, &pager, user, None).await?;
-    Ok(PaginatedResponse::from(entries).into())
+    let (keys, num_pages, num_items) =
+        ApiKeyQuery::get_all_filtered_for_user(&filter, &pager, user, None).await?;
+    let max_age_days = Config::get().max_key_age_days;
+    let now = chrono::offset::Utc::now();
+    let keys = keys
+        .into_iter()
+        .map(|key| key.with_status(max_age_days, now))
+        .collect::<Vec<_>>();
+    Ok(PaginatedResponse::from((keys, num_pages, num_items))
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
 }
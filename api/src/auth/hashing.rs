@@ -4,12 +4,14 @@ use argon2::password_hash::Error as ArgonError;
 use argon2::password_hash::{
     rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
 };
-use log::error;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
 use super::jwt::ARGON;
 
+// Minimum length for passwords that go through the complexity policy.
+const MIN_PASSWORD_LENGTH: usize = 12;
+
 pub struct UserPassword {
     password: String,
 }
@@ -80,6 +82,25 @@ impl UserPassword {
         self.password.try_get_argon_hash()
     }
 
+    /// Check this password against the complexity policy: at least
+    /// `MIN_PASSWORD_LENGTH` characters, with lowercase and uppercase letters
+    /// and digits.
+    pub fn verify_complexity(&self) -> Result<(), String> {
+        let password = &self.password;
+        if password.chars().count() < MIN_PASSWORD_LENGTH {
+            return Err(format!(
+                "password must be at least {MIN_PASSWORD_LENGTH} characters long"
+            ));
+        }
+        if !password.chars().any(|c| c.is_lowercase())
+            || !password.chars().any(|c| c.is_uppercase())
+            || !password.chars().any(|c| c.is_ascii_digit())
+        {
+            return Err("password must contain lowercase and uppercase letters and digits".into());
+        }
+        Ok(())
+    }
+
     /// verify whether a password matches a known stored hash.
     pub fn verify_password(user_input: &String, true_password: &str) -> Result<(), APIError> {
         user_input.try_validate_against_hash(true_password)
@@ -5,11 +5,13 @@ use central_repository_config::inner::Config;
 use central_repository_dao::{user::Model as UserModel, ApiKeyQuery, UserQuery};
 use chrono::{Duration, Utc};
 use entity::api_key::Model as ApiKeyModel;
-use jsonwebtoken::{decode, encode, Algorithm, Header, Validation};
+use jsonwebtoken::{decode, encode, errors::ErrorKind, Algorithm, DecodingKey, Header, Validation};
 use lazy_static::lazy_static;
-use log::{error, info, warn};
+use log::{info, warn};
 
 use serde::{Deserialize, Serialize};
+use strum::AsRefStr;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::error::APIError;
@@ -28,20 +30,60 @@ lazy_static! {
     };
 }
 
+/// What a short-lived token can be used for, see `LoginCredentials::scopes`.
+/// Every token can read.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema, AsRefStr,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum TokenScope {
+    Read,
+    /// Create, update or delete anything (what's allowed still depends on
+    /// the user's role and entitlements).
+    Write,
+}
+
+/// The scopes of the token of a request, added to its extensions by
+/// `AuthMiddleware`. Tokens without scopes (and API keys) can do anything
+/// their user can.
+#[derive(Debug, Clone, Default)]
+pub struct TokenScopes(Option<Vec<TokenScope>>);
+
+impl TokenScopes {
+    pub fn allows(&self, scope: TokenScope) -> bool {
+        match &self.0 {
+            Some(scopes) => scope == TokenScope::Read || scopes.contains(&scope),
+            _ => true,
+        }
+    }
+}
+
+/// The API key a request was authenticated with, added to its extensions by
+/// `AuthMiddleware`. Unset for short-lived (password) tokens.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuthenticatedKey(pub Option<Uuid>);
+
 #[derive(Debug, Deserialize)]
 pub struct Token {
     token: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TokenResponse {
     token: String,
+    #[schema(value_type = User)]
     user: UserModel,
 
     // Only applies to API keys.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<ApiKey>)]
     api_key: Option<ApiKeyModel>,
+
+    // Only set if the token was created with scopes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<TokenScope>>,
 }
 
 impl Token {
@@ -72,17 +114,37 @@ impl Token {
             user,
             token: claims.try_to_jwt()?,
             api_key: Some(api_key),
+            scopes: None,
         })
     }
 
-    /// Creates a short-lived token for a given user.
-    /// This function only forges short-lived tokens. Longer-lived tokens
+    /// Sign a short-lived token for `user` and validate it again, i.e. check
+    /// that the JWT keys are loaded and belong together.
+    pub fn self_test(user: &UserModel) -> bool {
+        if !APIConfig::has_jwt_keys() {
+            return false;
+        }
+        let claims = Claims::new_short_lived(user);
+        claims
+            .try_to_jwt()
+            .and_then(|jwt| Claims::try_from_jwt(&jwt))
+            .is_ok_and(|decoded| decoded.sub == user.id)
+    }
+
+    /// Creates a short-lived token for a given user, limited to `scopes` if
+    /// set. This function only forges short-lived tokens. Longer-lived tokens
     /// should be generated using the API key function.
-    pub fn build_from_user(user: UserModel) -> Result<TokenResponse, APIError> {
+    pub fn build_from_user(
+        user: UserModel,
+        scopes: Option<Vec<TokenScope>>,
+    ) -> Result<TokenResponse, APIError> {
+        let mut claims = Claims::new_short_lived(&user);
+        claims.scp = scopes.clone();
         Ok(TokenResponse {
-            token: Claims::new_short_lived(&user).try_to_jwt()?,
+            token: claims.try_to_jwt()?,
             user,
             api_key: None,
+            scopes,
         })
     }
 
@@ -121,6 +183,15 @@ impl Token {
             return Err(APIError::InvalidToken);
         }
 
+        let config = Config::get();
+        if config.strict_key_age && key.is_stale(config.max_key_age_days, Utc::now()) {
+            info!(
+                "Received a valid token but key is stale (user id: {}, token id: {})",
+                user.id, key.id
+            );
+            return Err(APIError::StaleKey(config.max_key_age_days));
+        }
+
         info!(
             "successfully validated API token for user: {}: '{}'",
             user.id, user.username
@@ -148,16 +219,21 @@ impl Token {
         Ok(user)
     }
 
-    /// Validates a JWT token. Returns an instance of the user on success.
+    /// Validates a JWT token. Returns an instance of the user, the scopes of
+    /// the token and the API key it belongs to (if any) on success.
     #[inline(always)]
-    pub async fn validate(&self) -> Result<UserModel, APIError> {
+    pub async fn validate(&self) -> Result<(UserModel, TokenScopes, AuthenticatedKey), APIError> {
         // try to decode and validate token data.
         let token = Claims::try_from_jwt(&self.token)?;
         // token is valid, now validate the user (and the token)
-        if token.aks.is_some() {
-            return Self::validate_api_key(token).await;
+        if let Some(api_key_data) = &token.aks {
+            let key = AuthenticatedKey(Some(api_key_data.id));
+            let user = Self::validate_api_key(token).await?;
+            return Ok((user, TokenScopes::default(), key));
         }
-        Self::validate_user_token(token).await
+        let scopes = TokenScopes(token.scp.clone());
+        let user = Self::validate_user_token(token).await?;
+        Ok((user, scopes, AuthenticatedKey::default()))
     }
 }
 
@@ -167,6 +243,12 @@ impl From<String> for Token {
     }
 }
 
+impl TokenResponse {
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
 impl From<TokenResponse> for HttpResponse {
     fn from(value: TokenResponse) -> Self {
         HttpResponse::Ok().json(value)
@@ -201,18 +283,37 @@ pub struct Claims {
     // ApiKey-only attributes
     #[serde(skip_serializing_if = "Option::is_none")]
     aks: Option<ApiKeyData>,
+
+    // scopes (short-lived tokens only), unset means unrestricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scp: Option<Vec<TokenScope>>,
 }
 
 impl Claims {
     /// Try to decode a Claim from a JWT.
     #[inline(always)]
     fn try_from_jwt(jwt: &str) -> Result<Claims, APIError> {
-        let decoding_key = APIConfig::get_decoding_key();
-        let token = decode::<Claims>(jwt, decoding_key, &VALIDATION).map_err(|err| {
+        Self::decode_with_keys(jwt, APIConfig::get_decoding_keys()).map_err(|err| {
             info!("Token validation failure: {:?}", err);
             APIError::InvalidToken
-        })?;
-        Ok(token.claims)
+        })
+    }
+
+    /// Decode a JWT signed with any of `keys`. Only a wrong signature makes it
+    /// try the next key: an expired token is rejected right away.
+    fn decode_with_keys(
+        jwt: &str,
+        keys: &[DecodingKey],
+    ) -> Result<Claims, jsonwebtoken::errors::Error> {
+        let mut result = Err(ErrorKind::InvalidSignature.into());
+        for key in keys {
+            result = decode::<Claims>(jwt, key, &VALIDATION).map(|token| token.claims);
+            match &result {
+                Err(err) if *err.kind() == ErrorKind::InvalidSignature => continue,
+                _ => break,
+            }
+        }
+        result
     }
 
     /// Convert this claim to an encoded JWT.
@@ -235,6 +336,7 @@ impl Claims {
             iat: now.timestamp() as usize,
             exp: (now + expires_in).timestamp() as usize,
             aks: None,
+            scp: None,
         }
     }
 
@@ -257,3 +359,48 @@ impl Claims {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::EncodingKey;
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+
+    use super::*;
+
+    /// A key pair, as used for ED25519_SIGNING_KEY and ED25519_PREVIOUS_PUBLIC_KEYS.
+    fn key_pair() -> (EncodingKey, DecodingKey) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        (
+            EncodingKey::from_ed_der(pkcs8.as_ref()),
+            DecodingKey::from_ed_der(key.public_key().as_ref()),
+        )
+    }
+
+    fn sign(key: &EncodingKey, expires_in: Duration) -> String {
+        let claims = Claims::new_from_user(&UserModel::default(), expires_in);
+        encode(&JWT_HEADER, &claims, key).unwrap()
+    }
+
+    #[test]
+    fn tokens_of_previous_keys_are_accepted() {
+        let (current, current_public) = key_pair();
+        let (previous, previous_public) = key_pair();
+        let (_, unknown_public) = key_pair();
+        let keys = [current_public, previous_public];
+
+        let token = sign(&current, Duration::minutes(5));
+        assert!(Claims::decode_with_keys(&token, &keys).is_ok());
+        let token = sign(&previous, Duration::minutes(5));
+        assert!(Claims::decode_with_keys(&token, &keys).is_ok());
+        let err = Claims::decode_with_keys(&token, &[unknown_public]).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::InvalidSignature);
+        // the next key isn't tried for other errors
+        let token = sign(&previous, Duration::minutes(-5));
+        let err = Claims::decode_with_keys(&token, &keys).unwrap_err();
+        assert_eq!(*err.kind(), ErrorKind::ExpiredSignature);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod hashing;
 pub mod jwt;
+pub mod totp;
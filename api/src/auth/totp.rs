@@ -0,0 +1,305 @@
+use actix_web::web;
+use base64::{engine::general_purpose, Engine as _};
+use central_repository_config::inner::Config;
+use central_repository_dao::{conf::DBConfig, user::Model as UserModel, UserMutation};
+use log::info;
+use ring::{
+    aead::{Aad, Nonce, NONCE_LEN},
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use uuid::Uuid;
+
+use crate::{common::handle_fatal, conf::APIConfig, error::APIError};
+
+use super::hashing::StringHashUtil;
+
+// RFC 6238 parameters. These are the defaults of every authenticator app,
+// which may ignore them if they're sent in the provisioning URI.
+const PERIOD_SECONDS: u64 = 30;
+const DIGITS: usize = 6;
+// RFC 4226 recommends 160 bits.
+const SECRET_LENGTH: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+// 16 base32 characters.
+const RECOVERY_CODE_LENGTH: usize = 10;
+const ISSUER: &str = "central-repository";
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub struct TotpSecret(Vec<u8>);
+
+impl TotpSecret {
+    pub fn generate() -> Result<Self, APIError> {
+        random_bytes(SECRET_LENGTH).map(Self)
+    }
+
+    /// Base32-encoded secret, for authenticator apps that can't scan a QR code.
+    pub fn to_base32(&self) -> String {
+        encode_base32(&self.0)
+    }
+
+    /// The `otpauth://` URI authenticator apps use to register this secret.
+    pub fn provisioning_uri(&self, username: &str) -> String {
+        format!(
+            "otpauth://totp/{ISSUER}:{}?secret={}&issuer={ISSUER}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECONDS}",
+            encode_uri_component(username),
+            self.to_base32()
+        )
+    }
+
+    fn code_at(&self, step: u64) -> u32 {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &self.0);
+        let digest = hmac::sign(&key, &step.to_be_bytes());
+        let digest = digest.as_ref();
+        // dynamic truncation (RFC 4226, section 5.3)
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let value = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        value % 10u32.pow(DIGITS as u32)
+    }
+
+    /// Check `code` against the code at `unix_time`, also accepting the codes of
+    /// the `skew` steps before and after it (to tolerate clock drift).
+    /// Returns the time step of the matching code.
+    pub fn verify_at(&self, code: &str, unix_time: u64, skew: u64) -> Option<u64> {
+        if !is_totp_code(code) {
+            return None;
+        }
+        let code: u32 = code.parse().ok()?;
+        let current = unix_time / PERIOD_SECONDS;
+        (current.saturating_sub(skew)..=current.saturating_add(skew))
+            .find(|step| self.code_at(*step) == code)
+    }
+
+    /// Check `code` against the current time, see [`Self::verify_at`].
+    pub fn verify(&self, code: &str) -> Option<i64> {
+        let now = chrono::offset::Utc::now().timestamp().max(0) as u64;
+        let skew = Config::get().totp_skew_steps.into();
+        self.verify_at(code, now, skew).map(|step| step as i64)
+    }
+
+    /// Encrypt this secret so it can be stored. The user id is authenticated
+    /// along with it, so the encrypted secret can't be copied to another user.
+    pub fn encrypt(&self, user_id: Uuid) -> Result<String, APIError> {
+        let key = APIConfig::get_totp_key().ok_or_else(|| {
+            APIError::InvalidOperation("2FA is not configured on this server".into())
+        })?;
+        let nonce = random_bytes(NONCE_LEN)?;
+        let mut in_out = self.0.clone();
+        key.seal_in_place_append_tag(
+            Nonce::try_assume_unique_for_key(&nonce).expect("nonce has the right length"),
+            Aad::from(user_id.as_bytes()),
+            &mut in_out,
+        )
+        .map_err(|err| handle_fatal!("TOTP secret encryption", err, APIError::ServerError))?;
+        Ok(general_purpose::STANDARD.encode([nonce, in_out].concat()))
+    }
+
+    pub fn decrypt(encrypted: &str, user_id: Uuid) -> Result<Self, APIError> {
+        let key = APIConfig::get_totp_key().ok_or_else(|| {
+            handle_fatal!(
+                "TOTP secret decryption",
+                "TOTP_ENCRYPTION_KEY is not set",
+                APIError::ServerError
+            )
+        })?;
+        let mut decoded = general_purpose::STANDARD
+            .decode(encrypted)
+            .map_err(|err| handle_fatal!("TOTP secret decoding", err, APIError::ServerError))?;
+        if decoded.len() < NONCE_LEN {
+            return handle_fatal!(
+                "TOTP secret decoding",
+                "secret is too short",
+                Err(APIError::ServerError)
+            );
+        }
+        let mut in_out = decoded.split_off(NONCE_LEN);
+        let secret = key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(&decoded).expect("nonce has the right length"),
+                Aad::from(user_id.as_bytes()),
+                &mut in_out,
+            )
+            // i.e. TOTP_ENCRYPTION_KEY changed
+            .map_err(|err| handle_fatal!("TOTP secret decryption", err, APIError::ServerError))?;
+        Ok(Self(secret.to_vec()))
+    }
+}
+
+/// Generate a new set of one-time recovery codes. Returns the codes (which
+/// are only shown to the user once) and their hashes.
+pub fn generate_recovery_codes() -> Result<(Vec<String>, Vec<String>), APIError> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = encode_base32(&random_bytes(RECOVERY_CODE_LENGTH)?);
+            let hash = code.try_get_argon_hash()?;
+            // group the code in blocks of 4 characters to make it easier to type.
+            let code = code
+                .as_bytes()
+                .chunks(4)
+                .map(|chunk| String::from_utf8_lossy(chunk))
+                .collect::<Vec<_>>()
+                .join("-");
+            Ok((code, hash))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|codes| codes.into_iter().unzip())
+}
+
+/// Check the second factor of a password login for a user with 2FA enabled:
+/// either a TOTP code or one of the user's unused recovery codes. Every code
+/// can only be used once.
+pub async fn verify_login_code(user: &UserModel, code: Option<&str>) -> Result<(), APIError> {
+    let code = code
+        .map(str::trim)
+        .filter(|code| !code.is_empty())
+        .ok_or(APIError::TotpRequired)?;
+    let db = DBConfig::get_connection();
+    if is_totp_code(code) {
+        let secret = TotpSecret::decrypt(user.totp_secret.as_deref().unwrap_or_default(), user.id)?;
+        let step = secret.verify(code).ok_or(APIError::InvalidTotpCode)?;
+        if !UserMutation::use_totp_step(db, user.id, step).await? {
+            info!(
+                "user {:?} (id={}) tried to reuse a TOTP code",
+                user.username, user.id
+            );
+            return Err(APIError::InvalidTotpCode);
+        }
+        return Ok(());
+    }
+    // recovery codes are case-insensitive and may be typed without dashes.
+    let code = code.replace(['-', ' '], "").to_uppercase();
+    let hashes = user.totp_recovery_codes.0.clone();
+    let index = web::block(move || {
+        hashes
+            .iter()
+            .position(|hash| code.try_validate_against_hash(hash).is_ok())
+    })
+    .await?
+    .ok_or(APIError::InvalidTotpCode)?;
+    if !UserMutation::use_totp_recovery_code(db, user, index).await? {
+        return Err(APIError::InvalidTotpCode);
+    }
+    info!(
+        "user {:?} (id={}) logged in with a recovery code, {} left",
+        user.username,
+        user.id,
+        user.totp_recovery_codes.0.len() - 1
+    );
+    Ok(())
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS && code.bytes().all(|c| c.is_ascii_digit())
+}
+
+fn random_bytes(length: usize) -> Result<Vec<u8>, APIError> {
+    let mut bytes = vec![0; length];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|err| handle_fatal!("random number generation", err, APIError::ServerError))?;
+    Ok(bytes)
+}
+
+/// RFC 4648 base32, without padding.
+fn encode_base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+fn encode_uri_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the SHA-1 secret of the RFC 6238 test vectors.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec())
+    }
+
+    #[test]
+    fn rfc_6238_vectors() {
+        // the RFC uses 8 digits, these are the last 6.
+        for (unix_time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            let step = unix_time / PERIOD_SECONDS;
+            assert_eq!(
+                rfc_secret().verify_at(code, unix_time, 0),
+                Some(step),
+                "{unix_time}"
+            );
+        }
+    }
+
+    #[test]
+    fn skew_window() {
+        let secret = rfc_secret();
+        // 287082 is the code of step 1.
+        assert_eq!(secret.verify_at("287082", 89, 0), None);
+        assert_eq!(secret.verify_at("287082", 89, 1), Some(1));
+        assert_eq!(secret.verify_at("287082", 0, 1), Some(1));
+        assert_eq!(secret.verify_at("287082", 119, 1), None);
+        assert_eq!(secret.verify_at("287082", 119, 2), Some(1));
+        // the window doesn't underflow at step 0.
+        assert_eq!(secret.verify_at("287082", 0, u64::MAX), Some(1));
+    }
+
+    #[test]
+    fn malformed_codes() {
+        let secret = rfc_secret();
+        for code in ["", "28708", "2870820", "28708a", " 287082", "+28708"] {
+            assert_eq!(secret.verify_at(code, 59, 1), None, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn base32() {
+        // RFC 4648 vectors, without padding.
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(encode_base32(bytes.as_bytes()), encoded);
+        }
+        assert_eq!(rfc_secret().to_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+}
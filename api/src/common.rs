@@ -1,11 +1,15 @@
 use std::{cell::RefCell, rc::Rc};
 
 use central_repository_dao::{Deserialize, Serialize};
+use utoipa::IntoParams;
 
 pub type RcRefCell<T> = Rc<RefCell<T>>;
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Default, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DebugMode {
+    /// Return the parsed search query, the formats it would search and the
+    /// kinds of their columns (a `QuerySummary`) instead of records.
     debug: Option<bool>,
 }
 
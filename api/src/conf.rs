@@ -1,17 +1,30 @@
 use central_repository_config::inner::Config;
-use central_repository_dao::LimitController;
+use central_repository_dao::{AttemptLimiter, LimitController};
 use jsonwebtoken::{DecodingKey, EncodingKey};
-use std::error::Error;
+use std::{
+    error::Error,
+    time::{Duration, Instant},
+};
 
 use base64::engine::general_purpose;
 use base64::Engine as _;
 use log::info;
 use once_cell::sync::OnceCell;
-use ring::signature::{Ed25519KeyPair, KeyPair};
+use ring::{
+    aead::{LessSafeKey, UnboundKey, AES_256_GCM},
+    signature::{Ed25519KeyPair, KeyPair},
+};
+
+use crate::util::TrustedProxy;
 
 static ENCODING_KEY: OnceCell<EncodingKey> = OnceCell::new();
-static DECODING_KEY: OnceCell<DecodingKey> = OnceCell::new();
+static DECODING_KEYS: OnceCell<Vec<DecodingKey>> = OnceCell::new();
 static LIMIT_SERVICE: OnceCell<LimitController> = OnceCell::new();
+static SSE_LIMIT_SERVICE: OnceCell<LimitController> = OnceCell::new();
+static TOTP_KEY: OnceCell<Option<LessSafeKey>> = OnceCell::new();
+static LOGIN_LIMITER: OnceCell<AttemptLimiter> = OnceCell::new();
+static TRUSTED_PROXIES: OnceCell<Vec<TrustedProxy>> = OnceCell::new();
+static STARTED_AT: OnceCell<Instant> = OnceCell::new();
 
 pub struct APIConfig;
 
@@ -32,30 +45,130 @@ impl APIConfig {
         if ENCODING_KEY.set(encoding_key).is_err() {
             return Err("Cannot set encoding key".into());
         }
-        if DECODING_KEY.set(decoding_key).is_err() {
-            return Err("Cannot set decoding key".into());
+        // tokens signed with a former key stay valid, see ED25519_PREVIOUS_PUBLIC_KEYS.
+        let mut decoding_keys = vec![decoding_key];
+        for previous in conf
+            .ed25519_previous_public_keys
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+        {
+            let decoded = general_purpose::STANDARD
+                .decode(previous)
+                .map_err(|err| format!("ED25519_PREVIOUS_PUBLIC_KEYS: {err}"))?;
+            decoding_keys.push(DecodingKey::from_ed_der(&decoded));
+        }
+        info!(
+            "Accepting tokens signed with {} previous keys",
+            decoding_keys.len() - 1
+        );
+        if DECODING_KEYS.set(decoding_keys).is_err() {
+            return Err("Cannot set decoding keys".into());
+        }
+        Ok(())
+    }
+
+    pub fn init_totp_key() -> Result<(), Box<dyn Error>> {
+        let conf = Config::get();
+        let key = match conf.totp_encryption_key.as_str() {
+            "" => {
+                info!("TOTP_ENCRYPTION_KEY is not set, 2FA enrollment is disabled.");
+                None
+            }
+            encoded => {
+                let decoded = general_purpose::STANDARD.decode(encoded)?;
+                let key = UnboundKey::new(&AES_256_GCM, &decoded)
+                    .map_err(|_| "TOTP_ENCRYPTION_KEY must be 32 bytes long")?;
+                Some(LessSafeKey::new(key))
+            }
+        };
+        if TOTP_KEY.set(key).is_err() {
+            return Err("Cannot set TOTP key".into());
         }
         Ok(())
     }
 
+    /// Remember when the server started, see [`Self::get_uptime`].
+    pub fn init_started_at() {
+        STARTED_AT.get_or_init(Instant::now);
+    }
+
     pub fn init_limit_service() -> Result<(), Box<dyn Error>> {
         let conf = Config::get();
         let service = LimitController::new(conf.db_max_streams_per_user);
         if LIMIT_SERVICE.set(service).is_err() {
             return Err("Cannot set limit service".into());
         }
+        let sse_service = LimitController::new(conf.max_sse_connections_per_user);
+        if SSE_LIMIT_SERVICE.set(sse_service).is_err() {
+            return Err("Cannot set SSE limit service".into());
+        }
+        let login_limiter = AttemptLimiter::new(
+            conf.login_max_failed_attempts as usize,
+            Duration::from_secs(conf.login_attempt_window_seconds),
+            conf.login_max_tracked_clients as usize,
+        );
+        if LOGIN_LIMITER.set(login_limiter).is_err() {
+            return Err("Cannot set login limiter".into());
+        }
+        let trusted_proxies = conf
+            .trusted_proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<TrustedProxy>, _>>()
+            .map_err(|err| format!("TRUSTED_PROXIES: {err}"))?;
+        if TRUSTED_PROXIES.set(trusted_proxies).is_err() {
+            return Err("Cannot set trusted proxies".into());
+        }
         Ok(())
     }
 
+    /// Whether [`Self::init_jwt_keys`] was called successfully.
+    pub fn has_jwt_keys() -> bool {
+        ENCODING_KEY.get().is_some() && DECODING_KEYS.get().is_some()
+    }
+
     pub fn get_encoding_key() -> &'static EncodingKey {
         ENCODING_KEY.get().expect("encoding key not initialized")
     }
 
-    pub fn get_decoding_key() -> &'static DecodingKey {
-        DECODING_KEY.get().expect("decoding key not initialized")
+    /// The public part of the signing key, followed by the previous ones.
+    pub fn get_decoding_keys() -> &'static [DecodingKey] {
+        DECODING_KEYS.get().expect("decoding keys not initialized")
+    }
+
+    /// The key used to encrypt TOTP secrets, if 2FA is enabled.
+    pub fn get_totp_key() -> Option<&'static LessSafeKey> {
+        TOTP_KEY.get().expect("TOTP key not initialized").as_ref()
+    }
+
+    pub fn get_uptime() -> Duration {
+        STARTED_AT
+            .get()
+            .expect("start time not initialized")
+            .elapsed()
     }
 
     pub fn get_limit_service() -> &'static LimitController {
         LIMIT_SERVICE.get().expect("limit service not initialized")
     }
+
+    /// Failed logins per client address.
+    pub fn get_login_limiter() -> &'static AttemptLimiter {
+        LOGIN_LIMITER.get().expect("login limiter not initialized")
+    }
+
+    pub fn get_trusted_proxies() -> &'static [TrustedProxy] {
+        TRUSTED_PROXIES
+            .get()
+            .expect("trusted proxies not initialized")
+    }
+
+    pub fn get_sse_limit_service() -> &'static LimitController {
+        SSE_LIMIT_SERVICE
+            .get()
+            .expect("SSE limit service not initialized")
+    }
 }
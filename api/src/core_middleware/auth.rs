@@ -48,9 +48,9 @@ create_middleware!(
             let token = Token::from(token);
 
             // handle token validation
-            let user = match token.validate().await {
+            let (user, scopes, key) = match token.validate().await {
                 Err(err) => return Ok(req.error_response(err).into()),
-                Ok(user) => user,
+                Ok(validated) => validated,
             };
 
             // add authenticated user to logging span
@@ -65,10 +65,12 @@ create_middleware!(
                 span.record("superuser", user.is_superuser);
             }
             info!(
-                "Authenticated token for user id: {}, username: {:?}.",
-                user.id, user.username
+                "Authenticated token for user id: {}, username: {:?}, api key: {:?}.",
+                user.id, user.username, key.0
             );
             req.extensions_mut().insert(user);
+            req.extensions_mut().insert(scopes);
+            req.extensions_mut().insert(key);
             svc.call(req).await
         })
     }
@@ -0,0 +1,98 @@
+use std::{
+    future::{ready, Ready},
+    pin::Pin,
+};
+
+use actix_http::{
+    body::{BodySize, MessageBody},
+    header::{self, HeaderValue},
+};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    mime, Error, HttpResponse,
+};
+use futures::Future;
+
+// This middleware decides which responses actix's `Compress` may touch.
+// It has to sit inside `Compress`: anything that isn't a JSON body of at
+// least `min_size` bytes gets `Content-Encoding: identity`, which `Compress`
+// leaves alone. This keeps tiny responses and the CSV/SSE streams as they are.
+pub struct CompressionFilter {
+    min_size: u64,
+}
+
+impl CompressionFilter {
+    pub fn new(min_size: u64) -> Self {
+        Self { min_size }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CompressionFilter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CompressionFilterInner<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionFilterInner {
+            service,
+            min_size: self.min_size,
+        }))
+    }
+}
+
+pub struct CompressionFilterInner<S> {
+    service: S,
+    min_size: u64,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionFilterInner<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let min_size = self.min_size;
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if !should_compress(res.response(), min_size) {
+                res.headers_mut().insert(
+                    header::CONTENT_ENCODING,
+                    HeaderValue::from_static("identity"),
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn should_compress<B: MessageBody>(res: &HttpResponse<B>, min_size: u64) -> bool {
+    if res.headers().contains_key(header::CONTENT_ENCODING) {
+        return false;
+    }
+    // application/json, application/problem+json, ...
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| mime.subtype() == mime::JSON || mime.suffix() == Some(mime::JSON));
+    match res.body().size() {
+        BodySize::Sized(size) => is_json && size >= min_size,
+        _ => false,
+    }
+}
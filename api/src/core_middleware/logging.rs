@@ -1,10 +1,14 @@
 use actix_http::header::{HeaderName, HeaderValue};
+use central_repository_dao::QueryStats;
 use lazy_static::lazy_static;
-use log::{error, info};
+use log::{info, warn};
 use tracing::{field, info_span};
 use uuid::Uuid;
 
-use crate::common::{create_middleware, handle_fatal};
+use crate::{
+    common::{create_middleware, handle_fatal},
+    API_V1_PREFIX,
+};
 
 lazy_static! {
     static ref HEADER_NAME: HeaderName = HeaderName::try_from("Request-Id").unwrap();
@@ -12,6 +16,32 @@ lazy_static! {
     static ref INVALID_HEADER_VAL: HeaderValue = HeaderValue::try_from("n/a").unwrap();
 }
 
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// `v1` for requests to `/api/v1/...`, `legacy` for the unprefixed routes.
+fn api_version(path: &str) -> &'static str {
+    match path.strip_prefix(API_V1_PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => "v1",
+        _ => "legacy",
+    }
+}
+
+/// The id of the request being processed. Only available inside [`LogMiddleware`].
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Run `fut` with `request_id` as the [`current_request_id`], for work that
+/// carries on after its request was answered.
+pub(crate) async fn with_request_id<F: std::future::Future>(
+    request_id: String,
+    fut: F,
+) -> F::Output {
+    REQUEST_ID.scope(request_id, fut).await
+}
+
 create_middleware!(
     LogMiddleware,
     LogMiddlewareInner,
@@ -23,14 +53,18 @@ create_middleware!(
         Box::pin(async move {
             let uuid = Uuid::new_v4().to_string();
             let method = req.method().to_string();
-            let span = info_span!("central_repository", id=%uuid, path=%req.path(), query=%req.query_string(), method=%method, user=field::Empty, user_id=field::Empty, superuser=field::Empty).entered();
+            let span = info_span!("central_repository", id=%uuid, path=%req.path(), api_version=api_version(req.path()), query=%req.query_string(), method=%method, user=field::Empty, user_id=field::Empty, superuser=field::Empty, db_queries=field::Empty, db_time_ms=field::Empty);
             // Insert span into request. This span will live until the request
             // extensions get dropped.
-            req.extensions_mut().insert(span);
+            req.extensions_mut().insert(span.clone().entered());
             let start = Instant::now();
-            let mut res = svc.call(req).await.map_err(|err| {
-                // this should never happen.
-                error!(
+            let (res, query_stats) =
+                QueryStats::track(REQUEST_ID.scope(uuid.clone(), svc.call(req))).await;
+            span.record("db_queries", query_stats.queries());
+            span.record("db_time_ms", query_stats.elapsed().as_millis() as u64);
+            let mut res = res.map_err(|err| {
+                // only RequestTimeout returns errors instead of responses.
+                warn!(
                     "middleware error: {:?}, status={}",
                     err,
                     err.as_response_error().status_code()
@@ -1,2 +1,5 @@
 pub mod auth;
+pub mod compression;
 pub mod logging;
+pub mod scope;
+pub mod timeout;
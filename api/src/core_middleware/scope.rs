@@ -0,0 +1,41 @@
+use log::{error, info};
+
+use crate::{
+    auth::jwt::{TokenScope, TokenScopes},
+    common::create_middleware,
+    error::APIError,
+};
+
+// This middleware rejects requests whose token lacks the "write" scope. It
+// guards the handlers that create, update or delete something: wrap them with
+// it, inside AuthMiddleware (which adds the token's scopes to the request).
+create_middleware!(
+    RequireWriteScope,
+    RequireWriteScopeInner,
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let allowed = req
+            .extensions()
+            .get::<TokenScopes>()
+            .map(|scopes| scopes.allows(TokenScope::Write));
+        Box::pin(async move {
+            match allowed {
+                Some(true) => svc.call(req).await,
+                Some(false) => {
+                    info!(
+                        "Denied {} {}: token lacks the write scope",
+                        req.method(),
+                        req.path()
+                    );
+                    Ok(req
+                        .error_response(APIError::MissingScope(TokenScope::Write))
+                        .into())
+                }
+                _ => {
+                    error!("RequireWriteScope must run after AuthMiddleware!");
+                    Ok(req.error_response(APIError::ServerError).into())
+                }
+            }
+        })
+    }
+);
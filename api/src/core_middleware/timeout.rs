@@ -0,0 +1,188 @@
+use std::{
+    future::{ready, Ready},
+    pin::Pin,
+    time::Duration,
+};
+
+use actix_web::{
+    body::BoxBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures::Future;
+use log::warn;
+
+use crate::{core_middleware::logging::current_request_id, error::APIError};
+
+// This middleware cancels handlers that take longer than their timeout and
+// answers with a 503 instead. It must sit inside `LogMiddleware`, which
+// provides the request id.
+//
+// Only the handler itself is timed: streamed bodies (CSV exports, SSE) are
+// sent after the handler returns, so for those this is a first-byte timeout.
+pub struct RequestTimeout {
+    default: Option<Duration>,
+    overrides: Vec<(&'static str, Option<Duration>)>,
+}
+
+impl RequestTimeout {
+    /// Time out requests after `seconds` (0 disables the timeout).
+    pub fn new(seconds: u64) -> Self {
+        Self {
+            default: to_duration(seconds),
+            overrides: vec![],
+        }
+    }
+
+    /// Use a different timeout for every path under `prefix`. Overrides are
+    /// checked in order.
+    pub fn with_override(mut self, prefix: &'static str, seconds: u64) -> Self {
+        self.overrides.push((prefix, to_duration(seconds)));
+        self
+    }
+}
+
+fn to_duration(seconds: u64) -> Option<Duration> {
+    match seconds {
+        0 => None,
+        seconds => Some(Duration::from_secs(seconds)),
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for RequestTimeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestTimeoutInner<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTimeoutInner {
+            service,
+            default: self.default,
+            overrides: self.overrides.clone(),
+        }))
+    }
+}
+
+pub struct RequestTimeoutInner<S> {
+    service: S,
+    default: Option<Duration>,
+    overrides: Vec<(&'static str, Option<Duration>)>,
+}
+
+impl<S> RequestTimeoutInner<S> {
+    fn timeout_for(&self, path: &str) -> Option<Duration> {
+        self.overrides
+            .iter()
+            .find(|(prefix, _)| {
+                path.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map_or(self.default, |(_, timeout)| *timeout)
+    }
+}
+
+impl<S> Service<ServiceRequest> for RequestTimeoutInner<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(timeout) = self.timeout_for(req.path()) else {
+            return Box::pin(self.service.call(req));
+        };
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            tokio::time::timeout(timeout, fut)
+                .await
+                .unwrap_or_else(|_| {
+                    warn!("request timed out after {timeout:?}, cancelling it");
+
+                    // The request can't be cloned before it's routed, so there's no
+                    // ServiceResponse to build: the error is turned into a response
+                    // by actix instead.
+                    Err(APIError::RequestTimeout(
+                        timeout.as_secs(),
+                        current_request_id().unwrap_or_default(),
+                    )
+                    .into())
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test::{init_service, try_call_service, TestRequest},
+        web, App, HttpResponse,
+    };
+
+    use super::*;
+
+    async fn sleep_for(seconds: web::Path<u64>) -> HttpResponse {
+        tokio::time::sleep(Duration::from_secs(*seconds)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn slow_handlers_time_out() {
+        let app = init_service(
+            App::new()
+                .wrap(
+                    RequestTimeout::new(1)
+                        .with_override("/slow", 5)
+                        .with_override("/unlimited", 0),
+                )
+                .route("/sleep/{seconds}", web::get().to(sleep_for))
+                .route("/slow/{seconds}", web::get().to(sleep_for))
+                .route("/unlimited/{seconds}", web::get().to(sleep_for)),
+        )
+        .await;
+        let call = |path: &str| {
+            let request = TestRequest::get().uri(path).to_request();
+            try_call_service(&app, request)
+        };
+
+        assert_eq!(call("/sleep/0").await.unwrap().status(), StatusCode::OK);
+        let (cancelled, slow, unlimited) =
+            futures::join!(call("/sleep/2"), call("/slow/2"), call("/unlimited/2"));
+        let err = cancelled.expect_err("the handler wasn't cancelled");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(slow.unwrap().status(), StatusCode::OK);
+        assert_eq!(unlimited.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn overrides_match_whole_segments() {
+        let timeout = RequestTimeout::new(30)
+            .with_override("/api/v1/record", 300)
+            .with_override("/api/v1", 0);
+        let inner = RequestTimeoutInner {
+            service: (),
+            default: timeout.default,
+            overrides: timeout.overrides,
+        };
+        let seconds = |path| inner.timeout_for(path).map(|timeout| timeout.as_secs());
+        assert_eq!(seconds("/api/v1/record"), Some(300));
+        assert_eq!(seconds("/api/v1/record/filter-stream"), Some(300));
+        // overrides are checked in order
+        assert_eq!(seconds("/api/v1/recordings"), None);
+        assert_eq!(seconds("/api/v2/record"), Some(30));
+    }
+}
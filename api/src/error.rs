@@ -1,33 +1,179 @@
 use actix_web::{
-    error::{self, BlockingError},
-    http::StatusCode,
+    error::{self, BlockingError, JsonPayloadError},
+    http::{header, StatusCode},
     web::{self, JsonConfig, PathConfig, QueryConfig},
-    HttpResponse,
+    HttpResponse, HttpResponseBuilder,
 };
 use central_repository_config::inner::Config;
 use central_repository_dao::CoreError;
-use entity::error::DatabaseQueryError;
-use log::{error, info};
+use entity::error::{ArgumentError, DatabaseQueryError};
+use log::info;
 use sea_orm::{DbErr, RuntimeErr};
 use serde::Serialize;
+use serde_json::{Map, Value};
 use sqlx::Error as SQLXError;
 use strum::AsRefStr;
 
 use thiserror::Error;
+use utoipa::ToSchema;
 
-use crate::common::handle_fatal;
+use crate::{
+    auth::jwt::TokenScope,
+    common::handle_fatal,
+    core_middleware::logging::current_request_id,
+    util::{RATE_LIMIT_LIMIT_HEADER, RATE_LIMIT_REMAINING_HEADER},
+};
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// There's no telling when a stream will end, this is just a hint.
+const STREAM_RETRY_AFTER_SECONDS: u64 = 10;
+// Same for the uploads in the ingestion queue.
+const INGESTION_RETRY_AFTER_SECONDS: u64 = 5;
 
 pub type APIResult<T> = Result<T, APIError>;
 
 pub type APIResponse = APIResult<HttpResponse>;
 
-#[derive(Debug, Serialize, Default)]
+/// The body of every error response.
+///
+/// `code` is always present. With PROBLEM_DETAILS_ERRORS enabled, the body follows
+/// RFC 7807 (`type`, `title`, `status`, `detail`, `instance`). `statusCode` and `kind` are
+/// deprecated and only sent while LEGACY_ERROR_FIELDS is enabled.
+#[derive(Debug, Serialize, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OutboundAPIError {
-    pub status_code: u16,
-    pub kind: String,
+    /// Stable error code, e.g. `REPO-1001`.
+    #[schema(example = "REPO-1001")]
+    pub code: &'static str,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub problem_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub detail: Option<String>,
+    /// The request id (`urn:uuid:<Request-Id>`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Every invalid search argument, if there's more than one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<ArgumentError>>,
+    /// Machine-readable details of some errors, e.g. `limit`, `inUse` and
+    /// `retryAfter` (seconds) for rate limits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub meta: Option<Map<String, Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(deprecated)]
+    pub status_code: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(deprecated)]
+    pub kind: Option<String>,
+}
+
+macro_rules! error_codes {
+    ($($variant:ident => $code:literal, $slug:literal, $title:literal;)+) => {
+        /// Machine-readable error codes. Unlike the variant names and messages of
+        /// [`APIError`], these never change once released.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum ErrorCode {
+            $($variant),+
+        }
+
+        impl ErrorCode {
+            pub const ALL: &'static [ErrorCode] = &[$(Self::$variant),+];
+
+            pub const fn code(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $code),+
+                }
+            }
+
+            pub const fn slug(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $slug),+
+                }
+            }
+
+            pub const fn title(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $title),+
+                }
+            }
+        }
+    };
+}
+
+// 1xxx: invalid requests, 2xxx: authentication/authorization, 3xxx: limits,
+// 5xxx: server errors. Never reuse or renumber a code.
+error_codes! {
+    Duplicate => "REPO-1001", "duplicate", "Duplicate item";
+    BadRequest => "REPO-1002", "bad-request", "Bad request";
+    ValidationFailure => "REPO-1003", "validation-failure", "Validation failure";
+    NotFound => "REPO-1004", "not-found", "Not found";
+    InvalidOperation => "REPO-1005", "invalid-operation", "Invalid operation";
+    ConflictingOperation => "REPO-1006", "conflicting-operation", "Conflicting operation";
+    CastError => "REPO-1007", "cast-error", "Invalid data type";
+    InvalidQuery => "REPO-1008", "invalid-query", "Invalid query";
+    InvalidPagination => "REPO-1009", "invalid-pagination", "Invalid pagination parameters";
+    InvalidCredentials => "REPO-2001", "invalid-credentials", "Invalid credentials";
+    InvalidToken => "REPO-2002", "invalid-token", "Invalid or expired token";
+    InsufficientPermissions => "REPO-2003", "insufficient-permissions", "Insufficient permissions";
+    AdminOnly => "REPO-2004", "admin-only", "Admin-only resource";
+    MissingAuthHeader => "REPO-2005", "missing-auth-header", "Missing authentication header";
+    InactiveUser => "REPO-2006", "inactive-user", "Inactive user";
+    InactiveKey => "REPO-2007", "inactive-key", "Inactive API key";
+    TotpRequired => "REPO-2008", "totp-required", "Two-factor code required";
+    InvalidTotpCode => "REPO-2009", "invalid-totp-code", "Invalid two-factor code";
+    MissingScope => "REPO-2010", "missing-scope", "Missing token scope";
+    StaleKey => "REPO-2011", "stale-key", "Stale API key";
+    RateLimit => "REPO-3001", "rate-limit", "Rate limit exceeded";
+    QuotaExceeded => "REPO-3002", "quota-exceeded", "Quota exceeded";
+    QueryTooLarge => "REPO-3003", "query-too-large", "Query too large";
+    UploadTooLarge => "REPO-3004", "upload-too-large", "Upload too large";
+    ServerError => "REPO-5001", "server-error", "Server error";
+    ThreadingError => "REPO-5002", "threading-error", "Server error";
+    RequestTimeout => "REPO-5003", "request-timeout", "Request timed out";
+    QueryTimeout => "REPO-5004", "query-timeout", "Query timed out";
+}
+
+// Fail to compile if two variants share a code or a slug.
+const _: () = {
+    const fn same(a: &str, b: &str) -> bool {
+        let (a, b) = (a.as_bytes(), b.as_bytes());
+        if a.len() != b.len() {
+            return false;
+        }
+        let mut i = 0;
+        while i < a.len() {
+            if a[i] != b[i] {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    let all = ErrorCode::ALL;
+    let mut i = 0;
+    while i < all.len() {
+        let mut j = i + 1;
+        while j < all.len() {
+            assert!(!same(all[i].code(), all[j].code()), "duplicate error code");
+            assert!(!same(all[i].slug(), all[j].slug()), "duplicate error slug");
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+impl ErrorCode {
+    /// The RFC 7807 `type` of this error.
+    pub fn problem_type(&self) -> String {
+        format!("urn:central-repository:error:{}", self.slug())
+    }
 }
 
 #[derive(Error, Debug, Serialize, Clone, Copy)]
@@ -42,6 +188,14 @@ pub enum ValidationFailureKind {
     InvalidComparisonKind,
     #[error("Regex match failure: data doesn't match regex")]
     RegexMatchFailure,
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("One or more fields contain objects or arrays, only flat values are supported")]
+    NestedValue,
+    #[error("One or more strings are longer than {0} characters")]
+    StringTooLong(u64),
+    #[error("One or more entries are larger than {0} bytes")]
+    RecordTooLarge(u64),
 }
 
 #[derive(Error, Debug, AsRefStr)]
@@ -52,6 +206,12 @@ pub enum APIError {
     BadRequest,
     #[error("Validation error: {0}.")]
     ValidationFailure(ValidationFailureKind),
+    #[error("Validation error: record {record} breaks rule {rule} ({description}).")]
+    RuleViolation {
+        record: u64,
+        rule: u64,
+        description: String,
+    },
     #[error("Server error.")]
     ServerError,
     #[error("Couldn't find {0}.")]
@@ -60,16 +220,26 @@ pub enum APIError {
     InactiveUser,
     #[error("Cannot authenticate: key is inactive")]
     InactiveKey,
+    #[error(
+        "Cannot authenticate: key wasn't rotated in the last {0} days, rotate it to keep using it"
+    )]
+    StaleKey(u32),
     #[error("Invalid credentials.")]
     InvalidCredentials,
     #[error("Invalid or expired token.")]
     InvalidToken,
+    #[error("Two-factor authentication is enabled for this user: `totpCode` is required.")]
+    TotpRequired,
+    #[error("Invalid or already used two-factor authentication code.")]
+    InvalidTotpCode,
     #[error("Missing 'Authentication' header.")]
     MissingAuthHeader,
     #[error("Insufficient permissions: only admins may use this resource.")]
     AdminOnlyResource,
     #[error("Insufficient permissions: you need one or more roles to access this resource.")]
     InsufficientPermissions,
+    #[error("Insufficient permissions: this token lacks the '{}' scope.", .0.as_ref())]
+    MissingScope(TokenScope),
     #[error("Invalid operation: {0}.")]
     InvalidOperation(String),
     #[error("Conflicting operation: {0}.")]
@@ -78,36 +248,150 @@ pub enum APIError {
     CastError(String, String),
     #[error("Query error: {0}")]
     InvalidQuery(String),
-    #[error("Invalid pagination size: {0}")]
+    #[error("Query error: {} search arguments are invalid (see `errors`)", .0.len())]
+    InvalidQueryArguments(Vec<ArgumentError>),
+    #[error("Invalid pagination parameters: {0}.")]
     InvalidPaginationParameters(String),
+    #[error("Query too large: {0}.")]
+    QueryTooLarge(String),
+    #[error("The query took longer than {0} seconds and was cancelled.")]
+    QueryTimeout(u64),
+    #[error("Upload too large: the request body is larger than {0} bytes.")]
+    UploadTooLarge(u64),
     #[error("Fatal threading error")]
     BlockingError(#[from] BlockingError),
-    #[error("Rate limit: {0}")]
-    RateLimit(String),
+    #[error("The request took longer than {0} seconds and was cancelled (request id: {1}).")]
+    RequestTimeout(u64, String),
+    #[error(
+        "Rate limit: {in_use} of {limit} concurrent streams in use, retry in {retry_after} seconds."
+    )]
+    RateLimit {
+        limit: u64,
+        in_use: u64,
+        retry_after: u64,
+    },
+    #[error("Rate limit: too many failed login attempts, retry in {0} seconds.")]
+    TooManyLoginAttempts(u64),
+    #[error(
+        "Rate limit: the ingestion queue is full (max. {0} uploads), retry in {} seconds.",
+        INGESTION_RETRY_AFTER_SECONDS
+    )]
+    IngestionQueueFull(u64),
+    #[error("Quota exceeded: {0}.")]
+    QuotaExceeded(String),
 }
 
+// Neither `error_code` nor `status_code` have a catch-all arm, so new
+// variants don't compile until they're given both.
 impl APIError {
+    pub fn error_code(&self) -> ErrorCode {
+        match self {
+            Self::DuplicateError => ErrorCode::Duplicate,
+            Self::BadRequest => ErrorCode::BadRequest,
+            Self::ValidationFailure(_) | Self::RuleViolation { .. } => ErrorCode::ValidationFailure,
+            Self::ServerError => ErrorCode::ServerError,
+            Self::NotFound(_) => ErrorCode::NotFound,
+            Self::InactiveUser => ErrorCode::InactiveUser,
+            Self::InactiveKey => ErrorCode::InactiveKey,
+            Self::StaleKey(_) => ErrorCode::StaleKey,
+            Self::InvalidCredentials => ErrorCode::InvalidCredentials,
+            Self::InvalidToken => ErrorCode::InvalidToken,
+            Self::TotpRequired => ErrorCode::TotpRequired,
+            Self::InvalidTotpCode => ErrorCode::InvalidTotpCode,
+            Self::MissingAuthHeader => ErrorCode::MissingAuthHeader,
+            Self::AdminOnlyResource => ErrorCode::AdminOnly,
+            Self::InsufficientPermissions => ErrorCode::InsufficientPermissions,
+            Self::MissingScope(_) => ErrorCode::MissingScope,
+            Self::InvalidOperation(_) => ErrorCode::InvalidOperation,
+            Self::ConflictingOperation(_) => ErrorCode::ConflictingOperation,
+            Self::CastError(_, _) => ErrorCode::CastError,
+            Self::InvalidQuery(_) | Self::InvalidQueryArguments(_) => ErrorCode::InvalidQuery,
+            Self::InvalidPaginationParameters(_) => ErrorCode::InvalidPagination,
+            Self::QueryTooLarge(_) => ErrorCode::QueryTooLarge,
+            Self::QueryTimeout(_) => ErrorCode::QueryTimeout,
+            Self::UploadTooLarge(_) => ErrorCode::UploadTooLarge,
+            Self::BlockingError(_) => ErrorCode::ThreadingError,
+            Self::RequestTimeout(_, _) => ErrorCode::RequestTimeout,
+            Self::RateLimit { .. }
+            | Self::TooManyLoginAttempts(_)
+            | Self::IngestionQueueFull(_) => ErrorCode::RateLimit,
+            Self::QuotaExceeded(_) => ErrorCode::QuotaExceeded,
+        }
+    }
+
     pub fn status_code(&self) -> StatusCode {
         match self {
-            Self::DuplicateError | Self::BadRequest | Self::ValidationFailure(_) => {
-                StatusCode::BAD_REQUEST
-            }
+            Self::DuplicateError
+            | Self::BadRequest
+            | Self::ValidationFailure(_)
+            | Self::RuleViolation { .. } => StatusCode::BAD_REQUEST,
             Self::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
-            Self::InvalidCredentials | Self::InvalidToken | Self::MissingAuthHeader => {
-                StatusCode::UNAUTHORIZED
-            }
+            Self::InvalidCredentials
+            | Self::InvalidToken
+            | Self::MissingAuthHeader
+            | Self::TotpRequired
+            | Self::InvalidTotpCode => StatusCode::UNAUTHORIZED,
             Self::AdminOnlyResource
             | Self::InsufficientPermissions
+            | Self::MissingScope(_)
             | Self::InactiveUser
-            | Self::InactiveKey => StatusCode::FORBIDDEN,
+            | Self::InactiveKey
+            | Self::StaleKey(_) => StatusCode::FORBIDDEN,
+            Self::ConflictingOperation(_) => StatusCode::CONFLICT,
             Self::InvalidOperation(_)
-            | Self::ConflictingOperation(_)
             | Self::InvalidQuery(_)
+            | Self::InvalidQueryArguments(_)
             | Self::CastError(_, _)
-            | Self::InvalidPaginationParameters(_) => StatusCode::BAD_REQUEST,
+            | Self::InvalidPaginationParameters(_)
+            | Self::QueryTooLarge(_) => StatusCode::BAD_REQUEST,
+            Self::UploadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
             Self::BlockingError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::RateLimit(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::RequestTimeout(_, _) | Self::QueryTimeout(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::RateLimit { .. }
+            | Self::TooManyLoginAttempts(_)
+            | Self::IngestionQueueFull(_)
+            | Self::QuotaExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+
+    /// Structured details sent along with the message, see [`OutboundAPIError::meta`].
+    fn meta(&self) -> Option<Map<String, Value>> {
+        let meta: &[(&str, u64)] = match self {
+            Self::RateLimit {
+                limit,
+                in_use,
+                retry_after,
+            } => &[
+                ("limit", *limit),
+                ("inUse", *in_use),
+                ("retryAfter", *retry_after),
+            ],
+            Self::TooManyLoginAttempts(seconds) => &[("retryAfter", *seconds)],
+            Self::IngestionQueueFull(_) => &[("retryAfter", INGESTION_RETRY_AFTER_SECONDS)],
+            Self::RuleViolation { record, rule, .. } => &[("record", *record), ("rule", *rule)],
+            _ => return None,
+        };
+        Some(
+            meta.iter()
+                .map(|(key, value)| (key.to_string(), Value::from(*value)))
+                .collect(),
+        )
+    }
+
+    /// Same as `From<DatabaseQueryError>`, for queries that ran with a
+    /// `statement_timeout` of `timeout_seconds`: cancelled queries are
+    /// reported as timeouts.
+    pub fn from_timed_query(error: DatabaseQueryError, timeout_seconds: u64) -> APIError {
+        match &error {
+            DatabaseQueryError::DbErr(
+                DbErr::Query(RuntimeErr::SqlxError(SQLXError::Database(err)))
+                | DbErr::Exec(RuntimeErr::SqlxError(SQLXError::Database(err))),
+            ) if err.code().as_deref() == Some(QUERY_CANCELED) => {
+                info!("query cancelled after {timeout_seconds}s");
+                APIError::QueryTimeout(timeout_seconds)
+            }
+            _ => error.into(),
         }
     }
 
@@ -116,7 +400,23 @@ impl APIError {
     fn from_db_err(error: &DbErr) -> APIError {
         info!("try cast: {}", error);
         match error {
-            DbErr::Query(RuntimeErr::SqlxError(SQLXError::Database(err))) => {
+            DbErr::Query(RuntimeErr::SqlxError(SQLXError::Database(err)))
+            | DbErr::Exec(RuntimeErr::SqlxError(SQLXError::Database(err))) => {
+                match err.code().as_deref() {
+                    Some(FOREIGN_KEY_VIOLATION) => {
+                        info!("Caught foreign key violation: {}", err);
+                        return APIError::ConflictingOperation(describe_constraint(
+                            err.constraint(),
+                        ));
+                    }
+                    Some(CHECK_VIOLATION) => {
+                        info!("Caught check constraint violation: {}", err);
+                        return APIError::ConflictingOperation(
+                            "the item violates a data constraint".into(),
+                        );
+                    }
+                    _ => {}
+                }
                 // SQLX::Database errors don't have any enums inside, so there's
                 // no other way to know what the error was. This "duplicate key value" is something
                 // that only works with postgres.
@@ -136,6 +436,74 @@ impl APIError {
     }
 }
 
+// Postgres SQLSTATEs.
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+const CHECK_VIOLATION: &str = "23514";
+const QUERY_CANCELED: &str = "57014";
+
+/// Foreign keys (constraint, child, parent) and the relationship they enforce,
+/// used to build messages that don't leak any SQL details. Every foreign key in
+/// the schema must be listed here.
+pub const FOREIGN_KEYS: &[(&str, &str, &str)] = &[
+    ("api_key_user_id_fkey", "API keys", "users"),
+    ("format_created_by_fkey", "formats", "users"),
+    (
+        "format_entitlement_created_by_fkey",
+        "entitlements",
+        "users",
+    ),
+    (
+        "format_entitlement_format_id_fkey",
+        "entitlements",
+        "formats",
+    ),
+    ("format_entitlement_user_id_fkey", "entitlements", "users"),
+    ("record_format_id_fkey", "records", "formats"),
+    (
+        "record_upload_session_id_fkey",
+        "records",
+        "upload sessions",
+    ),
+    (
+        "saved_search_share_saved_search_id_fkey",
+        "saved search shares",
+        "saved searches",
+    ),
+    (
+        "saved_search_share_user_id_fkey",
+        "saved search shares",
+        "users",
+    ),
+    ("saved_search_user_id_fkey", "saved searches", "users"),
+    (
+        "upload_session_api_key_id_fkey",
+        "upload sessions",
+        "API keys",
+    ),
+    (
+        "upload_session_format_id_fkey",
+        "upload sessions",
+        "formats",
+    ),
+    ("upload_session_user_id_fkey", "upload sessions", "users"),
+    (
+        "webhook_delivery_webhook_id_fkey",
+        "webhook deliveries",
+        "webhooks",
+    ),
+    ("webhook_format_id_fkey", "webhooks", "formats"),
+];
+
+fn describe_constraint(constraint: Option<&str>) -> String {
+    FOREIGN_KEYS
+        .iter()
+        .find(|(name, _, _)| Some(*name) == constraint)
+        .map(|(_, child, parent)| {
+            format!("the relationship between {child} and {parent} would be broken")
+        })
+        .unwrap_or_else(|| "a related item doesn't exist or is still in use".into())
+}
+
 impl From<DbErr> for APIError {
     #[inline(always)]
     fn from(error: DbErr) -> APIError {
@@ -157,7 +525,15 @@ impl From<&DatabaseQueryError> for APIError {
             // this is just a regular seaorm DbErr.
             DatabaseQueryError::DbErr(err) => APIError::from_db_err(err),
             DatabaseQueryError::InsufficientPermissions => APIError::InsufficientPermissions,
-            _ => APIError::InvalidQuery(value.to_string()),
+            DatabaseQueryError::QuotaExceeded(msg) => APIError::QuotaExceeded(msg.clone()),
+            DatabaseQueryError::Multiple(errors) => APIError::InvalidQueryArguments(errors.clone()),
+            DatabaseQueryError::QueryTooLarge(msg) => APIError::QueryTooLarge(msg.clone()),
+            DatabaseQueryError::InvalidColumnRequested(_)
+            | DatabaseQueryError::InvalidUsage(_)
+            | DatabaseQueryError::CastError
+            | DatabaseQueryError::ColumnWithMixedTypesError { .. }
+            | DatabaseQueryError::EmptyQuery
+            | DatabaseQueryError::InvalidRegex => APIError::InvalidQuery(value.to_string()),
         }
     }
 }
@@ -166,7 +542,11 @@ impl From<CoreError> for APIError {
     #[inline(always)]
     fn from(value: CoreError) -> Self {
         match value {
-            CoreError::GrantError(msg) => APIError::RateLimit(msg),
+            CoreError::GrantError { in_use, limit, .. } => APIError::RateLimit {
+                limit,
+                in_use,
+                retry_after: STREAM_RETRY_AFTER_SECONDS,
+            },
             CoreError::PoisonError => APIError::ServerError,
             // CoreError can also have DatabaseQueryError's inside. In this case,
             // we just delegate the conversion.
@@ -183,16 +563,72 @@ impl error::ResponseError for APIError {
 
     #[inline(always)]
     fn error_response(&self) -> HttpResponse {
-        let out = OutboundAPIError {
-            status_code: u16::from(self.status_code()),
+        self.response_builder().json(self.outbound())
+    }
+}
+
+impl APIError {
+    /// The body of the error response.
+    pub fn outbound(&self) -> OutboundAPIError {
+        let config = Config::get();
+        let code = self.error_code();
+        let status = u16::from(self.status_code());
+        let mut out = OutboundAPIError {
+            code: code.code(),
             detail: Some(self.to_string()),
-            kind: self.as_ref().into(),
+            ..Default::default()
         };
+        if let Self::InvalidQueryArguments(errors) = self {
+            out.errors = Some(errors.clone());
+        }
+        out.meta = self.meta();
+        if config.legacy_error_fields {
+            out.status_code = Some(status);
+            out.kind = Some(self.as_ref().into());
+        }
+        if config.problem_details_errors {
+            out.problem_type = Some(code.problem_type());
+            out.title = Some(code.title());
+            out.status = Some(status);
+            out.instance = current_request_id().map(|id| format!("urn:uuid:{id}"));
+        }
+        out
+    }
+
+    /// The status, content type and headers of the error response, for
+    /// handlers that send a body of their own (see [`Self::outbound`]).
+    pub fn response_builder(&self) -> HttpResponseBuilder {
         let mut response = HttpResponse::build(self.status_code());
+        if Config::get().problem_details_errors {
+            response.content_type(PROBLEM_JSON);
+        }
         if self.status_code() == StatusCode::UNAUTHORIZED {
             response.insert_header(("WWW-Authenticate", "Bearer"));
         }
-        response.json(out)
+        // timeouts are turned into responses outside LogMiddleware, which usually sets it.
+        if let Self::RequestTimeout(_, request_id) = self {
+            response.insert_header(("Request-Id", request_id.as_str()));
+        }
+        match self {
+            Self::TooManyLoginAttempts(seconds) => {
+                response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+            }
+            Self::IngestionQueueFull(_) => {
+                response.insert_header((
+                    header::RETRY_AFTER,
+                    INGESTION_RETRY_AFTER_SECONDS.to_string(),
+                ));
+            }
+            Self::RateLimit {
+                limit, retry_after, ..
+            } => {
+                response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+                response.insert_header((RATE_LIMIT_LIMIT_HEADER, limit.to_string()));
+                response.insert_header((RATE_LIMIT_REMAINING_HEADER, "0"));
+            }
+            _ => {}
+        }
+        response
     }
 }
 
@@ -203,13 +639,19 @@ impl From<APIError> for APIResponse {
     }
 }
 
-pub fn json_error_handler() -> JsonConfig {
+pub fn json_error_handler(limit: u64) -> JsonConfig {
     web::JsonConfig::default()
         // limit request payload size
-        .limit(Config::get().max_json_payload_size as usize)
+        .limit(limit as usize)
         .error_handler(|err, _| {
             info!("JSON deserialization error: {:?}", err);
-            APIError::BadRequest.into()
+            match err {
+                JsonPayloadError::Overflow { limit }
+                | JsonPayloadError::OverflowKnownLength { limit, .. } => {
+                    APIError::UploadTooLarge(limit as u64).into()
+                }
+                _ => APIError::BadRequest.into(),
+            }
         })
 }
 
@@ -240,3 +682,103 @@ impl AsAPIResult for HttpResponse {
         Ok(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, error::Error as StdError, fmt};
+
+    use sqlx::error::{DatabaseError, ErrorKind};
+
+    use super::*;
+
+    /// A postgres error with the given SQLSTATE and constraint.
+    #[derive(Debug)]
+    struct PgError {
+        code: &'static str,
+        constraint: &'static str,
+    }
+
+    impl fmt::Display for PgError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "insert or update violates constraint \"{}\"",
+                self.constraint
+            )
+        }
+    }
+
+    impl StdError for PgError {}
+
+    impl DatabaseError for PgError {
+        fn message(&self) -> &str {
+            "insert or update violates constraint"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(self.code.into())
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            Some(self.constraint)
+        }
+
+        fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn db_err(code: &'static str, constraint: &'static str) -> DbErr {
+        let err = SQLXError::Database(Box::new(PgError { code, constraint }));
+        DbErr::Exec(RuntimeErr::SqlxError(err))
+    }
+
+    /// The error is a 409 that mentions neither the constraint nor the SQL.
+    fn assert_conflict(error: APIError, constraint: &str) -> String {
+        assert_eq!(error.status_code(), StatusCode::CONFLICT, "{error}");
+        let message = error.to_string();
+        assert!(!message.contains(constraint), "{message}");
+        assert!(!message.contains("violates constraint"), "{message}");
+        message
+    }
+
+    #[test]
+    fn foreign_key_violations_are_described() {
+        for (constraint, child, parent) in FOREIGN_KEYS {
+            let error = APIError::from(db_err(FOREIGN_KEY_VIOLATION, constraint));
+            let message = assert_conflict(error, constraint);
+            assert_eq!(
+                message,
+                format!(
+                    "Conflicting operation: the relationship between {child} and {parent} \
+                    would be broken."
+                )
+            );
+        }
+        let error = APIError::from(db_err(FOREIGN_KEY_VIOLATION, "unknown_fkey"));
+        let message = assert_conflict(error, "unknown_fkey");
+        assert!(
+            message.contains("doesn't exist or is still in use"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn check_violations_are_conflicts() {
+        let error = APIError::from(db_err(CHECK_VIOLATION, "format_max_records_check"));
+        let message = assert_conflict(error, "format_max_records_check");
+        assert!(message.contains("violates a data constraint"), "{message}");
+    }
+}
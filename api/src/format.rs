@@ -1,37 +1,164 @@
 use actix_web::{
-    delete, get, post, web,
+    delete, get, patch, post, route, web,
     web::{Json, Path, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use central_repository_dao::{
-    format::ModelAsQuery, sea_orm::TryIntoModel, user::Model as User, FormatMutation, FormatQuery,
-    GetAllPaginated, PaginationOptions,
+    conf::DBConfig,
+    format::ModelAsQuery,
+    format_entitlement::AccessLevel,
+    sea_orm::{ColumnTrait, EntityTrait, QueryFilter, TryIntoModel},
+    user::{Model as User, Role},
+    FormatEntitlementQuery, FormatMutation, FormatQuery, GetAllPaginated, PaginationOptions,
+    UploadSessionMutation,
 };
 
-use entity::format::Model as FormatModel;
+use central_repository_config::inner::Config;
+use chrono::{DateTime, Utc};
+use entity::format::{self, Model as FormatModel, UpdatableModel};
 use log::info;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
-    core_middleware::auth::AuthMiddleware,
+    core_middleware::{auth::AuthMiddleware, scope::RequireWriteScope},
     error::{APIError, APIResponse, AsAPIResult},
-    pagination::{PaginatedResponse, Validate},
-    util::verify_admin,
+    pagination::{validated_pager, PaginatedResponse},
+    util::verify_role,
 };
 
-#[get("")]
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct ListFormatOptions {
+    /// Also list archived formats (auditors and superusers only).
+    #[serde(default)]
+    include_archived: bool,
+    /// Only list the formats this user has this access level to, e.g. `write`
+    /// for the formats they can upload to.
+    access: Option<AccessLevel>,
+    /// Return every format along with this user's access levels to it
+    /// (`FormatWithAccess`) instead of the bare format.
+    #[serde(default)]
+    include_access: bool,
+}
+
+/// A format and the access levels the user who listed it has to it.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatWithAccess {
+    #[serde(flatten)]
+    format: FormatModel,
+    /// Empty if the user can see the format without an entitlement (e.g. auditors).
+    access: Vec<AccessLevel>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/format",
+    tag = "format",
+    params(PaginationOptions, ModelAsQuery, ListFormatOptions),
+    responses((status = 200, description = "Formats visible to this user (with `access`, only the ones they have that access level to). \
+                                             With `includeAccess=true`, a `FormatWithAccess` for every format", body = Vec<Format>))
+)]
+#[route("", method = "GET", method = "HEAD")]
 async fn get_all_format(
+    req: HttpRequest,
     pager: Query<PaginationOptions>,
     filter: Query<ModelAsQuery>,
+    options: Query<ListFormatOptions>,
     user: ReqData<User>,
 ) -> APIResponse {
-    pager.validate()?;
+    let pager = validated_pager(&req, pager)?;
+    if options.include_archived {
+        verify_role(&user, Role::Auditor)?;
+    }
     let filter = filter.into_inner();
-    let pager = pager.into_inner();
     let user = user.into_inner();
-    let result = FormatQuery::get_all_filtered_for_user(&filter, &pager, user, None).await?;
-    Ok(PaginatedResponse::from(result).into())
+    let mut select = match options.include_archived {
+        true => None,
+        false => Some(format::Entity::find().filter(format::Column::Archived.eq(false))),
+    };
+    let options = options.into_inner();
+    if let Some(access) = options.access {
+        let base = select.unwrap_or_else(format::Entity::find);
+        select = Some(FormatQuery::filter_by_access(&user, base, access));
+    }
+    let (formats, num_pages, num_items) =
+        FormatQuery::get_all_filtered_for_user(&filter, &pager, user.clone(), select).await?;
+    if !options.include_access {
+        return Ok(PaginatedResponse::from((formats, num_pages, num_items))
+            .for_pager(&pager)
+            .with_etag(&req)
+            .into());
+    }
+    let format_ids = formats.iter().map(|format| format.id).collect::<Vec<_>>();
+    let mut access_by_format = FormatEntitlementQuery::access_by_format(&user, &format_ids).await?;
+    let formats = formats
+        .into_iter()
+        .map(|format| FormatWithAccess {
+            access: access_by_format.remove(&format.id).unwrap_or_default(),
+            format,
+        })
+        .collect::<Vec<_>>();
+    Ok(PaginatedResponse::from((formats, num_pages, num_items))
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
+}
+
+/// Formats to look up at once.
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatBatchRequest {
+    /// At most `MAX_SEARCH_FORMATS` ids.
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatBatchResponse {
+    /// The readable formats, in the requested order.
+    #[schema(value_type = Vec<Format>)]
+    formats: Vec<FormatModel>,
+    /// Requested ids that don't exist or aren't readable by this user.
+    missing: Vec<i32>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/format/batch",
+    tag = "format",
+    request_body = FormatBatchRequest,
+    responses((status = 200, description = "The requested formats", body = FormatBatchResponse))
+)]
+#[post("batch")]
+async fn get_format_batch(inbound: Json<FormatBatchRequest>, user: ReqData<User>) -> APIResponse {
+    let max_formats = Config::get().max_search_formats;
+    if inbound.ids.len() as u64 > max_formats {
+        return Err(APIError::QueryTooLarge(format!(
+            "at most {max_formats} formats can be requested at once"
+        )));
+    }
+    let formats = FormatQuery::find_by_ids(&user.into_inner(), &inbound.ids).await?;
+    let mut missing = vec![];
+    for id in inbound.ids.iter() {
+        if !formats.iter().any(|format| format.id == *id) && !missing.contains(id) {
+            missing.push(*id);
+        }
+    }
+    HttpResponse::Ok()
+        .json(FormatBatchResponse { formats, missing })
+        .to_ok()
+}
+
+#[utoipa::path(
+    get,
+    path = "/format/{id}",
+    tag = "format",
+    params(("id" = i32, Path, description = "Format ID")),
+    responses((status = 200, description = "The format", body = Format))
+)]
 #[get("{id}")]
 async fn get_format(id: Option<Path<i32>>, user: ReqData<User>) -> APIResponse {
     let id = *id.ok_or(APIError::BadRequest)?;
@@ -41,37 +168,150 @@ async fn get_format(id: Option<Path<i32>>, user: ReqData<User>) -> APIResponse {
     HttpResponse::Ok().json(format.try_into_model()?).to_ok()
 }
 
-#[delete("{id}")]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ActivityOptions {
+    /// Start of the range, e.g. `2024-04-01T00:00:00Z`. Defaults to
+    /// `MAX_ACTIVITY_RANGE_DAYS` days before `to`.
+    from: Option<DateTime<Utc>>,
+    /// End of the range (exclusive). Defaults to now.
+    to: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/format/{id}/activity",
+    tag = "format",
+    params(("id" = i32, Path, description = "Format ID"), ActivityOptions),
+    responses((status = 200, description = "Records uploaded to the format per (UTC) day, oldest first", body = Vec<FormatActivity>))
+)]
+#[get("{id}/activity")]
+async fn get_format_activity(
+    id: Option<Path<i32>>,
+    options: Query<ActivityOptions>,
+    user: ReqData<User>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let max_days = Config::get().max_activity_range_days;
+    let to = options.to.unwrap_or_else(chrono::offset::Utc::now);
+    let from = options
+        .from
+        .unwrap_or_else(|| to - chrono::Duration::days(max_days as i64));
+    if from >= to {
+        return Err(APIError::InvalidOperation(
+            "`from` must be before `to`".into(),
+        ));
+    }
+    if to - from > chrono::Duration::days(max_days as i64) {
+        return Err(APIError::QueryTooLarge(format!(
+            "at most {max_days} days of activity can be requested at once"
+        )));
+    }
+    let activity = FormatQuery::activity(&user.into_inner(), id, from, to)
+        .await?
+        .ok_or(APIError::NotFound(format!("format with ID {}", id)))?;
+    HttpResponse::Ok().json(activity).to_ok()
+}
+
+#[utoipa::path(
+    get,
+    path = "/format/{id}/prune-preview",
+    tag = "format",
+    params(("id" = i32, Path, description = "Format ID")),
+    responses((status = 200, description = "What the next prune would delete from the format", body = PrunePreview))
+)]
+#[get("{id}/prune-preview")]
+async fn get_format_prune_preview(id: Option<Path<i32>>, user: ReqData<User>) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let user = user.into_inner();
+    let format = FormatQuery::find_by_id(&user, id)
+        .await?
+        .ok_or(APIError::NotFound(format!("format with ID {}", id)))?
+        .try_into_model()?;
+    if !user.is_superuser {
+        info!("Denied access to prune preview, user id: {}", user.id);
+        return APIError::AdminOnlyResource.into();
+    }
+    let now = chrono::offset::Utc::now();
+    let preview =
+        UploadSessionMutation::preview_prune(DBConfig::get_connection(), &format, now).await?;
+    HttpResponse::Ok().json(preview).to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/format/{id}",
+    tag = "format",
+    params(("id" = i32, Path, description = "Format ID")),
+    responses((status = 204, description = "The format and all of its records were deleted"))
+)]
+#[delete("{id}", wrap = "RequireWriteScope")]
 async fn delete_format(id: Option<Path<i32>>, user: ReqData<User>) -> APIResponse {
-    verify_admin(&user)?;
     let id = *id.ok_or(APIError::BadRequest)?;
-    let result = FormatMutation::delete(id).await?;
+    FormatQuery::find_by_id(&user, id)
+        .await?
+        .ok_or(APIError::NotFound(format!("format with ID {}", id)))?;
+    verify_role(&user, Role::FormatManager)?;
+    let result = FormatMutation::delete(DBConfig::get_connection(), id).await?;
     info!("Delete: Success: {result:?}");
     HttpResponse::NoContent().finish().to_ok()
 }
 
-#[post("")]
+#[utoipa::path(
+    post,
+    path = "/format",
+    tag = "format",
+    request_body = Format,
+    responses((status = 201, description = "The created format", body = Format))
+)]
+#[post("", wrap = "RequireWriteScope")]
 async fn create_format(inbound: Json<FormatModel>, user: ReqData<User>) -> APIResponse {
-    verify_admin(&user)?;
-    if inbound.retention_period_minutes < 0 {
-        info!(
-            "invalid retention period: {:?}",
-            inbound.retention_period_minutes
-        );
-        return Err(APIError::BadRequest);
-    }
-    let outbound = FormatMutation::create(inbound.into_inner()).await?;
+    verify_role(&user, Role::FormatManager)?;
+    let outbound = FormatMutation::create(
+        DBConfig::get_connection(),
+        inbound.into_inner(),
+        Some(user.id),
+    )
+    .await?;
     HttpResponse::Created()
         .json(outbound.try_into_model()?)
         .to_ok()
 }
 
+#[utoipa::path(
+    patch,
+    path = "/format/{id}",
+    tag = "format",
+    params(("id" = i32, Path, description = "Format ID")),
+    request_body = FormatUpdate,
+    responses((status = 200, description = "The updated format", body = Format))
+)]
+#[patch("{id}", wrap = "RequireWriteScope")]
+async fn update_format(
+    id: Option<Path<i32>>,
+    inbound: Json<UpdatableModel>,
+    user: ReqData<User>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let format = FormatQuery::find_by_id(&user, id)
+        .await?
+        .ok_or(APIError::NotFound(format!("format with ID {}", id)))?;
+    verify_role(&user, Role::FormatManager)?;
+    let outbound =
+        FormatMutation::update(DBConfig::get_connection(), format, inbound.into_inner()).await?;
+    HttpResponse::Ok().json(outbound).to_ok()
+}
+
 pub fn init_format_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("/format")
         .wrap(AuthMiddleware)
         .service(create_format)
+        .service(get_format_batch)
         .service(get_all_format)
         .service(delete_format)
+        .service(update_format)
+        .service(get_format_activity)
+        .service(get_format_prune_preview)
         .service(get_format);
 
     cfg.service(scope);
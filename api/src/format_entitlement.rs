@@ -1,36 +1,42 @@
 use crate::{
-    core_middleware::auth::AuthMiddleware,
+    core_middleware::{auth::AuthMiddleware, scope::RequireWriteScope},
     error::{APIError, APIResponse, AsAPIResult},
-    pagination::{PaginatedResponse, Validate},
-    util::verify_admin,
+    pagination::{validated_pager, PaginatedResponse},
+    util::{verify_can_see_user, verify_role},
 };
 use actix_web::{
-    delete, get, post, web,
+    delete, patch, post, route, web,
     web::{Json, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use central_repository_dao::{
     conf::DBConfig,
     format_entitlement::{ModelAsQuery, SearchModel as FormatEntitlementSearch},
     sea_orm::ModelTrait,
-    user::Model,
+    user::{Model, Role},
     FormatEntitlementMutation, FormatEntitlementQuery, FormatQuery, GetAllPaginated,
     PaginationOptions, UserQuery,
 };
 use entity::format_entitlement::Model as FormatEntitlementModel;
 use log::info;
 
-#[post("")]
+#[utoipa::path(
+    post,
+    path = "/entitlement",
+    tag = "entitlement",
+    request_body = FormatEntitlement,
+    responses((status = 201, description = "The created entitlement", body = FormatEntitlement))
+)]
+#[post("", wrap = "RequireWriteScope")]
 async fn create_entitlement(
     inbound: Json<FormatEntitlementModel>,
     auth: ReqData<Model>,
 ) -> APIResponse {
-    verify_admin(&auth)?;
-
     if inbound.access.is_empty() {
         return Err(APIError::BadRequest);
     }
     // make sure we're assigning a format to a non-superuser
+    verify_can_see_user(&auth, inbound.user_id)?;
     UserQuery::find_nonsuperuser_by_id(inbound.user_id)
         .await?
         .ok_or_else(|| {
@@ -44,50 +50,114 @@ async fn create_entitlement(
             info!("Couldn't find format id {}", inbound.format_id);
             APIError::NotFound(format!("format with ID {}", inbound.format_id))
         })?;
+    verify_role(&auth, Role::FormatManager)?;
     HttpResponse::Created()
-        .json(FormatEntitlementMutation::create(inbound.into_inner()).await?)
+        .json(
+            FormatEntitlementMutation::create(
+                DBConfig::get_connection(),
+                inbound.into_inner(),
+                Some(auth.id),
+            )
+            .await?,
+        )
         .to_ok()
 }
 
-#[get("")]
+#[utoipa::path(
+    get,
+    path = "/entitlement",
+    tag = "entitlement",
+    params(PaginationOptions, ModelAsQuery),
+    responses((status = 200, description = "Entitlements visible to this user", body = Vec<FormatEntitlement>))
+)]
+#[route("", method = "GET", method = "HEAD")]
 async fn get_all_entitlements(
+    req: HttpRequest,
     pager: Query<PaginationOptions>,
     filter: Query<ModelAsQuery>,
     auth: ReqData<Model>,
 ) -> APIResponse {
-    pager.validate()?;
+    let pager = validated_pager(&req, pager)?;
     let auth = auth.into_inner();
     let filter = filter.into_inner();
-    let pager = pager.into_inner();
     Ok(PaginatedResponse::from(
         FormatEntitlementQuery::get_all_filtered_for_user(&filter, &pager, auth, None).await?,
     )
+    .for_pager(&pager)
+    .with_etag(&req)
     .into())
 }
 
-#[delete("")]
+#[utoipa::path(
+    delete,
+    path = "/entitlement",
+    tag = "entitlement",
+    request_body = FormatEntitlementKey,
+    responses((status = 204, description = "The entitlement was deleted"))
+)]
+#[delete("", wrap = "RequireWriteScope")]
 async fn delete_entitlement(
     inbound: Json<FormatEntitlementSearch>,
     auth: ReqData<Model>,
 ) -> APIResponse {
-    verify_admin(&auth)?;
     let inbound = inbound.into_inner();
+    let entitlement = FormatEntitlementQuery::find_by_id(&auth, &inbound)
+        .await?
+        .ok_or_else(|| APIError::NotFound("format entitlement".into()))?;
+    verify_role(&auth, Role::FormatManager)?;
     info!(
         "Preparing to delete format entitlement {:?} (requested by user ID {}).",
         inbound, auth.id
     );
-    FormatEntitlementQuery::find_by_id(&inbound)
-        .await?
-        .ok_or_else(|| APIError::NotFound("format entitlement".into()))?
-        .delete(DBConfig::get_connection())
-        .await?;
+    entitlement.delete(DBConfig::get_connection()).await?;
     HttpResponse::NoContent().finish().to_ok()
 }
 
+#[utoipa::path(
+    patch,
+    path = "/entitlement",
+    tag = "entitlement",
+    request_body = FormatEntitlement,
+    responses((status = 200, description = "The updated entitlement", body = FormatEntitlement))
+)]
+#[patch("", wrap = "RequireWriteScope")]
+async fn update_entitlement(
+    inbound: Json<FormatEntitlementModel>,
+    auth: ReqData<Model>,
+) -> APIResponse {
+    let inbound = inbound.into_inner();
+    if inbound.access.is_empty() {
+        return Err(APIError::BadRequest);
+    }
+    let key = FormatEntitlementSearch {
+        user_id: inbound.user_id,
+        format_id: inbound.format_id,
+    };
+    let entitlement = FormatEntitlementQuery::find_by_id(&auth, &key)
+        .await?
+        .ok_or_else(|| APIError::NotFound("format entitlement".into()))?;
+    verify_role(&auth, Role::FormatManager)?;
+    info!(
+        "Updating format entitlement {:?} to {:?} (requested by user ID {}).",
+        key, inbound.access, auth.id
+    );
+    HttpResponse::Ok()
+        .json(
+            FormatEntitlementMutation::update(
+                DBConfig::get_connection(),
+                entitlement,
+                inbound.access,
+            )
+            .await?,
+        )
+        .to_ok()
+}
+
 pub fn init_format_entitlement_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("/entitlement")
         .wrap(AuthMiddleware)
         .service(delete_entitlement)
+        .service(update_entitlement)
         .service(get_all_entitlements)
         .service(create_entitlement);
 
@@ -0,0 +1,137 @@
+use std::{error::Error, time::Instant};
+
+use central_repository_config::inner::Config;
+use central_repository_dao::{conf::DBConfig, UploadSessionMutation};
+use entity::upload_session::Model as UploadSessionModel;
+use log::{error, info, warn};
+use once_cell::sync::OnceCell;
+use tracing::{Instrument, Span};
+
+use crate::{
+    core_middleware::logging::{current_request_id, with_request_id},
+    error::APIError,
+    record::{elapsed_ms, session_detail, Upload},
+    upload_session::publish_upload_session,
+};
+
+static QUEUE: OnceCell<flume::Sender<IngestionJob>> = OnceCell::new();
+
+/// A `POST /record?async=true` upload waiting to be processed.
+struct IngestionJob {
+    upload: Upload,
+    upload_session: UploadSessionModel,
+    // The request the upload came in, so the logs of the job can be traced
+    // back to it.
+    request_id: String,
+    span: Span,
+}
+
+pub struct IngestionQueue;
+
+impl IngestionQueue {
+    /// Spawn the workers that process async uploads.
+    pub fn init() -> Result<(), Box<dyn Error>> {
+        let config = Config::get();
+        let (tx, rx) = flume::bounded(config.ingestion_queue_depth);
+        if QUEUE.set(tx).is_err() {
+            return Err("Cannot set ingestion queue".into());
+        }
+        for _ in 0..config.ingestion_workers {
+            tokio::spawn(Self::work(rx.clone()));
+        }
+        info!("Ingestion: {} workers started", config.ingestion_workers);
+        Ok(())
+    }
+
+    fn queue() -> Result<&'static flume::Sender<IngestionJob>, APIError> {
+        QUEUE.get().ok_or_else(|| {
+            error!("ingestion: the queue isn't running");
+            APIError::ServerError
+        })
+    }
+
+    fn queue_full() -> APIError {
+        APIError::IngestionQueueFull(Config::get().ingestion_queue_depth as u64)
+    }
+
+    /// Fail if the queue is full. Pushing may still fail afterwards, this only
+    /// saves work in the common case.
+    pub(crate) fn check_capacity() -> Result<(), APIError> {
+        match Self::queue()?.is_full() {
+            true => Err(Self::queue_full()),
+            false => Ok(()),
+        }
+    }
+
+    /// Queue `upload`, whose session is `upload_session`. This never blocks:
+    /// if the queue is full, the upload is dropped.
+    pub(crate) fn push(upload: Upload, upload_session: UploadSessionModel) -> Result<(), APIError> {
+        let upload_session_id = upload_session.id;
+        let job = IngestionJob {
+            upload,
+            upload_session,
+            request_id: current_request_id().unwrap_or_default(),
+            span: Span::current(),
+        };
+        Self::queue()?.try_send(job).map_err(|err| {
+            warn!("ingestion: rejecting upload session {upload_session_id}: {err}");
+            match err {
+                flume::TrySendError::Full(_) => Self::queue_full(),
+                flume::TrySendError::Disconnected(_) => APIError::ServerError,
+            }
+        })
+    }
+
+    async fn work(rx: flume::Receiver<IngestionJob>) {
+        while let Ok(job) = rx.recv_async().await {
+            let IngestionJob {
+                upload,
+                upload_session,
+                request_id,
+                span,
+            } = job;
+            let upload_session_id = upload_session.id;
+            let started = Instant::now();
+            // Every upload runs in its own task, so a panic only takes down
+            // that upload.
+            let processed = tokio::spawn(
+                with_request_id(request_id.clone(), upload.process_queued(upload_session))
+                    .instrument(span.clone()),
+            )
+            .await;
+            let err = match processed {
+                Ok(Ok(())) => continue,
+                Ok(Err(err)) => err,
+                Err(err) => {
+                    error!("ingestion: upload session {upload_session_id} panicked: {err}");
+                    APIError::ServerError
+                }
+            };
+            with_request_id(request_id, Self::fail(upload_session_id, err, started))
+                .instrument(span)
+                .await;
+        }
+    }
+
+    /// Fail the session of an upload that ended in `err`, unless the upload
+    /// did it already.
+    async fn fail(upload_session_id: i32, err: APIError, started: Instant) {
+        let failed = UploadSessionMutation::fail_if_in_progress(
+            DBConfig::get_connection(),
+            upload_session_id,
+            session_detail(&err),
+            elapsed_ms(started),
+        )
+        .await;
+        match failed {
+            Ok(Some(upload_session)) => {
+                info!("ingestion: upload session {upload_session_id} failed: {err}");
+                publish_upload_session(&upload_session);
+            }
+            Ok(None) => {}
+            Err(db_err) => {
+                error!("ingestion: cannot fail upload session {upload_session_id}: {db_err:?}")
+            }
+        }
+    }
+}
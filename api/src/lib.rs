@@ -1,3 +1,4 @@
+pub mod admin;
 pub mod api_key;
 pub mod auth;
 pub mod common;
@@ -6,70 +7,221 @@ pub mod core_middleware;
 pub mod error;
 pub mod format;
 pub mod format_entitlement;
+pub mod ingestion;
 pub mod model_prepare;
+pub mod openapi;
 pub mod pagination;
 pub mod record;
 pub mod record_validation;
+pub mod saved_search;
+pub mod stats;
 pub mod upload_session;
 pub mod user;
 pub mod util;
+pub mod webhook;
 
 use std::error::Error;
 
-use actix_web::{App, HttpServer};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse},
+    middleware::{Compress, Condition},
+    web, App, HttpServer,
+};
 use central_repository_config::{self, inner::Config};
-use central_repository_dao::{conf::DBConfig, tasks::Tasks};
+use central_repository_dao::{conf::DBConfig, tasks::Tasks, RecordCipher, WebhookDispatcher};
 use format::init_format_routes;
 use format_entitlement::init_format_entitlement_routes;
 use log::info;
-use migration::{Migrator, MigratorTrait};
+use migration::{MigrationStatus, Migrator, MigratorTrait};
 use mimalloc::MiMalloc;
 use record::init_record_routes;
-use user::init_user_routes;
+use user::{init_bootstrap_superuser, init_user_routes};
 
 use crate::{
     conf::APIConfig,
-    core_middleware::logging::LogMiddleware,
+    core_middleware::{
+        compression::CompressionFilter, logging::LogMiddleware, timeout::RequestTimeout,
+    },
     error::{json_error_handler, path_error_handler, query_error_handler},
-    upload_session::init_upload_session_routes,
+    ingestion::IngestionQueue,
+    openapi::init_openapi_routes,
+    stats::init_stats_routes,
+    upload_session::{init_upload_session_prune_routes, init_upload_session_routes},
+    webhook::init_webhook_routes,
 };
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
+/// Every endpoint is served under this prefix, and without it if
+/// LEGACY_ROUTES is set.
+pub const API_V1_PREFIX: &str = "/api/v1";
+
 #[actix_web::main]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     tracing_subscriber::fmt::init();
 
+    let config = init().await?;
+    Tasks::init_prune_task();
+    Tasks::init_stuck_session_task();
+    Tasks::init_stale_key_task();
+
+    let Some(admin_http_port) = config.admin_http_port else {
+        info!(
+            "Launching server on {}:{}",
+            config.http_address, config.http_port
+        );
+        HttpServer::new(move || build_app(config, true, true))
+            .bind(format!("{}:{}", config.http_address, config.http_port))?
+            .workers(config.workers.into())
+            .run()
+            .await?;
+        return Ok(());
+    };
+
+    info!(
+        "Launching server on {}:{}, admin endpoints on {}:{}",
+        config.http_address, config.http_port, config.admin_http_address, admin_http_port
+    );
+    let server = HttpServer::new(move || build_app(config, true, false))
+        .bind(format!("{}:{}", config.http_address, config.http_port))?
+        .workers(config.workers.into())
+        .run();
+    // the admin endpoints don't get much traffic.
+    let admin_server = HttpServer::new(move || build_app(config, false, true))
+        .bind(format!("{}:{}", config.admin_http_address, admin_http_port))?
+        .workers(1)
+        .run();
+    futures::try_join!(server, admin_server)?;
+    Ok(())
+}
+
+/// Read the config and set up everything the app needs: keys, the database
+/// (applying pending migrations), the bootstrap superuser and the webhook and
+/// ingestion queues. The periodic tasks aren't started.
+pub async fn init() -> Result<&'static Config, Box<dyn Error>> {
+    APIConfig::init_started_at();
     let config = Config::init_and_check()?;
     APIConfig::init_jwt_keys()?;
+    APIConfig::init_totp_key()?;
+    RecordCipher::init()?;
     APIConfig::init_limit_service()?;
     DBConfig::init_db_connection().await?;
 
-    // run pending migrations
-    Migrator::up(DBConfig::get_connection(), None).await?;
+    run_migrations().await?;
+    init_bootstrap_superuser().await?;
 
-    Tasks::init_prune_task();
+    WebhookDispatcher::init()?;
+    IngestionQueue::init()?;
+    Ok(config)
+}
+
+/// Build the app for a listener. `public` enables the regular endpoints and
+/// `admin` the administrative ones, which get their own listener if
+/// ADMIN_HTTP_PORT is set.
+pub fn build_app(
+    config: &'static Config,
+    public: bool,
+    admin: bool,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let long_request_timeout = config.long_request_timeout_seconds;
+    App::new()
+        .wrap(
+            RequestTimeout::new(config.request_timeout_seconds)
+                .with_override("/api/v1/record", long_request_timeout)
+                .with_override("/api/v1/upload_session/prune", long_request_timeout)
+                .with_override("/api/v1/user/bulk", long_request_timeout)
+                .with_override("/record", long_request_timeout)
+                .with_override("/upload_session/prune", long_request_timeout)
+                .with_override("/user/bulk", long_request_timeout),
+        )
+        // LogMiddleware has to be inside Compress: it only handles boxed bodies.
+        .wrap(LogMiddleware)
+        .wrap(Condition::new(
+            config.enable_compression,
+            CompressionFilter::new(config.compression_min_size_bytes),
+        ))
+        .wrap(Condition::new(
+            config.enable_compression,
+            Compress::default(),
+        ))
+        .app_data(json_error_handler(config.max_json_payload_size))
+        .app_data(query_error_handler())
+        .app_data(path_error_handler())
+        .service(web::scope(API_V1_PREFIX).configure(|cfg| init_routes(cfg, public, admin)))
+        .configure(|cfg| {
+            if config.legacy_routes {
+                init_routes(cfg, public, admin)
+            }
+        })
+}
 
+/// The admin routes go first, the prune trigger would be shadowed by the
+/// `/upload_session` scope otherwise.
+fn init_routes(cfg: &mut web::ServiceConfig, public: bool, admin: bool) {
+    if admin {
+        init_admin_routes(cfg);
+    }
+    if public {
+        init_public_routes(cfg);
+    }
+}
+
+fn init_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.configure(init_upload_session_prune_routes)
+        .configure(init_stats_routes);
+}
+
+fn init_public_routes(cfg: &mut web::ServiceConfig) {
+    cfg.configure(init_format_routes)
+        .configure(init_record_routes)
+        .configure(init_user_routes)
+        .configure(init_format_entitlement_routes)
+        .configure(init_upload_session_routes)
+        .configure(init_webhook_routes)
+        .configure(init_openapi_routes);
+}
+
+/// Apply pending migrations. This fails if the database has migrations that
+/// are unknown to this build, e.g. because a newer version already ran.
+async fn run_migrations() -> Result<(), Box<dyn Error>> {
+    let db = DBConfig::get_connection();
+    let migrations = Migrator::get_migration_with_status(db)
+        .await
+        .map_err(|err| format!("cannot check the state of the migrations: {err}"))?;
+    let (applied, pending): (Vec<_>, Vec<_>) = migrations
+        .iter()
+        .partition(|migration| migration.status() == MigrationStatus::Applied);
+    let applied = applied
+        .iter()
+        .map(|migration| migration.name())
+        .collect::<Vec<_>>();
+    let pending = pending
+        .iter()
+        .map(|migration| migration.name())
+        .collect::<Vec<_>>();
+    info!(
+        "DB: {} applied migrations: {}",
+        applied.len(),
+        applied.join(", ")
+    );
+    if pending.is_empty() {
+        return Ok(());
+    }
     info!(
-        "Launching server on {}:{}",
-        config.http_address, config.http_port
+        "DB: applying {} migrations: {}",
+        pending.len(),
+        pending.join(", ")
     );
-    HttpServer::new(move || {
-        App::new()
-            .wrap(LogMiddleware)
-            .app_data(json_error_handler())
-            .app_data(query_error_handler())
-            .app_data(path_error_handler())
-            .configure(init_format_routes)
-            .configure(init_record_routes)
-            .configure(init_user_routes)
-            .configure(init_format_entitlement_routes)
-            .configure(init_upload_session_routes)
-    })
-    .bind(format!("{}:{}", config.http_address, config.http_port))?
-    .workers(config.workers.into())
-    .run()
-    .await?;
+    Migrator::up(db, None).await?;
     Ok(())
 }
@@ -1,14 +1,54 @@
 use central_repository_dao::user::{Model as UserModel, UpdatableModel};
 
-use crate::{auth::hashing::UserPassword, error::APIError};
+use crate::{
+    auth::hashing::UserPassword,
+    error::{APIError, ValidationFailureKind},
+};
+
+// Max length of an email address (RFC 5321).
+const MAX_EMAIL_LENGTH: usize = 254;
 
 pub trait DBPrepare {
     /// Prepare any given object for database insertion.
     fn prepare(&mut self) -> impl std::future::Future<Output = Result<(), APIError>> + Send;
 }
 
+/// Basic syntax check for email addresses: `local@domain.tld`, without spaces.
+fn verify_email(email: &str) -> Result<(), APIError> {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            email.len() <= MAX_EMAIL_LENGTH
+                && !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+                && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        }
+        _ => false,
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(APIError::ValidationFailure(
+            ValidationFailureKind::InvalidEmail,
+        )),
+    }
+}
+
+fn verify_storage_quota(quota: Option<i64>) -> Result<(), APIError> {
+    match quota {
+        Some(quota) if quota < 0 => Err(APIError::InvalidOperation(
+            "storage quotas cannot be negative".into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
 impl DBPrepare for UserModel {
     async fn prepare(&mut self) -> Result<(), APIError> {
+        if let Some(email) = self.email.as_ref() {
+            verify_email(email)?;
+        }
+        verify_storage_quota(self.max_storage_bytes)?;
         let password = self.password.clone();
         // perform expensive crypto operation in threadpool
         let current_span = tracing::Span::current();
@@ -26,6 +66,10 @@ impl DBPrepare for UpdatableModel {
     /// This basically checks whether or not the password was updated.
     /// If it was, we just simply generate a hash for it.
     async fn prepare(&mut self) -> Result<(), APIError> {
+        if let Some(Some(email)) = self.email.as_ref() {
+            verify_email(email)?;
+        }
+        verify_storage_quota(self.max_storage_bytes.flatten())?;
         let password = match &self.password {
             Some(s) => s.to_owned(),
             _ => return Ok(()),
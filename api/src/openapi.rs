@@ -0,0 +1,685 @@
+use actix_web::{get, web, HttpResponse};
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    api_key, format, format_entitlement, record, saved_search, saved_search_share, upload_session,
+    user, webhook, webhook_delivery, ColumnStats, ColumnStatsQuery, ComparisonOperator,
+    ConditionKind, ExportFormat, ExportManifest, FormatActivity, FormatSummary, FormatUsage,
+    GlobalStats, JoinKind, ManifestColumn, PruneMaintenance, PrunePreview, QuerySummary,
+    RecordChanges, RecordChangesQuery, SearchArguments, SearchGroup, SearchQuery, TreatAs,
+    UploadSessionDeleteResult, UploadSessionPruneResult, UploaderFilter, UserTransferResult,
+    UserUsage,
+};
+use entity::error::ArgumentError;
+use lazy_static::lazy_static;
+use utoipa::{
+    openapi::{
+        header::HeaderBuilder,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        ContentBuilder, KnownFormat, ObjectBuilder, OpenApi as OpenApiSpec, Ref, RefOr,
+        ResponseBuilder, Schema, SchemaFormat, SchemaType,
+    },
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{
+    auth::jwt::{TokenResponse, TokenScope},
+    error::{OutboundAPIError, PROBLEM_JSON},
+    format::{FormatBatchRequest, FormatBatchResponse, FormatWithAccess},
+    record::{RecordPage, UploadResponse},
+    record_validation::{InboundRecordData, RecordValidationError, ValidationReport},
+    stats::{
+        AdminConfig, AdminStats, ConfigSelfTests, DerivedConfig, Diagnostics, LimitDiagnostics,
+        LimiterConfig, PaginationConfig, PruneSchedule, RuntimeDiagnostics, WorkerDiagnostics,
+    },
+    user::{
+        BulkUserFailure, BulkUserResult, LoginCredentials, TotpConfirmation, TotpEnrollment,
+        UserTransfer,
+    },
+};
+
+const SECURITY_SCHEME: &str = "bearer";
+const ERROR_RESPONSE: &str = "Error";
+// Headers set by PaginatedResponse.
+const PAGINATION_HEADERS: [(&str, SchemaType, &str); 7] = [
+    (
+        "repository-item-count",
+        SchemaType::Integer,
+        "Total number of items",
+    ),
+    (
+        "repository-current-page-count",
+        SchemaType::Integer,
+        "Number of items in this page",
+    ),
+    (
+        "repository-page-count",
+        SchemaType::Integer,
+        "Total number of pages",
+    ),
+    (
+        "repository-page",
+        SchemaType::Integer,
+        "The page that was fetched",
+    ),
+    (
+        "repository-per-page",
+        SchemaType::Integer,
+        "The number of items per page, after applying the default",
+    ),
+    (
+        "repository-count-enabled",
+        SchemaType::Boolean,
+        "Whether the items were counted, after applying the default",
+    ),
+    (
+        "ETag",
+        SchemaType::String,
+        "Weak tag of the ids in this page and the item count (GET requests only)",
+    ),
+];
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "central-repository",
+        description = "Store and query JSON records validated against user-defined formats."
+    ),
+    paths(
+        crate::format::get_all_format,
+        crate::format::get_format,
+        crate::format::get_format_batch,
+        crate::format::get_format_activity,
+        crate::format::get_format_prune_preview,
+        crate::format::create_format,
+        crate::format::update_format,
+        crate::format::delete_format,
+        crate::record::create_record,
+        crate::record::validate_records,
+        crate::record::get_all_filtered_records,
+        crate::record::get_all_filtered_records_stream,
+        crate::record::get_record_changes,
+        crate::record::get_column_stats,
+        crate::saved_search::get_all_saved_searches,
+        crate::saved_search::get_saved_search,
+        crate::saved_search::create_saved_search,
+        crate::saved_search::update_saved_search,
+        crate::saved_search::delete_saved_search,
+        crate::saved_search::execute_saved_search,
+        crate::saved_search::get_saved_search_shares,
+        crate::saved_search::create_saved_search_share,
+        crate::saved_search::delete_saved_search_share,
+        crate::user::login,
+        crate::user::healthcheck,
+        crate::user::create_user,
+        crate::user::create_users_bulk,
+        crate::user::get_all_users,
+        crate::user::get_self,
+        crate::user::enroll_totp,
+        crate::user::confirm_totp,
+        crate::user::get_user,
+        crate::user::get_user_usage,
+        crate::user::update_user,
+        crate::user::delete_user,
+        crate::user::transfer_user,
+        crate::api_key::get_all_api_keys,
+        crate::api_key::create_api_key,
+        crate::api_key::update_api_key,
+        crate::api_key::delete_api_key,
+        crate::format_entitlement::get_all_entitlements,
+        crate::format_entitlement::create_entitlement,
+        crate::format_entitlement::delete_entitlement,
+        crate::format_entitlement::update_entitlement,
+        crate::upload_session::get_all_upload_sessions,
+        crate::upload_session::upload_session_events,
+        crate::upload_session::get_upload_session,
+        crate::upload_session::delete,
+        crate::upload_session::prune,
+        crate::upload_session::export,
+        crate::webhook::get_all_webhooks,
+        crate::webhook::get_webhook,
+        crate::webhook::create_webhook,
+        crate::webhook::update_webhook,
+        crate::webhook::delete_webhook,
+        crate::webhook::get_webhook_deliveries,
+        crate::stats::get_stats,
+        crate::stats::get_diagnostics,
+        crate::stats::get_config,
+    ),
+    components(schemas(
+        OutboundAPIError,
+        ArgumentError,
+        LoginCredentials,
+        TotpEnrollment,
+        TotpConfirmation,
+        UserTransfer,
+        BulkUserResult,
+        BulkUserFailure,
+        UserTransferResult,
+        TokenResponse,
+        TokenScope,
+        InboundRecordData,
+        ValidationReport,
+        RecordValidationError,
+        RecordPage,
+        UploadResponse,
+        format::ColumnKind,
+        format::ColumnSchema,
+        format::FormatSchema,
+        format::FormatRule,
+        format::FormatRules,
+        format::RuleOperator,
+        format::Model,
+        format::UpdatableModel,
+        FormatBatchRequest,
+        FormatBatchResponse,
+        FormatWithAccess,
+        user::Model,
+        user::UpdatableModel,
+        user::Role,
+        api_key::Model,
+        api_key::ModelWithStatus,
+        api_key::UpdatableModel,
+        format_entitlement::AccessLevel,
+        format_entitlement::Access,
+        format_entitlement::Model,
+        format_entitlement::SearchModel,
+        record::RecordJsonData,
+        record::Model,
+        record::DatalessRecord,
+        upload_session::OutcomeKind,
+        upload_session::Model,
+        upload_session::ModelAsQuery,
+        saved_search::Model,
+        saved_search::UpdatableModel,
+        saved_search::SearchParameters,
+        saved_search_share::Model,
+        webhook::WebhookEvent,
+        webhook::WebhookEvents,
+        webhook::Model,
+        webhook::UpdatableModel,
+        webhook_delivery::Model,
+        SearchQuery,
+        UploaderFilter,
+        SearchGroup,
+        SearchArguments,
+        ConditionKind,
+        ComparisonOperator,
+        JoinKind,
+        TreatAs,
+        RecordChangesQuery,
+        RecordChanges,
+        ColumnStatsQuery,
+        ColumnStats,
+        QuerySummary,
+        FormatSummary,
+        ExportManifest,
+        ManifestColumn,
+        UploadSessionPruneResult,
+        PrunePreview,
+        UploadSessionDeleteResult,
+        PruneMaintenance,
+        ExportFormat,
+        GlobalStats,
+        FormatActivity,
+        UserUsage,
+        FormatUsage,
+        AdminStats,
+        Diagnostics,
+        RuntimeDiagnostics,
+        LimitDiagnostics,
+        WorkerDiagnostics,
+        AdminConfig,
+        DerivedConfig,
+        PaginationConfig,
+        LimiterConfig,
+        PruneSchedule,
+        ConfigSelfTests,
+        central_repository_dao::conf::PoolStats,
+    )),
+    modifiers(&BearerAuth, &ErrorResponses, &PaginationHeaders, &ServerPopulatedFields),
+    security(("bearer" = [])),
+    tags(
+        (name = "auth", description = "Authentication and health checks"),
+        (name = "format", description = "Record formats (schemas)"),
+        (name = "record", description = "Record upload and search"),
+        (name = "saved_search", description = "Stored search queries"),
+        (name = "user", description = "User management"),
+        (name = "api_key", description = "API keys"),
+        (name = "entitlement", description = "Per-format user permissions"),
+        (name = "upload_session", description = "Upload history"),
+        (name = "webhook", description = "Outbound notifications"),
+        (name = "admin", description = "Instance administration"),
+    )
+)]
+pub struct ApiDoc;
+
+lazy_static! {
+    static ref SPEC: OpenApiSpec = ApiDoc::openapi();
+}
+
+/// Every endpoint (unless told otherwise) needs a JWT, be it a user token or an API key.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut OpenApiSpec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            SECURITY_SCHEME,
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// All the errors share the same body (OutboundAPIError), so instead of listing every
+/// possible status code, add a reusable error response as the default response of
+/// every endpoint.
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut OpenApiSpec) {
+        let content = ContentBuilder::new()
+            .schema(Ref::from_schema_name("OutboundAPIError"))
+            .build();
+        let response = ResponseBuilder::new()
+            .description(
+                "The request failed. `code` is a stable error code and `detail` a \
+                human-readable message.",
+            )
+            .content("application/json", content.clone())
+            .content(PROBLEM_JSON, content)
+            .build();
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components
+            .responses
+            .insert(ERROR_RESPONSE.into(), RefOr::T(response));
+
+        for path in openapi.paths.paths.values_mut() {
+            for operation in path.operations.values_mut() {
+                operation
+                    .responses
+                    .responses
+                    .entry("default".into())
+                    .or_insert_with(|| Ref::from_response_name(ERROR_RESPONSE).into());
+            }
+        }
+    }
+}
+
+/// Paginated endpoints (i.e. those with a `page` query param) return the item and page
+/// counts in headers.
+struct PaginationHeaders;
+
+impl Modify for PaginationHeaders {
+    fn modify(&self, openapi: &mut OpenApiSpec) {
+        for path in openapi.paths.paths.values_mut() {
+            for operation in path.operations.values_mut() {
+                let is_paginated = operation
+                    .parameters
+                    .iter()
+                    .flatten()
+                    .any(|param| param.name == "page");
+                if !is_paginated {
+                    continue;
+                }
+                if let Some(RefOr::T(response)) = operation.responses.responses.get_mut("200") {
+                    for (name, schema_type, description) in PAGINATION_HEADERS {
+                        let format = match schema_type {
+                            SchemaType::Integer => {
+                                Some(SchemaFormat::KnownFormat(KnownFormat::Int64))
+                            }
+                            _ => None,
+                        };
+                        let header = HeaderBuilder::new()
+                            .schema(ObjectBuilder::new().schema_type(schema_type).format(format))
+                            .description(Some(description))
+                            .build();
+                        response.headers.insert(name.into(), header);
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum FieldKind {
+    Integer,
+    Uuid,
+    DateTime,
+    String,
+}
+
+enum FieldAccess {
+    ReadOnly,
+    WriteOnly,
+}
+
+/// Fields set by the server (or never sent back) are marked with
+/// `#[serde(skip_deserializing)]`/`#[serde(skip_serializing)]`, which utoipa takes
+/// as "skip this field entirely". Add them back as read-only/write-only properties.
+const SERVER_POPULATED_FIELDS: &[(&str, &str, FieldKind, FieldAccess)] = &[
+    ("Format", "id", FieldKind::Integer, FieldAccess::ReadOnly),
+    (
+        "Format",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "Format",
+        "createdBy",
+        FieldKind::Uuid,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "Format",
+        "updatedAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    ("User", "id", FieldKind::Uuid, FieldAccess::ReadOnly),
+    (
+        "User",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "User",
+        "password",
+        FieldKind::String,
+        FieldAccess::WriteOnly,
+    ),
+    ("ApiKey", "id", FieldKind::Uuid, FieldAccess::ReadOnly),
+    (
+        "ApiKey",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "ApiKey",
+        "lastRotatedAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "FormatEntitlement",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "FormatEntitlement",
+        "createdBy",
+        FieldKind::Uuid,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "FormatEntitlement",
+        "updatedAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    ("Record", "id", FieldKind::Integer, FieldAccess::ReadOnly),
+    (
+        "Record",
+        "upload_session_id",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "Record",
+        "format_id",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "Record",
+        "created_at",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "id",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "contentHash",
+        FieldKind::String,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "payloadBytes",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "processingMs",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "UploadSession",
+        "apiKeyId",
+        FieldKind::Uuid,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearch",
+        "id",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearch",
+        "userId",
+        FieldKind::Uuid,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearch",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearch",
+        "updatedAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearchShare",
+        "savedSearchId",
+        FieldKind::Integer,
+        FieldAccess::ReadOnly,
+    ),
+    (
+        "SavedSearchShare",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+    ("Webhook", "id", FieldKind::Integer, FieldAccess::ReadOnly),
+    (
+        "Webhook",
+        "secret",
+        FieldKind::String,
+        FieldAccess::WriteOnly,
+    ),
+    (
+        "Webhook",
+        "createdAt",
+        FieldKind::DateTime,
+        FieldAccess::ReadOnly,
+    ),
+];
+
+struct ServerPopulatedFields;
+
+impl Modify for ServerPopulatedFields {
+    fn modify(&self, openapi: &mut OpenApiSpec) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        for (schema, field, kind, access) in SERVER_POPULATED_FIELDS {
+            let Some(RefOr::T(Schema::Object(object))) = components.schemas.get_mut(*schema) else {
+                continue;
+            };
+            let property = match kind {
+                FieldKind::Integer => ObjectBuilder::new().schema_type(SchemaType::Integer),
+                FieldKind::Uuid => ObjectBuilder::new()
+                    .schema_type(SchemaType::String)
+                    .format(Some(SchemaFormat::KnownFormat(KnownFormat::Uuid))),
+                FieldKind::DateTime => ObjectBuilder::new()
+                    .schema_type(SchemaType::String)
+                    .format(Some(SchemaFormat::KnownFormat(KnownFormat::DateTime))),
+                FieldKind::String => ObjectBuilder::new().schema_type(SchemaType::String),
+            };
+            let property = match access {
+                FieldAccess::ReadOnly => property.read_only(Some(true)),
+                FieldAccess::WriteOnly => property.write_only(Some(true)),
+            };
+            object.properties.insert(field.to_string(), property.into());
+            object.required.push(field.to_string());
+        }
+    }
+}
+
+#[get("/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(&*SPEC)
+}
+
+/// The docs endpoints don't require authentication.
+pub fn init_openapi_routes(cfg: &mut web::ServiceConfig) {
+    let config = Config::get();
+    if !config.enable_openapi {
+        return;
+    }
+    match config.enable_swagger_ui {
+        // the swagger ui service also serves the spec itself
+        true => {
+            cfg.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/openapi.json", SPEC.clone()));
+        }
+        false => {
+            cfg.service(openapi_json);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every file with handlers.
+    const SOURCES: &[(&str, &str)] = &[
+        ("api_key.rs", include_str!("api_key.rs")),
+        ("format.rs", include_str!("format.rs")),
+        (
+            "format_entitlement.rs",
+            include_str!("format_entitlement.rs"),
+        ),
+        ("openapi.rs", include_str!("openapi.rs")),
+        ("record.rs", include_str!("record.rs")),
+        ("saved_search.rs", include_str!("saved_search.rs")),
+        ("stats.rs", include_str!("stats.rs")),
+        ("upload_session.rs", include_str!("upload_session.rs")),
+        ("user.rs", include_str!("user.rs")),
+        ("webhook.rs", include_str!("webhook.rs")),
+    ];
+    const ROUTE_MACROS: &[&str] = &[
+        "#[get(",
+        "#[post(",
+        "#[patch(",
+        "#[put(",
+        "#[delete(",
+        "#[route(",
+    ];
+    // Handlers that aren't part of the API itself.
+    const UNDOCUMENTED: &[&str] = &["#[get(\"/openapi.json\")]"];
+
+    /// A handler (its route macro) and the method and path of its
+    /// `#[utoipa::path]`, if any.
+    struct Handler {
+        location: String,
+        route: String,
+        documented: Option<(String, String)>,
+    }
+
+    fn handlers() -> Vec<Handler> {
+        let mut handlers = Vec::new();
+        for (file, source) in SOURCES {
+            let mut documented: Option<(String, String)> = None;
+            let mut lines = source.lines().enumerate();
+            while let Some((number, line)) = lines.next() {
+                if line.starts_with("#[utoipa::path(") {
+                    let (_, method) = lines.next().unwrap();
+                    let method = method.trim().trim_end_matches(',').to_string();
+                    let path = lines
+                        .by_ref()
+                        .find_map(|(_, line)| line.trim().strip_prefix("path = \""))
+                        .and_then(|path| path.split('"').next())
+                        .unwrap();
+                    documented = Some((method, path.to_string()));
+                } else if ROUTE_MACROS.iter().any(|route| line.starts_with(route)) {
+                    handlers.push(Handler {
+                        location: format!("{file}:{}", number + 1),
+                        route: line.to_string(),
+                        documented: documented.take(),
+                    });
+                } else if line.contains("fn ") {
+                    documented = None;
+                }
+            }
+        }
+        handlers
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = &spec["paths"];
+        let handlers = handlers();
+        assert!(
+            handlers.len() > 50,
+            "only found {} handlers",
+            handlers.len()
+        );
+        for handler in handlers {
+            if UNDOCUMENTED.contains(&handler.route.as_str()) {
+                continue;
+            }
+            let (method, path) = handler
+                .documented
+                .unwrap_or_else(|| panic!("{}: no #[utoipa::path]", handler.location));
+            if !handler.route.starts_with("#[route(") {
+                assert!(
+                    handler.route.starts_with(&format!("#[{method}(")),
+                    "{}: documented as {method}",
+                    handler.location
+                );
+            }
+            assert!(
+                paths[&path][&method].is_object(),
+                "{}: {method} {path} is missing from ApiDoc",
+                handler.location
+            );
+        }
+    }
+}
@@ -1,6 +1,17 @@
-use actix_web::HttpResponse;
+use std::io;
+
+use actix_web::{
+    http::{
+        header::{EntityTag, Header, IfNoneMatch, ETAG},
+        Method,
+    },
+    web::Query,
+    HttpRequest, HttpResponse,
+};
+use central_repository_config::inner::Config;
 use central_repository_dao::PaginationOptions;
 use log::info;
+use ring::digest::{Context, SHA256};
 use serde::Serialize;
 
 use crate::error::APIError;
@@ -12,18 +23,52 @@ pub trait Validate {
 impl Validate for PaginationOptions {
     fn validate(&self) -> Result<(), APIError> {
         if !self.is_valid() {
-            return Err(APIError::InvalidPaginationParameters(
-                "Pagination parameters are invalid".to_string(),
-            ));
+            return Err(APIError::InvalidPaginationParameters(format!(
+                "perPage must be between 1 and {}",
+                Config::get().max_pagination_size
+            )));
+        }
+        // without the count, pages past the last one can't be told apart
+        // and run the OFFSET query anyway.
+        let max_offset = Config::get().max_pagination_offset;
+        if !self.count && max_offset > 0 && self.offset() > max_offset {
+            return Err(APIError::InvalidPaginationParameters(format!(
+                "page * perPage must be at most {max_offset} without count=true"
+            )));
         }
         Ok(())
     }
 }
 
+/// Validate the pagination options of a list endpoint. List endpoints also
+/// answer HEAD requests, which only run the count query: the response has the
+/// same pagination headers as the GET request, but no body.
+///
+/// Pass the result to [`PaginatedResponse::for_pager`].
+pub fn validated_pager(
+    req: &HttpRequest,
+    pager: Query<PaginationOptions>,
+) -> Result<PaginationOptions, APIError> {
+    let mut pager = pager.into_inner();
+    if req.method() == Method::HEAD {
+        pager.count = true;
+        pager.count_only = true;
+    }
+    pager.validate()?;
+    Ok(pager)
+}
+
 pub struct PaginatedResponse<T> {
     items: Vec<T>,
     num_pages: u64,
     num_items: u64,
+    current_page_count: u64,
+    count_only: bool,
+    avg_item_bytes: Option<u64>,
+    truncated_after_id: Option<i64>,
+    pager: Option<PaginationOptions>,
+    etag: Option<EntityTag>,
+    not_modified: bool,
 }
 
 // From<> for load_and_count_pages's output
@@ -34,9 +79,150 @@ where
     fn from(data: (Vec<T>, u64, u64)) -> PaginatedResponse<T> {
         let (items, num_pages, num_items) = data;
         PaginatedResponse {
+            current_page_count: items.len() as u64,
             items,
             num_pages,
             num_items,
+            count_only: false,
+            avg_item_bytes: None,
+            truncated_after_id: None,
+            pager: None,
+            etag: None,
+            not_modified: false,
+        }
+    }
+}
+
+impl<T> PaginatedResponse<T> {
+    /// Respond to HEAD requests (see [`validated_pager`]) with headers only.
+    /// The effective `page`, `perPage` and `count` (after applying the
+    /// defaults) are sent in `repository-page`, `repository-per-page` and
+    /// `repository-count-enabled`.
+    pub fn for_pager(mut self, pager: &PaginationOptions) -> Self {
+        self.pager = Some(pager.clone());
+        if pager.count_only {
+            self.count_only = true;
+            // what the GET request would have returned.
+            self.current_page_count = self
+                .num_items
+                .saturating_sub(pager.page.saturating_mul(pager.per_page))
+                .min(pager.per_page);
+        }
+        self
+    }
+
+    /// With `size_hint`, also send the average size of the items in this page,
+    /// serialized as JSON, in `repository-avg-item-bytes` (if there are any).
+    pub fn with_size_hint(mut self, size_hint: bool) -> Self
+    where
+        T: Serialize,
+    {
+        if size_hint {
+            self.avg_item_bytes = average_json_size(&self.items);
+        }
+        self
+    }
+
+    /// Drop the items that don't fit in `max_bytes` (serialized as JSON, 0
+    /// disables the limit). The first item is always kept. If the page is cut
+    /// short, the id of its last item is sent in `repository-truncated-after-id`.
+    /// The page is already loaded by then: this bounds the size of the
+    /// response, not the memory used to build it (that's up to `perPage`).
+    pub fn with_byte_budget<F>(mut self, max_bytes: u64, id_of: F) -> Self
+    where
+        T: Serialize,
+        F: Fn(&T) -> i64,
+    {
+        if max_bytes == 0 {
+            return self;
+        }
+        let mut counter = ByteCounter(0);
+        let overflow = self.items.iter().position(|item| {
+            // items that can't be serialized fail the response anyway
+            let _ = serde_json::to_writer(&mut counter, item);
+            counter.0 > max_bytes
+        });
+        if let Some(keep) = overflow.map(|index| index.max(1)) {
+            if keep < self.items.len() {
+                info!(
+                    "page is over {max_bytes} bytes, truncating it from {} to {keep} items",
+                    self.items.len()
+                );
+                self.items.truncate(keep);
+                self.current_page_count = keep as u64;
+                self.truncated_after_id = self.items.last().map(id_of);
+            }
+        }
+        self
+    }
+
+    /// Send a weak `ETag` built from the items in this page (serialized as
+    /// JSON) and the item count, and answer with a `304 Not Modified` if it
+    /// matches the `If-None-Match` header of `req`. Call this last, once the
+    /// page won't change anymore.
+    pub fn with_etag(mut self, req: &HttpRequest) -> Self
+    where
+        T: Serialize,
+    {
+        // HEAD requests don't load the items.
+        if self.count_only {
+            return self;
+        }
+        let mut hasher = HashWriter(Context::new(&SHA256));
+        hasher.0.update(self.num_items.to_string().as_bytes());
+        for item in &self.items {
+            hasher.0.update(b",");
+            // items that can't be serialized fail the response anyway
+            let _ = serde_json::to_writer(&mut hasher, item);
+        }
+        let context = hasher.0;
+        let etag = EntityTag::new_weak(hex_prefix(context.finish().as_ref()));
+        self.not_modified = match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        };
+        self.etag = Some(etag);
+        self
+    }
+
+    /// Build the response with the usual pagination headers, but use
+    /// `body(items)` as the JSON body instead of the bare array of items.
+    pub fn respond_with<B, F>(self, body: F) -> HttpResponse
+    where
+        B: Serialize,
+        F: FnOnce(Vec<T>) -> B,
+    {
+        info!(
+            "page count: {}, item count: {}, returning {} items",
+            self.num_pages, self.num_items, self.current_page_count
+        );
+        let mut response = match self.not_modified {
+            true => HttpResponse::NotModified(),
+            false => HttpResponse::Ok(),
+        };
+        response
+            .insert_header(("repository-item-count", self.num_items))
+            .insert_header(("repository-current-page-count", self.current_page_count))
+            .insert_header(("repository-page-count", self.num_pages));
+        if let Some(avg_item_bytes) = self.avg_item_bytes {
+            response.insert_header(("repository-avg-item-bytes", avg_item_bytes));
+        }
+        if let Some(id) = self.truncated_after_id {
+            response.insert_header(("repository-truncated-after-id", id));
+        }
+        if let Some(pager) = self.pager {
+            response
+                .insert_header(("repository-page", pager.page))
+                .insert_header(("repository-per-page", pager.per_page))
+                .insert_header(("repository-count-enabled", pager.count.to_string()));
+        }
+        if let Some(etag) = self.etag {
+            response.insert_header((ETAG, etag.to_string()));
+        }
+        match self.count_only || self.not_modified {
+            true => response.finish(),
+            false => response.json(body(self.items)),
         }
     }
 }
@@ -46,16 +232,57 @@ where
     T: Serialize,
 {
     fn from(value: PaginatedResponse<T>) -> Self {
-        info!(
-            "page count: {}, item count: {}, returning {} items",
-            value.num_pages,
-            value.num_items,
-            value.items.len()
-        );
-        HttpResponse::Ok()
-            .insert_header(("repository-item-count", value.num_items))
-            .insert_header(("repository-current-page-count", value.items.len()))
-            .insert_header(("repository-page-count", value.num_pages))
-            .json(value.items)
+        value.respond_with(|items| items)
+    }
+}
+
+/// Average size in bytes of `items` serialized as JSON, without the separators
+/// of the array.
+fn average_json_size<T: Serialize>(items: &[T]) -> Option<u64> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut counter = ByteCounter(0);
+    for item in items {
+        serde_json::to_writer(&mut counter, item).ok()?;
+    }
+    Some(counter.0 / items.len() as u64)
+}
+
+/// The first 16 bytes of `digest` as hex, plenty for an ETag.
+fn hex_prefix(digest: &[u8]) -> String {
+    digest
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A writer that only counts bytes, so items aren't serialized into a buffer.
+pub(crate) struct ByteCounter(pub u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that feeds everything into a digest, so items aren't serialized
+/// into a buffer.
+struct HashWriter(Context);
+
+impl io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
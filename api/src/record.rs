@@ -1,29 +1,67 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
 use crate::{
+    auth::jwt::AuthenticatedKey,
     common::{timed, DebugMode},
     conf::APIConfig,
-    core_middleware::auth::AuthMiddleware,
-    error::{APIError, APIResponse, AsAPIResult},
+    core_middleware::{
+        auth::AuthMiddleware, logging::current_request_id, scope::RequireWriteScope,
+    },
+    error::{json_error_handler, APIError, APIResponse, AsAPIResult, OutboundAPIError},
+    ingestion::IngestionQueue,
     pagination::{PaginatedResponse, Validate},
     record_validation::InboundRecordData,
+    saved_search::saved_search_scope,
+    upload_session::publish_upload_session,
+    util::append_rate_limit_headers,
 };
 use central_repository_config::inner::Config;
 use central_repository_dao::{
-    record::ModelAsQuery, upload_session::OutcomeKind, user::Model as UserModel, FormatQuery,
-    PaginationOptions, ParallelStreamConfig, RecordMutation, RecordQuery, SearchQuery,
-    UploadSessionMutation, UserQuery,
+    conf::DBConfig, record::ModelAsQuery, upload_session::OutcomeKind, user::Model as UserModel,
+    ColumnStatsQuery, FormatMutation, FormatQuery, PaginationOptions, ParallelStreamConfig,
+    RecordChangesQuery, RecordExportOptions, RecordMutation, RecordQuery, SearchQuery,
+    UploadSessionMutation, UploadSessionQuery, UserMutation, UserQuery, WebhookDispatcher,
 };
 
 use actix_web::{
+    http::header::CONTENT_LENGTH,
     post,
     web::{self, Json, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use entity::error::DatabaseQueryError;
+use entity::format::{FormatSchema, Model as FormatModel};
 use entity::record::Model as RecordModel;
 use entity::upload_session::Model as UploadSessionModel;
-use futures::{future::join_all, StreamExt};
-use log::{debug, error, info};
-use rayon::{prelude::*, slice::ParallelSlice};
+use entity::webhook::WebhookEvent;
+use futures::StreamExt;
+use log::{error, info, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
 
+#[utoipa::path(
+    post,
+    path = "/record/filter",
+    tag = "record",
+    params(PaginationOptions, ModelAsQuery, DebugMode, FilterRecordOptions),
+    request_body = SearchQuery,
+    responses((
+        status = 200,
+        description = "A page of matching records, wrapped in a `RecordPage` if `envelope=true` \
+                       or without their data (`DatalessRecord`) if `dataless=true`. A \
+                       `QuerySummary` with `debug=true`",
+        body = Vec<Record>,
+        headers(
+            ("repository-avg-item-bytes" = u64, description = "Average size of the records in this page, in bytes (only with `sizeHint=true`)"),
+            ("repository-truncated-after-id" = i64, description = "Set if the page was cut short to stay under MAX_FILTER_RESPONSE_BYTES: the id of its last record")
+        )
+    ))
+)]
 #[post("/filter")]
 async fn get_all_filtered_records(
     pager: Query<PaginationOptions>,
@@ -31,7 +69,18 @@ async fn get_all_filtered_records(
     auth: ReqData<UserModel>,
     query: Json<SearchQuery>,
     debug: actix_web::web::Query<DebugMode>,
+    options: Query<FilterRecordOptions>,
 ) -> APIResponse {
+    if options.include_schemas && !options.envelope {
+        return Err(APIError::InvalidOperation(
+            "includeSchemas requires envelope=true".into(),
+        ));
+    }
+    if options.dataless && options.envelope {
+        return Err(APIError::InvalidOperation(
+            "dataless can't be combined with envelope=true".into(),
+        ));
+    }
     query.validate()?;
     // get this query's inner contents
     let query = query.into_inner();
@@ -40,22 +89,34 @@ async fn get_all_filtered_records(
     pager.validate()?;
     let pager = pager.into_inner();
     if **debug {
-        // if "?debug=true" is passed, return the full dict query
+        // if "?debug=true" is passed, return what the query would run on
         info!("accessed debugging interface");
-        return HttpResponse::Ok().json(query).to_ok();
+        return debug_summary(&auth, query).await;
     }
-    let prepared_search = query.get_readable_formats_for_user(&auth).await?;
-    // create extra filtering condition to search inside ALL JSONB hashmaps
-    let records = RecordQuery::filter_readable_records(&filter, &pager, prepared_search).await?;
-    Ok(PaginatedResponse::from(records).into())
+    filter_records(&auth, &filter, &pager, query, &options).await
 }
 
+#[utoipa::path(
+    post,
+    path = "/record/filter-stream",
+    tag = "record",
+    params(ModelAsQuery, DebugMode, StreamRecordOptions),
+    request_body = SearchQuery,
+    responses(
+        (status = 200, description = "All the matching records", content_type = "text/csv", body = String, headers(
+            ("repository-streams" = u64, description = "Number of database streams used, after clamping `streams`"),
+            ("repository-transform-workers" = u64, description = "Number of serializing workers used, after clamping `transformWorkers`")
+        )),
+        (status = 429, description = "Too many concurrent streams", body = OutboundAPIError)
+    )
+)]
 #[post("/filter-stream")]
 async fn get_all_filtered_records_stream(
     filter: Query<ModelAsQuery>,
     auth: ReqData<UserModel>,
     query: Json<SearchQuery>,
     debug: actix_web::web::Query<DebugMode>,
+    options: Query<StreamRecordOptions>,
 ) -> APIResponse {
     query.validate()?;
     // get this query's inner contents
@@ -64,36 +125,377 @@ async fn get_all_filtered_records_stream(
     let filter = filter.into_inner();
     if **debug {
         info!("accessed debugging interface");
-        return HttpResponse::Ok().json(query).to_ok();
+        return debug_summary(&auth, query).await;
+    }
+    stream_records(auth.into_inner(), &filter, query, &options).await
+}
+
+/// Get the records added after a given record id, for incremental syncs.
+/// Note: deleted records aren't reported.
+#[utoipa::path(
+    post,
+    path = "/record/changes",
+    tag = "record",
+    request_body = RecordChangesQuery,
+    responses((status = 200, description = "The next batch of records", body = RecordChanges))
+)]
+#[post("/changes")]
+async fn get_record_changes(
+    auth: ReqData<UserModel>,
+    query: Json<RecordChangesQuery>,
+) -> APIResponse {
+    query.validate()?;
+    let query = query.into_inner();
+    info!("changes query: {:?}", query);
+    let changes = RecordQuery::get_changes(&auth, query).await?;
+    HttpResponse::Ok().json(changes).to_ok()
+}
+
+/// Profile a single column of the records matching a search: value range,
+/// distinct values and missing values.
+#[utoipa::path(
+    post,
+    path = "/record/column-stats",
+    tag = "record",
+    request_body = ColumnStatsQuery,
+    responses(
+        (status = 200, description = "The statistics of the column", body = ColumnStats),
+        (status = 503, description = "The query took longer than COLUMN_STATS_TIMEOUT_SECONDS", body = OutboundAPIError)
+    )
+)]
+#[post("/column-stats")]
+async fn get_column_stats(auth: ReqData<UserModel>, query: Json<ColumnStatsQuery>) -> APIResponse {
+    query.validate()?;
+    let query = query.into_inner();
+    info!("column stats query: {:?}", query);
+    let stats = RecordQuery::column_stats(&auth, query)
+        .await
+        .map_err(|err| {
+            APIError::from_timed_query(err, Config::get().column_stats_timeout_seconds)
+        })?;
+    HttpResponse::Ok().json(stats).to_ok()
+}
+
+/// Resolve the formats of `query` for `auth` like a search would, and describe
+/// them instead of returning records.
+async fn debug_summary(auth: &UserModel, query: SearchQuery) -> APIResponse {
+    let summary = query.get_readable_formats_for_user(auth).await?.summary()?;
+    HttpResponse::Ok().json(summary).to_ok()
+}
+
+/// Run `query` on behalf of `auth` and return a single page of records.
+/// Formats `auth` can't read are silently left out of the search.
+pub(crate) async fn filter_records(
+    auth: &UserModel,
+    filter: &ModelAsQuery,
+    pager: &PaginationOptions,
+    query: SearchQuery,
+    options: &FilterRecordOptions,
+) -> APIResponse {
+    let prepared_search = query.get_readable_formats_for_user(auth).await?;
+    let max_bytes = Config::get().max_filter_response_bytes;
+    if options.dataless {
+        let records =
+            RecordQuery::filter_readable_records_dataless(filter, pager, prepared_search).await?;
+        return Ok(PaginatedResponse::from(records)
+            .for_pager(pager)
+            .with_byte_budget(max_bytes, |record| record.id)
+            .with_size_hint(options.size_hint)
+            .into());
+    }
+    // the formats were loaded already, so their schemas come for free.
+    let all_schemas = match options.include_schemas {
+        true => Some(
+            prepared_search
+                .format_schemas()
+                .into_iter()
+                .map(|(id, schema)| (id, schema.clone()))
+                .collect::<HashMap<_, _>>(),
+        ),
+        false => None,
+    };
+    // create extra filtering condition to search inside ALL JSONB hashmaps
+    let records = RecordQuery::filter_readable_records(filter, pager, prepared_search).await?;
+    let response = PaginatedResponse::from(records)
+        .for_pager(pager)
+        .with_byte_budget(max_bytes, |record| record.id)
+        .with_size_hint(options.size_hint);
+    if !options.envelope {
+        return Ok(response.into());
+    }
+    response
+        .respond_with(|items| {
+            // only send the schemas of the formats in this page
+            let schemas = all_schemas.map(|mut all_schemas| {
+                items
+                    .iter()
+                    .filter_map(|record| all_schemas.remove_entry(&record.format_id))
+                    .collect()
+            });
+            RecordPage { items, schemas }
+        })
+        .to_ok()
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FilterRecordOptions {
+    /// Wrap the records in a `RecordPage` object instead of returning a bare array.
+    #[serde(default)]
+    envelope: bool,
+    /// Add the schema of every format in the page to the response (requires `envelope=true`).
+    #[serde(default)]
+    include_schemas: bool,
+    /// Only return the id, upload session id and format id of every record.
+    #[serde(default)]
+    dataless: bool,
+    /// Send the average size of the records in the page, in bytes, in the
+    /// `repository-avg-item-bytes` header.
+    #[serde(default)]
+    size_hint: bool,
+}
+
+/// A page of records, returned by `POST /record/filter?envelope=true`.
+#[derive(Serialize, ToSchema)]
+pub struct RecordPage {
+    items: Vec<RecordModel>,
+    /// The schema of every format in `items`, by format id (only with `includeSchemas=true`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schemas: Option<BTreeMap<i32, FormatSchema>>,
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub(crate) struct StreamRecordOptions {
+    /// Only export the columns used in the search arguments (default: every
+    /// column of the searched formats).
+    #[serde(default)]
+    columns_from_query: bool,
+    /// Comma-separated columns to export, in this order. Every column must
+    /// belong to one of the searched formats. Spaces around the names and
+    /// empty names are ignored.
+    columns: Option<String>,
+    /// Number of database streams, up to `DB_CSV_MAX_STREAM_WORKERS` (default:
+    /// `DB_CSV_STREAM_WORKERS`). With 1, the records aren't counted first.
+    /// Non-superusers can only raise it above the default if they're listed
+    /// in `DB_CSV_PARALLEL_USERS`.
+    streams: Option<u64>,
+    /// Number of workers serializing the records, up to
+    /// `DB_CSV_MAX_TRANSFORM_WORKERS` (default: `DB_CSV_TRANSFORM_WORKERS`).
+    /// Same restrictions as `streams`.
+    transform_workers: Option<u64>,
+    /// Append a `#manifest {...}` trailer line with the searched formats, the
+    /// kind of every column, the number of rows and the SHA-256 of everything
+    /// before it.
+    #[serde(default)]
+    manifest: bool,
+    /// Append a `#pipelineStats {...}` trailer line with how full the queues
+    /// of the export were and how long its workers waited (superusers only).
+    #[serde(default)]
+    pipeline_stats: bool,
+}
+
+impl StreamRecordOptions {
+    /// The stream config of this request: the global one, with `streams` and
+    /// `transformWorkers` applied and clamped to what `auth` may use.
+    fn stream_config(&self, auth: &UserModel) -> Result<ParallelStreamConfig, APIError> {
+        let config = Config::get();
+        let parallel_allowed = auth.is_superuser
+            || config
+                .db_csv_parallel_users
+                .split(',')
+                .map(str::trim)
+                .filter(|username| !username.is_empty())
+                .any(|username| username == auth.username);
+        let clamp = |name: &str, requested: Option<u64>, default: u64, max: u64| {
+            let Some(requested) = requested else {
+                return Ok(default as usize);
+            };
+            if requested == 0 {
+                return Err(APIError::InvalidOperation(format!(
+                    "{name} must be greater than 0"
+                )));
+            }
+            let max = match parallel_allowed {
+                true => max,
+                false => default,
+            };
+            Ok(requested.min(max) as usize)
+        };
+        Ok(ParallelStreamConfig::new(
+            clamp(
+                "streams",
+                self.streams,
+                config.db_csv_stream_workers,
+                config.db_csv_max_stream_workers,
+            )?,
+            config.db_csv_worker_queue_depth as usize,
+            clamp(
+                "transformWorkers",
+                self.transform_workers,
+                config.db_csv_transform_workers,
+                config.db_csv_max_transform_workers,
+            )?,
+        ))
     }
+}
 
-    let config = ParallelStreamConfig::default();
+/// Run `query` on behalf of `auth` and stream all the matching records as CSV.
+pub(crate) async fn stream_records(
+    auth: UserModel,
+    filter: &ModelAsQuery,
+    query: SearchQuery,
+    options: &StreamRecordOptions,
+) -> APIResponse {
+    if options.columns.is_some() && options.columns_from_query {
+        return Err(APIError::InvalidOperation(
+            "columns can't be combined with columnsFromQuery=true".into(),
+        ));
+    }
+    if options.pipeline_stats && !auth.is_superuser {
+        info!("Denied access to pipeline stats, user id: {}", auth.id);
+        return Err(APIError::AdminOnlyResource);
+    }
+    let columns = options.columns.as_ref().map(|columns| {
+        columns
+            .split(',')
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .map(String::from)
+            .collect::<Vec<_>>()
+    });
+    if columns.as_ref().is_some_and(Vec::is_empty) {
+        return Err(APIError::InvalidOperation(
+            "columns must name at least one column".into(),
+        ));
+    }
+    let export_options = RecordExportOptions {
+        columns,
+        columns_from_query: options.columns_from_query,
+        manifest: options.manifest,
+        pipeline_stats: options.pipeline_stats,
+        request_id: current_request_id(),
+    };
+    let config = options.stream_config(&auth)?;
 
     let mut limit_grant = None;
     if !auth.is_superuser {
         limit_grant = Some(APIConfig::get_limit_service().new_grant_for_key(&auth.username)?);
     }
+    let mut response = HttpResponse::Ok();
+    append_rate_limit_headers(&mut response, limit_grant.as_ref());
+    response
+        .append_header(("repository-streams", config.num_streams()))
+        .append_header((
+            "repository-transform-workers",
+            config.num_transform_threads(),
+        ));
 
     let stream = RecordQuery::filter_readable_records_stream(
-        auth.into_inner(),
-        &filter,
+        auth,
+        filter,
         query,
+        export_options,
         config,
         limit_grant,
     )
     .await?
     .map(|it| Ok::<_, APIError>(web::Bytes::from(it)));
 
-    HttpResponse::Ok()
+    response
         .append_header(("Content-Type", "text/csv"))
         .streaming(stream)
         .to_ok()
 }
 
-#[post("")]
-async fn create_record(inbound: Json<InboundRecordData>, auth: ReqData<UserModel>) -> APIResponse {
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct CreateRecordOptions {
+    /// Skip the format's quota checks (superusers only).
+    #[serde(default)]
+    override_quota: bool,
+    /// Refuse the upload if the same records were already uploaded to this
+    /// format (by anyone) and that upload succeeded.
+    #[serde(default)]
+    reject_duplicate: bool,
+    /// Convert values into the kind of their column before validating them,
+    /// e.g. `"42.5"` into `42.5` for Number columns.
+    #[serde(default)]
+    coerce: bool,
+    /// Answer with `202 Accepted` and the upload session (in progress) once the
+    /// format was checked, and process the records in the background.
+    #[serde(default, rename = "async")]
+    run_async: bool,
+}
+
+/// Response of `POST /record`. Uploads that fail validation or go over the
+/// format's quota get one too, with the fields of the error next to these.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadResponse {
+    #[schema(value_type = UploadSession)]
+    pub upload_session: UploadSessionModel,
+    /// Records in the request.
+    pub received_count: i32,
+    /// Records that were saved: either all of them or none.
+    pub inserted_count: i32,
+    pub elapsed_ms: i64,
+    #[serde(flatten)]
+    #[schema(value_type = Option<OutboundAPIError>)]
+    pub error: Option<OutboundAPIError>,
+}
+
+impl UploadResponse {
+    fn failed(upload_session: UploadSessionModel, started: Instant, err: APIError) -> APIResponse {
+        let response = UploadResponse {
+            received_count: upload_session.record_count,
+            inserted_count: 0,
+            upload_session,
+            elapsed_ms: elapsed_ms(started),
+            error: Some(err.outbound()),
+        };
+        err.response_builder().json(response).to_ok()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/record",
+    tag = "record",
+    params(CreateRecordOptions),
+    request_body = InboundRecordData,
+    responses(
+        (status = 200, description = "The upload was saved", body = UploadResponse),
+        (status = 202, description = "The upload was queued (with `async`), the response includes its upload session", body = UploadResponse),
+        (status = 400, description = "The records are invalid, the response includes the failed upload session", body = UploadResponse),
+        (status = 409, description = "The same records were already uploaded (with `rejectDuplicate`)", body = OutboundAPIError),
+        (status = 429, description = "The upload would exceed the format's quota, the response includes the failed upload session. \
+                                      For async uploads, the queue is full", body = UploadResponse)
+    )
+)]
+#[post("", wrap = "RequireWriteScope")]
+async fn create_record(
+    req: HttpRequest,
+    inbound: Json<InboundRecordData>,
+    auth: ReqData<UserModel>,
+    key: ReqData<AuthenticatedKey>,
+    options: Query<CreateRecordOptions>,
+) -> APIResponse {
+    let started = Instant::now();
+    // chunked uploads don't have a length, use the size of the hashed
+    // records instead (see below).
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<i64>().ok());
     let auth = auth.into_inner();
-    let request_item_length = inbound.data.len() as i32;
+    let options = options.into_inner();
+    if options.override_quota && !auth.is_superuser {
+        return Err(APIError::AdminOnlyResource);
+    }
     let inbound = inbound.into_inner();
     let format = match auth.is_superuser {
         // bypass format check for superusers
@@ -101,109 +503,465 @@ async fn create_record(inbound: Json<InboundRecordData>, auth: ReqData<UserModel
             .await?
             .ok_or_else(|| APIError::NotFound(format!("format with ID {}", inbound.format_id)))?,
         // for normal users, check if they can write to this format
-        false => UserQuery::find_writable_format(&auth, inbound.format_id)
-            .await?
-            .ok_or_else(|| {
+        false => match UserQuery::find_writable_format(&auth, inbound.format_id).await? {
+            Some(format) => format,
+            // formats they can't see are a 404, not a 403.
+            None => {
+                FormatQuery::find_by_id(&auth, inbound.format_id)
+                    .await?
+                    .ok_or_else(|| {
+                        APIError::NotFound(format!("format with ID {}", inbound.format_id))
+                    })?;
                 info!(
                     "User {} doesn't have write permissions on format {}",
                     auth.id, inbound.format_id
                 );
-                APIError::InsufficientPermissions
-            })?,
+                return Err(APIError::InsufficientPermissions);
+            }
+        },
     };
-    let format_id = format.id;
-    let current_span = tracing::Span::current();
-    let payload_validation = timed!(
-        "validation of json data",
-        actix_web::web::block(move || {
-            // Enter the current logging span (we'll be running in another thread)
-            let _guard = current_span.enter();
-            // Validate the entire payload without blocking the main thread. If validation
-            // succeeds, we just return the data again (web::block takes ownership of the
-            // moved data).
-            inbound.validate_blocking(&format).map(|_| inbound)
-        })
-        .await?
-    );
-
-    let outcome_detail = match payload_validation.as_ref() {
-        Ok(_) => (
-            OutcomeKind::Success,
-            format!(
-                "User ID {} uploaded {} entries",
-                auth.id, request_item_length
-            ),
-        ),
-        Err(err) => (OutcomeKind::Error, err.to_string()),
+    reject_unwritable(&format)?;
+    let upload = Upload {
+        auth,
+        api_key_id: key.0,
+        format,
+        inbound,
+        options,
+        content_length,
+        started,
     };
+    match upload.options.run_async {
+        true => upload.enqueue().await,
+        false => upload.process(None).await,
+    }
+}
 
-    // upload session data
-    let upload_session = UploadSessionModel {
-        format_id,
-        user_id: auth.id,
-        record_count: request_item_length,
-        outcome: outcome_detail.0,
-        detail: outcome_detail.1,
-        ..Default::default()
-    };
-    let upload_session = UploadSessionMutation::create(upload_session).await?;
-
-    let saved_entries = match payload_validation {
-        Ok(inbound) => {
-            let request_entries = inbound.data.len() as u64;
-            let entries = inbound
-                .data
-                .into_par_iter()
-                .map(|entry| RecordModel::new(upload_session.id, format_id, entry))
-                .collect::<Vec<_>>();
-            let insert_tasks = entries
-                .par_chunks(Config::get().bulk_insert_chunk_size as usize)
-                .into_par_iter()
-                .map(|chunk| RecordMutation::create_many(chunk.to_owned()))
-                .collect::<Vec<_>>();
-            info!(
-                "Preparing {request_entries} entries/{} chunks = {} insert jobs.",
-                Config::get().bulk_insert_chunk_size,
-                insert_tasks.len()
-            );
-            join_all(insert_tasks)
-                .await
-                .into_iter()
-                .collect::<Result<Vec<u64>, _>>()
+/// An upload to a format the user can write to, see [`create_record`].
+pub(crate) struct Upload {
+    auth: UserModel,
+    api_key_id: Option<Uuid>,
+    format: FormatModel,
+    inbound: InboundRecordData,
+    options: CreateRecordOptions,
+    content_length: Option<i64>,
+    started: Instant,
+}
+
+impl Upload {
+    /// Create the (in progress) session of an async upload and queue it.
+    async fn enqueue(self) -> APIResponse {
+        // don't leave a failed session behind for every rejected upload.
+        IngestionQueue::check_capacity()?;
+        let upload_session = UploadSessionModel {
+            format_id: self.format.id,
+            user_id: self.auth.id,
+            api_key_id: self.api_key_id,
+            record_count: self.inbound.data.len() as i32,
+            outcome: OutcomeKind::InProgress,
+            payload_bytes: self.content_length.unwrap_or_default(),
+            ..Default::default()
+        };
+        let upload_session =
+            UploadSessionMutation::create(DBConfig::get_connection(), upload_session).await?;
+        let started = self.started;
+        let response = UploadResponse {
+            received_count: upload_session.record_count,
+            inserted_count: 0,
+            upload_session: upload_session.clone(),
+            elapsed_ms: elapsed_ms(started),
+            error: None,
+        };
+        if let Err(err) = IngestionQueue::push(self, upload_session) {
+            fail_session(response.upload_session.id, session_detail(&err), started).await?;
+            return Err(err);
         }
-        Err(err) => return Err(err),
-    };
+        HttpResponse::Accepted().json(response).to_ok()
+    }
+
+    /// Process a queued upload, whose session is `upload_session`. The time
+    /// spent in the queue doesn't count as processing time.
+    pub(crate) async fn process_queued(
+        mut self,
+        upload_session: UploadSessionModel,
+    ) -> Result<(), APIError> {
+        self.started = Instant::now();
+        self.process(Some(upload_session)).await.map(|_| ())
+    }
+
+    /// Validate and insert the records. Queued uploads pass their session in
+    /// `queued`, one is created for the others.
+    async fn process(self, queued: Option<UploadSessionModel>) -> APIResponse {
+        let Upload {
+            auth,
+            api_key_id,
+            format,
+            mut inbound,
+            options,
+            content_length,
+            started,
+        } = self;
+        let check_quota = !options.override_quota;
+        let coerce = options.coerce;
+        let request_item_length = inbound.data.len() as i32;
+        let format_id = format.id;
+        let encrypted = format.encrypted;
+        let current_span = tracing::Span::current();
+        let payload_validation = timed!(
+            "validation of json data",
+            actix_web::web::block(move || {
+                // Enter the current logging span (we'll be running in another thread)
+                let _guard = current_span.enter();
+                // Validate the entire payload without blocking the main thread. If validation
+                // succeeds, we just return the data again (web::block takes ownership of the
+                // moved data).
+                if coerce {
+                    inbound.coerce_blocking(&format)?;
+                }
+                inbound.validate_blocking(&format)?;
+                let (content_hash, hashed_bytes) = inbound.content_hash_blocking()?;
+                Ok::<_, APIError>((inbound, content_hash, hashed_bytes))
+            })
+            .await?
+        );
 
-    // verify whether we were able to save ALL the records successfully.
-    match saved_entries {
-        Ok(_) => {
-            info!(
-                "Successfully saved {request_item_length} entries for format {}.",
-                format_id
-            );
-            HttpResponse::Ok().json(upload_session).to_ok()
+        let (inbound, content_hash, hashed_bytes) = match payload_validation {
+            Ok(validated) => validated,
+            Err(err) => {
+                // keep track of the failed upload, there's nothing else to insert.
+                let failed_session = match queued {
+                    Some(upload_session) => {
+                        fail_session(upload_session.id, session_detail(&err), started).await?
+                    }
+                    None => {
+                        let failed_session = UploadSessionModel {
+                            format_id,
+                            user_id: auth.id,
+                            api_key_id,
+                            record_count: request_item_length,
+                            outcome: OutcomeKind::Error,
+                            detail: session_detail(&err),
+                            payload_bytes: content_length.unwrap_or_default(),
+                            processing_ms: elapsed_ms(started),
+                            ..Default::default()
+                        };
+                        save_failed_session(failed_session).await?
+                    }
+                };
+                return UploadResponse::failed(failed_session, started, err);
+            }
+        };
+
+        if options.reject_duplicate {
+            if let Some(duplicate) =
+                UploadSessionQuery::find_duplicate(format_id, &content_hash).await?
+            {
+                info!(
+                    "Rejected duplicate upload to format {format_id} (same as upload session {})",
+                    duplicate.id
+                );
+                return Err(APIError::ConflictingOperation(format!(
+                    "these records were already uploaded to format {format_id} (upload session {})",
+                    duplicate.id
+                )));
+            }
         }
-        // there was an error, roll back the SUCCESS status to a FAILED one
-        // (this should never happen).
-        Err(err) => {
-            error!("Rolling back success status to error (caused by: {err:?})");
-            UploadSessionMutation::update_as_failed(
-                upload_session.id,
-                format!("{:?}, {:?}", err, err.to_string()),
+
+        // The upload session exists (in progress) while its records are
+        // being inserted, so it can be looked up before the upload finishes.
+        let payload_bytes = content_length.unwrap_or(hashed_bytes as i64);
+        let db = DBConfig::get_connection();
+        let upload_session = match queued {
+            Some(upload_session) => {
+                UploadSessionMutation::set_content_hash(
+                    db,
+                    upload_session,
+                    content_hash,
+                    payload_bytes,
+                )
+                .await?
+            }
+            None => {
+                let upload_session = UploadSessionModel {
+                    format_id,
+                    user_id: auth.id,
+                    api_key_id,
+                    record_count: request_item_length,
+                    outcome: OutcomeKind::InProgress,
+                    content_hash: Some(content_hash),
+                    payload_bytes,
+                    ..Default::default()
+                };
+                UploadSessionMutation::create(db, upload_session).await?
+            }
+        };
+        let upload_session_id = upload_session.id;
+        let cancel_guard = CancelledUploadGuard {
+            upload_session_id: Some(upload_session_id),
+            started,
+        };
+        let user_id = auth.id;
+        let detail = format!(
+            "User ID {} uploaded {} entries",
+            auth.id, request_item_length
+        );
+
+        let chunk_size = Config::get().bulk_insert_chunk_size as usize;
+        // Insert all the records atomically: if any of the inserts fails, none
+        // of them are kept and the upload session is marked as failed.
+        let saved_session = timed!(
+            "insertion of records",
+            DBConfig::transaction(|txn| {
+                Box::pin(async move {
+                    if check_quota {
+                        FormatMutation::check_quota(txn, format_id, request_item_length.into())
+                            .await?;
+                        UserMutation::check_storage_quota(txn, user_id, payload_bytes).await?;
+                    }
+                    let chunks = inbound
+                        .data
+                        .into_par_iter()
+                        .map(|entry| {
+                            RecordModel::new(
+                                upload_session.id,
+                                format_id,
+                                upload_session.created_at,
+                                entry,
+                            )
+                        })
+                        .chunks(chunk_size)
+                        .collect::<Vec<_>>();
+                    info!(
+                        "Preparing {request_item_length} entries/{chunk_size} chunks = {} jobs.",
+                        chunks.len()
+                    );
+                    for chunk in chunks {
+                        RecordMutation::create_many(txn, chunk, encrypted).await?;
+                    }
+                    let upload_session = UploadSessionMutation::set_outcome(
+                        txn,
+                        upload_session,
+                        OutcomeKind::Success,
+                        detail,
+                        elapsed_ms(started),
+                    )
+                    .await?;
+                    Ok::<_, DatabaseQueryError>(upload_session)
+                })
+            })
+            .await
+        );
+
+        // verify whether we were able to save ALL the records successfully.
+        let response = match saved_session {
+            Ok(upload_session) => {
+                info!(
+                    "Successfully saved {request_item_length} entries for format {}.",
+                    format_id
+                );
+                publish_upload_session(&upload_session);
+                WebhookDispatcher::notify(
+                    WebhookEvent::UploadCompleted,
+                    Some(format_id),
+                    &upload_session,
+                );
+                let response = UploadResponse {
+                    received_count: request_item_length,
+                    inserted_count: request_item_length,
+                    upload_session,
+                    elapsed_ms: elapsed_ms(started),
+                    error: None,
+                };
+                HttpResponse::Ok().json(response).to_ok()
+            }
+            // the upload would go over the format's quota: keep track of it just like
+            // validation failures.
+            Err(err @ DatabaseQueryError::QuotaExceeded(_)) => {
+                let err = APIError::from(err);
+                let failed_session =
+                    fail_session(upload_session_id, session_detail(&err), started).await?;
+                UploadResponse::failed(failed_session, started, err)
+            }
+            // there was an error and the transaction was rolled back, so keep track
+            // of the failed upload (this should never happen).
+            Err(err) => {
+                error!("Upload transaction was rolled back (caused by: {err:?})");
+                let err = APIError::ServerError;
+                fail_session(upload_session_id, session_detail(&err), started).await?;
+                Err(err)
+            }
+        };
+        cancel_guard.disarm();
+        response
+    }
+}
+
+/// Fails an in-progress upload session if the upload is dropped before it
+/// finishes, i.e. if the request timed out (see `RequestTimeout`). The
+/// transaction is rolled back then, but the session would stay in progress.
+struct CancelledUploadGuard {
+    upload_session_id: Option<i32>,
+    started: Instant,
+}
+
+impl CancelledUploadGuard {
+    fn disarm(mut self) {
+        self.upload_session_id = None;
+    }
+}
+
+impl Drop for CancelledUploadGuard {
+    fn drop(&mut self) {
+        let Some(upload_session_id) = self.upload_session_id.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        warn!("upload session {upload_session_id} was cancelled, failing it");
+        let processing_ms = elapsed_ms(self.started);
+        runtime.spawn(async move {
+            match UploadSessionMutation::fail_if_in_progress(
+                DBConfig::get_connection(),
+                upload_session_id,
+                "the upload was cancelled before it finished",
+                processing_ms,
             )
             .await
-            .map(|_| Err(APIError::ServerError))?
-        }
+            {
+                Ok(Some(failed_session)) => publish_upload_session(&failed_session),
+                Ok(None) => {}
+                Err(err) => error!("cannot fail upload session {upload_session_id}: {err:?}"),
+            }
+        });
+    }
+}
+
+/// The `detail` of a failed upload session. Anyone who can read the session
+/// can see it, so server errors (which may contain SQL) only point to the
+/// logs.
+pub(crate) fn session_detail(err: &APIError) -> String {
+    match err.status_code().is_server_error() {
+        true => format!(
+            "Internal server error, see the logs of request {}",
+            current_request_id().unwrap_or_default()
+        ),
+        false => err.to_string(),
+    }
+}
+
+pub(crate) fn elapsed_ms(started: Instant) -> i64 {
+    started.elapsed().as_millis() as i64
+}
+
+/// Mark an in-progress upload session (whose upload started at `started`)
+/// as failed.
+async fn fail_session(
+    upload_session_id: i32,
+    detail: String,
+    started: Instant,
+) -> Result<UploadSessionModel, APIError> {
+    let failed_session = UploadSessionMutation::update_as_failed(
+        DBConfig::get_connection(),
+        upload_session_id,
+        detail,
+        elapsed_ms(started),
+    )
+    .await?;
+    publish_upload_session(&failed_session);
+    Ok(failed_session)
+}
+
+async fn save_failed_session(
+    failed_session: UploadSessionModel,
+) -> Result<UploadSessionModel, APIError> {
+    let failed_session =
+        UploadSessionMutation::create(DBConfig::get_connection(), failed_session).await?;
+    publish_upload_session(&failed_session);
+    Ok(failed_session)
+}
+
+/// Archived formats can't be uploaded to, and neither can formats without
+/// columns (these were only possible before schemas had to have one).
+fn reject_unwritable(format: &FormatModel) -> Result<(), APIError> {
+    if format.archived {
+        info!("Rejected upload to archived format {}", format.id);
+        return Err(APIError::InvalidOperation(format!(
+            "format {} is archived",
+            format.id
+        )));
     }
+    if format.schema.is_empty() {
+        info!("Rejected upload to format {} without columns", format.id);
+        return Err(APIError::InvalidOperation(format!(
+            "format {} has no columns",
+            format.id
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct ValidateRecordOptions {
+    /// Same as `coerce` in `POST /record`.
+    #[serde(default)]
+    coerce: bool,
+}
+
+/// Check records the way `POST /record` would, without saving anything (not
+/// even an upload session). Read access to the format is enough.
+#[utoipa::path(
+    post,
+    path = "/record/validate",
+    tag = "record",
+    params(ValidateRecordOptions),
+    request_body = InboundRecordData,
+    responses((status = 200, description = "The validation report, also sent for invalid records", body = ValidationReport))
+)]
+#[post("/validate")]
+async fn validate_records(
+    inbound: Json<InboundRecordData>,
+    auth: ReqData<UserModel>,
+    options: Query<ValidateRecordOptions>,
+) -> APIResponse {
+    let mut inbound = inbound.into_inner();
+    let format = FormatQuery::find_by_id(&auth, inbound.format_id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("format with ID {}", inbound.format_id)))?;
+    reject_unwritable(&format)?;
+    let coerce = options.coerce;
+    let current_span = tracing::Span::current();
+    let report = timed!(
+        "validation of json data",
+        actix_web::web::block(move || {
+            let _guard = current_span.enter();
+            inbound.report_blocking(&format, coerce)
+        })
+        .await?
+    )?;
+    info!(
+        "{} of {} records are invalid",
+        report.invalid_count, report.record_count
+    );
+    HttpResponse::Ok().json(report).to_ok()
 }
 
 pub fn init_record_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("/record")
         .wrap(AuthMiddleware)
+        // uploads are much bigger than anything else
+        .app_data(json_error_handler(
+            Config::get().record_max_json_payload_size,
+        ))
         // .service(get_all_records)
         .service(create_record)
+        .service(validate_records)
         .service(get_all_filtered_records)
-        .service(get_all_filtered_records_stream);
+        .service(get_all_filtered_records_stream)
+        .service(get_record_changes)
+        .service(get_column_stats)
+        .service(saved_search_scope());
 
     cfg.service(scope);
 }
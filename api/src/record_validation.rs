@@ -1,27 +1,188 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use central_repository_dao::{format::ColumnKind, record::DynamicHashmap, str_to_isodate};
-use entity::format::Model as FormatModel;
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    format::ColumnKind, preview_value, record::DynamicHashmap, str_to_isodate,
+};
+use entity::format::{FormatRule, Model as FormatModel, RuleOperator};
 use itertools::Itertools;
-use log::{debug, error, info};
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use log::{debug, info};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
 use regex::Regex;
+use ring::digest::{Context, SHA256};
 use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use utoipa::ToSchema;
 
 use crate::{
     common::handle_fatal,
     error::{APIError, ValidationFailureKind},
+    pagination::ByteCounter,
 };
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// Max. number of invalid records listed by `POST /record/validate`.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct InboundRecordData {
     pub format_id: i32,
+    #[schema(value_type = Vec<Object>)]
     pub data: Vec<DynamicHashmap>,
 }
 
 impl InboundRecordData {
+    /// SHA-256 (hex) of the records, in order, and the number of bytes hashed.
+    /// Every record is hashed as JSON with sorted keys, so the order of the
+    /// keys in the payload doesn't matter. Like validation, this can take a
+    /// while for big uploads.
+    pub fn content_hash_blocking(&self) -> Result<(String, usize), APIError> {
+        let mut context = Context::new(&SHA256);
+        let mut buffer = vec![];
+        let mut hashed_bytes = 0;
+        for record in &self.data {
+            buffer.clear();
+            serde_json::to_writer(&mut buffer, &record.iter().collect::<BTreeMap<_, _>>())
+                .map_err(|err| handle_fatal!("record hashing", err, APIError::ServerError))?;
+            buffer.push(b'\n');
+            context.update(&buffer);
+            hashed_bytes += buffer.len();
+        }
+        let hash = context
+            .finish()
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        Ok((hash, hashed_bytes))
+    }
+
+    /// Convert the values of every record into the kind of their column, for
+    /// `POST /record?coerce=true`. Values that already have the right kind are
+    /// left alone, and so are columns that aren't in the schema (validation
+    /// rejects them later). The rules are:
+    ///
+    /// - Number: strings are trimmed and parsed as plain decimal numbers, with
+    ///   an optional sign, fraction and exponent (`"-42"`, `" 4.2e1 "`).
+    ///   Thousands separators (`"1,000"`), hex, `NaN`, infinities and empty
+    ///   strings are rejected. Integers are stored as integers.
+    /// - String: numbers are stored as their JSON representation (`42.5` ->
+    ///   `"42.5"`).
+    /// - Datetime: strings are trimmed, and must be RFC 3339 dates in UTC.
+    ///
+    /// Booleans, nulls, arrays and objects are never coerced. The first value
+    /// that can't be coerced (by record, then by column in schema order) is
+    /// reported.
+    pub fn coerce_blocking(&mut self, inbound: &FormatModel) -> Result<(), APIError> {
+        self.data
+            .par_iter_mut()
+            .enumerate()
+            .find_map_first(|(row, hmap)| coerce_record(inbound, row, hmap).err())
+            .map_or(Ok(()), Err)
+    }
+
+    /// Check every record against the schema and rules of the format. The
+    /// first invalid record is reported.
     pub fn validate_blocking(&self, inbound: &FormatModel) -> Result<(), APIError> {
+        let validator = RecordValidator::new(inbound)?;
+        self.data
+            .par_iter()
+            .enumerate()
+            .find_map_first(|(row, hmap)| validator.validate(row, hmap).err())
+            .map_or(Ok(()), Err)
+    }
+
+    /// Run the same checks as an upload (coercion included, if enabled), but
+    /// report every invalid record instead of stopping at the first one.
+    pub fn report_blocking(
+        &mut self,
+        inbound: &FormatModel,
+        coerce: bool,
+    ) -> Result<ValidationReport, APIError> {
+        let validator = RecordValidator::new(inbound)?;
+        // only the first MAX_REPORTED_ERRORS errors are kept: every piece of
+        // the parallel iterator keeps its first ones, and pieces are merged
+        // in order.
+        let (invalid_count, errors) = self
+            .data
+            .par_iter_mut()
+            .enumerate()
+            .fold(
+                || (0u64, Vec::new()),
+                |(mut invalid_count, mut errors), (row, hmap)| {
+                    let coerced = match coerce {
+                        true => coerce_record(inbound, row, hmap),
+                        false => Ok(()),
+                    };
+                    if let Err(err) = coerced.and_then(|_| validator.validate(row, hmap)) {
+                        invalid_count += 1;
+                        if errors.len() < MAX_REPORTED_ERRORS {
+                            errors.push(RecordValidationError {
+                                record: row as u64,
+                                code: err.error_code().code(),
+                                detail: err.to_string(),
+                            });
+                        }
+                    }
+                    (invalid_count, errors)
+                },
+            )
+            .reduce(
+                || (0, Vec::new()),
+                |(left_count, mut left), (right_count, right)| {
+                    left.extend(right);
+                    left.truncate(MAX_REPORTED_ERRORS);
+                    (left_count + right_count, left)
+                },
+            );
+        Ok(ValidationReport {
+            ok: invalid_count == 0,
+            record_count: self.data.len() as u64,
+            invalid_count,
+            errors,
+        })
+    }
+}
+
+/// Result of `POST /record/validate`.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationReport {
+    /// Whether every record is valid, i.e. uploading them would pass validation.
+    pub ok: bool,
+    pub record_count: u64,
+    /// Number of invalid records, including the ones left out of `errors`.
+    pub invalid_count: u64,
+    /// The first 100 invalid records, in order.
+    pub errors: Vec<RecordValidationError>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordValidationError {
+    /// Index of the record in `data`.
+    pub record: u64,
+    /// The error code the upload would fail with, e.g. `REPO-1003`.
+    pub code: &'static str,
+    pub detail: String,
+}
+
+/// The checks of [`InboundRecordData::validate_blocking`], prepared once and
+/// run on every record.
+struct RecordValidator<'a> {
+    format: &'a FormatModel,
+    valid_keys: HashSet<&'a String>,
+    schema: HashMap<&'a String, &'a ColumnKind>,
+    column_to_regex: HashMap<&'a String, Regex>,
+    // RECORD_MAX_STRING_LENGTH and RECORD_MAX_ROW_BYTES (0 = no limit).
+    max_string_length: u64,
+    max_row_bytes: u64,
+}
+
+impl<'a> RecordValidator<'a> {
+    fn new(inbound: &'a FormatModel) -> Result<Self, APIError> {
         let valid_keys = inbound
             .schema
             .iter()
@@ -53,69 +214,249 @@ impl InboundRecordData {
             .collect::<Result<HashMap<_, _>, APIError>>()?;
 
         debug!("column to regex mapping: {:#?}", column_to_regex);
+        let config = Config::get();
+        Ok(Self {
+            format: inbound,
+            valid_keys,
+            schema,
+            column_to_regex,
+            max_string_length: config.record_max_string_length,
+            max_row_bytes: config.record_max_row_bytes,
+        })
+    }
 
-        let is_error = self.data.par_iter().find_map_any(|hmap| {
-            if hmap.keys().len() != valid_keys.len() {
-                info!(
-                    "hmap key length error: input has {} keys, but expected {}",
-                    hmap.keys().len(),
-                    valid_keys.len()
-                );
-                return Some(APIError::ValidationFailure(
-                    ValidationFailureKind::MissingDictKeys,
+    /// Check the length of the strings and the size of the record, as JSON.
+    fn validate_size(&self, hmap: &DynamicHashmap) -> Result<(), APIError> {
+        let max_length = self.max_string_length;
+        let too_long = |value: &str| {
+            // every character takes at least one byte
+            value.len() as u64 > max_length && value.chars().count() as u64 > max_length
+        };
+        if max_length > 0 && hmap.values().filter_map(Value::as_str).any(too_long) {
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::StringTooLong(max_length),
+            ));
+        }
+        if self.max_row_bytes > 0 {
+            let mut counter = ByteCounter(0);
+            serde_json::to_writer(&mut counter, hmap)
+                .map_err(|err| handle_fatal!("record serialization", err, APIError::ServerError))?;
+            if counter.0 > self.max_row_bytes {
+                return Err(APIError::ValidationFailure(
+                    ValidationFailureKind::RecordTooLarge(self.max_row_bytes),
                 ));
             }
+        }
+        Ok(())
+    }
 
-            let hmap_keys_sorted = hmap.keys().sorted().collect::<HashSet<&String>>();
-            // Validate ALL dicts have the keys present in the schema, otherwise
-            // error out
-            if !valid_keys.eq(&hmap_keys_sorted) {
-                info!(
-                    "hmap key mismatch: got {:?}, expected {:?}",
-                    hmap_keys_sorted, valid_keys
-                );
-                return Some(APIError::ValidationFailure(
-                    ValidationFailureKind::MissingDictKeys,
-                ));
-            }
-            // Validate whether the values in each map have the right data type
-            if hmap.iter().any(|(key, value)| {
-                if let Some(column_kind) = schema.get(key) {
-                    match *column_kind {
-                        ColumnKind::Number => value.as_f64().is_none(),
-                        ColumnKind::String => value.as_str().is_none(),
-                        ColumnKind::Datetime => value
-                            .as_str()
-                            .map(|s| str_to_isodate(s).is_none())
-                            .unwrap_or(true),
-                    }
-                } else {
-                    true
+    /// Check record number `row`.
+    fn validate(&self, row: usize, hmap: &DynamicHashmap) -> Result<(), APIError> {
+        if hmap.keys().len() != self.valid_keys.len() {
+            info!(
+                "hmap key length error: input has {} keys, but expected {}",
+                hmap.keys().len(),
+                self.valid_keys.len()
+            );
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::MissingDictKeys,
+            ));
+        }
+
+        let hmap_keys_sorted = hmap.keys().sorted().collect::<HashSet<&String>>();
+        // Validate ALL dicts have the keys present in the schema, otherwise
+        // error out
+        if !self.valid_keys.eq(&hmap_keys_sorted) {
+            info!(
+                "hmap key mismatch: got {:?}, expected {:?}",
+                hmap_keys_sorted, self.valid_keys
+            );
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::MissingDictKeys,
+            ));
+        }
+        // Records are flat: the search layer can't query nested values.
+        if hmap
+            .values()
+            .any(|value| value.is_object() || value.is_array())
+        {
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::NestedValue,
+            ));
+        }
+        self.validate_size(hmap)?;
+        // Validate whether the values in each map have the right data type
+        if hmap.iter().any(|(key, value)| {
+            if let Some(column_kind) = self.schema.get(key) {
+                match *column_kind {
+                    ColumnKind::Number => value.as_f64().is_none(),
+                    ColumnKind::String => value.as_str().is_none(),
+                    ColumnKind::Datetime => value
+                        .as_str()
+                        .map(|s| str_to_isodate(s).is_none())
+                        .unwrap_or(true),
                 }
-            }) {
-                return Some(APIError::ValidationFailure(
-                    ValidationFailureKind::MismatchedDataType,
-                ));
+            } else {
+                true
             }
+        }) {
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::MismatchedDataType,
+            ));
+        }
 
-            // match regex, if enabled
-            if column_to_regex.iter().any(|(key, regex)| {
-                if let Some(value) = hmap.get(*key).and_then(|v| v.as_str()) {
-                    !regex.is_match(value)
-                } else {
-                    true
-                }
-            }) {
-                info!("regex match failure for map: {:#?}", hmap);
-                return Some(APIError::ValidationFailure(
-                    ValidationFailureKind::RegexMatchFailure,
-                ));
+        // match regex, if enabled
+        if self.column_to_regex.iter().any(|(key, regex)| {
+            if let Some(value) = hmap.get(*key).and_then(|v| v.as_str()) {
+                !regex.is_match(value)
+            } else {
+                true
+            }
+        }) {
+            info!("regex match failure for map: {:#?}", hmap);
+            return Err(APIError::ValidationFailure(
+                ValidationFailureKind::RegexMatchFailure,
+            ));
+        }
+
+        if let Some((index, rule)) = self
+            .format
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| !rule_holds(rule, self.schema.get(&rule.left_column), hmap))
+        {
+            info!("record {row} breaks rule {index}: {rule}");
+            return Err(APIError::RuleViolation {
+                record: row as u64,
+                rule: index as u64,
+                description: rule.to_string(),
+            });
+        }
+
+        // This dict passed the validations above
+        Ok(())
+    }
+}
+
+/// Coerce the values of record number `row`, see
+/// [`InboundRecordData::coerce_blocking`].
+fn coerce_record(
+    inbound: &FormatModel,
+    row: usize,
+    hmap: &mut DynamicHashmap,
+) -> Result<(), APIError> {
+    for column in inbound.schema.iter() {
+        let Some(value) = hmap.get_mut(&column.name) else {
+            continue;
+        };
+        if !coerce_value(value, &column.kind) {
+            info!(
+                "cannot coerce row {row}, column {:?} to {:?}",
+                column.name, column.kind
+            );
+            return Err(APIError::CastError(
+                format!(
+                    "{} (record {row}, column '{}')",
+                    preview_value(value),
+                    column.name
+                ),
+                format!("{:?}", column.kind),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Coerce `value` into `kind` in place, see [`InboundRecordData::coerce_blocking`].
+/// Returns whether the value has the right kind now.
+fn coerce_value(value: &mut Value, kind: &ColumnKind) -> bool {
+    match (kind, &*value) {
+        (ColumnKind::Number, Value::Number(_)) => true,
+        (ColumnKind::Number, Value::String(string)) => match parse_number(string.trim()) {
+            Some(number) => {
+                *value = Value::Number(number);
+                true
+            }
+            None => false,
+        },
+        (ColumnKind::String, Value::String(_)) => true,
+        (ColumnKind::String, Value::Number(number)) => {
+            *value = Value::String(number.to_string());
+            true
+        }
+        (ColumnKind::Datetime, Value::String(string)) => {
+            let trimmed = string.trim();
+            if str_to_isodate(trimmed).is_none() {
+                return false;
             }
+            if trimmed.len() != string.len() {
+                *value = Value::String(trimmed.into());
+            }
+            true
+        }
+        _ => false,
+    }
+}
 
-            // This dict passed the two validations above, keep iterating
-            None
-        });
+/// Parse a plain decimal number: `[+-]digits[.digits][(e|E)[+-]digits]`.
+/// Unlike `f64::from_str`, this doesn't accept `inf`, `NaN` or `.5`.
+fn parse_number(string: &str) -> Option<Number> {
+    let unsigned = string.strip_prefix(['+', '-']).unwrap_or(string);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let exponent_digits =
+        exponent.map(|exponent| exponent.strip_prefix(['+', '-']).unwrap_or(exponent));
+    if !is_digits(integer)
+        || !fraction.is_none_or(is_digits)
+        || !exponent_digits.is_none_or(is_digits)
+    {
+        return None;
+    }
+    if fraction.is_none() && exponent.is_none() {
+        if let Ok(integer) = string.parse::<i64>() {
+            return Some(integer.into());
+        }
+    }
+    string.parse::<f64>().ok().and_then(Number::from_f64)
+}
 
-        is_error.map_or_else(|| Ok(()), Err)
+/// Whether a record passes a rule on a column of `kind`. The record was checked
+/// against the schema already, so both sides have that kind.
+fn rule_holds(rule: &FormatRule, kind: Option<&&ColumnKind>, hmap: &DynamicHashmap) -> bool {
+    let right = match (&rule.right_column, &rule.constant) {
+        (Some(column), _) => hmap.get(column),
+        (_, constant) => constant.as_ref(),
+    };
+    let ordering = match (kind, hmap.get(&rule.left_column), right) {
+        (Some(ColumnKind::Number), Some(Value::Number(left)), Some(Value::Number(right))) => {
+            left.as_f64().partial_cmp(&right.as_f64())
+        }
+        (Some(ColumnKind::String), Some(Value::String(left)), Some(Value::String(right))) => {
+            Some(left.cmp(right))
+        }
+        (Some(ColumnKind::Datetime), Some(Value::String(left)), Some(Value::String(right))) => {
+            str_to_isodate(left)
+                .zip(str_to_isodate(right))
+                .map(|(left, right)| left.cmp(&right))
+        }
+        _ => None,
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match rule.operator {
+        RuleOperator::Lt => ordering.is_lt(),
+        RuleOperator::Lte => ordering.is_le(),
+        RuleOperator::Eq => ordering.is_eq(),
+        RuleOperator::Gte => ordering.is_ge(),
+        RuleOperator::Gt => ordering.is_gt(),
     }
 }
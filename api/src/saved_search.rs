@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+
+use actix_web::{
+    delete, get, patch, post,
+    web::{self, Json, Path, Query, ReqData},
+    HttpRequest, HttpResponse, Scope,
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    record::ModelAsQuery as RecordModelAsQuery,
+    saved_search::{self, ModelAsQuery},
+    saved_search_share::{self, ModelAsQuery as ShareModelAsQuery},
+    sea_orm::{prelude::Json as JsonValue, ColumnTrait, EntityTrait, QueryFilter},
+    user::Model as UserModel,
+    GetAllPaginated, PaginationOptions, SavedSearchMutation, SavedSearchQuery,
+    SavedSearchShareMutation, SavedSearchShareQuery, SearchQuery, UserQuery,
+};
+use entity::{
+    saved_search::{
+        Model as SavedSearchModel, SearchParameters, UpdatableModel as SavedSearchUpdatableModel,
+    },
+    saved_search_share::Model as SavedSearchShareModel,
+};
+use log::info;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::{
+    core_middleware::scope::RequireWriteScope,
+    error::{APIError, APIResponse, AsAPIResult},
+    pagination::{PaginatedResponse, Validate},
+    record::{filter_records, stream_records, FilterRecordOptions, StreamRecordOptions},
+};
+
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExecuteOptions {
+    /// Stream the results as CSV (like `/record/filter-stream`) instead of
+    /// returning a single page.
+    #[serde(default)]
+    stream: bool,
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListSavedSearchOptions {
+    /// Only list the searches other users shared with you.
+    #[serde(default)]
+    shared: bool,
+}
+
+/// Parse and validate a stored search query, whose parameters (if any) must
+/// be declared in `parameters`.
+fn parse_search_query(
+    query: &JsonValue,
+    parameters: &SearchParameters,
+) -> Result<SearchQuery, APIError> {
+    let query: SearchQuery = serde_json::from_value(query.clone())
+        .map_err(|err| APIError::InvalidQuery(err.to_string()))?;
+    query.validate()?;
+    query.validate_parameters(parameters)?;
+    Ok(query)
+}
+
+async fn find_saved_search(auth: &UserModel, id: i32) -> Result<SavedSearchModel, APIError> {
+    SavedSearchQuery::find_by_id(auth, id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("saved search with ID {}", id)))
+}
+
+/// Same as `find_saved_search`, for changes: shared searches can only be
+/// modified by their owner.
+async fn find_owned_saved_search(auth: &UserModel, id: i32) -> Result<SavedSearchModel, APIError> {
+    let saved_search = find_saved_search(auth, id).await?;
+    if saved_search.user_id != auth.id && !auth.is_superuser {
+        return Err(APIError::InsufficientPermissions);
+    }
+    Ok(saved_search)
+}
+
+#[utoipa::path(
+    get,
+    path = "/record/saved",
+    tag = "saved_search",
+    params(PaginationOptions, ModelAsQuery, ListSavedSearchOptions),
+    responses((status = 200, description = "Saved searches visible to this user", body = Vec<SavedSearch>))
+)]
+#[get("")]
+async fn get_all_saved_searches(
+    req: HttpRequest,
+    pager: Query<PaginationOptions>,
+    filter: Query<ModelAsQuery>,
+    options: Query<ListSavedSearchOptions>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    pager.validate()?;
+    let filter = filter.into_inner();
+    let pager = pager.into_inner();
+    let auth = auth.into_inner();
+    let select = match options.shared {
+        true => Some(saved_search::Entity::find().filter(saved_search::Column::UserId.ne(auth.id))),
+        false => None,
+    };
+    let items = SavedSearchQuery::get_all_filtered_for_user(&filter, &pager, auth, select).await?;
+    Ok(PaginatedResponse::from(items)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/record/saved/{id}",
+    tag = "saved_search",
+    params(("id" = i32, Path, description = "Saved search ID")),
+    responses((status = 200, description = "The saved search", body = SavedSearch))
+)]
+#[get("{id}")]
+async fn get_saved_search(id: Option<Path<i32>>, auth: ReqData<UserModel>) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let saved_search = find_saved_search(&auth, id).await?;
+    HttpResponse::Ok().json(saved_search).to_ok()
+}
+
+#[utoipa::path(
+    post,
+    path = "/record/saved",
+    tag = "saved_search",
+    request_body = SavedSearch,
+    responses((status = 201, description = "The created saved search", body = SavedSearch))
+)]
+#[post("", wrap = "RequireWriteScope")]
+async fn create_saved_search(
+    inbound: Json<SavedSearchModel>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    parse_search_query(&inbound.query, &inbound.parameters)?;
+    let mut saved_search = inbound.into_inner();
+    saved_search.user_id = auth.id;
+    let saved_search =
+        SavedSearchMutation::create(DBConfig::get_connection(), saved_search).await?;
+    info!(
+        "user {} created saved search {}",
+        saved_search.user_id, saved_search.id
+    );
+    HttpResponse::Created().json(saved_search).to_ok()
+}
+
+#[utoipa::path(
+    patch,
+    path = "/record/saved/{id}",
+    tag = "saved_search",
+    params(("id" = i32, Path, description = "Saved search ID")),
+    request_body = SavedSearchUpdate,
+    responses((status = 200, description = "The updated saved search", body = SavedSearch))
+)]
+#[patch("{id}", wrap = "RequireWriteScope")]
+async fn update_saved_search(
+    id: Option<Path<i32>>,
+    new: Json<SavedSearchUpdatableModel>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let saved_search = find_owned_saved_search(&auth, id).await?;
+    if new.query.is_some() || new.parameters.is_some() {
+        parse_search_query(
+            new.query.as_ref().unwrap_or(&saved_search.query),
+            new.parameters.as_ref().unwrap_or(&saved_search.parameters),
+        )?;
+    }
+    let saved_search =
+        SavedSearchMutation::update(DBConfig::get_connection(), saved_search, new.into_inner())
+            .await?;
+    HttpResponse::Ok().json(saved_search).to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/record/saved/{id}",
+    tag = "saved_search",
+    params(("id" = i32, Path, description = "Saved search ID")),
+    responses((status = 204, description = "The saved search was deleted"))
+)]
+#[delete("{id}", wrap = "RequireWriteScope")]
+async fn delete_saved_search(id: Option<Path<i32>>, auth: ReqData<UserModel>) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let saved_search = find_owned_saved_search(&auth, id).await?;
+    SavedSearchMutation::delete(DBConfig::get_connection(), saved_search).await?;
+    HttpResponse::NoContent().finish().to_ok()
+}
+
+/// Run a saved search. The stored query is validated again and executed
+/// against the caller's current entitlements, exactly like ad-hoc queries.
+/// Query templates take the values of their parameters as the body, e.g.
+/// `{"minAmount": 100}`.
+#[utoipa::path(
+    post,
+    path = "/record/saved/{id}/execute",
+    tag = "saved_search",
+    params(
+        ("id" = i32, Path, description = "Saved search ID"),
+        PaginationOptions,
+        RecordModelAsQuery,
+        ExecuteOptions
+    ),
+    request_body(content = Option<Object>, description = "Values of the template parameters"),
+    responses((
+        status = 200,
+        description = "A page of matching records, or all of them as CSV if `stream=true`",
+        content(("application/json" = Vec<Record>), ("text/csv" = String))
+    ))
+)]
+#[post("{id}/execute")]
+async fn execute_saved_search(
+    id: Option<Path<i32>>,
+    pager: Query<PaginationOptions>,
+    filter: Query<RecordModelAsQuery>,
+    options: Query<ExecuteOptions>,
+    values: Option<Json<HashMap<String, JsonValue>>>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let saved_search = find_saved_search(&auth, id).await?;
+    let mut query = parse_search_query(&saved_search.query, &saved_search.parameters)?;
+    let values = values.map(Json::into_inner).unwrap_or_default();
+    query.substitute_parameters(&saved_search.parameters, &values)?;
+    info!("executing saved search {}: {:#?}", saved_search.id, query);
+    let filter = filter.into_inner();
+    if options.stream {
+        return stream_records(
+            auth.into_inner(),
+            &filter,
+            query,
+            &StreamRecordOptions::default(),
+        )
+        .await;
+    }
+    pager.validate()?;
+    filter_records(
+        &auth,
+        &filter,
+        &pager,
+        query,
+        &FilterRecordOptions::default(),
+    )
+    .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/record/saved/{id}/shares",
+    tag = "saved_search",
+    params(
+        ("id" = i32, Path, description = "Saved search ID"),
+        PaginationOptions,
+        ShareModelAsQuery
+    ),
+    responses((status = 200, description = "Users the saved search is shared with", body = Vec<SavedSearchShare>))
+)]
+#[get("{id}/shares")]
+async fn get_saved_search_shares(
+    req: HttpRequest,
+    id: Option<Path<i32>>,
+    pager: Query<PaginationOptions>,
+    filter: Query<ShareModelAsQuery>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    pager.validate()?;
+    let saved_search = find_owned_saved_search(&auth, id).await?;
+    let select = saved_search_share::Entity::find()
+        .filter(saved_search_share::Column::SavedSearchId.eq(saved_search.id));
+    let pager = pager.into_inner();
+    let items = SavedSearchShareQuery::get_all_filtered_for_user(
+        &filter.into_inner(),
+        &pager,
+        auth.into_inner(),
+        Some(select),
+    )
+    .await?;
+    Ok(PaginatedResponse::from(items)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
+}
+
+/// Share a saved search with a user (owner only). Sharing never grants
+/// access to data: the search runs with the entitlements of whoever
+/// executes it.
+#[utoipa::path(
+    post,
+    path = "/record/saved/{id}/shares",
+    tag = "saved_search",
+    params(("id" = i32, Path, description = "Saved search ID")),
+    request_body = SavedSearchShare,
+    responses((status = 201, description = "The created share", body = SavedSearchShare))
+)]
+#[post("{id}/shares", wrap = "RequireWriteScope")]
+async fn create_saved_search_share(
+    id: Option<Path<i32>>,
+    inbound: Json<SavedSearchShareModel>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let saved_search = find_owned_saved_search(&auth, id).await?;
+    let mut share = inbound.into_inner();
+    UserQuery::find_by_id(share.user_id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("user with ID {}", share.user_id)))?;
+    share.saved_search_id = saved_search.id;
+    let share = SavedSearchShareMutation::create(DBConfig::get_connection(), share).await?;
+    info!(
+        "user {} shared saved search {} with user {}",
+        auth.id, share.saved_search_id, share.user_id
+    );
+    HttpResponse::Created().json(share).to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/record/saved/{id}/shares/{user_id}",
+    tag = "saved_search",
+    params(
+        ("id" = i32, Path, description = "Saved search ID"),
+        ("user_id" = Uuid, Path, description = "User the search is shared with")
+    ),
+    responses((status = 204, description = "The share was deleted"))
+)]
+#[delete("{id}/shares/{user_id}", wrap = "RequireWriteScope")]
+async fn delete_saved_search_share(
+    path: Option<Path<(i32, Uuid)>>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let (id, user_id) = path.ok_or(APIError::BadRequest)?.into_inner();
+    let saved_search = find_owned_saved_search(&auth, id).await?;
+    let share = SavedSearchShareQuery::find_by_id(saved_search.id, user_id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("share with user {}", user_id)))?;
+    SavedSearchShareMutation::delete(DBConfig::get_connection(), share).await?;
+    HttpResponse::NoContent().finish().to_ok()
+}
+
+/// Saved search routes. These are nested inside the `/record` scope.
+pub fn saved_search_scope() -> Scope {
+    web::scope("/saved")
+        .service(get_all_saved_searches)
+        .service(create_saved_search)
+        .service(execute_saved_search)
+        .service(get_saved_search_shares)
+        .service(create_saved_search_share)
+        .service(delete_saved_search_share)
+        .service(update_saved_search)
+        .service(delete_saved_search)
+        .service(get_saved_search)
+}
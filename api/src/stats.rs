@@ -0,0 +1,259 @@
+use actix_web::{get, web, web::ReqData, HttpResponse};
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    conf::{DBConfig, PoolStats},
+    tasks::Tasks,
+    user::{Model as UserModel, Role},
+    AdminQuery, GlobalStats,
+};
+use log::info;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::jwt::Token,
+    conf::APIConfig,
+    core_middleware::auth::AuthMiddleware,
+    error::{APIError, APIResponse, AsAPIResult},
+    util::verify_role,
+};
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminStats {
+    #[serde(flatten)]
+    pub database: GlobalStats,
+    /// Number of CSV streams currently open.
+    pub active_streams: u64,
+    /// Number of open /upload_session/events connections.
+    pub active_sse_connections: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "admin",
+    responses((status = 200, description = "Instance-wide statistics", body = AdminStats))
+)]
+#[get("/stats")]
+async fn get_stats(auth: ReqData<UserModel>) -> APIResponse {
+    verify_role(&auth, Role::Auditor)?;
+    let stats = AdminStats {
+        database: AdminQuery::global_stats().await?,
+        active_streams: APIConfig::get_limit_service().active_grants(),
+        active_sse_connections: APIConfig::get_sse_limit_service().active_grants(),
+    };
+    HttpResponse::Ok().json(stats).to_ok()
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostics {
+    pub uptime_seconds: u64,
+    /// Resident set size of the process, in bytes. Only available on Linux.
+    pub rss_bytes: Option<u64>,
+    pub database_pool: PoolStats,
+    pub runtime: RuntimeDiagnostics,
+    pub limits: LimitDiagnostics,
+    pub workers: WorkerDiagnostics,
+}
+
+/// The async runtime of the HTTP worker that served the request (every worker
+/// has its own).
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeDiagnostics {
+    pub alive_tasks: usize,
+    pub worker_threads: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LimitDiagnostics {
+    /// Open CSV streams, out of `maxStreamsPerUser` per user.
+    pub active_streams: u64,
+    pub max_streams_per_user: u64,
+    /// Open /upload_session/events connections, out of
+    /// `maxSseConnectionsPerUser` per user.
+    pub active_sse_connections: u64,
+    pub max_sse_connections_per_user: u64,
+    /// Client addresses with recent failed logins.
+    pub login_throttled_clients: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerDiagnostics {
+    pub http_workers: u8,
+    pub csv_stream_workers: u64,
+    pub csv_transform_workers: u64,
+    pub csv_worker_queue_depth: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/diagnostics",
+    tag = "admin",
+    responses((status = 200, description = "Connection pool and runtime diagnostics", body = Diagnostics))
+)]
+#[get("/diagnostics")]
+async fn get_diagnostics(auth: ReqData<UserModel>) -> APIResponse {
+    if !auth.is_superuser {
+        info!("Denied access to diagnostics, user id: {}", auth.id);
+        return APIError::AdminOnlyResource.into();
+    }
+    let config = Config::get();
+    let runtime = tokio::runtime::Handle::current().metrics();
+    let diagnostics = Diagnostics {
+        uptime_seconds: APIConfig::get_uptime().as_secs(),
+        rss_bytes: rss_bytes(),
+        database_pool: DBConfig::pool_stats(),
+        runtime: RuntimeDiagnostics {
+            alive_tasks: runtime.num_alive_tasks(),
+            worker_threads: runtime.num_workers(),
+        },
+        limits: LimitDiagnostics {
+            active_streams: APIConfig::get_limit_service().active_grants(),
+            max_streams_per_user: config.db_max_streams_per_user,
+            active_sse_connections: APIConfig::get_sse_limit_service().active_grants(),
+            max_sse_connections_per_user: config.max_sse_connections_per_user,
+            login_throttled_clients: APIConfig::get_login_limiter().tracked_keys(),
+        },
+        workers: WorkerDiagnostics {
+            http_workers: config.workers,
+            csv_stream_workers: config.db_csv_stream_workers,
+            csv_transform_workers: config.db_csv_transform_workers,
+            csv_worker_queue_depth: config.db_csv_worker_queue_depth,
+        },
+    };
+    HttpResponse::Ok().json(diagnostics).to_ok()
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminConfig {
+    /// The effective configuration, keyed by environment variable. Secrets
+    /// are replaced by `<SECRET>` (or `null` if unset).
+    #[schema(value_type = Object)]
+    pub config: Value,
+    pub derived: DerivedConfig,
+    pub self_tests: ConfigSelfTests,
+}
+
+/// Settings computed from (or combining) several configuration keys.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivedConfig {
+    pub pagination: PaginationConfig,
+    pub limits: LimiterConfig,
+    pub prune: PruneSchedule,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PaginationConfig {
+    /// `perPage` used when the request doesn't set one.
+    pub default_per_page: u64,
+    pub max_per_page: u64,
+    /// Max `page * perPage` without `count=true`, 0 means no limit.
+    pub max_offset: u64,
+    /// Whether `count` defaults to true.
+    pub count_by_default: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LimiterConfig {
+    pub max_streams_per_user: u64,
+    pub max_sse_connections_per_user: u64,
+    pub login_max_failed_attempts: u32,
+    pub login_attempt_window_seconds: u64,
+    pub login_max_tracked_clients: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneSchedule {
+    pub enabled: bool,
+    pub interval_seconds: u64,
+    pub timeout_seconds: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigSelfTests {
+    /// A token can be signed and validated with the loaded keys.
+    pub jwt_keys_loaded: bool,
+    pub database_reachable: bool,
+    /// Always false if ENABLE_PRUNE_JOB is off.
+    pub prune_task_running: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/config",
+    tag = "admin",
+    responses((status = 200, description = "Effective configuration, with secrets masked", body = AdminConfig))
+)]
+#[get("/config")]
+async fn get_config(auth: ReqData<UserModel>) -> APIResponse {
+    if !auth.is_superuser {
+        info!("Denied access to config, user id: {}", auth.id);
+        return APIError::AdminOnlyResource.into();
+    }
+    let config = Config::get();
+    let admin_config = AdminConfig {
+        config: config.to_masked_json(),
+        derived: DerivedConfig {
+            pagination: PaginationConfig {
+                default_per_page: config.default_pagination_size,
+                max_per_page: config.max_pagination_size,
+                max_offset: config.max_pagination_offset,
+                count_by_default: config.return_query_count,
+            },
+            limits: LimiterConfig {
+                max_streams_per_user: config.db_max_streams_per_user,
+                max_sse_connections_per_user: config.max_sse_connections_per_user,
+                login_max_failed_attempts: config.login_max_failed_attempts,
+                login_attempt_window_seconds: config.login_attempt_window_seconds,
+                login_max_tracked_clients: config.login_max_tracked_clients,
+            },
+            prune: PruneSchedule {
+                enabled: config.enable_prune_job,
+                interval_seconds: config.prune_job_run_interval_seconds,
+                timeout_seconds: config.prune_job_timeout_seconds,
+            },
+        },
+        self_tests: ConfigSelfTests {
+            jwt_keys_loaded: Token::self_test(&auth),
+            database_reachable: DBConfig::get_connection().ping().await.is_ok(),
+            prune_task_running: Tasks::is_prune_task_running(),
+        },
+    };
+    HttpResponse::Ok().json(admin_config).to_ok()
+}
+
+/// Read the resident set size from /proc (`VmRSS`, in kB).
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+pub fn init_stats_routes(cfg: &mut web::ServiceConfig) {
+    let scope = web::scope("/admin")
+        .wrap(AuthMiddleware)
+        .service(get_stats)
+        .service(get_diagnostics)
+        .service(get_config);
+
+    cfg.service(scope);
+}
@@ -1,19 +1,104 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
 use crate::{
-    core_middleware::auth::AuthMiddleware,
+    common::handle_fatal,
+    conf::APIConfig,
+    core_middleware::{
+        auth::AuthMiddleware, logging::current_request_id, scope::RequireWriteScope,
+    },
     error::{APIError, APIResponse, AsAPIResult},
-    pagination::{PaginatedResponse, Validate},
-    util::verify_admin,
+    pagination::{validated_pager, PaginatedResponse},
+    util::{append_rate_limit_headers, verify_role},
 };
 use actix_web::{
-    delete, get, post,
+    delete, get, post, route,
     web::{self, Path, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use async_stream::stream;
+use central_repository_config::inner::Config;
 use central_repository_dao::{
-    upload_session::ModelAsQuery, user::Model as UserModel, GetAllPaginated, PaginationOptions,
-    UploadSessionMutation, UploadSessionQuery,
+    conf::DBConfig,
+    upload_session::{ModelAsQuery, OutcomeKind},
+    user::{Model as UserModel, Role},
+    webhook::WebhookEvent,
+    ExportFormat, GetAllPaginated, PaginationOptions, ParallelStreamConfig, RecordQuery,
+    SearchQuery, UploadSessionFormatFilter, UploadSessionMutation, UploadSessionQuery, UserQuery,
+    WebhookDispatcher,
 };
+use entity::upload_session::Model as UploadSessionModel;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::IntoParams;
+
+// Max number of upload sessions buffered for slow SSE clients.
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+lazy_static! {
+    static ref UPLOAD_SESSION_EVENTS: broadcast::Sender<UploadSessionModel> =
+        broadcast::channel(EVENT_CHANNEL_CAPACITY).0;
+    // Clients waiting for the outcome of an in-progress upload session, by
+    // session id. See OutcomeWaiter.
+    static ref OUTCOME_WAITERS: Mutex<HashMap<i32, broadcast::Sender<UploadSessionModel>>> =
+        Default::default();
+}
+
+/// Notify all the SSE clients about a new upload session, and the clients
+/// waiting for its outcome.
+pub fn publish_upload_session(upload_session: &UploadSessionModel) {
+    // this only fails if there are no clients listening.
+    let _ = UPLOAD_SESSION_EVENTS.send(upload_session.clone());
+    if upload_session.outcome == OutcomeKind::InProgress {
+        return;
+    }
+    if let Some(waiters) = outcome_waiters().remove(&upload_session.id) {
+        let _ = waiters.send(upload_session.clone());
+    }
+}
+
+fn outcome_waiters() -> MutexGuard<'static, HashMap<i32, broadcast::Sender<UploadSessionModel>>> {
+    OUTCOME_WAITERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+/// A subscription to the outcome of an upload session. Subscribe before
+/// reading the session, so an outcome published in between isn't missed.
+/// The session's entry in OUTCOME_WAITERS goes away with its last waiter.
+struct OutcomeWaiter {
+    id: i32,
+    receiver: broadcast::Receiver<UploadSessionModel>,
+}
+
+impl OutcomeWaiter {
+    fn subscribe(id: i32) -> Self {
+        let receiver = outcome_waiters()
+            .entry(id)
+            .or_insert_with(|| broadcast::channel(1).0)
+            .subscribe();
+        Self { id, receiver }
+    }
+}
+
+impl Drop for OutcomeWaiter {
+    fn drop(&mut self) {
+        let mut waiters = outcome_waiters();
+        // our own receiver is still alive at this point.
+        if waiters
+            .get(&self.id)
+            .is_some_and(|sender| sender.receiver_count() <= 1)
+        {
+            waiters.remove(&self.id);
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct LoginCredentials {
@@ -21,32 +106,310 @@ pub struct LoginCredentials {
     pub password: String,
 }
 
-#[get("")]
+#[utoipa::path(
+    get,
+    path = "/upload_session",
+    tag = "upload_session",
+    params(PaginationOptions, ModelAsQuery, UploadSessionFormatFilter),
+    responses((status = 200, description = "Upload sessions visible to this user", body = Vec<UploadSession>))
+)]
+#[route("", method = "GET", method = "HEAD")]
 async fn get_all_upload_sessions(
+    req: HttpRequest,
     pager: Query<PaginationOptions>,
     filter: Query<ModelAsQuery>,
+    format_filter: Query<UploadSessionFormatFilter>,
     auth: ReqData<UserModel>,
 ) -> APIResponse {
-    pager.validate()?;
+    let pager = validated_pager(&req, pager)?;
     let auth = auth.into_inner();
     let filter = filter.into_inner();
-    let pager = pager.into_inner();
-    let items = UploadSessionQuery::get_all_filtered_for_user(&filter, &pager, auth, None).await?;
-    Ok(PaginatedResponse::from(items).into())
+    let select = UploadSessionQuery::select_by_format(&format_filter);
+    let items =
+        UploadSessionQuery::get_all_filtered_for_user(&filter, &pager, auth, select).await?;
+    Ok(PaginatedResponse::from(items)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
 }
 
-#[delete("{id}")]
+/// Get the IDs of all the formats `user` can read.
+async fn readable_format_ids(user: &UserModel) -> Result<HashSet<i32>, APIError> {
+    let prepared_search = SearchQuery::default()
+        .get_readable_formats_for_user(user)
+        .await?;
+    Ok(prepared_search
+        .get_readable_format_ids()
+        .into_iter()
+        .collect())
+}
+
+enum SessionEvent {
+    Created(UploadSessionModel),
+    Lagged(u64),
+    Heartbeat,
+    Closed,
+}
+
+/// Stream new upload sessions (for readable formats only) as server-sent events.
+#[utoipa::path(
+    get,
+    path = "/upload_session/events",
+    tag = "upload_session",
+    responses(
+        (
+            status = 200,
+            description = "Server-sent events stream with an `uploadSession` event per new upload session",
+            content_type = "text/event-stream",
+            body = String
+        ),
+        (status = 429, description = "Too many concurrent streams", body = OutboundAPIError)
+    )
+)]
+#[get("/events")]
+async fn upload_session_events(auth: ReqData<UserModel>) -> APIResponse {
+    let mut auth = auth.into_inner();
+    let mut limit_grant = None;
+    if !auth.is_superuser {
+        limit_grant = Some(APIConfig::get_sse_limit_service().new_grant_for_key(&auth.username)?);
+    }
+    let mut response = HttpResponse::Ok();
+    append_rate_limit_headers(&mut response, limit_grant.as_ref());
+    let mut readable_formats = readable_format_ids(&auth).await?;
+    let mut receiver = UPLOAD_SESSION_EVENTS.subscribe();
+    let mut heartbeat =
+        tokio::time::interval(Duration::from_secs(Config::get().sse_heartbeat_seconds));
+    let current_span = tracing::Span::current();
+
+    let stream = stream!({
+        let _guard = current_span.enter();
+        // Capture user grant for this streaming operation
+        let _limit_grant = limit_grant;
+        info!("SSE: client connected");
+
+        loop {
+            let event = tokio::select! {
+                received = receiver.recv() => match received {
+                    Ok(upload_session) => SessionEvent::Created(upload_session),
+                    Err(RecvError::Lagged(skipped)) => SessionEvent::Lagged(skipped),
+                    Err(RecvError::Closed) => SessionEvent::Closed,
+                },
+                _ = heartbeat.tick() => SessionEvent::Heartbeat,
+            };
+            match event {
+                SessionEvent::Created(upload_session) => {
+                    if !auth.is_superuser && !readable_formats.contains(&upload_session.format_id) {
+                        continue;
+                    }
+                    let data = match serde_json::to_string(&upload_session) {
+                        Ok(data) => data,
+                        Err(err) => handle_fatal!("SSE serialization error", err, continue),
+                    };
+                    yield Ok::<_, APIError>(web::Bytes::from(format!(
+                        "id: {}\nevent: uploadSession\ndata: {data}\n\n",
+                        upload_session.id
+                    )));
+                }
+                SessionEvent::Lagged(skipped) => {
+                    warn!("SSE: client is too slow, skipped {skipped} upload sessions");
+                    yield Ok(web::Bytes::from(format!(": skipped {skipped} events\n\n")));
+                }
+                SessionEvent::Heartbeat => {
+                    // make sure this user is still allowed in, and pick up
+                    // entitlement changes.
+                    auth = match UserQuery::find_by_id(auth.id).await {
+                        Ok(Some(user)) if user.active => user,
+                        Ok(_) => {
+                            info!("SSE: user was deactivated or deleted, closing stream");
+                            break;
+                        }
+                        Err(err) => handle_fatal!("SSE user check failed", err, break),
+                    };
+                    readable_formats = match readable_format_ids(&auth).await {
+                        Ok(readable_formats) => readable_formats,
+                        Err(err) => handle_fatal!("SSE entitlement check failed", err, break),
+                    };
+                    yield Ok(web::Bytes::from_static(b": heartbeat\n\n"));
+                }
+                SessionEvent::Closed => break,
+            }
+        }
+        info!("SSE: stream closed");
+    });
+
+    response
+        .append_header(("Content-Type", "text/event-stream"))
+        .append_header(("Cache-Control", "no-cache"))
+        .streaming(stream)
+        .to_ok()
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct GetUploadSessionOptions {
+    /// If the session is still in progress, wait until it succeeds or fails.
+    #[serde(default)]
+    wait_for_outcome: bool,
+    /// Seconds to wait for with `waitForOutcome=true` (default and maximum:
+    /// `MAX_OUTCOME_WAIT_SECONDS`).
+    timeout: Option<u64>,
+}
+
+/// Get a single upload session. With `waitForOutcome=true`, in-progress
+/// sessions are only returned once their upload is done, or once `timeout`
+/// runs out (the session is still in progress then), whichever comes first.
+#[utoipa::path(
+    get,
+    path = "/upload_session/{id}",
+    tag = "upload_session",
+    params(("id" = i32, Path, description = "Upload session ID"), GetUploadSessionOptions),
+    responses(
+        (
+            status = 200,
+            description = "The upload session",
+            body = UploadSession,
+            headers(("repository-wait-timed-out" = bool, description = "Set if the wait timed out before the upload was done"))
+        ),
+        (status = 404, description = "The upload session doesn't exist", body = OutboundAPIError)
+    )
+)]
+#[get("{id}")]
+async fn get_upload_session(
+    auth: ReqData<UserModel>,
+    id: Option<Path<i32>>,
+    options: Query<GetUploadSessionOptions>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let waiter = options
+        .wait_for_outcome
+        .then(|| OutcomeWaiter::subscribe(id));
+    let upload_session = UploadSessionQuery::find_visible(&auth, id).await?;
+    let mut waiter = match waiter {
+        Some(waiter) if upload_session.outcome == OutcomeKind::InProgress => waiter,
+        _ => return HttpResponse::Ok().json(upload_session).to_ok(),
+    };
+    let max_wait = Config::get().max_outcome_wait_seconds;
+    let wait = Duration::from_secs(options.timeout.unwrap_or(max_wait).min(max_wait));
+    info!("waiting up to {wait:?} for the outcome of upload session {id}");
+    let mut response = HttpResponse::Ok();
+    let received = tokio::time::timeout(wait, waiter.receiver.recv()).await;
+    drop(waiter);
+    let upload_session = match received {
+        Ok(Ok(upload_session)) => upload_session,
+        received => {
+            if received.is_err() {
+                response.insert_header(("repository-wait-timed-out", "true"));
+            }
+            // sessions failed by the stuck session task aren't published,
+            // and the session may have been deleted in the meantime.
+            UploadSessionQuery::find_visible(&auth, id).await?
+        }
+    };
+    response.json(upload_session).to_ok()
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct ExportOptions {
+    /// Output format (default: csv).
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Stream all the records of an upload session. CSV columns follow the order
+/// of the format's schema.
+#[utoipa::path(
+    get,
+    path = "/upload_session/{id}/export",
+    tag = "upload_session",
+    params(("id" = i32, Path, description = "Upload session ID"), ExportOptions),
+    responses(
+        (status = 200, description = "The records of this upload session", content_type = "text/csv", body = String),
+        (status = 200, description = "The records of this upload session, with `format=ndjson`", content_type = "application/x-ndjson", body = String),
+        (status = 404, description = "The upload session doesn't exist", body = OutboundAPIError),
+        (status = 429, description = "Too many concurrent streams", body = OutboundAPIError)
+    )
+)]
+#[get("{id}/export")]
+async fn export(
+    auth: ReqData<UserModel>,
+    id: Option<Path<i32>>,
+    options: Query<ExportOptions>,
+) -> APIResponse {
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let auth = auth.into_inner();
+    let export_format = options.format;
+    let (upload_session, format) = UploadSessionQuery::find_readable(&auth, id).await?;
+
+    let mut limit_grant = None;
+    if !auth.is_superuser {
+        limit_grant = Some(APIConfig::get_limit_service().new_grant_for_key(&auth.username)?);
+    }
+    let mut response = HttpResponse::Ok();
+    append_rate_limit_headers(&mut response, limit_grant.as_ref());
+
+    let stream = RecordQuery::upload_session_stream(
+        &upload_session,
+        &format,
+        export_format,
+        ParallelStreamConfig::default(),
+        limit_grant,
+        current_request_id(),
+    )
+    .await?
+    .map(|it| Ok::<_, APIError>(web::Bytes::from(it)));
+
+    response
+        .append_header(("Content-Type", export_format.content_type()))
+        .append_header((
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"upload-session-{id}.{}\"",
+                export_format.extension()
+            ),
+        ))
+        .streaming(stream)
+        .to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/upload_session/{id}",
+    tag = "upload_session",
+    params(("id" = i32, Path, description = "Upload session ID")),
+    responses((status = 200, description = "The upload session and its records were deleted", body = UploadSessionDeleteResult))
+)]
+#[delete("{id}", wrap = "RequireWriteScope")]
 async fn delete(auth: ReqData<UserModel>, id: Option<Path<i32>>) -> APIResponse {
     let id = *id.ok_or(APIError::BadRequest)?;
     let auth = auth.into_inner();
-    UploadSessionMutation::delete(auth, id).await?;
-    HttpResponse::NoContent().finish().to_ok()
+    let result = UploadSessionMutation::delete(DBConfig::get_connection(), auth, id).await?;
+    HttpResponse::Ok().json(result).to_ok()
 }
 
-#[post("/prune")]
+#[utoipa::path(
+    post,
+    path = "/upload_session/prune",
+    tag = "upload_session",
+    responses((status = 200, description = "What was pruned, per format", body = Vec<UploadSessionPruneResult>))
+)]
+#[post(
+    "/upload_session/prune",
+    wrap = "RequireWriteScope",
+    wrap = "AuthMiddleware"
+)]
 async fn prune(auth: ReqData<UserModel>) -> APIResponse {
-    verify_admin(&auth)?;
-    let result = UploadSessionMutation::prune_old_items().await?;
+    verify_role(&auth, Role::FormatManager)?;
+    let result = UploadSessionMutation::prune_old_items(DBConfig::get_connection()).await?;
+    for prune_result in result.iter() {
+        WebhookDispatcher::notify(
+            WebhookEvent::Prune,
+            Some(prune_result.format_id),
+            prune_result,
+        );
+    }
     HttpResponse::Ok().json(result).to_ok()
 }
 
@@ -54,7 +417,16 @@ pub fn init_upload_session_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("/upload_session")
         .wrap(AuthMiddleware)
         .service(get_all_upload_sessions)
-        .service(prune)
+        .service(upload_session_events)
+        .service(export)
+        // after the other routes, so `{id}` doesn't shadow them.
+        .service(get_upload_session)
         .service(delete);
     cfg.service(scope);
 }
+
+/// The prune trigger lives outside of the `/upload_session` scope so it can
+/// be served by the admin listener. Register it before that scope.
+pub fn init_upload_session_prune_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(prune);
+}
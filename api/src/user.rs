@@ -1,40 +1,138 @@
 use crate::{
     api_key::{create_api_key, delete_api_key, get_all_api_keys, update_api_key},
     auth::hashing::UserPassword,
-    auth::jwt::Token,
-    core_middleware::auth::AuthMiddleware,
+    auth::jwt::{Token, TokenScope},
+    auth::totp::{self, TotpSecret},
+    conf::APIConfig,
+    core_middleware::{auth::AuthMiddleware, scope::RequireWriteScope},
     error::{APIError, APIResponse, AsAPIResult},
     model_prepare::DBPrepare,
-    pagination::{PaginatedResponse, Validate},
-    util::verify_admin,
+    pagination::{validated_pager, PaginatedResponse},
+    util::{client_ip, verify_can_manage, verify_can_see_user, verify_role},
 };
 use actix_web::{
-    delete, get, patch, post,
+    delete, get, patch, post, route,
     web::{self, Json, Path, Query, ReqData},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
 use central_repository_config::inner::Config;
 use central_repository_dao::{
     conf::DBConfig,
     sea_orm::{ModelTrait, TryIntoModel},
-    user::{Model as UserModel, ModelAsQuery, UpdatableModel},
-    GetAllPaginated, PaginationOptions, UserMutation, UserQuery,
+    user::{Model as UserModel, ModelAsQuery, Role, UpdatableModel},
+    GetAllPaginated, PaginationOptions, SuperuserBootstrap, UploadSessionQuery, UserMutation,
+    UserQuery,
 };
-use log::info;
+use futures::StreamExt;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::{collections::HashSet, error::Error};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize)]
+// Max number of users `POST /user/bulk` creates at once.
+const MAX_BULK_USERS: usize = 1000;
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct LoginCredentials {
     pub username: String,
     pub password: String,
+    /// TOTP (or recovery) code, required if the user has 2FA enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp_code: Option<String>,
+    /// Limit the token to these scopes, e.g. `["read"]` for a token that can't
+    /// change anything. Without scopes, the token can do anything the user can.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<Vec<TokenScope>>,
 }
 
-#[post("")]
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollment {
+    /// Base32-encoded TOTP secret.
+    secret: String,
+    /// `otpauth://` URI with the secret, usually shown as a QR code.
+    provisioning_uri: String,
+    /// One-time codes that can be used instead of a TOTP code. They can't be
+    /// retrieved again.
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TotpConfirmation {
+    /// The current TOTP code.
+    code: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTransfer {
+    /// The user that gets the upload sessions (and saved searches).
+    to: Uuid,
+    /// Also transfer the user's saved searches.
+    #[serde(default)]
+    saved_searches: bool,
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeleteUserOptions {
+    /// Delete the user even if it still owns upload sessions, deleting them
+    /// (and their records) too.
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize, Default, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+struct BulkUserOptions {
+    /// Create every user or none of them (default: create every valid user).
+    #[serde(default)]
+    all_or_nothing: bool,
+}
+
+/// A user that `POST /user/bulk` couldn't create.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkUserFailure {
+    /// Position of the user in the request.
+    index: usize,
+    username: String,
+    reason: String,
+}
+
+impl BulkUserFailure {
+    fn new(index: usize, username: String, reason: impl ToString) -> Self {
+        Self {
+            index,
+            username,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkUserResult {
+    #[schema(value_type = Vec<User>)]
+    created: Vec<UserModel>,
+    failed: Vec<BulkUserFailure>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/user",
+    tag = "user",
+    request_body = User,
+    responses((status = 201, description = "The created user", body = User))
+)]
+#[post("", wrap = "RequireWriteScope")]
 async fn create_user(user: Json<UserModel>, auth: ReqData<UserModel>) -> APIResponse {
-    verify_admin(&auth)?;
+    verify_role(&auth, Role::Admin)?;
     let mut user = user.into_inner();
+    verify_can_manage(&auth, &user)?;
     let exists = UserQuery::find_by_username(&user.username).await?;
     if exists.is_some() {
         info!("Username '{}' already exists.", user.username);
@@ -43,27 +141,183 @@ async fn create_user(user: Json<UserModel>, auth: ReqData<UserModel>) -> APIResp
     // prepare this user for insert... (i.e. set password, etc).
     user.prepare().await?;
     HttpResponse::Created()
-        .json(UserMutation::create(user).await?)
+        .json(UserMutation::create(DBConfig::get_connection(), user).await?)
         .to_ok()
 }
 
-#[get("")]
+#[utoipa::path(
+    post,
+    path = "/user/bulk",
+    tag = "user",
+    params(BulkUserOptions),
+    request_body = Vec<User>,
+    responses(
+        (status = 201, description = "Every user was created", body = BulkUserResult),
+        (status = 200, description = "Some users couldn't be created", body = BulkUserResult),
+    )
+)]
+#[post("bulk", wrap = "RequireWriteScope")]
+async fn create_users_bulk(
+    users: Json<Vec<UserModel>>,
+    options: Query<BulkUserOptions>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    if !auth.is_superuser {
+        info!("Denied access to bulk user creation, user id: {}", auth.id);
+        return APIError::AdminOnlyResource.into();
+    }
+    let users = users.into_inner();
+    if users.is_empty() || users.len() > MAX_BULK_USERS {
+        return APIError::InvalidOperation(format!(
+            "between 1 and {MAX_BULK_USERS} users can be created at once ({} given)",
+            users.len()
+        ))
+        .into();
+    }
+    let mut failed = vec![];
+    let usernames: Vec<String> = users.iter().map(|user| user.username.clone()).collect();
+    let existing: HashSet<String> = UserQuery::find_existing_usernames(&usernames)
+        .await?
+        .into_iter()
+        .collect();
+    let mut seen = HashSet::new();
+    let mut candidates = vec![];
+    for (index, user) in users.into_iter().enumerate() {
+        let username = user.username.to_lowercase();
+        let reason = if existing.contains(&username) {
+            "username already exists"
+        } else if !seen.insert(username) {
+            "username appears more than once in this request"
+        } else {
+            candidates.push((index, user));
+            continue;
+        };
+        failed.push(BulkUserFailure::new(index, user.username, reason));
+    }
+    if options.all_or_nothing && !failed.is_empty() {
+        // nothing will be created, don't bother hashing the passwords.
+        candidates.clear();
+    }
+
+    // hash the passwords on the blocking pool, a few at a time: each hash
+    // keeps a core busy.
+    let concurrency = std::thread::available_parallelism().map_or(1, |n| (n.get() / 2).max(1));
+    let mut prepared = futures::stream::iter(candidates)
+        .map(|(index, mut user)| async move {
+            let result = user.prepare().await;
+            (index, user, result)
+        })
+        .buffered(concurrency);
+    let mut valid = vec![];
+    let mut valid_names = vec![];
+    while let Some((index, user, result)) = prepared.next().await {
+        match result {
+            Ok(()) => {
+                valid_names.push((index, user.username.clone()));
+                valid.push(user);
+            }
+            Err(err) => {
+                failed.push(BulkUserFailure::new(index, user.username, err));
+                if options.all_or_nothing {
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut created = vec![];
+    let rejected = options.all_or_nothing && !failed.is_empty();
+    if !rejected && !valid.is_empty() {
+        let results =
+            UserMutation::create_many(DBConfig::get_connection(), valid, options.all_or_nothing)
+                .await?;
+        for ((index, username), result) in valid_names.into_iter().zip(results) {
+            match result {
+                Ok(user) => created.push(user),
+                Err(err) => failed.push(BulkUserFailure::new(index, username, APIError::from(err))),
+            }
+        }
+        if options.all_or_nothing && !failed.is_empty() {
+            // the transaction was rolled back.
+            created.clear();
+        }
+    }
+    failed.sort_unstable_by_key(|failure| failure.index);
+    info!(
+        "Bulk user creation by user ID {}: {} created, {} failed.",
+        auth.id,
+        created.len(),
+        failed.len()
+    );
+    let result = BulkUserResult { created, failed };
+    match result.failed.is_empty() {
+        true => HttpResponse::Created().json(result),
+        false => HttpResponse::Ok().json(result),
+    }
+    .to_ok()
+}
+
+#[utoipa::path(
+    get,
+    path = "/user",
+    tag = "user",
+    params(PaginationOptions, ModelAsQuery),
+    responses((status = 200, description = "All users", body = Vec<User>))
+)]
+#[route("", method = "GET", method = "HEAD")]
 async fn get_all_users(
+    req: HttpRequest,
     pager: Query<PaginationOptions>,
     filter: Query<ModelAsQuery>,
     auth: ReqData<UserModel>,
 ) -> APIResponse {
-    pager.validate()?;
-    verify_admin(&auth)?;
+    let pager = validated_pager(&req, pager)?;
+    verify_role(&auth, Role::Auditor)?;
     let filter = filter.into_inner();
-    let pager = pager.into_inner();
     let users = UserQuery::get_all(&filter, &pager, None).await?;
-    Ok(PaginatedResponse::from(users).into())
+    Ok(PaginatedResponse::from(users)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "auth",
+    request_body = LoginCredentials,
+    responses((status = 200, description = "A new token for this user", body = TokenResponse)),
+    security(())
+)]
 #[post("")]
-async fn login(inbound: Json<LoginCredentials>) -> APIResponse {
-    let inbound = inbound.into_inner();
+async fn login(req: HttpRequest, inbound: Json<LoginCredentials>) -> APIResponse {
+    let limiter = APIConfig::get_login_limiter();
+    let client = client_ip(&req).map_or_else(|| "unknown".into(), |ip| ip.to_string());
+    let attempt = match limiter.attempt(&client) {
+        Ok(attempt) => attempt,
+        Err(retry_after) => {
+            info!("throttling login attempts from {client}");
+            // round up, so clients don't retry too early.
+            let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            return APIError::TooManyLoginAttempts(retry_after).into();
+        }
+    };
+    let result = authenticate(inbound.into_inner()).await;
+    if let Err(APIError::InvalidCredentials | APIError::InvalidTotpCode) = result {
+        attempt.failed();
+    }
+    result
+}
+
+async fn authenticate(inbound: LoginCredentials) -> APIResponse {
+    let mut scopes = inbound.scopes.clone();
+    if let Some(scopes) = scopes.as_mut() {
+        if scopes.is_empty() {
+            return APIError::InvalidOperation("`scopes` can't be empty".into()).into();
+        }
+        scopes.sort_unstable();
+        scopes.dedup();
+    }
     let user = UserQuery::find_by_username(&inbound.username)
         .await?
         .ok_or(APIError::InvalidCredentials)?
@@ -73,39 +327,86 @@ async fn login(inbound: Json<LoginCredentials>) -> APIResponse {
         return APIError::InactiveUser.into();
     }
     let current_span = tracing::Span::current();
+    let password_hash = user.password.clone();
     // don't block the main thread with crypto operations.
-    Ok(web::block(move || {
+    web::block(move || {
         let _guard = current_span.enter();
-        UserPassword::verify_password(&inbound.password, &user.password)
-            .and_then(|_| Token::build_from_user(user))
+        UserPassword::verify_password(&inbound.password, &password_hash)
     })
-    .await??
-    .into())
+    .await??;
+    if user.totp_enabled {
+        totp::verify_login_code(&user, inbound.totp_code.as_deref()).await?;
+    }
+    Ok(web::block(move || Token::build_from_user(user, scopes))
+        .await??
+        .into())
 }
 
+#[utoipa::path(
+    get,
+    path = "/user/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses((status = 200, description = "The user", body = User))
+)]
 #[get("{id}")]
 async fn get_user(id: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
     let id = id.into_inner();
-    if !auth.is_superuser && auth.id != id {
-        // don't allow non-superusers to view other users
-        return Err(APIError::AdminOnlyResource);
-    }
+    verify_can_see_user(&auth, id)?;
     let user = UserQuery::find_by_id(id)
         .await?
         .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?;
     HttpResponse::Ok().json(user).to_ok()
 }
 
-#[delete("{id}")]
-async fn delete_user(id: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
-    verify_admin(&auth)?;
+#[utoipa::path(
+    get,
+    path = "/user/{id}/usage",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses((status = 200, description = "What the user uploaded, in total and per format", body = UserUsage))
+)]
+#[get("{id}/usage")]
+async fn get_user_usage(id: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
     let id = id.into_inner();
-    if auth.id == id {
-        return APIError::InvalidOperation("You can't delete yourself".into()).into();
-    }
+    verify_can_see_user(&auth, id)?;
+    let user = match auth.id == id {
+        true => auth.into_inner(),
+        false => UserQuery::find_by_id(id)
+            .await?
+            .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?,
+    };
+    HttpResponse::Ok()
+        .json(UserQuery::usage(&user).await?)
+        .to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/user/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "User ID"), DeleteUserOptions),
+    responses(
+        (status = 204, description = "The user was deleted"),
+        (status = 409, description = "The user still owns upload sessions and `force` isn't set")
+    )
+)]
+#[delete("{id}", wrap = "RequireWriteScope")]
+async fn delete_user(
+    id: Path<Uuid>,
+    options: Query<DeleteUserOptions>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = id.into_inner();
+    verify_can_see_user(&auth, id)?;
     let user = UserQuery::find_by_id(id)
         .await?
         .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?;
+    verify_role(&auth, Role::Admin)?;
+    if auth.id == id {
+        return APIError::InvalidOperation("You can't delete yourself".into()).into();
+    }
+    verify_can_manage(&auth, &user)?;
     if Config::get().protect_superuser && user.is_superuser {
         info!(
             "Prevented user deletionx: user ID {} tried to delete a superuser (ID: {})",
@@ -113,6 +414,15 @@ async fn delete_user(id: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
         );
         return APIError::ConflictingOperation("can't delete a superuser".into()).into();
     }
+    // the upload sessions of the user (and their records) go away with it.
+    let upload_sessions = UploadSessionQuery::count_for_user(id).await?;
+    if upload_sessions > 0 && !options.force {
+        return APIError::ConflictingOperation(format!(
+            "user still owns {upload_sessions} upload sessions, transfer them with \
+             POST /user/{id}/transfer first or delete them along with the user with force=true"
+        ))
+        .into();
+    }
     info!(
         "Preparing to delete user ID {} (requested by user ID {}).",
         id, auth.id
@@ -121,38 +431,157 @@ async fn delete_user(id: Path<Uuid>, auth: ReqData<UserModel>) -> APIResponse {
     HttpResponse::NoContent().finish().to_ok()
 }
 
+#[utoipa::path(
+    post,
+    path = "/user/{id}/transfer",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "User ID")),
+    request_body = UserTransfer,
+    responses((status = 200, description = "What was transferred", body = UserTransferResult))
+)]
+#[post("{id}/transfer", wrap = "RequireWriteScope")]
+async fn transfer_user(
+    id: Path<Uuid>,
+    body: Json<UserTransfer>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    let id = id.into_inner();
+    verify_can_see_user(&auth, id)?;
+    UserQuery::find_by_id(id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?;
+    if !auth.is_superuser {
+        info!("Denied access to user transfer, user id: {}", auth.id);
+        return APIError::AdminOnlyResource.into();
+    }
+    if body.to == id {
+        return APIError::InvalidOperation("can't transfer a user to itself".into()).into();
+    }
+    let to = UserQuery::find_by_id(body.to)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("user with ID {}", body.to)))?;
+    if !to.active {
+        return APIError::InvalidOperation(format!("user {} is inactive", to.id)).into();
+    }
+    info!(
+        "Transferring user ID {id} to user ID {} (requested by user ID {}).",
+        to.id, auth.id
+    );
+    let result =
+        UserMutation::transfer(DBConfig::get_connection(), id, to.id, body.saved_searches).await?;
+    HttpResponse::Ok().json(result).to_ok()
+}
+
+#[utoipa::path(
+    get,
+    path = "/user/self",
+    tag = "user",
+    responses((status = 200, description = "The authenticated user", body = User))
+)]
 #[get("/self")]
 async fn get_self(auth: ReqData<UserModel>) -> APIResponse {
     let user = auth.into_inner();
     HttpResponse::Ok().json(user).to_ok()
 }
 
-#[patch("{id}")]
+#[utoipa::path(
+    post,
+    path = "/user/self/2fa/enroll",
+    tag = "user",
+    responses((status = 200, description = "A new TOTP secret and recovery codes", body = TotpEnrollment))
+)]
+#[post("/self/2fa/enroll", wrap = "RequireWriteScope")]
+async fn enroll_totp(auth: ReqData<UserModel>) -> APIResponse {
+    let user = auth.into_inner();
+    if user.totp_enabled {
+        return APIError::InvalidOperation("2FA is already enabled".into()).into();
+    }
+    let secret = TotpSecret::generate()?;
+    let encrypted = secret.encrypt(user.id)?;
+    let (recovery_codes, hashes) = web::block(totp::generate_recovery_codes).await??;
+    let enrollment = TotpEnrollment {
+        secret: secret.to_base32(),
+        provisioning_uri: secret.provisioning_uri(&user.username),
+        recovery_codes,
+    };
+    UserMutation::enroll_totp(DBConfig::get_connection(), user, encrypted, hashes).await?;
+    HttpResponse::Ok().json(enrollment).to_ok()
+}
+
+#[utoipa::path(
+    post,
+    path = "/user/self/2fa/confirm",
+    tag = "user",
+    request_body = TotpConfirmation,
+    responses((status = 200, description = "The user, now with 2FA enabled", body = User))
+)]
+#[post("/self/2fa/confirm", wrap = "RequireWriteScope")]
+async fn confirm_totp(body: Json<TotpConfirmation>, auth: ReqData<UserModel>) -> APIResponse {
+    let user = auth.into_inner();
+    if user.totp_enabled {
+        return APIError::InvalidOperation("2FA is already enabled".into()).into();
+    }
+    let Some(encrypted) = user.totp_secret.as_deref() else {
+        return APIError::InvalidOperation("2FA enrollment hasn't been started".into()).into();
+    };
+    let step = TotpSecret::decrypt(encrypted, user.id)?
+        .verify(body.code.trim())
+        .ok_or(APIError::InvalidTotpCode)?;
+    info!("user {:?} (id={}) enabled 2FA", user.username, user.id);
+    let user = UserMutation::confirm_totp(DBConfig::get_connection(), user, step).await?;
+    HttpResponse::Ok().json(user).to_ok()
+}
+
+#[utoipa::path(
+    patch,
+    path = "/user/{id}",
+    tag = "user",
+    params(("id" = Uuid, Path, description = "User ID")),
+    request_body = UserUpdate,
+    responses((status = 200, description = "The updated user", body = User))
+)]
+#[patch("{id}", wrap = "RequireWriteScope")]
 async fn update_user(
     id: Path<Uuid>,
     user: Json<UpdatableModel>,
     auth: ReqData<UserModel>,
 ) -> APIResponse {
-    if !auth.is_superuser && auth.id != *id {
-        info!("non-superuser attempted to update another user");
+    verify_can_see_user(&auth, *id)?;
+    let user_to_update = UserQuery::find_by_id(*id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?;
+    let is_user_admin = auth.has_role(Role::Admin);
+    if !is_user_admin && auth.id != *id {
+        info!("non-admin attempted to update another user");
         return APIError::InsufficientPermissions.into();
     }
-    if !auth.is_superuser && (user.is_superuser.is_some() || user.active.is_some()) {
-        info!("non-superuser attempted to update sensitive fields");
+    if !is_user_admin
+        && (user.active.is_some() || user.role.is_some() || user.max_storage_bytes.is_some())
+    {
+        info!("non-admin attempted to update sensitive fields");
         return APIError::InsufficientPermissions.into();
     }
-    let user_to_update = UserQuery::find_by_id(*id)
-        .await?
-        .ok_or_else(|| APIError::NotFound(format!("user with ID {id}")))?;
+    if !auth.is_superuser && user.is_superuser.is_some() {
+        info!("non-superuser attempted to update the superuser flag");
+        return APIError::InsufficientPermissions.into();
+    }
+    verify_can_manage(&auth, &user_to_update)?;
     if Config::get().protect_superuser && user_to_update.is_superuser {
         return APIError::ConflictingOperation("can't modify a superuser".into()).into();
     }
     let mut user = user.into_inner();
     user.prepare().await?;
-    let user = UserMutation::update(user_to_update, user).await?;
+    let user = UserMutation::update(DBConfig::get_connection(), user_to_update, user).await?;
     HttpResponse::Ok().json(user).to_ok()
 }
 
+#[utoipa::path(
+    get,
+    path = "/healthcheck",
+    tag = "auth",
+    responses((status = 200, description = "The server is up", body = Object, example = json!({"status": "200"}))),
+    security(())
+)]
 #[get("")]
 async fn healthcheck() -> APIResponse {
     info!("healthcheck ping");
@@ -160,6 +589,62 @@ async fn healthcheck() -> APIResponse {
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// Create the initial superuser if BOOTSTRAP_ADMIN_USERNAME is set and there
+/// are no superusers yet. This must run after the migrations.
+pub async fn init_bootstrap_superuser() -> Result<(), Box<dyn Error>> {
+    bootstrap_superuser(Config::get()).await
+}
+
+/// Same as [`init_bootstrap_superuser`], with the BOOTSTRAP_ADMIN_* variables
+/// of `config`.
+pub async fn bootstrap_superuser(config: &Config) -> Result<(), Box<dyn Error>> {
+    let username = match config.bootstrap_admin_username.as_ref() {
+        Some(username) => username.clone(),
+        _ => return Ok(()),
+    };
+    let password = match (
+        config.bootstrap_admin_password.as_ref(),
+        config.bootstrap_admin_password_file.as_ref(),
+    ) {
+        (Some(password), _) => password.clone(),
+        (_, Some(path)) => std::fs::read_to_string(path)
+            .map_err(|err| format!("cannot read BOOTSTRAP_ADMIN_PASSWORD_FILE: {err}"))?
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+        _ => return Err("missing bootstrap superuser password".into()),
+    };
+    let password = UserPassword::from(password);
+    password
+        .verify_complexity()
+        .map_err(|err| format!("bootstrap superuser: {err}"))?;
+
+    let mut user = UserModel {
+        username,
+        ..Default::default()
+    };
+    user.password = password.to_hash()?;
+    match UserMutation::bootstrap_superuser(DBConfig::get_connection(), user).await? {
+        SuperuserBootstrap::Created(user) => warn!(
+            "========== BOOTSTRAP: created initial superuser {:?} (id={}). \
+            You can remove the BOOTSTRAP_ADMIN_* variables now. ==========",
+            user.username, user.id
+        ),
+        SuperuserBootstrap::SuperuserExists => {
+            info!("bootstrap: a superuser already exists, ignoring BOOTSTRAP_ADMIN_* variables")
+        }
+        // promoting it would hand its account to whoever knows the configured password.
+        SuperuserBootstrap::UsernameTaken(user) => {
+            return Err(format!(
+                "bootstrap superuser: user {:?} (id={}) already exists and isn't a superuser, \
+                pick another BOOTSTRAP_ADMIN_USERNAME",
+                user.username, user.id
+            )
+            .into())
+        }
+    }
+    Ok(())
+}
+
 pub fn init_user_routes(cfg: &mut web::ServiceConfig) {
     let login_scope = web::scope("/login").service(login);
     let health_scope = web::scope("/healthcheck").service(healthcheck);
@@ -167,10 +652,15 @@ pub fn init_user_routes(cfg: &mut web::ServiceConfig) {
         .wrap(AuthMiddleware)
         .service(get_all_api_keys)
         .service(get_self)
+        .service(enroll_totp)
+        .service(confirm_totp)
         .service(get_all_users)
+        .service(create_users_bulk)
         .service(create_user)
         .service(delete_user)
+        .service(transfer_user)
         .service(update_user)
+        .service(get_user_usage)
         .service(get_user)
         .service(update_api_key)
         .service(create_api_key)
@@ -1,14 +1,142 @@
-use actix_web::web::ReqData;
-use central_repository_dao::user::Model as UserModel;
+use std::{net::IpAddr, str::FromStr};
+
+use actix_web::{HttpRequest, HttpResponseBuilder};
+use central_repository_dao::{
+    user::{Model as UserModel, Role},
+    LimitGrant,
+};
 use log::info;
+use uuid::Uuid;
+
+use crate::{conf::APIConfig, error::APIError};
 
-use crate::error::APIError;
+pub const RATE_LIMIT_LIMIT_HEADER: &str = "X-RateLimit-Limit";
+pub const RATE_LIMIT_REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+
+/// Make sure `user` has (at least) the `required` role. Superusers always do.
+pub fn verify_role(user: &UserModel, required: Role) -> Result<(), APIError> {
+    if !user.has_role(required) {
+        info!(
+            "Denied access to {required:?} resource, user id: {} (role: {:?})",
+            user.id, user.role
+        );
+        return Err(APIError::AdminOnlyResource);
+    }
+    Ok(())
+}
 
-pub fn verify_admin(user: &ReqData<UserModel>) -> Result<(), APIError> {
-    if !user.is_superuser {
-        // only admins can create new users.
-        info!("Denied access to admin resource, user id: {}", user.id);
+/// Make sure `user` may manage `target`: only superusers can manage superusers,
+/// regardless of their role.
+pub fn verify_can_manage(user: &UserModel, target: &UserModel) -> Result<(), APIError> {
+    if target.is_superuser && !user.is_superuser {
+        info!(
+            "Denied access to superuser {}, user id: {}",
+            target.id, user.id
+        );
         return Err(APIError::AdminOnlyResource);
     }
     Ok(())
 }
+
+/// Make sure `user` can see the user with ID `target`: everyone can see
+/// themselves, auditors (and up) can see everyone. Users that can't be seen
+/// don't exist as far as `user` is concerned, so this is a 404 and not a 403.
+pub fn verify_can_see_user(user: &UserModel, target: Uuid) -> Result<(), APIError> {
+    if user.id != target && !user.has_role(Role::Auditor) {
+        info!("Hid user {target} from user id: {}", user.id);
+        return Err(APIError::NotFound(format!("user with ID {target}")));
+    }
+    Ok(())
+}
+
+/// An address or CIDR range (e.g. `10.0.0.0/8`) of a trusted reverse proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl TrustedProxy {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let shift = 32 - self.prefix;
+                (u32::from(network) ^ u32::from(addr))
+                    .checked_shr(shift)
+                    .unwrap_or(0)
+                    == 0
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let shift = 128 - self.prefix;
+                (u128::from(network) ^ u128::from(addr))
+                    .checked_shr(shift)
+                    .unwrap_or(0)
+                    == 0
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (network, prefix) = match value.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            _ => (value, None),
+        };
+        let network: IpAddr = network
+            .parse()
+            .map_err(|_| format!("invalid proxy address {value:?}"))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid proxy range {value:?}"))?,
+            _ => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+/// The address of the client that sent `req`. `X-Forwarded-For` is only
+/// used if the request comes from a trusted proxy: the client is then the
+/// last address that isn't a trusted proxy itself.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let trusted_proxies = APIConfig::get_trusted_proxies();
+    let is_trusted = |addr: IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(addr));
+    if !is_trusted(peer) {
+        return Some(peer);
+    }
+    let forwarded = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    let mut client = peer;
+    for addr in forwarded.into_iter().rev() {
+        // anything before a malformed entry can't be trusted.
+        let Ok(addr) = addr.trim().parse() else {
+            break;
+        };
+        client = addr;
+        if !is_trusted(addr) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// Tell the client how many concurrent streams it may open and how many it
+/// has left. Streams without a grant (superusers) aren't limited.
+pub fn append_rate_limit_headers(response: &mut HttpResponseBuilder, grant: Option<&LimitGrant>) {
+    if let Some(grant) = grant {
+        response.insert_header((RATE_LIMIT_LIMIT_HEADER, grant.limit().to_string()));
+        response.insert_header((RATE_LIMIT_REMAINING_HEADER, grant.remaining().to_string()));
+    }
+}
@@ -0,0 +1,177 @@
+use actix_web::{
+    delete, get, patch, post,
+    web::{self, Json, Path, Query, ReqData},
+    HttpRequest, HttpResponse,
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{ColumnTrait, EntityTrait, QueryFilter},
+    user::{Model as UserModel, Role},
+    webhook::ModelAsQuery,
+    webhook_delivery, FormatQuery, GetAllPaginated, PaginationOptions, WebhookDeliveryQuery,
+    WebhookMutation, WebhookQuery,
+};
+use entity::webhook::{Model as WebhookModel, UpdatableModel as WebhookUpdatableModel};
+use log::info;
+
+use crate::{
+    core_middleware::{auth::AuthMiddleware, scope::RequireWriteScope},
+    error::{APIError, APIResponse, AsAPIResult},
+    pagination::{PaginatedResponse, Validate},
+    util::verify_role,
+};
+
+async fn find_webhook(id: i32) -> Result<WebhookModel, APIError> {
+    WebhookQuery::find_by_id(id)
+        .await?
+        .ok_or_else(|| APIError::NotFound(format!("webhook with ID {}", id)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook",
+    tag = "webhook",
+    params(PaginationOptions, ModelAsQuery),
+    responses((status = 200, description = "All webhooks", body = Vec<Webhook>))
+)]
+#[get("")]
+async fn get_all_webhooks(
+    req: HttpRequest,
+    pager: Query<PaginationOptions>,
+    filter: Query<ModelAsQuery>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    pager.validate()?;
+    let filter = filter.into_inner();
+    let pager = pager.into_inner();
+    let items = WebhookQuery::get_all(&filter, &pager, None).await?;
+    Ok(PaginatedResponse::from(items)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}",
+    tag = "webhook",
+    params(("id" = i32, Path, description = "Webhook ID")),
+    responses((status = 200, description = "The webhook", body = Webhook))
+)]
+#[get("{id}")]
+async fn get_webhook(id: Option<Path<i32>>, auth: ReqData<UserModel>) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    let id = *id.ok_or(APIError::BadRequest)?;
+    HttpResponse::Ok().json(find_webhook(id).await?).to_ok()
+}
+
+/// Register a webhook. Only admins can do this, so its URL isn't restricted:
+/// it may point at private or internal addresses reachable from the server.
+#[utoipa::path(
+    post,
+    path = "/webhook",
+    tag = "webhook",
+    request_body = Webhook,
+    responses((status = 201, description = "The created webhook", body = Webhook))
+)]
+#[post("", wrap = "RequireWriteScope")]
+async fn create_webhook(inbound: Json<WebhookModel>, auth: ReqData<UserModel>) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    if let Some(format_id) = inbound.format_id {
+        // make sure this format exists before subscribing to it
+        FormatQuery::find_by_id(&auth, format_id)
+            .await?
+            .ok_or_else(|| APIError::NotFound(format!("format with ID {}", format_id)))?;
+    }
+    let webhook = WebhookMutation::create(DBConfig::get_connection(), inbound.into_inner()).await?;
+    info!("created webhook {} for {:?}", webhook.id, webhook.events);
+    HttpResponse::Created().json(webhook).to_ok()
+}
+
+/// Update a webhook. As with creation, the URL isn't restricted.
+#[utoipa::path(
+    patch,
+    path = "/webhook/{id}",
+    tag = "webhook",
+    params(("id" = i32, Path, description = "Webhook ID")),
+    request_body = WebhookUpdate,
+    responses((status = 200, description = "The updated webhook", body = Webhook))
+)]
+#[patch("{id}", wrap = "RequireWriteScope")]
+async fn update_webhook(
+    id: Option<Path<i32>>,
+    new: Json<WebhookUpdatableModel>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let webhook = find_webhook(id).await?;
+    let webhook =
+        WebhookMutation::update(DBConfig::get_connection(), webhook, new.into_inner()).await?;
+    HttpResponse::Ok().json(webhook).to_ok()
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhook/{id}",
+    tag = "webhook",
+    params(("id" = i32, Path, description = "Webhook ID")),
+    responses((status = 204, description = "The webhook and its delivery log were deleted"))
+)]
+#[delete("{id}", wrap = "RequireWriteScope")]
+async fn delete_webhook(id: Option<Path<i32>>, auth: ReqData<UserModel>) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let webhook = find_webhook(id).await?;
+    WebhookMutation::delete(DBConfig::get_connection(), webhook).await?;
+    HttpResponse::NoContent().finish().to_ok()
+}
+
+/// Get the delivery log for this webhook.
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}/delivery",
+    tag = "webhook",
+    params(
+        ("id" = i32, Path, description = "Webhook ID"),
+        PaginationOptions,
+        webhook_delivery::ModelAsQuery
+    ),
+    responses((status = 200, description = "The webhook's delivery log", body = Vec<WebhookDelivery>))
+)]
+#[get("{id}/delivery")]
+async fn get_webhook_deliveries(
+    req: HttpRequest,
+    id: Option<Path<i32>>,
+    pager: Query<PaginationOptions>,
+    filter: Query<webhook_delivery::ModelAsQuery>,
+    auth: ReqData<UserModel>,
+) -> APIResponse {
+    verify_role(&auth, Role::Admin)?;
+    pager.validate()?;
+    let id = *id.ok_or(APIError::BadRequest)?;
+    let webhook = find_webhook(id).await?;
+    let select =
+        webhook_delivery::Entity::find().filter(webhook_delivery::Column::WebhookId.eq(webhook.id));
+    let filter = filter.into_inner();
+    let pager = pager.into_inner();
+    let items = WebhookDeliveryQuery::get_all(&filter, &pager, Some(select)).await?;
+    Ok(PaginatedResponse::from(items)
+        .for_pager(&pager)
+        .with_etag(&req)
+        .into())
+}
+
+pub fn init_webhook_routes(cfg: &mut web::ServiceConfig) {
+    let scope = web::scope("/webhook")
+        .wrap(AuthMiddleware)
+        .service(get_all_webhooks)
+        .service(create_webhook)
+        .service(get_webhook_deliveries)
+        .service(update_webhook)
+        .service(delete_webhook)
+        .service(get_webhook);
+
+    cfg.service(scope);
+}
@@ -0,0 +1,192 @@
+use std::{str::FromStr, sync::Arc};
+
+use central_repository_dao::{record::DynamicHashmap, PaginationOptions, SearchQuery};
+use reqwest::{header::HeaderMap, Method, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    error::{APIError, ClientError},
+    models::{Login, Page, Record, Upload},
+    token::{SessionToken, TokenProvider},
+};
+
+#[derive(Serialize)]
+struct LoginCredentials<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InboundRecordData<'a> {
+    format_id: i32,
+    data: &'a [DynamicHashmap],
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    tokens: Arc<dyn TokenProvider>,
+}
+
+impl Client {
+    /// A client for the API at `base_url` (e.g. `http://127.0.0.1:8000/api/v1`),
+    /// which sends the token of its last [`login`](Self::login).
+    pub fn new<S: Into<String>>(base_url: S) -> Self {
+        Self::with_token_provider(base_url, SessionToken::default())
+    }
+
+    /// A client that gets its tokens from `tokens`, e.g. a fixed API key token.
+    pub fn with_token_provider<S, P>(base_url: S, tokens: P) -> Self
+    where
+        S: Into<String>,
+        P: TokenProvider + 'static,
+    {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').into(),
+            tokens: Arc::new(tokens),
+        }
+    }
+
+    /// Send the requests with `http`, e.g. to set timeouts or a proxy.
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let builder = self
+            .http
+            .request(method, format!("{}{path}", self.base_url));
+        match self.tokens.token() {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    /// Send `builder`, turning error responses into [`ClientError::Api`].
+    async fn send(builder: RequestBuilder) -> Result<Response, ClientError> {
+        let response = builder.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await?;
+        let error = serde_json::from_str(&body).unwrap_or_else(|_| APIError {
+            code: String::new(),
+            detail: Some(body),
+        });
+        Err(ClientError::Api { status, error })
+    }
+
+    async fn json<T: DeserializeOwned>(builder: RequestBuilder) -> Result<T, ClientError> {
+        Ok(Self::send(builder).await?.json().await?)
+    }
+
+    /// Log in and keep the token in the token provider.
+    pub async fn login(&self, username: &str, password: &str) -> Result<Login, ClientError> {
+        let credentials = LoginCredentials { username, password };
+        let login: Login = Self::json(
+            self.http
+                .post(format!("{}/login", self.base_url))
+                .json(&credentials),
+        )
+        .await?;
+        self.tokens.store(&login.token);
+        Ok(login)
+    }
+
+    /// Upload `data` to the format `format_id`. Uploads that fail validation
+    /// are errors, even though the server saves their (failed) session.
+    pub async fn create_record(
+        &self,
+        format_id: i32,
+        data: &[DynamicHashmap],
+    ) -> Result<Upload, ClientError> {
+        let body = InboundRecordData { format_id, data };
+        Self::json(self.request(Method::POST, "/record").json(&body)).await
+    }
+
+    /// Get a page of the records matching `query`.
+    pub async fn filter_records(
+        &self,
+        query: &SearchQuery,
+        pager: &PaginationOptions,
+    ) -> Result<Page<Record>, ClientError> {
+        let builder = self
+            .request(Method::POST, "/record/filter")
+            .query(pager)
+            .json(query);
+        let response = Self::send(builder).await?;
+        let headers = response.headers().clone();
+        let items = response.json().await?;
+        page_from(items, &headers)
+    }
+
+    /// Stream the records matching `query` into `writer` as CSV, and return the
+    /// number of bytes written. `columns` picks the exported columns (see
+    /// `?columns=`), every column of the searched formats is exported otherwise.
+    pub async fn stream_records<W>(
+        &self,
+        query: &SearchQuery,
+        columns: Option<&[&str]>,
+        writer: &mut W,
+    ) -> Result<u64, ClientError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut builder = self.request(Method::POST, "/record/filter-stream");
+        if let Some(columns) = columns {
+            builder = builder.query(&[("columns", columns.join(","))]);
+        }
+        let mut response = Self::send(builder.json(query)).await?;
+        let mut written = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(written)
+    }
+}
+
+/// Assemble a page from its items and the pagination headers.
+fn page_from<T>(items: Vec<T>, headers: &HeaderMap) -> Result<Page<T>, ClientError> {
+    let pager = PaginationOptions {
+        page: required_header(headers, "repository-page")?,
+        per_page: required_header(headers, "repository-per-page")?,
+        count: required_header(headers, "repository-count-enabled")?,
+        count_only: false,
+    };
+    let counted = |name| match pager.count {
+        true => header(headers, name),
+        false => Ok(None),
+    };
+    Ok(Page {
+        item_count: counted("repository-item-count")?,
+        page_count: counted("repository-page-count")?,
+        truncated_after_id: header(headers, "repository-truncated-after-id")?,
+        items,
+        pager,
+    })
+}
+
+fn header<T: FromStr>(headers: &HeaderMap, name: &'static str) -> Result<Option<T>, ClientError> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or(ClientError::InvalidHeader(name))
+        })
+        .transpose()
+}
+
+fn required_header<T: FromStr>(headers: &HeaderMap, name: &'static str) -> Result<T, ClientError> {
+    header(headers, name)?.ok_or(ClientError::InvalidHeader(name))
+}
@@ -0,0 +1,36 @@
+use reqwest::StatusCode;
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("{status}: {error}")]
+    Api { status: StatusCode, error: APIError },
+    #[error("invalid '{0}' header")]
+    InvalidHeader(&'static str),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// The body of an error response. Only the fields sent in every error mode
+/// (see `PROBLEM_DETAILS_ERRORS`) are kept.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct APIError {
+    /// Stable error code, e.g. `REPO-1001`. Empty if the body wasn't an error
+    /// of this API (e.g. one of a proxy in between).
+    #[serde(default)]
+    pub code: String,
+    pub detail: Option<String>,
+}
+
+impl std::fmt::Display for APIError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} {detail}", self.code),
+            None => f.write_str(&self.code),
+        }
+    }
+}
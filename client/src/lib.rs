@@ -0,0 +1,21 @@
+//! A typed client for the repository's HTTP API. Requests are built from the
+//! same types the server deserializes (`SearchQuery`, `PaginationOptions`,
+//! ...), so both sides can't drift apart.
+mod client;
+mod error;
+mod models;
+mod token;
+
+pub use client::*;
+pub use error::*;
+pub use models::*;
+pub use token::*;
+
+pub use central_repository_dao::{
+    record::{DynamicHashmap, RecordJsonData},
+    upload_session::OutcomeKind,
+    user::Role,
+    ComparisonOperator, ConditionKind, JoinKind, PaginationOptions, SearchArguments, SearchGroup,
+    SearchQuery, TreatAs,
+};
+pub use reqwest;
@@ -0,0 +1,96 @@
+use central_repository_dao::{
+    record::RecordJsonData, upload_session::OutcomeKind, user::Role, PaginationOptions,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+// The entity models skip deserializing their server-generated fields (ids,
+// timestamps, ...) so users can't set them, which means they can't be used to
+// read responses. These are the response side of those models.
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub id: Uuid,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub is_superuser: bool,
+    pub active: bool,
+    pub role: Role,
+}
+
+/// Response of `POST /login`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Login {
+    pub token: String,
+    pub user: User,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Record {
+    pub id: i64,
+    pub upload_session_id: i32,
+    pub format_id: i32,
+    pub created_at: DateTime<Utc>,
+    pub data: RecordJsonData,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSession {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub record_count: i32,
+    pub format_id: i32,
+    pub user_id: Uuid,
+    pub outcome: OutcomeKind,
+    pub detail: String,
+    pub content_hash: Option<String>,
+}
+
+/// Response of a successful `POST /record`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Upload {
+    pub upload_session: UploadSession,
+    pub received_count: i32,
+    pub inserted_count: i32,
+    pub elapsed_ms: i64,
+}
+
+/// A page of a list endpoint, built from the bare array in the body and the
+/// pagination headers.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The effective pagination options, after the server's defaults.
+    pub pager: PaginationOptions,
+    /// Total number of items and pages (only with `count` enabled).
+    pub item_count: Option<u64>,
+    pub page_count: Option<u64>,
+    /// Set if the page was cut short to fit in `MAX_FILTER_RESPONSE_BYTES`.
+    pub truncated_after_id: Option<i64>,
+}
+
+impl<T> Page<T> {
+    /// Whether there are more pages after this one. Without `count`, a full
+    /// page is assumed to have more.
+    pub fn has_next(&self) -> bool {
+        match self.page_count {
+            Some(page_count) => self.pager.page + 1 < page_count,
+            None => self.items.len() as u64 >= self.pager.per_page,
+        }
+    }
+
+    /// The options of the next page, if there is one. Truncated pages can't be
+    /// continued by page number without skipping the items that were cut, so
+    /// there's none for them: ask for fewer items per page instead.
+    pub fn next_pager(&self) -> Option<PaginationOptions> {
+        let continuable = self.truncated_after_id.is_none() && self.has_next();
+        continuable.then(|| PaginationOptions {
+            page: self.pager.page + 1,
+            ..self.pager.clone()
+        })
+    }
+}
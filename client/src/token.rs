@@ -0,0 +1,31 @@
+use std::sync::RwLock;
+
+/// Where the client gets the bearer token of its requests from.
+pub trait TokenProvider: Send + Sync {
+    /// The token to send, if any.
+    fn token(&self) -> Option<String>;
+
+    /// Called with the token of every successful [`login`](crate::Client::login).
+    fn store(&self, _token: &str) {}
+}
+
+/// A fixed token, e.g. the one of an API key.
+impl TokenProvider for String {
+    fn token(&self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+/// Keeps the token of the last login. This is the default provider.
+#[derive(Default)]
+pub struct SessionToken(RwLock<Option<String>>);
+
+impl TokenProvider for SessionToken {
+    fn token(&self) -> Option<String> {
+        self.0.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    fn store(&self, token: &str) {
+        *self.0.write().unwrap_or_else(|err| err.into_inner()) = Some(token.into());
+    }
+}
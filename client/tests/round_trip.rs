@@ -0,0 +1,118 @@
+use central_repository_client::{
+    Client, ClientError, ComparisonOperator, ConditionKind, DynamicHashmap, OutcomeKind,
+    PaginationOptions, SearchArguments, SearchGroup, SearchQuery,
+};
+use central_repository_dao::{format::ColumnKind, format_entitlement::AccessLevel};
+use central_repository_test_support::{run, TEST_PASSWORD};
+use serde_json::json;
+
+fn record(amount: i64, region: &str) -> DynamicHashmap {
+    serde_json::from_value(json!({"Amount": amount, "Region": region})).unwrap()
+}
+
+/// Log in, upload and search through the client against a running server.
+#[test]
+fn login_upload_and_filter() {
+    run(|ctx| async move {
+        let base_url = ctx.serve().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(
+                &admin,
+                &[
+                    ("Amount", ColumnKind::Number),
+                    ("Region", ColumnKind::String),
+                ],
+            )
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read, AccessLevel::Write])
+            .await;
+        let client = Client::new(base_url);
+
+        // nothing works before logging in
+        let query = SearchQuery::new(vec![]).with_formats(vec![format.id]);
+        let error = client
+            .filter_records(&query, &PaginationOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Api { status, .. } if status.as_u16() == 401),
+            "{error}"
+        );
+        let error = client
+            .login(&user.model.username, "wrong password")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ClientError::Api { .. }), "{error}");
+
+        let login = client
+            .login(&user.model.username, TEST_PASSWORD)
+            .await
+            .unwrap();
+        assert_eq!(login.user.id, user.model.id);
+
+        let records = [
+            record(50, "north"),
+            record(150, "north"),
+            record(250, "south"),
+        ];
+        let upload = client.create_record(format.id, &records).await.unwrap();
+        assert_eq!(upload.inserted_count, 3);
+        assert_eq!(upload.upload_session.outcome, OutcomeKind::Success);
+        assert_eq!(upload.upload_session.user_id, user.model.id);
+
+        // uploads that fail validation are errors
+        let invalid: DynamicHashmap =
+            serde_json::from_value(json!({"Amount": "not a number", "Region": "east"})).unwrap();
+        let error = client
+            .create_record(format.id, &[invalid])
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Api { status, .. } if status.as_u16() == 400),
+            "{error}"
+        );
+
+        let north = SearchQuery::new(vec![SearchGroup::new(
+            ConditionKind::All,
+            vec![SearchArguments::new(
+                "Region",
+                ComparisonOperator::Eq,
+                json!("north"),
+            )],
+        )])
+        .with_formats(vec![format.id]);
+        let pager = PaginationOptions {
+            page: 0,
+            per_page: 1,
+            count: true,
+            count_only: false,
+        };
+        let first = client.filter_records(&north, &pager).await.unwrap();
+        assert_eq!(first.items.len(), 1);
+        assert_eq!(first.item_count, Some(2));
+        assert_eq!(first.page_count, Some(2));
+        let second = client
+            .filter_records(&north, &first.next_pager().unwrap())
+            .await
+            .unwrap();
+        assert!(second.next_pager().is_none());
+        let mut amounts = [&first.items[0], &second.items[0]]
+            .map(|record| record.data.get("Amount").unwrap().as_i64().unwrap());
+        amounts.sort_unstable();
+        assert_eq!(amounts, [50, 150]);
+        assert_eq!(first.items[0].format_id, format.id);
+
+        let mut csv = Vec::new();
+        let written = client
+            .stream_records(&north, Some(&["Amount"]), &mut csv)
+            .await
+            .unwrap();
+        assert_eq!(written, csv.len() as u64);
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().ends_with(",Amount"), "{csv}");
+        assert_eq!(lines.count(), 2, "{csv}");
+    });
+}
@@ -3,13 +3,21 @@ use dotenvy::dotenv;
 use envconfig::Envconfig;
 use log::{info, warn};
 use once_cell::sync::OnceCell;
+use serde::{Serialize, Serializer};
 use std::error::Error;
 
+use crate::registry;
+
 pub static CONFIG: OnceCell<Config> = OnceCell::new();
 
-#[derive(Envconfig, BetterDebug)]
+// Every key must also be listed in `registry::KNOWN_KEYS`. Secrets need
+// both `#[better_debug(secret)]` (for the logs) and one of the `mask_*`
+// serializers (for `to_masked_json`).
+#[derive(Envconfig, BetterDebug, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub struct Config {
     #[better_debug(secret)]
+    #[serde(serialize_with = "mask_secret")]
     #[envconfig(from = "DATABASE_URL")]
     pub database_url: String,
 
@@ -19,19 +27,78 @@ pub struct Config {
     #[envconfig(from = "HTTP_PORT", default = "8000")]
     pub http_port: u16,
 
+    // If set, the administrative endpoints (/admin/* and the prune trigger)
+    // are only served on ADMIN_HTTP_ADDRESS:ADMIN_HTTP_PORT, e.g. to only
+    // expose them to an internal network.
+    // Default: not set, everything is served on HTTP_PORT
+    #[envconfig(from = "ADMIN_HTTP_ADDRESS", default = "127.0.0.1")]
+    pub admin_http_address: String,
+
+    #[envconfig(from = "ADMIN_HTTP_PORT")]
+    pub admin_http_port: Option<u16>,
+
     #[envconfig(from = "DB_POOL_MAX_CONN", default = "100")]
     pub db_pool_max_conn: u32,
 
     #[envconfig(from = "DB_POOL_MIN_CONN", default = "10")]
     pub db_pool_min_conn: u32,
 
+    // Open DB_POOL_MIN_CONN connections on startup (and check every one of
+    // them) instead of when the first requests come in. Disable for faster
+    // startups during development.
+    // Default: enabled
+    #[envconfig(from = "DB_POOL_WARM_UP", default = "true")]
+    pub db_pool_warm_up: bool,
+
     #[better_debug(secret)]
+    #[serde(serialize_with = "mask_secret")]
     #[envconfig(from = "ED25519_SIGNING_KEY")]
     pub ed25519_signing_key: String,
 
+    // Comma-separated, base64-encoded public keys of former signing keys.
+    // Tokens they signed are still accepted, see `repository-admin
+    // rotate-signing-key`.
+    #[envconfig(from = "ED25519_PREVIOUS_PUBLIC_KEYS", default = "")]
+    pub ed25519_previous_public_keys: String,
+
     #[envconfig(from = "TOKEN_EXPIRATION_SECONDS", default = "300")]
     pub token_expiration_seconds: u32,
 
+    // Reject logins from a client address for a while after this many failed
+    // attempts in LOGIN_ATTEMPT_WINDOW_SECONDS. Set to 0 to disable.
+    #[envconfig(from = "LOGIN_MAX_FAILED_ATTEMPTS", default = "10")]
+    pub login_max_failed_attempts: u32,
+
+    #[envconfig(from = "LOGIN_ATTEMPT_WINDOW_SECONDS", default = "60")]
+    pub login_attempt_window_seconds: u64,
+
+    // Max number of client addresses whose failed logins are remembered.
+    #[envconfig(from = "LOGIN_MAX_TRACKED_CLIENTS", default = "100000")]
+    pub login_max_tracked_clients: u32,
+
+    // Comma-separated addresses or CIDR ranges (e.g. `10.0.0.0/8`) of reverse
+    // proxies whose X-Forwarded-For header is trusted.
+    #[envconfig(from = "TRUSTED_PROXIES", default = "")]
+    pub trusted_proxies: String,
+
+    // Base64-encoded 256-bit key used to encrypt TOTP secrets. 2FA enrollment
+    // is disabled while it's empty.
+    #[better_debug(secret)]
+    #[serde(serialize_with = "mask_secret")]
+    #[envconfig(from = "TOTP_ENCRYPTION_KEY", default = "")]
+    pub totp_encryption_key: String,
+
+    // Base64-encoded 256-bit key used to encrypt the records of encrypted
+    // formats. Encrypted formats can't be created while it's empty.
+    #[better_debug(secret)]
+    #[serde(serialize_with = "mask_secret")]
+    #[envconfig(from = "RECORD_ENCRYPTION_KEY", default = "")]
+    pub record_encryption_key: String,
+
+    // Also accept TOTP codes from this many 30-second steps before/after now.
+    #[envconfig(from = "TOTP_SKEW_STEPS", default = "1")]
+    pub totp_skew_steps: u8,
+
     #[envconfig(from = "BULK_INSERT_CHUNK_SIZE", default = "200")]
     pub bulk_insert_chunk_size: u32,
 
@@ -44,6 +111,42 @@ pub struct Config {
     #[envconfig(from = "DEFAULT_PAGINATION_SIZE", default = "1000")]
     pub default_pagination_size: u64,
 
+    // Max offset (page * perPage) of paginated requests made with count=false,
+    // 0 means no limit. With count=true, pages past the last one are empty and
+    // don't need this.
+    #[envconfig(from = "MAX_PAGINATION_OFFSET", default = "1000000")]
+    pub max_pagination_offset: u64,
+
+    // Max number of records returned by a single /record/changes call.
+    #[envconfig(from = "MAX_CHANGES_LIMIT", default = "10000")]
+    pub max_changes_limit: u64,
+
+    // Max number of items in a `compareAgainst` array (e.g. for `in`).
+    #[envconfig(from = "MAX_COMPARE_AGAINST_ARRAY_LENGTH", default = "10000")]
+    pub max_compare_against_array_length: u64,
+
+    // Max number of formats that can be listed in a search (`formats`).
+    #[envconfig(from = "MAX_SEARCH_FORMATS", default = "1000")]
+    pub max_search_formats: u64,
+
+    // Max number of days covered by a single `/format/{id}/activity` call.
+    #[envconfig(from = "MAX_ACTIVITY_RANGE_DAYS", default = "366")]
+    pub max_activity_range_days: u64,
+
+    // Reject like/iLike/regex patterns with fewer literal characters than
+    // SEARCH_PATTERN_MIN_LENGTH (or only wildcards) from non-superusers, since
+    // they'd scan every record.
+    #[envconfig(from = "SEARCH_PATTERN_GUARD", default = "true")]
+    pub search_pattern_guard: bool,
+
+    #[envconfig(from = "SEARCH_PATTERN_MIN_LENGTH", default = "3")]
+    pub search_pattern_min_length: usize,
+
+    // Compare numbers as FLOAT instead of NUMERIC in searches. FLOAT is
+    // faster, but loses precision (e.g. integers above 2^53).
+    #[envconfig(from = "FLOAT_NUMBER_COMPARISONS", default = "false")]
+    pub float_number_comparisons: bool,
+
     #[envconfig(from = "WORKERS", default = "16")]
     pub workers: u8,
 
@@ -54,9 +157,46 @@ pub struct Config {
     #[envconfig(from = "MAX_JSON_PAYLOAD_SIZE", default = "100000")]
     pub max_json_payload_size: u64,
 
+    // Same as MAX_JSON_PAYLOAD_SIZE, but for /record (uploads and queries).
+    // Set by default to 10_000_000 bytes (10 MB).
+    #[envconfig(from = "RECORD_MAX_JSON_PAYLOAD_SIZE", default = "10000000")]
+    pub record_max_json_payload_size: u64,
+
+    // Strings in uploaded records can be at most this many characters long.
+    // 0 disables the limit.
+    #[envconfig(from = "RECORD_MAX_STRING_LENGTH", default = "65536")]
+    pub record_max_string_length: u64,
+
+    // Uploaded records can be at most this many bytes long (as JSON). 0
+    // disables the limit.
+    #[envconfig(from = "RECORD_MAX_ROW_BYTES", default = "1048576")]
+    pub record_max_row_bytes: u64,
+
+    // Pages of POST /record/filter are cut short once their records add up to
+    // more than this many bytes (as JSON). 0 disables the limit.
+    // This is checked once the page is loaded, so it doesn't bound memory use.
+    // Set by default to 268_435_456 bytes (256 MB).
+    #[envconfig(from = "MAX_FILTER_RESPONSE_BYTES", default = "268435456")]
+    pub max_filter_response_bytes: u64,
+
     #[envconfig(from = "DB_ACQUIRE_CONNECTION_TIMEOUT_SEC", default = "30")]
     pub db_acquire_connection_timeout_sec: u64,
 
+    // Cancel requests whose handler takes longer than this. For streamed
+    // responses, this is the time until the first byte. Set to 0 to disable.
+    #[envconfig(from = "REQUEST_TIMEOUT_SECONDS", default = "30")]
+    pub request_timeout_seconds: u64,
+
+    // Same as REQUEST_TIMEOUT_SECONDS, for uploads/searches (/record) and
+    // manual prunes, which legitimately take longer.
+    #[envconfig(from = "LONG_REQUEST_TIMEOUT_SECONDS", default = "300")]
+    pub long_request_timeout_seconds: u64,
+
+    // Column statistics (/record/column-stats) are cancelled by the database
+    // after this many seconds.
+    #[envconfig(from = "COLUMN_STATS_TIMEOUT_SECONDS", default = "30")]
+    pub column_stats_timeout_seconds: u64,
+
     #[envconfig(from = "DB_CSV_STREAM_WORKERS", default = "1")]
     pub db_csv_stream_workers: u64,
 
@@ -66,6 +206,26 @@ pub struct Config {
     #[envconfig(from = "DB_CSV_WORKER_QUEUE_DEPTH", default = "200")]
     pub db_csv_worker_queue_depth: u64,
 
+    // Max values of `?streams=` and `?transformWorkers=` on
+    // /record/filter-stream. Larger values are lowered to these.
+    #[envconfig(from = "DB_CSV_MAX_STREAM_WORKERS", default = "8")]
+    pub db_csv_max_stream_workers: u64,
+
+    #[envconfig(from = "DB_CSV_MAX_TRANSFORM_WORKERS", default = "8")]
+    pub db_csv_max_transform_workers: u64,
+
+    // Comma-separated usernames of the non-superusers who may raise those
+    // above DB_CSV_STREAM_WORKERS/DB_CSV_TRANSFORM_WORKERS. Everyone else can
+    // only lower them.
+    #[envconfig(from = "DB_CSV_PARALLEL_USERS", default = "")]
+    pub db_csv_parallel_users: String,
+
+    // Log a warning when the result queue of an export stays full for this
+    // long, i.e. the client reads slower than the export is produced. Set to
+    // 0 to disable.
+    #[envconfig(from = "DB_CSV_SATURATION_WARNING_SECONDS", default = "30")]
+    pub db_csv_saturation_warning_seconds: u64,
+
     #[envconfig(from = "MAX_API_KEYS_PER_USER", default = "10")]
     pub max_api_keys_per_user: u64,
 
@@ -73,14 +233,49 @@ pub struct Config {
     #[envconfig(from = "TOKEN_API_KEY_EXPIRATION_HOURS", default = "720")]
     pub token_api_key_expiration_hours: u64,
 
+    // API keys that weren't created or rotated in this many days are
+    // flagged as `stale`. Set to 0 to disable.
+    #[envconfig(from = "MAX_KEY_AGE_DAYS", default = "0")]
+    pub max_key_age_days: u32,
+
+    // Reject stale API keys (see MAX_KEY_AGE_DAYS) instead of just
+    // flagging them. Default: disabled
+    #[envconfig(from = "STRICT_KEY_AGE", default = "false")]
+    pub strict_key_age: bool,
+
+    // Once a day, report the API keys that go stale (see MAX_KEY_AGE_DAYS)
+    // within this many days.
+    #[envconfig(from = "KEY_AGE_WARNING_DAYS", default = "7")]
+    pub key_age_warning_days: u32,
+
     #[envconfig(from = "DB_MAX_STREAMS_PER_USER", default = "2")]
     pub db_max_streams_per_user: u64,
 
+    // Max number of concurrent /upload_session/events (SSE) connections per user.
+    #[envconfig(from = "MAX_SSE_CONNECTIONS_PER_USER", default = "2")]
+    pub max_sse_connections_per_user: u64,
+
+    // Interval between SSE heartbeats. The user is also re-checked (and the
+    // stream closed if it was deactivated) on every heartbeat.
+    #[envconfig(from = "SSE_HEARTBEAT_SECONDS", default = "15")]
+    pub sse_heartbeat_seconds: u64,
+
+    // Longest wait of GET /upload_session/{id}?waitForOutcome=true. Must be
+    // shorter than REQUEST_TIMEOUT_SECONDS (unless that's disabled).
+    #[envconfig(from = "MAX_OUTCOME_WAIT_SECONDS", default = "25")]
+    pub max_outcome_wait_seconds: u64,
+
     // For users with temporal delete permission, allow them
     // to delete entries uploaded in the last N hours.
     #[envconfig(from = "TEMPORAL_DELETE_HOURS", default = "24")]
     pub temporal_delete_hours: u64,
 
+    // Shortest retention period formats can be given, so their data isn't
+    // pruned right after it's uploaded. A retention period of 0 (keep
+    // forever) is always allowed.
+    #[envconfig(from = "MIN_RETENTION_MINUTES", default = "60")]
+    pub min_retention_minutes: u64,
+
     // Whether or not to enable the prune old data job. This will
     // spawn a background thread to delete old data on a per-format
     // basis.
@@ -98,19 +293,177 @@ pub struct Config {
     // Default: 300 seconds (5 minutes).
     #[envconfig(from = "PRUNE_JOB_TIMEOUT_SECONDS", default = "300")]
     pub prune_job_timeout_seconds: u64,
+
+    // Run `ANALYZE record` after a prune deleted at least
+    // PRUNE_ANALYZE_MIN_RECORDS records of a single format, so query plans
+    // don't degrade until autovacuum catches up. This counts towards
+    // PRUNE_JOB_TIMEOUT_SECONDS.
+    // Default: disabled
+    #[envconfig(from = "PRUNE_ANALYZE_AFTER", default = "false")]
+    pub prune_analyze_after: bool,
+
+    // See PRUNE_ANALYZE_AFTER.
+    // Default: 100000 records
+    #[envconfig(from = "PRUNE_ANALYZE_MIN_RECORDS", default = "100000")]
+    pub prune_analyze_min_records: u64,
+
+    // Run `VACUUM (ANALYZE) record` instead of `ANALYZE record`. This
+    // reclaims the space of the deleted records, but takes longer.
+    // Default: disabled
+    #[envconfig(from = "PRUNE_ANALYZE_VACUUM", default = "false")]
+    pub prune_analyze_vacuum: bool,
+
+    // Upload sessions that are still in progress after this many hours are
+    // marked as failed (this only happens if the server died mid-upload).
+    // Set to 0 to disable.
+    #[envconfig(from = "STUCK_UPLOAD_SESSION_HOURS", default = "24")]
+    pub stuck_upload_session_hours: u64,
+
+    // Truncate the `detail` of upload sessions to this many characters.
+    // Default: 1000 characters
+    #[envconfig(from = "UPLOAD_SESSION_DETAIL_MAX_LENGTH", default = "1000")]
+    pub upload_session_detail_max_length: u64,
+
+    // Username for the initial superuser. This user will only be created
+    // on startup if there are no superusers in the database.
+    #[envconfig(from = "BOOTSTRAP_ADMIN_USERNAME")]
+    pub bootstrap_admin_username: Option<String>,
+
+    // Password for the initial superuser. Mutually exclusive with
+    // BOOTSTRAP_ADMIN_PASSWORD_FILE.
+    #[better_debug(secret)]
+    #[serde(serialize_with = "mask_optional_secret")]
+    #[envconfig(from = "BOOTSTRAP_ADMIN_PASSWORD")]
+    pub bootstrap_admin_password: Option<String>,
+
+    // Path to a file containing the password for the initial superuser.
+    #[envconfig(from = "BOOTSTRAP_ADMIN_PASSWORD_FILE")]
+    pub bootstrap_admin_password_file: Option<String>,
+
+    // Outbound webhook requests are cancelled after WEBHOOK_TIMEOUT_SECONDS.
+    #[envconfig(from = "WEBHOOK_TIMEOUT_SECONDS", default = "10")]
+    pub webhook_timeout_seconds: u64,
+
+    // Max number of attempts per webhook delivery (including the first one).
+    #[envconfig(from = "WEBHOOK_MAX_ATTEMPTS", default = "5")]
+    pub webhook_max_attempts: u32,
+
+    // Delay before the first retry. Doubled after every failed attempt.
+    #[envconfig(from = "WEBHOOK_RETRY_BASE_DELAY_MS", default = "1000")]
+    pub webhook_retry_base_delay_ms: u64,
+
+    // Webhook payloads bigger than this won't be sent.
+    // Default: 1_000_000 bytes (1 MB)
+    #[envconfig(from = "WEBHOOK_MAX_BODY_BYTES", default = "1000000")]
+    pub webhook_max_body_bytes: usize,
+
+    // Max number of pending webhook notifications. Events are dropped
+    // (and logged) if the queue is full.
+    #[envconfig(from = "WEBHOOK_QUEUE_DEPTH", default = "1000")]
+    pub webhook_queue_depth: usize,
+
+    // Max number of `POST /record?async=true` uploads waiting to be processed.
+    // New async uploads are rejected while the queue is full.
+    #[envconfig(from = "INGESTION_QUEUE_DEPTH", default = "16")]
+    pub ingestion_queue_depth: usize,
+
+    // Number of queued async uploads processed at the same time.
+    #[envconfig(from = "INGESTION_WORKERS", default = "2")]
+    pub ingestion_workers: usize,
+
+    // Serve the OpenAPI specification under /openapi.json (no authentication
+    // required).
+    #[envconfig(from = "ENABLE_OPENAPI", default = "true")]
+    pub enable_openapi: bool,
+
+    // Serve a bundled Swagger UI under /swagger-ui/. Requires ENABLE_OPENAPI.
+    #[envconfig(from = "ENABLE_SWAGGER_UI", default = "false")]
+    pub enable_swagger_ui: bool,
+
+    // Cache the /admin/stats counters for this many seconds (0 disables the
+    // cache). Counting every record gets expensive on big instances.
+    #[envconfig(from = "ADMIN_STATS_CACHE_SECONDS", default = "60")]
+    pub admin_stats_cache_seconds: u64,
+
+    // Cache the merged column kinds of the formats searched by /record/filter
+    // for this many seconds (0 disables the cache).
+    #[envconfig(from = "COLUMN_KIND_CACHE_SECONDS", default = "300")]
+    pub column_kind_cache_seconds: u64,
+
+    // Send error responses as application/problem+json (RFC 7807), with the
+    // request id as `instance`.
+    #[envconfig(from = "PROBLEM_DETAILS_ERRORS", default = "false")]
+    pub problem_details_errors: bool,
+
+    // Keep the deprecated `statusCode` and `kind` fields in error responses.
+    // Clients should switch to `code` (or `status` with PROBLEM_DETAILS_ERRORS).
+    #[envconfig(from = "LEGACY_ERROR_FIELDS", default = "true")]
+    pub legacy_error_fields: bool,
+
+    // Keep serving every endpoint without the /api/v1 prefix too. Clients
+    // should switch to the prefixed routes.
+    #[envconfig(from = "LEGACY_ROUTES", default = "true")]
+    pub legacy_routes: bool,
+
+    // Compress JSON responses (gzip, br or zstd, whatever the client accepts).
+    // CSV exports and event streams are never compressed.
+    #[envconfig(from = "ENABLE_COMPRESSION", default = "true")]
+    pub enable_compression: bool,
+
+    // JSON responses smaller than this are sent uncompressed.
+    #[envconfig(from = "COMPRESSION_MIN_SIZE_BYTES", default = "1024")]
+    pub compression_min_size_bytes: u64,
+
+    // Refuse to start if there are variables that look like config keys
+    // (e.g. `HTTP_PRT`) but aren't. They're only logged otherwise.
+    #[envconfig(from = "STRICT_CONFIG", default = "false")]
+    pub strict_config: bool,
+}
+
+// Secrets in `Config::to_masked_json`, like BetterDebug does in the logs.
+const MASKED_SECRET: &str = "<SECRET>";
+
+/// Only tell whether a secret is set: empty ones are `null`.
+fn mask_secret<T: AsRef<str>, S: Serializer>(secret: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret.as_ref().is_empty() {
+        true => serializer.serialize_none(),
+        false => serializer.serialize_str(MASKED_SECRET),
+    }
+}
+
+fn mask_optional_secret<S: Serializer>(
+    secret: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(secret) => mask_secret(secret, serializer),
+        _ => serializer.serialize_none(),
+    }
 }
 
 impl Config {
+    /// The config as a JSON object keyed by environment variable, with the
+    /// secrets masked.
+    pub fn to_masked_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("config: Cannot serialize inner struct")
+    }
+
     pub fn init_and_check() -> Result<&'static Config, Box<dyn Error>> {
         if let Some(config) = CONFIG.get() {
             warn!("init_and_check() was called twice!");
             return Ok(config);
         }
+        let env_keys = registry::env_keys();
         dotenv().ok();
         info!("reading config from environment");
         let config = Config::init_from_env()?;
+        registry::check_unknown_keys(config.strict_config)?;
         config.verify()?;
         info!("config: OK: {:#?}", config);
+        info!(
+            "config sources (env, file or default):{}",
+            registry::source_report(&env_keys)
+        );
         CONFIG.set(config).expect("config: Cannot set inner struct");
         Ok(CONFIG.get().expect("config: Cannot get inner struct"))
     }
@@ -129,12 +482,39 @@ impl Config {
         if self.default_pagination_size > self.max_pagination_size {
             return Err("DEFAULT_PAGINATION_SIZE must be less than MAX_PAGINATION_SIZE".into());
         }
+        if self.enable_swagger_ui && !self.enable_openapi {
+            return Err("ENABLE_SWAGGER_UI requires ENABLE_OPENAPI".into());
+        }
+        if self.max_search_formats == 0 {
+            return Err("MAX_SEARCH_FORMATS must be greater than 0".into());
+        }
+        if self.max_activity_range_days == 0 {
+            return Err("MAX_ACTIVITY_RANGE_DAYS must be greater than 0".into());
+        }
+        if self.max_compare_against_array_length == 0 {
+            return Err("MAX_COMPARE_AGAINST_ARRAY_LENGTH must be greater than 0".into());
+        }
+        if self.max_changes_limit == 0 {
+            return Err("MAX_CHANGES_LIMIT must be greater than 0".into());
+        }
         if self.bulk_insert_chunk_size == 0 {
             return Err("BULK_INSERT_CHUNK_SIZE must be greater than 0".into());
         }
         if self.token_expiration_seconds == 0 {
             return Err("TOKEN_EXPIRATION_SECONDS must be greater than 0".into());
         }
+        if self.login_attempt_window_seconds == 0 {
+            return Err("LOGIN_ATTEMPT_WINDOW_SECONDS must be greater than 0".into());
+        }
+        if self.login_max_tracked_clients == 0 {
+            return Err("LOGIN_MAX_TRACKED_CLIENTS must be greater than 0".into());
+        }
+        if self.totp_skew_steps > 10 {
+            return Err("TOTP_SKEW_STEPS must be less than or equal to 10".into());
+        }
+        if self.admin_http_port == Some(self.http_port) {
+            return Err("ADMIN_HTTP_PORT must be different from HTTP_PORT".into());
+        }
         if self.db_pool_min_conn == 0 {
             return Err("DB_POOL_MIN_CONN must be greater than 0".into());
         }
@@ -147,9 +527,18 @@ impl Config {
         if self.max_json_payload_size == 0 {
             return Err("MAX_JSON_PAYLOAD_SIZE must be greater than 0".into());
         }
+        if self.record_max_json_payload_size < self.max_json_payload_size {
+            return Err(
+                "RECORD_MAX_JSON_PAYLOAD_SIZE must be greater than or equal to MAX_JSON_PAYLOAD_SIZE"
+                    .into(),
+            );
+        }
         if self.db_acquire_connection_timeout_sec == 0 {
             return Err("DB_ACQUIRE_CONNECTION_TIMEOUT_SEC must be greater than 0".into());
         }
+        if self.column_stats_timeout_seconds == 0 {
+            return Err("COLUMN_STATS_TIMEOUT_SECONDS must be greater than 0".into());
+        }
         if self.db_csv_stream_workers == 0 {
             return Err("DB_CSV_STREAM_WORKERS must be greater than 0".into());
         }
@@ -159,18 +548,49 @@ impl Config {
         if self.db_csv_worker_queue_depth == 0 {
             return Err("DB_CSV_WORKER_QUEUE_DEPTH must be greater than 0".into());
         }
+        if self.db_csv_max_stream_workers < self.db_csv_stream_workers {
+            return Err("DB_CSV_MAX_STREAM_WORKERS must be >= DB_CSV_STREAM_WORKERS".into());
+        }
+        if self.db_csv_max_transform_workers < self.db_csv_transform_workers {
+            return Err("DB_CSV_MAX_TRANSFORM_WORKERS must be >= DB_CSV_TRANSFORM_WORKERS".into());
+        }
         if self.max_api_keys_per_user == 0 {
             return Err("MAX_API_KEYS_PER_USER must be greater than 0".into());
         }
         if self.token_api_key_expiration_hours == 0 {
             return Err("TOKEN_API_KEY_EXPIRATION_HOURS must be greater than 0".into());
         }
+        if self.strict_key_age && self.max_key_age_days == 0 {
+            return Err("STRICT_KEY_AGE requires MAX_KEY_AGE_DAYS to be greater than 0".into());
+        }
         if self.db_max_streams_per_user == 0 {
             return Err("DB_MAX_STREAMS_PER_USER must be greater than 0".into());
         }
+        if self.max_sse_connections_per_user == 0 {
+            return Err("MAX_SSE_CONNECTIONS_PER_USER must be greater than 0".into());
+        }
+        if self.sse_heartbeat_seconds == 0 {
+            return Err("SSE_HEARTBEAT_SECONDS must be greater than 0".into());
+        }
+        if self.max_outcome_wait_seconds == 0 {
+            return Err("MAX_OUTCOME_WAIT_SECONDS must be greater than 0".into());
+        }
+        if self.request_timeout_seconds > 0
+            && self.max_outcome_wait_seconds >= self.request_timeout_seconds
+        {
+            return Err(
+                "MAX_OUTCOME_WAIT_SECONDS must be less than REQUEST_TIMEOUT_SECONDS".into(),
+            );
+        }
+        if self.upload_session_detail_max_length == 0 {
+            return Err("UPLOAD_SESSION_DETAIL_MAX_LENGTH must be greater than 0".into());
+        }
         if self.temporal_delete_hours == 0 {
             return Err("TEMPORAL_DELETE_HOURS must be greater than 0".into());
         }
+        if self.min_retention_minutes > i32::MAX as u64 {
+            return Err(format!("MIN_RETENTION_MINUTES must be at most {}", i32::MAX).into());
+        }
         if self.enable_prune_job {
             if self.prune_job_run_interval_seconds == 0 {
                 return Err("PRUNE_JOB_RUN_INTERVAL_SECONDS must be greater than 0".into());
@@ -179,6 +599,46 @@ impl Config {
                 return Err("PRUNE_JOB_TIMEOUT_SECONDS must be greater than 0".into());
             }
         }
+        if self.prune_analyze_after && self.prune_analyze_min_records == 0 {
+            return Err("PRUNE_ANALYZE_MIN_RECORDS must be greater than 0".into());
+        }
+        if self.prune_analyze_vacuum && !self.prune_analyze_after {
+            return Err("PRUNE_ANALYZE_VACUUM requires PRUNE_ANALYZE_AFTER".into());
+        }
+        if self.webhook_timeout_seconds == 0 {
+            return Err("WEBHOOK_TIMEOUT_SECONDS must be greater than 0".into());
+        }
+        if self.webhook_max_attempts == 0 {
+            return Err("WEBHOOK_MAX_ATTEMPTS must be greater than 0".into());
+        }
+        if self.webhook_max_body_bytes == 0 {
+            return Err("WEBHOOK_MAX_BODY_BYTES must be greater than 0".into());
+        }
+        if self.webhook_queue_depth == 0 {
+            return Err("WEBHOOK_QUEUE_DEPTH must be greater than 0".into());
+        }
+        if self.ingestion_queue_depth == 0 {
+            return Err("INGESTION_QUEUE_DEPTH must be greater than 0".into());
+        }
+        if self.ingestion_workers == 0 {
+            return Err("INGESTION_WORKERS must be greater than 0".into());
+        }
+        let has_bootstrap_password =
+            self.bootstrap_admin_password.is_some() || self.bootstrap_admin_password_file.is_some();
+        if self.bootstrap_admin_password.is_some() && self.bootstrap_admin_password_file.is_some() {
+            return Err(
+                "BOOTSTRAP_ADMIN_PASSWORD and BOOTSTRAP_ADMIN_PASSWORD_FILE are mutually exclusive"
+                    .into(),
+            );
+        }
+        if self.bootstrap_admin_username.is_some() != has_bootstrap_password {
+            return Err("BOOTSTRAP_ADMIN_USERNAME must be set together with either BOOTSTRAP_ADMIN_PASSWORD or BOOTSTRAP_ADMIN_PASSWORD_FILE".into());
+        }
+        if let Some(username) = self.bootstrap_admin_username.as_ref() {
+            if username.trim().is_empty() {
+                return Err("BOOTSTRAP_ADMIN_USERNAME cannot be empty".into());
+            }
+        }
 
         Ok(())
     }
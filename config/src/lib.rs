@@ -1 +1,2 @@
 pub mod inner;
+pub mod registry;
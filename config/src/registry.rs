@@ -0,0 +1,257 @@
+use std::{collections::HashSet, env, fmt};
+
+use log::warn;
+
+/// Every environment variable read by [`Config`](crate::inner::Config), in
+/// declaration order. envconfig doesn't expose its keys, so new config keys
+/// must be added here too (the tests compare both lists).
+pub const KNOWN_KEYS: &[&str] = &[
+    "DATABASE_URL",
+    "HTTP_ADDRESS",
+    "HTTP_PORT",
+    "ADMIN_HTTP_ADDRESS",
+    "ADMIN_HTTP_PORT",
+    "DB_POOL_MAX_CONN",
+    "DB_POOL_MIN_CONN",
+    "DB_POOL_WARM_UP",
+    "ED25519_SIGNING_KEY",
+    "ED25519_PREVIOUS_PUBLIC_KEYS",
+    "TOKEN_EXPIRATION_SECONDS",
+    "LOGIN_MAX_FAILED_ATTEMPTS",
+    "LOGIN_ATTEMPT_WINDOW_SECONDS",
+    "LOGIN_MAX_TRACKED_CLIENTS",
+    "TRUSTED_PROXIES",
+    "TOTP_ENCRYPTION_KEY",
+    "RECORD_ENCRYPTION_KEY",
+    "TOTP_SKEW_STEPS",
+    "BULK_INSERT_CHUNK_SIZE",
+    "PROTECT_SUPERUSER",
+    "MAX_PAGINATION_SIZE",
+    "DEFAULT_PAGINATION_SIZE",
+    "MAX_PAGINATION_OFFSET",
+    "MAX_CHANGES_LIMIT",
+    "MAX_COMPARE_AGAINST_ARRAY_LENGTH",
+    "MAX_SEARCH_FORMATS",
+    "MAX_ACTIVITY_RANGE_DAYS",
+    "SEARCH_PATTERN_GUARD",
+    "SEARCH_PATTERN_MIN_LENGTH",
+    "FLOAT_NUMBER_COMPARISONS",
+    "WORKERS",
+    "RETURN_QUERY_COUNT",
+    "MAX_JSON_PAYLOAD_SIZE",
+    "RECORD_MAX_JSON_PAYLOAD_SIZE",
+    "RECORD_MAX_STRING_LENGTH",
+    "RECORD_MAX_ROW_BYTES",
+    "MAX_FILTER_RESPONSE_BYTES",
+    "DB_ACQUIRE_CONNECTION_TIMEOUT_SEC",
+    "REQUEST_TIMEOUT_SECONDS",
+    "LONG_REQUEST_TIMEOUT_SECONDS",
+    "COLUMN_STATS_TIMEOUT_SECONDS",
+    "DB_CSV_STREAM_WORKERS",
+    "DB_CSV_TRANSFORM_WORKERS",
+    "DB_CSV_WORKER_QUEUE_DEPTH",
+    "DB_CSV_MAX_STREAM_WORKERS",
+    "DB_CSV_MAX_TRANSFORM_WORKERS",
+    "DB_CSV_PARALLEL_USERS",
+    "DB_CSV_SATURATION_WARNING_SECONDS",
+    "MAX_API_KEYS_PER_USER",
+    "TOKEN_API_KEY_EXPIRATION_HOURS",
+    "MAX_KEY_AGE_DAYS",
+    "STRICT_KEY_AGE",
+    "KEY_AGE_WARNING_DAYS",
+    "DB_MAX_STREAMS_PER_USER",
+    "MAX_SSE_CONNECTIONS_PER_USER",
+    "SSE_HEARTBEAT_SECONDS",
+    "MAX_OUTCOME_WAIT_SECONDS",
+    "TEMPORAL_DELETE_HOURS",
+    "MIN_RETENTION_MINUTES",
+    "ENABLE_PRUNE_JOB",
+    "PRUNE_JOB_RUN_INTERVAL_SECONDS",
+    "PRUNE_JOB_TIMEOUT_SECONDS",
+    "PRUNE_ANALYZE_AFTER",
+    "PRUNE_ANALYZE_MIN_RECORDS",
+    "PRUNE_ANALYZE_VACUUM",
+    "STUCK_UPLOAD_SESSION_HOURS",
+    "UPLOAD_SESSION_DETAIL_MAX_LENGTH",
+    "BOOTSTRAP_ADMIN_USERNAME",
+    "BOOTSTRAP_ADMIN_PASSWORD",
+    "BOOTSTRAP_ADMIN_PASSWORD_FILE",
+    "WEBHOOK_TIMEOUT_SECONDS",
+    "WEBHOOK_MAX_ATTEMPTS",
+    "WEBHOOK_RETRY_BASE_DELAY_MS",
+    "WEBHOOK_MAX_BODY_BYTES",
+    "WEBHOOK_QUEUE_DEPTH",
+    "INGESTION_QUEUE_DEPTH",
+    "INGESTION_WORKERS",
+    "ENABLE_OPENAPI",
+    "ENABLE_SWAGGER_UI",
+    "ADMIN_STATS_CACHE_SECONDS",
+    "COLUMN_KIND_CACHE_SECONDS",
+    "PROBLEM_DETAILS_ERRORS",
+    "LEGACY_ERROR_FIELDS",
+    "LEGACY_ROUTES",
+    "ENABLE_COMPRESSION",
+    "COMPRESSION_MIN_SIZE_BYTES",
+    "STRICT_CONFIG",
+];
+
+// Well-known variables that happen to share a prefix with our keys.
+const IGNORED_KEYS: &[&str] = &["HTTP_PROXY"];
+
+// Only suggest known keys that are at most this many edits away.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    Env,
+    File,
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Default => "default",
+            Self::Env => "env",
+            Self::File => "file",
+        })
+    }
+}
+
+/// Names of the variables set in the environment. Call this before loading
+/// `.env` to tell both sources apart.
+pub fn env_keys() -> HashSet<String> {
+    env::vars_os()
+        .filter_map(|(key, _)| key.into_string().ok())
+        .collect()
+}
+
+/// Where `key` was read from. `.env` never overrides the environment, so
+/// keys that weren't in `env_keys` but are set now come from the file.
+pub fn source_of(key: &str, env_keys: &HashSet<String>) -> ConfigSource {
+    if env_keys.contains(key) {
+        ConfigSource::Env
+    } else if env::var_os(key).is_some() {
+        ConfigSource::File
+    } else {
+        ConfigSource::Default
+    }
+}
+
+/// One `KEY: source` line per known key.
+pub fn source_report(env_keys: &HashSet<String>) -> String {
+    KNOWN_KEYS
+        .iter()
+        .map(|key| format!("\n    {key}: {}", source_of(key, env_keys)))
+        .collect()
+}
+
+/// Variables that look like config keys (i.e. they share a prefix with one,
+/// such as `HTTP_` or `DB_`) but aren't, most likely typos. Every key comes
+/// with the closest known key, if any.
+pub fn unknown_keys() -> Vec<(String, Option<&'static str>)> {
+    let mut unknown = env_keys()
+        .into_iter()
+        .filter(|key| {
+            !KNOWN_KEYS.contains(&key.as_str())
+                && !IGNORED_KEYS.contains(&key.as_str())
+                && KNOWN_KEYS
+                    .iter()
+                    .any(|known| key.starts_with(prefix_of(known)))
+        })
+        .map(|key| {
+            let suggestion = closest_key(&key);
+            (key, suggestion)
+        })
+        .collect::<Vec<_>>();
+    unknown.sort();
+    unknown
+}
+
+/// Format the result of [`unknown_keys`] for users.
+pub fn describe_unknown_keys(unknown: &[(String, Option<&'static str>)]) -> String {
+    unknown
+        .iter()
+        .map(|(key, suggestion)| match suggestion {
+            Some(suggestion) => format!("{key} (did you mean {suggestion}?)"),
+            _ => key.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Log the [`unknown_keys`], or refuse them if `strict` (STRICT_CONFIG).
+pub fn check_unknown_keys(strict: bool) -> Result<(), String> {
+    let unknown = unknown_keys();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    let unknown = describe_unknown_keys(&unknown);
+    if strict {
+        return Err(format!(
+            "unknown config keys (STRICT_CONFIG is set): {unknown}"
+        ));
+    }
+    warn!("ignoring unknown config keys: {unknown}");
+    Ok(())
+}
+
+/// `DB_POOL_MAX_CONN` -> `DB_`. Keys without underscores are their own prefix.
+fn prefix_of(key: &str) -> &str {
+    key.find('_').map_or(key, |index| &key[..=index])
+}
+
+fn closest_key(key: &str) -> Option<&'static str> {
+    KNOWN_KEYS
+        .iter()
+        .map(|known| (edit_distance(key, known), *known))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min()
+        .map(|(_, known)| known)
+}
+
+/// Levenshtein distance, keys are ASCII.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.as_bytes();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.bytes().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `from` of every `#[envconfig(...)]` attribute of `Config`.
+    fn declared_keys() -> Vec<&'static str> {
+        const ATTRIBUTE: &str = "#[envconfig(from = \"";
+        include_str!("inner.rs")
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix(ATTRIBUTE))
+            .filter_map(|rest| rest.split('"').next())
+            .collect()
+    }
+
+    #[test]
+    fn known_keys_match_config() {
+        let declared = declared_keys();
+        assert!(!declared.is_empty());
+        assert_eq!(KNOWN_KEYS, declared.as_slice());
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_keys() {
+        env::set_var("HTTP_PROT", "8000");
+        let err = check_unknown_keys(true).unwrap_err();
+        assert!(err.contains("HTTP_PROT (did you mean HTTP_PORT?)"), "{err}");
+        assert!(check_unknown_keys(false).is_ok());
+        env::remove_var("HTTP_PROT");
+    }
+}
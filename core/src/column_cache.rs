@@ -0,0 +1,82 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use central_repository_config::inner::Config;
+use entity::format::ColumnKind;
+use log::debug;
+use once_cell::sync::Lazy;
+
+/// Column name -> column kind, merged across a set of formats.
+pub type ColumnKinds = HashMap<String, ColumnKind>;
+
+/// Max number of format sets to keep. The cache is simply cleared once it's full.
+const MAX_ENTRIES: usize = 1024;
+
+struct CacheEntry {
+    created_at: Instant,
+    kinds: Arc<ColumnKinds>,
+}
+
+// keyed by the (sorted) ids of the formats a search runs on.
+static CACHE: Lazy<Mutex<HashMap<Vec<i32>, CacheEntry>>> = Lazy::new(Default::default);
+
+/// Cache of the column kinds used to validate searches, so repeated searches on
+/// the same formats don't have to merge their schemas again.
+///
+/// Schemas can't be edited, but every format mutation clears the cache anyway
+/// so a change there can't silently serve stale kinds. Other instances (and
+/// `repository-admin`) can't clear it, so entries also expire after
+/// COLUMN_KIND_CACHE_SECONDS.
+pub struct ColumnKindCache;
+
+impl ColumnKindCache {
+    fn ttl() -> Option<Duration> {
+        match Config::get().column_kind_cache_seconds {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+
+    fn lock() -> std::sync::MutexGuard<'static, HashMap<Vec<i32>, CacheEntry>> {
+        CACHE.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub(crate) fn get(format_ids: &[i32]) -> Option<Arc<ColumnKinds>> {
+        let ttl = Self::ttl()?;
+        let cache = Self::lock();
+        let entry = cache.get(format_ids)?;
+        match entry.created_at.elapsed() < ttl {
+            true => Some(entry.kinds.clone()),
+            false => None,
+        }
+    }
+
+    /// Whether fresh kinds are cached for `format_ids` (sorted by id).
+    pub fn is_cached(format_ids: &[i32]) -> bool {
+        Self::get(format_ids).is_some()
+    }
+
+    pub(crate) fn insert(format_ids: Vec<i32>, kinds: Arc<ColumnKinds>) {
+        if Self::ttl().is_none() {
+            return;
+        }
+        let mut cache = Self::lock();
+        if cache.len() >= MAX_ENTRIES {
+            debug!("column kind cache is full, clearing it");
+            cache.clear();
+        }
+        let entry = CacheEntry {
+            created_at: Instant::now(),
+            kinds,
+        };
+        cache.insert(format_ids, entry);
+    }
+
+    /// Drop every cached entry. Called whenever a format changes.
+    pub fn invalidate() {
+        Self::lock().clear();
+    }
+}
@@ -6,8 +6,12 @@ use thiserror::Error;
 
 #[derive(Error, Debug, AsRefStr)]
 pub enum CoreError {
-    #[error("User '{0}' exceeded grant limit")]
-    GrantError(String),
+    #[error("User '{key}' exceeded grant limit ({in_use} of {limit} in use)")]
+    GrantError {
+        key: String,
+        in_use: u64,
+        limit: u64,
+    },
     // Pass through PoisonedError
     #[error("Poisoned mutex error")]
     PoisonError,
@@ -1,18 +1,28 @@
+mod column_cache;
 pub mod conf;
 pub mod error;
 mod limiter;
 mod mutation;
 mod pagination_impl;
+mod pipeline_stats;
 mod query;
+mod query_stats;
+mod record_encryption;
 mod record_filtering;
 pub mod tasks;
+mod webhook_dispatch;
 
+pub use column_cache::*;
 pub use entity::*;
 pub use error::*;
 pub use limiter::*;
 pub use mutation::*;
 pub use pagination_impl::*;
+pub use pipeline_stats::*;
 pub use query::*;
+pub use query_stats::*;
+pub use record_encryption::*;
 pub use record_filtering::*;
+pub use webhook_dispatch::*;
 
 pub use sea_orm;
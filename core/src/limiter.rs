@@ -1,8 +1,10 @@
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
+use hashlink::LinkedHashMap;
 use log::{debug, error, info};
 
 use crate::CoreError;
@@ -20,6 +22,10 @@ pub struct LimiterControllerInner {
 
 pub struct LimitGrant {
     key: String,
+    // grants held by this key (including this one) and the max, when this
+    // grant was created.
+    in_use: u64,
+    limit: u64,
     inner: Arc<RwLock<LimiterControllerInner>>,
 }
 
@@ -34,6 +40,18 @@ impl LimitController {
         }
     }
 
+    /// Total number of grants currently held across all keys.
+    pub fn active_grants(&self) -> u64 {
+        let inner = match self.inner.read() {
+            Ok(inner) => inner,
+            Err(e) => {
+                error!("state was poisoned: {e:?}, recovering");
+                e.into_inner()
+            }
+        };
+        inner.state.values().sum()
+    }
+
     /// Try to create a new grant for key `key`.
     /// If this user already has more than `max_grants_per_user`, None will be returned.
     ///
@@ -52,7 +70,11 @@ impl LimitController {
                         "key {} currently holds {} grants, max is {}",
                         key, grants, self.max_grants_per_user
                     );
-                    return Err(CoreError::GrantError(format!("{}, {}", key, grants)));
+                    return Err(CoreError::GrantError {
+                        key: key.to_string(),
+                        in_use: *grants,
+                        limit: self.max_grants_per_user,
+                    });
                 }
             }
         }
@@ -66,11 +88,25 @@ impl LimitController {
         *current_grant_count += 1;
         Ok(LimitGrant {
             key: key.to_string(),
+            in_use: *current_grant_count,
+            limit: self.max_grants_per_user,
             inner: self.inner.clone(),
         })
     }
 }
 
+impl LimitGrant {
+    /// Max number of grants per key.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Number of grants this key could still get when this one was created.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.in_use)
+    }
+}
+
 impl Drop for LimitGrant {
     fn drop(&mut self) {
         let mut inner = match self.inner.write() {
@@ -90,3 +126,242 @@ impl Drop for LimitGrant {
         }
     }
 }
+
+/// Counts failed attempts (e.g. logins) per key over a sliding window, along
+/// with the attempts still in progress. Once a key reaches `max_attempts`,
+/// it's rejected until the oldest failure leaves the window.
+///
+/// At most `max_keys` keys are tracked: once that's reached, the least
+/// recently failed key is forgotten (keys without failures in the window go
+/// first, then).
+#[derive(Clone, Debug)]
+pub struct AttemptLimiter {
+    max_attempts: usize,
+    window: Duration,
+    max_keys: usize,
+    // in the order they last failed, the least recently failed first.
+    inner: Arc<Mutex<LinkedHashMap<String, VecDeque<Instant>>>>,
+}
+
+impl AttemptLimiter {
+    /// Create a new limiter. A `max_attempts` of 0 disables it.
+    pub fn new(max_attempts: usize, window: Duration, max_keys: usize) -> Self {
+        debug!("Initializing AttemptLimiter with {max_attempts} attempts every {window:?}.");
+        Self {
+            max_attempts,
+            window,
+            max_keys,
+            inner: Default::default(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LinkedHashMap<String, VecDeque<Instant>>> {
+        self.inner.lock().unwrap_or_else(|e| {
+            error!("state was poisoned: {e:?}, recovering");
+            e.into_inner()
+        })
+    }
+
+    /// Number of keys with failed (or ongoing) attempts currently remembered.
+    pub fn tracked_keys(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Start an attempt of `key`, if it may try again. If it can't, returns
+    /// how long it has to wait. The attempt counts towards `max_attempts`
+    /// until it's dropped, or for the whole window once it's
+    /// [`failed`](Attempt::failed). Checking and counting happen under the
+    /// same lock, so concurrent attempts can't get past the limit.
+    pub fn attempt(&self, key: &str) -> Result<Attempt<'_>, Duration> {
+        self.attempt_at(key, Instant::now())
+    }
+
+    /// Same as [`Self::attempt`], at `now`.
+    pub fn attempt_at(&self, key: &str, now: Instant) -> Result<Attempt<'_>, Duration> {
+        let mut attempt = Attempt {
+            limiter: self,
+            key: key.to_string(),
+            at: now,
+            pending: false,
+        };
+        if self.max_attempts == 0 {
+            return Ok(attempt);
+        }
+        let mut state = self.lock();
+        if let Some(failures) = state.get_mut(key) {
+            while failures
+                .front()
+                .is_some_and(|failure| now.saturating_duration_since(*failure) >= self.window)
+            {
+                failures.pop_front();
+            }
+            if let Some(oldest) = failures
+                .front()
+                .filter(|_| failures.len() >= self.max_attempts)
+            {
+                info!("key {key} reached {} failed attempts", failures.len());
+                let retry_after = self.window - now.saturating_duration_since(*oldest);
+                // keys that keep trying stay tracked.
+                state.to_back(key);
+                return Err(retry_after);
+            }
+        }
+        if state.to_back(key).is_none() {
+            while state.len() >= self.max_keys.max(1) {
+                let Some((evicted, _)) = state.pop_front() else {
+                    break;
+                };
+                debug!("attempt limiter is full, forgetting key {evicted}");
+            }
+        }
+        state
+            .entry(key.to_string())
+            .or_insert_with(VecDeque::new)
+            .push_back(now);
+        attempt.pending = true;
+        Ok(attempt)
+    }
+}
+
+/// An attempt started with [`AttemptLimiter::attempt`]. Dropping it takes it
+/// back, unless it [`failed`](Self::failed).
+pub struct Attempt<'a> {
+    limiter: &'a AttemptLimiter,
+    key: String,
+    at: Instant,
+    // whether this attempt is counted and has to be taken back on drop.
+    pending: bool,
+}
+
+impl Attempt<'_> {
+    /// Keep counting this attempt until it leaves the window.
+    pub fn failed(mut self) {
+        self.pending = false;
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.pending {
+            return;
+        }
+        let mut state = self.limiter.lock();
+        // the key may have been forgotten in the meantime.
+        let Some(failures) = state.get_mut(&self.key) else {
+            return;
+        };
+        if let Some(position) = failures.iter().rposition(|at| *at == self.at) {
+            failures.remove(position);
+        }
+        if failures.is_empty() {
+            state.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    fn fail(limiter: &AttemptLimiter, key: &str, times: usize, now: Instant) {
+        for _ in 0..times {
+            if let Ok(attempt) = limiter.attempt_at(key, now) {
+                attempt.failed();
+            }
+        }
+    }
+
+    /// Whether `key` may try again, without counting an attempt.
+    fn check(limiter: &AttemptLimiter, key: &str, now: Instant) -> Result<(), Duration> {
+        limiter.attempt_at(key, now).map(drop)
+    }
+
+    #[test]
+    fn blocks_until_the_oldest_failure_leaves_the_window() {
+        let limiter = AttemptLimiter::new(3, WINDOW, 10);
+        let now = Instant::now();
+        fail(&limiter, "key", 2, now);
+        assert_eq!(check(&limiter, "key", now), Ok(()));
+        fail(&limiter, "key", 1, now + Duration::from_secs(10));
+        assert_eq!(
+            check(&limiter, "key", now + Duration::from_secs(10)),
+            Err(Duration::from_secs(50))
+        );
+        assert_eq!(check(&limiter, "key", now + WINDOW), Ok(()));
+        assert_eq!(check(&limiter, "other", now), Ok(()));
+    }
+
+    #[test]
+    fn disabled_without_attempts() {
+        let limiter = AttemptLimiter::new(0, WINDOW, 10);
+        let now = Instant::now();
+        fail(&limiter, "key", 100, now);
+        assert_eq!(check(&limiter, "key", now), Ok(()));
+        assert_eq!(limiter.tracked_keys(), 0);
+    }
+
+    #[test]
+    fn burst_of_new_keys_forgets_the_least_recently_failed() {
+        let limiter = AttemptLimiter::new(3, WINDOW, 100);
+        let now = Instant::now();
+        fail(&limiter, "victim", 3, now);
+        fail(&limiter, "attacker", 3, now);
+        for i in 0..10_000 {
+            fail(&limiter, &format!("burst-{i}"), 1, now);
+            if i % 50 == 0 {
+                // the attacker keeps trying, so it stays tracked.
+                fail(&limiter, "attacker", 1, now);
+            }
+        }
+        assert_eq!(limiter.tracked_keys(), 100);
+        assert_eq!(check(&limiter, "victim", now), Ok(()));
+        assert!(check(&limiter, "attacker", now).is_err());
+        assert!(limiter.lock().contains_key("burst-9999"));
+        assert!(!limiter.lock().contains_key("burst-0"));
+    }
+
+    #[test]
+    fn expired_keys_are_forgotten_first() {
+        let limiter = AttemptLimiter::new(3, WINDOW, 3);
+        let now = Instant::now();
+        fail(&limiter, "expired", 3, now);
+        fail(&limiter, "recent", 3, now + WINDOW);
+        fail(&limiter, "other", 1, now + WINDOW);
+        fail(&limiter, "new", 1, now + WINDOW);
+        assert_eq!(limiter.tracked_keys(), 3);
+        assert!(!limiter.lock().contains_key("expired"));
+        assert!(check(&limiter, "recent", now + WINDOW).is_err());
+    }
+
+    #[test]
+    fn concurrent_attempts_count_until_they_succeed() {
+        let limiter = AttemptLimiter::new(3, WINDOW, 10);
+        let now = Instant::now();
+        let attempts = (0..3)
+            .map(|_| limiter.attempt_at("key", now).unwrap())
+            .collect::<Vec<_>>();
+        // the attempts in flight already count
+        assert_eq!(check(&limiter, "key", now), Err(WINDOW));
+        let mut attempts = attempts.into_iter();
+        attempts.next().unwrap().failed();
+        drop(attempts);
+        // only the failed one is left
+        assert_eq!(check(&limiter, "key", now), Ok(()));
+        fail(&limiter, "key", 1, now);
+        assert_eq!(check(&limiter, "key", now), Ok(()));
+        fail(&limiter, "key", 1, now);
+        assert_eq!(check(&limiter, "key", now), Err(WINDOW));
+    }
+
+    #[test]
+    fn succeeded_attempts_are_forgotten() {
+        let limiter = AttemptLimiter::new(3, WINDOW, 10);
+        let now = Instant::now();
+        for _ in 0..10 {
+            check(&limiter, "key", now).unwrap();
+        }
+        assert_eq!(limiter.tracked_keys(), 0);
+    }
+}
@@ -1,32 +1,45 @@
-use std::time::Duration;
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
 
 use ::entity::{
     api_key,
     error::DatabaseQueryError,
     format,
-    format::{ColumnKind, Entity as Format},
+    format::{ColumnKind, Entity as Format, FormatRules, FormatSchema},
     format_entitlement::{self, AccessLevel, ARRAY_CONTAINS_OP},
     record,
     record::Entity as Record,
+    saved_search, saved_search_share,
     upload_session::{self, OutcomeKind},
-    user,
+    user, webhook, webhook_delivery,
 };
 use better_debug::BetterDebug;
 use central_repository_config::inner::Config;
-use log::{debug, info};
+use log::{debug, error, info};
 use regex::Regex;
 use sea_orm::*;
-use sea_query::Expr;
+use sea_query::{Alias, Expr, Func, SimpleExpr};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::conf::DBConfig;
+use crate::{
+    column_cache::ColumnKindCache, str_to_isodate, GetAllTrait, RecordCipher, UploadSessionQuery,
+};
+
+// Longest allowed column name, in characters.
+const MAX_COLUMN_NAME_LENGTH: usize = 128;
 
 pub struct FormatMutation;
 
 impl FormatMutation {
-    pub async fn create(model: format::Model) -> Result<format::ActiveModel, DatabaseQueryError> {
-        let db = DBConfig::get_connection();
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: format::Model,
+        created_by: Option<Uuid>,
+    ) -> Result<format::ActiveModel, DatabaseQueryError> {
         let is_regex_invalid = model.schema.iter().filter(|i| i.regex.is_some()).any(|i| {
             // only string columns can be checked against a regex
             i.kind != ColumnKind::String || Regex::new(i.regex.as_ref().unwrap().as_str()).is_err()
@@ -35,34 +48,282 @@ impl FormatMutation {
             return Err(DatabaseQueryError::InvalidRegex);
         }
 
-        format::ActiveModel {
+        Self::validate_schema(&model.schema)?;
+        Self::validate_rules(&model.schema, &model.rules)?;
+        Self::validate(&model)?;
+        Self::validate_retention(model.retention_period_minutes)?;
+        if model.encrypted && !RecordCipher::is_enabled() {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "encrypted formats are disabled on this server (RECORD_ENCRYPTION_KEY is not set)"
+                    .into(),
+            ));
+        }
+
+        let now = chrono::offset::Utc::now();
+        let format = format::ActiveModel {
             name: Set(model.name),
             description: Set(model.description),
-            created_at: Set(chrono::offset::Utc::now()),
+            created_at: Set(now),
+            created_by: Set(created_by),
+            updated_at: Set(now),
             schema: Set(model.schema),
+            rules: Set(model.rules),
             retention_period_minutes: Set(model.retention_period_minutes),
+            max_records: Set(model.max_records),
+            max_records_per_day: Set(model.max_records_per_day),
+            encrypted: Set(model.encrypted),
             ..Default::default()
         }
         .save(db)
-        .await
-        .map_err(Into::into)
+        .await?;
+        ColumnKindCache::invalidate();
+        Ok(format)
+    }
+
+    fn validate(model: &format::Model) -> Result<(), DatabaseQueryError> {
+        let quotas = [model.max_records, model.max_records_per_day];
+        if quotas.into_iter().flatten().any(|quota| quota < 0) {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "format quotas cannot be negative".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Retention periods are either 0 (keep forever) or at least
+    /// MIN_RETENTION_MINUTES.
+    fn validate_retention(minutes: i32) -> Result<(), DatabaseQueryError> {
+        let min = Config::get().min_retention_minutes as i32;
+        match minutes {
+            ..=-1 => Err(DatabaseQueryError::InvalidUsage(format!(
+                "retention period cannot be negative ({minutes} minutes), \
+                 use 0 to keep records forever"
+            ))),
+            1.. if minutes < min => Err(DatabaseQueryError::InvalidUsage(format!(
+                "retention period must be at least {min} minutes ({minutes} minutes given), \
+                 or 0 to keep records forever"
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Schemas need at least one column. Column names must be unique (ignoring
+    /// case), non-empty, at most `MAX_COLUMN_NAME_LENGTH` characters long and
+    /// free of control characters. Records are JSON objects keyed by column
+    /// name, so the name is all there is to tell columns apart.
+    pub fn validate_schema(schema: &FormatSchema) -> Result<(), DatabaseQueryError> {
+        let invalid = |reason: String| Err(DatabaseQueryError::InvalidUsage(reason));
+        if schema.is_empty() {
+            return invalid("the schema must have at least one column".into());
+        }
+        let mut names = HashSet::new();
+        for (index, column) in schema.iter().enumerate() {
+            let name = &column.name;
+            if name.trim().is_empty() {
+                return invalid(format!("the name of column {index} is empty"));
+            }
+            if name.chars().count() > MAX_COLUMN_NAME_LENGTH {
+                return invalid(format!(
+                    "column name '{name}' is longer than {MAX_COLUMN_NAME_LENGTH} characters"
+                ));
+            }
+            if name.chars().any(char::is_control) {
+                return invalid(format!("column name {name:?} contains control characters"));
+            }
+            if !names.insert(name.to_lowercase()) {
+                return invalid(format!(
+                    "duplicate column name '{name}' (column names are case-insensitive)"
+                ));
+            }
+        }
+        Ok(())
     }
 
-    pub async fn delete(id: i32) -> Result<DeleteResult, DbErr> {
-        let db = DBConfig::get_connection();
+    /// Every rule compares a column against another column of the same kind,
+    /// or against a constant of that kind.
+    pub fn validate_rules(
+        schema: &FormatSchema,
+        rules: &FormatRules,
+    ) -> Result<(), DatabaseQueryError> {
+        let kind_of = |name: &String| {
+            schema
+                .iter()
+                .find(|column| &column.name == name)
+                .map(|column| &column.kind)
+        };
+        for (index, rule) in rules.iter().enumerate() {
+            let invalid = |reason: String| {
+                Err(DatabaseQueryError::InvalidUsage(format!(
+                    "rule {index} ({rule}): {reason}"
+                )))
+            };
+            let Some(kind) = kind_of(&rule.left_column) else {
+                return invalid(format!("column '{}' doesn't exist", rule.left_column));
+            };
+            match (&rule.right_column, &rule.constant) {
+                (Some(right), None) => match kind_of(right) {
+                    None => return invalid(format!("column '{right}' doesn't exist")),
+                    Some(right_kind) if right_kind != kind => {
+                        return invalid(format!(
+                            "can't compare a {kind:?} column to a {right_kind:?} column"
+                        ))
+                    }
+                    _ => {}
+                },
+                (None, Some(constant)) => {
+                    let valid = match kind {
+                        ColumnKind::Number => constant.is_number(),
+                        ColumnKind::String => constant.is_string(),
+                        ColumnKind::Datetime => {
+                            constant.as_str().and_then(str_to_isodate).is_some()
+                        }
+                    };
+                    if !valid {
+                        return invalid(format!("the constant isn't a valid {kind:?}"));
+                    }
+                }
+                _ => return invalid("set either `rightColumn` or `constant`".into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Update a format's metadata and quotas. The schema and rules can't be
+    /// changed, since existing records were validated against them.
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
+        old: format::Model,
+        new: format::UpdatableModel,
+    ) -> Result<format::Model, DatabaseQueryError> {
+        let mut model = old.into_active_model();
+        if let Some(name) = new.name {
+            model.name = Set(name);
+        }
+        if let Some(description) = new.description {
+            model.description = Set(description);
+        }
+        if let Some(retention_period_minutes) = new.retention_period_minutes {
+            Self::validate_retention(retention_period_minutes)?;
+            model.retention_period_minutes = Set(retention_period_minutes);
+        }
+        if let Some(max_records) = new.max_records {
+            model.max_records = Set(max_records);
+        }
+        if let Some(max_records_per_day) = new.max_records_per_day {
+            model.max_records_per_day = Set(max_records_per_day);
+        }
+        if let Some(archived) = new.archived {
+            model.archived = Set(archived);
+        }
+        model.updated_at = Set(chrono::offset::Utc::now());
+        Self::validate(&model.clone().try_into_model()?)?;
+        let model = model.update(db).await?;
+        ColumnKindCache::invalidate();
+        Ok(model)
+    }
+
+    /// Make sure `format_id` can take `count` more records without exceeding
+    /// its quotas.
+    ///
+    /// This must be called inside the upload transaction: if the format has a
+    /// quota, its row stays locked until the transaction ends, so concurrent
+    /// uploads to the same format can't both squeeze in under the cap. Formats
+    /// without quotas aren't locked.
+    pub async fn check_quota<C: ConnectionTrait>(
+        db: &C,
+        format_id: i32,
+        count: i64,
+    ) -> Result<(), DatabaseQueryError> {
+        let find = |lock: bool| {
+            let mut query = Format::find_by_id(format_id);
+            if lock {
+                query = query.lock_exclusive();
+            }
+            query.one(db)
+        };
+        let format = find(false)
+            .await?
+            .ok_or(DbErr::RecordNotFound("format".into()))?;
+        if format.max_records.is_none() && format.max_records_per_day.is_none() {
+            return Ok(());
+        }
+        // the quotas may have changed in the meantime.
+        let format = find(true)
+            .await?
+            .ok_or(DbErr::RecordNotFound("format".into()))?;
+
+        if let Some(max_records) = format.max_records {
+            let used = Self::count_ingested_records(db, format_id, None).await?;
+            Self::verify_quota(max_records, used, count, "in total")?;
+        }
+        if let Some(max_records_per_day) = format.max_records_per_day {
+            let today = chrono::offset::Utc::now()
+                .date_naive()
+                .and_time(chrono::NaiveTime::MIN)
+                .and_utc();
+            let used = Self::count_ingested_records(db, format_id, Some(today)).await?;
+            Self::verify_quota(max_records_per_day, used, count, "per day")?;
+        }
+        Ok(())
+    }
+
+    fn verify_quota(
+        quota: i64,
+        used: i64,
+        count: i64,
+        period: &str,
+    ) -> Result<(), DatabaseQueryError> {
+        let remaining = (quota - used).max(0);
+        if count > remaining {
+            info!("quota exceeded: {count} > {remaining} ({period})");
+            return Err(DatabaseQueryError::QuotaExceeded(format!(
+                "format accepts up to {quota} records {period}, {remaining} remaining, \
+                 but the upload contains {count}"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count the records ingested into a format, optionally only those uploaded
+    /// after `since`.
+    ///
+    /// Records can only be deleted along with their upload session, so summing
+    /// the successful sessions' record counts gives the same result as counting
+    /// the records themselves, while only touching the (much smaller, indexed)
+    /// upload session table.
+    async fn count_ingested_records<C: ConnectionTrait>(
+        db: &C,
+        format_id: i32,
+        since: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<i64, DbErr> {
+        let mut query = upload_session::Entity::find()
+            .select_only()
+            .column_as(upload_session::Column::RecordCount.sum(), "count")
+            .filter(upload_session::Column::FormatId.eq(format_id))
+            .filter(upload_session::Column::Outcome.eq(OutcomeKind::Success));
+        if let Some(since) = since {
+            query = query.filter(upload_session::Column::CreatedAt.gte(since));
+        }
+        let count = query.into_tuple::<Option<i64>>().one(db).await?;
+        Ok(count.flatten().unwrap_or_default())
+    }
+
+    pub async fn delete<C: ConnectionTrait>(db: &C, id: i32) -> Result<DeleteResult, DbErr> {
         let format: format::ActiveModel = Format::find_by_id(id)
             .one(db)
             .await?
             .ok_or(DbErr::RecordNotFound("format".into()))
             .map(Into::into)?;
 
-        format.delete(db).await
+        let result = format.delete(db).await?;
+        ColumnKindCache::invalidate();
+        Ok(result)
     }
 
     // Get all the formats with items that can be pruned.
     #[inline]
-    pub async fn get_prunable_formats(
-        db: &DatabaseConnection,
+    pub async fn get_prunable_formats<C: ConnectionTrait>(
+        db: &C,
     ) -> Result<Vec<format::Model>, DbErr> {
         // Note: This makes the entire thing faster because we'll only filter
         // formats that have data. Formats without data are automatically
@@ -73,7 +334,8 @@ impl FormatMutation {
             .distinct();
         let subquery = formats_with_data.as_query();
 
-        // A retention period of 0 minutes means keep forever.
+        // A retention period of 0 minutes means keep forever. Negative ones
+        // can't be set anymore, but may be left over from older versions.
         format::Entity::find()
             .filter(format::Column::RetentionPeriodMinutes.gt(0))
             .filter(format::Column::Id.in_subquery(subquery.to_owned()))
@@ -82,13 +344,69 @@ impl FormatMutation {
     }
 }
 
-#[derive(BetterDebug, Serialize, Deserialize)]
+#[derive(BetterDebug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UploadSessionPruneResult {
-    format_id: i32,
-    format_name: String,
-    pruned_created_at_before: chrono::DateTime<chrono::Utc>,
-    delete_count: u64,
+    pub format_id: i32,
+    pub format_name: String,
+    pub pruned_created_at_before: chrono::DateTime<chrono::Utc>,
+    pub delete_count: u64,
+    /// Records of the pruned upload sessions.
+    pub record_count: u64,
+    /// Set if the record table was analyzed after this prune.
+    pub maintenance: Option<PruneMaintenance>,
+}
+
+#[derive(BetterDebug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneMaintenance {
+    /// The statement that was run, e.g. `ANALYZE record`.
+    pub statement: String,
+    pub duration_ms: u64,
+}
+
+/// What pruning a format would delete right now, see
+/// [`UploadSessionMutation::preview_prune`].
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PrunePreview {
+    pub format_id: i32,
+    pub retention_period_minutes: i32,
+    /// Upload sessions created before this are past retention. Unset if the
+    /// format keeps its records forever.
+    pub cutoff: Option<chrono::DateTime<chrono::Utc>>,
+    pub upload_sessions: u64,
+    /// Records of those upload sessions.
+    pub records: u64,
+}
+
+/// What was removed along with an upload session.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSessionDeleteResult {
+    pub session_id: i32,
+    pub format_id: i32,
+    pub records_deleted: u64,
+}
+
+/// What was reassigned by [`UserMutation::transfer`].
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTransferResult {
+    pub upload_sessions: u64,
+    pub saved_searches: u64,
+}
+
+/// Truncate `detail` to `UPLOAD_SESSION_DETAIL_MAX_LENGTH` characters,
+/// ellipsis included.
+fn bounded_detail(detail: String) -> String {
+    let max_length = Config::get().upload_session_detail_max_length as usize;
+    if detail.chars().count() <= max_length {
+        return detail;
+    }
+    let mut bounded = detail.chars().take(max_length - 1).collect::<String>();
+    bounded.push('…');
+    bounded
 }
 
 pub struct UploadSessionMutation;
@@ -97,58 +415,244 @@ impl UploadSessionMutation {
     /// This function performs the following actions:
     ///
     ///  1. Get all the formats with data that can be pruned. This will automatically
-    ///  exclude formats without data or whose retention period is not set.
+    ///     exclude formats without data or whose retention period is not set.
     ///  2. Get all the upload sessions that are older than the retention period.
     ///  3. Delete the upload sessions.
-    pub async fn prune_old_items() -> Result<Vec<UploadSessionPruneResult>, DbErr> {
+    ///  4. If enabled, analyze the record table (see [`Self::analyze_after_prune`]).
+    pub async fn prune_old_items(
+        db: &DatabaseConnection,
+    ) -> Result<Vec<UploadSessionPruneResult>, DbErr> {
         let now = chrono::offset::Utc::now();
         info!("pruner: running job, start date = {now:?}");
-        let db = DBConfig::get_connection();
         let formats = FormatMutation::get_prunable_formats(db).await?;
         info!("pruner: found {} format(s) with data", formats.len());
         let mut prune_results = Vec::new();
 
         for format in formats {
-            let offset = Duration::from_secs(format.retention_period_minutes as u64 * 60);
-            let created_at_before = now - offset;
-
-            let delete = upload_session::Entity::delete_many()
-                .filter(upload_session::Column::CreatedAt.lt(created_at_before))
-                .filter(upload_session::Column::FormatId.eq(format.id))
-                .exec(db)
-                .await?;
-            if delete.rows_affected == 0 {
+            let prune_result = Self::prune_format(db, format, now, false).await?;
+            if prune_result.delete_count == 0 {
                 continue;
             }
-            info!(
-                "pruner: format '{}' (id={}): pruned {} upload sessions, created_at={:?}",
-                format.name, format.id, delete.rows_affected, created_at_before
-            );
-            let prune_result = UploadSessionPruneResult {
-                pruned_created_at_before: created_at_before,
-                format_id: format.id,
-                format_name: format.name,
-                delete_count: delete.rows_affected,
-            };
             prune_results.push(prune_result);
         }
+        Self::analyze_after_prune(db, &mut prune_results).await;
         info!("pruner: job completed");
         Ok(prune_results)
     }
 
-    pub async fn create(model: upload_session::Model) -> Result<upload_session::Model, DbErr> {
-        let db = DBConfig::get_connection();
+    /// Run `ANALYZE record` (or `VACUUM (ANALYZE) record`) if a format lost at
+    /// least `PRUNE_ANALYZE_MIN_RECORDS` records, and record it in the results
+    /// of those formats. VACUUM can't run inside a transaction, hence the plain
+    /// connection. Errors are only logged: the records are gone either way.
+    async fn analyze_after_prune(
+        db: &DatabaseConnection,
+        prune_results: &mut [UploadSessionPruneResult],
+    ) {
+        let config = Config::get();
+        let min_records = config.prune_analyze_min_records;
+        if !config.prune_analyze_after
+            || !prune_results
+                .iter()
+                .any(|result| result.record_count >= min_records)
+        {
+            return;
+        }
+        let statement = match config.prune_analyze_vacuum {
+            true => "VACUUM (ANALYZE) record",
+            false => "ANALYZE record",
+        };
+        let start = Instant::now();
+        if let Err(err) = db.execute_unprepared(statement).await {
+            error!("pruner: {statement} failed: {err}");
+            return;
+        }
+        let duration_ms = start.elapsed().as_millis() as u64;
+        info!("pruner: {statement} finished in {duration_ms}ms");
+        let maintenance = PruneMaintenance {
+            statement: statement.into(),
+            duration_ms,
+        };
+        prune_results
+            .iter_mut()
+            .filter(|result| result.record_count >= min_records)
+            .for_each(|result| result.maintenance = Some(maintenance.clone()));
+    }
+
+    /// The upload sessions of `format` that are past retention at `now`:
+    /// those created before `now` minus the format's retention period (the
+    /// returned cutoff). Shared by the pruner and [`Self::preview_prune`], so
+    /// the preview counts exactly what a prune would delete.
+    fn prune_candidates(
+        format: &format::Model,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> (chrono::DateTime<chrono::Utc>, Condition) {
+        let offset = Duration::from_secs(format.retention_period_minutes as u64 * 60);
+        let created_at_before = now - offset;
+        // in-progress sessions are left alone until they either finish or
+        // get flagged as stuck by fail_stuck_sessions().
+        let condition = Condition::all()
+            .add(upload_session::Column::CreatedAt.lt(created_at_before))
+            .add(upload_session::Column::FormatId.eq(format.id))
+            .add(upload_session::Column::Outcome.ne(OutcomeKind::InProgress));
+        (created_at_before, condition)
+    }
+
+    /// Number of upload sessions matching `condition` and their records.
+    /// Failed sessions are counted, but their `record_count` (the records
+    /// they received) isn't, since none of them were saved.
+    async fn count_prune_candidates<C: ConnectionTrait>(
+        db: &C,
+        condition: Condition,
+    ) -> Result<(u64, u64), DbErr> {
+        let saved_records = Expr::case(
+            upload_session::Column::Outcome.eq(OutcomeKind::Success),
+            Expr::col(upload_session::Column::RecordCount),
+        )
+        .finally(0);
+        let (sessions, records): (i64, Option<i64>) = upload_session::Entity::find()
+            .select_only()
+            .column_as(upload_session::Column::Id.count(), "sessions")
+            .column_as(SimpleExpr::from(Func::sum(saved_records)), "records")
+            .filter(condition)
+            .into_tuple()
+            .one(db)
+            .await?
+            .unwrap_or_default();
+        Ok((sessions as u64, records.unwrap_or_default() as u64))
+    }
+
+    /// What pruning `format` at `now` would delete, without deleting anything.
+    pub async fn preview_prune<C: ConnectionTrait>(
+        db: &C,
+        format: &format::Model,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<PrunePreview, DbErr> {
+        let mut preview = PrunePreview {
+            format_id: format.id,
+            retention_period_minutes: format.retention_period_minutes,
+            cutoff: None,
+            upload_sessions: 0,
+            records: 0,
+        };
+        // like get_prunable_formats(), formats kept forever are never pruned.
+        if format.retention_period_minutes <= 0 {
+            return Ok(preview);
+        }
+        let (cutoff, condition) = Self::prune_candidates(format, now);
+        let (upload_sessions, records) = Self::count_prune_candidates(db, condition).await?;
+        preview.cutoff = Some(cutoff);
+        preview.upload_sessions = upload_sessions;
+        preview.records = records;
+        Ok(preview)
+    }
+
+    /// Prune the upload sessions of a single format that were created
+    /// before `now` minus the format's retention period.
+    /// If `dry_run` is set, nothing is deleted and the returned delete count
+    /// is the number of upload sessions that would have been pruned.
+    pub async fn prune_format<C: ConnectionTrait>(
+        db: &C,
+        format: format::Model,
+        now: chrono::DateTime<chrono::Utc>,
+        dry_run: bool,
+    ) -> Result<UploadSessionPruneResult, DbErr> {
+        let (created_at_before, condition) = Self::prune_candidates(&format, now);
+        let (candidates, record_count) =
+            Self::count_prune_candidates(db, condition.clone()).await?;
+        let delete_count = if dry_run {
+            candidates
+        } else {
+            upload_session::Entity::delete_many()
+                .filter(condition)
+                .exec(db)
+                .await?
+                .rows_affected
+        };
+        if delete_count > 0 && !dry_run {
+            info!(
+                "pruner: format '{}' (id={}): pruned {} upload sessions, created_at={:?}",
+                format.name, format.id, delete_count, created_at_before
+            );
+        }
+        Ok(UploadSessionPruneResult {
+            pruned_created_at_before: created_at_before,
+            format_id: format.id,
+            format_name: format.name,
+            delete_count,
+            record_count,
+            maintenance: None,
+        })
+    }
+
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: upload_session::Model,
+    ) -> Result<upload_session::Model, DbErr> {
+        let detail = bounded_detail(model.detail.clone());
         let mut model = model.into_active_model();
         model.id = NotSet;
         model.created_at = Set(chrono::offset::Utc::now());
+        model.detail = Set(detail);
         model.insert(db).await
     }
 
-    pub async fn update_as_failed<I: Into<i32>, S: Into<String>>(
+    pub async fn set_outcome<C: ConnectionTrait, S: Into<String>>(
+        db: &C,
+        model: upload_session::Model,
+        outcome: OutcomeKind,
+        detail: S,
+        processing_ms: i64,
+    ) -> Result<upload_session::Model, DbErr> {
+        let mut model = model.into_active_model();
+        model.outcome = Set(outcome);
+        model.detail = Set(bounded_detail(detail.into()));
+        model.processing_ms = Set(processing_ms);
+        model.update(db).await
+    }
+
+    /// Set the content hash of an in-progress session once its records were
+    /// validated (async uploads get their session before that).
+    pub async fn set_content_hash<C: ConnectionTrait>(
+        db: &C,
+        model: upload_session::Model,
+        content_hash: String,
+        payload_bytes: i64,
+    ) -> Result<upload_session::Model, DbErr> {
+        let mut model = model.into_active_model();
+        model.content_hash = Set(Some(content_hash));
+        model.payload_bytes = Set(payload_bytes);
+        model.update(db).await
+    }
+
+    /// Mark upload sessions that have been in progress since before
+    /// `created_at_before` as failed. Those are left behind if the server
+    /// dies in the middle of an upload.
+    pub async fn fail_stuck_sessions<C: ConnectionTrait>(
+        db: &C,
+        created_at_before: chrono::DateTime<chrono::Utc>,
+    ) -> Result<u64, DbErr> {
+        let result = upload_session::Entity::update_many()
+            .col_expr(
+                upload_session::Column::Outcome,
+                Expr::value(OutcomeKind::Error),
+            )
+            .col_expr(
+                upload_session::Column::Detail,
+                Expr::value("upload did not finish in time"),
+            )
+            .filter(upload_session::Column::Outcome.eq(OutcomeKind::InProgress))
+            .filter(upload_session::Column::CreatedAt.lt(created_at_before))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    pub async fn update_as_failed<C: ConnectionTrait, I: Into<i32>, S: Into<String>>(
+        db: &C,
         upload_session_id: I,
         detail: S,
-    ) -> Result<(), DbErr> {
-        let db = DBConfig::get_connection();
+        processing_ms: i64,
+    ) -> Result<upload_session::Model, DbErr> {
         let session = upload_session::Entity::find_by_id(upload_session_id)
             .one(db)
             .await?;
@@ -156,38 +660,73 @@ impl UploadSessionMutation {
             Some(found) => {
                 let mut found = found.into_active_model();
                 found.outcome = Set(OutcomeKind::Error);
-                found.detail = Set(detail.into());
-                found.update(db).await.map(|_| Ok(()))?
+                found.detail = Set(bounded_detail(detail.into()));
+                found.processing_ms = Set(processing_ms);
+                found.update(db).await
             }
             _ => Err(DbErr::RecordNotFound("Not found".into())),
         }
     }
 
+    /// Same as [`Self::update_as_failed`], but only if the session is still in
+    /// progress. Returns the session if it was failed.
+    pub async fn fail_if_in_progress<C: ConnectionTrait, S: Into<String>>(
+        db: &C,
+        upload_session_id: i32,
+        detail: S,
+        processing_ms: i64,
+    ) -> Result<Option<upload_session::Model>, DbErr> {
+        let mut failed = upload_session::Entity::update_many()
+            .col_expr(
+                upload_session::Column::Outcome,
+                Expr::value(OutcomeKind::Error),
+            )
+            .col_expr(
+                upload_session::Column::Detail,
+                Expr::value(bounded_detail(detail.into())),
+            )
+            .col_expr(
+                upload_session::Column::ProcessingMs,
+                Expr::value(processing_ms),
+            )
+            .filter(upload_session::Column::Id.eq(upload_session_id))
+            .filter(upload_session::Column::Outcome.eq(OutcomeKind::InProgress))
+            .exec_with_returning(db)
+            .await?;
+        Ok(failed.pop())
+    }
+
     #[inline]
-    pub async fn delete(user: user::Model, id: i32) -> Result<(), DatabaseQueryError> {
-        match user.is_superuser {
-            true => Self::delete_by_id(id).await,
-            false => Self::delete_non_superuser(user, id).await,
-        }
+    pub async fn delete<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        user: user::Model,
+        id: i32,
+    ) -> Result<UploadSessionDeleteResult, DatabaseQueryError> {
+        let user_id = user.id;
+        let result = match user.is_superuser {
+            true => Self::delete_by_id(db, id).await,
+            false => Self::delete_non_superuser(db, user, id).await,
+        }?;
+        info!(
+            "user {user_id} deleted upload session {} of format {} and its {} records",
+            result.session_id, result.format_id, result.records_deleted
+        );
+        Ok(result)
     }
 
     #[inline(always)]
-    pub async fn delete_non_superuser(
+    pub async fn delete_non_superuser<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
         user: user::Model,
         id: i32,
-    ) -> Result<(), DatabaseQueryError> {
-        let db = DBConfig::get_connection();
+    ) -> Result<UploadSessionDeleteResult, DatabaseQueryError> {
         // Get the formats the user has access to.
         let user_formats = format_entitlement::Entity::find()
             .filter(format_entitlement::Column::UserId.eq(user.id));
-        let user_formats_subquery = user_formats
-            .clone()
-            .select_only()
-            .column(format_entitlement::Column::FormatId);
-        let formats_for_user_query = user_formats_subquery.as_query();
-        let upload_session = upload_session::Entity::find()
-            .filter(upload_session::Column::Id.eq(id))
-            .filter(upload_session::Column::FormatId.in_subquery(formats_for_user_query.to_owned()))
+        // sessions the user can't see are a 404, sessions they can see but
+        // not delete (e.g. as an auditor) are a 403.
+        let select = upload_session::Entity::find().filter(upload_session::Column::Id.eq(id));
+        let upload_session = UploadSessionQuery::filter_out_select(&user, select)
             .one(db)
             .await?
             .ok_or_else(|| DbErr::RecordNotFound("upload session".into()))?;
@@ -221,9 +760,7 @@ impl UploadSessionMutation {
 
         // Users with `Delete` permission can delete any upload session, no
         // matter when it was created.
-        if entitlement.access.contains(&AccessLevel::Delete) {
-            Self::delete_by_id(id).await?;
-        } else if entitlement.access.contains(&AccessLevel::LimitedDelete) {
+        if !entitlement.access.contains(&AccessLevel::Delete) {
             let now = chrono::offset::Utc::now();
             let delta = now - upload_session.created_at;
             if delta > chrono::Duration::hours(Config::get().temporal_delete_hours as i64) {
@@ -233,84 +770,388 @@ impl UploadSessionMutation {
                 );
                 return Err(DatabaseQueryError::InsufficientPermissions);
             }
-            Self::delete_by_id(id).await?;
         }
-        Ok(())
+        Self::delete_by_id(db, id).await
     }
 
-    pub async fn delete_by_id(id: i32) -> Result<(), DatabaseQueryError> {
-        let db = DBConfig::get_connection();
-        let result = upload_session::Entity::delete_by_id(id).exec(db).await?;
-        if result.rows_affected != 1 {
-            return Err(DatabaseQueryError::from(DbErr::RecordNotFound(format!(
-                "upload session with id '{id}'"
-            ))));
-        }
-        Ok(())
+    /// Attribute every upload session of user `from` to user `to`.
+    pub async fn transfer<C: ConnectionTrait>(db: &C, from: Uuid, to: Uuid) -> Result<u64, DbErr> {
+        let result = upload_session::Entity::update_many()
+            .col_expr(upload_session::Column::UserId, Expr::value(to))
+            .filter(upload_session::Column::UserId.eq(from))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// Delete an upload session. Its records would be deleted by the cascade
+    /// anyway, but deleting them first tells how many there were.
+    pub async fn delete_by_id<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        id: i32,
+    ) -> Result<UploadSessionDeleteResult, DatabaseQueryError> {
+        let txn = db.begin().await?;
+        let upload_session = upload_session::Entity::find_by_id(id)
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound(format!("upload session with id '{id}'")))?;
+        let records_deleted = Record::delete_many()
+            .filter(record::Column::UploadSessionId.eq(id))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        upload_session::Entity::delete_by_id(id).exec(&txn).await?;
+        txn.commit().await?;
+        Ok(UploadSessionDeleteResult {
+            session_id: id,
+            format_id: upload_session.format_id,
+            records_deleted,
+        })
     }
 }
 
 pub struct RecordMutation;
 impl RecordMutation {
+    /// Insert `entries`, encrypting their data first if `encrypt` is set (i.e.
+    /// they belong to an encrypted format).
     #[inline(always)]
-    pub async fn create_many<I>(entries: I) -> Result<u64, DbErr>
+    pub async fn create_many<C, I>(db: &C, entries: I, encrypt: bool) -> Result<u64, DbErr>
     where
+        C: ConnectionTrait,
         I: IntoIterator<Item = record::Model>,
     {
-        let db = DBConfig::get_connection();
-        let converted = entries.into_iter().map(|entry| record::ActiveModel {
-            upload_session_id: Set(entry.upload_session_id),
-            format_id: Set(entry.format_id),
-            data: Set(entry.data),
-            ..Default::default()
-        });
+        let converted = entries
+            .into_iter()
+            .map(|mut entry| {
+                if encrypt {
+                    RecordCipher::encrypt(&mut entry)?;
+                }
+                Ok(record::ActiveModel {
+                    upload_session_id: Set(entry.upload_session_id),
+                    format_id: Set(entry.format_id),
+                    created_at: Set(entry.created_at),
+                    data: Set(entry.data),
+                    encrypted_data: Set(entry.encrypted_data),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<Vec<_>, DbErr>>()?;
         Record::insert_many(converted)
             .exec_without_returning(db)
             .await
     }
 }
 
+/// Outcome of [`UserMutation::bootstrap_superuser`].
+#[derive(Debug)]
+pub enum SuperuserBootstrap {
+    Created(user::Model),
+    /// There's a superuser already, nothing was done.
+    SuperuserExists,
+    /// There are no superusers, but a regular user has the requested username.
+    UsernameTaken(user::Model),
+}
+
+// Key of the advisory lock held while bootstrapping the initial superuser.
+const BOOTSTRAP_LOCK_KEY: i64 = 0x6272_6570_6f5f_6273;
+
 pub struct UserMutation;
 impl UserMutation {
-    pub async fn create(user: user::Model) -> Result<user::Model, DbErr> {
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        user: user::Model,
+    ) -> Result<user::Model, DbErr> {
         let mut user = user::ActiveModel::from(user);
-        let db = DBConfig::get_connection();
         user.id = Set(Uuid::new_v4());
         user.insert(db).await
     }
 
-    pub async fn update(
+    /// Create `users` in a single transaction, one savepoint per user. The
+    /// passwords must already be hashed.
+    ///
+    /// Returns the outcome of every user, in order. Without `all_or_nothing`
+    /// the users that could be inserted are committed; with it, nothing is
+    /// committed unless every insert succeeded.
+    pub async fn create_many<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        users: Vec<user::Model>,
+        all_or_nothing: bool,
+    ) -> Result<Vec<Result<user::Model, DbErr>>, DbErr> {
+        let txn = db.begin().await?;
+        let mut results = Vec::with_capacity(users.len());
+        for user in users {
+            let savepoint = txn.begin().await?;
+            match Self::create(&savepoint, user).await {
+                Ok(user) => {
+                    savepoint.commit().await?;
+                    results.push(Ok(user));
+                }
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    results.push(Err(err));
+                }
+            }
+        }
+        let failed = results.iter().filter(|result| result.is_err()).count();
+        match all_or_nothing && failed > 0 {
+            true => txn.rollback().await?,
+            false => txn.commit().await?,
+        }
+        info!(
+            "bulk user creation: {} created, {failed} failed",
+            results.len() - failed
+        );
+        Ok(results)
+    }
+
+    /// Create the initial superuser, but only if there are no superusers yet.
+    /// `user`'s password must already be hashed.
+    ///
+    /// Instances starting at the same time are serialized by an advisory lock,
+    /// so at most one of them creates the superuser.
+    pub async fn bootstrap_superuser<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        mut user: user::Model,
+    ) -> Result<SuperuserBootstrap, DbErr> {
+        let txn = db.begin().await?;
+        txn.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT pg_advisory_xact_lock($1)",
+            [BOOTSTRAP_LOCK_KEY.into()],
+        ))
+        .await?;
+        let superuser = user::Entity::find()
+            .filter(user::Column::IsSuperuser.eq(true))
+            .one(&txn)
+            .await?;
+        if let Some(superuser) = superuser {
+            debug!(
+                "bootstrap: superuser {:?} already exists, skipping",
+                superuser.username
+            );
+            return Ok(SuperuserBootstrap::SuperuserExists);
+        }
+        // usernames are unique regardless of case.
+        let existing = user::Entity::find()
+            .filter(
+                Expr::expr(Func::lower(Expr::col(user::Column::Username)))
+                    .eq(Func::lower(Expr::val(user.username.as_str()))),
+            )
+            .one(&txn)
+            .await?;
+        if let Some(existing) = existing {
+            return Ok(SuperuserBootstrap::UsernameTaken(existing));
+        }
+        user.is_superuser = true;
+        user.active = true;
+        user.created_at = chrono::offset::Utc::now();
+        let user = Self::create(&txn, user).await?;
+        txn.commit().await?;
+        Ok(SuperuserBootstrap::Created(user))
+    }
+
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
         old_user: user::Model,
         new_user: user::UpdatableModel,
     ) -> Result<user::Model, DbErr> {
-        let db = DBConfig::get_connection();
         let mut user = old_user.into_active_model();
         user.username = new_user.username.map(Set).unwrap_or(NotSet);
         user.password = new_user.password.map(Set).unwrap_or(NotSet);
         user.is_superuser = new_user.is_superuser.map(Set).unwrap_or(NotSet);
         user.active = new_user.active.map(Set).unwrap_or(NotSet);
+        user.email = new_user.email.map(Set).unwrap_or(NotSet);
+        user.role = new_user.role.map(Set).unwrap_or(NotSet);
+        user.max_storage_bytes = new_user.max_storage_bytes.map(Set).unwrap_or(NotSet);
         user.update(db).await
     }
+
+    /// Reassign what user `from` owns to user `to`, all or nothing: its upload
+    /// sessions and, with `saved_searches`, its saved searches. Deleting `from`
+    /// afterwards doesn't take them along.
+    pub async fn transfer<C: ConnectionTrait + TransactionTrait>(
+        db: &C,
+        from: Uuid,
+        to: Uuid,
+        saved_searches: bool,
+    ) -> Result<UserTransferResult, DbErr> {
+        let txn = db.begin().await?;
+        let upload_sessions = UploadSessionMutation::transfer(&txn, from, to).await?;
+        let saved_searches = match saved_searches {
+            true => SavedSearchMutation::transfer(&txn, from, to).await?,
+            false => 0,
+        };
+        txn.commit().await?;
+        info!(
+            "transferred {upload_sessions} upload sessions and {saved_searches} saved searches \
+             from user {from} to user {to}"
+        );
+        Ok(UserTransferResult {
+            upload_sessions,
+            saved_searches,
+        })
+    }
+
+    /// Fail if uploading `bytes` would take user `user_id` over its storage
+    /// quota, if it has one.
+    ///
+    /// Like [`FormatMutation::check_quota`], this must be called inside the
+    /// upload transaction: the user row stays locked until it ends, so
+    /// concurrent uploads of the same user can't both squeeze in under the cap.
+    /// Users without a quota aren't locked.
+    pub async fn check_storage_quota<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+        bytes: i64,
+    ) -> Result<(), DatabaseQueryError> {
+        let find = |lock: bool| {
+            let mut query = user::Entity::find_by_id(user_id)
+                .select_only()
+                .column(user::Column::MaxStorageBytes)
+                // kept up to date by a trigger on the upload sessions.
+                .column_as(Expr::col(Alias::new("used_storage_bytes")), "used");
+            if lock {
+                query = query.lock_exclusive();
+            }
+            query.into_tuple::<(Option<i64>, i64)>().one(db)
+        };
+        let (quota, _) = find(false)
+            .await?
+            .ok_or(DbErr::RecordNotFound("user".into()))?;
+        if quota.is_none() {
+            return Ok(());
+        }
+        // the quota may have changed in the meantime.
+        let (Some(quota), used) = find(true)
+            .await?
+            .ok_or(DbErr::RecordNotFound("user".into()))?
+        else {
+            return Ok(());
+        };
+        let remaining = (quota - used).max(0);
+        if bytes > remaining {
+            info!("storage quota exceeded: {bytes} > {remaining} (user {user_id})");
+            return Err(DatabaseQueryError::QuotaExceeded(format!(
+                "user may upload up to {quota} bytes, {remaining} remaining, \
+                 but the upload is {bytes} bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Start (or restart) a TOTP enrollment. 2FA stays disabled until it's
+    /// confirmed with [`Self::confirm_totp`].
+    pub async fn enroll_totp<C: ConnectionTrait>(
+        db: &C,
+        user: user::Model,
+        secret: String,
+        recovery_codes: Vec<String>,
+    ) -> Result<user::Model, DbErr> {
+        let mut user = user.into_active_model();
+        user.totp_secret = Set(Some(secret));
+        user.totp_enabled = Set(false);
+        user.totp_recovery_codes = Set(user::RecoveryCodes(recovery_codes));
+        user.totp_last_step = Set(None);
+        user.update(db).await
+    }
+
+    /// Enable 2FA, `step` being the time step of the first valid code.
+    pub async fn confirm_totp<C: ConnectionTrait>(
+        db: &C,
+        user: user::Model,
+        step: i64,
+    ) -> Result<user::Model, DbErr> {
+        let mut user = user.into_active_model();
+        user.totp_enabled = Set(true);
+        user.totp_last_step = Set(Some(step));
+        user.update(db).await
+    }
+
+    /// Disable 2FA and forget the secret and recovery codes.
+    pub async fn reset_totp<C: ConnectionTrait>(
+        db: &C,
+        user: user::Model,
+    ) -> Result<user::Model, DbErr> {
+        let mut user = user.into_active_model();
+        user.totp_secret = Set(None);
+        user.totp_enabled = Set(false);
+        user.totp_recovery_codes = Set(user::RecoveryCodes::default());
+        user.totp_last_step = Set(None);
+        user.update(db).await
+    }
+
+    /// Mark the TOTP code for `step` as used. Returns `false` if a code for
+    /// this (or a later) step was already used, i.e. the code is being replayed.
+    pub async fn use_totp_step<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+        step: i64,
+    ) -> Result<bool, DbErr> {
+        let result = user::Entity::update_many()
+            .col_expr(user::Column::TotpLastStep, Expr::value(step))
+            .filter(user::Column::Id.eq(user_id))
+            .filter(
+                Condition::any()
+                    .add(user::Column::TotpLastStep.is_null())
+                    .add(user::Column::TotpLastStep.lt(step)),
+            )
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
+
+    /// Remove the recovery code at `index` from `user`'s unused codes. Returns
+    /// `false` if the codes changed in the meantime (e.g. a concurrent login
+    /// used the same code).
+    pub async fn use_totp_recovery_code<C: ConnectionTrait>(
+        db: &C,
+        user: &user::Model,
+        index: usize,
+    ) -> Result<bool, DbErr> {
+        let mut remaining = user.totp_recovery_codes.clone();
+        remaining.0.remove(index);
+        let result = user::Entity::update_many()
+            .col_expr(user::Column::TotpRecoveryCodes, Expr::value(remaining))
+            .filter(user::Column::Id.eq(user.id))
+            .filter(user::Column::TotpRecoveryCodes.eq(user.totp_recovery_codes.clone()))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected == 1)
+    }
 }
 
 pub struct FormatEntitlementMutation;
 
 impl FormatEntitlementMutation {
-    pub async fn create(
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
         model: format_entitlement::Model,
+        created_by: Option<Uuid>,
     ) -> Result<format_entitlement::Model, DbErr> {
-        let db = DBConfig::get_connection();
-        format_entitlement::ActiveModel::from(model)
-            .insert(db)
-            .await
+        let mut model = format_entitlement::ActiveModel::from(model);
+        model.created_by = Set(created_by);
+        model.updated_at = model.created_at.clone();
+        model.insert(db).await
+    }
+
+    /// Replace the access levels of an existing entitlement.
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
+        old: format_entitlement::Model,
+        access: format_entitlement::Access,
+    ) -> Result<format_entitlement::Model, DbErr> {
+        let mut model = old.into_active_model();
+        model.access = Set(access);
+        model.updated_at = Set(chrono::offset::Utc::now());
+        model.update(db).await
     }
 }
 
 pub struct ApiKeyMutation;
 
 impl ApiKeyMutation {
-    pub async fn delete(model: api_key::Model) -> Result<(), DbErr> {
-        let db = DBConfig::get_connection();
+    pub async fn delete<C: ConnectionTrait>(db: &C, model: api_key::Model) -> Result<(), DbErr> {
         api_key::Entity::delete_by_id(model.id)
             .exec(db)
             .await
@@ -318,8 +1159,10 @@ impl ApiKeyMutation {
     }
 
     /// Create an API Key for this user.
-    pub async fn create_for_user(user: &user::Model) -> Result<api_key::Model, DbErr> {
-        let db = DBConfig::get_connection();
+    pub async fn create_for_user<C: ConnectionTrait>(
+        db: &C,
+        user: &user::Model,
+    ) -> Result<api_key::Model, DbErr> {
         let now = chrono::offset::Utc::now();
         api_key::ActiveModel {
             user_id: Set(user.id),
@@ -333,11 +1176,11 @@ impl ApiKeyMutation {
         .try_into_model()
     }
 
-    pub async fn update(
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
         old: api_key::Model,
         new: api_key::UpdatableModel,
     ) -> Result<api_key::Model, DbErr> {
-        let db = DBConfig::get_connection();
         let mut model = old.into_active_model();
         // User enabled 'rotate' option, so let's just rotate this api key.
         if new.rotate.unwrap_or(false) {
@@ -349,3 +1192,167 @@ impl ApiKeyMutation {
         model.update(db).await
     }
 }
+
+pub struct SavedSearchMutation;
+
+impl SavedSearchMutation {
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: saved_search::Model,
+    ) -> Result<saved_search::Model, DbErr> {
+        let now = chrono::offset::Utc::now();
+        let mut model = model.into_active_model();
+        model.id = NotSet;
+        model.created_at = Set(now);
+        model.updated_at = Set(now);
+        model.insert(db).await
+    }
+
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
+        old: saved_search::Model,
+        new: saved_search::UpdatableModel,
+    ) -> Result<saved_search::Model, DbErr> {
+        let mut model = old.into_active_model();
+        model.name = new.name.map(Set).unwrap_or(NotSet);
+        model.description = new.description.map(Set).unwrap_or(NotSet);
+        model.query = new.query.map(Set).unwrap_or(NotSet);
+        model.parameters = new.parameters.map(Set).unwrap_or(NotSet);
+        model.shared = new.shared.map(Set).unwrap_or(NotSet);
+        model.updated_at = Set(chrono::offset::Utc::now());
+        model.update(db).await
+    }
+
+    pub async fn delete<C: ConnectionTrait>(
+        db: &C,
+        model: saved_search::Model,
+    ) -> Result<DeleteResult, DbErr> {
+        model.delete(db).await
+    }
+
+    /// Give every saved search of user `from` to user `to`. Shares of those
+    /// searches with `to` are dropped, since it owns them now.
+    pub async fn transfer<C: ConnectionTrait>(db: &C, from: Uuid, to: Uuid) -> Result<u64, DbErr> {
+        let searches = saved_search::Entity::find()
+            .select_only()
+            .column(saved_search::Column::Id)
+            .filter(saved_search::Column::UserId.eq(from))
+            .into_query();
+        saved_search_share::Entity::delete_many()
+            .filter(saved_search_share::Column::UserId.eq(to))
+            .filter(saved_search_share::Column::SavedSearchId.in_subquery(searches))
+            .exec(db)
+            .await?;
+        let result = saved_search::Entity::update_many()
+            .col_expr(saved_search::Column::UserId, Expr::value(to))
+            .col_expr(
+                saved_search::Column::UpdatedAt,
+                Expr::value(chrono::offset::Utc::now()),
+            )
+            .filter(saved_search::Column::UserId.eq(from))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+pub struct SavedSearchShareMutation;
+
+impl SavedSearchShareMutation {
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: saved_search_share::Model,
+    ) -> Result<saved_search_share::Model, DbErr> {
+        let mut model = model.into_active_model();
+        model.created_at = Set(chrono::offset::Utc::now());
+        model.insert(db).await
+    }
+
+    pub async fn delete<C: ConnectionTrait>(
+        db: &C,
+        model: saved_search_share::Model,
+    ) -> Result<DeleteResult, DbErr> {
+        model.delete(db).await
+    }
+}
+
+pub struct WebhookMutation;
+
+impl WebhookMutation {
+    /// Verify the user-editable fields of a webhook.
+    fn validate(model: &webhook::Model) -> Result<(), DatabaseQueryError> {
+        match reqwest::Url::parse(&model.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => {
+                return Err(DatabaseQueryError::InvalidUsage(
+                    "webhook url must be a valid http(s) url".into(),
+                ))
+            }
+        }
+        if model.secret.is_empty() {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "webhook secret cannot be empty".into(),
+            ));
+        }
+        if model.events.is_empty() {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "webhook must subscribe to at least one event".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: webhook::Model,
+    ) -> Result<webhook::Model, DatabaseQueryError> {
+        Self::validate(&model)?;
+        let mut model = model.into_active_model();
+        model.id = NotSet;
+        model.created_at = Set(chrono::offset::Utc::now());
+        model.insert(db).await.map_err(Into::into)
+    }
+
+    pub async fn update<C: ConnectionTrait>(
+        db: &C,
+        old: webhook::Model,
+        new: webhook::UpdatableModel,
+    ) -> Result<webhook::Model, DatabaseQueryError> {
+        let mut model = old.into_active_model();
+        if let Some(url) = new.url {
+            model.url = Set(url);
+        }
+        if let Some(secret) = new.secret {
+            model.secret = Set(secret);
+        }
+        if let Some(events) = new.events {
+            model.events = Set(events);
+        }
+        if let Some(active) = new.active {
+            model.active = Set(active);
+        }
+        Self::validate(&model.clone().try_into_model()?)?;
+        model.update(db).await.map_err(Into::into)
+    }
+
+    pub async fn delete<C: ConnectionTrait>(
+        db: &C,
+        model: webhook::Model,
+    ) -> Result<DeleteResult, DbErr> {
+        model.delete(db).await
+    }
+}
+
+pub struct WebhookDeliveryMutation;
+
+impl WebhookDeliveryMutation {
+    pub async fn create<C: ConnectionTrait>(
+        db: &C,
+        model: webhook_delivery::Model,
+    ) -> Result<webhook_delivery::Model, DbErr> {
+        let mut model = model.into_active_model();
+        model.id = NotSet;
+        model.created_at = Set(chrono::offset::Utc::now());
+        model.insert(db).await
+    }
+}
@@ -1,12 +1,13 @@
 use crate::{conf::DBConfig, traits::*};
-use ::entity::user;
+use ::entity::{error::DatabaseQueryError, user};
 use central_repository_config::inner::Config;
-use futures::{try_join, Stream};
+use futures::Stream;
 use log::{debug, info};
 use sea_orm::*;
 use sea_query::{Alias, Expr, SelectStatement};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use utoipa::IntoParams;
 
 /// This trait provides sorted + filtered + paginated searches
 /// for any type implementing the 3 associated types.
@@ -38,8 +39,9 @@ fn default_full_count() -> bool {
     Config::get().return_query_count
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, IntoParams)]
 #[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
 pub struct PaginationOptions {
     /// The page to fetch.
     #[serde(default = "default_page")]
@@ -50,6 +52,9 @@ pub struct PaginationOptions {
     /// Whether to fetch items and page count.
     #[serde(default = "default_full_count")]
     pub count: bool,
+    /// Only run the count query and return no items (used for HEAD requests).
+    #[serde(skip)]
+    pub count_only: bool,
 }
 
 impl PaginationOptions {
@@ -58,6 +63,18 @@ impl PaginationOptions {
     pub fn is_valid(&self) -> bool {
         self.per_page > 0 && self.per_page <= Config::get().max_pagination_size
     }
+
+    /// Offset of the first item of the requested page.
+    #[inline(always)]
+    pub fn offset(&self) -> u64 {
+        self.page.saturating_mul(self.per_page)
+    }
+
+    /// Whether the requested page comes after the last one, i.e. it's empty.
+    #[inline(always)]
+    pub fn is_past_last_page(&self, num_pages: u64) -> bool {
+        self.page >= num_pages
+    }
 }
 
 #[allow(async_fn_in_trait)]
@@ -104,16 +121,23 @@ pub trait GetAllPaginated<'db>: GetAllTrait<'db> {
     }
 
     /// Apply default sorting column and, additionally, filter
-    /// using any fields the user passed.
+    /// using any fields the user passed. Fails if the requested sort column
+    /// doesn't exist.
     fn apply_filters(
         filters: &Self::FilterQueryModel,
         select_stmt: Option<sea_orm::Select<Self::Entity>>,
-    ) -> Select<Self::Entity> {
+    ) -> Result<Select<Self::Entity>, DatabaseQueryError> {
         debug!("filter params: {:#?}", filters);
         let mut select_stmt = select_stmt.unwrap_or_else(Self::Entity::find);
         select_stmt = filters.filter(select_stmt);
-        select_stmt = filters.sort(select_stmt);
-        select_stmt
+        select_stmt = filters.sort(select_stmt)?;
+        // Rows with the same value in the sort column (e.g. all the records of an
+        // upload share their createdAt) come back in no particular order, so
+        // they could show up in two pages or in none. The primary key breaks ties.
+        for column in <Self::Entity as EntityTrait>::PrimaryKey::iter() {
+            select_stmt = select_stmt.order_by_asc(column.into_column());
+        }
+        Ok(select_stmt)
     }
 
     /// Get all available items as a stream.
@@ -126,10 +150,11 @@ pub trait GetAllPaginated<'db>: GetAllTrait<'db> {
     async fn get_all_as_stream(
         filters: &Self::FilterQueryModel,
         select_stmt: Option<sea_orm::Select<Self::Entity>>,
-    ) -> Result<impl Stream<Item = Result<Self::ResultModel, DbErr>> + 'db + Send, DbErr> {
+    ) -> Result<impl Stream<Item = Result<Self::ResultModel, DbErr>> + 'db + Send, DatabaseQueryError>
+    {
         let db = DBConfig::get_connection();
-        let select = Self::apply_filters(filters, select_stmt);
-        select.stream(db).await
+        let select = Self::apply_filters(filters, select_stmt)?;
+        Ok(select.stream(db).await?)
     }
 
     /// Get all available items using pagination.
@@ -137,10 +162,15 @@ pub trait GetAllPaginated<'db>: GetAllTrait<'db> {
         filters: &Self::FilterQueryModel,
         pagination_options: &PaginationOptions,
         select_stmt: Option<sea_orm::Select<Self::Entity>>,
-    ) -> Result<(Vec<Self::ResultModel>, u64, u64), DbErr> {
+    ) -> Result<(Vec<Self::ResultModel>, u64, u64), DatabaseQueryError> {
         let db = DBConfig::get_connection();
         debug!("pagination options: {:#?}", pagination_options);
-        let mut select = Self::apply_filters(filters, select_stmt);
+        let mut select = Self::apply_filters(filters, select_stmt)?;
+        if pagination_options.count_only {
+            let (num_pages, num_items) =
+                Self::num_items_and_pages(&mut select, pagination_options.per_page).await?;
+            return Ok((vec![], num_pages, num_items));
+        }
         let select_ordered = select.clone();
 
         // Create paginators.
@@ -151,14 +181,16 @@ pub trait GetAllPaginated<'db>: GetAllTrait<'db> {
 
         if pagination_options.count {
             info!("executing potentially slow query");
-            // let paginator = select.paginate(db, pagination_options.page_size);
-            let items_and_pages_fut =
-                Self::num_items_and_pages(&mut select, pagination_options.per_page);
-            // if items and pages is enabled, run two queries concurrently:
-            // - a normal SELECT query
-            // - a COUNT(*) query
-            let (items, (num_pages, num_items)) = try_join!(pagination_fut, items_and_pages_fut)?;
-            return Ok((items, num_pages, num_items));
+            // if items and pages is enabled, run the COUNT(*) query first:
+            // pages past the last one are empty, so there's no need to run
+            // the (potentially huge) OFFSET query for them.
+            let (num_pages, num_items) =
+                Self::num_items_and_pages(&mut select, pagination_options.per_page).await?;
+            if pagination_options.is_past_last_page(num_pages) {
+                debug!("page {} is past the last page", pagination_options.page);
+                return Ok((vec![], num_pages, num_items));
+            }
+            return Ok((pagination_fut.await?, num_pages, num_items));
         }
         // if items and pages is disabled, run a single query
         Ok((pagination_fut.await?, 0, 0))
@@ -170,7 +202,7 @@ pub trait GetAllPaginated<'db>: GetAllTrait<'db> {
         pagination_options: &PaginationOptions,
         user: user::Model,
         select_stmt: Option<sea_orm::Select<Self::Entity>>,
-    ) -> Result<(Vec<Self::ResultModel>, u64, u64), DbErr> {
+    ) -> Result<(Vec<Self::ResultModel>, u64, u64), DatabaseQueryError> {
         let mut select_stmt = select_stmt.unwrap_or_else(Self::Entity::find);
         select_stmt = Self::filter_out_select(&user, select_stmt);
         Self::get_all(filters, pagination_options, Some(select_stmt)).await
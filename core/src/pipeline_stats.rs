@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// How the stages of an export behaved, appended to CSV exports requested with
+/// `?pipelineStats=true`. Shared between the stream and its workers, which
+/// add their own numbers once they're done.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStats {
+    // The database streams, waiting for rows from the database and blocked
+    // on a full database queue.
+    pub streams: Vec<WorkerStats>,
+    // The workers serializing the rows, waiting for rows from the database
+    // queue and blocked on a full result queue.
+    pub transform_workers: Vec<WorkerStats>,
+    // Rows fetched from the database, waiting for a transform worker.
+    pub db_queue: QueueStats,
+    // Serialized rows, waiting to be sent to the client.
+    pub result_queue: QueueStats,
+    pub elapsed_ms: u64,
+}
+
+impl PipelineStats {
+    pub(crate) fn new(queue_capacity: usize) -> Self {
+        Self {
+            db_queue: QueueStats::new(queue_capacity),
+            result_queue: QueueStats::new(queue_capacity),
+            ..Default::default()
+        }
+    }
+
+    /// Sort the workers, which report in the order they finished.
+    pub(crate) fn finish(&mut self, elapsed: Duration) {
+        self.streams.sort_unstable_by_key(|worker| worker.worker);
+        self.transform_workers
+            .sort_unstable_by_key(|worker| worker.worker);
+        self.elapsed_ms = elapsed.as_millis() as u64;
+    }
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerStats {
+    pub worker: usize,
+    pub items: u64,
+    // Time spent waiting for the next item.
+    pub waiting_ms: u64,
+    // Time spent waiting for room in the next queue: high values mean the
+    // next stage is the bottleneck.
+    pub blocked_ms: u64,
+}
+
+impl WorkerStats {
+    pub(crate) fn new(worker: usize) -> Self {
+        Self {
+            worker,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn waited(&mut self, duration: Duration) {
+        self.waiting_ms += duration.as_millis() as u64;
+    }
+
+    pub(crate) fn blocked(&mut self, duration: Duration) {
+        self.blocked_ms += duration.as_millis() as u64;
+    }
+}
+
+/// Lengths of a queue, sampled while the export runs.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub capacity: usize,
+    pub samples: u64,
+    pub mean_len: f64,
+    pub max_len: usize,
+    // Samples in which the queue was full.
+    pub full_samples: u64,
+}
+
+impl QueueStats {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn sample(&mut self, len: usize) {
+        self.samples += 1;
+        self.mean_len += (len as f64 - self.mean_len) / self.samples as f64;
+        self.max_len = self.max_len.max(len);
+        if len >= self.capacity {
+            self.full_samples += 1;
+        }
+    }
+}
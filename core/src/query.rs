@@ -1,31 +1,56 @@
-use std::sync::Arc;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    conf::DBConfig, pagination_impl::GetAllTrait, CoreError, GetAllPaginated, LimitGrant,
-    PaginationOptions, PreparedSearchQuery, SearchQuery,
+    conf::DBConfig, pagination_impl::GetAllTrait, ColumnStats, ColumnStatsQuery, CoreError,
+    ExportManifest, GetAllPaginated, LimitGrant, ManifestColumn, PaginationOptions, PipelineStats,
+    PreparedSearchQuery, RecordChanges, RecordChangesQuery, RecordCipher, SearchQuery, WorkerStats,
+    PSQL_TZ_CAST,
 };
 use ::entity::{
     api_key,
     error::DatabaseQueryError,
     format,
-    format::Entity as Format,
+    format::{ColumnKind, Entity as Format},
     format_entitlement::{
         self, AccessLevel, SearchModel as FormatEntitlementSearch, ARRAY_CONTAINS_OP,
     },
-    record, upload_session, user,
+    record, saved_search, saved_search_share, upload_session, user,
     user::Entity as User,
+    webhook::{self, WebhookEvent},
+    webhook_delivery,
 };
 use async_stream::stream;
 use central_repository_config::inner::Config;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
 use futures::{Stream, StreamExt};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use sea_orm::*;
-use sea_query::Expr;
+use sea_query::{
+    extension::postgres::PgBinOper, Alias, CaseStatement, Expr, Func, Query, SimpleExpr,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tracing::Span;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+// How often the queues of an export are sampled for its pipeline stats.
+const QUEUE_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
 // Fixed headers for CSV exports
-const FIXED_HEADERS: &str = r#""ID","FormatId","UploadSessionId""#;
+const FIXED_HEADERS: &str = "ID,FormatId,UploadSessionId,CreatedAt";
+const FIXED_HEADER_KINDS: [ColumnKind; 4] = [
+    ColumnKind::Number,
+    ColumnKind::Number,
+    ColumnKind::Number,
+    ColumnKind::Datetime,
+];
 
 // Query objects
 pub struct UploadSessionQuery;
@@ -33,8 +58,13 @@ pub struct FormatEntitlementQuery;
 pub struct FormatQuery;
 pub struct UserQuery;
 pub struct RecordQuery;
+pub struct SavedSearchQuery;
+pub struct SavedSearchShareQuery;
+pub struct WebhookQuery;
+pub struct WebhookDeliveryQuery;
 
 pub struct ApiKeyQuery;
+pub struct AdminQuery;
 
 pub struct ParallelStreamConfig {
     num_streams: usize,
@@ -50,6 +80,14 @@ impl ParallelStreamConfig {
             num_transform_threads,
         }
     }
+
+    pub fn num_streams(&self) -> usize {
+        self.num_streams
+    }
+
+    pub fn num_transform_threads(&self) -> usize {
+        self.num_transform_threads
+    }
 }
 
 impl Default for ParallelStreamConfig {
@@ -63,6 +101,49 @@ impl Default for ParallelStreamConfig {
     }
 }
 
+/// What CSV exports of searches contain, besides the matching records.
+#[derive(Debug, Default)]
+pub struct RecordExportOptions {
+    /// Export these columns (in this order) instead of the default ones.
+    pub columns: Option<Vec<String>>,
+    /// See [`PreparedSearchQuery::export_columns`].
+    pub columns_from_query: bool,
+    /// Append an [`ExportManifest`] after the last row.
+    pub manifest: bool,
+    /// Append the [`PipelineStats`] of the export after the last row (and
+    /// the manifest).
+    pub pipeline_stats: bool,
+    /// The request this export belongs to, named in the `#error` trailer.
+    pub request_id: Option<String>,
+}
+
+/// Output format of record exports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One CSV row per record, with a header row.
+    #[default]
+    Csv,
+    /// One JSON record per line.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+        }
+    }
+}
+
 impl GetAllTrait<'_> for UserQuery {
     type Entity = user::Entity;
     type FilterQueryModel = user::ModelAsQuery;
@@ -78,7 +159,8 @@ impl GetAllTrait<'_> for FormatQuery {
         user: &user::Model,
         select: Select<Self::Entity>,
     ) -> sea_orm::Select<Self::Entity> {
-        if !user.is_superuser {
+        // auditors can see every format, but not necessarily its records.
+        if !user.has_role(user::Role::Auditor) {
             info!("filtering available formats for user {:?}", user.id);
             let filter = format_entitlement::Entity::find()
                 .select_only()
@@ -86,7 +168,9 @@ impl GetAllTrait<'_> for FormatQuery {
                 .filter(format_entitlement::Column::UserId.eq(user.id));
             let subquery = filter.as_query();
 
-            return select.filter(format::Column::Id.in_subquery(subquery.to_owned()));
+            return select
+                .filter(format::Column::Id.in_subquery(subquery.to_owned()))
+                .filter(format::Column::Archived.eq(false));
         }
         select
     }
@@ -101,7 +185,7 @@ impl GetAllTrait<'_> for FormatEntitlementQuery {
         user: &user::Model,
         select: Select<Self::Entity>,
     ) -> sea_orm::Select<Self::Entity> {
-        if !user.is_superuser {
+        if !user.has_role(user::Role::Auditor) {
             return select.filter(format_entitlement::Column::UserId.eq(user.id));
         }
         select
@@ -117,7 +201,7 @@ impl GetAllTrait<'_> for UploadSessionQuery {
         user: &user::Model,
         select: Select<Self::Entity>,
     ) -> sea_orm::Select<Self::Entity> {
-        if !user.is_superuser {
+        if !user.has_role(user::Role::Auditor) {
             let formats_for_user = format_entitlement::Entity::find()
                 .select_only()
                 .column(format_entitlement::Column::FormatId)
@@ -146,7 +230,7 @@ impl GetAllTrait<'_> for ApiKeyQuery {
         user: &user::Model,
         mut select: Select<Self::Entity>,
     ) -> sea_orm::Select<Self::Entity> {
-        if !user.is_superuser {
+        if !user.has_role(user::Role::Admin) {
             info!("filtering available keys for user {:?}", user.id);
             select = select.filter(api_key::Column::UserId.eq(user.id));
         }
@@ -154,6 +238,42 @@ impl GetAllTrait<'_> for ApiKeyQuery {
     }
 }
 
+impl GetAllTrait<'_> for SavedSearchQuery {
+    type FilterQueryModel = saved_search::ModelAsQuery;
+    type ResultModel = saved_search::Model;
+    type Entity = saved_search::Entity;
+
+    fn filter_out_select(
+        user: &user::Model,
+        select: Select<Self::Entity>,
+    ) -> sea_orm::Select<Self::Entity> {
+        if !user.is_superuser {
+            return select.filter(SavedSearchQuery::visible_to(user));
+        }
+        select
+    }
+}
+
+// Shares are only listed for the owner of the search, see the API.
+impl GetAllTrait<'_> for SavedSearchShareQuery {
+    type FilterQueryModel = saved_search_share::ModelAsQuery;
+    type ResultModel = saved_search_share::Model;
+    type Entity = saved_search_share::Entity;
+}
+
+// Webhooks are only accessible to admins, so there's nothing to filter out.
+impl GetAllTrait<'_> for WebhookQuery {
+    type FilterQueryModel = webhook::ModelAsQuery;
+    type ResultModel = webhook::Model;
+    type Entity = webhook::Entity;
+}
+
+impl GetAllTrait<'_> for WebhookDeliveryQuery {
+    type FilterQueryModel = webhook_delivery::ModelAsQuery;
+    type ResultModel = webhook_delivery::Model;
+    type Entity = webhook_delivery::Entity;
+}
+
 // Custom impl's for weird usecases.
 
 impl RecordQuery {
@@ -164,36 +284,307 @@ impl RecordQuery {
         prepared_search: PreparedSearchQuery,
     ) -> Result<(Vec<record::Model>, u64, u64), DatabaseQueryError> {
         let select = prepared_search.apply_condition(record::Entity::find())?;
-        RecordQuery::get_all(filters, pagination_options, Some(select))
-            .await
-            .map_err(DatabaseQueryError::from)
+        let (mut records, num_pages, num_items) =
+            RecordQuery::get_all(filters, pagination_options, Some(select)).await?;
+        RecordCipher::decrypt_all(&mut records)?;
+        Ok((records, num_pages, num_items))
+    }
+
+    /// Same as [`Self::filter_readable_records`], but only the fixed columns of
+    /// every record are selected, not their data.
+    pub async fn filter_readable_records_dataless(
+        filters: &record::ModelAsQuery,
+        pagination_options: &PaginationOptions,
+        prepared_search: PreparedSearchQuery,
+    ) -> Result<(Vec<record::DatalessRecord>, u64, u64), DatabaseQueryError> {
+        let db = DBConfig::get_connection();
+        let select = prepared_search.apply_condition(record::Entity::find())?;
+        let mut select = RecordQuery::apply_filters(filters, Some(select))?;
+        let per_page = pagination_options.per_page;
+        if pagination_options.count_only {
+            let (num_pages, num_items) =
+                RecordQuery::num_items_and_pages(&mut select, per_page).await?;
+            return Ok((vec![], num_pages, num_items));
+        }
+        let paginator = select
+            .clone()
+            .select_only()
+            .columns([
+                record::Column::Id,
+                record::Column::UploadSessionId,
+                record::Column::FormatId,
+            ])
+            .into_model::<record::DatalessRecord>()
+            .paginate(db, per_page);
+        let page_fut = paginator.fetch_page(pagination_options.page);
+        if pagination_options.count {
+            // see `get_all`: pages past the last one aren't fetched.
+            let (num_pages, num_items) =
+                RecordQuery::num_items_and_pages(&mut select, per_page).await?;
+            if pagination_options.is_past_last_page(num_pages) {
+                return Ok((vec![], num_pages, num_items));
+            }
+            return Ok((page_fut.await?, num_pages, num_items));
+        }
+        Ok((page_fut.await?, 0, 0))
+    }
+
+    /// Get the records added after `query.since_id` in the formats `auth` can read,
+    /// ordered by id. This doesn't support any search conditions, so it only
+    /// needs the (indexed) primary key and format id.
+    pub async fn get_changes(
+        auth: &user::Model,
+        query: RecordChangesQuery,
+    ) -> Result<RecordChanges, DatabaseQueryError> {
+        let db = DBConfig::get_connection();
+        let limit = query.limit.unwrap_or(Config::get().max_changes_limit);
+        let prepared_search = query.get_readable_formats_for_user(auth).await?;
+        // fetch an extra record to know whether there are more records left.
+        let mut records = record::Entity::find()
+            .filter(prepared_search.limit_visible_records())
+            .filter(record::Column::Id.gt(query.since_id))
+            .order_by_asc(record::Column::Id)
+            .limit(limit + 1)
+            .all(db)
+            .await?;
+        let has_more = records.len() as u64 > limit;
+        records.truncate(limit as usize);
+        RecordCipher::decrypt_all(&mut records)?;
+        let last_id = records.last().map_or(query.since_id, |it| it.id);
+        Ok(RecordChanges {
+            records,
+            last_id,
+            has_more,
+        })
+    }
+
+    /// Profile `query.column` over the records matching `query.search`, with a
+    /// single aggregate query. The query is cancelled by the database after
+    /// COLUMN_STATS_TIMEOUT_SECONDS.
+    pub async fn column_stats(
+        auth: &user::Model,
+        query: ColumnStatsQuery,
+    ) -> Result<ColumnStats, DatabaseQueryError> {
+        let ColumnStatsQuery { column, search } = query;
+        let prepared_search = search.get_readable_formats_for_user(auth).await?;
+        prepared_search.reject_encrypted_formats()?;
+        let kind = prepared_search.column_kind(&column)?;
+        let value = Expr::col(record::Column::Data)
+            .binary(PgBinOper::CastJsonField, Expr::val(column.as_str()));
+        let mut select = prepared_search
+            .apply_condition(record::Entity::find())?
+            .select_only()
+            .column_as(Expr::cust("COUNT(*)"), "count")
+            .column_as(SimpleExpr::from(Func::count(value.clone())), "present")
+            .column_as(
+                SimpleExpr::from(Func::count_distinct(value.clone())),
+                "distinct",
+            );
+        select = match kind {
+            ColumnKind::Number => {
+                let value = value.cast_as(Alias::new("FLOAT"));
+                select
+                    .column_as(SimpleExpr::from(Func::min(value.clone())), "min")
+                    .column_as(SimpleExpr::from(Func::max(value.clone())), "max")
+                    .column_as(SimpleExpr::from(Func::avg(value)), "mean")
+            }
+            ColumnKind::Datetime => {
+                let value = value.cast_as(Alias::new(PSQL_TZ_CAST));
+                select
+                    .column_as(SimpleExpr::from(Func::min(value.clone())), "min")
+                    .column_as(SimpleExpr::from(Func::max(value)), "max")
+            }
+            ColumnKind::String => {
+                let length = SimpleExpr::from(Func::char_length(value.clone()));
+                select
+                    .column_as(SimpleExpr::from(Func::min(value.clone())), "min")
+                    .column_as(SimpleExpr::from(Func::max(value)), "max")
+                    .column_as(SimpleExpr::from(Func::min(length.clone())), "min_length")
+                    .column_as(SimpleExpr::from(Func::max(length.clone())), "max_length")
+                    .column_as(
+                        Expr::expr(Func::avg(length)).cast_as(Alias::new("FLOAT")),
+                        "mean_length",
+                    )
+            }
+        };
+
+        let statement = select.build(DbBackend::Postgres);
+        let timeout_ms = Config::get().column_stats_timeout_seconds * 1000;
+        let row = DBConfig::transaction::<_, _, DbErr>(|txn| {
+            Box::pin(async move {
+                txn.execute_unprepared(&format!("SET LOCAL statement_timeout = {timeout_ms}"))
+                    .await?;
+                txn.query_one(statement).await
+            })
+        })
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("column stats".into()))?;
+
+        let count = row.try_get::<i64>("", "count")? as u64;
+        let mut stats = ColumnStats {
+            column,
+            kind: kind.clone(),
+            count,
+            missing: count - row.try_get::<i64>("", "present")? as u64,
+            distinct: row.try_get::<i64>("", "distinct")? as u64,
+            min: None,
+            max: None,
+            mean: None,
+            min_length: None,
+            max_length: None,
+            mean_length: None,
+        };
+        match kind {
+            ColumnKind::Number => {
+                let get = |name| row.try_get::<Option<f64>>("", name);
+                stats.min = get("min")?.map(Value::from);
+                stats.max = get("max")?.map(Value::from);
+                stats.mean = get("mean")?;
+            }
+            ColumnKind::Datetime => {
+                let get = |name| {
+                    row.try_get::<Option<DateTime<Utc>>>("", name).map(|date| {
+                        date.map(|date| {
+                            Value::from(date.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+                        })
+                    })
+                };
+                stats.min = get("min")?;
+                stats.max = get("max")?;
+            }
+            ColumnKind::String => {
+                let get_length = |name| {
+                    row.try_get::<Option<i32>>("", name)
+                        .map(|length| length.map(|length| length as u64))
+                };
+                stats.min = row.try_get::<Option<String>>("", "min")?.map(Value::from);
+                stats.max = row.try_get::<Option<String>>("", "max")?.map(Value::from);
+                stats.min_length = get_length("min_length")?;
+                stats.max_length = get_length("max_length")?;
+                stats.mean_length = row.try_get::<Option<f64>>("", "mean_length")?;
+            }
+        }
+        Ok(stats)
     }
 
     pub async fn filter_readable_records_stream(
         auth: user::Model,
         filters: &record::ModelAsQuery,
         query: SearchQuery,
+        options: RecordExportOptions,
         parallel_stream_config: ParallelStreamConfig,
         limit_grant: Option<LimitGrant>,
     ) -> Result<impl Stream<Item = Vec<u8>>, CoreError> {
-        let db = DBConfig::get_connection();
-        let prepared_search = query
-            .get_readable_formats_for_user(&auth)
-            .await
-            .map_err(DatabaseQueryError::from)?;
-        let schema_columns = Arc::new(prepared_search.schema_columns());
-
-        let mut headers = schema_columns
-            .iter()
-            .map(|col| format!("{:?}", col))
-            .collect::<Vec<_>>()
-            .join(",");
-        headers = format!("{FIXED_HEADERS},{headers}\n");
+        let prepared_search = query.get_readable_formats_for_user(&auth).await?;
+        let schema_columns = match options.columns {
+            Some(columns) => prepared_search.selected_columns(columns)?,
+            None => prepared_search.export_columns(options.columns_from_query),
+        };
+        let manifest = options.manifest.then(|| {
+            let mut formats = prepared_search.get_readable_format_ids();
+            formats.sort_unstable();
+            let fixed_columns = FIXED_HEADERS
+                .split(',')
+                .zip(FIXED_HEADER_KINDS)
+                .map(|(name, kind)| (name.to_string(), Some(kind)));
+            let schema_kinds = prepared_search.export_column_kinds(&schema_columns);
+            let columns = fixed_columns
+                .chain(schema_columns.iter().cloned().zip(schema_kinds))
+                .map(|(name, kind)| ManifestColumn { name, kind })
+                .collect();
+            ExportManifest {
+                formats,
+                columns,
+                rows: 0,
+                sha256: String::new(),
+            }
+        });
 
         // apply conditions and filters.
         let mut select = record::Entity::find().order_by_asc(record::Column::Id);
         select = prepared_search.apply_condition(select)?;
-        select = RecordQuery::apply_filters(filters, Some(select));
+        select = RecordQuery::apply_filters(filters, Some(select))?;
+
+        Self::stream_select(
+            select,
+            schema_columns,
+            ExportFormat::Csv,
+            manifest,
+            options.pipeline_stats,
+            parallel_stream_config,
+            limit_grant,
+            options.request_id,
+        )
+        .await
+    }
+
+    /// Stream all the records of `upload_session`, which must belong to `format`.
+    /// The CSV columns follow the order of the format's schema.
+    pub async fn upload_session_stream(
+        upload_session: &upload_session::Model,
+        format: &format::Model,
+        export_format: ExportFormat,
+        parallel_stream_config: ParallelStreamConfig,
+        limit_grant: Option<LimitGrant>,
+        request_id: Option<String>,
+    ) -> Result<impl Stream<Item = Vec<u8>>, CoreError> {
+        let schema_columns = format
+            .schema
+            .iter()
+            .map(|schema| schema.name.clone())
+            .collect();
+        let select = record::Entity::find()
+            .filter(record::Column::UploadSessionId.eq(upload_session.id))
+            .order_by_asc(record::Column::Id);
+        Self::stream_select(
+            select,
+            schema_columns,
+            export_format,
+            None,
+            false,
+            parallel_stream_config,
+            limit_grant,
+            request_id,
+        )
+        .await
+    }
+
+    /// Stream the records matched by `select` using multiple database streams,
+    /// serializing them as `export_format`. Only `schema_columns` are exported
+    /// to CSV (in this order). If there's a `manifest`, its row count and hash
+    /// are filled in while streaming and it's written after the last row,
+    /// followed by the [`PipelineStats`] with `pipeline_stats`.
+    #[allow(clippy::too_many_arguments)]
+    async fn stream_select(
+        select: Select<record::Entity>,
+        schema_columns: Vec<String>,
+        export_format: ExportFormat,
+        mut manifest: Option<ExportManifest>,
+        pipeline_stats: bool,
+        parallel_stream_config: ParallelStreamConfig,
+        limit_grant: Option<LimitGrant>,
+        request_id: Option<String>,
+    ) -> Result<impl Stream<Item = Vec<u8>>, CoreError> {
+        let db = DBConfig::get_connection();
+        let schema_columns = Arc::new(schema_columns);
+        let started = Instant::now();
+        let stats = pipeline_stats.then(|| {
+            Arc::new(Mutex::new(PipelineStats::new(
+                parallel_stream_config.num_queue_items,
+            )))
+        });
+
+        let headers = match export_format {
+            ExportFormat::Csv => {
+                // formats without columns only get the fixed headers.
+                let headers = schema_columns
+                    .iter()
+                    .map(|col| format!(",{}", csv_field(col)))
+                    .collect::<String>();
+                Some(format!("{FIXED_HEADERS}{headers}\n"))
+            }
+            ExportFormat::Ndjson => None,
+        };
 
         let mut limit = None;
 
@@ -218,6 +609,7 @@ impl RecordQuery {
             debug!("streaming: not issuing COUNT as there's only 1 stream");
         }
 
+        let failure = ExportFailure::new(request_id);
         let (tx_db_stream, rx_db_stream) = flume::bounded(parallel_stream_config.num_queue_items);
         let (tx_result, rx_result) = flume::bounded(parallel_stream_config.num_queue_items);
 
@@ -227,17 +619,45 @@ impl RecordQuery {
             let thread_select = select.clone().limit(limit).offset(offset);
             debug!("stream worker {stream_thread}: offset: {offset} limit: {limit:?}");
             let thread_tx_db_stream = tx_db_stream.clone();
+            let thread_stats = stats.clone();
+            let thread_failure = failure.clone();
             tokio::spawn(async move {
-                let mut received = 0;
-                let mut stream = thread_select.stream(db).await?;
-                while let Some(Ok(item)) = stream.next().await {
-                    received += 1;
+                let mut worker = WorkerStats::new(stream_thread);
+                let mut waiting = Instant::now();
+                let mut stream = match thread_select.stream(db).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        error!("stream_thread {stream_thread}: {err}");
+                        thread_failure.set("cannot read records");
+                        return;
+                    }
+                };
+                while let Some(item) = stream.next().await {
+                    let item = match item {
+                        Ok(item) if !thread_failure.is_set() => item,
+                        Ok(_) => break,
+                        Err(err) => {
+                            error!("stream_thread {stream_thread}: {err}");
+                            thread_failure.set("cannot read records");
+                            break;
+                        }
+                    };
+                    worker.waited(waiting.elapsed());
+                    worker.items += 1;
+                    let blocked = Instant::now();
                     if thread_tx_db_stream.send_async(item).await.is_err() {
                         break;
                     }
+                    worker.blocked(blocked.elapsed());
+                    waiting = Instant::now();
+                }
+                debug!(
+                    "stream_thread: {stream_thread}: received {} items",
+                    worker.items
+                );
+                if let Some(stats) = thread_stats {
+                    stats.lock().unwrap().streams.push(worker);
                 }
-                debug!("stream_thread: {stream_thread}: received {received} items");
-                Ok::<_, DatabaseQueryError>(())
             });
         }
         drop(tx_db_stream);
@@ -246,34 +666,49 @@ impl RecordQuery {
             let rx_db_stream_thread = rx_db_stream.clone();
             let tx_result_thread = tx_result.clone();
             let schema_columns_thread = schema_columns.clone();
+            let thread_stats = stats.clone();
+            let thread_failure = failure.clone();
             tokio::spawn(async move {
-                let mut processed = 0;
-                while let Ok(item) = rx_db_stream_thread.recv_async().await {
-                    processed += 1;
-                    let mut row = schema_columns_thread
-                        .iter()
-                        .map(|column| {
-                            item.data
-                                .get(column)
-                                .map_or("".into(), |value| format!("{}", value))
-                        })
-                        .collect::<Vec<_>>()
-                        .join(",");
-                    // Build CSV row.
-                    row = format!(
-                        "{},{},{},{row}\n",
-                        item.id, item.format_id, item.upload_session_id
-                    );
-                    if tx_result_thread.send_async(row.into_bytes()).await.is_err() {
+                let mut worker = WorkerStats::new(transform_thread);
+                let mut waiting = Instant::now();
+                while let Ok(mut item) = rx_db_stream_thread.recv_async().await {
+                    if thread_failure.is_set() {
                         break;
                     }
+                    worker.waited(waiting.elapsed());
+                    worker.items += 1;
+                    if let Err(err) = RecordCipher::decrypt(&mut item) {
+                        error!("transform_thread {transform_thread}: {err}");
+                        thread_failure.set(&err.to_string());
+                        break;
+                    }
+                    let row = match export_format {
+                        ExportFormat::Csv => csv_row(&item, &schema_columns_thread).into_bytes(),
+                        ExportFormat::Ndjson => {
+                            let mut row = serde_json::to_vec(&item)
+                                .expect("records can always be serialized");
+                            row.push(b'\n');
+                            row
+                        }
+                    };
+                    let blocked = Instant::now();
+                    if tx_result_thread.send_async(row).await.is_err() {
+                        break;
+                    }
+                    worker.blocked(blocked.elapsed());
+                    waiting = Instant::now();
+                }
+                debug!(
+                    "transform_thread {transform_thread}: processed {} items",
+                    worker.items
+                );
+                if let Some(stats) = thread_stats {
+                    stats.lock().unwrap().transform_workers.push(worker);
                 }
-                debug!("transform_thread {transform_thread}: processed {processed} items");
-                Ok::<_, DatabaseQueryError>(())
             });
         }
+        spawn_queue_sampler(rx_db_stream, tx_result.downgrade(), stats.clone());
         drop(tx_result);
-        drop(rx_db_stream);
 
         let current_span = Span::current();
 
@@ -282,17 +717,171 @@ impl RecordQuery {
             // Capture user grant for this streaming operation
             let _limit_grant = limit_grant;
 
-            yield headers.into_bytes();
+            let mut hasher = Sha256::new();
+            if let Some(headers) = headers {
+                if manifest.is_some() {
+                    hasher.update(headers.as_bytes());
+                }
+                yield headers.into_bytes();
+            }
 
             while let Ok(item) = rx_result.recv_async().await {
+                if failure.is_set() {
+                    break;
+                }
+                if let Some(manifest) = manifest.as_mut() {
+                    manifest.rows += 1;
+                    hasher.update(&item);
+                }
                 yield item;
             }
 
+            // the client already got a 200, the trailer is the only way to tell it.
+            if let Some(message) = failure.get() {
+                let error = serde_json::json!({ "message": message });
+                yield format!("#error {error}\n").into_bytes();
+                warn!("streaming aborted: {message}");
+                return;
+            }
+
+            if let Some(mut manifest) = manifest {
+                manifest.sha256 = hex::encode(hasher.finalize());
+                let manifest =
+                    serde_json::to_string(&manifest).expect("manifests can always be serialized");
+                yield format!("#manifest {manifest}\n").into_bytes();
+            }
+
+            if let Some(stats) = stats {
+                let mut stats = std::mem::take(&mut *stats.lock().unwrap());
+                stats.finish(started.elapsed());
+                let stats = serde_json::to_string(&stats).expect("stats can always be serialized");
+                yield format!("#pipelineStats {stats}\n").into_bytes();
+            }
+
             info!("finished streaming");
         }))
     }
 }
 
+/// The first error of the workers of an export. Once it's set, the workers
+/// stop and the export ends with an `#error` trailer instead of the others:
+/// the manifest of an export that lost records would look valid otherwise.
+/// Database errors may contain SQL, so the trailer only points to the logs of
+/// `request_id` for those.
+#[derive(Clone)]
+struct ExportFailure {
+    request_id: Arc<str>,
+    message: Arc<OnceLock<String>>,
+}
+
+impl ExportFailure {
+    fn new(request_id: Option<String>) -> Self {
+        Self {
+            request_id: request_id.unwrap_or_default().into(),
+            message: Default::default(),
+        }
+    }
+
+    fn set(&self, message: &str) {
+        // the first error is the interesting one.
+        let _ = self.message.set(format!(
+            "{message}, see the logs of request {}",
+            self.request_id
+        ));
+    }
+
+    fn is_set(&self) -> bool {
+        self.message.get().is_some()
+    }
+
+    fn get(&self) -> Option<&str> {
+        self.message.get().map(String::as_str)
+    }
+}
+
+/// Sample the queues of an export every `QUEUE_SAMPLE_INTERVAL` until the
+/// result queue is closed, adding their lengths to `stats` and warning if the
+/// result queue stays full for DB_CSV_SATURATION_WARNING_SECONDS: the workers
+/// are waiting for the client then.
+fn spawn_queue_sampler(
+    db_queue: flume::Receiver<record::Model>,
+    result_queue: flume::WeakSender<Vec<u8>>,
+    stats: Option<Arc<Mutex<PipelineStats>>>,
+) {
+    let warning = Duration::from_secs(Config::get().db_csv_saturation_warning_seconds);
+    if warning.is_zero() && stats.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(QUEUE_SAMPLE_INTERVAL);
+        // samples taken since `window`, and how many found the result queue full.
+        let mut window = Instant::now();
+        let (mut samples, mut full) = (0, 0);
+        let mut warned = false;
+        loop {
+            interval.tick().await;
+            // don't keep the result queue open, the stream ends once it's closed.
+            let Some((result_len, result_full)) = result_queue
+                .upgrade()
+                .map(|queue| (queue.len(), queue.is_full()))
+            else {
+                break;
+            };
+            if let Some(stats) = stats.as_ref() {
+                let mut stats = stats.lock().unwrap();
+                stats.db_queue.sample(db_queue.len());
+                stats.result_queue.sample(result_len);
+            }
+            samples += 1;
+            full += result_full as u64;
+            if !warning.is_zero() && window.elapsed() >= warning {
+                // the client reads in bursts, so the queue isn't always full.
+                if !warned && full * 10 >= samples * 9 {
+                    warn!(
+                        "streaming: result queue saturated for {}s, the client reads slower \
+                         than the records are exported",
+                        window.elapsed().as_secs()
+                    );
+                    warned = true;
+                }
+                window = Instant::now();
+                (samples, full) = (0, 0);
+            }
+        }
+    });
+}
+
+/// Escape a CSV field as per RFC 4180: fields with commas, quotes or line
+/// breaks are quoted, doubling any quotes inside them.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Build the CSV row of a record, with `FIXED_HEADERS` followed by `schema_columns`.
+/// Strings are written as they are (not as JSON) and missing values are left empty.
+fn csv_row(item: &record::Model, schema_columns: &[String]) -> String {
+    let row = schema_columns
+        .iter()
+        .map(|column| match item.data.get(column) {
+            Some(serde_json::Value::String(value)) => format!(",{}", csv_field(value)),
+            Some(serde_json::Value::Null) | None => ",".into(),
+            Some(value) => format!(",{}", csv_field(&value.to_string())),
+        })
+        .collect::<String>();
+    format!(
+        "{},{},{},{}{row}\n",
+        item.id,
+        item.format_id,
+        item.upload_session_id,
+        item.created_at
+            .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+    )
+}
+
 impl FormatQuery {
     pub async fn find_by_id(user: &user::Model, id: i32) -> Result<Option<format::Model>, DbErr> {
         let db = DBConfig::get_connection();
@@ -300,6 +889,228 @@ impl FormatQuery {
             .one(db)
             .await
     }
+
+    /// Find many formats at once, in the order of `ids`. Formats that don't
+    /// exist or aren't visible to this user (see [`Self::find_by_id`]) are
+    /// left out, as are repeated ids.
+    pub async fn find_by_ids(user: &user::Model, ids: &[i32]) -> Result<Vec<format::Model>, DbErr> {
+        let db = DBConfig::get_connection();
+        let mut formats = Self::filter_out_select(
+            user,
+            Format::find().filter(format::Column::Id.is_in(ids.iter().copied())),
+        )
+        .all(db)
+        .await?;
+        formats.sort_by_key(|format| ids.iter().position(|id| *id == format.id));
+        Ok(formats)
+    }
+
+    /// Only keep the formats `user` has `access` to through their entitlements,
+    /// on top of [`GetAllTrait::filter_out_select`] (auditors can see every
+    /// format, but that doesn't mean they can write to it). Superusers have
+    /// every access level on every format, so nothing is filtered out for them.
+    pub fn filter_by_access(
+        user: &user::Model,
+        select: Select<format::Entity>,
+        access: AccessLevel,
+    ) -> Select<format::Entity> {
+        if user.is_superuser {
+            return select;
+        }
+        let formats_with_access = format_entitlement::Entity::find()
+            .select_only()
+            .column(format_entitlement::Column::FormatId)
+            .filter(format_entitlement::Column::UserId.eq(user.id))
+            .filter(
+                Expr::col(format_entitlement::Column::Access)
+                    .binary(ARRAY_CONTAINS_OP, access.get_serialized().as_str()),
+            );
+        select.filter(format::Column::Id.in_subquery(formats_with_access.as_query().to_owned()))
+    }
+
+    /// Records uploaded to format `id` per (UTC) day between `from` and `to`
+    /// (exclusive), with a zero for every day without successful uploads.
+    /// Returns `None` if the format doesn't exist or `user` can't read it.
+    pub async fn activity(
+        user: &user::Model,
+        id: i32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Option<Vec<FormatActivity>>, DbErr> {
+        let db = DBConfig::get_connection();
+        let mut select = Self::filter_out_select(user, Format::find_by_id(id));
+        if !user.has_role(user::Role::Auditor) {
+            select = Self::filter_by_access(user, select, AccessLevel::Read);
+        }
+        if select.one(db).await?.is_none() {
+            return Ok(None);
+        }
+        let rows: Vec<(DateTime<Utc>, i64)> = upload_session::Entity::find()
+            .select_only()
+            .column_as(Expr::cust("date_trunc('day', created_at, 'UTC')"), "day")
+            .column_as(Expr::cust("SUM(record_count)::BIGINT"), "uploaded")
+            .filter(upload_session::Column::FormatId.eq(id))
+            .filter(upload_session::Column::Outcome.eq(upload_session::OutcomeKind::Success))
+            .filter(upload_session::Column::CreatedAt.gte(from))
+            .filter(upload_session::Column::CreatedAt.lt(to))
+            .group_by(Expr::cust("1"))
+            .into_tuple()
+            .all(db)
+            .await?;
+        let uploaded = rows
+            .into_iter()
+            .map(|(day, uploaded)| (day.date_naive(), uploaded as u64))
+            .collect::<HashMap<_, _>>();
+        let activity = from
+            .date_naive()
+            .iter_days()
+            .take_while(|date| date.and_time(NaiveTime::MIN).and_utc() < to)
+            .map(|date| FormatActivity {
+                date,
+                uploaded: uploaded.get(&date).copied().unwrap_or_default(),
+                pruned: None,
+            })
+            .collect();
+        Ok(Some(activity))
+    }
+}
+
+/// What happened to a format's records on a single day.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatActivity {
+    /// The (UTC) day, e.g. `2024-04-01`.
+    pub date: NaiveDate,
+    /// Records of the successful uploads of that day.
+    pub uploaded: u64,
+    /// Records pruned that day. Always null for now: prune runs aren't
+    /// persisted, only logged and sent to webhooks.
+    pub pruned: Option<u64>,
+}
+
+/// What a user uploaded, see [`UserQuery::usage`].
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUsage {
+    pub records: u64,
+    /// Size of the uploaded payloads (as received).
+    pub bytes: u64,
+    /// The storage quota of the user, compared against `bytes`.
+    pub max_storage_bytes: Option<i64>,
+    /// Usage per format, only for formats the user uploaded to.
+    pub formats: Vec<FormatUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatUsage {
+    pub format_id: i32,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+/// Upload session filters on the session's format, which the `AsQueryParam`
+/// filters can't express.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct UploadSessionFormatFilter {
+    /// Only sessions of the format with this name.
+    pub format_name_eq: Option<String>,
+    /// Only sessions of formats whose name matches this (case-insensitive)
+    /// ILIKE pattern.
+    pub format_name_ilike: Option<String>,
+}
+
+impl UploadSessionQuery {
+    /// Select the upload sessions matching `filter`, if it filters anything.
+    /// This is meant for `get_all_filtered_for_user`, so the usual permission
+    /// checks still apply on top.
+    pub fn select_by_format(
+        filter: &UploadSessionFormatFilter,
+    ) -> Option<Select<upload_session::Entity>> {
+        if filter.format_name_eq.is_none() && filter.format_name_ilike.is_none() {
+            return None;
+        }
+        let mut formats = Format::find().select_only().column(format::Column::Id);
+        if let Some(name) = filter.format_name_eq.as_ref() {
+            formats = formats.filter(format::Column::Name.eq(name));
+        }
+        if let Some(pattern) = filter.format_name_ilike.as_ref() {
+            formats =
+                formats.filter(Expr::col(format::Column::Name).binary(PgBinOper::ILike, pattern));
+        }
+        let select = upload_session::Entity::find()
+            .filter(upload_session::Column::FormatId.in_subquery(formats.into_query()));
+        Some(select)
+    }
+
+    /// Number of upload sessions attributed to user `user_id`.
+    pub async fn count_for_user(user_id: Uuid) -> Result<u64, DbErr> {
+        let db = DBConfig::get_connection();
+        upload_session::Entity::find()
+            .filter(upload_session::Column::UserId.eq(user_id))
+            .count(db)
+            .await
+    }
+
+    /// Find a successful upload of the same records (see `content_hash`) to
+    /// this format.
+    pub async fn find_duplicate(
+        format_id: i32,
+        content_hash: &str,
+    ) -> Result<Option<upload_session::Model>, DbErr> {
+        let db = DBConfig::get_connection();
+        upload_session::Entity::find()
+            .filter(upload_session::Column::FormatId.eq(format_id))
+            .filter(upload_session::Column::ContentHash.eq(content_hash))
+            .filter(upload_session::Column::Outcome.eq(upload_session::OutcomeKind::Success))
+            .order_by_asc(upload_session::Column::Id)
+            .one(db)
+            .await
+    }
+
+    /// Find an upload session by id, if `user` can see it (i.e. it's listed
+    /// by `GET /upload_session`).
+    pub async fn find_visible(user: &user::Model, id: i32) -> Result<upload_session::Model, DbErr> {
+        let db = DBConfig::get_connection();
+        // sessions outside the user's formats don't exist as far as they're concerned.
+        let select = upload_session::Entity::find().filter(upload_session::Column::Id.eq(id));
+        Self::filter_out_select(user, select)
+            .one(db)
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound("upload session".into()))
+    }
+
+    /// Find an upload session by id, along with its format. Normal users need
+    /// read access to the (non-archived) format, see [`Self::find_visible`].
+    pub async fn find_readable(
+        user: &user::Model,
+        id: i32,
+    ) -> Result<(upload_session::Model, format::Model), DatabaseQueryError> {
+        let db = DBConfig::get_connection();
+        let upload_session = Self::find_visible(user, id).await?;
+        let format = match user.is_superuser {
+            true => {
+                format::Entity::find_by_id(upload_session.format_id)
+                    .one(db)
+                    .await?
+            }
+            false => {
+                user.find_related(format::Entity)
+                    .filter(format::Column::Id.eq(upload_session.format_id))
+                    .filter(format::Column::Archived.eq(false))
+                    .filter(Expr::col(format_entitlement::Column::Access).binary(
+                        ARRAY_CONTAINS_OP,
+                        AccessLevel::Read.get_serialized().as_str(),
+                    ))
+                    .one(db)
+                    .await?
+            }
+        };
+        let format = format.ok_or(DatabaseQueryError::InsufficientPermissions)?;
+        Ok((upload_session, format))
+    }
 }
 
 impl UserQuery {
@@ -317,14 +1128,67 @@ impl UserQuery {
             .await
     }
 
+    /// Find a user by username, ignoring case.
     pub async fn find_by_username(username: &String) -> Result<Option<user::Model>, DbErr> {
         let db = DBConfig::get_connection();
+        // this matches the unique index on lower(username).
         User::find()
-            .filter(user::Column::Username.eq(username))
+            .filter(
+                Expr::expr(Func::lower(Expr::col(user::Column::Username)))
+                    .eq(Func::lower(Expr::val(username))),
+            )
             .one(db)
             .await
     }
 
+    /// Which of `usernames` are taken, lowercased, with a single query.
+    pub async fn find_existing_usernames(usernames: &[String]) -> Result<Vec<String>, DbErr> {
+        let db = DBConfig::get_connection();
+        let usernames = usernames.iter().map(|username| username.to_lowercase());
+        User::find()
+            .select_only()
+            .column_as(
+                SimpleExpr::from(Func::lower(Expr::col(user::Column::Username))),
+                "username",
+            )
+            .filter(Expr::expr(Func::lower(Expr::col(user::Column::Username))).is_in(usernames))
+            .into_tuple()
+            .all(db)
+            .await
+    }
+
+    /// The records and bytes `user` uploaded (in successful uploads), in total
+    /// and per format, with a single grouped query.
+    pub async fn usage(user: &user::Model) -> Result<UserUsage, DbErr> {
+        let db = DBConfig::get_connection();
+        let rows: Vec<(i32, i64, i64)> = upload_session::Entity::find()
+            .select_only()
+            .column(upload_session::Column::FormatId)
+            .column_as(Expr::cust("SUM(record_count)::BIGINT"), "records")
+            .column_as(Expr::cust("SUM(payload_bytes)::BIGINT"), "bytes")
+            .filter(upload_session::Column::UserId.eq(user.id))
+            .filter(upload_session::Column::Outcome.eq(upload_session::OutcomeKind::Success))
+            .group_by(upload_session::Column::FormatId)
+            .order_by_asc(upload_session::Column::FormatId)
+            .into_tuple()
+            .all(db)
+            .await?;
+        let formats = rows
+            .into_iter()
+            .map(|(format_id, records, bytes)| FormatUsage {
+                format_id,
+                records: records as u64,
+                bytes: bytes as u64,
+            })
+            .collect::<Vec<_>>();
+        Ok(UserUsage {
+            records: formats.iter().map(|usage| usage.records).sum(),
+            bytes: formats.iter().map(|usage| usage.bytes).sum(),
+            max_storage_bytes: user.max_storage_bytes,
+            formats,
+        })
+    }
+
     /// Verify whether the passed user has write access to `fmt` (a format).
     #[inline(always)]
     pub async fn find_writable_format(
@@ -347,32 +1211,180 @@ impl UserQuery {
     }
 }
 
-impl FormatEntitlementQuery {
+impl SavedSearchQuery {
+    /// Saved searches `user` can see: their own, the ones shared with them
+    /// and the shared ones over formats they can all read (any format if the
+    /// search doesn't list them).
+    fn visible_to(user: &user::Model) -> Condition {
+        let shared_with_user = Query::select()
+            .column(saved_search_share::Column::SavedSearchId)
+            .from(saved_search_share::Entity)
+            .and_where(saved_search_share::Column::UserId.eq(user.id))
+            .to_owned();
+        let readable = || {
+            Query::select()
+                .expr(Expr::val(1))
+                .from(format_entitlement::Entity)
+                .and_where(format_entitlement::Column::UserId.eq(user.id))
+                .and_where(Expr::col(format_entitlement::Column::Access).binary(
+                    ARRAY_CONTAINS_OP,
+                    AccessLevel::Read.get_serialized().as_str(),
+                ))
+                .to_owned()
+        };
+        let search_formats = Expr::col((saved_search::Entity, saved_search::Column::Query))
+            .binary(PgBinOper::GetJsonField, Expr::val("formats"));
+        let formats_kind =
+            SimpleExpr::from(Func::cust(Alias::new("jsonb_typeof")).arg(search_formats.clone()));
+        // jsonb_array_elements fails on anything but arrays.
+        let listed_formats = CaseStatement::new()
+            .case(Expr::expr(formats_kind).eq("array"), search_formats)
+            .finally(Expr::cust("'[]'::jsonb"));
+        let listed = Alias::new("listed");
+        let readable_listed = readable()
+            .and_where(
+                SimpleExpr::from(Func::cust(Alias::new("to_jsonb")).arg(Expr::col((
+                    format_entitlement::Entity,
+                    format_entitlement::Column::FormatId,
+                ))))
+                .eq(Expr::col((listed.clone(), Alias::new("value")))),
+            )
+            .to_owned();
+        let unreadable_listed = Query::select()
+            .expr(Expr::val(1))
+            .from_function(
+                Func::cust(Alias::new("jsonb_array_elements")).arg(listed_formats),
+                listed,
+            )
+            .and_where(Expr::exists(readable_listed).not())
+            .to_owned();
+        Condition::any()
+            .add(saved_search::Column::UserId.eq(user.id))
+            .add(saved_search::Column::Id.in_subquery(shared_with_user))
+            .add(
+                Condition::all()
+                    .add(saved_search::Column::Shared.eq(true))
+                    .add(Expr::exists(readable()))
+                    .add(Expr::exists(unreadable_listed).not()),
+            )
+    }
+
+    /// Find a saved search by id. Normal users can only see the searches
+    /// visible to them, see `visible_to`.
     pub async fn find_by_id(
-        id: &FormatEntitlementSearch,
-    ) -> Result<Option<format_entitlement::Model>, DbErr> {
+        user: &user::Model,
+        id: i32,
+    ) -> Result<Option<saved_search::Model>, DbErr> {
         let db = DBConfig::get_connection();
-        format_entitlement::Entity::find_by_id((id.user_id, id.format_id))
+        Self::filter_out_select(user, saved_search::Entity::find_by_id(id))
             .one(db)
             .await
     }
 }
 
-impl ApiKeyQuery {
-    /// Get the user associated with the given `user_id` and all its related
-    /// keys.
-    pub async fn get_user_and_keys(
+impl SavedSearchShareQuery {
+    pub async fn find_by_id(
+        saved_search_id: i32,
         user_id: Uuid,
-    ) -> Result<Option<(user::Model, Vec<api_key::Model>)>, DbErr> {
+    ) -> Result<Option<saved_search_share::Model>, DbErr> {
         let db = DBConfig::get_connection();
-        let mut first = user::Entity::find_by_id(user_id)
-            .find_with_related(api_key::Entity)
+        saved_search_share::Entity::find_by_id((saved_search_id, user_id))
+            .one(db)
+            .await
+    }
+}
+
+impl WebhookQuery {
+    pub async fn find_by_id(id: i32) -> Result<Option<webhook::Model>, DbErr> {
+        let db = DBConfig::get_connection();
+        webhook::Entity::find_by_id(id).one(db).await
+    }
+
+    /// Get all the active webhooks subscribed to `event`. Webhooks with a
+    /// format filter are only returned if it matches `format_id`.
+    pub async fn find_subscribed(
+        event: WebhookEvent,
+        format_id: Option<i32>,
+    ) -> Result<Vec<webhook::Model>, DbErr> {
+        let db = DBConfig::get_connection();
+        let mut format_condition = Condition::any().add(webhook::Column::FormatId.is_null());
+        if let Some(format_id) = format_id {
+            format_condition = format_condition.add(webhook::Column::FormatId.eq(format_id));
+        }
+        webhook::Entity::find()
+            .filter(webhook::Column::Active.eq(true))
+            .filter(
+                Expr::col(webhook::Column::Events)
+                    .binary(ARRAY_CONTAINS_OP, event.get_serialized().as_str()),
+            )
+            .filter(format_condition)
+            .all(db)
+            .await
+    }
+}
+
+impl FormatEntitlementQuery {
+    /// Find an entitlement by its key. Normal users can only see their own
+    /// entitlements.
+    pub async fn find_by_id(
+        user: &user::Model,
+        id: &FormatEntitlementSearch,
+    ) -> Result<Option<format_entitlement::Model>, DbErr> {
+        let db = DBConfig::get_connection();
+        let select = format_entitlement::Entity::find_by_id((id.user_id, id.format_id));
+        Self::filter_out_select(user, select).one(db).await
+    }
+
+    /// The access levels `user` has to each of `format_ids`, in a single query.
+    /// Formats without an entitlement are left out, and superusers have every
+    /// access level on every format.
+    pub async fn access_by_format(
+        user: &user::Model,
+        format_ids: &[i32],
+    ) -> Result<HashMap<i32, Vec<AccessLevel>>, DbErr> {
+        if user.is_superuser {
+            return Ok(format_ids
+                .iter()
+                .map(|id| (*id, AccessLevel::ALL.to_vec()))
+                .collect());
+        }
+        let db = DBConfig::get_connection();
+        let entitlements = format_entitlement::Entity::find()
+            .filter(format_entitlement::Column::UserId.eq(user.id))
+            .filter(format_entitlement::Column::FormatId.is_in(format_ids.iter().copied()))
             .all(db)
             .await?;
-        if first.is_empty() {
+        Ok(entitlements
+            .into_iter()
+            .map(|entitlement| {
+                let access = AccessLevel::ALL
+                    .into_iter()
+                    .filter(|level| entitlement.access.contains(level))
+                    .collect();
+                (entitlement.format_id, access)
+            })
+            .collect())
+    }
+}
+
+impl ApiKeyQuery {
+    /// Get the user associated with the given `user_id` and all their keys.
+    /// The user row stays locked until the transaction `db` belongs to ends,
+    /// so concurrent requests can't both count the keys and add one under
+    /// `MAX_API_KEYS_PER_USER`.
+    pub async fn get_user_and_keys<C: ConnectionTrait>(
+        db: &C,
+        user_id: Uuid,
+    ) -> Result<Option<(user::Model, Vec<api_key::Model>)>, DbErr> {
+        let Some(user) = user::Entity::find_by_id(user_id)
+            .lock_exclusive()
+            .one(db)
+            .await?
+        else {
             return Ok(None);
-        }
-        Ok(Some(first.remove(0)))
+        };
+        let keys = user.find_related(api_key::Entity).all(db).await?;
+        Ok(Some((user, keys)))
     }
 
     /// Get the user associated with the given `user_id` and the related key.
@@ -401,4 +1413,123 @@ impl ApiKeyQuery {
         }
         Ok(Some((user, key.remove(0))))
     }
+
+    /// Get the active keys of active users that were last rotated before
+    /// `rotated_before`, oldest first.
+    pub async fn find_rotated_before(
+        rotated_before: DateTime<Utc>,
+    ) -> Result<Vec<(api_key::Model, user::Model)>, DbErr> {
+        let db = DBConfig::get_connection();
+        let keys = api_key::Entity::find()
+            .find_also_related(user::Entity)
+            .filter(api_key::Column::Active.eq(true))
+            .filter(api_key::Column::LastRotatedAt.lt(rotated_before))
+            .filter(user::Column::Active.eq(true))
+            .order_by_asc(api_key::Column::LastRotatedAt)
+            .all(db)
+            .await?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|(key, user)| Some((key, user?)))
+            .collect())
+    }
+}
+
+/// Instance-wide counters for capacity dashboards.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GlobalStats {
+    pub users: u64,
+    pub active_users: u64,
+    pub inactive_users: u64,
+    pub formats: u64,
+    pub records: u64,
+    pub upload_sessions: u64,
+    pub records_last_24h: u64,
+    pub records_last_7d: u64,
+    pub database_size_bytes: i64,
+    /// When these numbers were computed. They're cached for
+    /// ADMIN_STATS_CACHE_SECONDS.
+    pub computed_at: DateTime<Utc>,
+}
+
+static GLOBAL_STATS_CACHE: Mutex<Option<GlobalStats>> = Mutex::new(None);
+
+impl AdminQuery {
+    /// Compute the instance-wide counters, or return the cached ones if they're
+    /// recent enough.
+    pub async fn global_stats() -> Result<GlobalStats, DbErr> {
+        let max_age = chrono::Duration::seconds(Config::get().admin_stats_cache_seconds as i64);
+        let now = chrono::offset::Utc::now();
+        if let Some(cached) = Self::cached_stats() {
+            if now - cached.computed_at < max_age {
+                return Ok(cached);
+            }
+        }
+        let stats = Self::compute_global_stats(now).await?;
+        // concurrent callers may have refreshed the cache as well; the numbers
+        // are approximate anyway.
+        *GLOBAL_STATS_CACHE
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(stats.clone());
+        Ok(stats)
+    }
+
+    fn cached_stats() -> Option<GlobalStats> {
+        GLOBAL_STATS_CACHE
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    async fn compute_global_stats(now: DateTime<Utc>) -> Result<GlobalStats, DbErr> {
+        let db = DBConfig::get_connection();
+        let records_since = |age: chrono::Duration| {
+            record::Entity::find()
+                .filter(record::Column::CreatedAt.gt(now - age))
+                .count(db)
+        };
+        let database_size = async {
+            db.query_one(Statement::from_string(
+                DbBackend::Postgres,
+                "SELECT pg_database_size(current_database()) AS size",
+            ))
+            .await?
+            .ok_or_else(|| DbErr::RecordNotFound("database size".into()))?
+            .try_get::<i64>("", "size")
+        };
+        let (
+            active_users,
+            inactive_users,
+            formats,
+            records,
+            upload_sessions,
+            records_last_24h,
+            records_last_7d,
+            database_size_bytes,
+        ) = futures::try_join!(
+            User::find().filter(user::Column::Active.eq(true)).count(db),
+            User::find()
+                .filter(user::Column::Active.eq(false))
+                .count(db),
+            Format::find().count(db),
+            record::Entity::find().count(db),
+            upload_session::Entity::find().count(db),
+            records_since(chrono::Duration::hours(24)),
+            records_since(chrono::Duration::days(7)),
+            database_size,
+        )?;
+        Ok(GlobalStats {
+            users: active_users + inactive_users,
+            active_users,
+            inactive_users,
+            formats,
+            records,
+            upload_sessions,
+            records_last_24h,
+            records_last_7d,
+            database_size_bytes,
+            computed_at: now,
+        })
+    }
 }
@@ -0,0 +1,51 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use sea_orm::metric::Info;
+
+tokio::task_local! {
+    static QUERY_STATS: Arc<QueryStats>;
+}
+
+/// Number of SQL statements run while processing a request, and the time
+/// spent running them.
+#[derive(Default, Debug)]
+pub struct QueryStats {
+    queries: AtomicU64,
+    micros: AtomicU64,
+}
+
+impl QueryStats {
+    /// Run `fut`, counting the statements it runs. Only statements run by the
+    /// task itself are counted: those of spawned tasks (and of streamed
+    /// bodies, which are sent once the handler is done) aren't.
+    pub async fn track<F: Future>(fut: F) -> (F::Output, Arc<QueryStats>) {
+        let stats = Arc::new(QueryStats::default());
+        let output = QUERY_STATS.scope(stats.clone(), fut).await;
+        (output, stats)
+    }
+
+    pub fn queries(&self) -> u64 {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::Relaxed))
+    }
+
+    /// Metric callback of the database connection.
+    pub(crate) fn record(info: &Info<'_>) {
+        let _ = QUERY_STATS.try_with(|stats| {
+            stats.queries.fetch_add(1, Ordering::Relaxed);
+            stats
+                .micros
+                .fetch_add(info.elapsed.as_micros() as u64, Ordering::Relaxed);
+        });
+    }
+}
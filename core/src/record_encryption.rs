@@ -0,0 +1,179 @@
+use std::error::Error;
+
+use base64::{engine::general_purpose, Engine as _};
+use central_repository_config::inner::Config;
+use entity::record::{self, RecordJsonData};
+use log::info;
+use once_cell::sync::OnceCell;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use sea_orm::DbErr;
+
+static KEY: OnceCell<Option<LessSafeKey>> = OnceCell::new();
+
+/// Encrypts the data of the records of encrypted formats with
+/// RECORD_ENCRYPTION_KEY (AES-256-GCM). Encrypted records keep
+/// `nonce || ciphertext` in `encrypted_data` and an empty `data` map, and their
+/// format and upload session are authenticated along with it, so the data of a
+/// record can't be copied into another one.
+pub struct RecordCipher;
+
+impl RecordCipher {
+    pub fn init() -> Result<(), Box<dyn Error>> {
+        let key = match Config::get().record_encryption_key.as_str() {
+            "" => {
+                info!("RECORD_ENCRYPTION_KEY is not set, encrypted formats are disabled.");
+                None
+            }
+            encoded => {
+                let decoded = general_purpose::STANDARD.decode(encoded)?;
+                let key = UnboundKey::new(&AES_256_GCM, &decoded)
+                    .map_err(|_| "RECORD_ENCRYPTION_KEY must be 32 bytes long")?;
+                Some(LessSafeKey::new(key))
+            }
+        };
+        if KEY.set(key).is_err() {
+            return Err("Cannot set record encryption key".into());
+        }
+        Ok(())
+    }
+
+    /// Whether encrypted formats can be created (and read).
+    pub fn is_enabled() -> bool {
+        KEY.get().is_some_and(Option::is_some)
+    }
+
+    fn key() -> Result<&'static LessSafeKey, DbErr> {
+        KEY.get()
+            .and_then(Option::as_ref)
+            .ok_or_else(|| DbErr::Custom("RECORD_ENCRYPTION_KEY is not set".into()))
+    }
+
+    fn aad(record: &record::Model) -> [u8; 8] {
+        let mut aad = [0; 8];
+        aad[..4].copy_from_slice(&record.format_id.to_be_bytes());
+        aad[4..].copy_from_slice(&record.upload_session_id.to_be_bytes());
+        aad
+    }
+
+    /// Move the data of `record` into `encrypted_data`.
+    pub fn encrypt(record: &mut record::Model) -> Result<(), DbErr> {
+        Self::seal(Self::key()?, record)
+    }
+
+    /// Move the data of `record` back into `data`. Records without encrypted
+    /// data are left as they are.
+    pub fn decrypt(record: &mut record::Model) -> Result<(), DbErr> {
+        if record.encrypted_data.is_none() {
+            return Ok(());
+        }
+        Self::open(Self::key()?, record)
+    }
+
+    fn seal(key: &LessSafeKey, record: &mut record::Model) -> Result<(), DbErr> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| DbErr::Custom("cannot generate record nonce".into()))?;
+        let mut in_out = serde_json::to_vec(&record.data)
+            .map_err(|err| DbErr::Custom(format!("cannot serialize record: {err}")))?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(Self::aad(record)),
+            &mut in_out,
+        )
+        .map_err(|_| DbErr::Custom("cannot encrypt record".into()))?;
+        record.encrypted_data = Some([nonce.as_slice(), &in_out].concat());
+        record.data = RecordJsonData::default();
+        Ok(())
+    }
+
+    fn open(key: &LessSafeKey, record: &mut record::Model) -> Result<(), DbErr> {
+        let Some(mut in_out) = record.encrypted_data.take() else {
+            return Ok(());
+        };
+        if in_out.len() < NONCE_LEN {
+            return Err(DbErr::Custom(format!("record {} is too short", record.id)));
+        }
+        let mut ciphertext = in_out.split_off(NONCE_LEN);
+        let plaintext = key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(&in_out).expect("nonce has the right length"),
+                Aad::from(Self::aad(record)),
+                &mut ciphertext,
+            )
+            // i.e. RECORD_ENCRYPTION_KEY changed
+            .map_err(|_| DbErr::Custom(format!("cannot decrypt record {}", record.id)))?;
+        record.data = serde_json::from_slice(plaintext)
+            .map_err(|err| DbErr::Custom(format!("cannot deserialize record: {err}")))?;
+        Ok(())
+    }
+
+    /// Decrypt all of `records`, see [`Self::decrypt`].
+    pub fn decrypt_all(records: &mut [record::Model]) -> Result<(), DbErr> {
+        records.iter_mut().try_for_each(Self::decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn key() -> LessSafeKey {
+        LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7; 32]).unwrap())
+    }
+
+    fn sealed() -> record::Model {
+        let mut record = record::Model {
+            id: 1,
+            format_id: 2,
+            upload_session_id: 3,
+            data: serde_json::from_value(json!({"Name": "secret", "Count": 5})).unwrap(),
+            ..Default::default()
+        };
+        RecordCipher::seal(&key(), &mut record).unwrap();
+        record
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut record = sealed();
+        assert!(record.data.is_empty());
+        assert!(record.encrypted_data.is_some());
+        RecordCipher::open(&key(), &mut record).unwrap();
+        assert_eq!(record.data["Name"], "secret");
+        assert_eq!(record.data["Count"], 5);
+        assert!(record.encrypted_data.is_none());
+    }
+
+    #[test]
+    fn data_is_bound_to_its_format_and_session() {
+        let mut record = sealed();
+        record.format_id = 4;
+        assert!(RecordCipher::open(&key(), &mut record).is_err());
+        let mut record = sealed();
+        record.upload_session_id = 4;
+        assert!(RecordCipher::open(&key(), &mut record).is_err());
+    }
+
+    #[test]
+    fn tampered_data_is_rejected() {
+        let mut record = sealed();
+        let data = record.encrypted_data.as_mut().unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        assert!(RecordCipher::open(&key(), &mut record).is_err());
+        let mut record = sealed();
+        record
+            .encrypted_data
+            .as_mut()
+            .unwrap()
+            .truncate(NONCE_LEN - 1);
+        assert!(RecordCipher::open(&key(), &mut record).is_err());
+        let other = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[8; 32]).unwrap());
+        assert!(RecordCipher::open(&other, &mut sealed()).is_err());
+    }
+}
@@ -1,46 +1,77 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::Arc,
+};
 
 use better_debug::BetterDebug;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use entity::{
-    error::DatabaseQueryError,
+    error::{ArgumentError, DatabaseQueryError},
     format::{self, ColumnKind},
     format_entitlement::{self, AccessLevel, ARRAY_CONTAINS_OP},
     record,
     traits::AsQueryParamFilterable,
     upload_session, user,
 };
-use log::{debug, error, info};
-use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use log::{debug, error, info, warn};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+};
 use sea_orm::{
-    sea_query::{extension::postgres::PgBinOper, Alias, BinOper, Expr, Query},
+    sea_query::{extension::postgres::PgBinOper, Alias, BinOper, Expr, Func, Query},
     ColumnTrait, Condition, EntityTrait, ModelTrait, QueryFilter, QuerySelect, QueryTrait,
     RelationTrait,
 };
 use sea_query::{IntoCondition, JoinType, SimpleExpr};
 use serde::*;
 use serde_json::Value;
+use utoipa::ToSchema;
+
+use central_repository_config::inner::Config;
 
-use crate::conf::DBConfig;
+use crate::{
+    column_cache::{ColumnKindCache, ColumnKinds},
+    conf::DBConfig,
+};
 
 const DEBUG_ARRAY_MAX_LOGGED: usize = 10;
-const PSQL_TZ_CAST: &str = "TIMESTAMP WITH TIME ZONE";
+/// Max. length of the value preview shown in validation errors.
+const VALUE_PREVIEW_LEN: usize = 32;
+pub(crate) const PSQL_TZ_CAST: &str = "TIMESTAMP WITH TIME ZONE";
+
+/// Type number columns and comparison values are cast to in searches.
+fn number_cast() -> Alias {
+    match Config::get().float_number_comparisons {
+        true => Alias::new("FLOAT"),
+        _ => Alias::new("NUMERIC"),
+    }
+}
+
+/// JSON numbers are finite, but keep 1e309 & co. from becoming Infinity in
+/// case that ever changes.
+fn is_finite_number(value: &Value) -> bool {
+    value.as_f64().is_some_and(f64::is_finite)
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 /// Proxy for sea_query's supported condition types.
 pub enum ConditionKind {
     Any,
+    #[default]
     All,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 /// Supported comparison operators.
 pub enum ComparisonOperator {
     JoinColumnEq,
     JoinColumnNotEq,
+    #[default]
     Eq,
+    /// Case-insensitive equality, for strings.
+    EqCaseInsensitive,
     Lt,
     Gt,
     Lte,
@@ -48,11 +79,15 @@ pub enum ComparisonOperator {
     In,
     ILike,
     Like,
+    /// Plain prefix match, for strings (no wildcards).
+    StartsWith,
+    /// Plain suffix match, for strings (no wildcards).
+    EndsWith,
     Regex,
     RegexCaseInsensitive,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum JoinKind {
     Inner,
@@ -72,22 +107,28 @@ impl JoinKind {
     }
 }
 
-impl ComparisonOperator {
-    /// Whether this operator is a join operator or not.
-    fn is_join(&self) -> bool {
-        matches!(&self, Self::JoinColumnEq | Self::JoinColumnNotEq)
-    }
+/// Kind to search a column with instead of the kind in the schema, for
+/// columns that have different kinds in the searched formats.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TreatAs {
+    Number,
+    String,
 }
 
-impl Default for ComparisonOperator {
-    fn default() -> Self {
-        Self::Eq
+impl From<TreatAs> for ColumnKind {
+    fn from(treat_as: TreatAs) -> Self {
+        match treat_as {
+            TreatAs::Number => ColumnKind::Number,
+            TreatAs::String => ColumnKind::String,
+        }
     }
 }
 
-impl Default for ConditionKind {
-    fn default() -> Self {
-        Self::All
+impl ComparisonOperator {
+    /// Whether this operator is a join operator or not.
+    fn is_join(&self) -> bool {
+        matches!(&self, Self::JoinColumnEq | Self::JoinColumnNotEq)
     }
 }
 
@@ -105,7 +146,7 @@ pub fn str_to_isodate(string: &str) -> Option<chrono::DateTime<Utc>> {
     None
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, BetterDebug)]
+#[derive(Serialize, Deserialize, Default, Clone, BetterDebug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 /// A single search argument. This basically allows
 /// users to define matches against a specific column.
@@ -115,6 +156,14 @@ pub struct SearchArguments {
     comparison_operator: ComparisonOperator,
     #[better_debug(cust_formatter = "search_args_fmt_compare_against")]
     compare_against: serde_json::Value,
+    // Match `%` and `_` literally in like/iLike patterns.
+    #[serde(default)]
+    escape_wildcards: bool,
+    // Search the column as this kind, regardless of its kind in the searched
+    // formats (superusers only). Records of formats where the column has
+    // another kind never match `number`, and are compared as text with
+    // `string`.
+    treat_as: Option<TreatAs>,
 }
 
 /// Avoid logging potentially a large array. This can impact
@@ -129,37 +178,163 @@ fn search_args_fmt_compare_against(s: &SearchArguments) -> Option<String> {
     None
 }
 
+/// Escape the LIKE wildcards (`%` and `_`) and the escape character (`\`)
+/// in `value`, so it's matched literally.
+fn escape_like_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Number of characters of a like/iLike pattern that are matched literally,
+/// i.e. everything but unescaped wildcards.
+fn like_literal_length(pattern: &str) -> usize {
+    let mut chars = pattern.chars();
+    let mut length = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '%' | '_' => {}
+            '\\' => length += usize::from(chars.next().is_some()),
+            _ => length += 1,
+        }
+    }
+    length
+}
+
+/// Rough number of characters of a regex that are matched literally. Classes
+/// (`[abc]`, `\d`), quantifiers, anchors and groups don't count.
+fn regex_literal_length(pattern: &str) -> usize {
+    let mut chars = pattern.chars();
+    let mut length = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // \d, \w, \s, \b & co.
+                Some(c) if c.is_ascii_alphanumeric() => {}
+                Some(_) => length += 1,
+                None => {}
+            },
+            '[' => {
+                chars.by_ref().find(|c| *c == ']');
+            }
+            '{' => {
+                chars.by_ref().find(|c| *c == '}');
+            }
+            '.' | '*' | '+' | '?' | '|' | '(' | ')' | '^' | '$' => {}
+            _ => length += 1,
+        }
+    }
+    length
+}
+
+/// Key of query template placeholders: `{"$param": "<name>"}`.
+const PARAMETER_KEY: &str = "$param";
+
+/// Name of the parameter `value` stands for, if it's a placeholder.
+fn parameter_name(value: &Value) -> Option<&str> {
+    match value.as_object() {
+        Some(object) if object.len() == 1 => object.get(PARAMETER_KEY)?.as_str(),
+        _ => None,
+    }
+}
+
+/// Whether `value` (or every item of it, for arrays) is a `kind`.
+fn parameter_has_kind(value: &Value, kind: &ColumnKind) -> bool {
+    match (value, kind) {
+        (Value::Array(items), _) => items.iter().all(|item| parameter_has_kind(item, kind)),
+        (_, ColumnKind::Number) => is_finite_number(value),
+        (Value::String(_), ColumnKind::String) => true,
+        (Value::String(date), ColumnKind::Datetime) => str_to_isodate(date).is_some(),
+        _ => false,
+    }
+}
+
+/// Short, printable representation of a value (e.g. an array item) for error
+/// messages.
+pub fn preview_value(value: &Value) -> String {
+    let value = value.to_string();
+    match value.char_indices().nth(VALUE_PREVIEW_LEN) {
+        Some((end, _)) => format!("{}...", &value[..end]),
+        None => value,
+    }
+}
+
 impl SearchArguments {
+    /// Compare `column` against `compare_against` with `comparison_operator`.
+    pub fn new<S: Into<String>>(
+        column: S,
+        comparison_operator: ComparisonOperator,
+        compare_against: serde_json::Value,
+    ) -> Self {
+        Self {
+            column: column.into(),
+            comparison_operator,
+            compare_against,
+            ..Default::default()
+        }
+    }
+
+    /// Join on the column named by `compare_against` (see `joinColumnEq`).
+    pub fn with_join_kind(mut self, join_kind: JoinKind) -> Self {
+        self.join_kind = Some(join_kind);
+        self
+    }
+
+    /// Search the column as `treat_as` (see `treatAs`).
+    pub fn with_treat_as(mut self, treat_as: TreatAs) -> Self {
+        self.treat_as = Some(treat_as);
+        self
+    }
+
     fn validate_array(
         &self,
         predicate: fn(&serde_json::Value) -> bool,
-        message: &str,
+        expected: &str,
     ) -> Result<(), DatabaseQueryError> {
-        let all_members_valid = self
+        let array = self
             .compare_against
             .as_array()
             .ok_or(DatabaseQueryError::InvalidUsage(
                 "comparison value isn't an array".into(),
-            ))?
-            .par_iter()
-            .all(predicate);
-        if !all_members_valid {
-            return Err(DatabaseQueryError::InvalidUsage(message.into()));
+            ))?;
+        let max_len = Config::get().max_compare_against_array_length;
+        if array.len() as u64 > max_len {
+            return Err(DatabaseQueryError::QueryTooLarge(format!(
+                "'{}': comparison array has {} items, at most {} are allowed",
+                self.column,
+                array.len(),
+                max_len
+            )));
+        }
+        // position_first() always returns the lowest failing index, regardless
+        // of how the work was split.
+        if let Some(index) = array.par_iter().position_first(|item| !predicate(item)) {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "'{}': item {} isn't a {}: {}",
+                self.column,
+                index,
+                expected,
+                preview_value(&array[index])
+            )));
         }
         Ok(())
     }
 
     fn validate_string(&self) -> Result<(), DatabaseQueryError> {
         match self.comparison_operator {
-            ComparisonOperator::In => {
-                self.validate_array(|i| i.is_string(), "one or more items isn't a string")
-            }
+            ComparisonOperator::In => self.validate_array(|i| i.is_string(), "string"),
             // TODO: Properly implement JOIN queries. This will just short-circuit the
             // validation regardless of the type and throw an error.
             ComparisonOperator::JoinColumnEq | ComparisonOperator::JoinColumnNotEq => Err(
                 DatabaseQueryError::InvalidUsage("joinEq queries aren't stable".into()),
             ),
             ComparisonOperator::Eq
+            | ComparisonOperator::EqCaseInsensitive
             | ComparisonOperator::Like
             | ComparisonOperator::ILike
             | ComparisonOperator::Regex
@@ -170,6 +345,16 @@ impl SearchArguments {
                     self.comparison_operator
                 ))),
             },
+            // an empty prefix/suffix would match everything.
+            ComparisonOperator::StartsWith | ComparisonOperator::EndsWith => {
+                match self.compare_against.as_str() {
+                    Some(value) if !value.is_empty() => Ok(()),
+                    _ => Err(DatabaseQueryError::InvalidUsage(format!(
+                        "'{}': operator '{:?}' needs a non-empty string",
+                        self.column, self.comparison_operator
+                    ))),
+                }
+            }
             _ => Err(DatabaseQueryError::InvalidUsage(
                 "cannot use numeric operator on string columns".into(),
             )),
@@ -178,9 +363,7 @@ impl SearchArguments {
 
     fn validate_number(&self) -> Result<(), DatabaseQueryError> {
         match self.comparison_operator {
-            ComparisonOperator::In => {
-                self.validate_array(|i| i.is_number(), "one or more items isn't a number")
-            }
+            ComparisonOperator::In => self.validate_array(is_finite_number, "finite number"),
             // TODO: Properly implement JOIN queries. This will just short-circuit the
             // validation regardless of the type and throw an error.
             ComparisonOperator::JoinColumnEq | ComparisonOperator::JoinColumnNotEq => Err(
@@ -191,7 +374,11 @@ impl SearchArguments {
             | ComparisonOperator::Lt
             | ComparisonOperator::Lte
             | ComparisonOperator::Gte => match self.compare_against.is_number() {
-                true => Ok(()),
+                true if is_finite_number(&self.compare_against) => Ok(()),
+                true => Err(DatabaseQueryError::InvalidUsage(format!(
+                    "'{}' can only be compared against finite numbers.",
+                    self.column
+                ))),
                 _ => Err(DatabaseQueryError::InvalidUsage(format!(
                     "'{}' can only be compared against numbers.",
                     self.column
@@ -233,20 +420,80 @@ impl SearchArguments {
         }
     }
 
-    pub fn validate(&self, db_column_kind: &ColumnKind) -> Result<(), DatabaseQueryError> {
+    /// Make sure like/iLike/regex patterns don't (trivially) match every record,
+    /// which means scanning all of them. Superusers are trusted to know what
+    /// they're doing.
+    fn validate_pattern(&self, is_superuser: bool) -> Result<(), DatabaseQueryError> {
+        let config = Config::get();
+        if !config.search_pattern_guard {
+            return Ok(());
+        }
+        let Some(pattern) = self.compare_against.as_str() else {
+            return Ok(());
+        };
+        let length = match self.comparison_operator {
+            ComparisonOperator::Like | ComparisonOperator::ILike if self.escape_wildcards => {
+                pattern.chars().count()
+            }
+            ComparisonOperator::Like | ComparisonOperator::ILike => like_literal_length(pattern),
+            ComparisonOperator::Regex | ComparisonOperator::RegexCaseInsensitive => {
+                regex_literal_length(pattern)
+            }
+            _ => return Ok(()),
+        };
+        let min_length = config.search_pattern_min_length;
+        let reason = match length {
+            0 => "it only has wildcards".to_string(),
+            length if length < min_length => {
+                format!(
+                    "only {length} of its characters are literal, at least {min_length} are needed"
+                )
+            }
+            _ => return Ok(()),
+        };
+        if is_superuser {
+            warn!(
+                "'{}': pattern {:?} would scan every record ({reason}), running it anyway",
+                self.column, pattern
+            );
+            return Ok(());
+        }
+        Err(DatabaseQueryError::InvalidUsage(format!(
+            "'{}': pattern {:?} would scan every record: {reason}",
+            self.column, pattern
+        )))
+    }
+
+    pub fn validate(
+        &self,
+        db_column_kind: &ColumnKind,
+        is_superuser: bool,
+    ) -> Result<(), DatabaseQueryError> {
         info!(
             "ColumnKind: validating {:?} against {:?}",
             self, db_column_kind
         );
+        if self.escape_wildcards
+            && !matches!(
+                self.comparison_operator,
+                ComparisonOperator::Like | ComparisonOperator::ILike
+            )
+        {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "'{}': escapeWildcards can only be used with like/iLike",
+                self.column
+            )));
+        }
         match db_column_kind {
             ColumnKind::Number => self.validate_number(),
             ColumnKind::String => self.validate_string(),
             ColumnKind::Datetime => self.validate_datetime(),
-        }
+        }?;
+        self.validate_pattern(is_superuser)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 /// A container for multiple search groups.
 pub struct SearchGroup {
@@ -260,6 +507,21 @@ pub struct SearchGroup {
 }
 
 impl SearchGroup {
+    /// Combine `args` with `condition_kind`.
+    pub fn new(condition_kind: ConditionKind, args: Vec<SearchArguments>) -> Self {
+        Self {
+            not: false,
+            condition_kind,
+            args,
+        }
+    }
+
+    /// Negate the whole group.
+    pub fn negated(mut self) -> Self {
+        self.not = !self.not;
+        self
+    }
+
     fn get_condition_type(&self) -> Condition {
         match self.condition_kind {
             ConditionKind::All => Condition::all(),
@@ -268,7 +530,7 @@ impl SearchGroup {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SearchQuery {
     // Optional list of formats to filter from. If left undefined:
@@ -276,19 +538,314 @@ pub struct SearchQuery {
     // - Non-admin users get data from all available read/readWrite formats.
     formats: Option<Vec<i32>>,
     // Optional upload session filters.
-    // The model as query provides a lot of knobs to search.
+    // The model as query provides a lot of knobs to search. Unlike in query
+    // strings, unknown (e.g. misspelled) filters are rejected.
+    #[serde(
+        default,
+        deserialize_with = "upload_session::ModelAsQuery::deserialize_strict"
+    )]
+    #[schema(value_type = Option<UploadSessionFilter>)]
     upload_session: Option<upload_session::ModelAsQuery>,
+    // Only records uploaded (i.e. whose upload session was created) at or
+    // after this UTC timestamp, e.g. `2024-04-01T00:00:00Z`. Combined with
+    // `uploadSession`, both have to match.
+    ingested_after: Option<String>,
+    // Only records uploaded before this UTC timestamp (exclusive).
+    ingested_before: Option<String>,
+    // Optional filters on the user who uploaded the records.
+    uploader: Option<UploaderFilter>,
     query: Vec<SearchGroup>,
+    // Also search archived formats (superusers only).
+    #[serde(default)]
+    include_archived: bool,
+    // Fail if any of `formats` doesn't exist or isn't readable, instead of
+    // silently leaving it out.
+    #[serde(default)]
+    strict_formats: bool,
+}
+
+// Bounds of the creation date of upload sessions, see `SearchQuery::ingested_after`.
+type IngestionWindow = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// Make sure a list of requested formats isn't too long.
+fn validate_formats_length(formats: &[i32]) -> Result<(), DatabaseQueryError> {
+    let max_formats = Config::get().max_search_formats;
+    if formats.len() as u64 > max_formats {
+        return Err(DatabaseQueryError::QueryTooLarge(format!(
+            "at most {max_formats} formats can be searched at once"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+/// Only match records uploaded by users matching all of these conditions.
+pub struct UploaderFilter {
+    // Exact username.
+    username: Option<String>,
+    // Case-insensitive username pattern (ILIKE syntax, e.g. "ali%").
+    username_ilike: Option<String>,
+    // The uploader's user id.
+    user_id: Option<uuid::Uuid>,
+}
+
+impl UploaderFilter {
+    fn validate(&self) -> Result<(), DatabaseQueryError> {
+        if self.username.is_none() && self.username_ilike.is_none() && self.user_id.is_none() {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "uploader filter needs at least one condition".into(),
+            ));
+        }
+        if [&self.username, &self.username_ilike]
+            .into_iter()
+            .flatten()
+            .any(|it| it.is_empty())
+        {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "uploader username can't be empty".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Condition matching the users described by this filter.
+    fn user_condition(&self) -> Condition {
+        let mut condition = Condition::all();
+        if let Some(username) = &self.username {
+            condition =
+                condition.add(Expr::col((user::Entity, user::Column::Username)).eq(username));
+        }
+        if let Some(pattern) = &self.username_ilike {
+            condition = condition.add(
+                Expr::col((user::Entity, user::Column::Username)).binary(PgBinOper::ILike, pattern),
+            );
+        }
+        if let Some(user_id) = self.user_id {
+            condition = condition.add(Expr::col((user::Entity, user::Column::Id)).eq(user_id));
+        }
+        condition
+    }
+}
+
+fn changes_since_id_default() -> i64 {
+    0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+/// Request for all the records added after a given record id.
+pub struct RecordChangesQuery {
+    // Only return records with an id greater than this one.
+    #[serde(default = "changes_since_id_default")]
+    pub since_id: i64,
+    // Max number of records to return. Defaults to MAX_CHANGES_LIMIT.
+    pub limit: Option<u64>,
+    // Optional list of formats to filter from, same as SearchQuery's.
+    pub formats: Option<Vec<i32>>,
+    // Also return records from archived formats (superusers only).
+    #[serde(default)]
+    pub include_archived: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordChanges {
+    #[schema(value_type = Vec<Record>)]
+    pub records: Vec<record::Model>,
+    // The highest record id returned (or `sinceId` if there are no new
+    // records). Pass it as `sinceId` to get the next batch.
+    pub last_id: i64,
+    // Whether there are more records after `lastId`.
+    pub has_more: bool,
+}
+
+impl RecordChangesQuery {
+    pub fn validate(&self) -> Result<(), DatabaseQueryError> {
+        let max_limit = Config::get().max_changes_limit;
+        if let Some(limit) = self.limit {
+            if limit == 0 || limit > max_limit {
+                return Err(DatabaseQueryError::InvalidUsage(format!(
+                    "limit must be between 1 and {max_limit}"
+                )));
+            }
+        }
+        if let Some(formats) = &self.formats {
+            if formats.is_empty() {
+                return Err(DatabaseQueryError::EmptyQuery);
+            }
+            validate_formats_length(formats)?;
+        }
+        Ok(())
+    }
+
+    /// Get the readable formats for `user`, exactly like a SearchQuery
+    /// without conditions would.
+    pub async fn get_readable_formats_for_user(
+        &self,
+        user: &user::Model,
+    ) -> Result<PreparedSearchQuery, DatabaseQueryError> {
+        SearchQuery {
+            formats: self.formats.clone(),
+            include_archived: self.include_archived,
+            ..Default::default()
+        }
+        .get_readable_formats_for_user(user)
+        .await
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+/// Request for the statistics of a single column of the records matching a search.
+pub struct ColumnStatsQuery {
+    // The column to profile.
+    pub column: String,
+    #[serde(flatten)]
+    pub search: SearchQuery,
+}
+
+impl ColumnStatsQuery {
+    pub fn validate(&self) -> Result<(), DatabaseQueryError> {
+        if self.column.is_empty() {
+            return Err(DatabaseQueryError::InvalidUsage(
+                "column can't be empty".into(),
+            ));
+        }
+        self.search.validate()
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnStats {
+    pub column: String,
+    pub kind: ColumnKind,
+    // Number of matching records.
+    pub count: u64,
+    // Matching records without a value for this column (e.g. because their
+    // format doesn't have it).
+    pub missing: u64,
+    // Number of distinct values.
+    pub distinct: u64,
+    // Smallest and largest values: numbers for number columns, strings
+    // (compared lexicographically) for string columns and RFC 3339 dates for
+    // datetime columns. Null if no record has a value.
+    #[schema(value_type = Option<Object>)]
+    pub min: Option<Value>,
+    #[schema(value_type = Option<Object>)]
+    pub max: Option<Value>,
+    // Average value (number columns only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean: Option<f64>,
+    // Length of the values in characters (string columns only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_length: Option<f64>,
+}
+
+/// What a search would run on, returned instead of its records with
+/// `?debug=true`.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct QuerySummary {
+    pub query: SearchQuery,
+    // The formats that would be searched: the requested ones (or all of them)
+    // the user can read.
+    pub formats: Vec<FormatSummary>,
+    // The kind of every column of those formats. Columns with mixed kinds are
+    // left out if they're searched with `treatAs`.
+    pub columns: BTreeMap<String, ColumnKind>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FormatSummary {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Trailer of CSV exports requested with `?manifest=true`, so they can be
+/// checked once they're downloaded.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportManifest {
+    // The searched formats, by id.
+    pub formats: Vec<i32>,
+    // Every CSV column, in order.
+    pub columns: Vec<ManifestColumn>,
+    // Number of exported records (the header isn't counted).
+    pub rows: u64,
+    // Hex-encoded SHA-256 of everything before the trailer.
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ManifestColumn {
+    pub name: String,
+    // Null if the searched formats disagree on it.
+    pub kind: Option<ColumnKind>,
 }
 
 #[derive(Debug)]
 pub struct PreparedSearchQuery {
     formats: Vec<format::Model>,
     query: SearchQuery,
+    is_superuser: bool,
 }
 
 impl SearchQuery {
+    /// Search every readable format with `query`.
+    pub fn new(query: Vec<SearchGroup>) -> Self {
+        Self {
+            query,
+            ..Default::default()
+        }
+    }
+
+    /// Only search these formats.
+    pub fn with_formats(mut self, formats: Vec<i32>) -> Self {
+        self.formats = Some(formats);
+        self
+    }
+
+    /// The `ingestedAfter` and `ingestedBefore` bounds, if set.
+    fn ingestion_window(&self) -> Result<IngestionWindow, DatabaseQueryError> {
+        let parse = |name: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| {
+                    str_to_isodate(value).ok_or_else(|| {
+                        DatabaseQueryError::InvalidUsage(format!(
+                            "`{name}` must be a UTC timestamp in RFC 3339 format, e.g. \
+                             2024-04-01T00:00:00Z ({value:?} given)"
+                        ))
+                    })
+                })
+                .transpose()
+        };
+        let after = parse("ingestedAfter", &self.ingested_after)?;
+        let before = parse("ingestedBefore", &self.ingested_before)?;
+        if let (Some(after), Some(before)) = (after, before) {
+            if after >= before {
+                return Err(DatabaseQueryError::InvalidUsage(
+                    "`ingestedAfter` must be before `ingestedBefore`".into(),
+                ));
+            }
+        }
+        Ok((after, before))
+    }
+
     pub fn validate(&self) -> Result<(), DatabaseQueryError> {
+        if let Some(upload_session) = &self.upload_session {
+            upload_session.validate()?;
+        }
+        self.ingestion_window()?;
+        if let Some(uploader) = &self.uploader {
+            uploader.validate()?;
+        }
         // validate the query vec isn't empty.
         // if the list of formats is defined, ensure it isn't empty.
         match self.formats.as_ref() {
@@ -296,13 +853,88 @@ impl SearchQuery {
                 if it.is_empty() {
                     Err(DatabaseQueryError::EmptyQuery)
                 } else {
-                    Ok(())
+                    validate_formats_length(it)
                 }
             }
             _ => Ok(()),
         }
     }
 
+    fn arguments_mut(&mut self) -> impl Iterator<Item = &mut SearchArguments> {
+        self.query
+            .iter_mut()
+            .flat_map(|group| group.args.iter_mut())
+    }
+
+    /// Names of the parameters used by this query (template).
+    fn parameters(&self) -> BTreeSet<&str> {
+        self.query
+            .iter()
+            .flat_map(|group| group.args.iter())
+            .filter_map(|argument| parameter_name(&argument.compare_against))
+            .collect()
+    }
+
+    /// Make sure every parameter used by this query is declared.
+    pub fn validate_parameters(
+        &self,
+        declared: &BTreeMap<String, ColumnKind>,
+    ) -> Result<(), DatabaseQueryError> {
+        let undeclared = self
+            .parameters()
+            .into_iter()
+            .filter(|name| !declared.contains_key(*name))
+            .collect::<Vec<_>>();
+        match undeclared.is_empty() {
+            true => Ok(()),
+            _ => Err(DatabaseQueryError::InvalidUsage(format!(
+                "undeclared parameters: {}",
+                undeclared.join(", ")
+            ))),
+        }
+    }
+
+    /// Replace the parameter placeholders with `values`. Every declared
+    /// parameter must be given, with a value of its kind (or an array of them,
+    /// for `in`); the offenders are listed otherwise.
+    pub fn substitute_parameters(
+        &mut self,
+        declared: &BTreeMap<String, ColumnKind>,
+        values: &HashMap<String, Value>,
+    ) -> Result<(), DatabaseQueryError> {
+        let mut offenders = declared
+            .iter()
+            .filter_map(|(name, kind)| match values.get(name) {
+                None => Some(format!("{name} (missing)")),
+                Some(value) if !parameter_has_kind(value, kind) => {
+                    Some(format!("{name} (expected {kind:?})"))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut unknown = values
+            .keys()
+            .filter(|name| !declared.contains_key(*name))
+            .map(|name| format!("{name} (not declared)"))
+            .collect::<Vec<_>>();
+        unknown.sort();
+        offenders.append(&mut unknown);
+        if !offenders.is_empty() {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "invalid parameters: {}",
+                offenders.join(", ")
+            )));
+        }
+        for argument in self.arguments_mut() {
+            if let Some(name) = parameter_name(&argument.compare_against) {
+                argument.compare_against = values.get(name).cloned().ok_or_else(|| {
+                    DatabaseQueryError::InvalidUsage(format!("undeclared parameter: {name}"))
+                })?;
+            }
+        }
+        Ok(())
+    }
+
     pub async fn get_readable_formats_for_user(
         self,
         user: &user::Model,
@@ -323,19 +955,72 @@ impl SearchQuery {
                 )),
         };
 
+        // archived formats are only visible to superusers, and only if
+        // they explicitly ask for them.
+        if self.include_archived && !user.is_superuser {
+            return Err(DatabaseQueryError::InsufficientPermissions);
+        }
+        if !self.include_archived {
+            filtered_formats = filtered_formats.filter(format::Column::Archived.eq(false));
+        }
+
         // if the user passed a list of formats to filter by, then
         // refine the search even further.
         if let Some(formats) = &self.formats {
             filtered_formats = filtered_formats.filter(format::Column::Id.is_in(formats.clone()));
         }
 
+        let formats = filtered_formats.all(db).await?;
+        if let (true, Some(requested)) = (self.strict_formats, &self.formats) {
+            // unknown and unreadable formats are reported the same way, so
+            // this doesn't tell users which formats exist.
+            let found = formats.iter().map(|it| it.id).collect::<HashSet<_>>();
+            let mut missing = requested
+                .iter()
+                .filter(|id| !found.contains(id))
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                missing.sort_unstable();
+                missing.dedup();
+                return Err(DatabaseQueryError::InvalidUsage(format!(
+                    "these formats don't exist or aren't readable: {missing:?}"
+                )));
+            }
+        }
+
         Ok(PreparedSearchQuery {
-            formats: filtered_formats.all(db).await?,
+            formats,
             query: self,
+            is_superuser: user.is_superuser,
         })
     }
 }
 
+/// Column name -> kind -> (id, name) of the formats with that kind.
+type FormatsByColumnKind<'a> =
+    HashMap<&'a String, BTreeMap<&'a ColumnKind, BTreeSet<(i32, &'a String)>>>;
+
+/// `Number in formats [3 'sales'] but String in [7 'legacy_sales']`.
+fn describe_mixed_kinds(kinds: &BTreeMap<&ColumnKind, BTreeSet<(i32, &String)>>) -> String {
+    let mut parts = kinds
+        .iter()
+        .enumerate()
+        .map(|(index, (kind, formats))| {
+            let formats = formats
+                .iter()
+                .map(|(id, name)| format!("{id} '{name}'"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match index {
+                0 => format!("{kind:?} in formats [{formats}]"),
+                _ => format!("{kind:?} in [{formats}]"),
+            }
+        })
+        .collect::<Vec<_>>();
+    let last = parts.pop().expect("no column kinds");
+    format!("{} but {last}", parts.join(", "))
+}
+
 impl PreparedSearchQuery {
     /// Get all the available columns in the schema. This is useful if we're building a
     /// csv file, since we have to know in beforehand the available columns that we might have.
@@ -347,77 +1032,209 @@ impl PreparedSearchQuery {
             .collect()
     }
 
-    /// Perform basic checks.
-    fn get_columns_and_verify_types(
+    /// The columns referenced by the search arguments, in order of appearance.
+    pub fn query_columns(&self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.query
+            .query
+            .iter()
+            .flat_map(|group| &group.args)
+            .filter(|arg| seen.insert(&arg.column))
+            .map(|arg| arg.column.clone())
+            .collect()
+    }
+
+    /// Get the columns to export. With `columns_from_query`, only the columns
+    /// referenced by the search arguments are exported, unless there are none.
+    pub fn export_columns(&self, columns_from_query: bool) -> Vec<String> {
+        let query_columns = match columns_from_query {
+            true => self.query_columns(),
+            false => vec![],
+        };
+        match query_columns.is_empty() {
+            true => self.schema_columns().into_iter().collect(),
+            false => query_columns,
+        }
+    }
+
+    /// Check the columns picked with `?columns=`: each of them must belong to
+    /// one of the searched formats, and they're exported in this order.
+    pub fn selected_columns(
         &self,
-    ) -> Result<HashMap<&String, &ColumnKind>, DatabaseQueryError> {
-        // Try to fetch the column name and column kind for all formats.
-        // Note that there might be more than one format with the same columns,
-        // but with different types. In that case, we check if any given column
-        // has more than one type (string/number) associated to them.
-        let column_and_kind = self
-            .formats
-            .par_iter()
-            .flat_map(|m| &m.schema.0)
-            .try_fold(
-                HashMap::new,
-                |mut hsmap: HashMap<_, HashSet<_>>, col_schema| {
-                    hsmap
-                        .entry(&col_schema.name)
-                        .or_default()
-                        .insert(&col_schema.kind);
-                    Ok(hsmap)
-                },
-            )
-            .try_reduce(HashMap::new, |mut accum, item| {
-                for (column, column_kinds) in item {
-                    if column_kinds.len() > 1 {
-                        return Err(DatabaseQueryError::ColumnWithMixedTypesError(
-                            column.to_string(),
-                        ));
-                    }
-                    let entry = accum.entry(column).or_default();
-                    entry.extend(column_kinds);
-                    if entry.len() > 1 {
-                        return Err(DatabaseQueryError::ColumnWithMixedTypesError(
-                            column.to_string(),
-                        ));
-                    }
+        columns: Vec<String>,
+    ) -> Result<Vec<String>, DatabaseQueryError> {
+        let mut seen = HashSet::new();
+        if let Some(column) = columns.iter().find(|column| !seen.insert(column.as_str())) {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "column '{column}' is selected more than once"
+            )));
+        }
+        let available = self.schema_columns();
+        let unknown = columns
+            .iter()
+            .filter(|column| !available.contains(*column))
+            .map(|column| format!("'{column}'"))
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "columns {} aren't in any of the searched formats",
+                unknown.join(", ")
+            )));
+        }
+        Ok(columns)
+    }
+
+    /// The kind of each of `columns` in the formats this query runs on, or
+    /// None if they have different kinds in different formats.
+    pub fn export_column_kinds(&self, columns: &[String]) -> Vec<Option<ColumnKind>> {
+        columns
+            .iter()
+            .map(|column| {
+                let kinds = self
+                    .formats
+                    .iter()
+                    .flat_map(|fmt| fmt.schema.iter())
+                    .filter(|schema| &schema.name == column)
+                    .map(|schema| &schema.kind)
+                    .collect::<BTreeSet<_>>();
+                match kinds.len() {
+                    1 => kinds.into_iter().next().cloned(),
+                    _ => None,
                 }
-                Ok(accum)
             })
-            .map(|it| {
-                // Convert HashMap<&String, HashSet<&ColumnKind>> to HashMap<&String, &ColumnKind>
-                // Note that we're sure there'll be a single ColumnKind
-                it.into_par_iter()
-                    .map(|(k, v)| (k, v.into_iter().next().expect("missing ColumnKind")))
-                    .collect::<HashMap<_, _>>()
-            })?;
+            .collect()
+    }
+
+    /// The schema of every format this query runs on, by format id.
+    pub fn format_schemas(&self) -> HashMap<i32, &format::FormatSchema> {
+        self.formats
+            .iter()
+            .map(|fmt| (fmt.id, &fmt.schema))
+            .collect()
+    }
+
+    /// Summarize this query without running it. The arguments are checked
+    /// the same way, so this fails if the search would.
+    pub fn summary(&self) -> Result<QuerySummary, DatabaseQueryError> {
+        let columns = self.get_columns_and_verify_types()?;
+        let mut formats = self
+            .formats
+            .iter()
+            .map(|fmt| FormatSummary {
+                id: fmt.id,
+                name: fmt.name.clone(),
+            })
+            .collect::<Vec<_>>();
+        formats.sort_unstable_by_key(|fmt| fmt.id);
+        Ok(QuerySummary {
+            query: self.query.clone(),
+            formats,
+            columns: columns
+                .iter()
+                .map(|(column, kind)| (column.clone(), kind.clone()))
+                .collect(),
+        })
+    }
+
+    /// The data of encrypted formats can't be searched, only their metadata
+    /// (id, upload session, uploader) can be filtered by.
+    pub fn reject_encrypted_formats(&self) -> Result<(), DatabaseQueryError> {
+        let mut encrypted = self
+            .formats
+            .iter()
+            .filter(|fmt| fmt.encrypted)
+            .map(|fmt| fmt.id)
+            .collect::<Vec<_>>();
+        if encrypted.is_empty() {
+            return Ok(());
+        }
+        encrypted.sort_unstable();
+        Err(DatabaseQueryError::InvalidUsage(format!(
+            "the data of encrypted formats can't be searched, leave out formats {encrypted:?} \
+            or only filter by id, upload session or uploader"
+        )))
+    }
+
+    /// Perform basic checks.
+    fn get_columns_and_verify_types(&self) -> Result<Arc<ColumnKinds>, DatabaseQueryError> {
+        if self.query.query.iter().any(|group| !group.args.is_empty()) {
+            self.reject_encrypted_formats()?;
+        }
+        let column_and_kind = self.column_kinds()?;
 
         // We already have the right column types, so let's just use them to validate
         // user-defined columns. We can also validate if the user passed a non-existent column,
-        // in one go. Yay!
-        self.query
+        // in one go. Yay! Every invalid argument is reported, not just the first one
+        // (collect() keeps the original order).
+        let mut errors = self
+            .query
             .query
             .par_iter()
-            .flat_map(|group| &group.args)
-            .map(|argument| {
-                // Make sure users don't use join operators with normal comparisons
-                if argument.join_kind.is_some() && !argument.comparison_operator.is_join() {
-                    return Err(DatabaseQueryError::InvalidUsage(format!(
-                        "'{}': cannot use joinKind with this operator ({:?})",
-                        argument.column, argument.comparison_operator
-                    )));
-                }
-
-                match column_and_kind.get(&argument.column) {
-                    Some(column_kind) => argument.validate(column_kind),
-                    _ => Err(DatabaseQueryError::InvalidColumnRequested(
-                        argument.column.to_string(),
-                    )),
-                }
+            .enumerate()
+            .flat_map(|(group_index, group)| {
+                group
+                    .args
+                    .par_iter()
+                    .enumerate()
+                    .map(move |(argument_index, argument)| (group_index, argument_index, argument))
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .filter_map(|(group_index, argument_index, argument)| {
+                self.verify_argument(&column_and_kind, argument)
+                    .err()
+                    .map(|err| (group_index, argument_index, argument, err))
+            })
+            .collect::<Vec<_>>();
+
+        match errors.len() {
+            0 => Ok(column_and_kind),
+            // keep the original error if there's a single one
+            1 => Err(errors.pop().expect("missing error").3),
+            _ => Err(DatabaseQueryError::Multiple(
+                errors
+                    .into_iter()
+                    .map(|(group, argument, search_argument, err)| ArgumentError {
+                        group,
+                        argument,
+                        column: search_argument.column.clone(),
+                        reason: err.to_string(),
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    fn verify_argument(
+        &self,
+        column_and_kind: &ColumnKinds,
+        argument: &SearchArguments,
+    ) -> Result<(), DatabaseQueryError> {
+        // Make sure users don't use join operators with normal comparisons
+        if argument.join_kind.is_some() && !argument.comparison_operator.is_join() {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "'{}': cannot use joinKind with this operator ({:?})",
+                argument.column, argument.comparison_operator
+            )));
+        }
+        if argument.treat_as.is_some() {
+            if !self.is_superuser {
+                return Err(DatabaseQueryError::InsufficientPermissions);
+            }
+            if argument.comparison_operator.is_join() {
+                return Err(DatabaseQueryError::InvalidUsage(format!(
+                    "'{}': cannot use treatAs with join operators",
+                    argument.column
+                )));
+            }
+        }
+
+        match self.argument_kind(column_and_kind, argument) {
+            Some(column_kind) => argument.validate(&column_kind, self.is_superuser)?,
+            _ => {
+                return Err(DatabaseQueryError::InvalidColumnRequested(
+                    argument.column.to_string(),
+                ))
+            }
+        }
 
         // Validate ColumnEq searches.
         // When using ColumnEq, we need to check for two conditions:
@@ -425,73 +1242,237 @@ impl PreparedSearchQuery {
         // - The source column type and target column type must have
         //   the same data type, i.e. we can only compare string columns against
         //   string columns and so on.
-        self.query
-            .query
+        if !argument.comparison_operator.is_join() {
+            return Ok(());
+        }
+        if argument.join_kind.is_none() {
+            return Err(DatabaseQueryError::InvalidUsage(format!(
+                "'{}': operator '{:?}' needs a joinKind",
+                argument.column, argument.comparison_operator
+            )));
+        }
+        let source_column_type = column_and_kind.get(&argument.column);
+        let compare_column = argument
+            .compare_against
+            .as_str()
+            .ok_or(DatabaseQueryError::InvalidUsage(
+                "compareAgainst must be a valid column".into(),
+            ))?
+            .to_string();
+        let compare_column_type = column_and_kind.get(&compare_column);
+        let column_types_matched = source_column_type
+            .zip(compare_column_type)
+            .map(|(a, b)| a == b)
+            .unwrap_or(false);
+        if !column_types_matched {
+            return Err(DatabaseQueryError::InvalidColumnRequested(compare_column));
+        }
+        Ok(())
+    }
+
+    /// The kind of `column` in the formats of this query.
+    pub fn column_kind(&self, column: &str) -> Result<ColumnKind, DatabaseQueryError> {
+        self.column_kinds()?
+            .get(column)
+            .cloned()
+            .ok_or_else(|| DatabaseQueryError::InvalidColumnRequested(column.into()))
+    }
+
+    /// The kind to search `argument` with: its `treatAs`, if any, or else the
+    /// kind of its column. `None` if the column isn't in any of the formats.
+    fn argument_kind(
+        &self,
+        column_and_kind: &ColumnKinds,
+        argument: &SearchArguments,
+    ) -> Option<ColumnKind> {
+        match argument.treat_as {
+            Some(treat_as) => self
+                .formats
+                .iter()
+                .flat_map(|fmt| &fmt.schema.0)
+                .any(|schema| schema.name == argument.column)
+                .then(|| treat_as.into()),
+            None => column_and_kind.get(&argument.column).cloned(),
+        }
+    }
+
+    /// The formats where `column` is a number, if it has another kind in some
+    /// of the other formats.
+    fn number_formats_of(&self, column: &str) -> Option<Vec<i32>> {
+        let (numbers, others): (Vec<_>, Vec<_>) = self
+            .formats
+            .iter()
+            .filter_map(|fmt| {
+                let schema = fmt.schema.iter().find(|schema| schema.name == column)?;
+                Some((fmt.id, &schema.kind))
+            })
+            .partition(|(_, kind)| **kind == ColumnKind::Number);
+        match others.is_empty() {
+            true => None,
+            false => Some(numbers.into_iter().map(|(id, _)| id).collect()),
+        }
+    }
+
+    /// Columns whose arguments all have a `treatAs`. These may have different
+    /// kinds in the searched formats.
+    fn overridden_columns(&self) -> HashSet<&String> {
+        let arguments = self.query.query.iter().flat_map(|group| &group.args);
+        let without_override = arguments
+            .clone()
+            .filter(|arg| arg.treat_as.is_none())
+            .map(|arg| &arg.column)
+            .collect::<HashSet<_>>();
+        arguments
+            .filter(|arg| arg.treat_as.is_some())
+            .map(|arg| &arg.column)
+            .filter(|column| !without_override.contains(column))
+            .collect()
+    }
+
+    /// The column kinds of all the formats in this query, from the cache if
+    /// possible. Columns with mixed kinds are left out if they're overridden
+    /// (see `overridden_columns`).
+    fn column_kinds(&self) -> Result<Arc<ColumnKinds>, DatabaseQueryError> {
+        let mut format_ids = self.get_readable_format_ids();
+        format_ids.sort_unstable();
+        if let Some(kinds) = ColumnKindCache::get(&format_ids) {
+            return Ok(kinds);
+        }
+        let overridden = self.overridden_columns();
+        let (kinds, mixed) = self.merge_column_kinds(&overridden)?;
+        let kinds = Arc::new(kinds);
+        // only what's true for every query can be cached.
+        if !mixed {
+            ColumnKindCache::insert(format_ids, kinds.clone());
+        }
+        Ok(kinds)
+    }
+
+    /// Merge the column kinds of all the formats in this query. Also returns
+    /// whether any of the `overridden` columns had mixed kinds (and was left out).
+    fn merge_column_kinds(
+        &self,
+        overridden: &HashSet<&String>,
+    ) -> Result<(ColumnKinds, bool), DatabaseQueryError> {
+        // Try to fetch the column name and column kind for all formats.
+        // Note that there might be more than one format with the same columns,
+        // but with different types. In that case, we check if any given column
+        // has more than one type (string/number) associated to them. The formats
+        // behind every type are kept around to tell users which ones disagree.
+        let kinds = self
+            .formats
             .par_iter()
-            .flat_map(|group| &group.args)
-            .filter(|it| it.comparison_operator.is_join())
-            .map(|it| {
-                if it.join_kind.is_none() {
-                    return Err(DatabaseQueryError::InvalidUsage(format!(
-                        "'{}': operator '{:?}' needs a joinKind",
-                        it.column, it.comparison_operator
-                    )));
-                }
-                let source_column_type = column_and_kind.get(&it.column);
-                let compare_column = it
-                    .compare_against
-                    .as_str()
-                    .ok_or(DatabaseQueryError::InvalidUsage(
-                        "compareAgainst must be a valid column".into(),
-                    ))?
-                    .to_string();
-                let compare_column_type = column_and_kind.get(&compare_column);
-                let column_types_matched = source_column_type
-                    .zip(compare_column_type)
-                    .map(|(a, b)| a == b)
-                    .unwrap_or(false);
-                if !column_types_matched {
-                    return Err(DatabaseQueryError::InvalidColumnRequested(compare_column));
+            .fold(HashMap::new, |mut hsmap: FormatsByColumnKind, fmt| {
+                for col_schema in &fmt.schema.0 {
+                    hsmap
+                        .entry(&col_schema.name)
+                        .or_default()
+                        .entry(&col_schema.kind)
+                        .or_default()
+                        .insert((fmt.id, &fmt.name));
                 }
-                Ok(())
+                hsmap
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .reduce(HashMap::new, |mut accum, item| {
+                for (column, column_kinds) in item {
+                    let entry = accum.entry(column).or_default();
+                    for (kind, formats) in column_kinds {
+                        entry.entry(kind).or_default().extend(formats);
+                    }
+                }
+                accum
+            });
 
-        Ok(column_and_kind)
+        // Report the first mixed column by name, so the error doesn't change
+        // between requests.
+        let (overridden_mixed, mixed): (Vec<_>, Vec<_>) = kinds
+            .iter()
+            .filter(|(_, column_kinds)| column_kinds.len() > 1)
+            .partition(|(column, _)| overridden.contains(*column));
+        if let Some((column, column_kinds)) = mixed.into_iter().min_by_key(|(column, _)| *column) {
+            return Err(DatabaseQueryError::ColumnWithMixedTypesError {
+                column: column.to_string(),
+                kinds: describe_mixed_kinds(column_kinds),
+            });
+        }
+        let has_mixed = !overridden_mixed.is_empty();
+        // Note that we're sure there'll be a single ColumnKind for the rest
+        let kinds = kinds
+            .into_par_iter()
+            .filter(|(_, v)| v.len() == 1)
+            .map(|(k, v)| {
+                let kind = v.into_keys().next().expect("missing ColumnKind");
+                (k.clone(), kind.clone())
+            })
+            .collect();
+        Ok((kinds, has_mixed))
     }
 
     /// Build a vec with the IDs of readable formats.
     #[inline(always)]
-    fn get_readable_format_ids(&self) -> Vec<i32> {
+    pub fn get_readable_format_ids(&self) -> Vec<i32> {
         self.formats.par_iter().map(|model| model.id).collect()
     }
 
     /// Limit the available visible records.
     #[inline(always)]
-    fn limit_visible_records(&self) -> Condition {
+    pub(crate) fn limit_visible_records(&self) -> Condition {
         Condition::all().add(record::Column::FormatId.is_in(self.get_readable_format_ids()))
     }
 
-    fn apply_query_parameter_filters(&self) -> Option<Condition> {
-        if let Some(upload_session_filters) = self.query.upload_session.as_ref() {
+    /// Only keep the records whose upload session matches the upload session
+    /// filters and the ingestion window (`ingestedAfter`/`ingestedBefore`).
+    /// Only the sessions of the readable formats are considered, so these
+    /// filters can never widen what the search sees.
+    fn apply_query_parameter_filters(&self) -> Result<Option<Condition>, DatabaseQueryError> {
+        let (ingested_after, ingested_before) = self.query.ingestion_window()?;
+        let upload_session_filters = self.query.upload_session.as_ref();
+        if upload_session_filters.is_none() && ingested_after.is_none() && ingested_before.is_none()
+        {
+            return Ok(None);
+        }
+        let mut subquery = upload_session::Entity::find()
+            .select_only()
+            .column(upload_session::Column::Id)
+            .filter(upload_session::Column::FormatId.is_in(self.get_readable_format_ids()));
+        if let Some(upload_session_filters) = upload_session_filters {
             debug!(
                 "applying upload session filters: {:?}",
                 upload_session_filters
             );
-            let mut subquery = upload_session::Entity::find()
-                .select_only()
-                .column(upload_session::Column::Id);
             subquery = upload_session_filters.filter(subquery);
-            let condition = Query::select()
-                .columns([upload_session::Column::Id])
-                .cond_where(upload_session::Column::Id.in_subquery(subquery.as_query().clone()))
-                .from(upload_session::Entity)
-                .to_owned();
-            return Some(
-                Condition::all().add(record::Column::UploadSessionId.in_subquery(condition)),
-            );
         }
-        None
+        if let Some(after) = ingested_after {
+            subquery = subquery.filter(upload_session::Column::CreatedAt.gte(after));
+        }
+        if let Some(before) = ingested_before {
+            subquery = subquery.filter(upload_session::Column::CreatedAt.lt(before));
+        }
+        let condition = Query::select()
+            .columns([upload_session::Column::Id])
+            .cond_where(upload_session::Column::Id.in_subquery(subquery.as_query().clone()))
+            .from(upload_session::Entity)
+            .to_owned();
+        Ok(Some(Condition::all().add(
+            record::Column::UploadSessionId.in_subquery(condition),
+        )))
+    }
+
+    /// Only keep the records uploaded by the users matching the uploader filter.
+    fn apply_uploader_filter(&self) -> Option<Condition> {
+        let uploader = self.query.uploader.as_ref()?;
+        debug!("applying uploader filters: {:?}", uploader);
+        let subquery = Query::select()
+            .column((upload_session::Entity, upload_session::Column::Id))
+            .from(upload_session::Entity)
+            .inner_join(
+                user::Entity,
+                Expr::col((user::Entity, user::Column::Id))
+                    .equals((upload_session::Entity, upload_session::Column::UserId)),
+            )
+            .cond_where(uploader.user_condition())
+            .to_owned();
+        Some(Condition::all().add(record::Column::UploadSessionId.in_subquery(subquery)))
     }
 
     #[inline(always)]
@@ -504,10 +1485,11 @@ impl PreparedSearchQuery {
                 let s = value.as_str().ok_or(DatabaseQueryError::CastError)?;
                 Ok(Expr::expr(s).cast_as(Alias::new(PSQL_TZ_CAST)))
             }
-            ColumnKind::Number => {
-                let f = value.as_f64().ok_or(DatabaseQueryError::CastError)?;
-                Ok(Expr::expr(f).into())
-            }
+            // Bind the number as written, so the cast doesn't go through f64
+            ColumnKind::Number => match value.is_number() {
+                true => Ok(Expr::val(value.to_string()).cast_as(number_cast())),
+                _ => Err(DatabaseQueryError::CastError),
+            },
             ColumnKind::String => {
                 let s = value.as_str().ok_or(DatabaseQueryError::CastError)?;
                 Ok(Expr::expr(s).into())
@@ -515,6 +1497,23 @@ impl PreparedSearchQuery {
         }
     }
 
+    /// The LIKE pattern of `expression`: the escaped value followed/preceded
+    /// by `%` for startsWith/endsWith, or the like/iLike pattern, escaped if
+    /// `escapeWildcards` is set.
+    fn like_pattern(expression: &SearchArguments) -> Result<SimpleExpr, DatabaseQueryError> {
+        let pattern = expression
+            .compare_against
+            .as_str()
+            .ok_or(DatabaseQueryError::CastError)?;
+        let pattern = match expression.comparison_operator {
+            ComparisonOperator::StartsWith => format!("{}%", escape_like_pattern(pattern)),
+            ComparisonOperator::EndsWith => format!("%{}", escape_like_pattern(pattern)),
+            _ if expression.escape_wildcards => escape_like_pattern(pattern),
+            _ => pattern.to_string(),
+        };
+        Ok(Expr::expr(pattern).into())
+    }
+
     pub fn build_condition_for_arg(
         &self,
         column_kind: &ColumnKind,
@@ -525,7 +1524,20 @@ impl PreparedSearchQuery {
 
         // determine whether the target column needs to be casted or not
         if (*column_kind).eq(&ColumnKind::Number) {
-            target_json_column = target_json_column.cast_as(Alias::new("FLOAT"));
+            target_json_column = target_json_column.cast_as(number_cast());
+            // with `treatAs`, don't cast the values of formats where the column
+            // isn't a number: the comparison is NULL for their records instead.
+            let number_formats = match expression.treat_as {
+                Some(_) => self.number_formats_of(&expression.column),
+                None => None,
+            };
+            if let Some(number_formats) = number_formats {
+                target_json_column = Expr::case(
+                    record::Column::FormatId.is_in(number_formats),
+                    target_json_column,
+                )
+                .into();
+            }
         } else if (*column_kind).eq(&ColumnKind::Datetime) {
             target_json_column = target_json_column.cast_as(Alias::new(PSQL_TZ_CAST));
         }
@@ -539,7 +1551,9 @@ impl PreparedSearchQuery {
                     .expect("arg isn't array");
                 match column_kind {
                     ColumnKind::Number => {
-                        let casted = PreparedSearchQuery::cast_value_array(array, |v| v.as_f64())?;
+                        let casted = PreparedSearchQuery::cast_value_array(array, |v| {
+                            Self::cast_value_to_type(v, &ColumnKind::Number).ok()
+                        })?;
                         Expr::expr(target_json_column).is_in(casted)
                     }
                     ColumnKind::String => {
@@ -557,18 +1571,23 @@ impl PreparedSearchQuery {
                 PgBinOper::RegexCaseInsensitive,
                 Self::cast_value_to_type(&expression.compare_against, column_kind)?,
             ),
-            ComparisonOperator::ILike => target_json_column.binary(
-                PgBinOper::ILike,
-                Self::cast_value_to_type(&expression.compare_against, column_kind)?,
-            ),
-            ComparisonOperator::Like => target_json_column.binary(
-                BinOper::Like,
-                Self::cast_value_to_type(&expression.compare_against, column_kind)?,
-            ),
+            ComparisonOperator::ILike => {
+                target_json_column.binary(PgBinOper::ILike, Self::like_pattern(expression)?)
+            }
+            ComparisonOperator::Like
+            | ComparisonOperator::StartsWith
+            | ComparisonOperator::EndsWith => {
+                target_json_column.binary(BinOper::Like, Self::like_pattern(expression)?)
+            }
             ComparisonOperator::Eq => target_json_column.binary(
                 BinOper::Equal,
                 Self::cast_value_to_type(&expression.compare_against, column_kind)?,
             ),
+            ComparisonOperator::EqCaseInsensitive => {
+                Expr::expr(Func::lower(target_json_column)).eq(Func::lower(
+                    Self::cast_value_to_type(&expression.compare_against, column_kind)?,
+                ))
+            }
             ComparisonOperator::Lt => target_json_column.binary(
                 BinOper::SmallerThan,
                 Self::cast_value_to_type(&expression.compare_against, column_kind)?,
@@ -619,8 +1638,8 @@ impl PreparedSearchQuery {
                     .binary(BinOper::Custom("."), Expr::col(record::Column::Data))
                     .binary(PgBinOper::CastJsonField, Expr::val(right_column.as_str()));
                 if column_kind.eq(&ColumnKind::Number) {
-                    left = left.cast_as(Alias::new("FLOAT"));
-                    right = right.cast_as(Alias::new("FLOAT"));
+                    left = left.cast_as(number_cast());
+                    right = right.cast_as(number_cast());
                 }
                 match operator {
                     ComparisonOperator::JoinColumnEq => right.eq(left).into_condition(),
@@ -652,7 +1671,10 @@ impl PreparedSearchQuery {
         let mut condition = Condition::all();
         condition = condition.add(self.limit_visible_records());
         // apply upload session filters, if any was passed.
-        if let Some(c) = self.apply_query_parameter_filters() {
+        if let Some(c) = self.apply_query_parameter_filters()? {
+            condition = condition.add(c);
+        }
+        if let Some(c) = self.apply_uploader_filter() {
             condition = condition.add(c);
         }
 
@@ -661,8 +1683,8 @@ impl PreparedSearchQuery {
             let mut group_condition = search_group.get_condition_type();
             // iterate over all expressions inside this group
             for expression in search_group.args.iter() {
-                let column_kind = requested_search_columns
-                    .get(&expression.column)
+                let column_kind = self
+                    .argument_kind(&requested_search_columns, expression)
                     .ok_or_else(|| {
                         // This should never happen as we previously validated the column type.
                         error!(
@@ -673,10 +1695,10 @@ impl PreparedSearchQuery {
                     })?;
 
                 if let Some(join_kind) = expression.join_kind {
-                    select = self.apply_join_filter(column_kind, join_kind, expression, select)
+                    select = self.apply_join_filter(&column_kind, join_kind, expression, select)
                 } else {
-                    group_condition =
-                        group_condition.add(self.build_condition_for_arg(column_kind, expression)?);
+                    group_condition = group_condition
+                        .add(self.build_condition_for_arg(&column_kind, expression)?);
                 }
             }
 
@@ -700,10 +1722,149 @@ impl PreparedSearchQuery {
     where
         T: Send,
     {
-        values
+        if let Some(casted) = values.par_iter().map(predicate).collect::<Option<Vec<T>>>() {
+            return Ok(casted);
+        }
+        // only look for the culprit if something actually failed
+        let index = values
             .par_iter()
-            .map(predicate)
-            .collect::<Option<Vec<T>>>()
-            .ok_or_else(|| DatabaseQueryError::CastError)
+            .position_first(|value| predicate(value).is_none())
+            .expect("cast failed but all items are valid");
+        Err(DatabaseQueryError::InvalidUsage(format!(
+            "cannot cast item {}: {}",
+            index,
+            preview_value(&values[index])
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::sea_query::PostgresQueryBuilder;
+    use serde_json::json;
+
+    use super::*;
+
+    fn readable_format(id: i32) -> format::Model {
+        format::Model {
+            id,
+            name: format!("format-{id}"),
+            description: String::new(),
+            created_at: Utc::now(),
+            created_by: None,
+            updated_at: Utc::now(),
+            schema: format::FormatSchema(vec![]),
+            rules: format::FormatRules(vec![]),
+            retention_period_minutes: 0,
+            max_records: None,
+            max_records_per_day: None,
+            archived: false,
+            encrypted: false,
+        }
+    }
+
+    /// The SQL of the upload session condition of `query`, if any.
+    fn upload_session_condition(query: Value) -> Option<String> {
+        let prepared = PreparedSearchQuery {
+            formats: vec![readable_format(7), readable_format(9)],
+            query: serde_json::from_value(query).unwrap(),
+            is_superuser: false,
+        };
+        let condition = prepared.apply_query_parameter_filters().unwrap()?;
+        Some(
+            Query::select()
+                .column(record::Column::Id)
+                .from(record::Entity)
+                .cond_where(condition)
+                .to_string(PostgresQueryBuilder),
+        )
+    }
+
+    #[test]
+    fn upload_session_filters_only_see_readable_formats() {
+        let scoped = r#""upload_session"."format_id" IN (7, 9)"#;
+        for query in [
+            json!({"query": [], "uploadSession": {"idGte": 1}}),
+            json!({"query": [], "ingestedAfter": "2024-04-01T00:00:00Z"}),
+        ] {
+            let sql = upload_session_condition(query).unwrap();
+            assert!(sql.contains(scoped), "unscoped subquery: {sql}");
+        }
+        assert_eq!(upload_session_condition(json!({"query": []})), None);
+    }
+
+    /// `Amount > $minAmount AND Region = $region`.
+    fn template() -> (SearchQuery, BTreeMap<String, ColumnKind>) {
+        let query = serde_json::from_value(json!({"query": [{"args": [
+            {"column": "Amount", "comparisonOperator": "gt", "compareAgainst": {"$param": "minAmount"}},
+            {"column": "Region", "comparisonOperator": "eq", "compareAgainst": {"$param": "region"}},
+        ]}]}))
+        .unwrap();
+        let declared = BTreeMap::from([
+            ("minAmount".to_string(), ColumnKind::Number),
+            ("region".to_string(), ColumnKind::String),
+        ]);
+        (query, declared)
+    }
+
+    /// The values compared against after substituting `values`, or the error.
+    fn substitute(values: Value) -> Result<Vec<Value>, String> {
+        let (mut query, declared) = template();
+        let values = serde_json::from_value(values).unwrap();
+        match query.substitute_parameters(&declared, &values) {
+            Ok(()) => Ok(query
+                .arguments_mut()
+                .map(|argument| argument.compare_against.clone())
+                .collect()),
+            Err(DatabaseQueryError::InvalidUsage(message)) => Err(message),
+            Err(err) => panic!("unexpected error: {err:?}"),
+        }
+    }
+
+    #[test]
+    fn parameters_are_substituted() {
+        let values = substitute(json!({"minAmount": 100, "region": "north"})).unwrap();
+        assert_eq!(values, [json!(100), json!("north")]);
+        let values = substitute(json!({"minAmount": [1, 2], "region": ["north"]})).unwrap();
+        assert_eq!(values, [json!([1, 2]), json!(["north"])]);
+    }
+
+    #[test]
+    fn missing_parameters_are_listed() {
+        let err = substitute(json!({})).unwrap_err();
+        assert_eq!(
+            err,
+            "invalid parameters: minAmount (missing), region (missing)"
+        );
+    }
+
+    #[test]
+    fn mistyped_and_undeclared_parameters_are_listed() {
+        let err = substitute(json!({"minAmount": "100", "region": 1, "limit": 5})).unwrap_err();
+        assert_eq!(
+            err,
+            "invalid parameters: minAmount (expected Number), region (expected String), \
+            limit (not declared)"
+        );
+        let err = substitute(json!({"minAmount": [1, "2"], "region": "north"})).unwrap_err();
+        assert_eq!(err, "invalid parameters: minAmount (expected Number)");
+    }
+
+    /// Values are only ever compared against, never interpreted: placeholders
+    /// and operators inside them are rejected by the type check or kept as is.
+    #[test]
+    fn parameter_values_cant_inject() {
+        let err =
+            substitute(json!({"minAmount": 1, "region": {"$param": "minAmount"}})).unwrap_err();
+        assert_eq!(err, "invalid parameters: region (expected String)");
+        let err = substitute(json!({
+            "minAmount": {"column": "Amount", "comparisonOperator": "gt", "compareAgainst": 0},
+            "region": "north",
+        }))
+        .unwrap_err();
+        assert_eq!(err, "invalid parameters: minAmount (expected Number)");
+        let injection = "north'; DROP TABLE record; --";
+        let values = substitute(json!({"minAmount": 1, "region": injection})).unwrap();
+        assert_eq!(values, [json!(1), json!(injection)]);
     }
 }
@@ -1,22 +1,145 @@
 use std::time::Duration;
 
 use central_repository_config::inner::Config;
+use chrono::{DateTime, Utc};
 use log::{error, info, warn};
-use tokio::time::{interval, timeout};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use tokio::{
+    task::JoinHandle,
+    time::{interval, timeout},
+};
+use uuid::Uuid;
 
-use crate::UploadSessionMutation;
+use entity::webhook::WebhookEvent;
+
+use crate::{conf::DBConfig, ApiKeyQuery, UploadSessionMutation, WebhookDispatcher};
+
+const STUCK_SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+const STALE_KEY_CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+static PRUNE_TASK: OnceCell<JoinHandle<()>> = OnceCell::new();
 
 pub struct Tasks;
 
+/// Payload of `apiKeyExpiring` webhooks.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExpiringApiKey<'a> {
+    key_id: Uuid,
+    user_id: Uuid,
+    username: &'a str,
+    email: Option<&'a str>,
+    last_rotated_at: DateTime<Utc>,
+    stale_at: DateTime<Utc>,
+    stale: bool,
+}
+
 impl Tasks {
     pub fn init_prune_task() {
         if Config::get().enable_prune_job {
-            tokio::spawn(Self::prune_periodically());
+            let task = tokio::spawn(Self::prune_periodically());
+            if PRUNE_TASK.set(task).is_err() {
+                warn!("init_prune_task() called twice!");
+            }
         } else {
             warn!("Prune job is disabled. This will cause increased storage usage.");
         }
     }
 
+    /// Whether the prune task was started (i.e. ENABLE_PRUNE_JOB is set) and
+    /// is still running.
+    pub fn is_prune_task_running() -> bool {
+        PRUNE_TASK.get().is_some_and(|task| !task.is_finished())
+    }
+
+    pub fn init_stuck_session_task() {
+        if Config::get().stuck_upload_session_hours > 0 {
+            tokio::spawn(Self::fail_stuck_sessions_periodically());
+        }
+    }
+
+    pub fn init_stale_key_task() {
+        if Config::get().max_key_age_days > 0 {
+            tokio::spawn(Self::report_stale_keys_periodically());
+        }
+    }
+
+    async fn report_stale_keys_periodically() {
+        let config = Config::get();
+        let max_age = chrono::Duration::days(config.max_key_age_days.into());
+        let warning = chrono::Duration::days(config.key_age_warning_days.into());
+        let mut sleep = interval(STALE_KEY_CHECK_INTERVAL);
+        loop {
+            sleep.tick().await;
+            let now = chrono::offset::Utc::now();
+            let Some(rotated_before) = now
+                .checked_sub_signed(max_age)
+                .and_then(|it| it.checked_add_signed(warning))
+            else {
+                continue;
+            };
+            let keys = match ApiKeyQuery::find_rotated_before(rotated_before).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    error!("stale key task: {:#?}", e);
+                    continue;
+                }
+            };
+            for (key, user) in keys.iter() {
+                let Some(stale_at) = key.stale_at(config.max_key_age_days) else {
+                    continue;
+                };
+                let stale = stale_at <= now;
+                if stale {
+                    warn!(
+                        "stale key task: API key {} of user {} ('{}') went stale at {}",
+                        key.id, user.id, user.username, stale_at
+                    );
+                } else {
+                    info!(
+                        "stale key task: API key {} of user {} ('{}') goes stale at {}",
+                        key.id, user.id, user.username, stale_at
+                    );
+                }
+                // there may be more keys than room in the queue.
+                WebhookDispatcher::notify_waiting(
+                    WebhookEvent::ApiKeyExpiring,
+                    None,
+                    &ExpiringApiKey {
+                        key_id: key.id,
+                        user_id: user.id,
+                        username: &user.username,
+                        email: user.email.as_deref(),
+                        last_rotated_at: key.last_rotated_at,
+                        stale_at,
+                        stale,
+                    },
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn fail_stuck_sessions_periodically() {
+        let max_age = Duration::from_secs(Config::get().stuck_upload_session_hours * 3600);
+        let mut sleep = interval(STUCK_SESSION_CHECK_INTERVAL);
+        loop {
+            sleep.tick().await;
+            let created_at_before = chrono::offset::Utc::now() - max_age;
+            match UploadSessionMutation::fail_stuck_sessions(
+                DBConfig::get_connection(),
+                created_at_before,
+            )
+            .await
+            {
+                Ok(0) => {}
+                Ok(count) => warn!("stuck session task: marked {count} upload sessions as failed"),
+                Err(e) => error!("stuck session task: {:#?}", e),
+            }
+        }
+    }
+
     async fn prune_periodically() {
         let config = Config::get();
         let mut sleep = interval(Duration::from_secs(config.prune_job_run_interval_seconds));
@@ -27,12 +150,24 @@ impl Tasks {
         );
         loop {
             sleep.tick().await;
-            let prune_fn = timeout(duration, UploadSessionMutation::prune_old_items());
+            let prune_fn = timeout(
+                duration,
+                UploadSessionMutation::prune_old_items(DBConfig::get_connection()),
+            );
             match prune_fn.await {
-                Ok(Ok(prune_result)) => info!(
-                    "pruner task: successfully pruned {} formats",
-                    prune_result.len()
-                ),
+                Ok(Ok(prune_result)) => {
+                    info!(
+                        "pruner task: successfully pruned {} formats",
+                        prune_result.len()
+                    );
+                    for result in prune_result.iter() {
+                        WebhookDispatcher::notify(
+                            WebhookEvent::Prune,
+                            Some(result.format_id),
+                            result,
+                        );
+                    }
+                }
                 Ok(Err(e)) => error!("pruner task: error during pruning: {:#?}", e),
                 Err(e) => error!("pruner task: timeout during pruning: {:#?}", e),
             };
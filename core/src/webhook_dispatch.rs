@@ -0,0 +1,248 @@
+use std::{error::Error, sync::Arc, time::Duration};
+
+use central_repository_config::inner::Config;
+use chrono::{DateTime, Utc};
+use entity::{
+    webhook::{self, WebhookEvent},
+    webhook_delivery,
+};
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{conf::DBConfig, WebhookDeliveryMutation, WebhookQuery};
+
+const SIGNATURE_HEADER: &str = "X-Repository-Signature";
+const EVENT_HEADER: &str = "X-Repository-Event";
+const DELIVERY_HEADER: &str = "X-Repository-Delivery";
+
+static QUEUE: OnceCell<flume::Sender<WebhookNotification>> = OnceCell::new();
+
+struct WebhookNotification {
+    event: WebhookEvent,
+    format_id: Option<i32>,
+    data: Value,
+}
+
+/// The JSON body sent to webhook receivers.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WebhookPayload<'a> {
+    event: WebhookEvent,
+    delivery_id: Uuid,
+    timestamp: DateTime<Utc>,
+    data: &'a Value,
+}
+
+pub struct WebhookDispatcher;
+
+impl WebhookDispatcher {
+    /// Spawn the background task that delivers webhook notifications.
+    pub fn init() -> Result<(), Box<dyn Error>> {
+        let config = Config::get();
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.webhook_timeout_seconds))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let (tx, rx) = flume::bounded(config.webhook_queue_depth);
+        if QUEUE.set(tx).is_err() {
+            return Err("Cannot set webhook queue".into());
+        }
+        tokio::spawn(Self::dispatch(client, rx));
+        info!("Webhooks: dispatcher started");
+        Ok(())
+    }
+
+    /// Queue a notification for all the webhooks subscribed to `event`.
+    /// This never blocks: if the queue is full, the notification is dropped.
+    pub fn notify<T: Serialize>(event: WebhookEvent, format_id: Option<i32>, data: &T) {
+        let Some((queue, notification)) = Self::notification(event, format_id, data) else {
+            return;
+        };
+        if let Err(err) = queue.try_send(notification) {
+            warn!("webhooks: dropping {event:?} notification: {err}");
+        }
+    }
+
+    /// Same as [`Self::notify`], but wait for room in the queue instead of
+    /// dropping the notification. For tasks that send many of them at once.
+    pub async fn notify_waiting<T: Serialize>(
+        event: WebhookEvent,
+        format_id: Option<i32>,
+        data: &T,
+    ) {
+        let Some((queue, notification)) = Self::notification(event, format_id, data) else {
+            return;
+        };
+        if let Err(err) = queue.send_async(notification).await {
+            warn!("webhooks: dropping {event:?} notification: {err}");
+        }
+    }
+
+    fn notification<T: Serialize>(
+        event: WebhookEvent,
+        format_id: Option<i32>,
+        data: &T,
+    ) -> Option<(
+        &'static flume::Sender<WebhookNotification>,
+        WebhookNotification,
+    )> {
+        let Some(queue) = QUEUE.get() else {
+            debug!("webhooks: dispatcher isn't running, ignoring {event:?}");
+            return None;
+        };
+        let data = match serde_json::to_value(data) {
+            Ok(data) => data,
+            Err(err) => {
+                error!("webhooks: cannot serialize {event:?} notification: {err}");
+                return None;
+            }
+        };
+        let notification = WebhookNotification {
+            event,
+            format_id,
+            data,
+        };
+        Some((queue, notification))
+    }
+
+    async fn dispatch(client: reqwest::Client, rx: flume::Receiver<WebhookNotification>) {
+        while let Ok(notification) = rx.recv_async().await {
+            let webhooks =
+                match WebhookQuery::find_subscribed(notification.event, notification.format_id)
+                    .await
+                {
+                    Ok(webhooks) => webhooks,
+                    Err(err) => {
+                        error!("webhooks: cannot get subscribed webhooks: {err:?}");
+                        continue;
+                    }
+                };
+            debug!(
+                "webhooks: {:?}: notifying {} webhook(s)",
+                notification.event,
+                webhooks.len()
+            );
+            let data = Arc::new(notification.data);
+            // Every delivery runs on its own so slow receivers can't hold up
+            // the rest.
+            for webhook in webhooks {
+                tokio::spawn(Self::deliver(
+                    client.clone(),
+                    webhook,
+                    notification.event,
+                    data.clone(),
+                ));
+            }
+        }
+    }
+
+    /// Deliver a notification to `webhook`, retrying with exponential backoff,
+    /// and log the outcome.
+    async fn deliver(
+        client: reqwest::Client,
+        webhook: webhook::Model,
+        event: WebhookEvent,
+        data: Arc<Value>,
+    ) {
+        let config = Config::get();
+        let delivery_id = Uuid::new_v4();
+        let mut delivery = webhook_delivery::Model {
+            id: 0,
+            webhook_id: webhook.id,
+            delivery_id,
+            event,
+            attempts: 0,
+            status_code: None,
+            success: false,
+            detail: String::new(),
+            created_at: Utc::now(),
+        };
+        let payload = WebhookPayload {
+            event,
+            delivery_id,
+            timestamp: Utc::now(),
+            data: &data,
+        };
+
+        match serde_json::to_vec(&payload) {
+            Ok(body) if body.len() > config.webhook_max_body_bytes => {
+                delivery.detail = format!(
+                    "payload too large: {} bytes (max {})",
+                    body.len(),
+                    config.webhook_max_body_bytes
+                );
+            }
+            Ok(body) => {
+                let signature = Self::sign(&webhook.secret, &body);
+                let mut delay = Duration::from_millis(config.webhook_retry_base_delay_ms);
+                while delivery.attempts < config.webhook_max_attempts as i32 {
+                    if delivery.attempts > 0 {
+                        tokio::time::sleep(delay).await;
+                        delay = delay.saturating_mul(2);
+                    }
+                    delivery.attempts += 1;
+                    let response = client
+                        .post(&webhook.url)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .header(EVENT_HEADER, event.get_serialized().as_str().unwrap_or(""))
+                        .header(DELIVERY_HEADER, delivery_id.to_string())
+                        .header(SIGNATURE_HEADER, signature.as_str())
+                        .body(body.clone())
+                        .send()
+                        .await;
+                    match response {
+                        Ok(response) => {
+                            let status = response.status();
+                            delivery.status_code = Some(status.as_u16().into());
+                            delivery.success = status.is_success();
+                            delivery.detail = format!("receiver answered with {status}");
+                        }
+                        Err(err) => {
+                            delivery.status_code = None;
+                            delivery.detail = err.to_string();
+                        }
+                    }
+                    if delivery.success {
+                        break;
+                    }
+                    debug!(
+                        "webhooks: delivery {delivery_id}: attempt {} failed: {}",
+                        delivery.attempts, delivery.detail
+                    );
+                }
+            }
+            Err(err) => delivery.detail = format!("cannot serialize payload: {err}"),
+        }
+
+        if delivery.success {
+            info!(
+                "webhooks: delivered {event:?} to webhook {} ({delivery_id})",
+                webhook.id
+            );
+        } else {
+            warn!(
+                "webhooks: couldn't deliver {event:?} to webhook {} after {} attempt(s): {}",
+                webhook.id, delivery.attempts, delivery.detail
+            );
+        }
+        if let Err(err) =
+            WebhookDeliveryMutation::create(DBConfig::get_connection(), delivery).await
+        {
+            error!("webhooks: cannot save delivery {delivery_id}: {err:?}");
+        }
+    }
+
+    /// Sign `body` with the webhook's secret. Receivers should compute the same
+    /// HMAC-SHA256 over the raw body and compare it with the signature header.
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key size");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+}
@@ -4,14 +4,25 @@ use central_repository_macros::AsQueryParam;
 use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 #[derive(
-    Default, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, AsQueryParam,
+    Default,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    AsQueryParam,
+    ToSchema,
 )]
 #[as_query(sort_default_column = "Column::CreatedAt", camel_case)]
 #[sea_orm(table_name = "api_key")]
 #[serde(rename_all = "camelCase")]
+#[schema(as = ApiKey)]
 pub struct Model {
     // Don't let users define this field.
     #[serde(skip_deserializing)]
@@ -47,8 +58,9 @@ pub struct Model {
     pub active: bool,
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Debug, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
+#[schema(as = ApiKeyUpdate)]
 pub struct UpdatableModel {
     pub active: Option<bool>,
     // Not part of the real model, but this can be used
@@ -56,6 +68,42 @@ pub struct UpdatableModel {
     pub rotate: Option<bool>,
 }
 
+/// An API key and whether it's due for rotation.
+#[derive(Clone, Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = ApiKeyWithStatus)]
+pub struct ModelWithStatus {
+    #[serde(flatten)]
+    pub key: Model,
+    /// Whether this key wasn't created or rotated in the last `MAX_KEY_AGE_DAYS` days.
+    pub stale: bool,
+}
+
+impl Model {
+    /// When this key goes stale, i.e. `max_age_days` after it was last rotated.
+    /// `None` if that's out of range.
+    pub fn stale_at(&self, max_age_days: u32) -> Option<DateTime<Utc>> {
+        self.last_rotated_at
+            .checked_add_signed(chrono::Duration::days(max_age_days.into()))
+    }
+
+    /// Whether this key wasn't rotated in the last `max_age_days` days.
+    /// Keys never go stale if `max_age_days` is 0.
+    pub fn is_stale(&self, max_age_days: u32, now: DateTime<Utc>) -> bool {
+        max_age_days > 0
+            && self
+                .stale_at(max_age_days)
+                .is_some_and(|stale_at| stale_at <= now)
+    }
+
+    pub fn with_status(self, max_age_days: u32, now: DateTime<Utc>) -> ModelWithStatus {
+        ModelWithStatus {
+            stale: self.is_stale(max_age_days, now),
+            key: self,
+        }
+    }
+}
+
 fn active_default() -> bool {
     true
 }
@@ -78,3 +126,47 @@ impl Related<super::user::Entity> for Entity {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    fn rotated_at(last_rotated_at: DateTime<Utc>) -> Model {
+        Model {
+            last_rotated_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stale_after_max_age_days() {
+        let rotated = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let key = rotated_at(rotated);
+        assert_eq!(key.stale_at(30), Some(rotated + Duration::days(30)));
+        let stale_at = rotated + Duration::days(30);
+        assert!(!key.is_stale(30, stale_at - Duration::seconds(1)));
+        assert!(key.is_stale(30, stale_at));
+        assert!(key.is_stale(30, stale_at + Duration::days(365)));
+        assert!(!key.clone().with_status(30, rotated).stale);
+        assert!(key.with_status(30, stale_at).stale);
+    }
+
+    #[test]
+    fn never_stale_without_max_age() {
+        let rotated = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let key = rotated_at(rotated);
+        // i.e. right away, but 0 disables MAX_KEY_AGE_DAYS.
+        assert_eq!(key.stale_at(0), Some(rotated));
+        assert!(!key.is_stale(0, rotated));
+        assert!(!key.is_stale(0, rotated + Duration::days(10_000)));
+    }
+
+    #[test]
+    fn out_of_range_keys_never_go_stale() {
+        let key = rotated_at(DateTime::<Utc>::MAX_UTC - Duration::days(1));
+        assert_eq!(key.stale_at(30), None);
+        assert!(!key.is_stale(30, DateTime::<Utc>::MAX_UTC));
+    }
+}
@@ -1,8 +1,22 @@
 use std::fmt::Debug;
 
 use sea_orm::DbErr;
+use serde::Serialize;
 use strum::AsRefStr;
 use thiserror::Error;
+use utoipa::ToSchema;
+
+/// A problem with a single search argument.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgumentError {
+    /// Index of the search group.
+    pub group: usize,
+    /// Index of the argument inside its group.
+    pub argument: usize,
+    pub column: String,
+    pub reason: String,
+}
 
 #[derive(Error, Debug, AsRefStr)]
 pub enum DatabaseQueryError {
@@ -14,12 +28,21 @@ pub enum DatabaseQueryError {
     InvalidUsage(String),
     #[error("Couldn't cast value to expected type")]
     CastError,
-    #[error("One or more formats have different types for column: '{0}'")]
-    ColumnWithMixedTypesError(String),
+    #[error(
+        "Column '{column}' is {kinds}. Narrow down the `formats` list to formats that \
+         agree on its type, or search it with `treatAs`"
+    )]
+    ColumnWithMixedTypesError { column: String, kinds: String },
     #[error("Empty query")]
     EmptyQuery,
     #[error("Regex error")]
     InvalidRegex,
+    #[error("Query too large: {0}")]
+    QueryTooLarge(String),
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("{} search arguments are invalid", .0.len())]
+    Multiple(Vec<ArgumentError>),
     #[error("Internal DB error: {0}")]
     DbErr(#[from] DbErr),
 }
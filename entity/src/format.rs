@@ -1,21 +1,22 @@
-use std::ops::Deref;
+use std::{fmt, ops::Deref};
 
 use crate::traits::{AsQueryParamFilterable, AsQueryParamSortable};
 use central_repository_macros::AsQueryParam;
 use chrono::{DateTime, Utc};
 use sea_orm::Select;
 use sea_orm::{entity::prelude::*, FromJsonQueryResult};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::hash::Hash;
+use utoipa::ToSchema;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema)]
 pub enum ColumnKind {
     Number,
     String,
     Datetime,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema)]
 pub struct ColumnSchema {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -23,7 +24,9 @@ pub struct ColumnSchema {
     pub kind: ColumnKind,
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult)]
+#[derive(
+    Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, ToSchema,
+)]
 pub struct FormatSchema(pub Vec<ColumnSchema>);
 
 impl Deref for FormatSchema {
@@ -33,6 +36,56 @@ impl Deref for FormatSchema {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum RuleOperator {
+    Lt,
+    Lte,
+    Eq,
+    Gte,
+    Gt,
+}
+
+/// A comparison every record of a format must pass: `leftColumn operator
+/// rightColumn`, or `leftColumn operator constant`. Use `constant` for
+/// comparisons against a fixed value (a number or, for string and datetime
+/// columns, a string).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatRule {
+    pub left_column: String,
+    pub operator: RuleOperator,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right_column: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub constant: Option<serde_json::Value>,
+}
+
+impl fmt::Display for FormatRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = serde_json::to_value(self.operator).map_err(|_| fmt::Error)?;
+        let operator = operator.as_str().unwrap_or_default();
+        match (&self.right_column, &self.constant) {
+            (Some(right), _) => write!(f, "'{}' {operator} '{right}'", self.left_column),
+            (_, Some(constant)) => write!(f, "'{}' {operator} {constant}", self.left_column),
+            _ => write!(f, "'{}' {operator} ?", self.left_column),
+        }
+    }
+}
+
+#[derive(
+    Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, ToSchema,
+)]
+pub struct FormatRules(pub Vec<FormatRule>);
+
+impl Deref for FormatRules {
+    type Target = Vec<FormatRule>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
 // Default retention period. Set to 3 months by default.
 // This value can also be set by the caller.
 fn retention_default() -> i32 {
@@ -40,10 +93,13 @@ fn retention_default() -> i32 {
     3 * 30 * 24 * 60
 }
 
-#[derive(AsQueryParam, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize)]
+#[derive(
+    AsQueryParam, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, ToSchema,
+)]
 #[sea_orm(table_name = "format")]
 #[serde(rename_all = "camelCase")]
 #[as_query(sort_default_column = "Column::Id", camel_case)]
+#[schema(as = Format)]
 pub struct Model {
     #[sea_orm(primary_key)]
     // Don't let users define this field.
@@ -57,10 +113,70 @@ pub struct Model {
     #[serde(skip_deserializing)]
     #[as_query(column = "Column::CreatedAt")]
     pub created_at: DateTime<Utc>,
+    /// The admin who created this format (unset for formats created before
+    /// this was tracked, or whose creator was deleted).
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::CreatedBy", eq, custom_convert = "*value")]
+    pub created_by: Option<Uuid>,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::UpdatedAt",
+        custom_convert = "*value"
+    )]
+    pub updated_at: DateTime<Utc>,
     pub schema: FormatSchema,
+    /// Comparisons between columns (or against constants) that every record
+    /// must pass. Like the schema, these can't be changed once the format
+    /// is created.
+    #[serde(default)]
+    pub rules: FormatRules,
     /// The period (in minutes), to keep data for this format.
     #[serde(default = "retention_default")]
     pub retention_period_minutes: i32,
+    /// Maximum number of records this format may hold (no limit if unset).
+    pub max_records: Option<i64>,
+    /// Maximum number of records that may be uploaded to this format
+    /// per (UTC) day (no limit if unset).
+    pub max_records_per_day: Option<i64>,
+    /// Archived formats are hidden from non-superusers and can't be uploaded
+    /// to, but their data is kept (and pruned) as usual.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::Archived", eq, custom_convert = "*value")]
+    pub archived: bool,
+    /// The data of the records of encrypted formats is encrypted at rest, so
+    /// it can't be searched. This can't be changed once the format is created.
+    #[serde(default)]
+    #[as_query(column = "Column::Encrypted", eq, custom_convert = "*value")]
+    pub encrypted: bool,
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = FormatUpdate)]
+pub struct UpdatableModel {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub retention_period_minutes: Option<i32>,
+    // Quotas can be removed by explicitly setting them to null, so we
+    // need to tell "missing" and "null" apart.
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_records: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_records_per_day: Option<Option<i64>>,
+    pub archived: Option<bool>,
+}
+
+pub(crate) fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
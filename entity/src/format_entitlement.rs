@@ -7,10 +7,13 @@ use chrono::{DateTime, Utc};
 use sea_orm::sea_query::BinOper;
 use sea_orm::{entity::prelude::*, FromJsonQueryResult};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 pub const ARRAY_CONTAINS_OP: BinOper = BinOper::Custom("?");
 
-#[derive(Eq, PartialEq, Deserialize, Serialize, Debug, Clone, FromJsonQueryResult, Hash)]
+#[derive(
+    Eq, PartialEq, Deserialize, Serialize, Debug, Clone, FromJsonQueryResult, Hash, ToSchema,
+)]
 #[serde(rename_all = "camelCase")]
 pub enum AccessLevel {
     Read,
@@ -24,13 +27,23 @@ pub enum AccessLevel {
 }
 
 impl AccessLevel {
+    /// Every access level, from the weakest to the strongest.
+    pub const ALL: [AccessLevel; 4] = [
+        AccessLevel::Read,
+        AccessLevel::Write,
+        AccessLevel::LimitedDelete,
+        AccessLevel::Delete,
+    ];
+
     #[inline(always)]
     pub fn get_serialized(&self) -> serde_json::Value {
         serde_json::to_value(self).expect("unexpected error: access level encode")
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, Default)]
+#[derive(
+    Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, Default, ToSchema,
+)]
 pub struct Access(pub HashSet<AccessLevel>);
 
 impl Deref for Access {
@@ -41,11 +54,21 @@ impl Deref for Access {
 }
 
 #[derive(
-    AsQueryParam, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, Default,
+    AsQueryParam,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    Default,
+    ToSchema,
 )]
 #[serde(rename_all = "camelCase")]
 #[as_query(sort_default_column = "Column::CreatedAt")]
 #[sea_orm(table_name = "format_entitlement")]
+#[schema(as = FormatEntitlement)]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     #[as_query(
@@ -80,11 +103,28 @@ pub struct Model {
         custom_convert = "sea_orm::Value::from(*value)"
     )]
     pub created_at: DateTime<Utc>,
+    /// The admin who granted this entitlement (unset for entitlements granted
+    /// before this was tracked, or whose creator was deleted).
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::CreatedBy", eq, custom_convert = "*value")]
+    pub created_by: Option<Uuid>,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        column = "Column::UpdatedAt",
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        custom_convert = "sea_orm::Value::from(*value)"
+    )]
+    pub updated_at: DateTime<Utc>,
     pub access: Access,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
+#[schema(as = FormatEntitlementKey)]
 pub struct SearchModel {
     pub user_id: Uuid,
     pub format_id: i32,
@@ -1,11 +1,16 @@
 pub mod api_key;
+pub mod as_query;
 pub mod error;
 pub mod format;
 pub mod format_entitlement;
 pub mod record;
+pub mod saved_search;
+pub mod saved_search_share;
 pub mod traits;
 pub mod upload_session;
 pub mod user;
+pub mod webhook;
+pub mod webhook_delivery;
 
 pub use serde;
 pub use serde::{Deserialize, Serialize};
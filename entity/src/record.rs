@@ -1,9 +1,11 @@
 use crate::traits::{AsQueryParamFilterable, AsQueryParamSortable};
 use central_repository_macros::AsQueryParam;
-use sea_orm::{entity::prelude::*, FromJsonQueryResult};
+use chrono::{DateTime, Utc};
+use sea_orm::{entity::prelude::*, FromJsonQueryResult, FromQueryResult};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{collections::HashMap, ops::Deref};
+use utoipa::ToSchema;
 
 /// Document type used throughout the entire project.
 /// Note that the JSON value can be any JSON object, though
@@ -11,7 +13,10 @@ use std::{collections::HashMap, ops::Deref};
 /// as defined by the Format.
 pub type DynamicHashmap = HashMap<String, Value>;
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult)]
+#[derive(
+    Default, Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, ToSchema,
+)]
+#[schema(value_type = Object)]
 pub struct RecordJsonData(pub DynamicHashmap);
 
 impl Deref for RecordJsonData {
@@ -23,10 +28,20 @@ impl Deref for RecordJsonData {
 }
 
 #[derive(
-    AsQueryParam, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, Default,
+    AsQueryParam,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    Default,
+    ToSchema,
 )]
 #[sea_orm(table_name = "record")]
 #[as_query(sort_default_column = "Column::Id", camel_case)]
+#[schema(as = Record)]
 pub struct Model {
     #[sea_orm(primary_key)]
     // Don't let users define this field.
@@ -46,21 +61,53 @@ pub struct Model {
     pub upload_session_id: i32,
     #[serde(skip_deserializing)]
     pub format_id: i32,
+    // Set when the record is inserted (all the records of an upload get the
+    // same timestamp as their upload session).
+    #[serde(skip_deserializing)]
+    #[as_query(
+        column = "Column::CreatedAt",
+        lt,
+        gt,
+        lte,
+        gte,
+        custom_convert = "*value"
+    )]
+    pub created_at: DateTime<Utc>,
     pub data: RecordJsonData,
+    // The data of the records of encrypted formats (`data` is left empty).
+    // Never exposed, records are decrypted after they're fetched.
+    #[serde(skip)]
+    pub encrypted_data: Option<Vec<u8>>,
 }
 
 impl Model {
     #[inline(always)]
-    pub fn new(upload_session_id: i32, format_id: i32, data: DynamicHashmap) -> Self {
+    pub fn new(
+        upload_session_id: i32,
+        format_id: i32,
+        created_at: DateTime<Utc>,
+        data: DynamicHashmap,
+    ) -> Self {
         Self {
             upload_session_id,
             format_id,
+            created_at,
             data: RecordJsonData(data),
             id: Default::default(),
+            encrypted_data: None,
         }
     }
 }
 
+/// The fixed columns of a record, without its data. Returned by
+/// `POST /record/filter?dataless=true`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, FromQueryResult, ToSchema)]
+pub struct DatalessRecord {
+    pub id: i64,
+    pub upload_session_id: i32,
+    pub format_id: i32,
+}
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     // define inverse relation
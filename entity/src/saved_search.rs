@@ -0,0 +1,126 @@
+use std::{collections::BTreeMap, ops::Deref};
+
+use crate::{
+    format::ColumnKind,
+    traits::{AsQueryParamFilterable, AsQueryParamSortable},
+};
+use central_repository_macros::AsQueryParam;
+use chrono::{DateTime, Utc};
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(
+    AsQueryParam,
+    Default,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[as_query(sort_default_column = "Column::Id", camel_case)]
+#[sea_orm(table_name = "saved_search")]
+#[serde(rename_all = "camelCase")]
+#[schema(as = SavedSearch)]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    // Don't let users define this field.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::Id", eq, lt, gt, lte, gte, custom_convert = "*value")]
+    pub id: i32,
+    // The owner of this saved search. Always set to the caller.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::UserId", eq, custom_convert = "*value")]
+    pub user_id: Uuid,
+    #[as_query(column = "Column::Name", eq, contains, like)]
+    pub name: String,
+    #[sea_orm(column_type = "Text")]
+    #[serde(default)]
+    pub description: String,
+    // The stored SearchQuery. It's kept as raw JSON so the search query
+    // can evolve without touching this table.
+    #[sea_orm(column_type = "JsonBinary")]
+    #[schema(value_type = SearchQuery)]
+    pub query: Json,
+    // Named parameters of the query, see `SearchParameters`.
+    #[sea_orm(column_type = "JsonBinary")]
+    #[serde(default)]
+    pub parameters: SearchParameters,
+    // Share this search with every user that can read one of its formats
+    // (any format if it doesn't list them). It still runs with the
+    // entitlements of whoever executes it, and only the owner can modify it.
+    #[serde(default)]
+    #[as_query(column = "Column::Shared", eq, custom_convert = "*value")]
+    pub shared: bool,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::CreatedAt",
+        custom_convert = "*value"
+    )]
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::UpdatedAt",
+        custom_convert = "*value"
+    )]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = SavedSearchUpdate)]
+pub struct UpdatableModel {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[schema(value_type = Option<SearchQuery>)]
+    pub query: Option<Json>,
+    pub parameters: Option<SearchParameters>,
+    pub shared: Option<bool>,
+}
+
+/// Parameters of a query template: name -> expected column kind. The stored
+/// query refers to them with `{"$param": "<name>"}` in `compareAgainst`.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, Default, ToSchema,
+)]
+pub struct SearchParameters(pub BTreeMap<String, ColumnKind>);
+
+impl Deref for SearchParameters {
+    type Target = BTreeMap<String, ColumnKind>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,75 @@
+use crate::traits::{AsQueryParamFilterable, AsQueryParamSortable};
+use central_repository_macros::AsQueryParam;
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A saved search shared with a specific user.
+#[derive(
+    AsQueryParam,
+    Default,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[as_query(sort_default_column = "Column::CreatedAt", camel_case)]
+#[sea_orm(table_name = "saved_search_share")]
+#[serde(rename_all = "camelCase")]
+#[schema(as = SavedSearchShare)]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    // Always set to the saved search in the path.
+    #[serde(skip_deserializing)]
+    pub saved_search_id: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    #[as_query(column = "Column::UserId", eq, custom_convert = "*value")]
+    pub user_id: Uuid,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::CreatedAt",
+        custom_convert = "*value"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::saved_search::Entity",
+        from = "Column::SavedSearchId",
+        to = "super::saved_search::Column::Id"
+    )]
+    SavedSearch,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::saved_search::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SavedSearch.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
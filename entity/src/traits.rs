@@ -1,9 +1,13 @@
 use sea_orm::{EntityTrait, Select};
 
+use crate::error::DatabaseQueryError;
+
 pub trait AsQueryParamFilterable {
     fn filter<E: EntityTrait>(&self, select: Select<E>) -> Select<E>;
 }
 
 pub trait AsQueryParamSortable {
-    fn sort<E: EntityTrait>(&self, select: Select<E>) -> Select<E>;
+    /// Sort by the requested `orderBy` column, or by the default one if there's
+    /// none. Fails if `orderBy` isn't a sortable column.
+    fn sort<E: EntityTrait>(&self, select: Select<E>) -> Result<Select<E>, DatabaseQueryError>;
 }
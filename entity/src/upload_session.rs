@@ -3,28 +3,53 @@ use central_repository_macros::AsQueryParam;
 use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(EnumIter, DeriveActiveEnum, Eq, PartialEq, Deserialize, Serialize, Debug, Clone)]
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Eq,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Default,
+    ToSchema,
+)]
 #[sea_orm(rs_type = "String", db_type = "String(None)")]
 pub enum OutcomeKind {
     #[sea_orm(string_value = "SUCCESS")]
     Success,
     #[sea_orm(string_value = "ERROR")]
+    #[default]
     Error,
-}
-
-impl Default for OutcomeKind {
-    fn default() -> Self {
-        Self::Error
-    }
+    // Records are still being inserted.
+    #[sea_orm(string_value = "IN_PROGRESS")]
+    InProgress,
 }
 
 #[derive(
-    AsQueryParam, Default, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize,
+    AsQueryParam,
+    Default,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[as_query(
+    sort_default_column = "Column::Id",
+    camel_case,
+    schema_name = "UploadSessionFilter",
+    deny_unknown_fields
 )]
-#[as_query(sort_default_column = "Column::Id", camel_case)]
 #[sea_orm(table_name = "upload_session")]
 #[serde(rename_all = "camelCase")]
+#[schema(as = UploadSession)]
 pub struct Model {
     #[sea_orm(primary_key)]
     // Don't let users define this field.
@@ -86,6 +111,38 @@ pub struct Model {
     )]
     pub outcome: OutcomeKind,
     pub detail: String,
+    // SHA-256 of the uploaded records (hex), if they passed validation.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::ContentHash", eq, custom_convert = "value.clone()")]
+    pub content_hash: Option<String>,
+    // Size of the upload's JSON body, in bytes.
+    #[serde(skip_deserializing)]
+    #[as_query(
+        column = "Column::PayloadBytes",
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        custom_convert = "*value"
+    )]
+    pub payload_bytes: i64,
+    // Time spent validating and inserting the records, in milliseconds.
+    #[serde(skip_deserializing)]
+    #[as_query(
+        column = "Column::ProcessingMs",
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        custom_convert = "*value"
+    )]
+    pub processing_ms: i64,
+    // API key the upload was authenticated with, unset for password tokens.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::ApiKeyId", eq, custom_convert = "*value")]
+    pub api_key_id: Option<Uuid>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -3,12 +3,48 @@ use crate::traits::AsQueryParamSortable;
 use better_debug::BetterDebug;
 use central_repository_macros::AsQueryParam;
 use chrono::{DateTime, Utc};
-use sea_orm::entity::prelude::*;
 use sea_orm::sea_query::extension::postgres::PgBinOper;
 use sea_orm::sea_query::Expr;
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+/// What a (non-superuser) user can manage, besides its own data. Each role
+/// includes the permissions of the previous ones. Superusers can do anything
+/// regardless of their role.
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Eq,
+    PartialEq,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(None)")]
+pub enum Role {
+    /// Regular user, only has access to its entitled formats.
+    #[sea_orm(string_value = "USER")]
+    #[default]
+    User,
+    /// Can read users, entitlements, upload sessions and statistics.
+    #[sea_orm(string_value = "AUDITOR")]
+    Auditor,
+    /// Can also manage formats and entitlements, and prune upload sessions.
+    #[sea_orm(string_value = "FORMAT_MANAGER")]
+    FormatManager,
+    /// Can also manage users, their API keys and webhooks.
+    #[sea_orm(string_value = "ADMIN")]
+    Admin,
+}
+
 #[derive(
     Default,
     Clone,
@@ -19,10 +55,12 @@ use uuid::Uuid;
     Deserialize,
     Serialize,
     AsQueryParam,
+    ToSchema,
 )]
 #[as_query(sort_default_column = "Column::CreatedAt", camel_case)]
 #[sea_orm(table_name = "user")]
 #[serde(rename_all = "camelCase")]
+#[schema(as = User)]
 pub struct Model {
     // Don't let users define this field.
     #[as_query(column = "Column::Id", eq, lt, gt, lte, gte, custom_convert = "*value")]
@@ -41,15 +79,62 @@ pub struct Model {
     pub is_superuser: bool,
     #[serde(default = "active_default")]
     pub active: bool,
+    /// Unique (regardless of case) email address.
+    #[as_query(column = "Column::Email", eq, ilike, custom_convert = "value.clone()")]
+    #[serde(default)]
+    pub email: Option<String>,
+    #[as_query(
+        column = "Column::Role",
+        eq,
+        custom_convert = "sea_orm::Value::from(value.to_value())"
+    )]
+    #[serde(default)]
+    pub role: Role,
+    /// Encrypted TOTP secret, set once the user starts enrolling in 2FA.
+    #[serde(skip)]
+    #[better_debug(ignore = true)]
+    pub totp_secret: Option<String>,
+    /// Whether password logins also require a TOTP (or recovery) code.
+    #[serde(skip_deserializing)]
+    pub totp_enabled: bool,
+    #[serde(skip)]
+    #[better_debug(ignore = true)]
+    pub totp_recovery_codes: RecoveryCodes,
+    /// Last time step a TOTP code was accepted for, so codes can't be replayed.
+    #[serde(skip)]
+    pub totp_last_step: Option<i64>,
+    /// Maximum number of bytes this user may upload, counting the payloads of
+    /// all its successful uploads (no limit if unset).
+    #[serde(default)]
+    pub max_storage_bytes: Option<i64>,
+}
+
+/// Hashes of the recovery codes that haven't been used yet.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, Default)]
+pub struct RecoveryCodes(pub Vec<String>);
+
+impl Model {
+    /// Whether this user has (at least) `role`.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.is_superuser || self.role >= role
+    }
 }
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Debug, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
+#[schema(as = UserUpdate)]
 pub struct UpdatableModel {
     pub username: Option<String>,
     pub password: Option<String>,
     pub is_superuser: Option<bool>,
     pub active: Option<bool>,
+    // The email can be removed by explicitly setting it to null.
+    #[serde(default, deserialize_with = "crate::format::nullable")]
+    pub email: Option<Option<String>>,
+    pub role: Option<Role>,
+    // The quota can be removed by explicitly setting it to null.
+    #[serde(default, deserialize_with = "crate::format::nullable")]
+    pub max_storage_bytes: Option<Option<i64>>,
 }
 
 fn is_superuser_default() -> bool {
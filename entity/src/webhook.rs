@@ -0,0 +1,136 @@
+use std::collections::HashSet;
+use std::ops::Deref;
+
+use crate::traits::{AsQueryParamFilterable, AsQueryParamSortable};
+use central_repository_macros::AsQueryParam;
+use chrono::{DateTime, Utc};
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(
+    EnumIter,
+    DeriveActiveEnum,
+    Eq,
+    PartialEq,
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    Hash,
+    ToSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(None)")]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEvent {
+    /// Fired after all the records of an upload have been saved.
+    #[sea_orm(string_value = "UPLOAD_COMPLETED")]
+    UploadCompleted,
+    /// Fired after the upload sessions of a format have been pruned.
+    #[sea_orm(string_value = "PRUNE")]
+    Prune,
+    /// Fired once a day for every API key that is stale or about to go stale.
+    #[sea_orm(string_value = "API_KEY_EXPIRING")]
+    ApiKeyExpiring,
+}
+
+impl WebhookEvent {
+    #[inline(always)]
+    pub fn get_serialized(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("unexpected error: webhook event encode")
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, Debug, Clone, PartialEq, Eq, FromJsonQueryResult, Default, ToSchema,
+)]
+pub struct WebhookEvents(pub HashSet<WebhookEvent>);
+
+impl Deref for WebhookEvents {
+    type Target = HashSet<WebhookEvent>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(
+    AsQueryParam,
+    Default,
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    DeriveEntityModel,
+    Deserialize,
+    Serialize,
+    ToSchema,
+)]
+#[as_query(sort_default_column = "Column::Id", camel_case)]
+#[sea_orm(table_name = "webhook")]
+#[serde(rename_all = "camelCase")]
+#[schema(as = Webhook)]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    // Don't let users define this field.
+    #[serde(skip_deserializing)]
+    #[as_query(column = "Column::Id", eq, lt, gt, lte, gte, custom_convert = "*value")]
+    pub id: i32,
+    #[as_query(column = "Column::Url", eq, contains, like)]
+    pub url: String,
+    // Used to sign the payloads (HMAC-SHA256). Never returned to clients.
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: WebhookEvents,
+    // Only fire for events related to this format. If not set, the webhook
+    // fires for all formats.
+    pub format_id: Option<i32>,
+    #[serde(default = "active_default")]
+    #[as_query(column = "Column::Active", eq, custom_convert = "*value")]
+    pub active: bool,
+    #[serde(skip_deserializing, default = "chrono::offset::Utc::now")]
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::CreatedAt",
+        custom_convert = "*value"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = WebhookUpdate)]
+pub struct UpdatableModel {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    pub events: Option<WebhookEvents>,
+    pub active: Option<bool>,
+}
+
+fn active_default() -> bool {
+    true
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::format::Entity",
+        from = "Column::FormatId",
+        to = "super::format::Column::Id"
+    )]
+    Format,
+    #[sea_orm(has_many = "super::webhook_delivery::Entity")]
+    WebhookDelivery,
+}
+
+impl Related<super::webhook_delivery::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WebhookDelivery.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
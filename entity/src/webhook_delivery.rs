@@ -0,0 +1,60 @@
+use crate::traits::{AsQueryParamFilterable, AsQueryParamSortable};
+use crate::webhook::WebhookEvent;
+use central_repository_macros::AsQueryParam;
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// The outcome of a single webhook delivery (after all of its attempts).
+#[derive(
+    AsQueryParam, Clone, Debug, PartialEq, Eq, DeriveEntityModel, Deserialize, Serialize, ToSchema,
+)]
+#[as_query(sort_default_column = "Column::Id", camel_case)]
+#[sea_orm(table_name = "webhook_delivery")]
+#[serde(rename_all = "camelCase")]
+#[schema(as = WebhookDelivery)]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[as_query(column = "Column::Id", eq, lt, gt, lte, gte, custom_convert = "*value")]
+    pub id: i32,
+    pub webhook_id: i32,
+    // Sent to the receiver in the X-Repository-Delivery header.
+    pub delivery_id: Uuid,
+    pub event: WebhookEvent,
+    pub attempts: i32,
+    // HTTP status code of the last attempt, if the receiver answered.
+    pub status_code: Option<i32>,
+    #[as_query(column = "Column::Success", eq, custom_convert = "*value")]
+    pub success: bool,
+    pub detail: String,
+    #[as_query(
+        eq,
+        lt,
+        gt,
+        lte,
+        gte,
+        column = "Column::CreatedAt",
+        custom_convert = "*value"
+    )]
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::webhook::Entity",
+        from = "Column::WebhookId",
+        to = "super::webhook::Column::Id"
+    )]
+    Webhook,
+}
+
+impl Related<super::webhook::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Webhook.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
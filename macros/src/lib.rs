@@ -4,16 +4,21 @@ use proc_macro::TokenStream;
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro_error::{abort, proc_macro_error};
 use quote::quote;
-use syn::{
-    parse_macro_input, spanned::Spanned, AngleBracketedGenericArguments, DataStruct, DeriveInput,
-    Expr, Ident, PathArguments,
-};
+use syn::{parse_macro_input, spanned::Spanned, DataStruct, DeriveInput, Expr, Ident};
 
 #[derive(FromAttributes, Default, Debug)]
 #[darling(attributes(as_query))]
 struct AsQueryStructOptions {
     sort_default_column: Option<String>,
     camel_case: Option<bool>,
+    // Name of the generated struct in the OpenAPI spec. Only needed if it's
+    // used as a schema (i.e. in a request body), since every entity's
+    // generated struct is called "ModelAsQuery".
+    schema_name: Option<String>,
+    // Also generate `deserialize_strict`, which rejects unknown fields. Query
+    // strings can't be strict since they're shared with other extractors
+    // (pagination, ...), but request bodies can.
+    deny_unknown_fields: Option<bool>,
 }
 
 #[derive(FromAttributes, Default, Debug)]
@@ -62,42 +67,18 @@ impl AsQueryInnerFieldOptions {
     }
 }
 
-#[derive(Debug)]
-struct PathSegmentAnalyze {
-    is_option: bool,
-    _inner_type: String,
-}
-
-impl PathSegmentAnalyze {
-    pub fn new(ty: &syn::Type) -> syn::Result<Self> {
-        if let syn::Type::Path(ty_path) = ty {
-            let last_segment = ty_path.path.segments.last().ok_or_else(|| {
-                syn::Error::new(ty_path.span(), "Couldn't determine the type of this field")
-            })?;
-            if let PathArguments::AngleBracketed(AngleBracketedGenericArguments { args, .. }) =
-                &last_segment.arguments
-            {
-                if let Some(syn::GenericArgument::Type(syn::Type::Path(syn::TypePath {
-                    path,
-                    ..
-                }))) = args.last()
-                {
-                    let last_segment = path.segments.last().ok_or_else(|| {
-                        syn::Error::new(last_segment.span(), "Couldn't extract inner type")
-                    })?;
-                    return Ok(PathSegmentAnalyze {
-                        is_option: last_segment.ident == "Option",
-                        _inner_type: last_segment.ident.to_string(),
-                    });
-                }
-            }
-            Ok(PathSegmentAnalyze {
-                is_option: last_segment.ident == "Option",
-                _inner_type: last_segment.ident.to_string(),
-            })
-        } else {
-            Err(syn::Error::new(ty.span(), "Couldn't extract type"))
-        }
+/// Human-readable description of a filter, used in the OpenAPI docs.
+fn describe_filter(filter: &str) -> &'static str {
+    match filter {
+        "lte" => "is less than or equal to this value",
+        "gte" => "is greater than or equal to this value",
+        "eq" => "is equal to this value",
+        "lt" => "is less than this value",
+        "gt" => "is greater than this value",
+        "like" => "matches this LIKE pattern",
+        "ilike" => "matches this (case-insensitive) ILIKE pattern",
+        "contains" => "contains this string",
+        _ => "matches this value",
     }
 }
 
@@ -116,6 +97,7 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
         abort!(struct_name, "This macro may only be used with structs.");
     };
     let mut available_filtering_columns = vec![];
+    let mut sortable_names = vec![];
     // the attributes for this struct (not its fields).
     let struct_options = AsQueryStructOptions::from_attributes(&ast.attrs)?;
     let mut sort_expr = None;
@@ -125,6 +107,9 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
 
     let mut optionized_fields = vec![];
     let mut filter_fn_matches = vec![];
+    let mut deserialize_arms = vec![];
+    let mut known_keys = vec!["orderBy".to_string()];
+    let mut validations = vec![];
 
     for field in fields {
         let (field_ident, ty) = (&field.ident, &field.ty);
@@ -140,16 +125,43 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
             // this field has no "column" attribute.
             continue;
         }
-
-        let path_analyzed = PathSegmentAnalyze::new(ty)?;
         let column = attr2
             .column
             .clone()
             .ok_or_else(|| syn::Error::new(field.span(), "Missing 'column=blah' attribute."))?;
         let db_column = syn::parse_str::<Expr>(&column)?;
 
+        // values are only compared as-is in SQL if they aren't converted
+        // (e.g. enums are compared as strings), so only check those.
+        let comparable = attr2.custom_convert.as_deref() == Some("*value");
+        if comparable && attr2.gte.is_some() && attr2.lte.is_some() {
+            let gte = Ident::new(&format!("{field_ident}_gte"), field_ident.span());
+            let lte = Ident::new(&format!("{field_ident}_lte"), field_ident.span());
+            let message = format!(
+                "`{}` is greater than `{}`, nothing can match",
+                gte.to_string().to_case(Case::Camel),
+                lte.to_string().to_case(Case::Camel)
+            );
+            // repeated values are ORed, so the range is empty only if the
+            // smallest lower bound is above the largest upper bound.
+            validations.push(quote! {
+                if let (Some(gte), Some(lte)) = (self.#gte.iter().min(), self.#lte.iter().max()) {
+                    if gte > lte {
+                        return Err(crate::error::DatabaseQueryError::InvalidUsage(#message.into()));
+                    }
+                }
+            });
+        }
+
         for f in attr2.filters_as_list() {
+            let doc = format!(
+                "Only return items whose `{}` {}. Repeat it to match any of several values.",
+                field_ident.to_string().to_case(Case::Camel),
+                describe_filter(&f)
+            );
             let new_field_name = format!("{field_ident}_{}", f);
+            let key = new_field_name.to_case(Case::Camel);
+            known_keys.push(key.clone());
             let field_ident = Ident::new(&new_field_name, field_ident.span());
             let value = match &attr2.custom_convert {
                 Some(value) => syn::parse_str::<Expr>(value)?,
@@ -158,34 +170,36 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
 
             let f = Ident::new(&f, field_ident.span());
 
-            if f.eq("ilike") {
+            let condition = if f.eq("ilike") {
                 // handle special ilike case
-                filter_fn_matches.push(quote! {
-                    select = match self.#field_ident.as_ref() {
-                        Some(value) => select.filter(
-                            Expr::col(#db_column).binary(PgBinOper::ILike, #value)
-                        ),
-                        _ => select,
-                    };
-                });
+                quote! { Expr::col(#db_column).binary(PgBinOper::ILike, #value) }
             } else {
                 // all other methods can be directly accessed in the column instance
-                filter_fn_matches.push(quote! {
-                    select = match self.#field_ident.as_ref() {
-                        Some(value) => select.filter(#db_column.#f(#value)),
-                        _ => select,
-                    };
-                });
-            }
-
-            optionized_fields.push(if path_analyzed.is_option {
-                quote! {
-                    #field_ident: #ty
-                }
-            } else {
-                quote! {
-                    #field_ident: std::option::Option<#ty>
+                quote! { #db_column.#f(#value) }
+            };
+            // repeated values are ORed, different filters are ANDed.
+            filter_fn_matches.push(quote! {
+                if !self.#field_ident.is_empty() {
+                    let condition = self.#field_ident.iter().fold(
+                        sea_orm::Condition::any(),
+                        |condition, value| condition.add(#condition),
+                    );
+                    select = select.filter(condition);
                 }
+            });
+
+            deserialize_arms.push(quote! {
+                #key => query
+                    .#field_ident
+                    .extend(map.next_value::<crate::as_query::OneOrMany<#ty>>()?.0),
+            });
+
+            optionized_fields.push(quote! {
+                #[doc = #doc]
+                #[serde(skip_serializing_if = "std::vec::Vec::is_empty")]
+                #[param(required = false)]
+                #[schema(required = false)]
+                #field_ident: std::vec::Vec<#ty>
             })
         }
 
@@ -197,6 +211,7 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
                 field_name_asc = field_name_asc.to_case(Case::Camel)
             }
             let field_name_desc = format!("-{field_name_asc}");
+            sortable_names.push(field_name_asc.clone());
             // build "match =>" arms.
             available_filtering_columns.push(quote! {
                 #field_name_asc => select.order_by_asc(#db_column),
@@ -211,35 +226,149 @@ fn expand(ast: DeriveInput) -> syn::Result<TokenStream2> {
                 struct_name.span(),
                 "Missing default sort column",
             ))?)?;
+        let sortable = sortable_names.join(", ");
         sort_expr = Some(quote! {
             impl AsQueryParamSortable for #bident{
-                fn sort<E: sea_orm::EntityTrait>(&self, mut select: Select<E>) -> Select<E> {
-                    if self.order_by.as_ref().is_none(){
-                        return select;
-                    }
-                    select = match self.order_by.as_ref().unwrap().as_str(){
+                fn sort<E: sea_orm::EntityTrait>(
+                    &self,
+                    mut select: Select<E>,
+                ) -> Result<Select<E>, crate::error::DatabaseQueryError> {
+                    // without orderBy, sort by the default column. Unknown values
+                    // are rejected, so typos don't silently sort by the default.
+                    select = match self.order_by.as_deref().unwrap_or_default() {
                         #(#available_filtering_columns)*
-                        _ => select.order_by_asc(#default_sort_column)
+                        "" => select.order_by_asc(#default_sort_column),
+                        other => {
+                            return Err(crate::error::DatabaseQueryError::InvalidUsage(format!(
+                                "cannot sort by '{other}', orderBy must be one of: {} \
+                                (prefixed with '-' to sort in descending order)",
+                                #sortable
+                            )))
+                        }
                     };
-                    select
+                    Ok(select)
                 }
             }
         })
     }
 
+    let schema_name = match struct_options.schema_name.as_ref() {
+        Some(name) => {
+            let name = Ident::new(name, struct_name.span());
+            Some(quote! { #[schema(as = #name)] })
+        }
+        _ => None,
+    };
+
+    let visitor = Ident::new(
+        &format!("{struct_name_optionized}Visitor"),
+        struct_name.span(),
+    );
+    let strict_fn = match struct_options.deny_unknown_fields.unwrap_or(false) {
+        true => Some(quote! {
+            impl #bident {
+                /// Deserialize an optional filter of a request body (use it with
+                /// `#[serde(default, deserialize_with = "...")]`), rejecting
+                /// unknown fields instead of ignoring them.
+                pub fn deserialize_strict<'de, D: serde::Deserializer<'de>>(
+                    deserializer: D,
+                ) -> Result<std::option::Option<Self>, D::Error> {
+                    struct Strict(#bident);
+
+                    impl<'de> serde::Deserialize<'de> for Strict {
+                        fn deserialize<D: serde::Deserializer<'de>>(
+                            deserializer: D,
+                        ) -> Result<Self, D::Error> {
+                            deserializer
+                                .deserialize_map(#visitor { strict: true })
+                                .map(Strict)
+                        }
+                    }
+
+                    let strict = <std::option::Option<Strict> as serde::Deserialize>::deserialize(
+                        deserializer,
+                    )?;
+                    Ok(strict.map(|strict| strict.0))
+                }
+            }
+        }),
+        false => None,
+    };
+
     let expanded = quote! {
         #[allow(dead_code)]
         use sea_orm::QueryOrder;
 
-        #[derive(Debug, serde::Deserialize, serde::Serialize, Default, std::clone::Clone)]
+        #[derive(
+            Debug,
+            serde::Serialize,
+            Default,
+            std::clone::Clone,
+            utoipa::IntoParams,
+            utoipa::ToSchema,
+        )]
         #[serde(rename_all = "camelCase")]
+        #[into_params(parameter_in = Query)]
+        #schema_name
         pub struct #bident {
+            /// Sort by this field (prefix it with `-` to sort in descending order).
             order_by: std::option::Option<String>,
             #(#optionized_fields,)*
         }
 
         #sort_expr
 
+        // Not derived: query strings can repeat a filter, and every value
+        // must be kept.
+        struct #visitor {
+            strict: bool,
+        }
+
+        impl<'de> serde::de::Visitor<'de> for #visitor {
+            type Value = #bident;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str(#struct_name_optionized)
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                const FIELDS: &[&str] = &[#(#known_keys),*];
+                let mut query = #bident::default();
+                while let Some(key) = map.next_key::<std::string::String>()? {
+                    match key.as_str() {
+                        "orderBy" => query.order_by = map.next_value()?,
+                        #(#deserialize_arms)*
+                        _ if self.strict => {
+                            return Err(serde::de::Error::unknown_field(&key, FIELDS));
+                        }
+                        _ => {
+                            map.next_value::<serde::de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(query)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for #bident {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer.deserialize_map(#visitor { strict: false })
+            }
+        }
+
+        #strict_fn
+
+        impl #bident {
+            /// Make sure these filters can match something, e.g. that no
+            /// `...Gte` is above its `...Lte`.
+            pub fn validate(&self) -> Result<(), crate::error::DatabaseQueryError> {
+                #(#validations)*
+                Ok(())
+            }
+        }
 
         impl AsQueryParamFilterable for #bident {
 
@@ -1,4 +1,5 @@
 pub use sea_orm_migration::prelude::*;
+pub use sea_orm_migration::MigrationStatus;
 
 mod m20230220_183928_create_user;
 mod m20230220_192731_format;
@@ -7,6 +8,25 @@ mod m20230221_195143_record;
 mod m20230315_035330_create_format_entitlement;
 mod m20231011_185400_user_key;
 mod m20231222_175743_format_add_retention;
+mod m20240108_120000_saved_search;
+mod m20240115_120000_webhook;
+mod m20240122_120000_format_add_quotas;
+mod m20240129_120000_audit_columns;
+mod m20240205_120000_record_add_created_at;
+mod m20240212_120000_format_add_archived;
+mod m20240219_120000_user_username_lower_index;
+mod m20240219_130000_user_add_email;
+mod m20240226_120000_user_add_role;
+mod m20240304_120000_user_add_totp;
+mod m20240311_120000_upload_session_add_content_hash;
+mod m20240318_120000_saved_search_add_parameters;
+mod m20240325_120000_saved_search_share;
+mod m20240401_120000_upload_session_add_metrics;
+mod m20240408_120000_format_add_rules;
+mod m20240415_120000_record_encryption;
+mod m20240422_120000_user_add_storage_quota;
+mod m20240429_120000_upload_session_add_api_key;
+mod m20240506_120000_user_add_storage_usage;
 
 pub struct Migrator;
 
@@ -21,6 +41,25 @@ impl MigratorTrait for Migrator {
             Box::new(m20230315_035330_create_format_entitlement::Migration),
             Box::new(m20231011_185400_user_key::Migration),
             Box::new(m20231222_175743_format_add_retention::Migration),
+            Box::new(m20240108_120000_saved_search::Migration),
+            Box::new(m20240115_120000_webhook::Migration),
+            Box::new(m20240122_120000_format_add_quotas::Migration),
+            Box::new(m20240129_120000_audit_columns::Migration),
+            Box::new(m20240205_120000_record_add_created_at::Migration),
+            Box::new(m20240212_120000_format_add_archived::Migration),
+            Box::new(m20240219_120000_user_username_lower_index::Migration),
+            Box::new(m20240219_130000_user_add_email::Migration),
+            Box::new(m20240226_120000_user_add_role::Migration),
+            Box::new(m20240304_120000_user_add_totp::Migration),
+            Box::new(m20240311_120000_upload_session_add_content_hash::Migration),
+            Box::new(m20240318_120000_saved_search_add_parameters::Migration),
+            Box::new(m20240325_120000_saved_search_share::Migration),
+            Box::new(m20240401_120000_upload_session_add_metrics::Migration),
+            Box::new(m20240408_120000_format_add_rules::Migration),
+            Box::new(m20240415_120000_record_encryption::Migration),
+            Box::new(m20240422_120000_user_add_storage_quota::Migration),
+            Box::new(m20240429_120000_upload_session_add_api_key::Migration),
+            Box::new(m20240506_120000_user_add_storage_usage::Migration),
         ]
     }
 }
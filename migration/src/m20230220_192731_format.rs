@@ -53,4 +53,9 @@ pub enum Format {
     CreatedAt,
     Schema,
     RetentionPeriodMinutes,
+    MaxRecords,
+    MaxRecordsPerDay,
+    Archived,
+    Rules,
+    Encrypted,
 }
@@ -65,7 +65,7 @@ impl MigrationTrait for Migration {
 
 /// Learn more at https://docs.rs/sea-query#iden
 #[derive(Iden)]
-enum UploadSession {
+pub enum UploadSession {
     Table,
     Id,
     CreatedAt,
@@ -74,4 +74,6 @@ enum UploadSession {
     RecordCount,
     Outcome,
     Detail,
+    PayloadBytes,
+    ProcessingMs,
 }
@@ -0,0 +1,90 @@
+use entity::{saved_search, user};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SavedSearch::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SavedSearch::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(SavedSearch::UserId)
+                            .uuid()
+                            .not_null()
+                            .comment("Foreign key (owner of this search)"),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(saved_search::Entity, saved_search::Column::UserId)
+                            .to(user::Entity, user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(SavedSearch::Name).string().not_null())
+                    .col(ColumnDef::new(SavedSearch::Description).text().not_null())
+                    .col(
+                        ColumnDef::new(SavedSearch::Query)
+                            .comment("The stored search query")
+                            .json_binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SavedSearch::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SavedSearch::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("saved_search_user_id")
+                    .table(SavedSearch::Table)
+                    .col(SavedSearch::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SavedSearch::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+pub enum SavedSearch {
+    Table,
+    Id,
+    UserId,
+    Name,
+    Description,
+    Query,
+    CreatedAt,
+    UpdatedAt,
+    Parameters,
+    Shared,
+}
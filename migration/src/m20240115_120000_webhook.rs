@@ -0,0 +1,159 @@
+use entity::{format, webhook, webhook_delivery};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Webhook::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Webhook::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Webhook::Url).string().not_null())
+                    .col(
+                        ColumnDef::new(Webhook::Secret)
+                            .comment("HMAC-SHA256 signing secret")
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(Webhook::Events).json_binary().not_null())
+                    .col(
+                        ColumnDef::new(Webhook::FormatId)
+                            .integer()
+                            .comment("Only fire for this format (optional)"),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(webhook::Entity, webhook::Column::FormatId)
+                            .to(format::Entity, format::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::Active)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .col(
+                        ColumnDef::new(Webhook::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(WebhookDelivery::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::WebhookId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                webhook_delivery::Entity,
+                                webhook_delivery::Column::WebhookId,
+                            )
+                            .to(webhook::Entity, webhook::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(WebhookDelivery::DeliveryId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WebhookDelivery::Event).string().not_null())
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Attempts)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WebhookDelivery::StatusCode).integer())
+                    .col(
+                        ColumnDef::new(WebhookDelivery::Success)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(WebhookDelivery::Detail).string().not_null())
+                    .col(
+                        ColumnDef::new(WebhookDelivery::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("webhook_delivery_webhook_id")
+                    .table(WebhookDelivery::Table)
+                    .col(WebhookDelivery::WebhookId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WebhookDelivery::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Webhook::Table).to_owned())
+            .await
+    }
+}
+
+/// Learn more at https://docs.rs/sea-query#iden
+#[derive(Iden)]
+enum Webhook {
+    Table,
+    Id,
+    Url,
+    Secret,
+    Events,
+    FormatId,
+    Active,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum WebhookDelivery {
+    Table,
+    Id,
+    WebhookId,
+    DeliveryId,
+    Event,
+    Attempts,
+    StatusCode,
+    Success,
+    Detail,
+    CreatedAt,
+}
@@ -0,0 +1,60 @@
+/// Adds the (optional) ingestion quotas to the Format table, plus an index
+/// on upload sessions so the quotas can be checked cheaply.
+use entity::upload_session;
+use sea_orm_migration::prelude::*;
+
+use crate::m20230220_192731_format::Format;
+
+const UPLOAD_SESSION_FORMAT_INDEX: &str = "upload_session_format_id_created_at";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .add_column_if_not_exists(ColumnDef::new(Format::MaxRecords).big_integer())
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Format::MaxRecordsPerDay).big_integer(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // Quotas are checked by summing the record count of the format's
+        // upload sessions (optionally, only today's).
+        manager
+            .create_index(
+                Index::create()
+                    .name(UPLOAD_SESSION_FORMAT_INDEX)
+                    .table(upload_session::Entity)
+                    .col(upload_session::Column::FormatId)
+                    .col(upload_session::Column::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(UPLOAD_SESSION_FORMAT_INDEX)
+                    .table(upload_session::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .drop_column(Format::MaxRecords)
+                    .drop_column(Format::MaxRecordsPerDay)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,94 @@
+/// Records who created formats and entitlements, and when they were last
+/// updated. Existing rows get a NULL creator and `updated_at = created_at`.
+use entity::{format, format_entitlement, user};
+use sea_orm_migration::prelude::*;
+
+const FORMAT_CREATED_BY_FKEY: &str = "format_created_by_fkey";
+const FORMAT_ENTITLEMENT_CREATED_BY_FKEY: &str = "format_entitlement_created_by_fkey";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Audit {
+    CreatedBy,
+    UpdatedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, created_at, fkey) in [
+            (
+                format::Entity.into_table_ref(),
+                format::Column::CreatedAt.into_iden(),
+                FORMAT_CREATED_BY_FKEY,
+            ),
+            (
+                format_entitlement::Entity.into_table_ref(),
+                format_entitlement::Column::CreatedAt.into_iden(),
+                FORMAT_ENTITLEMENT_CREATED_BY_FKEY,
+            ),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table.clone())
+                        .add_column_if_not_exists(
+                            ColumnDef::new(Audit::CreatedBy)
+                                .uuid()
+                                .comment("Foreign key (user who created this item)"),
+                        )
+                        .add_column_if_not_exists(
+                            ColumnDef::new(Audit::UpdatedAt)
+                                .timestamp_with_time_zone()
+                                .not_null()
+                                .default(Expr::current_timestamp()),
+                        )
+                        .add_foreign_key(
+                            TableForeignKey::new()
+                                .name(fkey)
+                                .from_tbl(table.clone())
+                                .from_col(Audit::CreatedBy)
+                                .to_tbl(user::Entity)
+                                .to_col(user::Column::Id)
+                                .on_delete(ForeignKeyAction::SetNull)
+                                .on_update(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(table)
+                        .value(Audit::UpdatedAt, Expr::col(created_at))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, fkey) in [
+            (format::Entity.into_table_ref(), FORMAT_CREATED_BY_FKEY),
+            (
+                format_entitlement::Entity.into_table_ref(),
+                FORMAT_ENTITLEMENT_CREATED_BY_FKEY,
+            ),
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table)
+                        .drop_foreign_key(Alias::new(fkey))
+                        .drop_column(Audit::CreatedBy)
+                        .drop_column(Audit::UpdatedAt)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+/// Gives records a timestamp of their own. Existing records inherit the
+/// creation date of their upload session.
+use entity::{record, upload_session};
+use sea_orm_migration::prelude::*;
+
+const RECORD_CREATED_AT_INDEX: &str = "record_created_at";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(record::Entity)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(record::Column::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(record::Entity)
+                    .value(
+                        record::Column::CreatedAt,
+                        SimpleExpr::SubQuery(
+                            None,
+                            Box::new(
+                                Query::select()
+                                    .column(upload_session::Column::CreatedAt)
+                                    .from(upload_session::Entity)
+                                    .and_where(
+                                        Expr::col((
+                                            upload_session::Entity,
+                                            upload_session::Column::Id,
+                                        ))
+                                        .equals((record::Entity, record::Column::UploadSessionId)),
+                                    )
+                                    .to_owned()
+                                    .into_sub_query_statement(),
+                            ),
+                        ),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(RECORD_CREATED_AT_INDEX)
+                    .table(record::Entity)
+                    .col(record::Column::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(RECORD_CREATED_AT_INDEX)
+                    .table(record::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(record::Entity)
+                    .drop_column(record::Column::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,38 @@
+/// Adds the `archived` flag to the Format table (formats hidden from
+/// non-superusers, but whose data is kept).
+use sea_orm_migration::prelude::*;
+
+use crate::m20230220_192731_format::Format;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Format::Archived)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .drop_column(Format::Archived)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,50 @@
+/// Makes usernames unique regardless of their case ("Alice" and "alice" are
+/// the same user). Usernames are still stored as entered.
+///
+/// This migration fails (without changing anything) if there are usernames
+/// that only differ in case; rename or delete those users and run it again.
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+const INDEX_NAME: &str = "user_username_lower_idx";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+        let duplicates = db
+            .query_all(Statement::from_string(
+                backend,
+                r#"SELECT string_agg("username", ', ' ORDER BY "username") AS "usernames"
+                FROM "user" GROUP BY lower("username") HAVING count(*) > 1"#,
+            ))
+            .await?
+            .iter()
+            .map(|row| row.try_get::<String>("", "usernames"))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !duplicates.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "these usernames only differ in case, rename or delete them first: {}",
+                duplicates.join("; ")
+            )));
+        }
+        // sea-query can't build indexes on expressions.
+        db.execute_unprepared(&format!(
+            r#"CREATE UNIQUE INDEX IF NOT EXISTS "{INDEX_NAME}" ON "user" (lower("username"))"#
+        ))
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name(INDEX_NAME).to_owned())
+            .await
+    }
+}
@@ -0,0 +1,50 @@
+/// Adds an optional email address to the User table. Like usernames, emails
+/// are unique regardless of their case.
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+const INDEX_NAME: &str = "user_email_lower_idx";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column_if_not_exists(ColumnDef::new(User::Email).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        // sea-query can't build indexes on expressions.
+        manager
+            .get_connection()
+            .execute_unprepared(&format!(
+                r#"CREATE UNIQUE INDEX IF NOT EXISTS "{INDEX_NAME}" ON "user" (lower("email"))"#
+            ))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(Index::drop().name(INDEX_NAME).to_owned())
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::Email)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum User {
+    Table,
+    Email,
+}
@@ -0,0 +1,58 @@
+/// Adds the columns used for TOTP two-factor authentication to the User
+/// table. Existing users don't have 2FA enabled.
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column_if_not_exists(ColumnDef::new(User::TotpSecret).string().null())
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::TotpEnabled)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::TotpRecoveryCodes)
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'[]'::jsonb")),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::TotpLastStep).big_integer().null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::TotpSecret)
+                    .drop_column(User::TotpEnabled)
+                    .drop_column(User::TotpRecoveryCodes)
+                    .drop_column(User::TotpLastStep)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum User {
+    Table,
+    TotpSecret,
+    TotpEnabled,
+    TotpRecoveryCodes,
+    TotpLastStep,
+}
@@ -0,0 +1,61 @@
+/// Adds the content hash of uploads to the UploadSession table, so an upload
+/// of the same data can be detected without comparing its records.
+use sea_orm_migration::prelude::*;
+
+const CONTENT_HASH_INDEX: &str = "upload_session_format_id_content_hash";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(UploadSession::ContentHash).string().null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // duplicates are looked up per format.
+        manager
+            .create_index(
+                Index::create()
+                    .name(CONTENT_HASH_INDEX)
+                    .table(UploadSession::Table)
+                    .col(UploadSession::FormatId)
+                    .col(UploadSession::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(CONTENT_HASH_INDEX)
+                    .table(UploadSession::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .drop_column(UploadSession::ContentHash)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UploadSession {
+    Table,
+    FormatId,
+    ContentHash,
+}
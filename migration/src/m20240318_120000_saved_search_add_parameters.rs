@@ -0,0 +1,46 @@
+/// Adds query template parameters and the `shared` flag to the SavedSearch
+/// table.
+use sea_orm_migration::prelude::*;
+
+use crate::m20240108_120000_saved_search::SavedSearch;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SavedSearch::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(SavedSearch::Parameters)
+                            .comment("Parameter name -> expected column kind")
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'{}'::jsonb")),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(SavedSearch::Shared)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SavedSearch::Table)
+                    .drop_column(SavedSearch::Parameters)
+                    .drop_column(SavedSearch::Shared)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,88 @@
+/// Adds the SavedSearchShare table: saved searches shared with specific users.
+use entity::{saved_search, saved_search_share, user};
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SavedSearchShare::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SavedSearchShare::SavedSearchId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                saved_search_share::Entity,
+                                saved_search_share::Column::SavedSearchId,
+                            )
+                            .to(saved_search::Entity, saved_search::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(SavedSearchShare::UserId)
+                            .uuid()
+                            .not_null()
+                            .comment("Foreign key (user the search is shared with)"),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(
+                                saved_search_share::Entity,
+                                saved_search_share::Column::UserId,
+                            )
+                            .to(user::Entity, user::Column::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(SavedSearchShare::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        sea_query::Index::create()
+                            .col(SavedSearchShare::SavedSearchId)
+                            .col(SavedSearchShare::UserId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // searches shared with a user are looked up on every listing.
+        manager
+            .create_index(
+                Index::create()
+                    .name("saved_search_share_user_id")
+                    .table(SavedSearchShare::Table)
+                    .col(SavedSearchShare::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SavedSearchShare::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum SavedSearchShare {
+    Table,
+    SavedSearchId,
+    UserId,
+    CreatedAt,
+}
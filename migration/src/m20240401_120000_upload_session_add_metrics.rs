@@ -0,0 +1,45 @@
+/// Adds the size of the upload and the time it took to process it to the
+/// UploadSession table.
+use sea_orm_migration::prelude::*;
+
+use crate::m20230221_184209_session::UploadSession;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(UploadSession::PayloadBytes)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column_if_not_exists(
+                        ColumnDef::new(UploadSession::ProcessingMs)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .drop_column(UploadSession::PayloadBytes)
+                    .drop_column(UploadSession::ProcessingMs)
+                    .to_owned(),
+            )
+            .await
+    }
+}
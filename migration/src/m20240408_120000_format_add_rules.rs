@@ -0,0 +1,37 @@
+/// Adds cross-column validation rules to the Format table.
+use sea_orm_migration::prelude::*;
+
+use crate::m20230220_192731_format::Format;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Format::Rules)
+                            .json_binary()
+                            .not_null()
+                            .default(Expr::cust("'[]'::jsonb")),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .drop_column(Format::Rules)
+                    .to_owned(),
+            )
+            .await
+    }
+}
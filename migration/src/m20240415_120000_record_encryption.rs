@@ -0,0 +1,59 @@
+/// Adds the `encrypted` flag to the Format table and the column that holds
+/// the data of the records of encrypted formats.
+use entity::record;
+use sea_orm_migration::prelude::*;
+
+use crate::m20230220_192731_format::Format;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(Format::Encrypted)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(record::Entity)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(record::Column::EncryptedData)
+                            .binary()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(record::Entity)
+                    .drop_column(record::Column::EncryptedData)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Format::Table)
+                    .drop_column(Format::Encrypted)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,57 @@
+/// Adds the (optional) storage quota to the User table, plus an index on
+/// upload sessions so the usage of a user can be summed cheaply.
+use entity::upload_session;
+use sea_orm_migration::prelude::*;
+
+const UPLOAD_SESSION_USER_INDEX: &str = "upload_session_user_id";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column_if_not_exists(ColumnDef::new(User::MaxStorageBytes).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name(UPLOAD_SESSION_USER_INDEX)
+                    .table(upload_session::Entity)
+                    .col(upload_session::Column::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(UPLOAD_SESSION_USER_INDEX)
+                    .table(upload_session::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::MaxStorageBytes)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum User {
+    Table,
+    MaxStorageBytes,
+}
@@ -0,0 +1,72 @@
+/// Adds the API key an upload was authenticated with to the UploadSession
+/// table, so uploads made by different keys of the same user can be told
+/// apart. Password tokens (and deleted keys) leave it null.
+use entity::{api_key, upload_session};
+use sea_orm_migration::prelude::*;
+
+const API_KEY_FOREIGN_KEY: &str = "upload_session_api_key_id_fkey";
+const API_KEY_INDEX: &str = "upload_session_api_key_id";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .add_column_if_not_exists(ColumnDef::new(UploadSession::ApiKeyId).uuid().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name(API_KEY_FOREIGN_KEY)
+                            .from_tbl(upload_session::Entity)
+                            .from_col(UploadSession::ApiKeyId)
+                            .to_tbl(api_key::Entity)
+                            .to_col(api_key::Column::Id)
+                            // keep the session when the key is deleted.
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // also used by the foreign key when a key is deleted.
+        manager
+            .create_index(
+                Index::create()
+                    .name(API_KEY_INDEX)
+                    .table(UploadSession::Table)
+                    .col(UploadSession::ApiKeyId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(API_KEY_INDEX)
+                    .table(UploadSession::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadSession::Table)
+                    .drop_foreign_key(Alias::new(API_KEY_FOREIGN_KEY))
+                    .drop_column(UploadSession::ApiKeyId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UploadSession {
+    Table,
+    ApiKeyId,
+}
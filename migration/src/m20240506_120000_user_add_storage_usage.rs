@@ -0,0 +1,93 @@
+/// Keeps the storage used by each user (the payloads of its successful upload
+/// sessions) in the User table, so storage quotas don't have to sum every
+/// session of the user on each upload. A trigger keeps it up to date, since
+/// sessions are also deleted by cascades (i.e. along with their format).
+use sea_orm_migration::{prelude::*, sea_orm::ConnectionTrait};
+
+const FUNCTION_NAME: &str = "upload_session_track_storage";
+const TRIGGER_NAME: &str = "upload_session_track_storage";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column_if_not_exists(
+                        ColumnDef::new(User::UsedStorageBytes)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // sea-query can't build functions or triggers.
+        let db = manager.get_connection();
+        db.execute_unprepared(&format!(
+            r#"CREATE OR REPLACE FUNCTION "{FUNCTION_NAME}"() RETURNS trigger AS $$
+            BEGIN
+                IF TG_OP IN ('UPDATE', 'DELETE') AND OLD."outcome" = 'SUCCESS' THEN
+                    UPDATE "user" SET "used_storage_bytes" = "used_storage_bytes" - OLD."payload_bytes"
+                    WHERE "id" = OLD."user_id";
+                END IF;
+                IF TG_OP IN ('INSERT', 'UPDATE') AND NEW."outcome" = 'SUCCESS' THEN
+                    UPDATE "user" SET "used_storage_bytes" = "used_storage_bytes" + NEW."payload_bytes"
+                    WHERE "id" = NEW."user_id";
+                END IF;
+                RETURN NULL;
+            END;
+            $$ LANGUAGE plpgsql"#
+        ))
+        .await?;
+        db.execute_unprepared(&format!(
+            r#"DROP TRIGGER IF EXISTS "{TRIGGER_NAME}" ON "upload_session""#
+        ))
+        .await?;
+        db.execute_unprepared(&format!(
+            r#"CREATE TRIGGER "{TRIGGER_NAME}"
+            AFTER INSERT OR DELETE OR UPDATE OF "outcome", "payload_bytes", "user_id"
+            ON "upload_session" FOR EACH ROW EXECUTE FUNCTION "{FUNCTION_NAME}"()"#
+        ))
+        .await?;
+        // sessions uploaded before the trigger existed.
+        db.execute_unprepared(
+            r#"UPDATE "user" SET "used_storage_bytes" = "used"."bytes"
+            FROM (
+                SELECT "user_id", SUM("payload_bytes") AS "bytes" FROM "upload_session"
+                WHERE "outcome" = 'SUCCESS' GROUP BY "user_id"
+            ) AS "used"
+            WHERE "user"."id" = "used"."user_id""#,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared(&format!(
+            r#"DROP TRIGGER IF EXISTS "{TRIGGER_NAME}" ON "upload_session""#
+        ))
+        .await?;
+        db.execute_unprepared(&format!(r#"DROP FUNCTION IF EXISTS "{FUNCTION_NAME}""#))
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(User::UsedStorageBytes)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum User {
+    Table,
+    UsedStorageBytes,
+}
@@ -2,15 +2,19 @@ from repoclient.models.format import (
     Format,
     ColumnSchema,
     ColumnKind,
+    FormatRule,
+    RuleOperator,
     FormatUploadSession,
     FormatUploadSessionFilter,
+    ValidationReport,
 )
-from repoclient.models.user import User, UserApiKey
+from repoclient.models.user import User, UserApiKey, UserRole, TotpEnrollment
 from repoclient.models.query import (
     Query,
     QueryGroup,
     QueryGroupKind,
     Column,
+    Uploader,
 )
 from repoclient.models.upload_session import UploadSession, P
 from repoclient.models.entitlement import (
@@ -24,17 +28,23 @@ __all__ = [
     "Format",
     "User",
     "ColumnSchema",
+    "FormatRule",
+    "RuleOperator",
     "PaginatedResponse",
     "Query",
     "QueryGroup",
     "QueryGroupKind",
     "Column",
+    "Uploader",
     "FormatEntitlement",
     "EntitlementAccessLevel",
     "FormatUploadSession",
     "FormatUploadSessionFilter",
+    "ValidationReport",
     "RepositoryException",
     "PaginationStrategy",
     "UserApiKey",
+    "UserRole",
+    "TotpEnrollment",
     "P",
 ]
@@ -1,6 +1,6 @@
 from __future__ import annotations
 import logging
-from typing import Optional
+from typing import Any, Optional
 from httpx import Response, HTTPStatusError
 
 from pydantic import BaseModel, Field
@@ -8,10 +8,25 @@ from pydantic import BaseModel, Field
 logger = logging.getLogger(__name__)
 
 
+class ArgumentError(BaseModel):
+    group: int
+    argument: int
+    column: str
+    reason: str
+
+
 class RepositoryError(BaseModel):
-    status_code: int = Field(None, alias="statusCode")
-    kind: str
+    code: str
+    status_code: Optional[int] = Field(None, alias="statusCode")
+    kind: Optional[str] = None
     detail: str
+    # Every invalid search argument, if there's more than one.
+    errors: Optional[list[ArgumentError]] = None
+    # Machine-readable details, e.g. `limit`, `inUse` and `retryAfter` for
+    # rate limits.
+    meta: Optional[dict[str, Any]] = None
+    # The failed upload session, for uploads rejected by POST /record.
+    upload_session: Optional[dict[str, Any]] = Field(None, alias="uploadSession")
 
     @staticmethod
     def _try_extract_request_id(response: Response) -> Optional[str]:
@@ -57,7 +72,7 @@ class RepositoryError(BaseModel):
 
 class BaseRepositoryException(Exception):
     def __init__(self, error: RepositoryError, request_id: str):
-        message = f"[{error.status_code}] [{request_id}] {error.kind}: {error.detail}"
+        message = f"[{error.code}] [{request_id}] {error.kind}: {error.detail}"
         logger.error("error: %s", message)
         super().__init__(message)
         self.error = error
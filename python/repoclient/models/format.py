@@ -42,6 +42,7 @@ significantly slow down your query. Please specify a format_id."""
 class Record(RequestModel):
     id: int
     upload_session_id: int
+    created_at: datetime
     data: dict[str, int | float | str]
 
 
@@ -85,6 +86,44 @@ class ColumnSchema(RequestModel):
         raise RuntimeError("Unknown kind")
 
 
+class RuleOperator(str, Enum):
+    LT = "lt"
+    LTE = "lte"
+    EQ = "eq"
+    GTE = "gte"
+    GT = "gt"
+
+
+class FormatRule(RequestModel):
+    """A comparison every record of a format must pass: `left_column operator
+    right_column`, or `left_column operator constant`.
+
+    Both sides must have the same kind: numbers, strings (compared
+    lexicographically) or datetimes (ISO 8601 strings for constants).
+    """
+
+    left_column: str = Field(alias="leftColumn")
+    operator: RuleOperator
+    right_column: Optional[str] = Field(default=None, alias="rightColumn")
+    constant: Optional[int | float | str] = None
+
+
+class RecordValidationError(RequestModel):
+    record: int
+    code: str
+    detail: str
+
+
+class ValidationReport(RequestModel):
+    """Result of `Format.validate_data`. `errors` only holds the first 100
+    invalid records, `invalid_count` counts all of them."""
+
+    ok: bool
+    record_count: int = Field(alias="recordCount")
+    invalid_count: int = Field(alias="invalidCount")
+    errors: list[RecordValidationError]
+
+
 class FormatUploadSessionFilter(str, enum.Enum):
     """Upload Session filtering utilities.
 
@@ -108,10 +147,18 @@ class FormatUploadSessionFilter(str, enum.Enum):
     FORMAT_ID = "formatId"
     # Filter upload sessions made by this user.
     USER_ID = "userId"
-    # Upload sessions with successful outcomes.
+    # Upload sessions by outcome ("Success", "Error" or "InProgress").
     OUTCOME = "outcome"
     # Upload sessions created at this time.
     CREATED_AT = "createdAt"
+    # Upload sessions with this content hash (SHA-256 of their records).
+    CONTENT_HASH = "contentHash"
+    # Size of the request body of the upload, in bytes.
+    PAYLOAD_BYTES = "payloadBytes"
+    # Time spent validating and inserting the records, in milliseconds.
+    PROCESSING_MS = "processingMs"
+    # Upload sessions made with this API key.
+    API_KEY_ID = "apiKeyId"
 
 
 class FormatUploadSession(QueryParamBase):
@@ -136,6 +183,18 @@ class FormatUploadSession(QueryParamBase):
         FormatUploadSessionFilter.CREATED_AT: ComparisonValidator(
             datetime, ComparisonMethod.supports_all()
         ),
+        FormatUploadSessionFilter.CONTENT_HASH: ComparisonValidator(
+            str, [ComparisonMethod.EQUAL]
+        ),
+        FormatUploadSessionFilter.PAYLOAD_BYTES: ComparisonValidator(
+            int, ComparisonMethod.supports_all()
+        ),
+        FormatUploadSessionFilter.PROCESSING_MS: ComparisonValidator(
+            int, ComparisonMethod.supports_all()
+        ),
+        FormatUploadSessionFilter.API_KEY_ID: ComparisonValidator(
+            str, [ComparisonMethod.EQUAL]
+        ),
     }
 
 
@@ -145,6 +204,10 @@ class Format(RequestModel):
     description: str
     created_at: Optional[datetime] = None
     schema_ref: list[ColumnSchema] = Field(alias="schema")
+    rules: list[FormatRule] = []
+    archived: bool = False
+    # Encrypt the data of this format's records at rest (it can't be searched)
+    encrypted: bool = False
     _checked: bool = PrivateAttr(False)
 
     @property
@@ -157,7 +220,10 @@ class Format(RequestModel):
 
     @staticmethod
     async def get_all(
-        client: AsyncClient, user: User, per_page: int = 1000
+        client: AsyncClient,
+        user: User,
+        per_page: int = 1000,
+        access: Optional[str] = None,
     ) -> Iterator[Format]:
         """Get all available formats.
         Note: superusers have complete visibility of all formats.
@@ -168,9 +234,15 @@ class Format(RequestModel):
         :param client:
         :param user:
         :param per_page:
+        :param access: Only get the formats this user has this access
+            level to (an EntitlementAccessLevel, e.g. "write").
         """
+        upstream = FORMAT_URL
+        if access is not None:
+            access = access.value if isinstance(access, Enum) else access
+            upstream = f"{FORMAT_URL}?access={access}&"
         async for item in PaginatedResponse.get_all(
-            upstream=FORMAT_URL,
+            upstream=upstream,
             klass=list[Format],
             client=client,
             user=user,
@@ -181,7 +253,7 @@ class Format(RequestModel):
                 yield it
 
     async def create(self, client: AsyncClient, user: User) -> Format:
-        """Create the format. This call may only be used by superusers.
+        """Create the format. This call may only be used by superusers and format managers.
 
         :param client: HTTP Client
         :param user: Authenticated user
@@ -216,6 +288,28 @@ class Format(RequestModel):
         ret._checked = True
         return ret
 
+    @classmethod
+    async def get_many(
+        cls, client: AsyncClient, ids: list[int], user: User
+    ) -> tuple[list[Format], list[int]]:
+        """Get many formats by ID at once.
+
+        :param client: HTTP Client
+        :param ids: Format IDs
+        :param user: Authenticated user
+        :return: The readable formats (in the order of `ids`) and the IDs
+            that don't exist or aren't readable by this user
+        """
+        response = await client.post(
+            f"{FORMAT_URL}/batch", json={"ids": ids}, headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        json = response.json()
+        formats = [cls(**it) for it in json["formats"]]
+        for it in formats:
+            it._checked = True
+        return formats, json["missing"]
+
     async def delete(self, client: AsyncClient, user: User):
         """Delete this format. Only superusers may use this call.
 
@@ -229,6 +323,90 @@ class Format(RequestModel):
         logger.debug("successfully deleted format, id: %s", self.id)
         return True
 
+    async def set_archived(self, client: AsyncClient, user: User, archived: bool):
+        """Archive (or unarchive) this format. Archived formats are hidden from
+        normal users and can't be uploaded to. Only superusers may use this call.
+
+        :param client: HTTP Client
+        :param user: Authenticated user
+        :param archived: Whether to archive this format
+        :return: Format
+        """
+        assert self._checked, "Uninitialized format; call create or get first"
+        response = await client.patch(
+            f"{FORMAT_URL}/{self.id}", json={"archived": archived}, headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        self.archived = response.json()["archived"]
+        return self
+
+    async def get_column_stats(
+        self,
+        client: AsyncClient,
+        user: User,
+        column: str,
+        query: Query = Query.new_empty(),
+    ) -> dict:
+        """Get the statistics (count, missing values, distinct values, min/max
+        and mean/length stats) of `column` over the records matching `query`.
+
+        :param client: HTTP Client
+        :param user: User
+        :param column: Column to profile
+        :param query: Only take the records matching this query into account
+        :return: The statistics, as returned by the server
+        """
+        assert self._checked, "Uninitialized format; call create or get first"
+        json_query = query.model_dump(by_alias=True)
+        json_query["column"] = column
+        response = await client.post(
+            f"{RECORD_URL}/column-stats", json=json_query, headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        return response.json()
+
+    async def get_activity(
+        self,
+        client: AsyncClient,
+        user: User,
+        start: Optional[datetime] = None,
+        end: Optional[datetime] = None,
+    ) -> list[dict]:
+        """Get the number of records uploaded to this format per (UTC) day.
+
+        :param client: HTTP Client
+        :param user: User with read access on this format
+        :param start: Start of the range (the server defaults to
+            `MAX_ACTIVITY_RANGE_DAYS` days before `end`)
+        :param end: End of the range, exclusive (the server defaults to now)
+        :return: A `{date, uploaded, pruned}` dict per day, oldest first
+        """
+        assert self._checked, "Uninitialized format; call create or get first"
+        params = {}
+        if start is not None:
+            params["from"] = start.isoformat()
+        if end is not None:
+            params["to"] = end.isoformat()
+        response = await client.get(
+            f"{FORMAT_URL}/{self.id}/activity", params=params, headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        return response.json()
+
+    async def get_prune_preview(self, client: AsyncClient, user: User) -> dict:
+        """Get what the next prune would delete from this format. Superusers only.
+
+        :param client: HTTP Client
+        :param user: Superuser
+        :return: The `cutoff` and the `uploadSessions` and `records` past it
+        """
+        assert self._checked, "Uninitialized format; call create or get first"
+        response = await client.get(
+            f"{FORMAT_URL}/{self.id}/prune-preview", headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        return response.json()
+
     async def get_count(
         self, client: AsyncClient, user: User, query: Query = Query.new_empty()
     ) -> Iterator[Record]:
@@ -446,6 +624,9 @@ class Format(RequestModel):
         query: Query,
         output: IO[bytes],
         chunk_size: int = 1024 * (1024 * 10),
+        columns_from_query: bool = False,
+        columns: Optional[list[str]] = None,
+        manifest: bool = False,
     ):
         """Get all data from the repository, and save it to a IO-like file.
 
@@ -464,6 +645,9 @@ class Format(RequestModel):
         :param query: Filers to use for this query
         :param output: Bytes-like writable object
         :param chunk_size: Buffer size. Default: 10 MiB
+        :param columns_from_query: Only export the columns used in `query`
+        :param columns: Only export these columns, in this order
+        :param manifest: Append a `#manifest` trailer line to the export
         """
         assert self._checked, "Uninitialized format; call create or get first"
         if query.format_id is None:
@@ -485,8 +669,17 @@ class Format(RequestModel):
         read_bytes = 0
         logger.debug("json query:  %s", pformat(json_query))
 
+        params = {"columnsFromQuery": "true"} if columns_from_query else {}
+        if columns is not None:
+            params["columns"] = ",".join(columns)
+        if manifest:
+            params["manifest"] = "true"
         async with client.stream(
-            "POST", f"{RECORD_URL}/filter-stream", json=json_query, headers=user.bearer
+            "POST",
+            f"{RECORD_URL}/filter-stream",
+            json=json_query,
+            params=params,
+            headers=user.bearer,
         ) as response:
             if response.status_code != 200:
                 req_id = response.headers.get("request-id", "N/A")
@@ -548,7 +741,13 @@ class Format(RequestModel):
                 yield it
 
     async def upload_data(
-        self, client: AsyncClient, user: User, data: list[dict]
+        self,
+        client: AsyncClient,
+        user: User,
+        data: list[dict],
+        reject_duplicate: bool = False,
+        coerce: bool = False,
+        run_async: bool = False,
     ) -> UploadSession:
         """Upload data to this format.
 
@@ -561,6 +760,12 @@ class Format(RequestModel):
         :param client: HTTP Client
         :param user: Authenticated user with Read/ReadWrite access on this format
         :param data: Raw dict data
+        :param reject_duplicate: Fail with a `REPO-1006` error if the same data was
+            already uploaded to this format
+        :param coerce: Let the server convert values into the kind of their column,
+            e.g. `"42.5"` into `42.5` for numeric columns
+        :param run_async: Return as soon as the server queued the upload, with the
+            session in progress. Use `UploadSession.get_by_id` to wait for its outcome
         :return: Upload session
         """
         assert self._checked, "Uninitialized format; call create or get first"
@@ -581,6 +786,42 @@ class Format(RequestModel):
                 payload_size,
                 MAX_SUGGESTED_PAYLOAD_SIZE,
             )
-        response = await client.post(RECORD_URL, json=payload, headers=user.bearer)
+        params = {}
+        if reject_duplicate:
+            params["rejectDuplicate"] = "true"
+        if coerce:
+            params["coerce"] = "true"
+        if run_async:
+            params["async"] = "true"
+        response = await client.post(
+            RECORD_URL, json=payload, params=params, headers=user.bearer
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        return UploadSession.model_validate(response.json()["uploadSession"])
+
+    async def validate_data(
+        self,
+        client: AsyncClient,
+        user: User,
+        data: list[dict],
+        coerce: bool = False,
+    ) -> ValidationReport:
+        """Check `data` the way `upload_data` would, without uploading it.
+
+        Invalid records don't raise an exception, they are listed in the report.
+
+        :param client: HTTP Client
+        :param user: Authenticated user with at least Read access on this format
+        :param data: Raw dict data
+        :param coerce: Same as in `upload_data`
+        :return: Validation report
+        """
+        assert self._checked, "Uninitialized format; call create or get first"
+        assert isinstance(data, list), "`data` must be an array of dicts!"
+        payload = {"formatId": int(self.id), "data": data}
+        params = {"coerce": "true"} if coerce else {}
+        response = await client.post(
+            f"{RECORD_URL}/validate", json=payload, params=params, headers=user.bearer
+        )
         RepositoryError.verify_raise_conditionally(response)
-        return UploadSession.model_validate(response.json())
+        return ValidationReport.model_validate(response.json())
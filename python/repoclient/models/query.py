@@ -2,7 +2,7 @@ import datetime
 from enum import Enum
 from pydantic import BaseModel, Field
 from typing import Optional, Any, Annotated
-from datetime import datetime
+from datetime import datetime, timezone
 from repoclient.models.base_model import ClientBaseModel
 
 from pydantic import (
@@ -17,6 +17,10 @@ class Column(BaseModel):
     column: str
     operator: Optional[str] = Field(None, alias="comparisonOperator")
     other: Optional[int | float | str | list] = Field(None, alias="compareAgainst")
+    # Match `%` and `_` literally (like/iLike only).
+    escape_wildcards: bool = Field(False, alias="escapeWildcards")
+    # Search the column as a "number" or a "string" (superusers only).
+    treat_as: Optional[str] = Field(None, alias="treatAs")
 
     def _set(self, other: Any, operator: str):
         self.other = other
@@ -33,6 +37,14 @@ class Column(BaseModel):
     def _assert_arg_is_str(arg: Any):
         assert isinstance(arg, str), f"{arg} is not a string!"
 
+    def treated_as(self, kind: str):
+        """Search this column as a "number" or a "string", whatever its kind in
+        the searched formats (superusers only). Records of formats where it's
+        not a number never match "number"; "string" compares them as text."""
+        assert kind in ("number", "string"), f"{kind} is not number or string!"
+        self.treat_as = kind
+        return self
+
     def matches_regex(self, other: str):
         self._assert_arg_is_str(other)
         return self._set(other, "regex")
@@ -41,14 +53,28 @@ class Column(BaseModel):
         self._assert_arg_is_str(other)
         return self._set(other, "regexCaseInsensitive")
 
-    def is_like_case_insensitive(self, other: str):
+    def is_like_case_insensitive(self, other: str, escape_wildcards: bool = False):
         self._assert_arg_is_str(other)
+        self.escape_wildcards = escape_wildcards
         return self._set(other, "iLike")
 
-    def is_like(self, other: str):
+    def is_like(self, other: str, escape_wildcards: bool = False):
         self._assert_arg_is_str(other)
+        self.escape_wildcards = escape_wildcards
         return self._set(other, "like")
 
+    def starts_with(self, other: str):
+        self._assert_arg_is_str(other)
+        return self._set(other, "startsWith")
+
+    def ends_with(self, other: str):
+        self._assert_arg_is_str(other)
+        return self._set(other, "endsWith")
+
+    def equals_case_insensitive(self, other: str):
+        self._assert_arg_is_str(other)
+        return self._set(other, "eqCaseInsensitive")
+
     def is_in(self, other: list[int | float | str]):
         return self._set(other, "in")
 
@@ -136,12 +162,40 @@ UploadSessionSerialized = Annotated[
 ]
 
 
+class Uploader(ClientBaseModel):
+    """Only match records uploaded by users matching all of these conditions."""
+
+    username: Optional[str] = None
+    # Case-insensitive pattern, e.g. "ali%"
+    username_ilike: Optional[str] = Field(None, alias="usernameIlike")
+    user_id: Optional[str] = Field(None, alias="userId")
+
+
+def _utc_isoformat(value: datetime) -> str:
+    assert value.tzinfo is not None, f"{value} needs a timezone"
+    return value.astimezone(timezone.utc).isoformat()
+
+
+# The server only takes UTC timestamps, so convert them before sending.
+UtcTimestamp = Annotated[
+    datetime, PlainSerializer(_utc_isoformat, return_type=str, when_used="unless-none")
+]
+
+
 class Query(ClientBaseModel):
     format_id: Optional[list[int]] = Field(None, alias="formats")
     upload_session: Optional[UploadSessionSerialized] = Field(
         None, alias="uploadSession"
     )
+    uploader: Optional[Uploader] = None
+    # Only records uploaded at or after (before, exclusive) this moment.
+    ingested_after: Optional[UtcTimestamp] = Field(None, alias="ingestedAfter")
+    ingested_before: Optional[UtcTimestamp] = Field(None, alias="ingestedBefore")
     query: list[QueryGroup] = []
+    # Also search archived formats (superusers only).
+    include_archived: bool = Field(False, alias="includeArchived")
+    # Fail if any of `format_id` doesn't exist or isn't readable.
+    strict_formats: bool = Field(False, alias="strictFormats")
 
     @classmethod
     def new_empty(cls) -> "Query":
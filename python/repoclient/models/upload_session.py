@@ -215,6 +215,12 @@ class QueryParamBase:
         return buff[:-1]
 
 
+class UploadSessionDeleteResult(BaseModel):
+    session_id: int = Field(alias="sessionId")
+    format_id: int = Field(alias="formatId")
+    records_deleted: int = Field(alias="recordsDeleted")
+
+
 class UploadSession(BaseModel):
     id: int
     created_at: datetime = Field(alias="createdAt")
@@ -223,6 +229,10 @@ class UploadSession(BaseModel):
     user_id: UUID4 = Field(alias="userId")
     outcome: str
     detail: str
+    content_hash: Optional[str] = Field(None, alias="contentHash")
+    payload_bytes: int = Field(0, alias="payloadBytes")
+    processing_ms: int = Field(0, alias="processingMs")
+    api_key_id: Optional[UUID4] = Field(None, alias="apiKeyId")
 
     @staticmethod
     async def get_all(
@@ -249,23 +259,77 @@ class UploadSession(BaseModel):
                 yield it
 
     @staticmethod
-    async def delete_by_id(client: AsyncClient, user: User, upload_id: int):
+    async def get_by_id(
+        client: AsyncClient,
+        user: User,
+        upload_id: int,
+        wait_for_outcome: bool = False,
+        timeout: Optional[int] = None,
+    ) -> Tuple[UploadSession, bool]:
+        """Get a single upload session.
+
+        :param client: HTTP Client
+        :param user: Authenticated user
+        :param upload_id: Upload session ID
+        :param wait_for_outcome: If the session is in progress, wait until the
+            upload is done
+        :param timeout: Seconds to wait for (default and maximum: the server's
+            MAX_OUTCOME_WAIT_SECONDS)
+        :return: The session and whether the wait timed out before the
+            upload was done
+        """
+        params = {}
+        if wait_for_outcome:
+            params["waitForOutcome"] = "true"
+        if timeout is not None:
+            params["timeout"] = timeout
+        response = await client.get(
+            f"/upload_session/{upload_id}",
+            params=params,
+            headers=user.bearer,
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        timed_out = response.headers.get("repository-wait-timed-out") == "true"
+        return UploadSession.model_validate(response.json()), timed_out
+
+    @staticmethod
+    async def delete_by_id(
+        client: AsyncClient, user: User, upload_id: int
+    ) -> UploadSessionDeleteResult:
         """Delete this upload session.
 
         :param client: HTTP Client
         :param user: Authenticated user
         :param upload_id: Upload session ID to delete.
-        :return:
+        :return: What was deleted
         """
         upstream = f"/upload_session/{upload_id}"
         response = await client.delete(upstream, headers=user.bearer)
         RepositoryError.verify_raise_conditionally(response)
+        return UploadSessionDeleteResult.model_validate(response.json())
 
-    async def delete(self, client: AsyncClient, user: User):
+    async def delete(
+        self, client: AsyncClient, user: User
+    ) -> UploadSessionDeleteResult:
         """Delete this upload session.
 
         :param client: HTTP Client
         :param user: Authenticated user
-        :return:
+        :return: What was deleted
+        """
+        return await UploadSession.delete_by_id(client, user, self.id)
+
+    async def export(
+        self, client: AsyncClient, user: User, output_format: str = "csv"
+    ) -> bytes:
+        """Download all the records of this upload session.
+
+        :param client: HTTP Client
+        :param user: Authenticated user
+        :param output_format: Either "csv" or "ndjson"
+        :return: The raw file contents
         """
-        await UploadSession.delete_by_id(client, user, self.id)
+        upstream = f"/upload_session/{self.id}/export?format={output_format}"
+        response = await client.get(upstream, headers=user.bearer)
+        RepositoryError.verify_raise_conditionally(response)
+        return response.content
@@ -1,5 +1,6 @@
 from __future__ import annotations
 
+from enum import Enum
 from typing import Optional, Iterator
 from datetime import datetime
 
@@ -19,6 +20,8 @@ class UserApiKey(RequestModel):
     created_at: datetime = Field(..., alias="createdAt")
     last_rotated_at: datetime = Field(..., alias="lastRotatedAt")
     active: bool
+    # Only set when listing keys, see MAX_KEY_AGE_DAYS.
+    stale: bool = False
     _token: str = PrivateAttr(None)
     _parent_user: User = PrivateAttr(None)
 
@@ -155,6 +158,22 @@ class UserApiKey(RequestModel):
         return ret
 
 
+class UserRole(str, Enum):
+    """What a user can manage. Each role includes the previous ones."""
+
+    USER = "User"
+    AUDITOR = "Auditor"
+    FORMAT_MANAGER = "FormatManager"
+    ADMIN = "Admin"
+
+
+class TotpEnrollment(RequestModel):
+    secret: str
+    provisioning_uri: str = Field(..., alias="provisioningUri")
+    # One-time codes that can be used instead of a TOTP code.
+    recovery_codes: list[str] = Field(..., alias="recoveryCodes")
+
+
 class User(RequestModel):
     username: str
     password: Optional[str] = None
@@ -162,6 +181,11 @@ class User(RequestModel):
     created_at: Optional[datetime] = Field(None, alias="createdAt")
     is_superuser: Optional[bool] = Field(False, alias="isSuperuser")
     active: Optional[bool] = None
+    email: Optional[str] = None
+    role: Optional[UserRole] = None
+    totp_enabled: Optional[bool] = Field(None, alias="totpEnabled")
+    # Max number of bytes this user may upload (no limit if unset)
+    max_storage_bytes: Optional[int] = Field(None, alias="maxStorageBytes")
     token: Optional[str] = None
     _checked: bool = PrivateAttr(False)
 
@@ -169,6 +193,11 @@ class User(RequestModel):
     def is_valid(self):
         return self._checked
 
+    @property
+    def is_admin(self) -> bool:
+        """Whether this user can manage other users."""
+        return bool(self.is_superuser) or self.role is UserRole.ADMIN
+
     def _decode_user_from_jwt(s: str) -> User:
         # Decode base64
         decoded = base64.urlsafe_b64decode(s + "=" * (4 - len(s) % 4))
@@ -211,14 +240,27 @@ class User(RequestModel):
         this.token = key
         return this
 
-    async def login(self, client: AsyncClient) -> User:
+    async def login(
+        self,
+        client: AsyncClient,
+        totp_code: Optional[str] = None,
+        scopes: Optional[list[str]] = None,
+    ) -> User:
         """Authenticate with the user's credentials.
 
         :param client: HTTP Client
+        :param totp_code: TOTP (or recovery) code, if the user has 2FA enabled
+        :param scopes: Limit the token to these scopes, e.g. `["read"]` (default:
+            the token can do anything the user can)
         :return: User
         """
         assert self.password is not None, "password isn't set!"
-        response = await client.post("/login", json=self.model_dump())
+        json = self.model_dump()
+        if totp_code is not None:
+            json["totpCode"] = totp_code
+        if scopes is not None:
+            json["scopes"] = scopes
+        response = await client.post("/login", json=json)
         RepositoryError.verify_raise_conditionally(response)
         json = response.json()
         ret: User = User.model_validate(json["user"])
@@ -253,7 +295,7 @@ class User(RequestModel):
         )
 
     async def create_user(self, client: AsyncClient, user: User) -> User:
-        assert self.is_superuser, "only superusers may use this resource"
+        assert self.is_admin, "only admins may use this resource"
         response = await client.post(
             "/user",
             headers=self.bearer,
@@ -266,6 +308,76 @@ class User(RequestModel):
         ret._checked = True
         return ret
 
+    async def create_users_bulk(
+        self, client: AsyncClient, users: list[User], all_or_nothing: bool = False
+    ) -> dict:
+        """Create several users at once. Superusers only.
+
+        :param client: HTTP Client
+        :param users: Users to create
+        :param all_or_nothing: Create none of the users if any of them fails
+        :return: The created users and the ones that failed, with the reason
+        """
+        response = await client.post(
+            "/user/bulk",
+            params={"allOrNothing": "true"} if all_or_nothing else {},
+            headers=self.bearer,
+            json=[user.model_dump(by_alias=True, exclude_none=True) for user in users],
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        return response.json()
+
+    async def set_email(self, client: AsyncClient, email: Optional[str]) -> User:
+        """Change (or remove, if `email` is None) this user's email address.
+
+        :param client: HTTP Client
+        :param email: New email address
+        :return: The updated user
+        """
+        response = await client.patch(
+            f"/user/{self.id}", headers=self.bearer, json={"email": email}
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        self.email = response.json()["email"]
+        return self
+
+    async def get_usage(self, client: AsyncClient, user: User) -> dict:
+        """Get the number of records and bytes this user uploaded, in total and
+        per format.
+
+        :param client: HTTP Client
+        :param user: This user, or an authenticated auditor (or admin)
+        :return: Usage report
+        """
+        response = await client.get(f"/user/{self.id}/usage", headers=user.bearer)
+        RepositoryError.verify_raise_conditionally(response)
+        return response.json()
+
+    async def enroll_totp(self, client: AsyncClient) -> TotpEnrollment:
+        """Start enrolling this user in TOTP two-factor authentication.
+        2FA isn't enabled until `confirm_totp()` is called.
+
+        :param client: HTTP Client
+        :return: The TOTP secret and recovery codes
+        """
+        response = await client.post("/user/self/2fa/enroll", headers=self.bearer)
+        RepositoryError.verify_raise_conditionally(response)
+        return TotpEnrollment.model_validate(response.json())
+
+    async def confirm_totp(self, client: AsyncClient, code: str) -> User:
+        """Enable 2FA for this user with the first TOTP code.
+
+        :param client: HTTP Client
+        :param code: Current TOTP code
+        :return: The updated user
+        """
+        response = await client.post(
+            "/user/self/2fa/confirm", headers=self.bearer, json={"code": code}
+        )
+        RepositoryError.verify_raise_conditionally(response)
+        self.totp_enabled = response.json()["totpEnabled"]
+        return self
+
     async def create_api_key(self, client: AsyncClient) -> UserApiKey:
         """Create an API key for this user.
 
@@ -279,21 +391,48 @@ class User(RequestModel):
         ), f"user not initialized: call create_user(), get() or login() first"
         return await UserApiKey.create_for_user(client, self, self)
 
-    async def delete_user(self, client: AsyncClient, user: User) -> User:
+    async def delete_user(
+        self, client: AsyncClient, user: User, force: bool = False
+    ) -> User:
         """
 
         :param client: HTTP Client
         :param user: Target user to delete
+        :param force: Also delete the upload sessions `user` still owns
         :return: None
         """
-        assert self.is_superuser, "only superusers may use this resource"
+        assert self.is_admin, "only admins may use this resource"
         assert user.id is not None, f"{user}: user is not initialized"
 
         response = await client.delete(
             f"/user/{user.id}",
+            params={"force": "true"} if force else {},
+            headers=self.bearer,
+        )
+        RepositoryError.verify_raise_conditionally(response)
+
+    async def transfer_user(
+        self, client: AsyncClient, user: User, to: User, saved_searches: bool = False
+    ) -> dict:
+        """Reassign the upload sessions (and saved searches) of `user` to `to`,
+        e.g. before deleting `user`. Superusers only.
+
+        :param client: HTTP Client
+        :param user: User whose resources are transferred
+        :param to: User that gets them
+        :param saved_searches: Also transfer the saved searches of `user`
+        :return: Number of transferred upload sessions and saved searches
+        """
+        assert user.id is not None, f"{user}: user is not initialized"
+        assert to.id is not None, f"{to}: user is not initialized"
+
+        response = await client.post(
+            f"/user/{user.id}/transfer",
+            json={"to": to.id, "savedSearches": saved_searches},
             headers=self.bearer,
         )
         RepositoryError.verify_raise_conditionally(response)
+        return response.json()
 
     async def get_all_keys(
         self, client: AsyncClient, user: User = None, per_page: int = 1000
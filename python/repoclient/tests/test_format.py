@@ -1,12 +1,24 @@
-from datetime import datetime
+import os
+from datetime import datetime, timedelta, timezone
 from typing import Tuple, Any, Callable
 
 import repoclient
 import pytest
 import operator
 
-from .util import get_random_string, api_client, admin_user
+from .util import get_random_string, api_client, admin_user, normal_user, sample_format
 from repoclient import ColumnSchema, FormatUploadSession, FormatUploadSessionFilter, P
+from repoclient import FormatRule, RuleOperator
+
+MIN_RETENTION_MINUTES = int(os.environ.get("MIN_RETENTION_MINUTES", 60))
+
+RULE_SCHEMA = [
+    ColumnSchema.datetime("start"),
+    ColumnSchema.datetime("end"),
+    ColumnSchema.numeric("net"),
+    ColumnSchema.numeric("gross"),
+    ColumnSchema.string("note"),
+]
 
 
 @pytest.mark.asyncio
@@ -47,6 +59,516 @@ async def test_create_format(api_client, admin_user):
     await fmt.delete(api_client, admin_user)
 
 
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "names,reason",
+    [
+        (["amount", "amount"], "duplicate column name"),
+        (["amount", "Amount"], "duplicate column name"),
+        (["amount", ""], "is empty"),
+        (["  "], "is empty"),
+        (["x" * 129], "longer than 128 characters"),
+        (["tab\tseparated"], "control characters"),
+        ([], "at least one column"),
+    ],
+)
+async def test_create_format_invalid_column_names(
+    api_client, admin_user, names: list[str], reason: str
+):
+    columns = [ColumnSchema.numeric(name) for name in names]
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await repoclient.Format(
+            name=get_random_string(10), description="invalid", schema=columns
+        ).create(api_client, admin_user)
+    assert exc.value.error.code == "REPO-1008"
+    assert reason in exc.value.error.detail
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "rule,reason",
+    [
+        ({"left_column": "nope", "constant": 1}, "doesn't exist"),
+        ({"left_column": "net", "right_column": "nope"}, "doesn't exist"),
+        (
+            {"left_column": "net", "right_column": "start"},
+            "can't compare a Number column to a Datetime column",
+        ),
+        ({"left_column": "net", "constant": "1"}, "isn't a valid Number"),
+        ({"left_column": "start", "constant": "yesterday"}, "isn't a valid Datetime"),
+        ({"left_column": "net"}, "set either"),
+        ({"left_column": "net", "right_column": "gross", "constant": 1}, "set either"),
+    ],
+)
+async def test_create_format_invalid_rules(
+    api_client, admin_user, rule: dict, reason: str
+):
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await repoclient.Format(
+            name=get_random_string(10),
+            description="invalid rules",
+            schema=RULE_SCHEMA,
+            rules=[FormatRule(operator=RuleOperator.LT, **rule)],
+        ).create(api_client, admin_user)
+    assert exc.value.error.code == "REPO-1008"
+    assert "rule 0" in exc.value.error.detail
+    assert reason in exc.value.error.detail
+
+
+@pytest.mark.asyncio
+async def test_format_rules(api_client, admin_user):
+    fmt = await repoclient.Format(
+        name=get_random_string(10),
+        description="records with invariants",
+        schema=RULE_SCHEMA,
+        rules=[
+            FormatRule(
+                left_column="end", operator=RuleOperator.GTE, right_column="start"
+            ),
+            FormatRule(
+                left_column="gross", operator=RuleOperator.GTE, right_column="net"
+            ),
+            FormatRule(left_column="net", operator=RuleOperator.GT, constant=0),
+            FormatRule(
+                left_column="start",
+                operator=RuleOperator.GTE,
+                constant="2024-01-01T00:00:00Z",
+            ),
+        ],
+    ).create(api_client, admin_user)
+    fmt = await repoclient.Format.get(api_client, fmt.id, admin_user)
+    assert len(fmt.rules) == 4
+
+    def record(
+        start="2024-01-01T00:00:00Z", end="2024-01-02T00:00:00Z", net=1, gross=2
+    ):
+        return {"start": start, "end": end, "net": net, "gross": gross, "note": ""}
+
+    # the bounds are inclusive for gte
+    data = [record(), record(end="2024-01-01T00:00:00Z", gross=1)]
+    await fmt.upload_data(api_client, admin_user, data)
+
+    for broken, rule in [
+        (record(end="2023-12-31T23:59:59Z"), 0),
+        (record(net=3), 1),
+        (record(net=0, gross=0), 2),
+        (record(net=2.5), 1),
+        (record(start="2023-12-31T00:00:00Z", end="2023-12-31T00:00:00Z"), 3),
+    ]:
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await fmt.upload_data(api_client, admin_user, data + [broken])
+        assert exc.value.error.code == "REPO-1003"
+        assert f"record 2 breaks rule {rule}" in exc.value.error.detail
+
+    # nothing but the first upload was saved
+    query = repoclient.Query(query=[], format_id=[fmt.id])
+    assert await fmt.get_count(api_client, admin_user, query) == 2
+    await fmt.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_create_format_valid_column_names(api_client, admin_user):
+    names = ["amount", "amount_2", "x" * 128, "Ünïcode name"]
+    fmt = await repoclient.Format(
+        name=get_random_string(10),
+        description="valid",
+        schema=[ColumnSchema.numeric(name) for name in names],
+    ).create(api_client, admin_user)
+    fmt = await repoclient.Format.get(api_client, fmt.id, admin_user)
+    assert [column.name for column in fmt.schema_ref] == names
+    await fmt.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_archived_format_visibility(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+    data = [{"NumericColumn": 123, "StringColumn": "abcdeasf"}] * 10
+    await sample_format.upload_data(api_client, normal_user, data)
+    await sample_format.set_archived(api_client, admin_user, True)
+    assert sample_format.archived
+
+    # hidden from normal users
+    formats = [fmt.id async for fmt in repoclient.Format.get_all(api_client, normal_user)]
+    assert sample_format.id not in formats
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await repoclient.Format.get(api_client, sample_format.id, normal_user)
+    assert exc.value.error.code == "REPO-1004"
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    assert await sample_format.get_count(api_client, normal_user, query) == 0
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(
+            api_client, normal_user, query.model_copy(update={"include_archived": True})
+        )
+    assert exc.value.error.code == "REPO-2003"
+
+    # nobody can upload to it
+    for user in (normal_user, admin_user):
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await sample_format.upload_data(api_client, user, data)
+        assert exc.value.error.code == "REPO-1005"
+
+    # superusers only see its data if they ask for it
+    assert await sample_format.get_count(api_client, admin_user, query) == 0
+    query.include_archived = True
+    assert await sample_format.get_count(api_client, admin_user, query) == 10
+
+    await sample_format.set_archived(api_client, admin_user, False)
+    formats = [fmt.id async for fmt in repoclient.Format.get_all(api_client, normal_user)]
+    assert sample_format.id in formats
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_get_many_formats(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    hidden = await repoclient.Format(
+        name=get_random_string(10),
+        description="not shared",
+        schema=[ColumnSchema.numeric("NumericColumn")],
+    ).create(api_client, admin_user)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    ids = [hidden.id, sample_format.id, -1]
+
+    # the requested order is kept
+    formats, missing = await repoclient.Format.get_many(api_client, ids, admin_user)
+    assert [fmt.id for fmt in formats] == [hidden.id, sample_format.id]
+    assert missing == [-1]
+    # formats without an entitlement are reported like unknown ones
+    formats, missing = await repoclient.Format.get_many(api_client, ids, normal_user)
+    assert [fmt.id for fmt in formats] == [sample_format.id]
+    assert missing == [hidden.id, -1]
+
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await repoclient.Format.get_many(api_client, list(range(10_000)), admin_user)
+    assert exc.value.error.code == "REPO-3003"
+    await entitlement.delete(api_client, admin_user)
+    await hidden.delete(api_client, admin_user)
+
+
+@pytest.mark.parametrize(
+    "access,writable",
+    [
+        ([repoclient.EntitlementAccessLevel.READ], False),
+        (
+            [
+                repoclient.EntitlementAccessLevel.READ,
+                repoclient.EntitlementAccessLevel.WRITE,
+            ],
+            True,
+        ),
+    ],
+)
+@pytest.mark.asyncio
+async def test_list_writable_formats(
+    api_client,
+    admin_user,
+    normal_user,
+    sample_format: repoclient.Format,
+    access: list[repoclient.EntitlementAccessLevel],
+    writable: bool,
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id, format_id=sample_format.id, access=access
+    ).create(api_client, admin_user)
+
+    async def format_ids(user: repoclient.User, **kwargs) -> list[int]:
+        formats = repoclient.Format.get_all(api_client, user, **kwargs)
+        return [fmt.id async for fmt in formats]
+
+    write = repoclient.EntitlementAccessLevel.WRITE
+    assert await format_ids(normal_user) == [sample_format.id]
+    writable_ids = await format_ids(normal_user, access=write)
+    assert writable_ids == ([sample_format.id] if writable else [])
+    # superusers can write to every format
+    assert sample_format.id in await format_ids(admin_user, access=write)
+    assert await format_ids(admin_user, access=write) == await format_ids(admin_user)
+
+    response = await api_client.get(
+        "/format", params={"access": "everything"}, headers=normal_user.bearer
+    )
+    assert response.status_code == 400
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_list_formats_include_access(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+
+    async def access_by_format(user: repoclient.User, **params) -> dict:
+        response = await api_client.get("/format", params=params, headers=user.bearer)
+        assert response.status_code == 200
+        return {fmt["id"]: fmt.get("access") for fmt in response.json()}
+
+    # access levels are opt-in
+    assert await access_by_format(normal_user) == {sample_format.id: None}
+    assert await access_by_format(normal_user, includeAccess="true") == {
+        sample_format.id: ["read", "write"]
+    }
+    # superusers have every access level on every format
+    admin_access = await access_by_format(admin_user, includeAccess="true")
+    assert admin_access[sample_format.id] == [
+        "read",
+        "write",
+        "limitedDelete",
+        "delete",
+    ]
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_list_formats_etag(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    async def list_formats(**headers):
+        return await api_client.get(
+            "/format",
+            params={"perPage": 10},
+            headers={**normal_user.bearer, **headers},
+        )
+
+    # the effective pagination settings are echoed back
+    response = await api_client.get("/format", headers=normal_user.bearer)
+    assert response.headers["repository-page"] == "0"
+    assert int(response.headers["repository-per-page"]) >= 1
+    assert response.headers["repository-count-enabled"] in ("true", "false")
+    response = await list_formats()
+    assert response.headers["repository-per-page"] == "10"
+
+    etag = response.headers["etag"]
+    assert etag.startswith('W/"')
+    # hit: the page didn't change
+    response = await list_formats(**{"if-none-match": etag})
+    assert response.status_code == 304
+    assert response.content == b""
+    assert response.headers["etag"] == etag
+    assert response.headers["repository-item-count"] == "0"
+    # miss: unknown tags
+    response = await list_formats(**{"if-none-match": 'W/"nope", "other"'})
+    assert response.status_code == 200
+    assert response.headers["etag"] == etag
+
+    # miss: the page changed
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    response = await list_formats(**{"if-none-match": etag})
+    assert response.status_code == 200
+    assert [fmt["id"] for fmt in response.json()] == [sample_format.id]
+    assert response.headers["etag"] != etag
+    etag = response.headers["etag"]
+    response = await list_formats(**{"if-none-match": f'"other", {etag}'})
+    assert response.status_code == 304
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "minutes,reason",
+    [
+        (-1, "cannot be negative"),
+        (0, None),
+        (1, f"at least {MIN_RETENTION_MINUTES} minutes"),
+        (MIN_RETENTION_MINUTES - 1, f"at least {MIN_RETENTION_MINUTES} minutes"),
+        (MIN_RETENTION_MINUTES, None),
+    ],
+)
+async def test_format_retention_period(
+    api_client, admin_user, sample_format: repoclient.Format, minutes: int, reason
+):
+    body = {
+        "name": get_random_string(10),
+        "description": "retention",
+        "schema": [{"name": "a", "kind": "Number"}],
+        "retentionPeriodMinutes": minutes,
+    }
+    response = await api_client.post("/format", json=body, headers=admin_user.bearer)
+    update = await api_client.patch(
+        f"/format/{sample_format.id}",
+        json={"retentionPeriodMinutes": minutes},
+        headers=admin_user.bearer,
+    )
+    if reason is None:
+        assert response.status_code == 201
+        assert response.json()["retentionPeriodMinutes"] == minutes
+        assert update.status_code == 200
+        assert update.json()["retentionPeriodMinutes"] == minutes
+        await api_client.delete(
+            f"/format/{response.json()['id']}", headers=admin_user.bearer
+        )
+        return
+    for response in (response, update):
+        assert response.status_code == 400
+        assert response.json()["code"] == "REPO-1008"
+        assert reason in response.json()["detail"]
+
+
+@pytest.mark.asyncio
+async def test_format_prune_preview(api_client, admin_user, normal_user):
+    body = {
+        "name": get_random_string(10),
+        "description": "prune preview",
+        "schema": [{"name": "a", "kind": "Number"}],
+        "retentionPeriodMinutes": MIN_RETENTION_MINUTES,
+    }
+    response = await api_client.post("/format", json=body, headers=admin_user.bearer)
+    assert response.status_code == 201
+    format = await repoclient.Format.get(api_client, response.json()["id"], admin_user)
+    await format.upload_data(api_client, admin_user, [{"a": 1}, {"a": 2}])
+
+    before = datetime.now(timezone.utc)
+    preview = await format.get_prune_preview(api_client, admin_user)
+    after = datetime.now(timezone.utc)
+    assert preview["formatId"] == format.id
+    assert preview["retentionPeriodMinutes"] == MIN_RETENTION_MINUTES
+    cutoff = datetime.fromisoformat(preview["cutoff"])
+    retention = timedelta(minutes=MIN_RETENTION_MINUTES)
+    assert before - retention - timedelta(seconds=5) <= cutoff <= after - retention
+    # the upload is newer than the cutoff
+    assert preview["uploadSessions"] == 0
+    assert preview["records"] == 0
+
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await format.get_prune_preview(api_client, normal_user)
+    # the format isn't shared with this user, so it doesn't exist for them
+    assert exc.value.error.code == "REPO-1004"
+
+    # formats kept forever are never pruned
+    response = await api_client.patch(
+        f"/format/{format.id}",
+        json={"retentionPeriodMinutes": 0},
+        headers=admin_user.bearer,
+    )
+    assert response.status_code == 200
+    preview = await format.get_prune_preview(api_client, admin_user)
+    assert preview["cutoff"] is None
+    assert preview["uploadSessions"] == 0
+    await format.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_format_request_limits(api_client, admin_user):
+    for per_page in (0, 1_000_000):
+        response = await api_client.get(
+            "/format", params={"perPage": per_page}, headers=admin_user.bearer
+        )
+        assert response.status_code == 400
+        assert response.json()["code"] == "REPO-1009"
+
+    # bigger than MAX_JSON_PAYLOAD_SIZE
+    body = {"name": get_random_string(10), "description": "x" * 200_000, "schema": []}
+    response = await api_client.post("/format", json=body, headers=admin_user.bearer)
+    assert response.status_code == 413
+    assert response.json()["code"] == "REPO-3004"
+
+
+@pytest.mark.asyncio
+async def test_format_activity(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    rows = [{"NumericColumn": i, "StringColumn": "x"} for i in range(5)]
+    await sample_format.upload_data(api_client, admin_user, rows[:3])
+    await sample_format.upload_data(api_client, admin_user, rows[3:])
+    # failed uploads don't count
+    with pytest.raises(repoclient.RepositoryException):
+        await sample_format.upload_data(api_client, admin_user, [{"NumericColumn": 1}])
+
+    now = datetime.now(timezone.utc)
+    today = now.replace(hour=0, minute=0, second=0, microsecond=0)
+    start, end = today - timedelta(days=2), today + timedelta(days=1)
+    activity = await sample_format.get_activity(api_client, admin_user, start, end)
+    # one entry per day, including the days without uploads
+    assert [day["date"] for day in activity] == [
+        (start + timedelta(days=i)).date().isoformat() for i in range(3)
+    ]
+    assert [day["uploaded"] for day in activity] == [0, 0, 5]
+    assert all(day["pruned"] is None for day in activity)
+    # without a range, the last MAX_ACTIVITY_RANGE_DAYS days are returned
+    activity = await sample_format.get_activity(api_client, admin_user)
+    assert activity[-1]["date"] == today.date().isoformat()
+    assert activity[-1]["uploaded"] == 5
+
+    # only readers of the format can see its activity
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_activity(api_client, normal_user, start, end)
+    assert exc.value.error.code == "REPO-1004"
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    activity = await sample_format.get_activity(api_client, normal_user, start, end)
+    assert [day["uploaded"] for day in activity] == [0, 0, 5]
+
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_activity(api_client, normal_user, end, start)
+    assert exc.value.error.code == "REPO-1005"
+    # bigger than MAX_ACTIVITY_RANGE_DAYS
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_activity(
+            api_client, normal_user, end - timedelta(days=10_000), end
+        )
+    assert exc.value.error.code == "REPO-3003"
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.parametrize(
+    "order_by,valid",
+    [
+        ("id", True),
+        ("-id", True),
+        ("createdAt", True),
+        ("-createdAt", True),
+        ("crratedAt", False),
+        ("-crratedAt", False),
+        ("--id", False),
+        ("-", False),
+        ("description", False),
+    ],
+)
+@pytest.mark.asyncio
+async def test_list_formats_order_by(
+    api_client, admin_user, sample_format: repoclient.Format, order_by: str, valid: bool
+):
+    response = await api_client.get(
+        "/format", params={"orderBy": order_by}, headers=admin_user.bearer
+    )
+    if not valid:
+        # unknown columns are rejected instead of sorting by the default one
+        assert response.status_code == 400
+        assert response.json()["code"] == "REPO-1008"
+        assert "createdAt" in response.json()["detail"]
+        return
+    assert response.status_code == 200
+    ids = [fmt["id"] for fmt in response.json()]
+    assert len(ids) > 0
+    assert ids == sorted(ids, reverse=order_by.startswith("-"))
+
+
 @pytest.mark.parametrize(
     "compare",
     # (compare against, whether to expect an exception or not)
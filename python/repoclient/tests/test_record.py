@@ -1,9 +1,19 @@
+import asyncio
+import hashlib
 import operator
+import os
+import re
+from datetime import timedelta, timezone
+from io import BytesIO
+from pathlib import Path
+
+import orjson
 
 import repoclient
 import pytest
 
 from repoclient import P, FormatUploadSession, FormatUploadSessionFilter, QueryGroupKind
+from repoclient.exception import RepositoryError
 
 from .util import (
     get_random_string,
@@ -29,6 +39,20 @@ RECORD_PAYLOAD_SIZES: list[int] = [
     9_000,
     10_000,
 ]
+UPLOAD_SESSION_DETAIL_MAX_LENGTH = int(
+    os.environ.get("UPLOAD_SESSION_DETAIL_MAX_LENGTH", 1000)
+)
+MAX_FILTER_RESPONSE_BYTES = int(os.environ.get("MAX_FILTER_RESPONSE_BYTES", 268435456))
+INGESTION_QUEUE_DEPTH = int(os.environ.get("INGESTION_QUEUE_DEPTH", 16))
+INGESTION_WORKERS = int(os.environ.get("INGESTION_WORKERS", 2))
+DB_CSV_MAX_STREAM_WORKERS = int(os.environ.get("DB_CSV_MAX_STREAM_WORKERS", 8))
+DB_CSV_STREAM_WORKERS = int(os.environ.get("DB_CSV_STREAM_WORKERS", 1))
+DB_CSV_TRANSFORM_WORKERS = int(os.environ.get("DB_CSV_TRANSFORM_WORKERS", 2))
+RECORD_ENCRYPTION_KEY = os.environ.get("RECORD_ENCRYPTION_KEY", "")
+# Internals that must never show up in the detail of an upload session.
+LEAKY_DETAIL_PATTERN = re.compile(
+    r"sqlx|DbErr|RuntimeErr|\b(SELECT|INSERT|UPDATE|DELETE)\b", re.IGNORECASE
+)
 
 
 @pytest.mark.asyncio
@@ -45,6 +69,607 @@ async def test_upload_record_admin(
     assert count == 100, "wrong record count"
 
 
+@pytest.mark.asyncio
+async def test_upload_content_hash(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "hashed"} for i in range(10)]
+    first = await sample_format.upload_data(api_client, admin_user, data)
+    assert first.content_hash is not None
+    # the order of the keys doesn't matter, the order of the records does
+    reordered = [{"StringColumn": "hashed", "NumericColumn": i} for i in range(10)]
+    second = await sample_format.upload_data(api_client, admin_user, reordered)
+    assert second.content_hash == first.content_hash
+    third = await sample_format.upload_data(api_client, admin_user, data[::-1])
+    assert third.content_hash != first.content_hash
+
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.upload_data(
+            api_client, admin_user, data, reject_duplicate=True
+        )
+    assert exc.value.error.code == "REPO-1006"
+
+    upload_session = FormatUploadSession(
+        [P(FormatUploadSessionFilter.CONTENT_HASH) == first.content_hash]
+    )
+    query = repoclient.Query(
+        query=[], format_id=[sample_format.id], upload_session=upload_session
+    )
+    assert await sample_format.get_count(api_client, admin_user, query) == 20
+
+
+@pytest.mark.asyncio
+async def test_upload_coerce(api_client, admin_user):
+    fmt = await repoclient.Format(
+        name=get_random_string(10),
+        description="values of the wrong kind",
+        schema=[
+            repoclient.ColumnSchema.numeric("N"),
+            repoclient.ColumnSchema.string("S"),
+            repoclient.ColumnSchema.datetime("D"),
+        ],
+    ).create(api_client, admin_user)
+    when = "2024-01-02T03:04:05Z"
+    data = [
+        {"N": " 42.5 ", "S": 7, "D": f" {when}\n"},
+        {"N": "-3", "S": 2.5, "D": when},
+        {"N": "1e2", "S": "as is", "D": when},
+        {"N": 8, "S": "as is", "D": when},
+    ]
+    # rejected without coerce=true
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await fmt.upload_data(api_client, admin_user, data)
+    assert exc.value.error.code == "REPO-1003"
+
+    await fmt.upload_data(api_client, admin_user, data, coerce=True)
+    query = repoclient.Query(query=[], format_id=[fmt.id])
+    response = await api_client.post(
+        "/record/filter",
+        json=query.model_dump(by_alias=True),
+        headers=admin_user.bearer,
+    )
+    stored = [record["data"] for record in response.json()]
+    assert stored == [
+        {"N": 42.5, "S": "7", "D": when},
+        {"N": -3, "S": "2.5", "D": when},
+        {"N": 100.0, "S": "as is", "D": when},
+        {"N": 8, "S": "as is", "D": when},
+    ]
+    # the stored values have the right kind, so they can be searched
+    numbers = repoclient.Column(column="N").is_in([-3, 8])
+    query = repoclient.Query(
+        query=[repoclient.QueryGroup(kind=QueryGroupKind.ALL, args=[numbers])],
+        format_id=[fmt.id],
+    )
+    assert await fmt.get_count(api_client, admin_user, query) == 2
+
+    # the first value that can't be coerced is reported
+    for value in ("1,000", "NaN", "inf", ".5", "0x10", "", True, None):
+        bad = data[:2] + [{"N": value, "S": "x", "D": when}]
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await fmt.upload_data(api_client, admin_user, bad, coerce=True)
+        assert exc.value.error.code == "REPO-1007"
+        assert "(record 2, column 'N')" in exc.value.error.detail
+    await fmt.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_upload_nested_and_oversized_values(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    valid = {"NumericColumn": 1, "StringColumn": "valid"}
+    invalid = [
+        ({"NumericColumn": 1, "StringColumn": {"a": 1}}, "objects or arrays"),
+        ({"NumericColumn": [1, 2], "StringColumn": "x"}, "objects or arrays"),
+        ({"NumericColumn": 1, "StringColumn": "x" * 65537}, "longer than 65536"),
+    ]
+    for record, detail in invalid:
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await sample_format.upload_data(api_client, admin_user, [valid, record])
+        assert exc.value.error.code == "REPO-1003"
+        assert detail in exc.value.error.detail
+    # the longest allowed string is fine
+    record = {"NumericColumn": 1, "StringColumn": "\u00e9" * 65536}
+    await sample_format.upload_data(api_client, admin_user, [valid, record])
+
+    data = [valid] + [record for record, _ in invalid]
+    report = await sample_format.validate_data(api_client, admin_user, data)
+    assert [(e.record, e.code) for e in report.errors] == [
+        (1, "REPO-1003"),
+        (2, "REPO-1003"),
+        (3, "REPO-1003"),
+    ]
+
+
+@pytest.mark.asyncio
+async def test_validate_data(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    async def get_sessions() -> list[dict]:
+        response = await api_client.get(
+            "/upload_session",
+            headers=admin_user.bearer,
+            params={"perPage": 1000, "formatNameEq": sample_format.name},
+        )
+        assert response.status_code == 200
+        return response.json()
+
+    valid = [{"NumericColumn": i, "StringColumn": "valid"} for i in range(10)]
+    report = await sample_format.validate_data(api_client, admin_user, valid)
+    assert report.ok
+    assert report.record_count == 10
+    assert report.invalid_count == 0
+    assert report.errors == []
+
+    data = valid + [
+        {"NumericColumn": 1},
+        {"NumericColumn": "1.5", "StringColumn": "x"},
+    ]
+    report = await sample_format.validate_data(api_client, admin_user, data)
+    assert not report.ok
+    assert report.invalid_count == 2
+    assert [(e.record, e.code) for e in report.errors] == [
+        (10, "REPO-1003"),
+        (11, "REPO-1003"),
+    ]
+    # with coerce=true, only values that can't be coerced are reported
+    data.append({"NumericColumn": "abc", "StringColumn": "x"})
+    report = await sample_format.validate_data(
+        api_client, admin_user, data, coerce=True
+    )
+    assert [(e.record, e.code) for e in report.errors] == [
+        (10, "REPO-1003"),
+        (12, "REPO-1007"),
+    ]
+    assert "(record 12, column 'NumericColumn')" in report.errors[1].detail
+    # every invalid record is counted, but only 100 are listed
+    invalid = [{"NumericColumn": "x", "StringColumn": "x"}] * 150
+    report = await sample_format.validate_data(api_client, admin_user, invalid)
+    assert report.record_count == report.invalid_count == 150
+    assert [e.record for e in report.errors] == list(range(100))
+    # nothing was saved
+    assert await get_sessions() == []
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    assert await sample_format.get_count(api_client, admin_user, query) == 0
+
+    # read access is enough to validate, but not to upload
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.validate_data(api_client, normal_user, valid)
+    assert exc.value.error.code == "REPO-1004"
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    report = await sample_format.validate_data(api_client, normal_user, valid)
+    assert report.ok
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.upload_data(api_client, normal_user, valid)
+    assert exc.value.error.code == "REPO-2003"
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_upload_session_metrics(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "metrics"} for i in range(10)]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.payload_bytes > 0
+    assert upload.processing_ms >= 0
+
+    upload_session = FormatUploadSession(
+        [
+            P(FormatUploadSessionFilter.ID) == upload.id,
+            P(FormatUploadSessionFilter.PAYLOAD_BYTES) >= upload.payload_bytes,
+        ]
+    )
+    query = repoclient.Query(
+        query=[], format_id=[sample_format.id], upload_session=upload_session
+    )
+    assert await sample_format.get_count(api_client, admin_user, query) == 10
+
+    upload_session = FormatUploadSession(
+        [
+            P(FormatUploadSessionFilter.ID) == upload.id,
+            P(FormatUploadSessionFilter.PAYLOAD_BYTES) > upload.payload_bytes,
+        ]
+    )
+    query = repoclient.Query(
+        query=[], format_id=[sample_format.id], upload_session=upload_session
+    )
+    assert await sample_format.get_count(api_client, admin_user, query) == 0
+
+
+@pytest.mark.asyncio
+async def test_upload_session_api_key(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    # admins can't have API keys
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+    data = [{"NumericColumn": 1, "StringColumn": "api key"}]
+    upload = await sample_format.upload_data(api_client, normal_user, data)
+    assert upload.api_key_id is None
+
+    api_key = await normal_user.create_api_key(api_client)
+    key_user = repoclient.User.from_api_key(api_key.token)
+    upload = await sample_format.upload_data(api_client, key_user, data)
+    assert str(upload.api_key_id) == api_key.id
+    response = await api_client.get(
+        "/upload_session",
+        params={"apiKeyIdEq": api_key.id},
+        headers=admin_user.bearer,
+    )
+    assert response.status_code == 200
+    assert [session["id"] for session in response.json()] == [upload.id]
+    await api_key.delete_key(api_client)
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_upload_session_wait_for_outcome(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    get_by_id = repoclient.UploadSession.get_by_id
+    # finished sessions are returned right away
+    data = [{"NumericColumn": 1, "StringColumn": "done"}]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    session, timed_out = await get_by_id(
+        api_client, admin_user, upload.id, wait_for_outcome=True
+    )
+    assert (session.id, session.outcome, timed_out) == (upload.id, "Success", False)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await get_by_id(api_client, admin_user, 2**31 - 1, wait_for_outcome=True)
+    assert exc.value.error.code == "REPO-1004"
+
+    # in-progress sessions are returned once the upload is done
+    data = [{"NumericColumn": i, "StringColumn": "slow"} for i in range(50_000)]
+    upload_task = asyncio.create_task(
+        sample_format.upload_data(api_client, admin_user, data)
+    )
+    params = {"formatIdEq": sample_format.id, "outcomeEq": "InProgress"}
+    in_progress = []
+    while not in_progress and not upload_task.done():
+        response = await api_client.get(
+            "/upload_session", params=params, headers=admin_user.bearer
+        )
+        in_progress = response.json()
+        await asyncio.sleep(0.05)
+    assert in_progress, "the upload finished too quickly"
+    session_id = in_progress[0]["id"]
+    session, timed_out = await get_by_id(
+        api_client, admin_user, session_id, wait_for_outcome=True, timeout=0
+    )
+    assert (session.outcome, timed_out) == ("InProgress", True)
+    session, timed_out = await get_by_id(
+        api_client, admin_user, session_id, wait_for_outcome=True
+    )
+    assert (session.outcome, timed_out) == ("Success", False)
+    assert (await upload_task).id == session_id
+
+
+async def wait_for_outcome(
+    client, user: repoclient.User, upload_id: int
+) -> repoclient.UploadSession:
+    timed_out = True
+    while timed_out:
+        session, timed_out = await repoclient.UploadSession.get_by_id(
+            client, user, upload_id, wait_for_outcome=True
+        )
+    return session
+
+
+@pytest.mark.asyncio
+async def test_upload_async(api_client, admin_user, sample_format: repoclient.Format):
+    data = [{"NumericColumn": i, "StringColumn": "async"} for i in range(100)]
+    upload = await sample_format.upload_data(
+        api_client, admin_user, data, run_async=True
+    )
+    assert upload.outcome == "InProgress"
+    assert upload.record_count == 100
+    session = await wait_for_outcome(api_client, admin_user, upload.id)
+    assert session.outcome == "Success"
+    assert session.content_hash is not None
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    assert await sample_format.get_count(api_client, admin_user, query) == 100
+
+    # invalid records fail the session, not the request
+    data = [{"NumericColumn": "1", "StringColumn": "async"}]
+    upload = await sample_format.upload_data(
+        api_client, admin_user, data, run_async=True
+    )
+    session = await wait_for_outcome(api_client, admin_user, upload.id)
+    assert session.outcome == "Error"
+    assert "mistmatched types" in session.detail
+    assert await sample_format.get_count(api_client, admin_user, query) == 100
+
+
+@pytest.mark.asyncio
+async def test_upload_async_queue_full(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    # more uploads than the workers and the queue can take at once
+    data = [{"NumericColumn": i, "StringColumn": "queued"} for i in range(20_000)]
+    results = await asyncio.gather(
+        *(
+            sample_format.upload_data(api_client, admin_user, data, run_async=True)
+            for _ in range(2 * (INGESTION_QUEUE_DEPTH + INGESTION_WORKERS))
+        ),
+        return_exceptions=True,
+    )
+    rejected = [r for r in results if isinstance(r, repoclient.RepositoryException)]
+    assert rejected, "the queue never filled up"
+    assert all(exc.error.code == "REPO-3001" for exc in rejected)
+    # the queued uploads are processed anyway
+    queued = [r for r in results if isinstance(r, repoclient.UploadSession)]
+    assert len(queued) + len(rejected) == len(results)
+    for upload in queued:
+        session = await wait_for_outcome(api_client, admin_user, upload.id)
+        assert session.outcome == "Success"
+
+
+@pytest.mark.asyncio
+async def test_upload_session_format_name_filter(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": 1, "StringColumn": "by name"}]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+
+    async def get_sessions(user: repoclient.User, **params) -> list[dict]:
+        response = await api_client.get(
+            "/upload_session", headers=user.bearer, params={"perPage": 1000, **params}
+        )
+        assert response.status_code == 200
+        return response.json()
+
+    sessions = await get_sessions(admin_user, formatNameEq=sample_format.name)
+    assert {session["formatId"] for session in sessions} == {sample_format.id}
+    assert upload.id in {session["id"] for session in sessions}
+    # exact matches are case-sensitive, patterns aren't
+    name = sample_format.name.upper()
+    assert await get_sessions(admin_user, formatNameEq=name) == []
+    pattern = sample_format.name[:5].upper() + "%"
+    sessions = await get_sessions(admin_user, formatNameIlike=pattern)
+    assert upload.id in {session["id"] for session in sessions}
+    # the filter is combined with the other ones and the permission checks
+    params = {"formatNameIlike": pattern, "outcomeEq": "Error"}
+    sessions = await get_sessions(admin_user, **params)
+    assert upload.id not in {session["id"] for session in sessions}
+    assert await get_sessions(normal_user, formatNameEq=sample_format.name) == []
+
+
+@pytest.mark.asyncio
+async def test_repeated_filters(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    other = await repoclient.Format(
+        name=get_random_string(12),
+        description="repeated filters",
+        schema=sample_format.schema_ref,
+    ).create(api_client, admin_user)
+    data = [{"NumericColumn": 1, "StringColumn": "repeated"}]
+    first = await sample_format.upload_data(api_client, admin_user, data)
+    second = await other.upload_data(api_client, admin_user, data)
+
+    async def session_ids(params: list[tuple]) -> set[int]:
+        response = await api_client.get(
+            "/upload_session",
+            headers=admin_user.bearer,
+            params=[("perPage", 1000), *params],
+        )
+        assert response.status_code == 200
+        return {session["id"] for session in response.json()}
+
+    # repeated values of the same filter are ORed...
+    assert await session_ids([("formatIdEq", sample_format.id)]) == {first.id}
+    params = [("formatIdEq", sample_format.id), ("formatIdEq", other.id)]
+    assert await session_ids(params) == {first.id, second.id}
+    # ...and different filters are still ANDed
+    assert await session_ids([*params, ("idLt", second.id)]) == {first.id}
+    assert await session_ids([*params, ("outcomeEq", "Error")]) == set()
+    response = await api_client.get(
+        "/upload_session",
+        headers=admin_user.bearer,
+        params=[("formatIdEq", sample_format.id), ("formatIdEq", "x")],
+    )
+    assert response.status_code == 400
+
+    params = [("usernameEq", admin_user.username), ("usernameEq", normal_user.username)]
+    response = await api_client.get("/user", headers=admin_user.bearer, params=params)
+    assert response.status_code == 200
+    assert {user["username"] for user in response.json()} == {
+        admin_user.username,
+        normal_user.username,
+    }
+
+    # JSON filters take a single value or an array
+    for id_eq, expected in (([first.id, second.id], 2), (first.id, 1), ([], 2)):
+        query = repoclient.Query(query=[], format_id=[sample_format.id, other.id])
+        body = {**query.model_dump(by_alias=True), "uploadSession": {"idEq": id_eq}}
+        response = await api_client.post(
+            "/record/filter", json=body, headers=admin_user.bearer
+        )
+        assert response.status_code == 200
+        assert len(response.json()) == expected
+    await other.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_upload_session_filters_in_body(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    await sample_format.upload_data(
+        api_client, admin_user, [{"NumericColumn": 1, "StringColumn": "a"}]
+    )
+
+    async def search(upload_session: dict):
+        query = repoclient.Query(query=[], format_id=[sample_format.id])
+        body = {**query.model_dump(by_alias=True), "uploadSession": upload_session}
+        return await api_client.post(
+            "/record/filter", json=body, headers=admin_user.bearer
+        )
+
+    response = await search({"recordCountGte": 1})
+    assert response.status_code == 200
+    assert len(response.json()) == 1
+    # typos aren't ignored, unlike in query strings
+    response = await search({"recordCuontGte": 1})
+    assert response.status_code == 400
+    response = await api_client.get(
+        "/upload_session",
+        params={"formatIdEq": sample_format.id, "recordCuontGte": 1},
+        headers=admin_user.bearer,
+    )
+    assert response.status_code == 200
+    # empty ranges
+    response = await search({"idGte": 10, "idLte": 1})
+    assert response.status_code == 400
+    assert response.json()["code"] == "REPO-1008"
+    # repeated bounds are ORed
+    response = await search({"idGte": [10, 1], "idLte": 1_000_000_000})
+    assert response.status_code == 200
+
+
+@pytest.mark.asyncio
+async def test_upload_session_filters_are_scoped(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    other_format = await repoclient.Format(
+        name=get_random_string(12),
+        description="session filter scoping",
+        schema=sample_format.schema_ref,
+    ).create(api_client, admin_user)
+    other_user = repoclient.User(
+        username="test_" + get_random_string(20), password="random"
+    )
+    await admin_user.create_user(api_client, other_user)
+    other_user = await other_user.login(api_client)
+    entitlements = [
+        await repoclient.FormatEntitlement(
+            user_id=user.id,
+            format_id=fmt.id,
+            access=[repoclient.EntitlementAccessLevel.READ],
+        ).create(api_client, admin_user)
+        for user, fmt in ((normal_user, sample_format), (other_user, other_format))
+    ]
+    data = [{"NumericColumn": 1, "StringColumn": "scoped"}]
+    first = await sample_format.upload_data(api_client, admin_user, data)
+    second = await other_format.upload_data(api_client, admin_user, data)
+
+    async def search(user: repoclient.User) -> set[int]:
+        format_ids = [sample_format.id, other_format.id]
+        query = repoclient.Query(query=[], format_id=format_ids)
+        body = {
+            **query.model_dump(by_alias=True),
+            "uploadSession": {"idEq": [first.id, second.id]},
+        }
+        response = await api_client.post(
+            "/record/filter", json=body, headers=user.bearer
+        )
+        assert response.status_code == 200
+        return {record["upload_session_id"] for record in response.json()}
+
+    # the same session filter only matches the sessions of readable formats
+    assert await search(admin_user) == {first.id, second.id}
+    assert await search(normal_user) == {first.id}
+    assert await search(other_user) == {second.id}
+
+    for entitlement in entitlements:
+        await entitlement.delete(api_client, admin_user)
+    await admin_user.delete_user(api_client, other_user, force=True)
+    await other_format.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_ingestion_window(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    session = await sample_format.upload_data(
+        api_client, admin_user, [{"NumericColumn": 1, "StringColumn": "a"}]
+    )
+    uploaded = session.created_at
+    second = timedelta(seconds=1)
+
+    async def count(**kwargs) -> int:
+        query = repoclient.Query(query=[], format_id=[sample_format.id], **kwargs)
+        return await sample_format.get_count(api_client, admin_user, query)
+
+    assert await count(ingested_after=uploaded - second) == 1
+    assert await count(ingested_after=uploaded + second) == 0
+    assert await count(ingested_before=uploaded + second) == 1
+    assert await count(ingested_before=uploaded - second) == 0
+    assert (
+        await count(ingested_after=uploaded - second, ingested_before=uploaded + second)
+        == 1
+    )
+    # the client converts other timezones to UTC
+    other_tz = timezone(timedelta(hours=2))
+    assert await count(ingested_after=(uploaded - second).astimezone(other_tz)) == 1
+    assert await count(ingested_after=(uploaded + second).astimezone(other_tz)) == 0
+    # both the window and the upload session filters have to match
+    upload_session = FormatUploadSession(
+        [P(FormatUploadSessionFilter.RECORD_COUNT) >= 2]
+    )
+    assert (
+        await count(ingested_after=uploaded - second, upload_session=upload_session)
+        == 0
+    )
+
+    async def search(**window):
+        query = repoclient.Query(query=[], format_id=[sample_format.id])
+        body = {**query.model_dump(by_alias=True), **window}
+        return await api_client.post(
+            "/record/filter", json=body, headers=admin_user.bearer
+        )
+
+    for window in (
+        # the server only takes UTC timestamps
+        {"ingestedAfter": "2024-04-01T02:00:00+02:00"},
+        {"ingestedAfter": "2024-04-01"},
+        {"ingestedBefore": "yesterday"},
+        # empty windows
+        {
+            "ingestedAfter": "2024-04-02T00:00:00Z",
+            "ingestedBefore": "2024-04-01T00:00:00Z",
+        },
+        {
+            "ingestedAfter": "2024-04-01T00:00:00Z",
+            "ingestedBefore": "2024-04-01T00:00:00Z",
+        },
+    ):
+        response = await search(**window)
+        assert response.status_code == 400, window
+        assert response.json()["code"] == "REPO-1008"
+    response = await search(ingestedAfter="2024-04-01T00:00:00+00:00")
+    assert response.status_code == 200
+
+
+@pytest.mark.asyncio
+async def test_upload_session_details_are_sanitized(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    # a huge invalid upload, with a failure in every record
+    data = [{"NumericColumn": "x" * 100, "StringColumn": i} for i in range(1_000)]
+    with pytest.raises(repoclient.RepositoryException):
+        await sample_format.upload_data(api_client, admin_user, data)
+    async for upload_session in repoclient.UploadSession.get_all(
+        api_client, admin_user
+    ):
+        detail = upload_session.detail
+        assert len(detail) <= UPLOAD_SESSION_DETAIL_MAX_LENGTH, upload_session.id
+        assert not LEAKY_DETAIL_PATTERN.search(detail), (upload_session.id, detail)
+
+
 @pytest.mark.asyncio
 async def test_upload_record_admin_wrong_type(
     api_client, admin_user, sample_format: repoclient.Format
@@ -55,7 +680,35 @@ async def test_upload_record_admin_wrong_type(
         # user cannot upload because they have insufficient perms
     exc: repoclient.RepositoryException = exc.value
     assert exc.request_id is not None
+    assert exc.error.code == "REPO-1003"
     assert exc.error.kind == "ValidationFailure"
+    # the failed upload session is part of the error
+    assert exc.error.upload_session["outcome"] == "Error"
+    assert exc.error.upload_session["recordCount"] == 10
+
+
+@pytest.mark.asyncio
+async def test_upload_response(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "response"} for i in range(5)]
+    payload = {"formatId": sample_format.id, "data": data}
+    response = await api_client.post("/record", json=payload, headers=admin_user.bearer)
+    assert response.status_code == 200
+    body = response.json()
+    assert body["uploadSession"]["outcome"] == "Success"
+    assert body["receivedCount"] == body["insertedCount"] == 5
+    assert body["elapsedMs"] >= 0
+    assert "code" not in body
+
+    payload["data"] = data + [{"NumericColumn": "5", "StringColumn": "response"}]
+    response = await api_client.post("/record", json=payload, headers=admin_user.bearer)
+    assert response.status_code == 400
+    body = response.json()
+    assert body["code"] == "REPO-1003"
+    assert body["uploadSession"]["outcome"] == "Error"
+    assert body["receivedCount"] == 6
+    assert body["insertedCount"] == 0
 
 
 @pytest.mark.asyncio
@@ -64,11 +717,12 @@ async def test_upload_record_no_entitlement(
 ):
     data = [{"NumericColumn": 123, "StringColumn": "abcdeasf"}] * 100
     with pytest.raises(repoclient.RepositoryException) as exc:
-        # user cannot upload because they have insufficient perms
+        # user cannot upload because they can't even see the format
         _upload = await sample_format.upload_data(api_client, normal_user, data)
     exc: repoclient.RepositoryException = exc.value
     assert exc.request_id is not None
-    assert exc.error.kind == "InsufficientPermissions"
+    assert exc.error.code == "REPO-1004"
+    assert exc.error.kind == "NotFound"
 
 
 @pytest.mark.asyncio
@@ -93,6 +747,7 @@ async def test_entitlement_read_only(
     await entitlement.delete(api_client, admin_user)
     exc: repoclient.RepositoryException = exc.value
     assert exc.request_id is not None
+    assert exc.error.code == "REPO-2003"
     assert exc.error.kind == "InsufficientPermissions"
 
 
@@ -124,7 +779,15 @@ async def test_delete_upload_session_admin_no_perm(
     # user can write (rw perms)
     upload_session = await sample_format.upload_data(api_client, admin_user, data)
     assert upload_session.record_count == 100, "wrong record count"
-    await upload_session.delete(api_client, admin_user)
+    result = await upload_session.delete(api_client, admin_user)
+    assert result.session_id == upload_session.id
+    assert result.format_id == sample_format.id
+    assert result.records_deleted == 100
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    assert await sample_format.get_count(api_client, admin_user, query) == 0
+    # it's gone, so there's nothing left to delete
+    with pytest.raises(repoclient.RepositoryException):
+        await upload_session.delete(api_client, admin_user)
 
 
 async def test_delete_upload_session_no_permission(
@@ -147,6 +810,7 @@ async def test_delete_upload_session_no_permission(
         await upload_session.delete(api_client, normal_user)
     exc: repoclient.RepositoryException = exc.value
     assert exc.request_id is not None
+    assert exc.error.code == "REPO-2003"
     assert exc.error.kind == "InsufficientPermissions"
     # delete this entitlement
     await entitlement.delete(api_client, admin_user)
@@ -176,26 +840,43 @@ async def test_delete_upload_session_permission(
     # user can write (rw perms)
     upload_session = await sample_format.upload_data(api_client, normal_user, data)
     assert upload_session.record_count == 100, "wrong record count"
-    # Assert raise exception
-    await upload_session.delete(api_client, normal_user)
+    result = await upload_session.delete(api_client, normal_user)
+    assert result.records_deleted == 100
     # delete this entitlement
     await entitlement.delete(api_client, admin_user)
 
 
 async def test_entitlement_create_normal_user(
-    api_client, normal_user: repoclient.User, sample_format: repoclient.Format
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
 ):
+    write_entitlement = repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.WRITE],
+    )
+    # formats the user can't see are a 404
     with pytest.raises(repoclient.RepositoryException) as exc:
-        await repoclient.FormatEntitlement(
-            user_id=normal_user.id,
-            format_id=sample_format.id,
-            access=[repoclient.EntitlementAccessLevel.WRITE],
-        ).create(api_client, normal_user)
-
-    # can't create stuff without admin perms
+        await write_entitlement.create(api_client, normal_user)
     exc: repoclient.RepositoryException = exc.value
     assert exc.request_id is not None
+    assert exc.error.code == "REPO-1004"
+    assert exc.error.kind == "NotFound"
+
+    # can't create stuff without admin perms, even on formats they can read
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await write_entitlement.create(api_client, normal_user)
+    exc: repoclient.RepositoryException = exc.value
+    assert exc.error.code == "REPO-2004"
     assert exc.error.kind == "AdminOnlyResource"
+    await entitlement.delete(api_client, admin_user)
 
 
 @pytest.mark.parametrize(
@@ -628,3 +1309,1332 @@ async def test_query_regex_case_sensitive(
     )
     unique_values = set(list(dataframe["StringColumn"]))
     assert len(unique_values) == 1
+
+
+async def _string_column_values(sample_format, api_client, user, column):
+    query = repoclient.Query(
+        query=[repoclient.QueryGroup(kind=QueryGroupKind.ALL, args=[column])],
+        format_id=[sample_format.id],
+    )
+    values = set()
+    async for item in sample_format.get_data(api_client, user, query):
+        values.add(item.data["StringColumn"])
+    return values
+
+
+@pytest.mark.asyncio
+async def test_query_eq_case_insensitive(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    titles = ["A New Hope", "a new hope", "A NEW HOPE!", "a_new%hope"]
+    data = [{"NumericColumn": 0, "StringColumn": title} for title in titles]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+
+    column = repoclient.Column(column="StringColumn").equals_case_insensitive(
+        "a NEW hope"
+    )
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"A New Hope", "a new hope"}
+    # wildcards aren't special here
+    column = repoclient.Column(column="StringColumn").equals_case_insensitive(
+        "A_NEW%HOPE"
+    )
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"a_new%hope"}
+    with pytest.raises(RepositoryError):
+        column = repoclient.Column(column="NumericColumn").equals_case_insensitive("0")
+        await _string_column_values(sample_format, api_client, admin_user, column)
+
+
+@pytest.mark.asyncio
+async def test_query_like_escape_wildcards(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    titles = ["50%_off", "50% off", "50%_OFF", "back\\slash", "backXslash"]
+    data = [{"NumericColumn": 0, "StringColumn": title} for title in titles]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+
+    column = repoclient.Column(column="StringColumn").is_like("50%_off")
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"50%_off", "50% off"}
+    column = repoclient.Column(column="StringColumn").is_like(
+        "50%_off", escape_wildcards=True
+    )
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"50%_off"}
+    column = repoclient.Column(column="StringColumn").is_like_case_insensitive(
+        "50%_off", escape_wildcards=True
+    )
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"50%_off", "50%_OFF"}
+    column = repoclient.Column(column="StringColumn").is_like(
+        "back\\slash", escape_wildcards=True
+    )
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"back\\slash"}
+    # escapeWildcards only makes sense for like/iLike
+    column = repoclient.Column(column="StringColumn") == "50%_off"
+    column.escape_wildcards = True
+    with pytest.raises(RepositoryError):
+        await _string_column_values(sample_format, api_client, admin_user, column)
+
+
+@pytest.mark.parametrize(
+    "operator,pattern,allowed",
+    [
+        ("is_like", "%", False),
+        ("is_like", "%a%", False),
+        ("is_like", "__%", False),
+        ("is_like", "ab%", False),
+        ("is_like", "abc%", True),
+        ("is_like_case_insensitive", "%a%", False),
+        ("is_like_case_insensitive", "%ABC%", True),
+        ("matches_regex", ".*", False),
+        ("matches_regex", "^[abc]+x$", False),
+        ("matches_regex", "\\d\\d\\d", False),
+        ("matches_regex", "^abc", True),
+        ("matches_regex_case_insensitive", "a.*", False),
+        ("matches_regex_case_insensitive", "ABC.*", True),
+    ],
+)
+@pytest.mark.asyncio
+async def test_query_pattern_guard(
+    api_client,
+    admin_user,
+    normal_user,
+    sample_format: repoclient.Format,
+    operator: str,
+    pattern: str,
+    allowed: bool,
+):
+    data = [{"NumericColumn": 0, "StringColumn": title} for title in ("abcd", "xyz")]
+    await sample_format.upload_data(api_client, admin_user, data)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    column = getattr(repoclient.Column(column="StringColumn"), operator)(pattern)
+
+    # superusers can always run these, the guard only logs a warning
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    if allowed:
+        assert values == {"abcd"}
+        values = await _string_column_values(
+            sample_format, api_client, normal_user, column
+        )
+        assert values == {"abcd"}
+    else:
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await _string_column_values(sample_format, api_client, normal_user, column)
+        assert exc.value.error.code == "REPO-1008"
+        assert "would scan every record" in exc.value.error.detail
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_pattern_guard_escaped_wildcards(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": 0, "StringColumn": "%%%"}]
+    await sample_format.upload_data(api_client, admin_user, data)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    # escaped wildcards are literal characters
+    column = repoclient.Column(column="StringColumn").is_like(
+        "%%%", escape_wildcards=True
+    )
+    values = await _string_column_values(sample_format, api_client, normal_user, column)
+    assert values == {"%%%"}
+    column = repoclient.Column(column="StringColumn").is_like("\\%\\%\\%")
+    values = await _string_column_values(sample_format, api_client, normal_user, column)
+    assert values == {"%%%"}
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_starts_ends_with(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    titles = ["invoice-2024_01%", "invoice-2024-02", "invoiceX2024_01%", "2024_01%"]
+    data = [{"NumericColumn": 0, "StringColumn": title} for title in titles]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+
+    column = repoclient.Column(column="StringColumn").starts_with("invoice-2024")
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"invoice-2024_01%", "invoice-2024-02"}
+    # `_` and `%` aren't wildcards
+    column = repoclient.Column(column="StringColumn").starts_with("invoice_")
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == set()
+    column = repoclient.Column(column="StringColumn").ends_with("2024_01%")
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"invoice-2024_01%", "invoiceX2024_01%", "2024_01%"}
+    column = repoclient.Column(column="StringColumn").ends_with("%")
+    values = await _string_column_values(sample_format, api_client, admin_user, column)
+    assert values == {"invoice-2024_01%", "invoiceX2024_01%", "2024_01%"}
+    with pytest.raises(RepositoryError):
+        column = repoclient.Column(column="StringColumn").starts_with("")
+        await _string_column_values(sample_format, api_client, admin_user, column)
+
+
+@pytest.mark.asyncio
+async def test_query_large_integers(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    # 2**53 + 1 isn't representable as a float, so both would be equal as FLOATs
+    data = [
+        {"NumericColumn": 2**53, "StringColumn": "a"},
+        {"NumericColumn": 2**53 + 1, "StringColumn": "b"},
+        {"NumericColumn": 2**63 - 1, "StringColumn": "c"},
+    ]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+
+    async def query(*args):
+        group = repoclient.QueryGroup(kind=QueryGroupKind.ALL, args=list(args))
+        query = repoclient.Query(query=[group], format_id=[sample_format.id])
+        return [
+            item.data["StringColumn"]
+            async for item in sample_format.get_data(api_client, admin_user, query)
+        ]
+
+    column = repoclient.Column(column="NumericColumn")
+    assert await query(column == 2**53 + 1) == ["b"]
+    assert await query(column == 2**53) == ["a"]
+    assert await query(column == 2**63 - 1) == ["c"]
+    assert sorted(await query(column > 2**53)) == ["b", "c"]
+    assert await query(column.is_in([2**53 + 1, 2**53 + 3])) == ["b"]
+
+
+@pytest.mark.asyncio
+async def test_column_stats(api_client, admin_user, sample_format: repoclient.Format):
+    data = [
+        {"NumericColumn": 1, "StringColumn": "kiwi"},
+        {"NumericColumn": 2.5, "StringColumn": "apple"},
+        {"NumericColumn": 2.5, "StringColumn": "banana"},
+    ]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+    query = repoclient.Query(format_id=[sample_format.id])
+
+    stats = await sample_format.get_column_stats(
+        api_client, admin_user, "NumericColumn", query
+    )
+    assert stats["kind"] == "Number"
+    assert (stats["count"], stats["missing"], stats["distinct"]) == (3, 0, 2)
+    assert (stats["min"], stats["max"], stats["mean"]) == (1, 2.5, 2)
+    assert "minLength" not in stats
+
+    stats = await sample_format.get_column_stats(
+        api_client, admin_user, "StringColumn", query
+    )
+    assert stats["kind"] == "String"
+    assert (stats["min"], stats["max"]) == ("apple", "kiwi")
+    assert (stats["minLength"], stats["maxLength"]) == (4, 6)
+    assert stats["meanLength"] == pytest.approx(5)
+    assert "mean" not in stats
+
+    # only the matching records are profiled
+    query.query = [
+        repoclient.QueryGroup(
+            kind=QueryGroupKind.ALL,
+            args=[repoclient.Column(column="NumericColumn") > 2],
+        )
+    ]
+    stats = await sample_format.get_column_stats(
+        api_client, admin_user, "StringColumn", query
+    )
+    assert (stats["count"], stats["min"], stats["max"]) == (2, "apple", "banana")
+    with pytest.raises(RepositoryError):
+        await sample_format.get_column_stats(
+            api_client, admin_user, "MissingColumn", query
+        )
+
+
+@pytest.mark.asyncio
+async def test_query_compressed_response(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    data = [
+        {"NumericColumn": i, "StringColumn": "some fairly repetitive string"}
+        for i in range(0, 500)
+    ]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+    url = "/record/filter?perPage=500&count=true"
+
+    responses = {}
+    for encoding in ("identity", "gzip"):
+        headers = {**admin_user.bearer, "Accept-Encoding": encoding}
+        response = await api_client.post(url, json=json_query, headers=headers)
+        assert response.status_code == 200
+        assert response.headers.get("request-id") is not None
+        assert response.headers.get("repository-item-count") == "500"
+        assert response.headers.get("repository-page-count") == "1"
+        responses[encoding] = response
+
+    plain = responses.pop("identity")
+    assert plain.headers.get("content-encoding", "identity") == "identity"
+    for encoding, response in responses.items():
+        assert response.headers.get("content-encoding") == encoding
+        assert response.num_bytes_downloaded < plain.num_bytes_downloaded
+        assert response.json() == plain.json(), "decoded payloads differ"
+
+    # the CSV stream is never compressed
+    headers = {**admin_user.bearer, "Accept-Encoding": "gzip"}
+    response = await api_client.post(
+        "/record/filter-stream", json=json_query, headers=headers
+    )
+    assert response.status_code == 200
+    assert response.headers.get("content-encoding", "identity") == "identity"
+
+
+@pytest.mark.asyncio
+async def test_stream_parallelism(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "parallel"} for i in range(0, 100)]
+    await sample_format.upload_data(api_client, admin_user, data)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+
+    async def stream(user: repoclient.User, **params):
+        return await api_client.post(
+            "/record/filter-stream",
+            json=json_query,
+            params=params,
+            headers=user.bearer,
+        )
+
+    single = await stream(admin_user, streams=1, transformWorkers=1)
+    assert single.status_code == 200
+    assert single.headers["repository-streams"] == "1"
+    # superusers are clamped to DB_CSV_MAX_STREAM_WORKERS
+    response = await stream(admin_user, streams=10_000)
+    assert response.status_code == 200
+    assert int(response.headers["repository-streams"]) == DB_CSV_MAX_STREAM_WORKERS
+    # the same records (the streams are interleaved, so not in order)
+    lines = response.text.splitlines()
+    assert lines[0] == single.text.splitlines()[0]
+    assert sorted(lines) == sorted(single.text.splitlines())
+    assert len(lines) == 101
+
+    # other users can't raise them above the defaults
+    response = await stream(normal_user, streams=4, transformWorkers=4)
+    assert response.status_code == 200
+    assert int(response.headers["repository-streams"]) == DB_CSV_STREAM_WORKERS
+    workers = int(response.headers["repository-transform-workers"])
+    assert workers == DB_CSV_TRANSFORM_WORKERS
+    assert sorted(response.text.splitlines()) == sorted(single.text.splitlines())
+
+    for params in ({"streams": 0}, {"transformWorkers": 0}):
+        response = await stream(admin_user, **params)
+        assert response.status_code == 400
+        assert response.json()["code"] == "REPO-1005"
+    response = await stream(admin_user, streams="many")
+    assert response.status_code == 400
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.parametrize("order_by", ["createdAt", "-createdAt", "-uploadSessionId"])
+@pytest.mark.asyncio
+async def test_pages_are_stable_with_ties(
+    api_client, admin_user, sample_format: repoclient.Format, order_by: str
+):
+    # every record of an upload has the same createdAt and uploadSessionId
+    data = [{"NumericColumn": i, "StringColumn": "tie"} for i in range(0, 100)]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+
+    pages = []
+    for page in range(0, 15):
+        response = await api_client.post(
+            f"/record/filter?orderBy={order_by}&perPage=7&page={page}",
+            json=json_query,
+            headers=admin_user.bearer,
+        )
+        assert response.status_code == 200
+        pages.append([item["id"] for item in response.json()])
+    ids = [id for page in pages for id in page]
+    # no record is repeated or skipped across page boundaries, and ties are
+    # sorted by id
+    assert len(ids) == len(set(ids)) == 100
+    assert ids == sorted(ids)
+
+
+@pytest.mark.asyncio
+async def test_query_dataless(api_client, admin_user, sample_format: repoclient.Format):
+    data = [{"NumericColumn": i, "StringColumn": "dataless"} for i in range(0, 30)]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[repoclient.Column(column="NumericColumn") >= 10],
+    )
+    query = repoclient.Query(query=[group], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+
+    async def get_page(page: int, **params):
+        response = await api_client.post(
+            "/record/filter",
+            json=json_query,
+            headers=admin_user.bearer,
+            params={"perPage": 7, "page": page, "count": "true", **params},
+        )
+        assert response.status_code == 200
+        return response
+
+    # same conditions and pagination, minus the data
+    for page in range(0, 4):
+        full = await get_page(page)
+        dataless = await get_page(page, dataless="true")
+        for header in ("repository-item-count", "repository-page-count"):
+            assert dataless.headers[header] == full.headers[header]
+        assert dataless.json() == [
+            {
+                "id": record["id"],
+                "upload_session_id": upload.id,
+                "format_id": sample_format.id,
+            }
+            for record in full.json()
+        ]
+    assert full.headers["repository-item-count"] == "20"
+
+    response = await api_client.post(
+        "/record/filter",
+        json=json_query,
+        headers=admin_user.bearer,
+        params={"dataless": "true", "envelope": "true"},
+    )
+    assert response.status_code == 400
+
+
+@pytest.mark.asyncio
+async def test_query_size_hint(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "x" * i} for i in range(0, 10)]
+    await sample_format.upload_data(api_client, admin_user, data)
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+
+    async def get_page(**params):
+        response = await api_client.post(
+            "/record/filter",
+            json=json_query,
+            headers=admin_user.bearer,
+            params={"perPage": 4, **params},
+        )
+        assert response.status_code == 200
+        return response
+
+    # off by default
+    response = await get_page()
+    assert "repository-avg-item-bytes" not in response.headers
+
+    for params in ({}, {"dataless": "true"}, {"envelope": "true"}):
+        response = await get_page(sizeHint="true", **params)
+        items = response.json()
+        items = items["items"] if "items" in items else items
+        sizes = [len(orjson.dumps(item)) for item in items]
+        avg_item_bytes = int(response.headers["repository-avg-item-bytes"])
+        assert avg_item_bytes == sum(sizes) // len(sizes)
+
+    # ...and not sent for empty pages
+    response = await get_page(sizeHint="true", page=10)
+    assert response.json() == []
+    assert "repository-avg-item-bytes" not in response.headers
+
+
+@pytest.mark.skipif(
+    not 0 < MAX_FILTER_RESPONSE_BYTES <= 2_000_000,
+    reason="the response budget is too big to go over in a test",
+)
+@pytest.mark.asyncio
+async def test_query_response_budget(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    # every record takes up a bit more than a third of the budget
+    fat = "x" * (MAX_FILTER_RESPONSE_BYTES // 3)
+    data = [{"NumericColumn": i, "StringColumn": fat} for i in range(0, 5)]
+    await sample_format.upload_data(api_client, admin_user, data)
+    query = repoclient.Query(query=[], format_id=[sample_format.id])
+    json_query = query.model_dump(by_alias=True)
+
+    async def get_page(**params):
+        response = await api_client.post(
+            "/record/filter",
+            json=json_query,
+            headers=admin_user.bearer,
+            params={"perPage": 5, **params},
+        )
+        assert response.status_code == 200
+        return response
+
+    pages = []
+    params = {}
+    while True:
+        response = await get_page(**params)
+        records = response.json()
+        assert response.headers["repository-item-count"] == str(5 - sum(pages))
+        assert response.headers["repository-current-page-count"] == str(len(records))
+        pages.append(len(records))
+        if "repository-truncated-after-id" not in response.headers:
+            break
+        # continue right after the last record of the truncated page
+        last_id = int(response.headers["repository-truncated-after-id"])
+        assert last_id == records[-1]["id"]
+        params = {"idGt": last_id}
+    assert pages == [2, 2, 1]
+
+    # pages that fit aren't truncated
+    response = await get_page(dataless="true")
+    assert len(response.json()) == 5
+    assert "repository-truncated-after-id" not in response.headers
+
+
+@pytest.mark.asyncio
+async def test_query_in_reports_offending_item(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    values = list(range(0, 999)) + ["not a number"]
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[repoclient.Column(column="NumericColumn").is_in(values)],
+    )
+    query = repoclient.Query(query=[group], format_id=[sample_format.id])
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(api_client, admin_user, query)
+    exc: repoclient.RepositoryException = exc.value
+    assert exc.error.code == "REPO-1008"
+    assert "item 999 isn't a number" in exc.error.detail
+
+    # arrays over MAX_COMPARE_AGAINST_ARRAY_LENGTH are rejected upfront
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[
+            repoclient.Column(column="NumericColumn").is_in(list(range(0, 10_001)))
+        ],
+    )
+    query = repoclient.Query(query=[group], format_id=[sample_format.id])
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(api_client, admin_user, query)
+    exc: repoclient.RepositoryException = exc.value
+    assert exc.error.code == "REPO-3003"
+    assert "at most 10000 are allowed" in exc.error.detail
+
+
+@pytest.mark.asyncio
+async def test_query_uploader(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+    admin_data = [{"NumericColumn": 1, "StringColumn": "admin"}] * 10
+    await sample_format.upload_data(api_client, admin_user, admin_data)
+    user_data = [{"NumericColumn": 2, "StringColumn": "user"}] * 20
+    first_upload = await sample_format.upload_data(api_client, normal_user, user_data)
+    await sample_format.upload_data(api_client, normal_user, user_data)
+
+    by_name = repoclient.Uploader(username=normal_user.username)
+    by_pattern = repoclient.Uploader(username_ilike=normal_user.username.upper())
+    by_id = repoclient.Uploader(user_id=normal_user.id)
+    for uploader in (by_name, by_pattern, by_id):
+        query = repoclient.Query(format_id=[sample_format.id], uploader=uploader)
+        # non-superusers can use it too
+        for user in (admin_user, normal_user):
+            count = await sample_format.get_count(api_client, user, query)
+            assert count == 40, "wrong record count"
+
+    # combined with the upload session filters
+    upload_session = FormatUploadSession(
+        [P(FormatUploadSessionFilter.ID) == first_upload.id]
+    )
+    query = repoclient.Query(
+        format_id=[sample_format.id], uploader=by_name, upload_session=upload_session
+    )
+    assert await sample_format.get_count(api_client, admin_user, query) == 20
+    query.uploader = repoclient.Uploader(username=admin_user.username)
+    assert await sample_format.get_count(api_client, admin_user, query) == 0
+
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        query = repoclient.Query(
+            format_id=[sample_format.id], uploader=repoclient.Uploader(username="")
+        )
+        await sample_format.get_count(api_client, admin_user, query)
+    assert exc.value.error.code == "REPO-1008"
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_column_kinds_follow_format_changes(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[repoclient.Column(column="NumericColumn") >= 0],
+    )
+    query = repoclient.Query(query=[group])
+    # warm up the column kind cache
+    await sample_format.get_count(api_client, admin_user, query)
+
+    # a new format with a conflicting column kind must be taken into account
+    conflicting = await repoclient.Format(
+        name=get_random_string(12),
+        description="conflicting column kinds",
+        schema=[repoclient.ColumnSchema.string("NumericColumn")],
+    ).create(api_client, admin_user)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(api_client, admin_user, query)
+    assert exc.value.error.code == "REPO-1008"
+
+    # ...and so must its deletion
+    await conflicting.delete(api_client, admin_user)
+    await sample_format.get_count(api_client, admin_user, query)
+
+
+@pytest.mark.asyncio
+async def test_query_mixed_column_kinds_name_formats(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    conflicting = await repoclient.Format(
+        name=get_random_string(12),
+        description="conflicting column kinds",
+        schema=[repoclient.ColumnSchema.string("NumericColumn")],
+    ).create(api_client, admin_user)
+    query = repoclient.Query(format_id=[sample_format.id, conflicting.id])
+    try:
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await sample_format.get_count(api_client, admin_user, query)
+        assert exc.value.error.code == "REPO-1008"
+        assert (
+            f"Column 'NumericColumn' is Number in formats "
+            f"[{sample_format.id} '{sample_format.name}'] "
+            f"but String in [{conflicting.id} '{conflicting.name}']"
+        ) in exc.value.error.detail
+        assert "Narrow down the `formats` list" in exc.value.error.detail
+
+        # searching a single one of them works
+        query = repoclient.Query(format_id=[conflicting.id])
+        assert await sample_format.get_count(api_client, admin_user, query) == 0
+    finally:
+        await conflicting.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_mixed_column_kinds_treat_as(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    conflicting = await repoclient.Format(
+        name=get_random_string(12),
+        description="conflicting column kinds",
+        schema=[repoclient.ColumnSchema.string("NumericColumn")],
+    ).create(api_client, admin_user)
+    try:
+        data = [
+            {"NumericColumn": 7, "StringColumn": "a"},
+            {"NumericColumn": 3, "StringColumn": "b"},
+        ]
+        await sample_format.upload_data(api_client, admin_user, data)
+        data = [{"NumericColumn": "7"}, {"NumericColumn": "abc"}]
+        await conflicting.upload_data(api_client, admin_user, data)
+
+        def query(*args):
+            group = repoclient.QueryGroup(kind=QueryGroupKind.ALL, args=list(args))
+            return repoclient.Query(
+                query=[group], format_id=[sample_format.id, conflicting.id]
+            )
+
+        async def search(*args, user=admin_user):
+            return sorted(
+                [
+                    repr(item.data["NumericColumn"])
+                    async for item in sample_format.get_data(
+                        api_client, user, query(*args)
+                    )
+                ]
+            )
+
+        def column():
+            return repoclient.Column(column="NumericColumn")
+
+        # without treatAs the kinds still conflict
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await search(column() > 5)
+        assert exc.value.error.code == "REPO-1008"
+        # ...even if only some of the arguments have one
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await search(column().treated_as("number") > 5, column() == "7")
+        assert exc.value.error.code == "REPO-1008"
+
+        # numbers only match the format where the column is a number...
+        assert await search(column().treated_as("number") > 5) == ["7"]
+        assert await search(column().treated_as("number").is_in([3, 7])) == [
+            "3",
+            "7",
+        ]
+        # ...while strings match both, as text
+        assert await search(column().treated_as("string") == "7") == ["'7'", "7"]
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await search(column().treated_as("number") == "7")
+        assert exc.value.error.code == "REPO-1008"
+
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await search(column().treated_as("number") > 5, user=normal_user)
+        assert exc.value.error.code == "REPO-2003"
+    finally:
+        await conflicting.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_reports_all_invalid_arguments(
+    api_client, admin_user, sample_format: repoclient.Format
+):
+    query = repoclient.Query(
+        format_id=[sample_format.id],
+        query=[
+            repoclient.QueryGroup(
+                kind=QueryGroupKind.ALL,
+                args=[
+                    repoclient.Column(column="NumericColumn") >= 0,
+                    repoclient.Column(column="MissingColumn") == "abc",
+                ],
+            ),
+            repoclient.QueryGroup(
+                kind=QueryGroupKind.ALL,
+                args=[repoclient.Column(column="NumericColumn") == "not a number"],
+            ),
+        ],
+    )
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(api_client, admin_user, query)
+    error = exc.value.error
+    assert error.code == "REPO-1008"
+    assert [(e.group, e.argument, e.column) for e in error.errors] == [
+        (0, 1, "MissingColumn"),
+        (1, 0, "NumericColumn"),
+    ]
+
+    # a single invalid argument is reported as before
+    query.query = query.query[:1]
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await sample_format.get_count(api_client, admin_user, query)
+    assert exc.value.error.code == "REPO-1008"
+    assert exc.value.error.errors is None
+    assert "MissingColumn" in exc.value.error.detail
+
+
+@pytest.mark.asyncio
+async def test_query_strict_formats(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    unentitled = await repoclient.Format(
+        name=get_random_string(12),
+        description="not readable by normal_user",
+        schema=[repoclient.ColumnSchema.numeric("NumericColumn")],
+    ).create(api_client, admin_user)
+    unknown_id = 2**31 - 1
+
+    # unknown and unreadable formats are silently left out by default...
+    for format_id in (unknown_id, unentitled.id):
+        query = repoclient.Query(format_id=[sample_format.id, format_id])
+        await sample_format.get_count(api_client, normal_user, query)
+
+    # ...but rejected with strictFormats
+    for format_id in (unknown_id, unentitled.id):
+        query = repoclient.Query(
+            format_id=[sample_format.id, format_id], strict_formats=True
+        )
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await sample_format.get_count(api_client, normal_user, query)
+        assert exc.value.error.code == "REPO-1008"
+        assert str(format_id) in exc.value.error.detail
+
+    # superusers can read every existing format
+    query = repoclient.Query(
+        format_id=[sample_format.id, unentitled.id], strict_formats=True
+    )
+    await sample_format.get_count(api_client, admin_user, query)
+    await unentitled.delete(api_client, admin_user)
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_query_debug_summary(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    unentitled = await repoclient.Format(
+        name=get_random_string(12),
+        description="not readable by normal_user",
+        schema=[repoclient.ColumnSchema.datetime("DatetimeColumn")],
+    ).create(api_client, admin_user)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    query = repoclient.Query(
+        query=[
+            repoclient.QueryGroup(
+                kind=QueryGroupKind.ALL,
+                args=[repoclient.Column(column="NumericColumn") > 0],
+            )
+        ],
+        format_id=[sample_format.id, unentitled.id],
+    )
+
+    async def summary(user: repoclient.User, path: str) -> dict:
+        response = await api_client.post(
+            path,
+            json=query.model_dump(by_alias=True),
+            headers=user.bearer,
+            params={"debug": "true"},
+        )
+        assert response.status_code == 200
+        return response.json()
+
+    for path in ("/record/filter", "/record/filter-stream"):
+        # instead of records, what the search would run on
+        body = await summary(admin_user, path)
+        assert body["query"]["formats"] == [sample_format.id, unentitled.id]
+        assert body["formats"] == [
+            {"id": sample_format.id, "name": sample_format.name},
+            {"id": unentitled.id, "name": unentitled.name},
+        ]
+        assert body["columns"] == {
+            "DatetimeColumn": "Datetime",
+            "NumericColumn": "Number",
+            "StringColumn": "String",
+        }
+        # formats normal_user can't read are left out
+        body = await summary(normal_user, path)
+        assert body["formats"] == [
+            {"id": sample_format.id, "name": sample_format.name}
+        ]
+        assert body["columns"] == {
+            "NumericColumn": "Number",
+            "StringColumn": "String",
+        }
+
+    # invalid queries fail like they would without debug
+    query.query[0].args.append(repoclient.Column(column="DatetimeColumn") == "x")
+    response = await api_client.post(
+        "/record/filter",
+        json=query.model_dump(by_alias=True),
+        headers=normal_user.bearer,
+        params={"debug": "true"},
+    )
+    assert response.status_code == 400
+    assert response.json()["code"] == "REPO-1008"
+    await entitlement.delete(api_client, admin_user)
+    await unentitled.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_export_upload_session(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    data = [{"NumericColumn": i, "StringColumn": f"row {i}"} for i in range(50)]
+    upload = await sample_format.upload_data(api_client, admin_user, data)
+    # records from other upload sessions must be left out
+    await sample_format.upload_data(api_client, admin_user, data)
+
+    csv = (await upload.export(api_client, admin_user)).decode().splitlines()
+    assert csv[0] == "ID,FormatId,UploadSessionId,CreatedAt,NumericColumn,StringColumn"
+    assert len(csv) == 51, "wrong record count"
+    assert all(f",{upload.id}," in line for line in csv[1:])
+
+    ndjson = await upload.export(api_client, admin_user, "ndjson")
+    records = [orjson.loads(line) for line in ndjson.splitlines()]
+    assert sorted(record["data"]["NumericColumn"] for record in records) == list(
+        range(50)
+    )
+
+    # normal users need read access to the session's format
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await upload.export(api_client, normal_user)
+    assert exc.value.error.code == "REPO-1004"
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.WRITE],
+    ).create(api_client, admin_user)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await upload.export(api_client, normal_user)
+    assert exc.value.error.code == "REPO-2003"
+    await entitlement.delete(api_client, admin_user)
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    # rows can come in any order
+    normal_csv = (await upload.export(api_client, normal_user)).decode().splitlines()
+    assert normal_csv[0] == csv[0]
+    assert sorted(normal_csv[1:]) == sorted(csv[1:])
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_stream_columns_from_query(
+    api_client, admin_user: repoclient.User, sample_format: repoclient.Format
+):
+    other = await repoclient.Format(
+        name=get_random_string(12),
+        description="extra columns",
+        schema=[
+            repoclient.ColumnSchema.numeric("NumericColumn"),
+            repoclient.ColumnSchema.string("OtherColumn"),
+        ],
+    ).create(api_client, admin_user)
+    await sample_format.upload_data(
+        api_client, admin_user, [{"NumericColumn": 1, "StringColumn": "a"}]
+    )
+    await other.upload_data(
+        api_client, admin_user, [{"NumericColumn": 2, "OtherColumn": "b"}]
+    )
+    fixed = ["ID", "FormatId", "UploadSessionId", "CreatedAt"]
+
+    async def get_header(query: repoclient.Query, **kwargs) -> list[str]:
+        buffer = BytesIO()
+        await sample_format.get_data_csv_stream(
+            api_client, admin_user, query, buffer, **kwargs
+        )
+        header = buffer.getvalue().decode().splitlines()[0]
+        return [column.strip('"') for column in header.split(",")]
+
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[repoclient.Column(column="NumericColumn") >= 0],
+    )
+    query = repoclient.Query(format_id=[sample_format.id, other.id], query=[group])
+    header = await get_header(query, columns_from_query=True)
+    assert header == fixed + ["NumericColumn"]
+    # by default, every column of the searched formats is exported
+    header = await get_header(query)
+    assert header[:4] == fixed
+    assert sorted(header[4:]) == ["NumericColumn", "OtherColumn", "StringColumn"]
+    # without search arguments there's nothing to restrict the columns to
+    query = repoclient.Query(format_id=[sample_format.id, other.id])
+    header = await get_header(query, columns_from_query=True)
+    assert sorted(header[4:]) == ["NumericColumn", "OtherColumn", "StringColumn"]
+    await other.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_stream_selected_columns(
+    api_client, admin_user: repoclient.User, sample_format: repoclient.Format
+):
+    other = await repoclient.Format(
+        name=get_random_string(12),
+        description="extra columns",
+        schema=[
+            repoclient.ColumnSchema.numeric("NumericColumn"),
+            repoclient.ColumnSchema.string("OtherColumn"),
+        ],
+    ).create(api_client, admin_user)
+    await sample_format.upload_data(
+        api_client, admin_user, [{"NumericColumn": 1, "StringColumn": "a"}]
+    )
+    await other.upload_data(
+        api_client, admin_user, [{"NumericColumn": 2, "OtherColumn": "b"}]
+    )
+    query = repoclient.Query(format_id=[sample_format.id, other.id])
+
+    async def export(**kwargs) -> list[list[str]]:
+        buffer = BytesIO()
+        await sample_format.get_data_csv_stream(
+            api_client, admin_user, query, buffer, **kwargs
+        )
+        return [line.split(",") for line in buffer.getvalue().decode().splitlines()]
+
+    # the fixed columns come first, then the selected ones in the given order
+    rows = await export(columns=["OtherColumn", "NumericColumn"])
+    fixed = ["ID", "FormatId", "UploadSessionId", "CreatedAt"]
+    assert rows[0] == fixed + ["OtherColumn", "NumericColumn"]
+    assert sorted(row[4:] for row in rows[1:]) == [["", "1"], ["b", "2"]]
+    rows = await export(columns=["StringColumn"])
+    assert rows[0] == fixed + ["StringColumn"]
+    assert sorted(row[4:] for row in rows[1:]) == [[""], ["a"]]
+
+    for columns, reason in (
+        (["NumericColumn", "Nope", "numericcolumn"], "'Nope', 'numericcolumn'"),
+        (["NumericColumn", ""], "''"),
+        (["NumericColumn", "NumericColumn"], "more than once"),
+    ):
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await export(columns=columns)
+        assert exc.value.error.code == "REPO-1008"
+        assert reason in exc.value.error.detail
+    # columns of other formats can't be selected
+    query = repoclient.Query(format_id=[sample_format.id])
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await export(columns=["OtherColumn"])
+    assert exc.value.error.code == "REPO-1008"
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await export(columns=["NumericColumn"], columns_from_query=True)
+    assert exc.value.error.code == "REPO-1005"
+    await other.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_stream_manifest(
+    api_client, admin_user: repoclient.User, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "manifest"} for i in range(0, 25)]
+    await sample_format.upload_data(api_client, admin_user, data)
+    query = repoclient.Query(format_id=[sample_format.id])
+
+    buffer = BytesIO()
+    await sample_format.get_data_csv_stream(
+        api_client, admin_user, query, buffer, manifest=True
+    )
+    body = buffer.getvalue()
+    content, _, trailer = body.rpartition(b"#manifest ")
+    assert trailer.endswith(b"\n")
+    manifest = orjson.loads(trailer)
+    assert manifest["formats"] == [sample_format.id]
+    assert manifest["columns"][:4] == [
+        {"name": "ID", "kind": "Number"},
+        {"name": "FormatId", "kind": "Number"},
+        {"name": "UploadSessionId", "kind": "Number"},
+        {"name": "CreatedAt", "kind": "Datetime"},
+    ]
+    assert sorted(column["name"] for column in manifest["columns"][4:]) == [
+        "NumericColumn",
+        "StringColumn",
+    ]
+    lines = content.decode().splitlines()
+    assert lines[0] == ",".join(column["name"] for column in manifest["columns"])
+    assert manifest["rows"] == len(lines) - 1
+    assert manifest["sha256"] == hashlib.sha256(content).hexdigest()
+
+    # without it, there's no trailer
+    plain = BytesIO()
+    await sample_format.get_data_csv_stream(api_client, admin_user, query, plain)
+    assert b"#manifest" not in plain.getvalue()
+
+
+@pytest.mark.asyncio
+async def test_stream_pipeline_stats(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    data = [{"NumericColumn": i, "StringColumn": "stats"} for i in range(0, 50)]
+    await sample_format.upload_data(api_client, admin_user, data)
+    await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[repoclient.EntitlementAccessLevel.READ],
+    ).create(api_client, admin_user)
+    json_query = repoclient.Query(format_id=[sample_format.id]).model_dump(
+        by_alias=True
+    )
+
+    async def stream(user: repoclient.User, **params):
+        return await api_client.post(
+            "/record/filter-stream",
+            json=json_query,
+            params={"pipelineStats": "true", **params},
+            headers=user.bearer,
+        )
+
+    response = await stream(admin_user, streams=2, transformWorkers=2, manifest="true")
+    assert response.status_code == 200
+    *lines, manifest, trailer = response.text.splitlines()
+    assert manifest.startswith("#manifest ")
+    assert trailer.startswith("#pipelineStats ")
+    stats = orjson.loads(trailer.removeprefix("#pipelineStats "))
+    assert [worker["worker"] for worker in stats["streams"]] == [0, 1]
+    assert [worker["worker"] for worker in stats["transformWorkers"]] == [0, 1]
+    assert sum(worker["items"] for worker in stats["streams"]) == len(lines) - 1
+    assert sum(worker["items"] for worker in stats["transformWorkers"]) == 50
+    for queue in ("dbQueue", "resultQueue"):
+        assert stats[queue]["capacity"] > 0
+        assert stats[queue]["maxLen"] <= stats[queue]["capacity"]
+    # superusers only
+    response = await stream(normal_user)
+    assert response.status_code == 403
+
+
+@pytest.mark.asyncio
+async def test_stream_rate_limit(api_client, normal_user):
+    # normal users get MAX_SSE_CONNECTIONS_PER_USER (2) concurrent event streams
+    url = "/upload_session/events"
+    headers = normal_user.bearer
+    async with api_client.stream("GET", url, headers=headers) as first:
+        assert first.status_code == 200
+        assert first.headers.get("x-ratelimit-limit") == "2"
+        assert first.headers.get("x-ratelimit-remaining") == "1"
+        async with api_client.stream("GET", url, headers=headers) as second:
+            assert second.status_code == 200
+            assert second.headers.get("x-ratelimit-remaining") == "0"
+
+            response = await api_client.get(url, headers=headers)
+            assert response.status_code == 429
+            assert response.headers.get("x-ratelimit-remaining") == "0"
+            assert int(response.headers.get("retry-after")) > 0
+            with pytest.raises(repoclient.RepositoryException) as exc:
+                RepositoryError.verify_raise_conditionally(response)
+            assert exc.value.error.code == "REPO-3001"
+            assert exc.value.error.meta["limit"] == 2
+            assert exc.value.error.meta["inUse"] == 2
+            assert exc.value.error.meta["retryAfter"] > 0
+
+
+def split_csv_records(csv: str) -> tuple[str, list[str]]:
+    """Split a CSV export into its header and its records (without their fixed
+    columns), which may span multiple lines."""
+    header, body = csv.split("\n", 1)
+    return header, re.split(r"(?m)^\d+,\d+,\d+,[^,\n]+Z,", body)[1:]
+
+
+@pytest.mark.asyncio
+async def test_export_quoting(api_client, admin_user):
+    fmt = await repoclient.Format(
+        name=get_random_string(10),
+        description="names and values that need quoting",
+        schema=[
+            repoclient.ColumnSchema.string("Name"),
+            repoclient.ColumnSchema.numeric("Amount, EUR"),
+            repoclient.ColumnSchema.string('Say "hi"'),
+            repoclient.ColumnSchema.datetime("When"),
+            repoclient.ColumnSchema.string("Note"),
+        ],
+    ).create(api_client, admin_user)
+    when = "2024-01-02T03:04:05Z"
+    data = [
+        {
+            "Name": "plain",
+            "Amount, EUR": 1.5,
+            'Say "hi"': 'he said "hi"',
+            "When": when,
+            "Note": "two\nlines",
+        },
+        {
+            "Name": "with, comma",
+            "Amount, EUR": -2,
+            'Say "hi"': "",
+            "When": when,
+            "Note": "back\\slash and ünïcode",
+        },
+        {
+            "Name": " spaced ",
+            "Amount, EUR": 0,
+            'Say "hi"': "'single'",
+            "When": when,
+            "Note": "tab\there",
+        },
+    ]
+    upload = await fmt.upload_data(api_client, admin_user, data)
+    csv = (await upload.export(api_client, admin_user)).decode()
+
+    fixture = Path(__file__).parent / "fixtures" / "export_quoting.csv"
+    expected_header, expected = split_csv_records(fixture.read_text(encoding="utf-8"))
+    header, records = split_csv_records(csv)
+    assert header == expected_header
+    # rows can come in any order
+    assert sorted(records) == sorted(expected)
+    await fmt.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_shared_saved_search_entitlements(
+    api_client,
+    admin_user: repoclient.User,
+    normal_user: repoclient.User,
+    sample_format: repoclient.Format,
+):
+    other_format = await repoclient.Format(
+        name=get_random_string(12),
+        description="only readable by second_user",
+        schema=[repoclient.ColumnSchema.numeric("NumericColumn")],
+    ).create(api_client, admin_user)
+    row = {"NumericColumn": 1, "StringColumn": "abc"}
+    await sample_format.upload_data(api_client, admin_user, [row] * 3)
+    await other_format.upload_data(api_client, admin_user, [{"NumericColumn": 1}] * 2)
+    second_user = repoclient.User(
+        username="test_" + get_random_string(20), password="random"
+    )
+    await admin_user.create_user(api_client, second_user)
+    second_user = await second_user.login(api_client)
+    entitlements = [
+        await repoclient.FormatEntitlement(
+            user_id=user.id,
+            format_id=fmt.id,
+            access=[repoclient.EntitlementAccessLevel.READ],
+        ).create(api_client, admin_user)
+        for user, fmt in [
+            (normal_user, sample_format),
+            (second_user, sample_format),
+            (second_user, other_format),
+        ]
+    ]
+    argument = {
+        "column": "NumericColumn",
+        "comparisonOperator": "gte",
+        "compareAgainst": 0,
+    }
+    query = {
+        "formats": [sample_format.id, other_format.id],
+        "query": [{"args": [argument]}],
+    }
+    response = await api_client.post(
+        "/record/saved",
+        json={"name": get_random_string(12), "query": query, "shared": True},
+        headers=admin_user.bearer,
+    )
+    assert response.status_code == 201
+    saved_id = response.json()["id"]
+
+    async def execute(user: repoclient.User) -> int:
+        response = await api_client.post(
+            f"/record/saved/{saved_id}/execute",
+            headers=user.bearer,
+            params={"count": "true"},
+        )
+        assert response.status_code == 200
+        return int(response.headers["repository-item-count"])
+
+    # the same search only returns the records each user can read
+    assert await execute(admin_user) == 5
+    assert await execute(normal_user) == 3
+    assert await execute(second_user) == 5
+    response = await api_client.get(
+        "/record/saved", headers=normal_user.bearer, params={"shared": "true"}
+    )
+    assert saved_id in [search["id"] for search in response.json()]
+    # only the owner can change a shared search
+    response = await api_client.patch(
+        f"/record/saved/{saved_id}", json={"name": "x"}, headers=normal_user.bearer
+    )
+    assert response.status_code == 403
+
+    # without entitlements, only explicit shares make the search visible
+    await entitlements[0].delete(api_client, admin_user)
+    path = f"/record/saved/{saved_id}"
+    response = await api_client.get(path, headers=normal_user.bearer)
+    assert response.status_code == 404
+    response = await api_client.post(
+        f"{path}/shares", json={"userId": normal_user.id}, headers=admin_user.bearer
+    )
+    assert response.status_code == 201
+    response = await api_client.get(f"{path}/shares", headers=admin_user.bearer)
+    assert [share["userId"] for share in response.json()] == [normal_user.id]
+    # ...which never grant access to data
+    response = await api_client.post(f"{path}/execute", headers=normal_user.bearer)
+    assert response.status_code == 400
+    response = await api_client.delete(
+        f"{path}/shares/{normal_user.id}", headers=admin_user.bearer
+    )
+    assert response.status_code == 204
+    response = await api_client.get(path, headers=normal_user.bearer)
+    assert response.status_code == 404
+
+    await api_client.delete(path, headers=admin_user.bearer)
+    for entitlement in entitlements[1:]:
+        await entitlement.delete(api_client, admin_user)
+    await admin_user.delete_user(api_client, second_user)
+    await other_format.delete(api_client, admin_user)
+
+
+@pytest.mark.skipif(not RECORD_ENCRYPTION_KEY, reason="encryption is disabled")
+@pytest.mark.asyncio
+async def test_encrypted_format_round_trip(api_client, admin_user):
+    fmt = await repoclient.Format(
+        name=get_random_string(12),
+        description="encrypted",
+        schema=[
+            repoclient.ColumnSchema.numeric("NumericColumn"),
+            repoclient.ColumnSchema.string("StringColumn"),
+        ],
+        encrypted=True,
+    ).create(api_client, admin_user)
+    data = [{"NumericColumn": i, "StringColumn": f"secret {i}"} for i in range(0, 10)]
+    upload = await fmt.upload_data(api_client, admin_user, data)
+    assert upload.outcome == "Success"
+
+    async def search(json_query: dict, path: str = "/record/filter"):
+        return await api_client.post(path, json=json_query, headers=admin_user.bearer)
+
+    # records are decrypted after they're fetched...
+    json_query = repoclient.Query(query=[], format_id=[fmt.id]).model_dump(
+        by_alias=True
+    )
+    response = await search(json_query)
+    assert response.status_code == 200
+    assert [record["data"] for record in response.json()] == data
+    response = await search(json_query, "/record/filter-stream")
+    assert response.status_code == 200
+    # the streams are interleaved, so the rows aren't in order
+    rows = [line.split(",") for line in response.text.splitlines()[1:]]
+    rows.sort(key=lambda row: int(row[0]))
+    assert [row[-2:] for row in rows] == [[str(i), f"secret {i}"] for i in range(0, 10)]
+    response = await search({"sinceId": int(rows[0][0]) - 1}, "/record/changes")
+    assert response.status_code == 200
+    records = [it for it in response.json()["records"] if it["format_id"] == fmt.id]
+    assert [record["data"] for record in records] == data
+
+    # ...but their data can't be searched
+    group = repoclient.QueryGroup(
+        kind=QueryGroupKind.ALL,
+        args=[repoclient.Column(column="NumericColumn") == 1],
+    )
+    json_query = repoclient.Query(query=[group], format_id=[fmt.id]).model_dump(
+        by_alias=True
+    )
+    for path in ("/record/filter", "/record/filter-stream"):
+        response = await search(json_query, path)
+        assert response.status_code == 400
+        assert response.json()["code"] == "REPO-1008"
+    response = await search(
+        {"column": "NumericColumn", "formats": [fmt.id], "query": []},
+        "/record/column-stats",
+    )
+    assert response.status_code == 400
+    assert response.json()["code"] == "REPO-1008"
+
+    # metadata filters still work
+    response = await search(
+        {"formats": [fmt.id], "query": [], "uploadSession": {"idEq": upload.id}}
+    )
+    assert response.status_code == 200
+    assert len(response.json()) == 10
+    await fmt.delete(api_client, admin_user)
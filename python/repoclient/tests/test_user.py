@@ -1,12 +1,45 @@
+import asyncio
 import repoclient
 import pytest
 import os
+import random
+import time
 
-from .util import get_random_string, api_client, admin_user, normal_user
+from httpx import AsyncClient
+
+from .util import (
+    get_random_string,
+    api_client,
+    admin_user,
+    normal_user,
+    sample_format,
+    totp_code,
+)
 
 ADMIN_USERNAME = os.environ.get("ADMIN_USERNAME", "admin")
 ADMIN_PASSWORD = os.environ.get("ADMIN_PASSWORD", "admin")
 SERVER_MAX_API_KEYS: int = 10
+LOGIN_MAX_FAILED_ATTEMPTS = int(os.environ.get("LOGIN_MAX_FAILED_ATTEMPTS", 10))
+# Set if the server trusts the X-Forwarded-For header of this client
+# (TRUSTED_PROXIES), which the login throttling tests use to fake addresses.
+TRUSTED_PROXY = bool(os.environ.get("TRUSTED_PROXY", False))
+# Set if the server also serves its routes without the /api/v1 prefix.
+LEGACY_ROUTES = os.environ.get("LEGACY_ROUTES", "true") == "true"
+# URL of the admin listener, if the server has one (ADMIN_HTTP_PORT).
+ADMIN_REPOSITORY_URL = os.environ.get("ADMIN_REPOSITORY_URL")
+ADMIN_ENDPOINTS = [
+    ("GET", "/admin/stats"),
+    ("GET", "/admin/diagnostics"),
+    ("GET", "/admin/config"),
+    ("POST", "/upload_session/prune"),
+]
+LIST_ENDPOINTS = [
+    "/user",
+    "/user/api-key",
+    "/format",
+    "/upload_session",
+    "/entitlement",
+]
 
 
 @pytest.mark.asyncio
@@ -52,6 +85,24 @@ async def test_max_api_keys_per_user(api_client, admin_user, normal_user):
     assert exc is not None
 
 
+async def test_max_api_keys_per_user_concurrent(api_client, admin_user, normal_user):
+    # concurrent requests can't all get under the cap
+    results = await asyncio.gather(
+        *(
+            normal_user.create_api_key(api_client)
+            for _ in range(SERVER_MAX_API_KEYS * 2)
+        ),
+        return_exceptions=True,
+    )
+    errors = [r for r in results if isinstance(r, repoclient.RepositoryException)]
+    assert len(errors) == SERVER_MAX_API_KEYS
+    assert all(error.error.code == "REPO-1005" for error in errors)
+    key_count = 0
+    async for _key in normal_user.get_all_keys(api_client):
+        key_count += 1
+    assert key_count == SERVER_MAX_API_KEYS
+
+
 async def test_get_all_keys_for_user(api_client, admin_user, normal_user):
     assert normal_user.is_valid, "user is not valid"
     seen_keys = set()
@@ -64,6 +115,7 @@ async def test_get_all_keys_for_user(api_client, admin_user, normal_user):
     fetch_keys = set()
     async for key in normal_user.get_all_keys(api_client):
         assert key.has_token is False, "token shouldn't be initialized"
+        assert key.stale is False, "freshly created keys shouldn't be stale"
         key_count += 1
         fetch_keys.add(key.id)
     assert fetch_keys == seen_keys
@@ -78,3 +130,556 @@ async def test_create_delete_repeatedly(api_client, admin_user, normal_user):
         api_key = await normal_user.create_api_key(api_client)
         assert api_key.token is not None, "api key failure"
         await api_key.delete_key(api_client)
+
+
+@pytest.mark.asyncio
+async def test_usernames_ignore_case(api_client, admin_user):
+    username = "Test_" + get_random_string(20)
+    new_user = await admin_user.create_user(
+        api_client, repoclient.User(username=username, password="random")
+    )
+    # usernames are stored as entered...
+    assert new_user.username == username
+    # ...but are unique regardless of their case
+    for conflicting in (username.lower(), username.upper()):
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await admin_user.create_user(
+                api_client, repoclient.User(username=conflicting, password="random")
+            )
+        assert exc.value.error.code == "REPO-1001"
+    # and users can log in with any casing
+    for login_username in (username, username.lower(), username.upper()):
+        user = await repoclient.User(
+            username=login_username, password="random"
+        ).login(api_client)
+        assert user.is_valid, "user is not valid"
+    await admin_user.delete_user(api_client, new_user)
+
+
+@pytest.mark.asyncio
+async def test_bulk_create_users(api_client, admin_user, normal_user):
+    username = "test_" + get_random_string(20)
+    users = [
+        repoclient.User(username=username, password="random"),
+        repoclient.User(username="test_" + get_random_string(20), password="random"),
+        # duplicates, regardless of their case
+        repoclient.User(username=username.upper(), password="random"),
+        repoclient.User(username=ADMIN_USERNAME, password="random"),
+    ]
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await normal_user.create_users_bulk(api_client, users)
+    assert exc.value.error.code == "REPO-2004"
+
+    result = await admin_user.create_users_bulk(api_client, users)
+    assert [user["username"] for user in result["created"]] == [
+        users[0].username,
+        users[1].username,
+    ]
+    assert [failure["index"] for failure in result["failed"]] == [2, 3]
+    assert "more than once" in result["failed"][0]["reason"]
+    assert "already exists" in result["failed"][1]["reason"]
+    # the created users can log in
+    user = await users[1].login(api_client)
+    assert user.is_valid, "user is not valid"
+
+    # a single failure creates none of the users
+    fresh = repoclient.User(username="test_" + get_random_string(20), password="random")
+    result = await admin_user.create_users_bulk(
+        api_client, [fresh, users[0]], all_or_nothing=True
+    )
+    assert result["created"] == []
+    assert [failure["index"] for failure in result["failed"]] == [1]
+    with pytest.raises(repoclient.RepositoryException):
+        await fresh.login(api_client)
+
+    for created in (users[0], users[1]):
+        created = await created.login(api_client)
+        await admin_user.delete_user(api_client, created)
+
+
+@pytest.mark.asyncio
+async def test_user_email(api_client, admin_user, normal_user):
+    email = f"{get_random_string(20)}@Example.com"
+    new_user = await admin_user.create_user(
+        api_client,
+        repoclient.User(
+            username="test_" + get_random_string(20), password="random", email=email
+        ),
+    )
+    assert new_user.email == email
+    # emails are unique regardless of their case
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await admin_user.create_user(
+            api_client,
+            repoclient.User(
+                username="test_" + get_random_string(20),
+                password="random",
+                email=email.lower(),
+            ),
+        )
+    assert exc.value.error.code == "REPO-1001"
+    for invalid in ("no-at-sign", "@example.com", "a@b", "a b@example.com"):
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await normal_user.set_email(api_client, invalid)
+        assert exc.value.error.code == "REPO-1003"
+
+    # users can change their own email, as long as nobody else uses it
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await normal_user.set_email(api_client, email.upper())
+    assert exc.value.error.code == "REPO-1001"
+    other_email = f"{get_random_string(20)}@example.org"
+    assert (await normal_user.set_email(api_client, other_email)).email == other_email
+    response = await api_client.get(
+        "/user", headers=admin_user.bearer, params={"emailIlike": email}
+    )
+    assert [user["id"] for user in response.json()] == [new_user.id]
+    assert (await normal_user.set_email(api_client, None)).email is None
+    await admin_user.delete_user(api_client, new_user)
+
+
+@pytest.mark.asyncio
+async def test_user_storage_quota(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    entitlement = await repoclient.FormatEntitlement(
+        user_id=normal_user.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+    usage = await normal_user.get_usage(api_client, normal_user)
+    assert usage == {"records": 0, "bytes": 0, "maxStorageBytes": None, "formats": []}
+
+    sessions = []
+    for size in (3, 5):
+        data = [{"NumericColumn": i, "StringColumn": "x"} for i in range(size)]
+        sessions.append(await sample_format.upload_data(api_client, normal_user, data))
+    # failed uploads don't count
+    with pytest.raises(repoclient.RepositoryException):
+        await sample_format.upload_data(api_client, normal_user, [{"Unknown": 1}])
+    used = sum(session.payload_bytes for session in sessions)
+    usage = await normal_user.get_usage(api_client, admin_user)
+    assert usage["records"] == 8
+    assert usage["bytes"] == used
+    assert usage["formats"] == [
+        {"formatId": sample_format.id, "records": 8, "bytes": used}
+    ]
+
+    # users can't see the usage of others (or the others themselves), nor
+    # change their own quota
+    response = await api_client.get(
+        f"/user/{admin_user.id}/usage", headers=normal_user.bearer
+    )
+    assert response.status_code == 404
+    path = f"/user/{normal_user.id}"
+    response = await api_client.patch(
+        path, json={"maxStorageBytes": None}, headers=normal_user.bearer
+    )
+    assert response.status_code == 403
+
+    response = await api_client.patch(
+        path, json={"maxStorageBytes": -1}, headers=admin_user.bearer
+    )
+    assert response.status_code == 400
+    response = await api_client.patch(
+        path, json={"maxStorageBytes": used + 1}, headers=admin_user.bearer
+    )
+    assert response.json()["maxStorageBytes"] == used + 1
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        data = [{"NumericColumn": 1, "StringColumn": "x"}]
+        await sample_format.upload_data(api_client, normal_user, data)
+    assert exc.value.error.code == "REPO-3002"
+    usage = await normal_user.get_usage(api_client, normal_user)
+    assert usage["bytes"] == used
+    assert usage["maxStorageBytes"] == used + 1
+
+    # without a quota, uploads go through again
+    response = await api_client.patch(
+        path, json={"maxStorageBytes": None}, headers=admin_user.bearer
+    )
+    assert response.json()["maxStorageBytes"] is None
+    await sample_format.upload_data(api_client, normal_user, data)
+    await entitlement.delete(api_client, admin_user)
+
+
+@pytest.mark.asyncio
+async def test_user_transfer(
+    api_client, admin_user, normal_user, sample_format: repoclient.Format
+):
+    users = []
+    for _ in range(2):
+        user = repoclient.User(
+            username="test_" + get_random_string(20), password="random"
+        )
+        users.append(await admin_user.create_user(api_client, user))
+    leaving, successor = users
+    leaving = await leaving.login(api_client)
+    await repoclient.FormatEntitlement(
+        user_id=leaving.id,
+        format_id=sample_format.id,
+        access=[
+            repoclient.EntitlementAccessLevel.READ,
+            repoclient.EntitlementAccessLevel.WRITE,
+        ],
+    ).create(api_client, admin_user)
+    data = [{"NumericColumn": 1, "StringColumn": "transfer"}]
+    session = await sample_format.upload_data(api_client, leaving, data)
+    response = await api_client.post(
+        "/record/saved",
+        json={"name": get_random_string(12), "query": {"query": []}},
+        headers=leaving.bearer,
+    )
+    assert response.status_code == 201
+    saved_id = response.json()["id"]
+
+    # users that still own upload sessions can't be deleted by accident
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await admin_user.delete_user(api_client, leaving)
+    assert exc.value.error.code == "REPO-1006"
+    assert "1 upload sessions" in exc.value.error.detail
+    # only superusers can transfer, and other users don't even exist for
+    # normal users
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await normal_user.transfer_user(api_client, leaving, successor)
+    assert exc.value.error.code == "REPO-1004"
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await admin_user.transfer_user(api_client, leaving, leaving)
+    assert exc.value.error.code == "REPO-1005"
+
+    result = await admin_user.transfer_user(
+        api_client, leaving, successor, saved_searches=True
+    )
+    assert result == {"uploadSessions": 1, "savedSearches": 1}
+    await admin_user.delete_user(api_client, leaving)
+    # the upload is still there, attributed to the successor
+    response = await api_client.get(
+        f"/upload_session/{session.id}", headers=admin_user.bearer
+    )
+    assert response.json()["userId"] == successor.id
+    response = await api_client.get(
+        f"/record/saved/{saved_id}", headers=admin_user.bearer
+    )
+    assert response.json()["userId"] == successor.id
+    await admin_user.delete_user(api_client, successor, force=True)
+
+
+# (method, url, minimum role). Superusers can do everything.
+ROLE_PROTECTED_ENDPOINTS = [
+    ("GET", "/admin/stats", repoclient.UserRole.AUDITOR),
+    ("GET", "/user", repoclient.UserRole.AUDITOR),
+    ("GET", "/format?includeArchived=true", repoclient.UserRole.AUDITOR),
+    ("POST", "/upload_session/prune", repoclient.UserRole.FORMAT_MANAGER),
+    ("GET", "/webhook", repoclient.UserRole.ADMIN),
+]
+if ADMIN_REPOSITORY_URL:
+    # these are only served by the admin listener, see test_admin_listener
+    ROLE_PROTECTED_ENDPOINTS = [
+        (method, url, role)
+        for (method, url, role) in ROLE_PROTECTED_ENDPOINTS
+        if (method, url) not in ADMIN_ENDPOINTS
+    ]
+ROLE_ORDER = list(repoclient.UserRole)
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("role", ROLE_ORDER)
+async def test_user_roles(api_client, admin_user, role: repoclient.UserRole):
+    password = "random"
+    user = await admin_user.create_user(
+        api_client,
+        repoclient.User(
+            username="test_" + get_random_string(20), password=password, role=role
+        ),
+    )
+    assert user.role == role
+    user = await repoclient.User(username=user.username, password=password).login(
+        api_client
+    )
+    for method, url, required in ROLE_PROTECTED_ENDPOINTS:
+        response = await api_client.request(method, url, headers=user.bearer)
+        if ROLE_ORDER.index(role) >= ROLE_ORDER.index(required):
+            assert response.status_code == 200, f"{method} {url} failed for {role}"
+        else:
+            assert response.status_code == 403, f"{method} {url} allowed for {role}"
+
+    # format management
+    can_manage_formats = ROLE_ORDER.index(role) >= ROLE_ORDER.index(
+        repoclient.UserRole.FORMAT_MANAGER
+    )
+    create_format = repoclient.Format(
+        name=get_random_string(12),
+        description="created by a role",
+        schema=[repoclient.ColumnSchema.numeric("NumericColumn")],
+    ).create(api_client, user)
+    if can_manage_formats:
+        fmt = await create_format
+        await fmt.delete(api_client, user)
+    else:
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await create_format
+        assert exc.value.error.code == "REPO-2004"
+
+    # user management, which never extends to superusers
+    other = repoclient.User(username="test_" + get_random_string(20), password="x")
+    if role is repoclient.UserRole.ADMIN:
+        other = await user.create_user(api_client, other)
+        await user.delete_user(api_client, other)
+        for superuser_only in (
+            user.create_user(
+                api_client,
+                repoclient.User(
+                    username="test_" + get_random_string(20),
+                    password=password,
+                    is_superuser=True,
+                ),
+            ),
+            user.delete_user(api_client, admin_user),
+        ):
+            with pytest.raises(repoclient.RepositoryException) as exc:
+                await superuser_only
+            assert exc.value.error.code == "REPO-2004"
+    else:
+        response = await api_client.post(
+            "/user", headers=user.bearer, json=other.model_dump(exclude_none=True)
+        )
+        assert response.status_code == 403
+
+    # only admins can change roles, even their own
+    response = await api_client.patch(
+        f"/user/{user.id}", headers=user.bearer, json={"role": "Admin"}
+    )
+    assert response.status_code == (200 if role is repoclient.UserRole.ADMIN else 403)
+    await admin_user.delete_user(api_client, user)
+
+
+async def test_totp_login(api_client, admin_user):
+    password = "Password1234"
+    user = await admin_user.create_user(
+        api_client,
+        repoclient.User(username="test_" + get_random_string(20), password=password),
+    )
+    credentials = repoclient.User(username=user.username, password=password)
+    user = await credentials.login(api_client)
+    assert user.totp_enabled is False
+    try:
+        enrollment = await user.enroll_totp(api_client)
+    except repoclient.RepositoryException as exc:
+        await admin_user.delete_user(api_client, user)
+        pytest.skip(f"TOTP_ENCRYPTION_KEY isn't set: {exc.error.detail}")
+    assert enrollment.secret in enrollment.provisioning_uri
+    assert len(set(enrollment.recovery_codes)) == 10
+
+    # 2FA isn't enabled until the enrollment is confirmed
+    await credentials.login(api_client)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await user.confirm_totp(api_client, "000000")
+    assert exc.value.error.code == "REPO-2009"
+    # codes are checked against a fixed clock: the server accepts the codes of the
+    # steps right before and after the current one, but not older ones.
+    now = time.time()
+    await user.confirm_totp(api_client, totp_code(enrollment.secret, now))
+    assert user.totp_enabled is True
+
+    for code, error in (
+        (None, "REPO-2008"),
+        ("12345", "REPO-2009"),
+        (totp_code(enrollment.secret, now - 300), "REPO-2009"),
+        # already used to confirm the enrollment
+        (totp_code(enrollment.secret, now), "REPO-2009"),
+    ):
+        with pytest.raises(repoclient.RepositoryException) as exc:
+            await credentials.login(api_client, totp_code=code)
+        assert exc.value.error.code == error
+    next_code = totp_code(enrollment.secret, now + 30)
+    assert (await credentials.login(api_client, totp_code=next_code)).is_valid
+    # codes can't be replayed
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await credentials.login(api_client, totp_code=next_code)
+    assert exc.value.error.code == "REPO-2009"
+
+    # recovery codes work (in any case and without dashes), but only once
+    recovery_code = enrollment.recovery_codes[0]
+    user = await credentials.login(api_client, totp_code=recovery_code)
+    with pytest.raises(repoclient.RepositoryException) as exc:
+        await credentials.login(
+            api_client, totp_code=recovery_code.replace("-", "").lower()
+        )
+    assert exc.value.error.code == "REPO-2009"
+    recovery_code = enrollment.recovery_codes[1].replace("-", "").lower()
+    user = await credentials.login(api_client, totp_code=recovery_code)
+
+    # API keys don't need a second factor
+    api_key = await user.create_api_key(api_client)
+    key_user = repoclient.User.from_api_key(api_key.token)
+    response = await api_client.get("/user/self", headers=key_user.bearer)
+    assert response.status_code == 200
+    assert response.json()["totpEnabled"] is True
+    await admin_user.delete_user(api_client, user)
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize(
+    "scopes,can_write",
+    [(None, True), (["read"], False), (["write"], True), (["read", "write"], True)],
+)
+async def test_token_scopes(
+    api_client, admin_user, sample_format: repoclient.Format, scopes, can_write
+):
+    user = await repoclient.User(
+        username=ADMIN_USERNAME, password=ADMIN_PASSWORD
+    ).login(api_client, scopes=scopes)
+    # every token can read, including with read-only POSTs
+    response = await api_client.get("/format", headers=user.bearer)
+    assert response.status_code == 200
+    response = await api_client.post(
+        "/format/batch", json={"ids": [sample_format.id]}, headers=user.bearer
+    )
+    assert response.status_code == 200
+
+    body = {"name": get_random_string(12), "query": {"query": []}}
+    response = await api_client.post("/record/saved", json=body, headers=user.bearer)
+    if not can_write:
+        # even superusers can't write with a read-only token
+        assert response.status_code == 403
+        assert response.json()["code"] == "REPO-2010"
+        response = await api_client.patch(
+            f"/format/{sample_format.id}",
+            json={"description": "read-only"},
+            headers=user.bearer,
+        )
+        assert response.json()["code"] == "REPO-2010"
+        response = await api_client.delete(
+            f"/format/{sample_format.id}", headers=user.bearer
+        )
+        assert response.json()["code"] == "REPO-2010"
+        return
+    assert response.status_code == 201
+    saved_id = response.json()["id"]
+    response = await api_client.delete(f"/record/saved/{saved_id}", headers=user.bearer)
+    assert response.status_code == 204
+
+
+@pytest.mark.asyncio
+async def test_token_scopes_invalid(api_client):
+    user = repoclient.User(username=ADMIN_USERNAME, password=ADMIN_PASSWORD)
+    for scopes in ([], ["admin"]):
+        with pytest.raises(repoclient.RepositoryException):
+            await user.login(api_client, scopes=scopes)
+
+
+def random_client_ip() -> str:
+    # TEST-NET-3 (RFC 5737)
+    return f"203.0.113.{random.randint(1, 254)}"
+
+
+async def login_from(api_client, client_ip: str, password: str):
+    return await api_client.post(
+        "/login",
+        headers={"X-Forwarded-For": client_ip},
+        json={"username": ADMIN_USERNAME, "password": password},
+    )
+
+
+@pytest.mark.skipif(not TRUSTED_PROXY, reason="the server doesn't trust this client")
+async def test_login_throttling(api_client):
+    client_ip, other_ip = random.sample([f"198.51.100.{i}" for i in range(1, 255)], 2)
+    # a burst of failures from one address...
+    for _ in range(LOGIN_MAX_FAILED_ATTEMPTS):
+        response = await login_from(api_client, client_ip, "wrong password")
+        assert response.status_code == 401
+    # ...blocks it, even with the right password
+    for password in ("wrong password", ADMIN_PASSWORD):
+        response = await login_from(api_client, client_ip, password)
+        assert response.status_code == 429
+        assert response.json()["code"] == "REPO-3001"
+        assert int(response.headers["Retry-After"]) > 0
+    # a spoofed address before the real one doesn't help
+    response = await login_from(api_client, f"{other_ip}, {client_ip}", ADMIN_PASSWORD)
+    assert response.status_code == 429
+    # but other addresses are independent
+    response = await login_from(api_client, other_ip, ADMIN_PASSWORD)
+    assert response.status_code == 200
+    response = await login_from(api_client, random_client_ip(), "wrong password")
+    assert response.status_code == 401
+
+
+@pytest.mark.skipif(ADMIN_REPOSITORY_URL, reason="served by the admin listener")
+async def test_diagnostics(api_client, admin_user, normal_user):
+    response = await api_client.get("/admin/diagnostics", headers=admin_user.bearer)
+    assert response.status_code == 200
+    diagnostics = response.json()
+    pool = diagnostics["databasePool"]
+    assert pool["inUse"] + pool["idle"] == pool["size"] <= pool["maxConnections"]
+    assert diagnostics["uptimeSeconds"] >= 0
+    assert diagnostics["workers"]["httpWorkers"] > 0
+    assert "DATABASE_URL" not in response.text and "postgres://" not in response.text
+    response = await api_client.get("/admin/diagnostics", headers=normal_user.bearer)
+    assert response.status_code == 403
+
+
+@pytest.mark.skipif(not ADMIN_REPOSITORY_URL, reason="the server has no admin listener")
+async def test_admin_listener(api_client, admin_user):
+    async with AsyncClient(base_url=ADMIN_REPOSITORY_URL) as admin_client:
+        for method, url in ADMIN_ENDPOINTS:
+            response = await api_client.request(method, url, headers=admin_user.bearer)
+            assert response.status_code == 404, f"{method} {url} is public"
+            response = await admin_client.request(
+                method, url, headers=admin_user.bearer
+            )
+            assert response.status_code == 200, f"{method} {url} failed"
+        # the admin listener only serves the admin endpoints
+        response = await admin_client.get("/format", headers=admin_user.bearer)
+        assert response.status_code == 404
+
+
+@pytest.mark.asyncio
+async def test_head_list_endpoints(api_client, admin_user, normal_user):
+    for path in LIST_ENDPOINTS:
+        params = {"perPage": 2, "page": 1, "count": "true"}
+        response = await api_client.get(path, headers=admin_user.bearer, params=params)
+        assert response.status_code == 200
+        head = await api_client.head(path, headers=admin_user.bearer, params=params)
+        assert head.status_code == 200
+        assert head.content == b""
+        for header in [
+            "repository-item-count",
+            "repository-page-count",
+            "repository-current-page-count",
+        ]:
+            assert head.headers[header] == response.headers[header], (path, header)
+        assert int(head.headers["repository-current-page-count"]) == len(
+            response.json()
+        )
+    # same permission checks and pager validation as GET.
+    response = await api_client.head("/user", headers=normal_user.bearer)
+    assert response.status_code == 403
+    response = await api_client.head("/user")
+    assert response.status_code == 401
+    params = {"perPage": 0}
+    response = await api_client.head("/user", headers=admin_user.bearer, params=params)
+    assert response.status_code == 400
+
+
+@pytest.mark.skipif(not LEGACY_ROUTES, reason="the server has no legacy routes")
+@pytest.mark.asyncio
+async def test_versioned_routes(api_client, admin_user, normal_user):
+    for path in LIST_ENDPOINTS + ["/user/self", "/healthcheck", "/does-not-exist"]:
+        params = {"perPage": 2, "page": 1, "count": "true"}
+        for user in (admin_user, normal_user):
+            legacy = await api_client.get(path, headers=user.bearer, params=params)
+            v1 = await api_client.get(
+                f"/api/v1{path}", headers=user.bearer, params=params
+            )
+            assert v1.status_code == legacy.status_code, path
+            assert v1.content == legacy.content, path
+    # the prefix applies to the unauthenticated routes too
+    response = await api_client.post(
+        "/api/v1/login",
+        json={"username": ADMIN_USERNAME, "password": ADMIN_PASSWORD},
+    )
+    assert response.status_code == 200
+    bearer = {"Authorization": f"Bearer {response.json()['token']}"}
+    response = await api_client.get("/api/v1/user/self", headers=bearer)
+    assert response.json()["username"] == ADMIN_USERNAME
@@ -1,4 +1,8 @@
+import base64
+import hashlib
+import hmac
 import string
+import struct
 from io import BytesIO
 from random import choice
 import logging
@@ -29,6 +33,17 @@ def get_random_string(length):
     return "".join(choice(letters) for i in range(length))
 
 
+def totp_code(secret: str, unix_time: float) -> str:
+    """Compute the TOTP code (RFC 6238, SHA1, 6 digits, 30s) of `secret` at
+    `unix_time`."""
+    key = base64.b32decode(secret + "=" * (-len(secret) % 8))
+    digest = hmac.new(key, struct.pack(">Q", int(unix_time) // 30), hashlib.sha1)
+    digest = digest.digest()
+    offset = digest[-1] & 0x0F
+    value = struct.unpack(">I", digest[offset : offset + 4])[0] & 0x7FFFFFFF
+    return f"{value % 1_000_000:06d}"
+
+
 @pytest.fixture
 async def api_client():
     async with AsyncClient(
@@ -64,7 +79,7 @@ async def normal_user(api_client, admin_user):
     except Exception as _:
         pass
     finally:
-        await admin_user.delete_user(api_client, new_user)
+        await admin_user.delete_user(api_client, new_user, force=True)
 
 
 @pytest.fixture
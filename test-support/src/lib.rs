@@ -0,0 +1,292 @@
+//! Helpers for integration tests that run the app against a real database.
+//!
+//! Point `TEST_DATABASE_URL` at a disposable Postgres database: the
+//! migrations are applied to it, and the users and formats created by the
+//! tests aren't cleaned up. Without it, the tests are skipped.
+//!
+//! The app keeps its config and database pool in globals, and the pool is
+//! tied to the runtime it was created on. Tests are plain `#[test]`s that
+//! call [`run`], which runs them on a runtime shared by the whole test binary,
+//! so a file can have as many of them as it needs.
+
+use std::{collections::HashSet, future::Future, sync::OnceLock};
+
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::{header, StatusCode},
+    test::{self, TestRequest},
+    HttpServer,
+};
+use base64::{engine::general_purpose, Engine as _};
+use central_repository_api::{
+    auth::{hashing::UserPassword, jwt::Token},
+    build_app, init, API_V1_PREFIX,
+};
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    conf::DBConfig, sea_orm::TryIntoModel, FormatEntitlementMutation, FormatMutation, UserMutation,
+};
+use entity::{
+    format::{self, ColumnKind},
+    format_entitlement::{self, Access, AccessLevel},
+    user::{self, Role},
+};
+use ring::{
+    rand::{SecureRandom, SystemRandom},
+    signature::Ed25519KeyPair,
+};
+use serde_json::{json, Value};
+use tokio::{
+    runtime::{Builder, Runtime},
+    sync::OnceCell,
+    task::LocalSet,
+};
+use uuid::Uuid;
+
+/// Connection string of the database the tests run against.
+pub const TEST_DATABASE_URL: &str = "TEST_DATABASE_URL";
+
+/// Password of every user created by [`TestContext`].
+pub const TEST_PASSWORD: &str = "TestPassword1234";
+
+static CONTEXT: OnceCell<Option<TestContext>> = OnceCell::const_new();
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Run `test` with the [`TestContext`], or skip it if `TEST_DATABASE_URL`
+/// isn't set.
+///
+/// The runtime (and with it the database pool and the background tasks of
+/// the app) is shared by every test of the binary. Each test runs on its own
+/// `LocalSet`, since the app built by [`TestContext::app`] isn't `Send`.
+pub fn run<F, Fut>(test: F)
+where
+    F: FnOnce(&'static TestContext) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let runtime = RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("cannot start the test runtime")
+    });
+    LocalSet::new().block_on(runtime, async {
+        match TestContext::init().await {
+            Some(ctx) => test(ctx).await,
+            None => eprintln!("{TEST_DATABASE_URL} is not set, skipping"),
+        }
+    });
+}
+
+pub struct TestContext {
+    config: &'static Config,
+}
+
+/// A user created by [`TestContext`], along with a short-lived token.
+pub struct TestUser {
+    pub model: user::Model,
+    pub token: String,
+}
+
+impl TestUser {
+    /// The `Authorization` header for requests made as this user.
+    pub fn auth_header(&self) -> (header::HeaderName, String) {
+        (header::AUTHORIZATION, format!("Bearer {}", self.token))
+    }
+
+    /// A request to `path` (under [`API_V1_PREFIX`]) made as this user.
+    pub fn request(&self, request: TestRequest, path: &str) -> TestRequest {
+        request
+            .uri(&format!("{API_V1_PREFIX}{path}"))
+            .insert_header(self.auth_header())
+    }
+}
+
+/// A unique name, e.g. for users and formats.
+pub fn random_name(prefix: &str) -> String {
+    format!("{prefix}_{}", &Uuid::new_v4().simple().to_string()[..16])
+}
+
+/// A base64-encoded PKCS#8 Ed25519 key, like ED25519_SIGNING_KEY.
+fn ephemeral_signing_key() -> String {
+    let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .expect("cannot generate an Ed25519 key");
+    general_purpose::STANDARD.encode(document.as_ref())
+}
+
+/// A base64-encoded 32-byte key, like RECORD_ENCRYPTION_KEY and TOTP_ENCRYPTION_KEY.
+fn ephemeral_encryption_key() -> String {
+    let mut key = [0; 32];
+    SystemRandom::new()
+        .fill(&mut key)
+        .expect("cannot generate an encryption key");
+    general_purpose::STANDARD.encode(key)
+}
+
+impl TestContext {
+    /// Set up the app against `TEST_DATABASE_URL`, with a freshly generated
+    /// signing key and encryption keys. Returns `None` if `TEST_DATABASE_URL` isn't set. Tests
+    /// should use [`run`] instead, which calls this on the shared runtime.
+    pub async fn init() -> Option<&'static TestContext> {
+        CONTEXT
+            .get_or_init(|| async {
+                let database_url = std::env::var(TEST_DATABASE_URL).ok()?;
+                std::env::set_var("DATABASE_URL", database_url);
+                std::env::set_var("ED25519_SIGNING_KEY", ephemeral_signing_key());
+                std::env::set_var("RECORD_ENCRYPTION_KEY", ephemeral_encryption_key());
+                std::env::set_var("TOTP_ENCRYPTION_KEY", ephemeral_encryption_key());
+                let config = init().await.expect("cannot initialize the app");
+                Some(TestContext { config })
+            })
+            .await
+            .as_ref()
+    }
+
+    /// The app, with both the regular and the administrative endpoints.
+    pub async fn app(
+        &self,
+    ) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>
+    {
+        test::init_service(build_app(self.config, true, true)).await
+    }
+
+    /// Serve the app on a local port, for clients that need a real socket.
+    /// Returns the base URL of the API, e.g. `http://127.0.0.1:1234/api/v1`.
+    /// The server runs until the test binary exits.
+    pub async fn serve(&self) -> String {
+        let config = self.config;
+        let server = HttpServer::new(move || build_app(config, true, true))
+            .workers(1)
+            .disable_signals()
+            .bind(("127.0.0.1", 0))
+            .expect("cannot bind the test server");
+        let address = server.addrs()[0];
+        tokio::spawn(server.run());
+        format!("http://{address}{API_V1_PREFIX}")
+    }
+
+    async fn create(&self, is_superuser: bool, role: Role) -> TestUser {
+        let password = UserPassword::from(TEST_PASSWORD.to_string())
+            .to_hash()
+            .expect("cannot hash the test password");
+        let user = user::Model {
+            username: random_name("test"),
+            password,
+            created_at: chrono::offset::Utc::now(),
+            is_superuser,
+            active: true,
+            role,
+            ..Default::default()
+        };
+        let model = UserMutation::create(DBConfig::get_connection(), user)
+            .await
+            .expect("cannot create the test user");
+        let token = Token::build_from_user(model.clone(), None)
+            .expect("cannot create a token for the test user")
+            .token()
+            .to_string();
+        TestUser { model, token }
+    }
+
+    pub async fn create_superuser(&self) -> TestUser {
+        self.create(true, Role::Admin).await
+    }
+
+    /// A user without any entitlements, see [`Self::grant`].
+    pub async fn create_user(&self) -> TestUser {
+        self.create(false, Role::default()).await
+    }
+
+    /// Same as [`Self::create_user`], with `role`.
+    pub async fn create_user_with_role(&self, role: Role) -> TestUser {
+        self.create(false, role).await
+    }
+
+    /// Create a format with `columns`, as `creator`.
+    pub async fn create_format(
+        &self,
+        creator: &TestUser,
+        columns: &[(&str, ColumnKind)],
+    ) -> format::Model {
+        let schema = columns
+            .iter()
+            .map(|(name, kind)| json!({"name": name, "kind": kind}))
+            .collect::<Vec<_>>();
+        let model = serde_json::from_value(json!({
+            "name": random_name("format"),
+            "description": "created by a test",
+            "schema": schema,
+        }))
+        .expect("invalid test format");
+        FormatMutation::create(DBConfig::get_connection(), model, Some(creator.model.id))
+            .await
+            .expect("cannot create the test format")
+            .try_into_model()
+            .expect("cannot convert the test format")
+    }
+
+    /// Give `user` `access` to `format`.
+    pub async fn grant(
+        &self,
+        user: &TestUser,
+        format: &format::Model,
+        access: &[AccessLevel],
+    ) -> format_entitlement::Model {
+        let model = format_entitlement::Model {
+            created_at: chrono::offset::Utc::now(),
+            user_id: user.model.id,
+            format_id: format.id,
+            access: Access(access.iter().cloned().collect::<HashSet<_>>()),
+            ..Default::default()
+        };
+        FormatEntitlementMutation::create(DBConfig::get_connection(), model, None)
+            .await
+            .expect("cannot create the test entitlement")
+    }
+}
+
+/// Send `request` to `app`, returning the status and the JSON body.
+pub async fn call_json<S, B>(app: &S, request: TestRequest) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(app, request.to_request()).await;
+    let status = response.status();
+    let body = test::read_body(response).await;
+    let body = match body.is_empty() {
+        true => Value::Null,
+        false => serde_json::from_slice(&body).expect("the response isn't JSON"),
+    };
+    (status, body)
+}
+
+/// Upload `records` to `format` as `user` (`POST /record`).
+pub async fn upload<S, B>(
+    app: &S,
+    user: &TestUser,
+    format: &format::Model,
+    records: Value,
+) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let body = json!({"formatId": format.id, "data": records});
+    let request = user.request(TestRequest::post(), "/record").set_json(body);
+    call_json(app, request).await
+}
+
+/// Search records with `query` as `user` (`POST /record/filter`).
+pub async fn search<S, B>(app: &S, user: &TestUser, query: Value) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let request = user
+        .request(TestRequest::post(), "/record/filter")
+        .set_json(query);
+    call_json(app, request).await
+}
@@ -0,0 +1,140 @@
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_test_support::{call_json, run, upload, TestUser};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use serde_json::json;
+use uuid::Uuid;
+
+/// Send the request made by `request` to `path` as every user in `expected`,
+/// checking the status each of them gets.
+async fn assert_statuses<S, B>(
+    app: &S,
+    path: &str,
+    request: impl Fn() -> TestRequest,
+    expected: &[(&TestUser, StatusCode)],
+) where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    for (user, status) in expected {
+        let response = test::call_service(app, user.request(request(), path).to_request()).await;
+        assert_eq!(
+            response.status(),
+            *status,
+            "{path} as {}",
+            user.model.username
+        );
+    }
+}
+
+/// Resources the user can't see are a 404, actions on resources they can see
+/// but aren't allowed to perform are a 403.
+#[test]
+fn access_matrix() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let owner = ctx.create_user().await;
+        let other = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&owner, &format, &[AccessLevel::Read]).await;
+        let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let session_id = &body["uploadSession"]["id"];
+        let missing = Uuid::new_v4();
+        let (ok, forbidden, not_found) =
+            (StatusCode::OK, StatusCode::FORBIDDEN, StatusCode::NOT_FOUND);
+
+        // users
+        let path = format!("/user/{}", owner.model.id);
+        let expected = [(&owner, ok), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, &path, TestRequest::get, &expected).await;
+        let usage = format!("{path}/usage");
+        assert_statuses(&app, &usage, TestRequest::get, &expected).await;
+        let promote = || TestRequest::patch().set_json(json!({"role": "Admin"}));
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, &path, promote, &expected).await;
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+        let transfer = || TestRequest::post().set_json(json!({"to": admin.model.id}));
+        let transfer_path = format!("{path}/transfer");
+        assert_statuses(&app, &transfer_path, transfer, &expected).await;
+        let expected = [(&admin, not_found)];
+        let missing_user = format!("/user/{missing}");
+        assert_statuses(&app, &missing_user, TestRequest::get, &expected).await;
+
+        // api keys
+        let keys = format!("{path}/api-key");
+        let request = owner.request(TestRequest::post(), &keys);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let key = format!("{keys}/{}", body["apiKey"]["id"].as_str().unwrap());
+        let expected = [(&other, not_found)];
+        assert_statuses(&app, &keys, TestRequest::post, &expected).await;
+        assert_statuses(&app, &key, TestRequest::delete, &expected).await;
+        let activate = || TestRequest::patch().set_json(json!({"active": true}));
+        let expected = [(&owner, ok), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, &key, activate, &expected).await;
+        let expected = [(&admin, not_found)];
+        let missing_key = format!("{keys}/{missing}");
+        assert_statuses(&app, &missing_key, TestRequest::delete, &expected).await;
+
+        // formats
+        let path = format!("/format/{}", format.id);
+        let expected = [(&owner, ok), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, &path, TestRequest::get, &expected).await;
+        let describe = || TestRequest::patch().set_json(json!({"description": "updated"}));
+        let expected = [(&owner, forbidden), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, &path, describe, &expected).await;
+        let preview = format!("{path}/prune-preview");
+        assert_statuses(&app, &preview, TestRequest::get, &expected).await;
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+        let expected = [(&admin, not_found)];
+        assert_statuses(&app, "/format/-1", TestRequest::get, &expected).await;
+
+        // records
+        let records = json!({"formatId": format.id, "data": [{"NumericColumn": 2}]});
+        let records = || TestRequest::post().set_json(&records);
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, "/record", records, &expected).await;
+
+        // upload sessions
+        let path = format!("/upload_session/{session_id}");
+        let expected = [(&owner, ok), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, &path, TestRequest::get, &expected).await;
+        let export = format!("{path}/export");
+        assert_statuses(&app, &export, TestRequest::get, &expected).await;
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+
+        // entitlements
+        let key = json!({"userId": owner.model.id, "formatId": format.id});
+        let mut entitlement = key.clone();
+        entitlement["access"] = json!(["read"]);
+        let create = || TestRequest::post().set_json(&entitlement);
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, "/entitlement", create, &expected).await;
+        let update = || TestRequest::patch().set_json(&entitlement);
+        let expected = [(&owner, forbidden), (&other, not_found), (&admin, ok)];
+        assert_statuses(&app, "/entitlement", update, &expected).await;
+        let delete = || TestRequest::delete().set_json(&key);
+        let expected = [(&owner, forbidden), (&other, not_found)];
+        assert_statuses(&app, "/entitlement", delete, &expected).await;
+
+        // and everything the admin can do
+        let expected = [(&admin, ok)];
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+        let expected = [(&admin, StatusCode::NO_CONTENT)];
+        let path = format!("/format/{}", format.id);
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+        let path = format!("/user/{}", owner.model.id);
+        assert_statuses(&app, &path, TestRequest::delete, &expected).await;
+    });
+}
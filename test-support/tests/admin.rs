@@ -0,0 +1,40 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run};
+
+#[test]
+fn admin_config_masks_secrets() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+
+        let (status, body) =
+            call_json(&app, user.request(TestRequest::get(), "/admin/config")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert_eq!(body["code"], "REPO-2004");
+
+        let (status, body) =
+            call_json(&app, admin.request(TestRequest::get(), "/admin/config")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let raw = body.to_string();
+        for key in [
+            "DATABASE_URL",
+            "ED25519_SIGNING_KEY",
+            "RECORD_ENCRYPTION_KEY",
+            "TOTP_ENCRYPTION_KEY",
+        ] {
+            let secret = std::env::var(key).expect("the secret isn't set");
+            assert!(!raw.contains(&secret), "{key} leaked");
+            assert_eq!(body["config"][key], "<SECRET>");
+        }
+        assert!(!raw.contains("postgres://"));
+        assert_eq!(
+            body["config"]["BOOTSTRAP_ADMIN_PASSWORD"],
+            serde_json::Value::Null
+        );
+        assert!(body["config"]["MAX_PAGINATION_SIZE"].is_u64());
+        assert!(body["derived"]["pagination"]["maxPerPage"].is_u64());
+        assert_eq!(body["selfTests"]["jwtKeysLoaded"], true);
+        assert_eq!(body["selfTests"]["databaseReachable"], true);
+    });
+}
@@ -0,0 +1,261 @@
+use std::{error::Error, path::PathBuf};
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use base64::{engine::general_purpose, Engine as _};
+use central_repository_api::admin::run_with_args;
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+    UserQuery,
+};
+use central_repository_test_support::{call_json, random_name, run, upload};
+use chrono::{Duration, Utc};
+use entity::{format::ColumnKind, upload_session, user};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::{json, Value};
+
+/// Run `repository-admin` with `args`, returning what it printed.
+async fn admin_cli(args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let mut out = Vec::new();
+    run_with_args(args, &mut out).await?;
+    Ok(String::from_utf8(out)?)
+}
+
+/// A file holding `contents`, for `--password-file` and `format import`.
+fn temp_file(contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(random_name("repository-admin"));
+    std::fs::write(&path, contents).expect("cannot write the temporary file");
+    path
+}
+
+fn login(username: &str, password: &str) -> TestRequest {
+    TestRequest::post()
+        .uri("/api/v1/login")
+        .set_json(json!({"username": username, "password": password}))
+}
+
+#[test]
+fn user_commands() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let username = random_name("cli");
+        let first = temp_file("FirstPassword1234\n");
+        let first = first.to_str().unwrap();
+        let output = admin_cli(&[
+            "user",
+            "create",
+            &username,
+            "--superuser",
+            "--password-file",
+            first,
+        ])
+        .await
+        .expect("cannot create the user");
+        assert!(
+            output.starts_with(&format!("created user {username:?}")),
+            "{output}"
+        );
+        let created = UserQuery::find_by_username(&username)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(created.is_superuser);
+        let (status, body) = call_json(&app, login(&username, "FirstPassword1234")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let err = admin_cli(&["user", "create", &username, "--password-file", first])
+            .await
+            .expect_err("created the user twice");
+        assert!(err.to_string().contains("already exists"), "{err}");
+
+        let second = temp_file("SecondPassword1234");
+        let second = second.to_str().unwrap();
+        admin_cli(&["user", "set-password", &username, "--password-file", second])
+            .await
+            .expect("cannot set the password");
+        let (status, body) = call_json(&app, login(&username, "SecondPassword1234")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = call_json(&app, login(&username, "FirstPassword1234")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{body}");
+
+        user::Entity::update_many()
+            .col_expr(user::Column::TotpEnabled, Expr::value(true))
+            .filter(user::Column::Id.eq(created.id))
+            .exec(DBConfig::get_connection())
+            .await
+            .unwrap();
+        admin_cli(&["user", "reset-2fa", &username])
+            .await
+            .expect("cannot reset 2FA");
+        let reset = UserQuery::find_by_username(&username)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!reset.totp_enabled);
+
+        // passwords follow the complexity policy
+        let weak = temp_file("weak\n");
+        let weak = weak.to_str().unwrap();
+        let other = random_name("cli");
+        for args in [
+            &[
+                "user",
+                "create",
+                &other,
+                "--superuser",
+                "--password-file",
+                weak,
+            ][..],
+            &["user", "set-password", &username, "--password-file", weak],
+        ] {
+            let err = admin_cli(args).await.expect_err("accepted a weak password");
+            assert!(err.to_string().contains("at least 12 characters"), "{err}");
+        }
+        assert!(UserQuery::find_by_username(&other).await.unwrap().is_none());
+        let (status, body) = call_json(&app, login(&username, "SecondPassword1234")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let missing = random_name("missing");
+        let err = admin_cli(&["user", "set-password", &missing, "--password-file", second])
+            .await
+            .expect_err("set the password of a missing user");
+        assert!(err.to_string().contains("does not exist"), "{err}");
+    });
+}
+
+#[test]
+fn prune_command() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let session_id = body["uploadSession"]["id"].as_i64().unwrap();
+        // past the retention period
+        let created_at = Utc::now()
+            - Duration::minutes(format.retention_period_minutes.into())
+            - Duration::hours(1);
+        upload_session::Entity::update_many()
+            .col_expr(upload_session::Column::CreatedAt, Expr::value(created_at))
+            .filter(upload_session::Column::FormatId.eq(format.id))
+            .exec(DBConfig::get_connection())
+            .await
+            .unwrap();
+
+        let id = format.id.to_string();
+        let session_path = format!("/upload_session/{session_id}");
+        for dry_run in [true, false] {
+            let mut args = vec!["prune", "--format", &id];
+            if dry_run {
+                args.push("--dry-run");
+            }
+            let output = admin_cli(&args).await.expect("cannot prune");
+            let result: Value = serde_json::from_str(&output).expect("the output isn't JSON");
+            assert_eq!(result["formatId"], format.id);
+            assert_eq!(result["deleteCount"], 1);
+            assert_eq!(result["recordCount"], 1);
+            let request = admin.request(TestRequest::get(), &session_path);
+            let (status, body) = call_json(&app, request).await;
+            let expected = match dry_run {
+                true => StatusCode::OK,
+                false => StatusCode::NOT_FOUND,
+            };
+            assert_eq!(status, expected, "{body}");
+        }
+
+        let err = admin_cli(&["prune", "--format", &i32::MAX.to_string()])
+            .await
+            .expect_err("pruned a missing format");
+        assert!(err.to_string().contains("does not exist"), "{err}");
+    });
+}
+
+#[test]
+fn format_export_and_import() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let columns = [
+            ("NumericColumn", ColumnKind::Number),
+            ("StringColumn", ColumnKind::String),
+        ];
+        let format = ctx.create_format(&admin, &columns).await;
+
+        let output = admin_cli(&["format", "export", &format.id.to_string()])
+            .await
+            .expect("cannot export the format");
+        let mut exported: Value = serde_json::from_str(&output).expect("the output isn't JSON");
+        assert_eq!(exported["id"], format.id);
+        exported["name"] = random_name("imported").into();
+        let file = temp_file(&exported.to_string());
+        let output = admin_cli(&["format", "import", file.to_str().unwrap()])
+            .await
+            .expect("cannot import the format");
+        let imported_id = output
+            .trim_end()
+            .rsplit_once("(id=")
+            .and_then(|(_, id)| id.strip_suffix(')'))
+            .and_then(|id| id.parse::<i32>().ok())
+            .unwrap_or_else(|| panic!("unexpected output {output:?}"));
+        assert_ne!(imported_id, format.id);
+
+        let path = format!("/format/{imported_id}");
+        let (status, imported) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{imported}");
+        assert_eq!(imported["name"], exported["name"]);
+        assert_eq!(imported["schema"], exported["schema"]);
+
+        let err = admin_cli(&["format", "export", &i32::MAX.to_string()])
+            .await
+            .expect_err("exported a missing format");
+        assert!(err.to_string().contains("does not exist"), "{err}");
+    });
+}
+
+#[test]
+fn invalid_arguments_are_rejected() {
+    run(|_| async move {
+        for args in [
+            &["user", "create"][..],
+            &["prune", "--format", "first"],
+            &["migrate", "sideways"],
+        ] {
+            let err = admin_cli(args)
+                .await
+                .expect_err("accepted invalid arguments");
+            // clap's own errors, which make the binary exit with status 2
+            assert!(err.to_string().starts_with("error: "), "{args:?}: {err}");
+        }
+    });
+}
+
+#[test]
+fn rotate_signing_key() {
+    run(|_| async move {
+        let output = admin_cli(&["rotate-signing-key"])
+            .await
+            .expect("cannot rotate the key");
+        let variables = output
+            .lines()
+            .map(|line| line.split_once('=').expect("not a variable"))
+            .collect::<Vec<_>>();
+        let [("ED25519_SIGNING_KEY", new), ("ED25519_PREVIOUS_PUBLIC_KEYS", previous)] =
+            variables[..]
+        else {
+            panic!("unexpected output {output:?}");
+        };
+        let current = std::env::var("ED25519_SIGNING_KEY").unwrap();
+        assert_ne!(new, current);
+        let new = general_purpose::STANDARD.decode(new).unwrap();
+        Ed25519KeyPair::from_pkcs8(&new).expect("the new key isn't valid");
+        let current = general_purpose::STANDARD.decode(current).unwrap();
+        let current = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&current).unwrap();
+        // no previous keys are set
+        assert_eq!(
+            previous,
+            general_purpose::STANDARD.encode(current.public_key())
+        );
+    });
+}
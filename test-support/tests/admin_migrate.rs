@@ -0,0 +1,45 @@
+use central_repository_api::admin::run_with_args;
+use central_repository_test_support::run;
+
+// Reverting a migration changes the schema under every other test, hence
+// the binary of its own.
+
+async fn migration_status() -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    run_with_args(["migrate", "status"], &mut out)
+        .await
+        .expect("cannot get the migration status");
+    String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (status, name) = line.split_once('\t').expect("unexpected status line");
+            (status.to_string(), name.to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn migrations_can_be_reverted_and_reapplied() {
+    run(|_| async move {
+        let status = migration_status().await;
+        assert!(!status.is_empty());
+        assert!(
+            status.iter().all(|(status, _)| status == "Applied"),
+            "{status:?}"
+        );
+
+        run_with_args(["migrate", "down", "-n", "1"], &mut Vec::new())
+            .await
+            .expect("cannot revert the last migration");
+        let reverted = migration_status().await;
+        let (last, applied) = reverted.split_last().unwrap();
+        assert_eq!(last.0, "Pending", "{reverted:?}");
+        assert!(applied.iter().all(|(status, _)| status == "Applied"));
+
+        run_with_args(["migrate", "up"], &mut Vec::new())
+            .await
+            .expect("cannot apply the migrations");
+        assert_eq!(migration_status().await, status);
+    });
+}
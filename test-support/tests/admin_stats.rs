@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run, upload};
+use entity::{format::ColumnKind, user::Role};
+use serde_json::json;
+
+const CACHE_SECONDS: u64 = 2;
+
+/// `GET /admin/stats` is for auditors and up, and its database counters are
+/// cached for ADMIN_STATS_CACHE_SECONDS.
+#[test]
+fn stats_are_counted_and_cached() {
+    std::env::set_var("ADMIN_STATS_CACHE_SECONDS", CACHE_SECONDS.to_string());
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let auditor = ctx.create_user_with_role(Role::Auditor).await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let stats = || {
+            let request = auditor.request(TestRequest::get(), "/admin/stats");
+            let app = &app;
+            async move {
+                let (status, body) = call_json(app, request).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                body
+            }
+        };
+
+        let (status, body) =
+            call_json(&app, user.request(TestRequest::get(), "/admin/stats")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+        let before = stats().await;
+        for key in ["databaseSizeBytes", "activeStreams", "activeSseConnections"] {
+            assert!(before[key].is_u64(), "{key} in {before}");
+        }
+        assert_eq!(
+            before["users"],
+            before["activeUsers"].as_u64().unwrap() + before["inactiveUsers"].as_u64().unwrap()
+        );
+        let records = json!([{"NumericColumn": 1}, {"NumericColumn": 2}]);
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        ctx.create_user().await;
+
+        // still cached
+        assert_eq!(stats().await, before);
+
+        tokio::time::sleep(Duration::from_secs(CACHE_SECONDS) + Duration::from_millis(100)).await;
+        let after = stats().await;
+        assert_ne!(after["computedAt"], before["computedAt"]);
+        let delta = |key: &str| after[key].as_u64().unwrap() - before[key].as_u64().unwrap();
+        assert_eq!(delta("users"), 1);
+        assert_eq!(delta("activeUsers"), 1);
+        assert_eq!(delta("inactiveUsers"), 0);
+        assert_eq!(delta("formats"), 0);
+        assert_eq!(delta("uploadSessions"), 1);
+        assert_eq!(delta("records"), 2);
+        assert_eq!(delta("recordsLast24h"), 2);
+        assert_eq!(delta("recordsLast7d"), 2);
+    });
+}
@@ -0,0 +1,86 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, random_name, run};
+use entity::{format::ColumnKind, user::Role};
+use serde_json::json;
+
+/// `createdBy` is always the caller, and can't be changed afterwards.
+#[test]
+fn created_by_is_the_caller() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let manager = ctx.create_user_with_role(Role::FormatManager).await;
+        let user = ctx.create_user().await;
+        let (manager_id, admin_id) = (manager.model.id.to_string(), admin.model.id);
+
+        let request = manager
+            .request(TestRequest::post(), "/format")
+            .set_json(json!({
+                "name": random_name("format"),
+                "description": "audited",
+                "schema": [{"name": "NumericColumn", "kind": ColumnKind::Number}],
+                "createdBy": admin_id,
+            }));
+        let (status, format) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{format}");
+        assert_eq!(format["createdBy"], manager_id.as_str());
+
+        let request = manager
+            .request(TestRequest::post(), "/entitlement")
+            .set_json(json!({
+                "userId": user.model.id,
+                "formatId": format["id"],
+                "access": ["read"],
+                "createdBy": admin_id,
+            }));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["createdBy"], manager_id.as_str());
+
+        // neither the creator nor an admin can rewrite it
+        let path = format!("/format/{}", format["id"]);
+        for caller in [&manager, &admin] {
+            let request = caller
+                .request(TestRequest::patch(), &path)
+                .set_json(json!({"description": "changed", "createdBy": user.model.id}));
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["createdBy"], manager_id.as_str());
+
+            let request = caller
+                .request(TestRequest::patch(), "/entitlement")
+                .set_json(json!({
+                    "userId": user.model.id,
+                    "formatId": format["id"],
+                    "access": ["read", "write"],
+                    "createdBy": user.model.id,
+                }));
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body["createdBy"], manager_id.as_str());
+        }
+
+        // and nobody else can create formats or entitlements on their behalf
+        let request = user
+            .request(TestRequest::post(), "/format")
+            .set_json(json!({
+                "name": random_name("format"),
+                "description": "audited",
+                "schema": [{"name": "NumericColumn", "kind": ColumnKind::Number}],
+                "createdBy": manager_id,
+            }));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+        let path = format!("/format?createdByEq={manager_id}");
+        let (status, body) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let ids = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|format| &format["id"])
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [&format["id"]]);
+    });
+}
@@ -0,0 +1,224 @@
+use std::{collections::HashMap, future::Future, sync::OnceLock};
+
+use central_repository_api::{
+    auth::hashing::UserPassword,
+    user::{bootstrap_superuser, init_bootstrap_superuser},
+};
+use central_repository_config::inner::Config;
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, QuerySelect},
+    SuperuserBootstrap, UserMutation, UserQuery,
+};
+use central_repository_test_support::{random_name, run, TEST_PASSWORD};
+use entity::user;
+use envconfig::Envconfig;
+use futures::future::join_all;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+// The tests below take turns at running without superusers.
+static SUPERUSERS: Mutex<()> = Mutex::const_new(());
+
+/// The BOOTSTRAP_ADMIN_USERNAME of this test binary. The config is read once,
+/// so every test sets the same variables.
+fn bootstrap_username() -> &'static str {
+    static USERNAME: OnceLock<String> = OnceLock::new();
+    USERNAME.get_or_init(|| {
+        let username = random_name("bootstrap");
+        std::env::set_var("BOOTSTRAP_ADMIN_USERNAME", &username);
+        std::env::set_var("BOOTSTRAP_ADMIN_PASSWORD", TEST_PASSWORD);
+        username
+    })
+}
+
+fn user(username: &str) -> user::Model {
+    user::Model {
+        username: username.into(),
+        password: UserPassword::from(TEST_PASSWORD.to_string())
+            .to_hash()
+            .unwrap(),
+        ..Default::default()
+    }
+}
+
+async fn find_by_username(username: &str) -> Option<user::Model> {
+    UserQuery::find_by_username(&username.to_string())
+        .await
+        .unwrap()
+}
+
+/// Run `test` while the database has no superusers, deleting the superusers
+/// it created and restoring the previous ones afterwards. Assert on the
+/// output of `test` rather than inside it, so the superusers are restored
+/// even if the test fails.
+async fn without_superusers<T, Fut>(test: impl FnOnce() -> Fut) -> T
+where
+    Fut: Future<Output = T>,
+{
+    let _turn = SUPERUSERS.lock().await;
+    let db = DBConfig::get_connection();
+    let superusers: Vec<Uuid> = user::Entity::find()
+        .select_only()
+        .column(user::Column::Id)
+        .filter(user::Column::IsSuperuser.eq(true))
+        .into_tuple()
+        .all(db)
+        .await
+        .unwrap();
+    let set_superuser = |is_superuser: bool| {
+        user::Entity::update_many()
+            .col_expr(user::Column::IsSuperuser, Expr::value(is_superuser))
+            .filter(user::Column::Id.is_in(superusers.clone()))
+            .exec(db)
+    };
+    set_superuser(false).await.unwrap();
+    let output = test().await;
+    user::Entity::delete_many()
+        .filter(user::Column::IsSuperuser.eq(true))
+        .exec(db)
+        .await
+        .unwrap();
+    set_superuser(true).await.unwrap();
+    output
+}
+
+/// Remove the bootstrap user created on startup, if there were no superusers.
+async fn delete_bootstrap_user() {
+    if let Some(user) = find_by_username(bootstrap_username()).await {
+        user.delete(DBConfig::get_connection()).await.unwrap();
+    }
+}
+
+#[test]
+fn creates_superuser() {
+    bootstrap_username();
+    run(|_| async move {
+        delete_bootstrap_user().await;
+        let result = without_superusers(|| async {
+            init_bootstrap_superuser()
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok::<_, String>(find_by_username(bootstrap_username()).await)
+        })
+        .await;
+        let user = result.unwrap().expect("the superuser wasn't created");
+        assert!(user.is_superuser);
+        assert!(user.active);
+        UserPassword::verify_password(&TEST_PASSWORD.to_string(), &user.password).unwrap();
+    });
+}
+
+#[test]
+fn skips_when_superuser_exists() {
+    bootstrap_username();
+    run(|ctx| async move {
+        let _turn = SUPERUSERS.lock().await;
+        delete_bootstrap_user().await;
+        ctx.create_superuser().await;
+        init_bootstrap_superuser().await.unwrap();
+        assert!(find_by_username(bootstrap_username()).await.is_none());
+    });
+}
+
+#[test]
+fn refuses_existing_username() {
+    bootstrap_username();
+    run(|ctx| async move {
+        let existing = ctx.create_user().await;
+        let username = existing.model.username.clone();
+        // usernames are unique regardless of case
+        for taken in [username.clone(), username.to_uppercase()] {
+            let (outcome, user) = without_superusers(|| async {
+                let db = DBConfig::get_connection();
+                let outcome = UserMutation::bootstrap_superuser(db, user(&taken)).await;
+                (outcome, find_by_username(&username).await)
+            })
+            .await;
+            match outcome.unwrap() {
+                SuperuserBootstrap::UsernameTaken(user) => assert_eq!(user.id, existing.model.id),
+                outcome => panic!("expected UsernameTaken for {taken:?}, got {outcome:?}"),
+            }
+            let user = user.unwrap();
+            assert!(!user.is_superuser);
+            assert_eq!(user.password, existing.model.password);
+        }
+    });
+}
+
+/// Instances starting at the same time create a single superuser.
+#[test]
+fn concurrent_bootstraps_create_one_superuser() {
+    bootstrap_username();
+    run(|_| async move {
+        let usernames = (0..8).map(|_| random_name("bootstrap")).collect::<Vec<_>>();
+        let outcomes = without_superusers(|| {
+            join_all(usernames.iter().map(|username| {
+                UserMutation::bootstrap_superuser(DBConfig::get_connection(), user(username))
+            }))
+        })
+        .await;
+        let created = outcomes
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(|outcome| matches!(outcome, SuperuserBootstrap::Created(_)))
+            .count();
+        assert_eq!(created, 1);
+    });
+}
+
+/// The bootstrap config of this binary, with `overrides` (`None` removes the
+/// variable).
+fn config_with(overrides: &[(&str, Option<&str>)]) -> Config {
+    let mut env = std::env::vars().collect::<HashMap<_, _>>();
+    for (key, value) in overrides {
+        match value {
+            Some(value) => env.insert(key.to_string(), value.to_string()),
+            None => env.remove(*key),
+        };
+    }
+    Config::init_from_hashmap(&env).unwrap()
+}
+
+/// Startup fails on passwords that don't satisfy the complexity policy, and
+/// no superuser is created.
+#[test]
+fn refuses_weak_passwords() {
+    bootstrap_username();
+    run(|_| async move {
+        let username = random_name("weak");
+        let empty_file = std::env::temp_dir().join(random_name("bootstrap-password"));
+        std::fs::write(&empty_file, "\n").unwrap();
+        let empty_file = empty_file.to_str().unwrap();
+        let mut configs = ["", "short1A", "alllowercase1234", "NoDigitsInThisOne"]
+            .into_iter()
+            .map(|password| {
+                config_with(&[
+                    ("BOOTSTRAP_ADMIN_USERNAME", Some(&username)),
+                    ("BOOTSTRAP_ADMIN_PASSWORD", Some(password)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        configs.push(config_with(&[
+            ("BOOTSTRAP_ADMIN_USERNAME", Some(&username)),
+            ("BOOTSTRAP_ADMIN_PASSWORD", None),
+            ("BOOTSTRAP_ADMIN_PASSWORD_FILE", Some(empty_file)),
+        ]));
+        let results = without_superusers(|| async {
+            let mut results = Vec::new();
+            for config in &configs {
+                let result = bootstrap_superuser(config)
+                    .await
+                    .map_err(|err| err.to_string());
+                results.push((result, find_by_username(&username).await));
+            }
+            results
+        })
+        .await;
+        for (result, user) in results {
+            let err = result.expect_err("started with a weak password");
+            assert!(err.starts_with("bootstrap superuser: password"), "{err}");
+            assert!(user.is_none());
+        }
+    });
+}
@@ -0,0 +1,77 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use serde_json::{json, Value};
+
+const MAX_CHANGES_LIMIT: u64 = 4;
+
+/// `POST /record/changes` pages through the records the caller can read, by
+/// id, up to MAX_CHANGES_LIMIT at a time.
+#[test]
+fn pages_through_readable_records() {
+    std::env::set_var("MAX_CHANGES_LIMIT", MAX_CHANGES_LIMIT.to_string());
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let columns = [("NumericColumn", ColumnKind::Number)];
+        let readable = ctx.create_format(&admin, &columns).await;
+        let hidden = ctx.create_format(&admin, &columns).await;
+        ctx.grant(&user, &readable, &[AccessLevel::Read]).await;
+        for format in [&readable, &hidden, &readable] {
+            let records = (0..3)
+                .map(|n| json!({"NumericColumn": n}))
+                .collect::<Value>();
+            let (status, body) = upload(&app, &admin, format, records).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+
+        let changes = |query: Value| {
+            let request = user
+                .request(TestRequest::post(), "/record/changes")
+                .set_json(query);
+            call_json(&app, request)
+        };
+        // without `limit`, the batches are MAX_CHANGES_LIMIT long
+        let (mut since_id, mut ids) = (0, Vec::new());
+        for (expected, has_more) in [(4, true), (2, false)] {
+            let (status, body) = changes(json!({"sinceId": since_id})).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            let records = body["records"].as_array().unwrap();
+            assert_eq!(records.len(), expected, "{body}");
+            assert_eq!(body["hasMore"], has_more);
+            assert!(
+                records
+                    .iter()
+                    .all(|record| record["format_id"] == readable.id),
+                "{body}"
+            );
+            ids.extend(records.iter().map(|record| record["id"].as_i64().unwrap()));
+            since_id = body["lastId"].as_i64().unwrap();
+            assert_eq!(since_id, *ids.last().unwrap());
+        }
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{ids:?}");
+
+        // nothing new: an empty batch that keeps the checkpoint
+        let (status, body) = changes(json!({"sinceId": since_id})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["records"], json!([]));
+        assert_eq!(body["lastId"], since_id);
+        assert_eq!(body["hasMore"], false);
+
+        let (status, body) = changes(json!({"sinceId": ids[0], "limit": 2})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["lastId"], ids[2]);
+        assert_eq!(body["hasMore"], true);
+
+        // formats the user can't read are left out
+        let (status, body) = changes(json!({"formats": [hidden.id]})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["records"], json!([]));
+
+        for limit in [0, MAX_CHANGES_LIMIT + 1] {
+            let (status, body) = changes(json!({"limit": limit})).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        }
+    });
+}
@@ -0,0 +1,82 @@
+use std::time::Instant;
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{ColumnKindCache, SearchQuery};
+use central_repository_test_support::{call_json, run, search};
+use entity::format::ColumnKind;
+use serde_json::json;
+
+// These run one after the other: every format mutation clears the whole cache.
+
+#[test]
+fn format_mutations_invalidate_the_column_kind_cache() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let columns = [("NumericColumn", ColumnKind::Number)];
+        let format = ctx.create_format(&admin, &columns).await;
+        let warm_up = || async {
+            // archived formats are only searched on request
+            let query = json!({"formats": [format.id], "query": [], "includeArchived": true});
+            let (status, body) = search(&app, &admin, query).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert!(ColumnKindCache::is_cached(&[format.id]));
+        };
+        let path = format!("/format/{}", format.id);
+        for (what, update) in [
+            ("update", json!({"description": "updated by a test"})),
+            ("archive", json!({"archived": true})),
+            ("unarchive", json!({"archived": false})),
+        ] {
+            warm_up().await;
+            let request = admin.request(TestRequest::patch(), &path).set_json(update);
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::OK, "{what}: {body}");
+            assert!(!ColumnKindCache::is_cached(&[format.id]), "{what}");
+        }
+        warm_up().await;
+        let (status, body) = call_json(&app, admin.request(TestRequest::delete(), &path)).await;
+        assert!(status.is_success(), "{body}");
+        assert!(!ColumnKindCache::is_cached(&[format.id]));
+    });
+}
+
+/// Run with `cargo test --test column_cache -- --ignored --nocapture`.
+#[test]
+#[ignore = "benchmark"]
+fn bench_column_kinds() {
+    const FORMATS: usize = 50;
+    const SEARCHES: u32 = 1000;
+    run(|ctx| async move {
+        let admin = ctx.create_superuser().await;
+        let columns = (0..50)
+            .map(|i| (format!("Column{i}"), ColumnKind::Number))
+            .collect::<Vec<_>>();
+        let columns = columns
+            .iter()
+            .map(|(name, kind)| (name.as_str(), kind.clone()))
+            .collect::<Vec<_>>();
+        let mut formats = vec![];
+        for _ in 0..FORMATS {
+            formats.push(ctx.create_format(&admin, &columns).await.id);
+        }
+        let query = SearchQuery::new(vec![]).with_formats(formats);
+        let prepared = query
+            .get_readable_formats_for_user(&admin.model)
+            .await
+            .expect("cannot prepare the search");
+        for cached in [false, true] {
+            let start = Instant::now();
+            for _ in 0..SEARCHES {
+                if !cached {
+                    ColumnKindCache::invalidate();
+                }
+                prepared.summary().expect("invalid search");
+            }
+            println!(
+                "{FORMATS} formats, cached: {cached}: {:?} per search",
+                start.elapsed() / SEARCHES
+            );
+        }
+    });
+}
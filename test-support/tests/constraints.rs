@@ -0,0 +1,101 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_api::error::{APIError, FOREIGN_KEYS};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{ConnectionTrait, DbBackend, DbErr, Statement, TransactionTrait},
+    FormatEntitlementMutation,
+};
+use central_repository_test_support::{call_json, run};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+
+/// The error is a 409 whose body mentions neither `constraint` nor the SQL
+/// that failed. Returns the message.
+fn assert_conflict(error: DbErr, constraint: &str) -> String {
+    let error = APIError::from(error);
+    assert_eq!(error.status_code(), StatusCode::CONFLICT, "{error}");
+    let body = serde_json::to_value(error.outbound()).unwrap();
+    let text = body.to_string();
+    for leak in [
+        constraint,
+        "violates check",
+        "violates foreign key",
+        "INSERT",
+    ] {
+        assert!(!text.contains(leak), "{leak} in {text}");
+    }
+    body["detail"].as_str().unwrap().to_string()
+}
+
+/// Inserting a child whose parent was deleted in the meantime.
+#[test]
+fn deleted_parent_is_a_conflict() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let entitlement = ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+        let path = format!("/user/{}", user.model.id);
+        let (status, body) = call_json(&app, admin.request(TestRequest::delete(), &path)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+
+        let error =
+            FormatEntitlementMutation::create(DBConfig::get_connection(), entitlement, None)
+                .await
+                .unwrap_err();
+        let message = assert_conflict(error, "format_entitlement_user_id_fkey");
+        assert_eq!(
+            message,
+            "Conflicting operation: the relationship between entitlements and users would be broken."
+        );
+    });
+}
+
+#[test]
+fn check_violation_is_a_conflict() {
+    run(|_| async move {
+        // a temporary table keeps the schema untouched, the transaction keeps
+        // both statements on the same connection.
+        let txn = DBConfig::get_connection().begin().await.unwrap();
+        let statement = |sql: &str| Statement::from_string(DbBackend::Postgres, sql);
+        txn.execute(statement(
+            "CREATE TEMPORARY TABLE quota (amount integer CONSTRAINT quota_amount_check CHECK (amount > 0)) ON COMMIT DROP",
+        ))
+        .await
+        .unwrap();
+        let error = txn
+            .execute(statement("INSERT INTO quota VALUES (0)"))
+            .await
+            .unwrap_err();
+        let message = assert_conflict(error, "quota_amount_check");
+        assert!(message.contains("violates a data constraint"), "{message}");
+    });
+}
+
+/// Constraints missing from [`FOREIGN_KEYS`] get a generic message; keep the
+/// table in sync with the schema.
+#[test]
+fn every_foreign_key_is_described() {
+    run(|_| async move {
+        let rows = DBConfig::get_connection()
+            .query_all(Statement::from_string(
+                DbBackend::Postgres,
+                "SELECT conname FROM pg_constraint \
+                WHERE contype = 'f' AND connamespace = current_schema()::regnamespace",
+            ))
+            .await
+            .unwrap();
+        assert!(!rows.is_empty());
+        let missing = rows
+            .iter()
+            .map(|row| row.try_get::<String>("", "conname").unwrap())
+            .filter(|name| !FOREIGN_KEYS.iter().any(|(known, _, _)| known == name))
+            .collect::<Vec<_>>();
+        assert!(
+            missing.is_empty(),
+            "add these foreign keys to FOREIGN_KEYS: {missing:?}"
+        );
+    });
+}
@@ -0,0 +1,108 @@
+use std::{future::poll_fn, pin::Pin, time::Duration};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    http::{header, StatusCode},
+    test::{self, TestRequest},
+    web::Bytes,
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+};
+use central_repository_test_support::{run, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel, user};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+const HEARTBEAT_SECONDS: u64 = 1;
+
+/// The next chunk of the stream, or `None` once it's closed.
+async fn next_chunk(events: &mut BoxBody) -> Option<String> {
+    let chunk = tokio::time::timeout(
+        Duration::from_secs(HEARTBEAT_SECONDS * 5),
+        poll_fn(|cx| Pin::new(&mut *events).poll_next(cx)),
+    )
+    .await
+    .expect("no event or heartbeat in time")?;
+    let chunk: Bytes = chunk.unwrap();
+    Some(String::from_utf8(chunk.to_vec()).unwrap())
+}
+
+/// Skip heartbeats until the next `uploadSession` event, returning its data.
+async fn next_upload_session(events: &mut BoxBody) -> Value {
+    loop {
+        let chunk = next_chunk(events).await.expect("the stream was closed");
+        if chunk.starts_with(':') {
+            continue;
+        }
+        let mut lines = chunk.lines();
+        let id = lines.next().unwrap().strip_prefix("id: ").unwrap();
+        assert_eq!(lines.next(), Some("event: uploadSession"));
+        let data = lines.next().unwrap().strip_prefix("data: ").unwrap();
+        let data: Value = serde_json::from_str(data).unwrap();
+        assert_eq!(data["id"].to_string(), id);
+        return data;
+    }
+}
+
+async fn set_active(user_id: Uuid, active: bool) {
+    user::Entity::update_many()
+        .col_expr(user::Column::Active, Expr::value(active))
+        .filter(user::Column::Id.eq(user_id))
+        .exec(DBConfig::get_connection())
+        .await
+        .unwrap();
+}
+
+/// `GET /upload_session/events` only streams the sessions of readable
+/// formats, is limited to MAX_SSE_CONNECTIONS_PER_USER connections and closes
+/// once the user is deactivated.
+#[test]
+fn streams_readable_upload_sessions() {
+    std::env::set_var("SSE_HEARTBEAT_SECONDS", HEARTBEAT_SECONDS.to_string());
+    std::env::set_var("MAX_SSE_CONNECTIONS_PER_USER", "1");
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let columns = [("NumericColumn", ColumnKind::Number)];
+        let readable = ctx.create_format(&admin, &columns).await;
+        let hidden = ctx.create_format(&admin, &columns).await;
+        ctx.grant(&user, &readable, &[AccessLevel::Read]).await;
+        let connect = || {
+            let request = user.request(TestRequest::get(), "/upload_session/events");
+            test::call_service(&app, request.to_request())
+        };
+
+        let response = connect().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+        let mut events = response.into_body().boxed();
+        // the first heartbeat is sent right away
+        assert_eq!(next_chunk(&mut events).await.unwrap(), ": heartbeat\n\n");
+
+        let response = connect().await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        for format in [&hidden, &readable] {
+            let (status, body) = upload(&app, &admin, format, json!([{"NumericColumn": 1}])).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+        let upload_session = next_upload_session(&mut events).await;
+        assert_eq!(upload_session["formatId"], readable.id);
+        assert_eq!(upload_session["outcome"], "Success");
+
+        set_active(user.model.id, false).await;
+        while next_chunk(&mut events).await.is_some() {}
+        drop(events);
+
+        // closing the stream gives the connection back
+        set_active(user.model.id, true).await;
+        let response = connect().await;
+        assert_eq!(response.status(), StatusCode::OK);
+    });
+}
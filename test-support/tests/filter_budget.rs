@@ -0,0 +1,89 @@
+use actix_web::{
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_test_support::{run, upload};
+use entity::format::ColumnKind;
+use serde_json::{json, Value};
+
+#[test]
+fn filter_pages_are_cut_short_past_the_byte_budget() {
+    // the config is only read once, by the first test of this binary.
+    std::env::set_var("MAX_FILTER_RESPONSE_BYTES", "2000");
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(
+                &admin,
+                &[
+                    ("NumericColumn", ColumnKind::Number),
+                    ("StringColumn", ColumnKind::String),
+                ],
+            )
+            .await;
+        let padding = "x".repeat(500);
+        let records: Vec<Value> = (0..10)
+            .map(|i| json!({"NumericColumn": i, "StringColumn": padding}))
+            .collect();
+        let (status, body) = upload(&app, &admin, &format, json!(records)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let query = json!({"formats": [format.id], "query": []});
+        let mut numbers = vec![];
+        let mut path = "/record/filter?perPage=50".to_string();
+        let mut pages = 0;
+        loop {
+            let request = admin
+                .request(TestRequest::post(), &path)
+                .set_json(query.clone());
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let truncated_after = response
+                .headers()
+                .get("repository-truncated-after-id")
+                .map(|id| id.to_str().unwrap().to_string());
+            let body: Value = test::read_body_json(response).await;
+            let page = body.as_array().expect("not a list of records");
+            pages += 1;
+            // every page keeps at least one record, and stays under the budget
+            // unless it only has that one
+            assert!(!page.is_empty(), "{body}");
+            if page.len() > 1 {
+                let bytes: usize = page.iter().map(|record| record.to_string().len()).sum();
+                assert!(bytes <= 2000, "{body}");
+            }
+            numbers.extend(
+                page.iter()
+                    .map(|record| record["data"]["NumericColumn"].clone()),
+            );
+            let Some(id) = truncated_after else {
+                break;
+            };
+            assert_eq!(page.last().unwrap()["id"].to_string(), id);
+            path = format!("/record/filter?perPage=50&idGt={id}&page=0");
+        }
+        assert!(pages > 1, "the page wasn't truncated");
+        assert_eq!(numbers, (0..10).map(|i| json!(i)).collect::<Vec<_>>());
+
+        // a record bigger than the budget is still sent, on its own
+        let huge = json!([{"NumericColumn": 10, "StringColumn": "x".repeat(3000)}]);
+        let (status, body) = upload(&app, &admin, &format, huge).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let query = json!({"formats": [format.id], "query": [{
+            "conditionKind": "all",
+            "args": [{"column": "NumericColumn", "comparisonOperator": "eq", "compareAgainst": 10}],
+        }]});
+        let request = admin
+            .request(TestRequest::post(), "/record/filter")
+            .set_json(query);
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response
+            .headers()
+            .get("repository-truncated-after-id")
+            .is_none());
+        let body: Value = test::read_body_json(response).await;
+        assert_eq!(body.as_array().map(Vec::len), Some(1), "{body}");
+    });
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test::TestRequest,
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{
+        sea_query::{Expr, LockType},
+        ColumnTrait, EntityTrait, QueryFilter, QuerySelect, TransactionTrait,
+    },
+};
+use central_repository_test_support::{call_json, run, upload, TestUser};
+use entity::{
+    format::{self, ColumnKind},
+    upload_session,
+};
+use futures::future::join_all;
+use serde_json::{json, Value};
+
+/// Set the quotas of `format` (`PATCH /format/{id}`).
+async fn set_quotas<S, B>(app: &S, admin: &TestUser, format: &format::Model, quotas: Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let path = format!("/format/{}", format.id);
+    let request = admin.request(TestRequest::patch(), &path).set_json(quotas);
+    let (status, body) = call_json(app, request).await;
+    assert_eq!(status, StatusCode::OK, "{body}");
+}
+
+fn records(count: usize) -> Value {
+    (0..count).map(|i| json!({"NumericColumn": i})).collect()
+}
+
+#[test]
+fn uploads_over_the_quota_are_rejected() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        set_quotas(&app, &admin, &format, json!({"maxRecords": 3})).await;
+
+        let (status, body) = upload(&app, &admin, &format, records(2)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = upload(&app, &admin, &format, records(2)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(body["uploadSession"]["outcome"], "Error", "{body}");
+        let detail = body["uploadSession"]["detail"].as_str().unwrap();
+        assert!(detail.contains("1 remaining"), "{detail}");
+        // what's left still fits
+        let (status, body) = upload(&app, &admin, &format, records(1)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        // superusers can skip the check
+        let body = json!({"formatId": format.id, "data": records(1)});
+        let request = admin
+            .request(TestRequest::post(), "/record?overrideQuota=true")
+            .set_json(body);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    });
+}
+
+#[test]
+fn the_daily_quota_only_counts_todays_uploads() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        set_quotas(&app, &admin, &format, json!({"maxRecordsPerDay": 2})).await;
+
+        let (status, body) = upload(&app, &admin, &format, records(2)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = upload(&app, &admin, &format, records(1)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+
+        // the day is over for the first upload
+        let yesterday = chrono::offset::Utc::now() - chrono::Duration::days(1);
+        upload_session::Entity::update_many()
+            .col_expr(upload_session::Column::CreatedAt, Expr::value(yesterday))
+            .filter(upload_session::Column::FormatId.eq(format.id))
+            .exec(DBConfig::get_connection())
+            .await
+            .expect("cannot move the upload sessions back");
+        let (status, body) = upload(&app, &admin, &format, records(2)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // the total quota still counts every upload
+        let quotas = json!({"maxRecords": 5, "maxRecordsPerDay": null});
+        set_quotas(&app, &admin, &format, quotas).await;
+        let (status, body) = upload(&app, &admin, &format, records(2)).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        let detail = body["uploadSession"]["detail"].as_str().unwrap();
+        assert!(detail.contains("1 remaining"), "{detail}");
+    });
+}
+
+#[test]
+fn concurrent_uploads_stop_at_the_quota() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        set_quotas(&app, &admin, &format, json!({"maxRecords": 5})).await;
+
+        let uploads = (0..6).map(|_| upload(&app, &admin, &format, records(2)));
+        let statuses = join_all(uploads)
+            .await
+            .into_iter()
+            .map(|(status, _)| status)
+            .collect::<Vec<_>>();
+        let saved = statuses.iter().filter(|s| **s == StatusCode::OK).count();
+        let rejected = statuses
+            .iter()
+            .filter(|s| **s == StatusCode::TOO_MANY_REQUESTS)
+            .count();
+        assert_eq!((saved, rejected), (2, 4), "{statuses:?}");
+    });
+}
+
+#[test]
+fn uploads_to_formats_without_quotas_dont_lock_the_format() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+
+        // the quota check would wait for this lock. The session can still be
+        // created: foreign keys only take a key share lock.
+        let txn = DBConfig::get_connection().begin().await.unwrap();
+        format::Entity::find_by_id(format.id)
+            .lock(LockType::NoKeyUpdate)
+            .one(&txn)
+            .await
+            .expect("cannot lock the format");
+        let uploaded = upload(&app, &admin, &format, records(1));
+        let (status, body) = tokio::time::timeout(Duration::from_secs(10), uploaded)
+            .await
+            .expect("the upload waited for the format");
+        assert_eq!(status, StatusCode::OK, "{body}");
+        txn.rollback().await.unwrap();
+    });
+}
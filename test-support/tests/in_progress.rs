@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{
+        sea_query::{Expr, LockType},
+        ColumnTrait, EntityTrait, QueryFilter, QuerySelect, TransactionTrait,
+    },
+    UploadSessionMutation,
+};
+use central_repository_test_support::{call_json, run, upload};
+use chrono::Utc;
+use entity::{
+    format::{self, ColumnKind},
+    upload_session::{self, OutcomeKind},
+};
+use serde_json::json;
+
+/// Sessions are created in progress, before their records are inserted.
+#[test]
+fn upload_is_in_progress_until_done() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        // the quota check waits for the format row, the session exists by then
+        let request = admin
+            .request(TestRequest::patch(), &format!("/format/{}", format.id))
+            .set_json(json!({"maxRecords": 1000}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let txn = DBConfig::get_connection().begin().await.unwrap();
+        format::Entity::find_by_id(format.id)
+            .lock(LockType::NoKeyUpdate)
+            .one(&txn)
+            .await
+            .expect("cannot lock the format");
+
+        let in_progress = async {
+            let path = format!(
+                "/upload_session?formatIdEq={}&outcomeEq=InProgress",
+                format.id
+            );
+            let started = Instant::now();
+            loop {
+                let (status, body) =
+                    call_json(&app, admin.request(TestRequest::get(), &path)).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                if body.as_array().is_some_and(|sessions| !sessions.is_empty()) {
+                    txn.commit().await.unwrap();
+                    return body[0].clone();
+                }
+                assert!(
+                    started.elapsed() < Duration::from_secs(10),
+                    "no session in progress"
+                );
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        let ((status, body), session) = futures::join!(
+            upload(&app, &admin, &format, json!([{"NumericColumn": 1}])),
+            in_progress
+        );
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(session["outcome"], "InProgress");
+        assert_eq!(body["uploadSession"]["id"], session["id"]);
+        assert_eq!(body["uploadSession"]["outcome"], "Success");
+    });
+}
+
+/// The pruner leaves sessions in progress alone; they're failed once they've
+/// been stuck for too long instead.
+#[test]
+fn stuck_sessions_are_failed_not_pruned() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": 1}])).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            ids.push(body["uploadSession"]["id"].as_i64().unwrap() as i32);
+        }
+        let (done, stuck, recent) = (ids[0], ids[1], ids[2]);
+        let db = DBConfig::get_connection();
+        upload_session::Entity::update_many()
+            .col_expr(
+                upload_session::Column::CreatedAt,
+                Expr::value(Utc::now() - chrono::Duration::hours(2)),
+            )
+            .filter(upload_session::Column::Id.eq(stuck))
+            .exec(db)
+            .await
+            .unwrap();
+        upload_session::Entity::update_many()
+            .col_expr(
+                upload_session::Column::Outcome,
+                Expr::value(OutcomeKind::InProgress),
+            )
+            .filter(upload_session::Column::Id.is_in([stuck, recent]))
+            .exec(db)
+            .await
+            .unwrap();
+
+        // far enough in the future for every session to be past retention
+        let now = Utc::now()
+            + chrono::Duration::minutes(format.retention_period_minutes.into())
+            + chrono::Duration::hours(1);
+        let pruned = UploadSessionMutation::prune_format(db, format, now, false)
+            .await
+            .unwrap();
+        assert_eq!(pruned.delete_count, 1);
+        let outcome = |id: i32| async move {
+            upload_session::Entity::find_by_id(id)
+                .one(db)
+                .await
+                .unwrap()
+                .map(|session| (session.outcome, session.detail))
+        };
+        assert_eq!(outcome(done).await, None);
+
+        UploadSessionMutation::fail_stuck_sessions(db, Utc::now() - chrono::Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome(stuck).await,
+            Some((OutcomeKind::Error, "upload did not finish in time".into()))
+        );
+        assert_eq!(outcome(recent).await.unwrap().0, OutcomeKind::InProgress);
+    });
+}
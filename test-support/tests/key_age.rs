@@ -0,0 +1,73 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_api::auth::jwt::Token;
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{ActiveModelTrait, IntoActiveModel, Set},
+};
+use central_repository_test_support::{call_json, run, TestUser};
+use entity::api_key;
+use serde_json::json;
+use uuid::Uuid;
+
+const MAX_KEY_AGE_DAYS: i64 = 30;
+
+#[test]
+fn strict_key_age_rejects_stale_keys() {
+    // the config is only read once, by the first test of this binary.
+    std::env::set_var("MAX_KEY_AGE_DAYS", MAX_KEY_AGE_DAYS.to_string());
+    std::env::set_var("STRICT_KEY_AGE", "true");
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let user = ctx.create_user().await;
+        let path = format!("/user/{}/api-key", user.model.id);
+        let (status, body) = call_json(&app, user.request(TestRequest::post(), &path)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let key: api_key::Model = serde_json::from_value(body["apiKey"].clone()).unwrap();
+        let key_id: Uuid = body["apiKey"]["id"].as_str().unwrap().parse().unwrap();
+        let fresh = TestUser {
+            model: user.model.clone(),
+            token: body["token"].as_str().unwrap().to_string(),
+        };
+        let (status, body) = call_json(&app, fresh.request(TestRequest::get(), "/format")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // as if it was rotated a day too long ago
+        let mut stale = api_key::Model { id: key_id, ..key }.into_active_model();
+        stale.last_rotated_at =
+            Set(chrono::offset::Utc::now() - chrono::Duration::days(MAX_KEY_AGE_DAYS + 1));
+        let stale = stale
+            .update(DBConfig::get_connection())
+            .await
+            .expect("cannot backdate the key");
+        let response = Token::create_api_key(user.model.clone(), stale)
+            .await
+            .expect("cannot create the key token");
+        let stale = TestUser {
+            model: user.model.clone(),
+            token: serde_json::to_value(response).unwrap()["token"]
+                .as_str()
+                .unwrap()
+                .to_string(),
+        };
+        let (status, body) = call_json(&app, stale.request(TestRequest::get(), "/format")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        assert_eq!(body["code"], "REPO-2011");
+        let (status, body) =
+            call_json(&app, user.request(TestRequest::get(), "/user/api-key")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body[0]["stale"], true, "{body}");
+
+        // rotating it makes it usable again
+        let request = user
+            .request(TestRequest::patch(), &format!("{path}/{key_id}"))
+            .set_json(json!({"rotate": true}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let rotated = TestUser {
+            model: user.model.clone(),
+            token: body["token"].as_str().unwrap().to_string(),
+        };
+        let (status, body) = call_json(&app, rotated.request(TestRequest::get(), "/format")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    });
+}
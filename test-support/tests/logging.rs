@@ -0,0 +1,60 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run};
+use tracing::{
+    field::{Field, Visit},
+    span::{Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, Layer, Registry};
+
+/// Collects the `db_queries` recorded in the request spans.
+#[derive(Clone, Default)]
+struct DbQueries(Arc<Mutex<Vec<u64>>>);
+
+impl DbQueries {
+    fn take(&self) -> Vec<u64> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl<S: Subscriber> Layer<S> for DbQueries {
+    fn on_record(&self, _span: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = self;
+        values.record(&mut visitor);
+    }
+}
+
+impl Visit for &DbQueries {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "db_queries" {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+#[test]
+fn request_spans_count_queries() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let user = ctx.create_user().await;
+        let queries = DbQueries::default();
+        // the app runs on this thread, see `run`.
+        let _guard = Registry::default().with(queries.clone()).set_default();
+
+        let (status, body) = call_json(&app, user.request(TestRequest::get(), "/format")).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let counts = queries.take();
+        assert_eq!(counts.len(), 1, "{counts:?}");
+        // the user and the formats, at least
+        assert!(counts[0] >= 2, "{counts:?}");
+
+        let request = TestRequest::get().uri("/api/v1/healthcheck");
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(queries.take(), [0]);
+    });
+}
@@ -0,0 +1,125 @@
+use actix_web::{
+    http::{header, StatusCode},
+    test::{self, TestRequest},
+};
+use central_repository_dao::{
+    upload_session::ModelAsQuery, GetAllPaginated, PaginationOptions, QueryStats,
+    UploadSessionQuery,
+};
+use central_repository_test_support::{call_json, run, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use serde_json::json;
+
+#[test]
+fn pagination_flow() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        for i in 0..3 {
+            let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": i}])).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+        }
+        let filter: ModelAsQuery =
+            serde_json::from_value(json!({"formatIdEq": format.id})).expect("invalid filter");
+        let pager = |page, count| PaginationOptions {
+            page,
+            per_page: 2,
+            count,
+            count_only: false,
+        };
+
+        // the last page runs both the COUNT and the page query
+        let (result, stats) =
+            QueryStats::track(UploadSessionQuery::get_all(&filter, &pager(1, true), None)).await;
+        let (sessions, num_pages, num_items) = result.expect("cannot list the sessions");
+        assert_eq!((sessions.len(), num_pages, num_items), (1, 2, 3));
+        assert_eq!(stats.queries(), 2);
+        // pages past the last one only run the COUNT query
+        for page in [2, 99_999_999] {
+            let (result, stats) = QueryStats::track(UploadSessionQuery::get_all(
+                &filter,
+                &pager(page, true),
+                None,
+            ))
+            .await;
+            let (sessions, num_pages, num_items) = result.expect("cannot list the sessions");
+            assert_eq!((sessions.len(), num_pages, num_items), (0, 2, 3));
+            assert_eq!(stats.queries(), 1, "page {page}");
+        }
+        // without the count, there's no way to tell
+        let (result, stats) =
+            QueryStats::track(UploadSessionQuery::get_all(&filter, &pager(2, false), None)).await;
+        assert!(result.expect("cannot list the sessions").0.is_empty());
+        assert_eq!(stats.queries(), 1);
+
+        let path = format!("/upload_session?formatIdEq={}&perPage=2", format.id);
+        let request = admin.request(
+            TestRequest::get(),
+            &format!("{path}&page=99999999&count=true"),
+        );
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body, json!([]));
+        // offsets over MAX_PAGINATION_OFFSET need the count
+        let request = admin.request(
+            TestRequest::get(),
+            &format!("{path}&page=99999999&count=false"),
+        );
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "REPO-1009");
+        for page in ["-1", "abc"] {
+            let request = admin.request(TestRequest::get(), &format!("{path}&page={page}"));
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        }
+    });
+}
+
+#[test]
+fn etag_follows_item_edits() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+        let list = |etag: Option<&str>| {
+            let request = user.request(TestRequest::get(), "/format?perPage=10");
+            match etag {
+                Some(etag) => request.insert_header((header::IF_NONE_MATCH, etag)),
+                None => request,
+            }
+        };
+        let etag_of = |response: &actix_web::dev::ServiceResponse<_>| {
+            response
+                .headers()
+                .get(header::ETAG)
+                .expect("no ETag")
+                .to_str()
+                .expect("invalid ETag")
+                .to_string()
+        };
+
+        let response = test::call_service(&app, list(None).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = etag_of(&response);
+        let response = test::call_service(&app, list(Some(&etag)).to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // same ids and count, different contents
+        let request = admin
+            .request(TestRequest::patch(), &format!("/format/{}", format.id))
+            .set_json(json!({"description": "edited by a test"}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let response = test::call_service(&app, list(Some(&etag)).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(etag_of(&response), etag);
+    });
+}
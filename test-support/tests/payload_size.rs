@@ -0,0 +1,71 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_config::inner::Config;
+use central_repository_test_support::{call_json, random_name, run, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use envconfig::Envconfig;
+use serde_json::{json, Value};
+
+const MAX_JSON_PAYLOAD_SIZE: usize = 1_000;
+const RECORD_MAX_JSON_PAYLOAD_SIZE: usize = 20_000;
+
+/// Bodies over the limit get the usual JSON error.
+fn assert_too_large(status: StatusCode, body: &Value, limit: usize) {
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{body}");
+    assert_eq!(body["code"], "REPO-3004", "{body}");
+    let detail = body["detail"].as_str().unwrap();
+    assert!(detail.contains(&limit.to_string()), "{detail}");
+}
+
+/// `/record` takes bodies up to RECORD_MAX_JSON_PAYLOAD_SIZE, everything
+/// else up to MAX_JSON_PAYLOAD_SIZE.
+#[test]
+fn record_routes_take_bigger_bodies() {
+    std::env::set_var("MAX_JSON_PAYLOAD_SIZE", MAX_JSON_PAYLOAD_SIZE.to_string());
+    std::env::set_var(
+        "RECORD_MAX_JSON_PAYLOAD_SIZE",
+        RECORD_MAX_JSON_PAYLOAD_SIZE.to_string(),
+    );
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("StringColumn", ColumnKind::String)])
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Write]).await;
+        let text = |length: usize| "x".repeat(length);
+
+        let request = admin
+            .request(TestRequest::post(), "/format")
+            .set_json(json!({
+                "name": random_name("format"),
+                "description": text(MAX_JSON_PAYLOAD_SIZE),
+                "schema": [{"name": "StringColumn", "kind": "String"}],
+            }));
+        let (status, body) = call_json(&app, request).await;
+        assert_too_large(status, &body, MAX_JSON_PAYLOAD_SIZE);
+        let request = user
+            .request(TestRequest::patch(), &format!("/user/{}", user.model.id))
+            .set_json(json!({"username": text(MAX_JSON_PAYLOAD_SIZE)}));
+        let (status, body) = call_json(&app, request).await;
+        assert_too_large(status, &body, MAX_JSON_PAYLOAD_SIZE);
+
+        let records = json!([{"StringColumn": text(MAX_JSON_PAYLOAD_SIZE * 2)}]);
+        let (status, body) = upload(&app, &user, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let records = json!([{"StringColumn": text(RECORD_MAX_JSON_PAYLOAD_SIZE)}]);
+        let (status, body) = upload(&app, &user, &format, records).await;
+        assert_too_large(status, &body, RECORD_MAX_JSON_PAYLOAD_SIZE);
+
+        // the record limit can't be lower than the global one
+        std::env::set_var(
+            "RECORD_MAX_JSON_PAYLOAD_SIZE",
+            (MAX_JSON_PAYLOAD_SIZE - 1).to_string(),
+        );
+        let error = Config::init_from_env().unwrap().verify().unwrap_err();
+        assert!(
+            error.to_string().contains("RECORD_MAX_JSON_PAYLOAD_SIZE"),
+            "{error}"
+        );
+    });
+}
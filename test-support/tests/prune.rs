@@ -0,0 +1,39 @@
+use actix_web::http::StatusCode;
+use central_repository_dao::{conf::DBConfig, UploadSessionMutation};
+use central_repository_test_support::{run, upload};
+use chrono::{Duration, Utc};
+use entity::format::ColumnKind;
+use serde_json::json;
+
+#[test]
+fn prune_preview_counts_saved_records() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let records = json!([{"NumericColumn": 1}, {"NumericColumn": 2}]);
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        // failed uploads keep the number of records they received
+        let invalid = json!([{"NumericColumn": "x"}, {"NumericColumn": 3}, {"NumericColumn": 4}]);
+        let (status, body) = upload(&app, &admin, &format, invalid).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["uploadSession"]["recordCount"], 3);
+
+        // far enough in the future for both sessions to be past retention
+        let now = Utc::now()
+            + Duration::minutes(format.retention_period_minutes.into())
+            + Duration::hours(1);
+        let db = DBConfig::get_connection();
+        let preview = UploadSessionMutation::preview_prune(db, &format, now)
+            .await
+            .expect("cannot preview the prune");
+        assert_eq!((preview.upload_sessions, preview.records), (2, 2));
+        let pruned = UploadSessionMutation::prune_format(db, format, now, true)
+            .await
+            .expect("cannot dry-run the prune");
+        assert_eq!((pruned.delete_count, pruned.record_count), (2, 2));
+    });
+}
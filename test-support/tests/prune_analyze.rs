@@ -0,0 +1,76 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+};
+use central_repository_test_support::{call_json, run, upload};
+use chrono::{Duration, Utc};
+use entity::{
+    format::{self, ColumnKind},
+    upload_session,
+};
+use serde_json::{json, Value};
+
+const MIN_RECORDS: usize = 3;
+
+/// Move the upload sessions of `format` past its retention period.
+async fn expire(format: &format::Model) {
+    let created_at =
+        Utc::now() - Duration::minutes(format.retention_period_minutes.into()) - Duration::hours(1);
+    upload_session::Entity::update_many()
+        .col_expr(upload_session::Column::CreatedAt, Expr::value(created_at))
+        .filter(upload_session::Column::FormatId.eq(format.id))
+        .exec(DBConfig::get_connection())
+        .await
+        .unwrap();
+}
+
+/// With PRUNE_ANALYZE_AFTER, `POST /upload_session/prune` (format managers
+/// only) reports the analyze in the formats that lost enough records.
+#[test]
+fn prune_reports_analyze() {
+    std::env::set_var("PRUNE_ANALYZE_AFTER", "true");
+    std::env::set_var("PRUNE_ANALYZE_MIN_RECORDS", MIN_RECORDS.to_string());
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let columns = [("NumericColumn", ColumnKind::Number)];
+        let large = ctx.create_format(&admin, &columns).await;
+        let small = ctx.create_format(&admin, &columns).await;
+        for (format, count) in [(&large, MIN_RECORDS), (&small, 1)] {
+            let records = (0..count)
+                .map(|n| json!({"NumericColumn": n}))
+                .collect::<Value>();
+            let (status, body) = upload(&app, &admin, format, records).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            expire(format).await;
+        }
+
+        let request = user.request(TestRequest::post(), "/upload_session/prune");
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+        let request = admin.request(TestRequest::post(), "/upload_session/prune");
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let result = |format: &format::Model| {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .find(|result| result["formatId"] == format.id)
+                .unwrap_or_else(|| panic!("format {} wasn't pruned: {body}", format.id))
+                .clone()
+        };
+        let large = result(&large);
+        assert_eq!(large["deleteCount"], 1);
+        assert_eq!(large["recordCount"], MIN_RECORDS);
+        let maintenance = large["maintenance"].as_object().expect("no maintenance");
+        assert_eq!(maintenance["statement"], "ANALYZE record");
+        assert!(maintenance["durationMs"].is_u64(), "{large}");
+        assert_eq!(maintenance.len(), 2, "{large}");
+        let small = result(&small);
+        assert_eq!(small["recordCount"], 1);
+        assert_eq!(small["maintenance"], Value::Null);
+    });
+}
@@ -0,0 +1,79 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{
+        sea_query::{Alias, Expr},
+        EntityTrait, QuerySelect,
+    },
+};
+use central_repository_test_support::{call_json, run, upload, TestUser};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel, user};
+use serde_json::json;
+
+/// The running usage of `user`, as kept by the upload session trigger.
+async fn used_storage_bytes(user: &TestUser) -> i64 {
+    user::Entity::find_by_id(user.model.id)
+        .select_only()
+        .column_as(Expr::col(Alias::new("used_storage_bytes")), "used")
+        .into_tuple()
+        .one(DBConfig::get_connection())
+        .await
+        .expect("cannot read the storage usage")
+        .expect("the user is gone")
+}
+
+#[test]
+fn storage_quota_follows_uploads_and_deletes() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read, AccessLevel::Write])
+            .await;
+        let records = json!([{"NumericColumn": 1}, {"NumericColumn": 2}]);
+
+        // without a quota, the usage is still kept
+        let (status, first) = upload(&app, &user, &format, records.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        let bytes = first["uploadSession"]["payloadBytes"].as_i64().unwrap();
+        assert!(bytes > 0, "{first}");
+        assert_eq!(used_storage_bytes(&user).await, bytes);
+
+        // room for one more upload only
+        let path = format!("/user/{}", user.model.id);
+        let request = admin
+            .request(TestRequest::patch(), &path)
+            .set_json(json!({"maxStorageBytes": bytes * 2}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, second) = upload(&app, &user, &format, records.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{second}");
+        assert_eq!(used_storage_bytes(&user).await, bytes * 2);
+        let (status, body) = upload(&app, &user, &format, records.clone()).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        // failed sessions don't count
+        assert_eq!(used_storage_bytes(&user).await, bytes * 2);
+
+        // deleting a session frees its bytes
+        let id = second["uploadSession"]["id"].as_i64().unwrap();
+        let request = admin.request(TestRequest::delete(), &format!("/upload_session/{id}"));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(used_storage_bytes(&user).await, bytes);
+        let (status, body) = upload(&app, &user, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        // and so does deleting the format, along with its sessions
+        let request = admin.request(TestRequest::delete(), &format!("/format/{}", format.id));
+        let (status, body) = call_json(&app, request).await;
+        assert!(status.is_success(), "{status}: {body}");
+        assert_eq!(used_storage_bytes(&user).await, 0);
+        let usage_path = format!("/user/{}/usage", user.model.id);
+        let (status, usage) = call_json(&app, user.request(TestRequest::get(), &usage_path)).await;
+        assert_eq!(status, StatusCode::OK, "{usage}");
+        assert_eq!(usage["bytes"], 0);
+    });
+}
@@ -0,0 +1,115 @@
+use actix_web::{
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+};
+use central_repository_test_support::{call_json, run, upload};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use entity::{format::ColumnKind, record};
+use serde_json::{json, Value};
+
+fn timestamp(value: &Value) -> DateTime<Utc> {
+    value.as_str().unwrap().parse().unwrap()
+}
+
+/// Records get the timestamp of their upload session, can be filtered with
+/// `createdAt{Lt,Gt,Lte,Gte}` and are exported with a `CreatedAt` column.
+#[test]
+fn records_have_created_at() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let mut sessions = Vec::new();
+        for upload_number in [1, 2] {
+            let records = (0..3)
+                .map(|_| json!({"NumericColumn": upload_number}))
+                .collect::<Value>();
+            let (status, body) = upload(&app, &admin, &format, records).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            sessions.push(body["uploadSession"].clone());
+        }
+
+        let filter = |params: String| {
+            let request = admin
+                .request(TestRequest::post(), &format!("/record/filter?{params}"))
+                .set_json(json!({"formats": [format.id], "query": []}));
+            let app = &app;
+            async move {
+                let (status, body) = call_json(app, request).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                body.as_array().unwrap().clone()
+            }
+        };
+        // every record of an upload has the same timestamp as its session
+        let records = filter(String::new()).await;
+        assert_eq!(records.len(), 6);
+        for record in &records {
+            let session = sessions
+                .iter()
+                .find(|session| session["id"] == record["upload_session_id"])
+                .unwrap();
+            assert_eq!(
+                timestamp(&record["created_at"]),
+                timestamp(&session["createdAt"])
+            );
+        }
+
+        // move the first upload back in time, as if it had been there for a while
+        let first_session = sessions[0]["id"].as_i64().unwrap() as i32;
+        let backdated = Utc::now() - Duration::days(2);
+        record::Entity::update_many()
+            .col_expr(record::Column::CreatedAt, Expr::value(backdated))
+            .filter(record::Column::UploadSessionId.eq(first_session))
+            .exec(DBConfig::get_connection())
+            .await
+            .unwrap();
+        let cutoff = (Utc::now() - Duration::days(1)).to_rfc3339_opts(SecondsFormat::Secs, true);
+        for (params, expected) in [
+            (format!("createdAtLt={cutoff}"), 1),
+            (format!("createdAtLte={cutoff}"), 1),
+            (format!("createdAtGt={cutoff}"), 2),
+            (format!("createdAtGte={cutoff}"), 2),
+        ] {
+            let records = filter(params.clone()).await;
+            assert_eq!(records.len(), 3, "{params}");
+            assert!(
+                records
+                    .iter()
+                    .all(|record| record["data"]["NumericColumn"] == expected),
+                "{params}"
+            );
+        }
+
+        let path = format!("/upload_session/{first_session}/export");
+        let request = admin.request(TestRequest::get(), &path);
+        let body = test::call_and_read_body(&app, request.to_request()).await;
+        let csv = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(csv.lines().count(), 4, "{csv}");
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("ID,FormatId,UploadSessionId,CreatedAt,NumericColumn")
+        );
+        for line in lines {
+            let created_at = line.split(',').nth(3).unwrap();
+            let created_at: DateTime<Utc> = created_at.parse().unwrap();
+            assert_eq!(created_at.timestamp_micros(), backdated.timestamp_micros());
+        }
+
+        let request = admin.request(TestRequest::get(), &format!("{path}?format=ndjson"));
+        let body = test::call_and_read_body(&app, request.to_request()).await;
+        let lines = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(lines.lines().count(), 3, "{lines}");
+        for line in lines.lines() {
+            let record: Value = serde_json::from_str(line).unwrap();
+            let created_at = timestamp(&record["created_at"]);
+            assert_eq!(created_at.timestamp_micros(), backdated.timestamp_micros());
+        }
+    });
+}
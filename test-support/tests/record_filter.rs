@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run, search, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use serde_json::{json, Value};
+
+/// The `NumericColumn` of every record in a search response.
+fn numbers(records: &Value) -> HashSet<i64> {
+    records
+        .as_array()
+        .expect("not a list of records")
+        .iter()
+        .map(|record| {
+            record["data"]["NumericColumn"]
+                .as_i64()
+                .expect("not a number")
+        })
+        .collect()
+}
+
+#[test]
+fn record_filter_flow() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let columns = [
+            ("NumericColumn", ColumnKind::Number),
+            ("StringColumn", ColumnKind::String),
+        ];
+        let shared = ctx.create_format(&admin, &columns).await;
+        let hidden = ctx.create_format(&admin, &columns).await;
+        ctx.grant(&user, &shared, &[AccessLevel::Read]).await;
+        let records = |offset: i64| {
+            json!([
+                {"NumericColumn": offset + 1, "StringColumn": "first"},
+                {"NumericColumn": offset + 2, "StringColumn": "second"},
+                {"NumericColumn": offset + 3, "StringColumn": "third"},
+            ])
+        };
+        let (status, body) = upload(&app, &admin, &shared, records(0)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = upload(&app, &admin, &hidden, records(10)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let formats = [shared.id, hidden.id];
+
+        let query = json!({"formats": formats, "query": []});
+        let (status, body) = search(&app, &admin, query.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([1, 2, 3, 11, 12, 13]));
+        // unreadable formats are left out
+        let (status, body) = search(&app, &user, query).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([1, 2, 3]));
+
+        let query = json!({
+            "formats": formats,
+            "query": [{
+                "conditionKind": "all",
+                "args": [
+                    {"column": "NumericColumn", "comparisonOperator": "gte", "compareAgainst": 2},
+                    {"column": "StringColumn", "comparisonOperator": "eq", "compareAgainst": "third"},
+                ],
+            }],
+        });
+        let (status, body) = search(&app, &admin, query.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([3, 13]));
+        let (status, body) = search(&app, &user, query).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([3]));
+
+        let query = json!({
+            "formats": formats,
+            "query": [{
+                "conditionKind": "any",
+                "not": true,
+                "args": [{"column": "NumericColumn", "comparisonOperator": "lt", "compareAgainst": 12}],
+            }],
+        });
+        let (status, body) = search(&app, &admin, query).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([12, 13]));
+
+        // the upload session filter can't widen what the search sees
+        let query = json!({
+            "formats": formats,
+            "query": [],
+            "uploadSession": {"recordCountEq": 3},
+        });
+        let (status, body) = search(&app, &user, query).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(numbers(&body), HashSet::from([1, 2, 3]));
+
+        // strict format lists report the unreadable formats
+        let query = json!({"formats": formats, "query": [], "strictFormats": true});
+        let (status, body) = search(&app, &user, query).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "REPO-1008");
+    });
+}
+
+/// `includeSchemas=true` adds the schemas of the formats in the page to the
+/// envelope, and only then.
+#[test]
+fn envelope_include_schemas() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let numbers = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let strings = ctx
+            .create_format(&admin, &[("StringColumn", ColumnKind::String)])
+            .await;
+        let empty = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let (status, body) = upload(&app, &admin, &numbers, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = upload(&app, &admin, &strings, json!([{"StringColumn": "a"}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let query = json!({"formats": [numbers.id, strings.id, empty.id], "query": []});
+        let filter = |options: &str| {
+            let request = admin
+                .request(TestRequest::post(), &format!("/record/filter?{options}"))
+                .set_json(query.clone());
+            call_json(&app, request)
+        };
+
+        let (status, body) = filter("envelope=true").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["items"].as_array().unwrap().len(), 2);
+        assert!(body.get("schemas").is_none(), "{body}");
+
+        let (status, body) = filter("envelope=true&includeSchemas=true").await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["items"].as_array().unwrap().len(), 2);
+        let schemas = body["schemas"].as_object().unwrap();
+        // `empty` has no records in the page
+        let mut ids = schemas.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        let mut expected = vec![numbers.id.to_string(), strings.id.to_string()];
+        expected.sort();
+        assert_eq!(ids, expected);
+        for format in [&numbers, &strings] {
+            let schema = &schemas[&format.id.to_string()];
+            assert_eq!(schema, &serde_json::to_value(&format.schema).unwrap());
+        }
+
+        let (status, body) = filter("includeSchemas=true").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+    });
+}
@@ -0,0 +1,278 @@
+use actix_http::Request;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceResponse},
+    http::StatusCode,
+    test::TestRequest,
+};
+use central_repository_test_support::{call_json, random_name, run, upload, TestUser};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel};
+use serde_json::{json, Value};
+
+/// Create a saved search as `user` (`POST /record/saved`).
+async fn create<S, B>(app: &S, user: &TestUser, saved_search: Value) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let request = user
+        .request(TestRequest::post(), "/record/saved")
+        .set_json(saved_search);
+    call_json(app, request).await
+}
+
+#[test]
+fn create_update_and_delete() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+
+        let name = random_name("search");
+        let query = json!({"formats": [format.id], "query": []});
+        let (status, body) = create(
+            &app,
+            &user,
+            json!({"name": name, "query": query, "userId": admin.model.id}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["name"], name.as_str());
+        assert_eq!(body["query"], query);
+        assert_eq!(body["shared"], false);
+        // the owner is always the caller
+        assert_eq!(body["userId"], user.model.id.to_string());
+        let path = format!("/record/saved/{}", body["id"]);
+
+        // invalid queries are rejected
+        let (status, body) =
+            create(&app, &user, json!({"name": name, "query": {"formats": 1}})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        let request = user
+            .request(TestRequest::patch(), &path)
+            .set_json(json!({"description": "updated", "shared": true}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["description"], "updated");
+        assert_eq!(body["shared"], true);
+        assert_eq!(body["name"], name.as_str());
+        let request = user
+            .request(TestRequest::patch(), &path)
+            .set_json(json!({"query": {"formats": "all"}}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        let (status, body) = call_json(&app, user.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["description"], "updated");
+        assert_eq!(body["query"], query);
+
+        let (status, body) = call_json(&app, user.request(TestRequest::delete(), &path)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+        let (status, body) = call_json(&app, user.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+    });
+}
+
+/// Users a search is shared with can see and run it, but only its owner
+/// (or a superuser) can change it.
+#[test]
+fn only_owner_changes_shared_search() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let owner = ctx.create_user().await;
+        let reader = ctx.create_user().await;
+        let shared_with = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&owner, &format, &[AccessLevel::Read]).await;
+        ctx.grant(&reader, &format, &[AccessLevel::Read]).await;
+
+        let query = json!({"formats": [format.id], "query": []});
+        let saved_search = json!({"name": random_name("search"), "query": query, "shared": true});
+        let (status, body) = create(&app, &owner, saved_search).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let id = body["id"].clone();
+        let path = format!("/record/saved/{id}");
+        let request = owner
+            .request(TestRequest::post(), &format!("{path}/shares"))
+            .set_json(json!({"userId": shared_with.model.id}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+
+        // `reader` sees it through `shared`, `shared_with` through the share
+        for user in [&reader, &shared_with] {
+            let (status, body) = call_json(&app, user.request(TestRequest::get(), &path)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+
+            let request = user
+                .request(TestRequest::patch(), &path)
+                .set_json(json!({"name": random_name("stolen")}));
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+            let request = user
+                .request(TestRequest::post(), &format!("{path}/shares"))
+                .set_json(json!({"userId": user.model.id}));
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+            let (status, body) = call_json(&app, user.request(TestRequest::delete(), &path)).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+        }
+
+        let (status, body) = call_json(&app, owner.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["query"], query);
+        let request = admin
+            .request(TestRequest::patch(), &path)
+            .set_json(json!({"description": "by an admin"}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let (status, body) = call_json(&app, owner.request(TestRequest::delete(), &path)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT, "{body}");
+    });
+}
+
+/// Shared searches are only visible to users who can read every format they
+/// list.
+#[test]
+fn shared_search_needs_every_format() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let owner = ctx.create_user().await;
+        let partial = ctx.create_user().await;
+        let full = ctx.create_user().await;
+        let columns = [("NumericColumn", ColumnKind::Number)];
+        let first = ctx.create_format(&admin, &columns).await;
+        let second = ctx.create_format(&admin, &columns).await;
+        for user in [&owner, &full] {
+            ctx.grant(user, &first, &[AccessLevel::Read]).await;
+            ctx.grant(user, &second, &[AccessLevel::Read]).await;
+        }
+        ctx.grant(&partial, &first, &[AccessLevel::Read]).await;
+
+        let mut ids = Vec::new();
+        for query in [
+            json!({"formats": [first.id, second.id], "query": []}),
+            json!({"query": []}),
+        ] {
+            let saved_search =
+                json!({"name": random_name("search"), "query": query, "shared": true});
+            let (status, body) = create(&app, &owner, saved_search).await;
+            assert_eq!(status, StatusCode::CREATED, "{body}");
+            ids.push(body["id"].clone());
+        }
+        let (both_formats, any_format) = (&ids[0], &ids[1]);
+
+        let visible = |user: &TestUser| {
+            let path = format!("/record/saved?shared=true&userIdEq={}", owner.model.id);
+            let request = user.request(TestRequest::get(), &path);
+            let app = &app;
+            async move {
+                let (status, body) = call_json(app, request).await;
+                assert_eq!(status, StatusCode::OK, "{body}");
+                body.as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|saved_search| saved_search["id"].clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+        let partial_visible = visible(&partial).await;
+        assert!(!partial_visible.contains(both_formats));
+        assert!(partial_visible.contains(any_format));
+        let path = format!("/record/saved/{both_formats}");
+        let (status, body) = call_json(&app, partial.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{body}");
+
+        let full_visible = visible(&full).await;
+        assert!(full_visible.contains(both_formats));
+        assert!(full_visible.contains(any_format));
+        let (status, body) = call_json(&app, full.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+    });
+}
+
+/// Query templates get their `$param`s from the body of `execute`.
+#[test]
+fn execute_template() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(
+                &admin,
+                &[
+                    ("Amount", ColumnKind::Number),
+                    ("Region", ColumnKind::String),
+                ],
+            )
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+        let records = json!([
+            {"Amount": 50, "Region": "north"},
+            {"Amount": 150, "Region": "north"},
+            {"Amount": 250, "Region": "south"},
+        ]);
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let saved_search = json!({
+            "name": random_name("template"),
+            "query": {"formats": [format.id], "query": [{"args": [
+                {"column": "Amount", "comparisonOperator": "gt", "compareAgainst": {"$param": "minAmount"}},
+                {"column": "Region", "comparisonOperator": "eq", "compareAgainst": {"$param": "region"}},
+            ]}]},
+            "parameters": {"minAmount": "Number", "region": "String"},
+        });
+        let (status, body) = create(&app, &user, saved_search).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let path = format!("/record/saved/{}/execute", body["id"]);
+        let execute = |values: Value| {
+            let request = user.request(TestRequest::post(), &path).set_json(values);
+            call_json(&app, request)
+        };
+        let amounts = |body: &Value| {
+            body.as_array()
+                .expect("not a list of records")
+                .iter()
+                .map(|record| record["data"]["Amount"].as_i64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let (status, body) = execute(json!({"minAmount": 100, "region": "north"})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(amounts(&body), [150]);
+
+        let (status, body) = execute(json!({"minAmount": 100})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        let detail = body["detail"].as_str().unwrap();
+        assert!(detail.contains("region (missing)"), "{detail}");
+
+        let (status, body) = execute(json!({"minAmount": "100", "region": ["north", 1]})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        let detail = body["detail"].as_str().unwrap();
+        assert!(detail.contains("minAmount (expected Number)"), "{detail}");
+        assert!(detail.contains("region (expected String)"), "{detail}");
+
+        // values are bound, never spliced into the SQL
+        for region in ["north' OR '1'='1", "north'; DELETE FROM record; --"] {
+            let (status, body) = execute(json!({"minAmount": 0, "region": region})).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(amounts(&body), Vec::<i64>::new());
+        }
+        let (status, body) = execute(json!({"minAmount": 0, "region": "north"})).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let mut found = amounts(&body);
+        found.sort_unstable();
+        assert_eq!(found, [50, 150]);
+    });
+}
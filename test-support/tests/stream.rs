@@ -0,0 +1,178 @@
+use actix_web::{
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+};
+use central_repository_test_support::{call_json, random_name, run, upload};
+use entity::{format::ColumnKind, format_entitlement::AccessLevel, record};
+use serde_json::json;
+
+#[test]
+fn stream_worker_limits() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+        let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let search = json!({"formats": [format.id], "query": []});
+        // DB_CSV_STREAM_WORKERS and DB_CSV_TRANSFORM_WORKERS are 1 and 2
+        for (params, as_admin, as_user) in [
+            ("", (1, 2), (1, 2)),
+            ("streams=1&transformWorkers=1", (1, 1), (1, 1)),
+            // users outside DB_CSV_PARALLEL_USERS can't go above the defaults
+            ("streams=4&transformWorkers=5", (4, 5), (1, 2)),
+        ] {
+            for (who, expected) in [(&admin, as_admin), (&user, as_user)] {
+                let path = format!("/record/filter-stream?{params}");
+                let request = who.request(TestRequest::post(), &path).set_json(&search);
+                let response = test::call_service(&app, request.to_request()).await;
+                assert_eq!(response.status(), StatusCode::OK, "{params}");
+                let header = |name: &str| {
+                    let value = response.headers().get(name).expect("missing header");
+                    value.to_str().unwrap().parse::<u64>().unwrap()
+                };
+                let used = (
+                    header("repository-streams"),
+                    header("repository-transform-workers"),
+                );
+                assert_eq!(used, expected, "{params} as {}", who.model.username);
+            }
+        }
+    });
+}
+
+#[test]
+fn stream_aborts_on_undecryptable_records() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let request = admin
+            .request(TestRequest::post(), "/format")
+            .set_json(json!({
+                "name": random_name("format"),
+                "description": "created by a test",
+                "schema": [{"name": "NumericColumn", "kind": "Number"}],
+                "encrypted": true,
+            }));
+        let (status, format) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{format}");
+        let format_id = format["id"].as_i64().unwrap() as i32;
+        let records = (0..20).map(|i| json!({"NumericColumn": i})).collect();
+        let body = json!({"formatId": format_id, "data": serde_json::Value::Array(records)});
+        let request = admin.request(TestRequest::post(), "/record").set_json(body);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let search = json!({"formats": [format_id], "query": []});
+        let export = |params: &'static str| {
+            let path = format!("/record/filter-stream?manifest=true&{params}");
+            admin.request(TestRequest::post(), &path).set_json(&search)
+        };
+        let body = test::call_and_read_body(&app, export("").to_request()).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("\n#manifest "), "{body}");
+
+        // as if RECORD_ENCRYPTION_KEY changed
+        record::Entity::update_many()
+            .col_expr(record::Column::EncryptedData, Expr::value(vec![0u8; 40]))
+            .filter(record::Column::FormatId.eq(format_id))
+            .exec(DBConfig::get_connection())
+            .await
+            .expect("cannot corrupt the records");
+        for params in ["", "streams=2&transformWorkers=3"] {
+            let body = test::call_and_read_body(&app, export(params).to_request()).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let (rows, trailer) = body.trim_end().rsplit_once('\n').unwrap();
+            assert!(trailer.starts_with("#error "), "{params}: {body}");
+            assert!(trailer.contains("cannot decrypt record"), "{trailer}");
+            assert!(!rows.contains("#manifest"), "{params}: {body}");
+        }
+    });
+}
+
+#[test]
+fn stream_skips_the_manifest_on_database_errors() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let records = (0..20).map(|i| json!({"NumericColumn": i})).collect();
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        // records whose data can't be read back
+        record::Entity::update_many()
+            .col_expr(record::Column::Data, Expr::cust("'[1]'::jsonb"))
+            .filter(record::Column::FormatId.eq(format.id))
+            .exec(DBConfig::get_connection())
+            .await
+            .expect("cannot corrupt the records");
+
+        let search = json!({"formats": [format.id], "query": []});
+        for params in ["", "streams=3&transformWorkers=2"] {
+            let path = format!("/record/filter-stream?manifest=true&{params}");
+            let request = admin.request(TestRequest::post(), &path).set_json(&search);
+            let body = test::call_and_read_body(&app, request.to_request()).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let (rows, trailer) = body.trim_end().rsplit_once('\n').unwrap();
+            assert!(trailer.starts_with("#error "), "{params}: {body}");
+            assert!(trailer.contains("cannot read records"), "{trailer}");
+            // only a pointer to the logs, no driver errors or SQL.
+            assert!(trailer.contains("see the logs of request"), "{trailer}");
+            for leak in ["error returned from database", "jsonb", "SELECT"] {
+                assert!(!trailer.contains(leak), "{trailer}");
+            }
+            assert!(!rows.contains("#manifest"), "{params}: {body}");
+        }
+    });
+}
+
+#[test]
+fn selected_columns_are_trimmed() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(
+                &admin,
+                &[
+                    ("NumericColumn", ColumnKind::Number),
+                    ("StringColumn", ColumnKind::String),
+                ],
+            )
+            .await;
+        let records = json!([{"NumericColumn": 1, "StringColumn": "a"}]);
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let search = json!({"formats": [format.id], "query": []});
+        let export = |columns: &str| {
+            let path = format!("/record/filter-stream?columns={columns}");
+            admin.request(TestRequest::post(), &path).set_json(&search)
+        };
+        let request = export("%20StringColumn%20,,NumericColumn,");
+        let response = test::call_service(&app, request.to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let csv = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        let header = csv.lines().next().unwrap();
+        assert!(
+            header.ends_with(",CreatedAt,StringColumn,NumericColumn"),
+            "{header}"
+        );
+
+        for columns in ["", ",%20,"] {
+            let (status, body) = call_json(&app, export(columns)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{columns}: {body}");
+        }
+    });
+}
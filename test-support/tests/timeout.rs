@@ -0,0 +1,72 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::LockType, EntityTrait, QuerySelect, TransactionTrait},
+};
+use central_repository_test_support::{call_json, run};
+use entity::format::{self, ColumnKind};
+use serde_json::{json, Value};
+
+#[test]
+fn timed_out_uploads_fail_their_session() {
+    // the config is only read once, by the first test of this binary.
+    std::env::set_var("LONG_REQUEST_TIMEOUT_SECONDS", "1");
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+
+        // the quota check waits for the format row until the request times out.
+        // The session can still be created: foreign keys only take a key share lock.
+        // Formats without quotas aren't locked.
+        let path = format!("/format/{}", format.id);
+        let request = admin
+            .request(TestRequest::patch(), &path)
+            .set_json(json!({"maxRecords": 1000}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let txn = DBConfig::get_connection().begin().await.unwrap();
+        format::Entity::find_by_id(format.id)
+            .lock(LockType::NoKeyUpdate)
+            .one(&txn)
+            .await
+            .expect("cannot lock the format");
+        let body = json!({"formatId": format.id, "data": [{"NumericColumn": 1}]});
+        let request = admin.request(TestRequest::post(), "/record").set_json(body);
+        let err = test::try_call_service(&app, request.to_request())
+            .await
+            .err()
+            .expect("the upload didn't time out");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        txn.rollback().await.unwrap();
+
+        // the session is failed in the background
+        let path = format!("/upload_session?formatIdEq={}", format.id);
+        let started = Instant::now();
+        let session = loop {
+            let (status, body) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+            assert_eq!(status, StatusCode::OK, "{body}");
+            assert_eq!(body.as_array().map(Vec::len), Some(1), "{body}");
+            if body[0]["outcome"] != "InProgress" || started.elapsed() > Duration::from_secs(10) {
+                break body[0].clone();
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+        assert_eq!(session["outcome"], "Error", "{session}");
+        assert_eq!(
+            session["detail"],
+            Value::from("the upload was cancelled before it finished")
+        );
+        assert_eq!(session["recordCount"], 1);
+    });
+}
@@ -0,0 +1,77 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, run, TestUser, TEST_PASSWORD};
+use ring::hmac;
+use serde_json::{json, Value};
+
+/// The TOTP code of `secret` (base32) at time step `step`.
+fn code_at(secret: &str, step: u64) -> String {
+    let (mut bytes, mut buffer, mut bits) = (Vec::new(), 0u32, 0);
+    for c in secret.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => panic!("invalid base32 secret"),
+        };
+        buffer = (buffer << 5) | u32::from(value);
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &bytes);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:06}", value % 1_000_000)
+}
+
+fn current_step() -> u64 {
+    chrono::offset::Utc::now().timestamp() as u64 / 30
+}
+
+fn login(user: &TestUser, code: &str) -> TestRequest {
+    TestRequest::post().uri("/api/v1/login").set_json(json!({
+        "username": user.model.username,
+        "password": TEST_PASSWORD,
+        "totpCode": code,
+    }))
+}
+
+#[test]
+fn totp_codes_cant_be_replayed() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let user = ctx.create_user().await;
+        let request = user.request(TestRequest::post(), "/user/self/2fa/enroll");
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let secret = body["secret"].as_str().unwrap().to_string();
+
+        let step = current_step();
+        let request = user
+            .request(TestRequest::post(), "/user/self/2fa/confirm")
+            .set_json(json!({"code": code_at(&secret, step)}));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["totpEnabled"], true, "{body}");
+
+        let invalid = |(status, body): (StatusCode, Value)| {
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{body}");
+            assert_eq!(body["code"], "REPO-2009");
+        };
+        // the code used to confirm the enrollment
+        invalid(call_json(&app, login(&user, &code_at(&secret, step))).await);
+        // the next one is within TOTP_SKEW_STEPS, but only works once
+        let next = code_at(&secret, step + 1);
+        let (status, body) = call_json(&app, login(&user, &next)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        invalid(call_json(&app, login(&user, &next)).await);
+        // and older codes don't work anymore either
+        invalid(call_json(&app, login(&user, &code_at(&secret, step))).await);
+        // way out of the window
+        invalid(call_json(&app, login(&user, &code_at(&secret, step + 10))).await);
+    });
+}
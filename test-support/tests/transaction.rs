@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{
+        ColumnTrait, ConnectionTrait, EntityTrait, PaginatorTrait, QueryFilter, TryIntoModel,
+    },
+    FormatEntitlementMutation, FormatMutation,
+};
+use central_repository_test_support::{call_json, random_name, run, upload};
+use entity::{
+    error::DatabaseQueryError,
+    format::{self, ColumnKind},
+    format_entitlement::{self, Access, AccessLevel},
+    record,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+async fn record_count(upload_session_id: i64) -> u64 {
+    record::Entity::find()
+        .filter(record::Column::UploadSessionId.eq(upload_session_id))
+        .count(DBConfig::get_connection())
+        .await
+        .expect("cannot count the records")
+}
+
+/// Insert uploads 2 records at a time. The config is only read once, by
+/// the first test of this binary to run, so every test must call this.
+fn set_chunk_size() {
+    std::env::set_var("BULK_INSERT_CHUNK_SIZE", "2");
+}
+
+#[test]
+fn failed_inserts_roll_back_the_whole_upload() {
+    set_chunk_size();
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        // valid records the database refuses to store, in the last chunk
+        let function = random_name("reject_record");
+        let db = DBConfig::get_connection();
+        db.execute_unprepared(&format!(
+            r#"CREATE FUNCTION "{function}"() RETURNS trigger AS $$
+            BEGIN
+                IF NEW.format_id = {} AND NEW.data->>'NumericColumn' = '-1' THEN
+                    RAISE EXCEPTION 'rejected by the test';
+                END IF;
+                RETURN NEW;
+            END $$ LANGUAGE plpgsql;
+            CREATE TRIGGER "{function}" BEFORE INSERT ON record
+            FOR EACH ROW EXECUTE FUNCTION "{function}"();"#,
+            format.id
+        ))
+        .await
+        .expect("cannot create the trigger");
+
+        let records = json!([
+            {"NumericColumn": 1}, {"NumericColumn": 2},
+            {"NumericColumn": 3}, {"NumericColumn": 4},
+            {"NumericColumn": -1},
+        ]);
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        db.execute_unprepared(&format!(
+            r#"DROP TRIGGER "{function}" ON record; DROP FUNCTION "{function}";"#
+        ))
+        .await
+        .expect("cannot drop the trigger");
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{body}");
+        let path = format!("/upload_session?formatIdEq={}", format.id);
+        let (status, sessions) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{sessions}");
+        assert_eq!(sessions.as_array().map(Vec::len), Some(1), "{sessions}");
+        assert_eq!(sessions[0]["outcome"], "Error");
+        // the first two chunks were inserted, and rolled back
+        let session_id = sessions[0]["id"].as_i64().unwrap();
+        assert_eq!(record_count(session_id).await, 0);
+
+        let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": -1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let session_id = body["uploadSession"]["id"].as_i64().unwrap();
+        assert_eq!(record_count(session_id).await, 1);
+    });
+}
+
+#[test]
+fn transactions_roll_back_every_write() {
+    set_chunk_size();
+    run(|_| async move {
+        let name = random_name("format");
+        let result = DBConfig::transaction(|txn| {
+            let name = name.clone();
+            Box::pin(async move {
+                let model = serde_json::from_value(json!({
+                    "name": name,
+                    "description": "created by a test",
+                    "schema": [{"name": "NumericColumn", "kind": "Number"}],
+                }))
+                .unwrap();
+                let format = FormatMutation::create(txn, model, None)
+                    .await?
+                    .try_into_model()?;
+                // there's no such user
+                let entitlement = format_entitlement::Model {
+                    created_at: chrono::offset::Utc::now(),
+                    user_id: Uuid::new_v4(),
+                    format_id: format.id,
+                    access: Access(HashSet::from([AccessLevel::Read])),
+                    ..Default::default()
+                };
+                FormatEntitlementMutation::create(txn, entitlement, None).await?;
+                Ok::<_, DatabaseQueryError>(())
+            })
+        })
+        .await;
+        assert!(result.is_err(), "{result:?}");
+        let format = format::Entity::find()
+            .filter(format::Column::Name.eq(name))
+            .one(DBConfig::get_connection())
+            .await
+            .unwrap();
+        assert!(format.is_none(), "{format:?}");
+    });
+}
@@ -0,0 +1,210 @@
+use actix_web::{
+    http::StatusCode,
+    test::{self, TestRequest},
+};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter},
+};
+use central_repository_test_support::{call_json, random_name, run, upload, TestUser};
+use entity::{
+    format::{self, ColumnKind},
+    format_entitlement::AccessLevel,
+};
+use serde_json::{json, Value};
+
+#[test]
+fn upload_flow() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let user = ctx.create_user().await;
+        let format = ctx
+            .create_format(
+                &admin,
+                &[
+                    ("NumericColumn", ColumnKind::Number),
+                    ("StringColumn", ColumnKind::String),
+                ],
+            )
+            .await;
+        let records = json!([
+            {"NumericColumn": 1, "StringColumn": "a"},
+            {"NumericColumn": 2, "StringColumn": "b"},
+        ]);
+
+        // uploading takes the write access level
+        ctx.grant(&user, &format, &[AccessLevel::Read]).await;
+        let (status, body) = upload(&app, &user, &format, records.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{body}");
+
+        let (status, body) = upload(&app, &admin, &format, records).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["receivedCount"], 2);
+        assert_eq!(body["insertedCount"], 2);
+        assert_eq!(body["uploadSession"]["recordCount"], 2);
+        assert_eq!(body["uploadSession"]["outcome"], "Success");
+        let session_id = body["uploadSession"]["id"].clone();
+
+        // invalid records fail the whole upload, but still get a session
+        let invalid = json!([
+            {"NumericColumn": 3, "StringColumn": "c"},
+            {"NumericColumn": "not a number", "StringColumn": "d"},
+        ]);
+        let (status, body) = upload(&app, &admin, &format, invalid).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["insertedCount"], 0);
+        assert_eq!(body["uploadSession"]["outcome"], "Error");
+
+        let request = user.request(
+            TestRequest::get(),
+            &format!("/upload_session?formatIdEq={}", format.id),
+        );
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let sessions = body.as_array().expect("not a list of sessions");
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().any(|session| session["id"] == session_id));
+    });
+}
+
+#[test]
+fn upload_api_key_flow() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        // admins can't have API keys
+        let user = ctx.create_user().await;
+        ctx.grant(&user, &format, &[AccessLevel::Read, AccessLevel::Write])
+            .await;
+
+        // password tokens don't have a key
+        let (status, body) = upload(&app, &user, &format, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["uploadSession"]["apiKeyId"], Value::Null);
+
+        let path = format!("/user/{}/api-key", user.model.id);
+        let (status, body) = call_json(&app, user.request(TestRequest::post(), &path)).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        let key_id = body["apiKey"]["id"].clone();
+        let key_user = TestUser {
+            model: user.model.clone(),
+            token: body["token"].as_str().expect("no token").to_string(),
+        };
+        let (status, body) = upload(&app, &key_user, &format, json!([{"NumericColumn": 2}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let session = &body["uploadSession"];
+        assert_eq!(session["apiKeyId"], key_id);
+        let request = admin.request(
+            TestRequest::get(),
+            &format!("/upload_session/{}", session["id"]),
+        );
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["apiKeyId"], key_id);
+
+        let path = format!(
+            "/upload_session?formatIdEq={}&apiKeyIdEq={}",
+            format.id,
+            key_id.as_str().unwrap()
+        );
+        let (status, body) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body.as_array().map(Vec::len), Some(1), "{body}");
+        assert_eq!(body[0]["id"], session["id"]);
+    });
+}
+
+#[test]
+fn validation_report_keeps_the_first_errors() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        // every odd record is invalid
+        let records = (0..5000)
+            .map(|i| match i % 2 {
+                0 => json!({"NumericColumn": i}),
+                _ => json!({"NumericColumn": "invalid"}),
+            })
+            .collect::<Vec<_>>();
+        let body = json!({"formatId": format.id, "data": records});
+        let request = admin
+            .request(TestRequest::post(), "/record/validate")
+            .set_json(body);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["ok"], false);
+        assert_eq!(body["recordCount"], 5000);
+        assert_eq!(body["invalidCount"], 2500);
+        let reported = body["errors"]
+            .as_array()
+            .expect("no errors")
+            .iter()
+            .map(|error| error["record"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(reported, (0..100).map(|i| i * 2 + 1).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn formats_without_columns_cannot_be_uploaded_to() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let request = admin
+            .request(TestRequest::post(), "/format")
+            .set_json(json!({
+                "name": random_name("format"),
+                "description": "created by a test",
+                "schema": [],
+            }));
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+
+        // formats created before schemas had to have a column
+        let format = ctx
+            .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+            .await;
+        let (status, body) = upload(&app, &admin, &format, json!([{"NumericColumn": 1}])).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        format::Entity::update_many()
+            .col_expr(format::Column::Schema, Expr::cust("'[]'::jsonb"))
+            .filter(format::Column::Id.eq(format.id))
+            .exec(DBConfig::get_connection())
+            .await
+            .expect("cannot empty the schema");
+
+        let body = json!({"formatId": format.id, "data": [{"NumericColumn": 2}]});
+        for path in ["/record", "/record/validate"] {
+            let request = admin
+                .request(TestRequest::post(), path)
+                .set_json(body.clone());
+            let (status, body) = call_json(&app, request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{path}: {body}");
+            assert_eq!(body["code"], "REPO-1005", "{path}: {body}");
+            assert!(
+                body["detail"].as_str().unwrap().contains("has no columns"),
+                "{path}: {body}"
+            );
+        }
+
+        // their records can still be exported, with the fixed columns only
+        let search = json!({"formats": [format.id], "query": []});
+        let request = admin
+            .request(TestRequest::post(), "/record/filter-stream")
+            .set_json(search);
+        let export = test::call_and_read_body(&app, request.to_request()).await;
+        let export = String::from_utf8(export.to_vec()).unwrap();
+        let mut lines = export.lines();
+        assert_eq!(lines.next(), Some("ID,FormatId,UploadSessionId,CreatedAt"));
+        let row = lines.next().expect("the record wasn't exported");
+        assert_eq!(row.split(',').count(), 4, "{row}");
+        assert_eq!(lines.next(), None, "{export}");
+    });
+}
@@ -0,0 +1,41 @@
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_test_support::{call_json, random_name, run, TEST_PASSWORD};
+use serde_json::json;
+
+#[test]
+fn bulk_users_all_or_nothing() {
+    run(|ctx| async move {
+        let app = ctx.app().await;
+        let admin = ctx.create_superuser().await;
+        let mut users = (0..50)
+            .map(|_| json!({"username": random_name("bulk"), "password": TEST_PASSWORD}))
+            .collect::<Vec<_>>();
+        // already taken
+        users.push(json!({"username": admin.model.username, "password": TEST_PASSWORD}));
+
+        let request = admin
+            .request(TestRequest::post(), "/user/bulk?allOrNothing=true")
+            .set_json(&users);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["created"], json!([]));
+        assert_eq!(body["failed"].as_array().map(Vec::len), Some(1), "{body}");
+        assert_eq!(body["failed"][0]["index"], 50);
+        let path = format!(
+            "/user?usernameEq={}",
+            users[0]["username"].as_str().unwrap()
+        );
+        let (status, body) = call_json(&app, admin.request(TestRequest::get(), &path)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body, json!([]));
+
+        // without the duplicate, everyone is created
+        users.pop();
+        let request = admin
+            .request(TestRequest::post(), "/user/bulk?allOrNothing=true")
+            .set_json(&users);
+        let (status, body) = call_json(&app, request).await;
+        assert_eq!(status, StatusCode::CREATED, "{body}");
+        assert_eq!(body["created"].as_array().map(Vec::len), Some(50));
+    });
+}
@@ -0,0 +1,295 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use actix_web::{http::StatusCode, test::TestRequest};
+use central_repository_dao::{
+    conf::DBConfig,
+    sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, TransactionTrait},
+    WebhookDispatcher,
+};
+use central_repository_test_support::{call_json, random_name, run, TestContext};
+use entity::{format::ColumnKind, webhook::WebhookEvent, webhook_delivery};
+use ring::hmac;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+};
+
+const SECRET: &str = "webhook test secret";
+const RETRY_BASE_DELAY_MS: u64 = 100;
+const MAX_ATTEMPTS: i32 = 3;
+
+// There's a single dispatcher and queue, the tests take turns using them.
+static DISPATCHER: Mutex<()> = Mutex::const_new(());
+
+/// The config is read once, so every test sets the same variables.
+fn set_config() {
+    std::env::set_var(
+        "WEBHOOK_RETRY_BASE_DELAY_MS",
+        RETRY_BASE_DELAY_MS.to_string(),
+    );
+    std::env::set_var("WEBHOOK_MAX_ATTEMPTS", MAX_ATTEMPTS.to_string());
+    std::env::set_var("WEBHOOK_QUEUE_DEPTH", "1");
+}
+
+/// A request received by [`receiver`].
+struct Received {
+    at: Instant,
+    headers: HashMap<String, String>,
+    raw_body: Vec<u8>,
+}
+
+/// Listen on a local port, answering the nth request with the nth status
+/// (the last one repeats). Returns the URL and the received requests.
+async fn receiver(statuses: &[u16]) -> (String, mpsc::UnboundedReceiver<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let statuses = statuses.to_vec();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        for n in 0.. {
+            let (stream, _) = listener.accept().await.unwrap();
+            let status = statuses[n.min(statuses.len() - 1)];
+            let received = respond(stream, status).await;
+            if tx.send(received).is_err() {
+                break;
+            }
+        }
+    });
+    (url, rx)
+}
+
+async fn respond(mut stream: TcpStream, status: u16) -> Received {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "connection closed before the headers ended");
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+    let head = String::from_utf8(buffer[..header_end].to_vec()).unwrap();
+    let headers = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| (name.to_lowercase(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+    let length = headers["content-length"].parse::<usize>().unwrap();
+    let mut body = buffer[header_end..].to_vec();
+    body.resize(length, 0);
+    stream
+        .read_exact(&mut body[buffer.len() - header_end..])
+        .await
+        .unwrap();
+    let response =
+        format!("HTTP/1.1 {status} Test\r\ncontent-length: 0\r\nconnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await.unwrap();
+    Received {
+        at: Instant::now(),
+        headers,
+        raw_body: body,
+    }
+}
+
+impl Received {
+    fn body(&self) -> Value {
+        serde_json::from_slice(&self.raw_body).unwrap()
+    }
+}
+
+/// The signature header the receiver expects for `body`.
+fn signature(body: &[u8]) -> String {
+    let signature = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes()), body);
+    let hex = signature
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!("sha256={hex}")
+}
+
+/// Register a webhook for `uploadCompleted` events of a new format, returning
+/// its ID and the format ID.
+async fn create_webhook(ctx: &TestContext, url: &str) -> (i32, i32) {
+    let app = ctx.app().await;
+    let admin = ctx.create_superuser().await;
+    let format = ctx
+        .create_format(&admin, &[("NumericColumn", ColumnKind::Number)])
+        .await;
+    let request = admin
+        .request(TestRequest::post(), "/webhook")
+        .set_json(json!({
+            "url": url,
+            "secret": SECRET,
+            "events": ["uploadCompleted"],
+            "formatId": format.id,
+        }));
+    let (status, body) = call_json(&app, request).await;
+    assert_eq!(status, StatusCode::CREATED, "{body}");
+    assert!(body.get("secret").is_none(), "{body}");
+    (body["id"].as_i64().unwrap() as i32, format.id)
+}
+
+async fn next(requests: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+    tokio::time::timeout(Duration::from_secs(10), requests.recv())
+        .await
+        .expect("the webhook wasn't called")
+        .unwrap()
+}
+
+/// Wait for the dispatcher to record the delivery to `webhook_id`.
+async fn delivery(webhook_id: i32) -> webhook_delivery::Model {
+    let started = Instant::now();
+    loop {
+        let delivery = webhook_delivery::Entity::find()
+            .filter(webhook_delivery::Column::WebhookId.eq(webhook_id))
+            .one(DBConfig::get_connection())
+            .await
+            .unwrap();
+        if let Some(delivery) = delivery {
+            return delivery;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "the delivery wasn't recorded"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[test]
+fn signs_and_records_deliveries() {
+    set_config();
+    run(|ctx| async move {
+        let _turn = DISPATCHER.lock().await;
+        let (url, mut requests) = receiver(&[200]).await;
+        let (webhook_id, format_id) = create_webhook(ctx, &url).await;
+        let data = json!({"name": random_name("upload")});
+        WebhookDispatcher::notify_waiting(WebhookEvent::UploadCompleted, Some(format_id), &data)
+            .await;
+
+        let received = next(&mut requests).await;
+        assert_eq!(
+            received.headers["x-repository-signature"],
+            signature(&received.raw_body)
+        );
+        assert_eq!(received.headers["content-type"], "application/json");
+        assert_eq!(received.headers["x-repository-event"], "uploadCompleted");
+        let body = received.body();
+        let delivery_id = body["deliveryId"].as_str().unwrap();
+        assert_eq!(received.headers["x-repository-delivery"], delivery_id);
+        assert_eq!(body["event"], "uploadCompleted");
+        assert_eq!(body["data"], data);
+
+        let delivery = delivery(webhook_id).await;
+        assert_eq!(delivery.delivery_id.to_string(), delivery_id);
+        assert_eq!(delivery.event, WebhookEvent::UploadCompleted);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status_code, Some(200));
+        assert!(delivery.success);
+    });
+}
+
+#[test]
+fn retries_with_backoff() {
+    set_config();
+    run(|ctx| async move {
+        let _turn = DISPATCHER.lock().await;
+        let (url, mut requests) = receiver(&[500, 503, 204]).await;
+        let (webhook_id, format_id) = create_webhook(ctx, &url).await;
+        WebhookDispatcher::notify_waiting(
+            WebhookEvent::UploadCompleted,
+            Some(format_id),
+            &json!({}),
+        )
+        .await;
+
+        let mut attempts = Vec::new();
+        for _ in 0..MAX_ATTEMPTS {
+            attempts.push(next(&mut requests).await);
+        }
+        // every attempt is the same delivery, with the same body
+        assert!(attempts
+            .iter()
+            .all(|attempt| attempt.raw_body == attempts[0].raw_body));
+        // the delay doubles after every failed attempt
+        let base = Duration::from_millis(RETRY_BASE_DELAY_MS);
+        assert!(attempts[1].at - attempts[0].at >= base);
+        assert!(attempts[2].at - attempts[1].at >= base * 2);
+
+        let delivery = delivery(webhook_id).await;
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(delivery.status_code, Some(204));
+        assert!(delivery.success);
+    });
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+    set_config();
+    run(|ctx| async move {
+        let _turn = DISPATCHER.lock().await;
+        let (url, mut requests) = receiver(&[500]).await;
+        let (webhook_id, format_id) = create_webhook(ctx, &url).await;
+        WebhookDispatcher::notify_waiting(
+            WebhookEvent::UploadCompleted,
+            Some(format_id),
+            &json!({}),
+        )
+        .await;
+
+        for _ in 0..MAX_ATTEMPTS {
+            next(&mut requests).await;
+        }
+        let delivery = delivery(webhook_id).await;
+        assert_eq!(delivery.attempts, MAX_ATTEMPTS);
+        assert_eq!(delivery.status_code, Some(500));
+        assert!(!delivery.success);
+        assert!(delivery.detail.contains("500"), "{}", delivery.detail);
+        tokio::time::sleep(Duration::from_millis(RETRY_BASE_DELAY_MS * 8)).await;
+        assert!(
+            requests.try_recv().is_err(),
+            "retried past WEBHOOK_MAX_ATTEMPTS"
+        );
+    });
+}
+
+/// With WEBHOOK_QUEUE_DEPTH=1, a notification sent while the dispatcher is
+/// busy and another one is waiting is dropped.
+#[test]
+fn drops_notifications_when_queue_is_full() {
+    set_config();
+    run(|ctx| async move {
+        let _turn = DISPATCHER.lock().await;
+        let (url, mut requests) = receiver(&[200]).await;
+        let (_, format_id) = create_webhook(ctx, &url).await;
+
+        // the dispatcher blocks on looking up the subscribed webhooks
+        let txn = DBConfig::get_connection().begin().await.unwrap();
+        txn.execute_unprepared("LOCK TABLE webhook IN ACCESS EXCLUSIVE MODE")
+            .await
+            .unwrap();
+        for n in 1..=3 {
+            WebhookDispatcher::notify(WebhookEvent::UploadCompleted, Some(format_id), &json!(n));
+            // let the dispatcher take the first one
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        txn.commit().await.unwrap();
+
+        let mut delivered = vec![
+            next(&mut requests).await.body()["data"].clone(),
+            next(&mut requests).await.body()["data"].clone(),
+        ];
+        delivered.sort_by_key(|data| data.as_i64());
+        assert_eq!(delivered, [json!(1), json!(2)]);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(requests.try_recv().is_err(), "the third one was delivered");
+    });
+}